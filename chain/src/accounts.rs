@@ -0,0 +1,172 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Account balances at a block as a json dump, and genesis allocations built from one, to
+//! restart or migrate a chain without losing balances.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use map_core::balance::{Account, Balance};
+use map_core::chain_spec::{GenesisAccount, GenesisSpec};
+use map_core::genesis;
+use map_core::runtime::Interpreter;
+use map_core::staking::{Staking, Validator};
+use map_core::state;
+use map_core::types::{Address, Hash};
+use crate::blockchain::BlockChain;
+
+pub const ACCOUNT_DUMP_VERSION: u8 = 1;
+
+/// Accounts of the state of a block, sorted by address
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountDump {
+    pub version: u8,
+    pub height: u64,
+    pub hash: Hash,
+    pub state_root: Hash,
+    pub accounts: Vec<DumpedAccount>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpedAccount {
+    pub address: Address,
+    pub balance: u128,
+    pub nonce: u64,
+    pub locked_balance: u128,
+}
+
+/// Dump the accounts of the canonical block at `height`. The state keys accounts by a hash
+/// of their address, addresses are recovered from `genesis`, the validators of the state and
+/// the transactions of the stored blocks up to `height`, from the checkpoint on a bootstrapped
+/// chain. A state entry keyed by none of the addresses fails the export.
+pub fn export(chain: &BlockChain, genesis: &GenesisSpec, height: u64) -> Result<AccountDump, String> {
+    let block = chain.get_block_by_number(height).ok_or(format!("no block at height {}", height))?;
+    let state = chain.checked_state_at(block.state_root())
+        .map_err(|_| format!("state of height {} not available", height))?;
+    let entries: HashMap<Hash, Vec<u8>> = state.borrow().entries()?.into_iter().collect();
+    let staking = Staking::new(Interpreter::new(state));
+
+    let mut addresses = BTreeSet::new();
+    addresses.insert(Address::default());
+    for account in &genesis.allocation {
+        addresses.insert(account.address.parse().map_err(|_| format!("invalid genesis address {}", account.address))?);
+    }
+    for validator in &genesis.validators {
+        addresses.insert(validator.address.parse().map_err(|_| format!("invalid genesis address {}", validator.address))?);
+    }
+    addresses.extend(staking.validator_set().into_iter().map(|validator| validator.address));
+    // Blocks below a checkpoint were never stored
+    let first = chain.checkpoint().and_then(|hash| chain.get_block(hash)).map_or(1, |b| b.height());
+    for number in first..=height {
+        let block = chain.get_block_by_number(number)
+            .ok_or(format!("block {} missing, accounts can't be recovered", number))?;
+        for tx in block.get_txs() {
+            addresses.insert(tx.sender);
+            if let Some(transfer) = tx.transfer_msg() {
+                addresses.insert(transfer.receiver);
+            }
+        }
+    }
+
+    let mut known: HashSet<Hash> = [staking.validators.head_key, genesis::address_scheme_key()].iter().cloned().collect();
+    let mut accounts = Vec::new();
+    for address in addresses {
+        known.extend(&[
+            Validator::key_index(&address),
+            Validator::rotations_index(&address),
+            state::storage_root_key(&address),
+        ]);
+        let key = Balance::address_key(address);
+        known.insert(key);
+        if let Some(data) = entries.get(&key) {
+            let account: Account = bincode::deserialize(data).map_err(|e| format!("invalid account {}: {}", address, e))?;
+            accounts.push(DumpedAccount {
+                address,
+                balance: account.get_balance(),
+                nonce: account.get_nonce(),
+                locked_balance: account.get_locked_balance(),
+            });
+        }
+    }
+    let unknown = entries.keys().filter(|key| !known.contains(key)).count();
+    if unknown > 0 {
+        return Err(format!("{} state entries of unknown addresses", unknown));
+    }
+
+    Ok(AccountDump {
+        version: ACCOUNT_DUMP_VERSION,
+        height,
+        hash: block.hash(),
+        state_root: block.state_root(),
+        accounts,
+    })
+}
+
+impl AccountDump {
+    /// Genesis allocation funding each account with its balance plus its locked funds. Stakes
+    /// are not carried over: locked funds become spendable on the new chain, whose validators
+    /// are the ones of its spec, and nonces start over.
+    pub fn to_allocation(&self) -> Vec<GenesisAccount> {
+        self.accounts.iter()
+            .filter(|account| account.balance + account.locked_balance > 0)
+            .map(|account| GenesisAccount {
+                address: account.address.to_string(),
+                balance: account.balance + account.locked_balance,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::test_utils::{self, BlockBuilder};
+
+    #[test]
+    fn export_and_allocate() {
+        let mut spec = ChainSpec::default();
+        // Funded at genesis and never seen in a transaction
+        let idle = test_utils::test_address(9);
+        spec.genesis.allocation.push(GenesisAccount { address: idle.to_string(), balance: 5 });
+        let mut chain = BlockChain::new_in_memory(&spec);
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+        let receiver = test_utils::test_address(1);
+        let tx = test_utils::transfer(&test_utils::genesis_key(), 1, receiver, 100);
+        let mut block = BlockBuilder::on(&genesis).txs(vec![tx]).build();
+        block.set_state_root(chain.apply_transactions(genesis.state_root(), &block));
+        chain.import_block(&block).unwrap();
+
+        let dump = export(&chain, &spec.genesis, 1).unwrap();
+        assert_eq!(dump.hash, block.hash());
+        let received = dump.accounts.iter().find(|account| account.address == receiver).unwrap();
+        assert_eq!(received.balance, 100);
+        assert!(dump.accounts.windows(2).all(|pair| pair[0].address < pair[1].address));
+
+        let encoded = serde_json::to_string(&dump).unwrap();
+        assert_eq!(serde_json::from_str::<AccountDump>(&encoded).unwrap(), dump);
+        let allocation = dump.to_allocation();
+        assert_eq!(allocation.len(), dump.accounts.iter().filter(|a| a.balance + a.locked_balance > 0).count());
+        assert!(allocation.contains(&GenesisAccount { address: receiver.to_string(), balance: 100 }));
+        assert!(allocation.contains(&GenesisAccount { address: idle.to_string(), balance: 5 }));
+
+        assert!(export(&chain, &spec.genesis, 2).is_err());
+        // The idle account is keyed by an address only the spec gives
+        let mut unknown = spec.genesis.clone();
+        unknown.allocation.retain(|account| account.address != idle.to_string());
+        assert_eq!(export(&chain, &unknown, 1), Err("1 state entries of unknown addresses".to_string()));
+    }
+}
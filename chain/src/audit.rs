@@ -0,0 +1,87 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background audit of the invariants the node relies on when serving data: the head and the
+//! height index point at stored blocks, the root node of the head state is stored, the fee,
+//! staking and proposer indexes cover the recent blocks, and the pool holds no transaction the
+//! state it was last reset to already included. Violations are logged and exported as
+//! metrics, so corruption shows up before a request or an import trips on it. The deeper
+//! nodes of the head state are not walked, a missing one fails the next read of it.
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::blockchain::BlockChain;
+use crate::lock::TimedRwLock;
+use crate::metrics;
+
+/// Heights below the head whose index and parent links are checked on each run
+pub const AUDIT_DEPTH: u64 = 1024;
+/// Names of the checks, each exported even when it finds nothing
+pub const CHECKS: [&str; 8] = [
+    "head", "head_state", "height_index", "fee_index", "staking_index", "proposer_index", "pool_nonce", "tx_index",
+];
+
+/// An invariant found broken
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// Name of the check, the label of the `audit_violations` metric
+    pub check: &'static str,
+    pub detail: String,
+}
+
+impl Violation {
+    pub fn new(check: &'static str, detail: String) -> Self {
+        Violation { check, detail }
+    }
+}
+
+/// Checks of each run, besides the chain ones
+pub type ExtraCheck = Box<dyn Fn() -> Vec<Violation> + Send>;
+
+/// Audit the chain and run `extra` every `interval` from a background thread, until the
+/// returned sender is signalled or dropped. A zero interval disables the audit.
+pub fn start(chain: Arc<TimedRwLock<BlockChain>>, interval: Duration, extra: ExtraCheck) -> mpsc::Sender<()> {
+    let (stop, signal) = mpsc::channel();
+    if interval == Duration::from_secs(0) {
+        return stop;
+    }
+    thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = signal.recv_timeout(interval) {
+            let mut violations = chain.read().expect("acquiring block_chain read lock").audit(AUDIT_DEPTH);
+            violations.extend(extra());
+            report(&violations);
+        }
+    });
+    stop
+}
+
+/// Log the violations of a run and export their count by check
+pub fn report(violations: &[Violation]) {
+    metrics::inc_counter(&metrics::AUDIT_RUNS);
+    for check in CHECKS.iter() {
+        let count = violations.iter().filter(|v| v.check == *check).count();
+        if let Some(gauge) = metrics::get_int_gauge(&metrics::AUDIT_VIOLATIONS, &[check]) {
+            gauge.set(count as i64);
+        }
+    }
+    for violation in violations {
+        error!("audit {}: {}", violation.check, violation.detail);
+    }
+    if violations.is_empty() {
+        debug!("audit passed");
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Canonical blocks proposed by each validator in an epoch, the counterpart of the missed
+//! proposals.
+use serde::{Deserialize, Serialize};
+
+use ed25519::pubkey::Pubkey;
+use map_core::block::Block;
+use map_core::types::{Address, AddressScheme};
+use crate::committee::EpochCommittee;
+
+/// Blocks of one validator in an epoch.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProposedBlocks {
+    pub validator: Address,
+    pub blocks: u64,
+    /// Fees of the transactions of the blocks.
+    pub rewards: u128,
+}
+
+/// Address of the key sealing a block under `scheme`, None for unsigned dev blocks.
+pub fn proposer(block: &Block, scheme: AddressScheme) -> Option<Address> {
+    proposer_in(block, None, scheme)
+}
+
+/// Staking address of the validator sealing a block, the member of the epoch `committee` with
+/// the sealing key, so that blocks keep their validator across key rotations. Keys of no member,
+/// as on dev chains, stand for the address they derive with `scheme`.
+pub fn proposer_in(block: &Block, committee: Option<&EpochCommittee>, scheme: AddressScheme) -> Option<Address> {
+    let proof = block.proof_one()?;
+    if proof.2 != 0 {
+        return None;
+    }
+    committee.and_then(|c| c.member_address(&proof.0))
+        .or_else(|| Some(Address::from_pubkey(&Pubkey::from_bytes(&proof.0), scheme)))
+}
+
+/// Count a block of `validator` with `rewards` in fees.
+pub fn record(epoch: &mut Vec<ProposedBlocks>, validator: Address, rewards: u128) {
+    let index = match epoch.iter().position(|p| p.validator == validator) {
+        Some(i) => i,
+        None => {
+            epoch.push(ProposedBlocks { validator, ..Default::default() });
+            epoch.len() - 1
+        }
+    };
+    epoch[index].blocks += 1;
+    epoch[index].rewards += rewards;
+}
+
+/// Take back a block of `validator` counted by `record`, for a block no longer canonical.
+pub fn unrecord(epoch: &mut Vec<ProposedBlocks>, validator: Address, rewards: u128) {
+    if let Some(index) = epoch.iter().position(|p| p.validator == validator) {
+        epoch[index].blocks = epoch[index].blocks.saturating_sub(1);
+        epoch[index].rewards = epoch[index].rewards.saturating_sub(rewards);
+        if epoch[index].blocks == 0 {
+            epoch.remove(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::test_utils::{self, BlockBuilder};
+    use map_core::types::Hash;
+    use crate::committee::CommitteeMember;
+
+    #[test]
+    fn test_record() {
+        let a = Address([1u8; 20]);
+        let b = Address([2u8; 20]);
+        let mut epoch = Vec::new();
+        record(&mut epoch, a, 10);
+        record(&mut epoch, b, 0);
+        record(&mut epoch, a, 5);
+
+        assert_eq!(epoch.len(), 2);
+        assert_eq!((epoch[0].blocks, epoch[0].rewards), (2, 15));
+        assert_eq!(epoch[1].validator, b);
+
+        unrecord(&mut epoch, a, 5);
+        unrecord(&mut epoch, b, 0);
+        assert_eq!(epoch, vec![ProposedBlocks { validator: a, blocks: 1, rewards: 10 }]);
+    }
+
+    #[test]
+    fn test_proposer() {
+        let key = test_utils::test_key(1);
+        let block = BlockBuilder::on(&Block::default()).signed_by(key).build();
+        assert_eq!(proposer(&block, AddressScheme::default()), Some(test_utils::address_of(&key)));
+        assert_eq!(proposer(&block, AddressScheme::Keccak256), Some(Address::from_pubkey(&key.to_pubkey().unwrap(), AddressScheme::Keccak256)));
+        assert_eq!(proposer(&BlockBuilder::on(&Block::default()).build(), AddressScheme::default()), None);
+
+        // Credited to the staking address of the member with the key
+        let mut pubkey = [0u8; 32];
+        pubkey.copy_from_slice(&key.to_pubkey().unwrap().to_bytes());
+        let staking = Address([9u8; 20]);
+        let member = CommitteeMember { pubkey, stake: 1, address: staking };
+        let committee = EpochCommittee::new(0, Hash::default(), &Hash::default(), vec![member]);
+        assert_eq!(proposer_in(&block, Some(&committee), AddressScheme::default()), Some(staking));
+    }
+}
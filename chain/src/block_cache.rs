@@ -0,0 +1,112 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Decoded recently read and written blocks, so serving the same recent blocks to several
+//! syncing peers and RPC clients doesn't read, decompress and decode them each time.
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+
+use map_core::block::Block;
+use map_core::types::Hash;
+
+use crate::metrics;
+
+/// The number of block bodies kept by default.
+pub const BLOCK_CACHE_SIZE: usize = 256;
+
+/// LRU of blocks by hash, empty with a capacity of 0.
+pub struct BlockCache {
+    capacity: usize,
+    blocks: Mutex<LruCache<Hash, Arc<Block>>>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            blocks: Mutex::new(LruCache::new(capacity.max(1))),
+        }
+    }
+
+    /// Cached block, counted as a hit or a miss.
+    pub fn get(&self, hash: &Hash) -> Option<Arc<Block>> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let cached = self.blocks.lock().expect("acquiring block cache lock").get(hash).cloned();
+        match cached {
+            Some(_) => metrics::inc_counter(&metrics::BLOCK_CACHE_HITS),
+            None => metrics::inc_counter(&metrics::BLOCK_CACHE_MISSES),
+        }
+        cached
+    }
+
+    pub fn insert(&self, hash: Hash, block: Arc<Block>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.blocks.lock().expect("acquiring block cache lock").put(hash, block);
+    }
+
+    pub fn remove(&self, hash: &Hash) {
+        self.blocks.lock().expect("acquiring block cache lock").pop(hash);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.lock().expect("acquiring block cache lock").len()
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new(BLOCK_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u64) -> Arc<Block> {
+        let mut block = Block::default();
+        block.header.height = height;
+        Arc::new(block)
+    }
+
+    #[test]
+    fn bounded_by_capacity() {
+        let cache = BlockCache::new(2);
+        for i in 1..=3u8 {
+            cache.insert(Hash([i; 32]), block(i as u64));
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&Hash([1; 32])).is_none());
+        assert_eq!(cache.get(&Hash([3; 32])).unwrap().height(), 3);
+        cache.remove(&Hash([3; 32]));
+        assert!(cache.get(&Hash([3; 32])).is_none());
+
+        let disabled = BlockCache::new(0);
+        disabled.insert(Hash([1; 32]), block(1));
+        assert!(disabled.get(&Hash([1; 32])).is_none());
+        assert_eq!(disabled.len(), 0);
+    }
+}
@@ -14,83 +14,502 @@
 // You should have received a copy of the GNU General Public License
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::rc::Rc;
 use std::cell::RefCell;
 
 use errors::Error;
-use map_consensus::poa;
+use map_consensus::engine::{self, ConsensusEngine};
 use map_core;
 use map_core::trie::NULL_ROOT;
 use map_core::block::{Block, Header};
+use map_core::transaction::Transaction;
+use map_core::chain_spec::ChainSpec;
 use map_core::genesis;
 #[allow(unused_imports)]
-use map_core::state::{ArchiveDB, StateDB};
-use map_core::types::{Hash, Address};
+use map_core::state::{ArchiveDB, StateDB, StateJournal};
+use map_core::types::{Hash, Address, AddressScheme};
 use map_core::runtime::Interpreter;
+use map_core::staking::{Staking, StakingEvent};
 use map_core::balance::Balance;
 use executor::Executor;
 use map_store;
-use map_store::mapdb::MapDB;
-use crate::store::ChainDB;
+use map_store::lock::DirLock;
+use map_store::MemoryKV;
+use crate::store::{self, ChainDB, Compression, DbEngine};
+use crate::fee_stats::BlockFeeStats;
+use crate::missed_proposals::{self, MissedProposals};
+use crate::authorship::{self, ProposedBlocks};
+use crate::committee::{self, EpochCommittee};
+use crate::filters::Filters;
+use crate::verify_cache::{self, VerifyCache};
+use crate::epoch_diff::EpochCache;
+use crate::prune::{NodeMode, StatePin, STATE_HISTORY, PRUNE_BATCH};
+use crate::metrics;
+use crate::import_timer::{self, ImportTimer};
+use crate::schema::{self, ChainMetadata};
+use crate::audit::Violation;
 
 use super::BlockChainErrorKind;
 
+/// Blocks between two calls of the reindex progress callback
+pub const REINDEX_PROGRESS_INTERVAL: u64 = 1000;
+
+/// Seconds a block time may be ahead of the local clock
+pub const MAX_FUTURE_DRIFT: u64 = 15;
+
+/// Blocks the head may be rolled back at load without an operator allowing it
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 64;
+
+/// Staking event emitted by a transaction of a canonical block
+#[derive(Clone, Debug, PartialEq)]
+pub struct StakingEventRecord {
+    pub height: u64,
+    pub block_hash: Hash,
+    pub tx_hash: Hash,
+    pub event: StakingEvent,
+}
+
+/// Head rolled back by the recovery at load
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadRewind {
+    /// Height of the head found at load
+    pub from_height: u64,
+    /// Height of the committed block rolled back to
+    pub to_height: u64,
+    /// Height of the head once the stored blocks above were imported again
+    pub head_height: u64,
+}
+
 pub struct BlockChain {
     db: ChainDB,
     state_backend: ArchiveDB,
     validator: Validator,
     genesis: Block,
-    #[allow(dead_code)]
-    consensus: poa::POA
+    /// Imports slower than this are logged with a per-stage breakdown
+    slow_import_threshold: Option<Duration>,
+    consensus: Box<dyn ConsensusEngine>,
+    spec: ChainSpec,
+    /// Derivation of addresses from public keys, pinned by the genesis state at load
+    address_scheme: AddressScheme,
+    /// Block and transaction filters matched on import
+    filters: Filters,
+    /// Blocks whose signatures and seal were checked, kept across sync retries
+    verify_cache: VerifyCache,
+    /// Validator sets and named accounts of the epochs, for the epoch diffs
+    epoch_cache: Arc<EpochCache>,
+    /// Whether the state of old blocks is pruned
+    mode: NodeMode,
+    /// Readers of the state holding a `StatePin`
+    state_readers: Arc<AtomicUsize>,
+    /// State root found missing trie nodes on import, until they are fetched again
+    unhealthy_state: Option<Hash>,
+    /// Head rollback of the last load, if any
+    rewind: Option<HeadRewind>,
+    /// Deepest head rollback done at load without `allow_deep_reorg`
+    max_reorg_depth: u64,
+    /// Head rollback refused at load for exceeding `max_reorg_depth`
+    held_rewind: Option<HeadRewind>,
+    /// Blocks of the last head rollback not imported again, oldest first
+    unwound: Vec<Block>,
+    /// Exclusive use of the datadir, not taken by read-only instances
+    _lock: Option<DirLock>,
 }
 
 impl BlockChain {
-    pub fn new(datadir: PathBuf, key: String) -> Self {
+    /// Open the chain in `datadir` for writing. Fails if another process is using it.
+    pub fn new(datadir: PathBuf, spec: &ChainSpec) -> Result<Self, Error> {
+        Self::with_compression(datadir, spec, Compression::default())
+    }
+
+    /// Open the chain in `datadir` for writing, compressing the block bodies and trie nodes
+    /// written from now on with the codecs of `compression`.
+    pub fn with_compression(datadir: PathBuf, spec: &ChainSpec, compression: Compression) -> Result<Self, Error> {
+        Self::with_storage(datadir, spec, compression, None)
+    }
+
+    /// Open the chain in `datadir` for writing, creating its databases with `engine` if new.
+    pub fn with_storage(datadir: PathBuf, spec: &ChainSpec, compression: Compression, engine: Option<DbEngine>) -> Result<Self, Error> {
         info!("using datadir {}", datadir.display());
-        let db_cfg = map_store::Config::new(datadir.clone());
-        let backend;
-        {
-            let mut dir = datadir.clone();
-            dir.push("data");
-            let db = MapDB::open(map_store::Config::new(dir.clone())).unwrap();
-            let kv: Arc<RwLock<dyn map_store::KVDB>> = Arc::new(RwLock::new(db));
-            backend = ArchiveDB::new(Arc::clone(&kv));
+        let lock = DirLock::acquire(&datadir)?;
+        let (chain_kv, state_kv) = store::open_backends(&datadir, compression, engine, false)?;
+        let db = ChainDB::with_backend(chain_kv);
+        if let Some(metadata) = db.metadata() {
+            schema::check_version(metadata.schema_version)?;
         }
+        // The state of a block is on disk before the block is marked committed
+        let mut state_backend = ArchiveDB::new(state_kv);
+        state_backend.set_sync(true);
+
+        Ok(BlockChain {
+            db,
+            genesis: genesis::to_genesis(&spec.genesis),
+            state_backend,
+            validator: Validator{},
+            slow_import_threshold: Some(import_timer::DEFAULT_SLOW_IMPORT_THRESHOLD),
+            consensus: engine::from_spec(spec),
+            spec: spec.clone(),
+            address_scheme: spec.genesis.address_scheme,
+            filters: Filters::new(),
+            verify_cache: VerifyCache::default(),
+            epoch_cache: Arc::new(EpochCache::default()),
+            mode: NodeMode::default(),
+            state_readers: Arc::new(AtomicUsize::new(0)),
+            unhealthy_state: None,
+            rewind: None,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            held_rewind: None,
+            unwound: Vec::new(),
+            _lock: Some(lock),
+        })
+    }
 
+    /// Chain kept in memory only, for running several nodes in one process.
+    pub fn new_in_memory(spec: &ChainSpec) -> Self {
+        let kv: Arc<RwLock<dyn map_store::KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
         BlockChain {
-            db: ChainDB::new(db_cfg).unwrap(),
-            genesis: genesis::to_genesis(),
-            state_backend: backend,
+            db: ChainDB::with_backend(Box::new(MemoryKV::new())),
+            genesis: genesis::to_genesis(&spec.genesis),
+            state_backend: ArchiveDB::new(kv),
             validator: Validator{},
-            consensus: poa::POA::new_from_string(key),
+            slow_import_threshold: Some(import_timer::DEFAULT_SLOW_IMPORT_THRESHOLD),
+            consensus: engine::from_spec(spec),
+            spec: spec.clone(),
+            address_scheme: spec.genesis.address_scheme,
+            filters: Filters::new(),
+            verify_cache: VerifyCache::default(),
+            epoch_cache: Arc::new(EpochCache::default()),
+            mode: NodeMode::default(),
+            state_readers: Arc::new(AtomicUsize::new(0)),
+            unhealthy_state: None,
+            rewind: None,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            held_rewind: None,
+            unwound: Vec::new(),
+            _lock: None,
         }
     }
 
+    /// Open existing chain data without write access, used by query-only services.
+    /// Returns None if the chain has not been initialized in `datadir`.
+    pub fn open_read_only(datadir: PathBuf) -> Option<Self> {
+        info!("using read-only datadir {}", datadir.display());
+        let (chain_kv, kv) = match store::open_backends(&datadir, Compression::default(), None, true) {
+            Ok(backends) => backends,
+            Err(e) => {
+                error!("open chain db error: {}", e);
+                return None;
+            }
+        };
+        let chain_db = ChainDB::with_backend(chain_kv);
+        if let Err(e) = chain_db.metadata().map_or(Ok(()), |m| schema::check_version(m.schema_version)) {
+            error!("open chain db error: {}", e);
+            return None;
+        }
+        let genesis = chain_db.get_block_by_number(0)?;
+        let mode = chain_db.node_mode().unwrap_or_default();
+
+        let mut chain = BlockChain {
+            db: chain_db,
+            genesis: genesis,
+            state_backend: ArchiveDB::new(kv),
+            validator: Validator{},
+            slow_import_threshold: Some(import_timer::DEFAULT_SLOW_IMPORT_THRESHOLD),
+            consensus: engine::from_spec(&ChainSpec::default()),
+            spec: ChainSpec::default(),
+            address_scheme: AddressScheme::default(),
+            filters: Filters::new(),
+            verify_cache: VerifyCache::default(),
+            epoch_cache: Arc::new(EpochCache::default()),
+            mode,
+            state_readers: Arc::new(AtomicUsize::new(0)),
+            unhealthy_state: None,
+            rewind: None,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            held_rewind: None,
+            unwound: Vec::new(),
+            _lock: None,
+        };
+        chain.pin_address_scheme();
+        Some(chain)
+    }
+
     pub fn setup_genesis(&mut self) -> Hash {
         let state_db = Rc::new(RefCell::new(StateDB::from_existing(&self.state_backend, NULL_ROOT)));
-        let root = genesis::setup_allocation(state_db.clone());
+        let root = genesis::setup_allocation(state_db.clone(), &self.spec.genesis);
         self.genesis.set_state_root(root);
 
         self.db.write_block(&self.genesis).expect("can not write block");
+        self.db.write_committed_hash(&self.genesis.hash()).expect("can not write committed block");
         self.db.write_head_hash(self.genesis.hash()).expect("can not wirte head");
+        self.db.write_metadata(&ChainMetadata::new(schema::SCHEMA_VERSION, self.genesis.hash()))
+            .expect("can not write metadata");
         info!("setup genesis hash={}", self.genesis.hash());
         self.genesis.hash()
     }
 
-    pub fn load(&mut self) {
+    pub fn load(&mut self) -> Result<(), Error> {
         let block_zero = self.get_block_by_number(0);
         if block_zero.is_none() {
             self.setup_genesis();
+            self.count_state();
         } else {
             self.genesis = block_zero.unwrap();
             let current = self.current_block();
             info!("load genesis hash={}", self.genesis.hash());
             info!("load block height={} hash={}", current.height(), current.hash());
+            self.check_genesis()?;
+            let pinned = self.pin_address_scheme();
+            if pinned != self.spec.genesis.address_scheme {
+                warn!("genesis pins address scheme {:?}, ignoring {:?} of the chain spec", pinned, self.spec.genesis.address_scheme);
+            }
+            self.count_state();
+            self.recover_head(false);
+            self.migrate()?;
+        }
+        Ok(())
+    }
+
+    /// Refuse a db whose recorded genesis is not its block 0
+    fn check_genesis(&self) -> Result<(), Error> {
+        match self.db.metadata() {
+            Some(metadata) if metadata.genesis_hash != self.genesis.hash() => {
+                Err(BlockChainErrorKind::GenesisMismatch.reason(format!(
+                    "chain db records genesis {} but holds block 0 {}", metadata.genesis_hash, self.genesis.hash())).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop what the import of canonical block `b` derived from it: its fee statistics, its
+    /// staking events and its count and height in the proposer indexes of its epoch.
+    fn unindex_block(&mut self, b: &Block) {
+        let hash = b.hash();
+        let fees = self.db.get_fee_stats(&hash).map_or(0, |stats| stats.total_fees);
+        let epoch = committee::epoch_of(b.header.slot);
+        let mut proposed = self.db.get_proposed_blocks(epoch);
+        let validators: Vec<Address> = proposed.iter().map(|p| p.validator).collect();
+        for validator in validators {
+            let mut heights = self.db.get_proposer_index(&validator, epoch);
+            if !heights.contains(&b.height()) {
+                continue;
+            }
+            heights.retain(|height| *height != b.height());
+            self.db.write_proposer_index(&validator, epoch, &heights).expect("can not write proposer index");
+            authorship::unrecord(&mut proposed, validator, fees);
+        }
+        self.db.write_proposed_blocks(epoch, &proposed).expect("can not write proposed blocks");
+        self.db.delete_fee_stats(&hash).expect("can not delete fee stats");
+        self.db.delete_staking_events(&hash).expect("can not delete staking events");
+    }
+
+    /// Deepest head rollback done at load, deeper ones wait for `allow_deep_reorg`. Set it
+    /// before `load`.
+    pub fn set_max_reorg_depth(&mut self, depth: u64) {
+        self.max_reorg_depth = depth;
+    }
+
+    /// Roll the head back to the last committed block and import the stored blocks above it
+    /// again, for a head left ahead of its state by an unclean shutdown. A rollback deeper than
+    /// `max_reorg_depth` is held, raising a critical alert, unless `allow_deep`.
+    fn recover_head(&mut self, allow_deep: bool) {
+        let head = self.current_block();
+        let committed = self.db.committed_hash();
+        if committed == Some(head.hash()) && self.has_state(head.state_root()) {
+            return;
+        }
+        // Without a marker, from before it was recorded, any block with its state will do
+        let committed_height = committed.and_then(|hash| self.get_block(hash)).map(|b| b.height());
+
+        let mut pending = Vec::new();
+        let mut block = head.clone();
+        while block.height() > committed_height.unwrap_or(block.height()) || !self.has_state(block.state_root()) {
+            let parent = match self.get_block(block.header.parent_hash) {
+                Some(parent) => parent,
+                None => {
+                    error!("no committed block below head height={} hash={}", head.height(), head.hash());
+                    return;
+                }
+            };
+            pending.push(block);
+            block = parent;
+        }
+
+        let depth = head.height() - block.height();
+        if depth > self.max_reorg_depth && !allow_deep {
+            error!("CRITICAL: head height={} is {} blocks ahead of its committed state, beyond the max reorg depth {}; \
+                holding the rollback to height={} hash={} until admin_allowDeepReorg",
+                head.height(), depth, self.max_reorg_depth, block.height(), block.hash());
+            metrics::set_gauge(&metrics::HELD_REORG_DEPTH, depth as i64);
+            self.held_rewind = Some(HeadRewind { from_height: head.height(), to_height: block.height(), head_height: head.height() });
+            return;
+        }
+        if !pending.is_empty() {
+            warn!("head height={} is ahead of its committed state, rolling back to height={} hash={}",
+                head.height(), block.height(), block.hash());
+        }
+        // Imported again below, which derives them anew
+        for b in pending.iter() {
+            self.unindex_block(b);
+        }
+        self.db.delete_header_heights_above(block.height()).expect("can not delete height index");
+        self.db.write_committed_hash(&block.hash()).expect("can not write committed block");
+        self.db.write_head_hash(block.hash()).expect("can not wirte head");
+
+        for b in pending.iter().rev() {
+            if let Err(e) = self.import_block(b) {
+                warn!("can not import again block height={} hash={}: {}", b.height(), b.hash(), e);
+                break;
+            }
+        }
+        let current = self.current_block();
+        if current.hash() != head.hash() {
+            info!("recovered head height={} hash={}", current.height(), current.hash());
+        }
+        if !pending.is_empty() {
+            self.rewind = Some(HeadRewind { from_height: head.height(), to_height: block.height(), head_height: current.height() });
+        }
+        self.unwound = pending.into_iter().rev()
+            .filter(|b| self.db.get_header_hash(b.height()) != Some(b.hash()))
+            .collect();
+    }
+
+    /// Head rollback done when loading the chain, for a head ahead of its committed state
+    pub fn head_rewind(&self) -> Option<HeadRewind> {
+        self.rewind.clone()
+    }
+
+    /// Transactions of the blocks the last head rollback left out of the chain, in block order,
+    /// for the pool to take back
+    pub fn unwound_transactions(&self) -> Vec<Transaction> {
+        self.unwound.iter().flat_map(|b| b.get_txs().iter().cloned()).collect()
+    }
+
+    /// Head rollback refused at load for exceeding the max reorg depth, blocking imports
+    pub fn held_rewind(&self) -> Option<HeadRewind> {
+        self.held_rewind.clone()
+    }
+
+    /// Do the held head rollback, returning the rollback done or None if none was held
+    pub fn allow_deep_reorg(&mut self) -> Option<HeadRewind> {
+        self.held_rewind.take()?;
+        self.recover_head(true);
+        metrics::set_gauge(&metrics::HELD_REORG_DEPTH, 0);
+        self.head_rewind()
+    }
+
+    /// Run the migrations of a db written by an older client and record this client as the last
+    /// one writing it.
+    fn migrate(&mut self) -> Result<(), Error> {
+        let mut metadata = self.db.metadata()
+            .unwrap_or_else(|| ChainMetadata::new(schema::UNVERSIONED, self.genesis.hash()));
+        if metadata.client_version != schema::CLIENT_VERSION {
+            info!("chain db last written by {}", metadata.client_version);
+        }
+        for migration in schema::pending(metadata.schema_version) {
+            info!("migrating chain db to schema version {}: {}", migration.version, migration.description);
+            (migration.run)(self)?;
+            metadata.schema_version = migration.version;
+            self.db.write_metadata(&metadata).map_err(storage_error("metadata"))?;
+        }
+        metadata.client_version = schema::CLIENT_VERSION.to_string();
+        self.db.write_metadata(&metadata).map_err(storage_error("metadata"))?;
+        Ok(())
+    }
+
+    /// Schema version, genesis and last client of the chain db
+    pub fn metadata(&self) -> Option<ChainMetadata> {
+        self.db.metadata()
+    }
+
+    /// Derive addresses with the scheme pinned by the genesis state, which wins over the spec
+    /// of a chain set up earlier.
+    fn pin_address_scheme(&mut self) -> AddressScheme {
+        self.address_scheme = genesis::pinned_address_scheme(&self.state_at(self.genesis.state_root()).borrow());
+        self.address_scheme
+    }
+
+    /// Derivation of the addresses of this chain from public keys
+    pub fn address_scheme(&self) -> AddressScheme {
+        self.address_scheme
+    }
+
+    /// Account which sealed `block`, if the consensus engine records it
+    pub fn author(&self, block: &Block) -> Option<Address> {
+        self.consensus.author(block, self.address_scheme)
+    }
+
+    /// Set the import time above which blocks are logged as slow, None disables the warning
+    pub fn set_slow_import_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_import_threshold = threshold;
+    }
+
+    /// Keep the state of every block, or prune the state of old blocks on import. The mode is
+    /// recorded, a datadir run as a full node lacks old states and can't be reopened as archive.
+    pub fn set_mode(&mut self, mode: NodeMode) -> Result<(), Error> {
+        if mode == NodeMode::Archive && self.db.node_mode() == Some(NodeMode::Full) {
+            return Err(BlockChainErrorKind::StateUnavailable
+                .reason("the datadir was run as a full node and its old states are pruned, start it with --mode full").into());
+        }
+        if mode == NodeMode::Full {
+            self.db.write_node_mode(mode).expect("can not write node mode");
+        }
+        self.mode = mode;
+        // Counting starts once the genesis state is written
+        if self.get_block_by_number(0).is_some() {
+            self.count_state();
+        }
+        Ok(())
+    }
+
+    /// Count the references of the state nodes written from now on in full mode, so that
+    /// pruning can delete them. The state written before, like the genesis state, is kept.
+    fn count_state(&mut self) {
+        if self.mode == NodeMode::Full {
+            self.state_backend.set_counted(true);
         }
     }
 
+    /// Keep pruning from deleting state nodes until the pin is dropped, for reading a state
+    /// without holding the chain lock
+    pub fn pin_state(&self) -> StatePin {
+        StatePin::new(&self.state_readers)
+    }
+
+    /// Epoch data kept across epoch diffs
+    pub fn epoch_cache(&self) -> Arc<EpochCache> {
+        self.epoch_cache.clone()
+    }
+
+    pub fn mode(&self) -> NodeMode {
+        self.mode
+    }
+
+    /// Keep the encoded bodies of the last `capacity` blocks read or written in memory, served to
+    /// the RPC block getters and to syncing peers. 0 disables the cache.
+    pub fn set_block_cache_size(&mut self, capacity: usize) {
+        self.db.set_block_cache_size(capacity);
+    }
+
+    pub fn filters(&mut self) -> &mut Filters {
+        &mut self.filters
+    }
+
+
+    pub fn consensus(&self) -> &dyn ConsensusEngine {
+        self.consensus.as_ref()
+    }
+
+    /// Seal a locally produced block with the consensus engine
+    pub fn finalize_block(&self, block: Block, state_root: Hash) -> Result<Block, Error> {
+        self.consensus.finalize(block, state_root)
+    }
+
     pub fn statedb(&self) -> &ArchiveDB {
         &self.state_backend
     }
@@ -99,10 +518,58 @@ impl BlockChain {
         Rc::new(RefCell::new(StateDB::from_existing(&self.state_backend, root)))
     }
 
+    /// Fail with `MissingStateNodes` if reading the state of `root` hit missing trie nodes since
+    /// the last check, marking it for healing.
+    fn check_state_nodes(&mut self, root: Hash) -> Result<(), Error> {
+        let missing = self.state_backend.take_missing();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        error!("state {} is missing {} trie nodes, fetching them from peers", root, missing.len());
+        self.unhealthy_state = Some(root);
+        Err(BlockChainErrorKind::MissingStateNodes.reason(format!("state {} is missing node {}", root, missing[0])).into())
+    }
+
+    /// State root whose missing trie nodes must be fetched before blocks import on it again
+    pub fn unhealthy_state(&self) -> Option<Hash> {
+        self.unhealthy_state
+    }
+
+    /// Clear the mark of `root` once the nodes under it were fetched again
+    pub fn mark_healed(&mut self, root: Hash) {
+        if self.unhealthy_state == Some(root) {
+            info!("state {} healed", root);
+            self.unhealthy_state = None;
+        }
+    }
+
+    /// Whether the state trie of `root` is in the state db, it is gone once pruned or lost.
+    pub fn has_state(&self, root: Hash) -> bool {
+        root == NULL_ROOT || self.state_backend.get_node(&root).is_some()
+    }
+
+    /// State at `root`, failing with `StateUnavailable` instead of reading a missing trie.
+    pub fn checked_state_at(&self, root: Hash) -> Result<Rc<RefCell<StateDB>>, Error> {
+        if !self.has_state(root) {
+            return Err(BlockChainErrorKind::StateUnavailable.reason(format!("missing state root {}", root)).into());
+        }
+        Ok(self.state_at(root))
+    }
+
     pub fn genesis_hash(&self) -> Hash {
         self.genesis.hash()
     }
 
+    /// Unix time of the genesis block, the origin of the slot clock.
+    pub fn genesis_time(&self) -> u64 {
+        self.genesis.header.time
+    }
+
+    /// Parameters of the network this chain belongs to.
+    pub fn spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
     pub fn current_block(&self) -> Block {
         self.db.head_block().unwrap()
     }
@@ -124,21 +591,492 @@ impl BlockChain {
         self.db.get_block(&hash)
     }
 
+    /// Block shared with the block cache, sparing a copy when it is only read
+    pub fn get_shared_block(&self, hash: Hash) -> Option<Arc<Block>> {
+        self.db.get_shared_block(&hash)
+    }
+
+    pub fn get_shared_block_by_number(&self, num: u64) -> Option<Arc<Block>> {
+        self.db.get_shared_block_by_number(num)
+    }
+
+    /// Block as stored in the chain db, after decompression
+    pub fn get_raw_block(&self, hash: Hash) -> Option<Vec<u8>> {
+        self.db.get_raw_block(&hash)
+    }
+
+    /// Header as stored in the chain db
+    pub fn get_raw_header(&self, hash: Hash) -> Option<Vec<u8>> {
+        self.db.get_raw_header(&hash)
+    }
+
     pub fn get_header_by_number(&self, num: u64) -> Option<Header> {
         self.db.get_header_by_number(num)
     }
 
+    /// Fee statistics of a block, computed from its transactions if not recorded at import
+    pub fn get_fee_stats(&self, hash: Hash) -> Option<BlockFeeStats> {
+        if let Some(stats) = self.db.get_fee_stats(&hash) {
+            return Some(stats);
+        }
+        self.db.get_block(&hash).map(|b| BlockFeeStats::from_block(&b))
+    }
+
+    /// Staking events of canonical blocks in `from..=to`, optionally of a single address
+    pub fn get_staking_events(&self, from: u64, to: u64, address: Option<&Address>) -> Vec<StakingEventRecord> {
+        let mut records = Vec::new();
+        for num in from..=to {
+            let hash = match self.db.get_header_hash(num) {
+                Some(h) => h,
+                None => break,
+            };
+            for (tx_hash, event) in self.db.get_staking_events(&hash) {
+                if address.map_or(true, |addr| event.address() == addr) {
+                    records.push(StakingEventRecord { height: num, block_hash: hash, tx_hash, event });
+                }
+            }
+        }
+        records
+    }
+
+    /// Validators that were elected but proposed no block in `epoch`, with the missed slots
+    pub fn get_missed_proposals(&self, epoch: u64) -> Vec<MissedProposals> {
+        self.db.get_missed_proposals(epoch)
+    }
+
+    /// Canonical blocks of each validator in `epoch` and the fees they collected
+    pub fn get_proposed_blocks(&self, epoch: u64) -> Vec<ProposedBlocks> {
+        self.db.get_proposed_blocks(epoch)
+    }
+
+    /// Heights of the canonical blocks sealed by `proposer` in `epoch`, ascending
+    pub fn get_proposer_blocks(&self, proposer: &Address, epoch: u64) -> Vec<u64> {
+        self.db.get_proposer_index(proposer, epoch)
+    }
+
+    /// Heights of the canonical blocks sealed by `proposer` in `from..=to`, ascending. Only the
+    /// epochs spanned by the range are read from the proposer index.
+    pub fn get_blocks_by_proposer(&self, proposer: &Address, from: u64, to: u64) -> Vec<u64> {
+        let to = cmp::min(to, self.current_block().height());
+        let (first, last) = match (self.get_header_by_number(from), self.get_header_by_number(to)) {
+            (Some(first), Some(last)) if from <= to => (first.slot, last.slot),
+            _ => return Vec::new(),
+        };
+        (committee::epoch_of(first)..=committee::epoch_of(last))
+            .flat_map(|epoch| self.db.get_proposer_index(proposer, epoch))
+            .filter(|height| *height >= from && *height <= to)
+            .collect()
+    }
+
+    /// Record that `validator` was elected for `slot` of `epoch` and no block appeared
+    pub fn record_missed_proposal(&mut self, epoch: u64, validator: Address, slot: u64) {
+        let mut missed = self.db.get_missed_proposals(epoch);
+        if missed_proposals::record(&mut missed, validator, slot) {
+            self.db.write_missed_proposals(epoch, &missed).expect("can not write missed proposals");
+            if let Some(gauge) = metrics::get_int_gauge(&metrics::VALIDATOR_MISSED_PROPOSALS, &[&validator.to_string()]) {
+                gauge.inc();
+            }
+        }
+    }
+
+    /// Validators of `epoch` for a block on top of `parent`, read from the state of the last
+    /// block before the epoch. The snapshot taken when the epoch was entered is used once
+    /// that state is pruned.
+    pub fn epoch_committee(&self, parent: &Header, epoch: u64) -> Result<EpochCommittee, Error> {
+        let mut boundary = parent.clone();
+        while boundary.height > 0 && committee::epoch_of(boundary.slot) >= epoch {
+            boundary = self.db.get_header(&boundary.parent_hash)
+                .ok_or_else(|| BlockChainErrorKind::UnknownAncestor.reason(format!("parent of block {}", boundary.height)))?;
+        }
+        let hash = boundary.hash();
+        let members = match self.db.get_committee(&hash) {
+            Some(members) => members,
+            None => {
+                let state = self.checked_state_at(boundary.state_root)?;
+                committee::members_of(&Staking::new(Interpreter::new(state)))
+            }
+        };
+        Ok(EpochCommittee::new(epoch, hash, &self.genesis_hash(), members))
+    }
+
+    /// Last canonical block before the first slot of `epoch`, genesis for epoch 0. None until the
+    /// head reaches the epoch.
+    pub fn epoch_boundary(&self, epoch: u64) -> Option<Block> {
+        let start = epoch.checked_mul(genesis::EPOCH_LENGTH)?;
+        let head = self.current_block();
+        if head.header.slot < start {
+            return None;
+        }
+        let (mut low, mut high) = (0, head.height());
+        while low < high {
+            let mid = high - (high - low) / 2;
+            if self.db.get_header_by_number(mid)?.slot < start {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        self.db.get_block_by_number(low)
+    }
+
+    /// Staking address of the validator sealing `block` on top of `parent`, looked up in the
+    /// committee of the block's epoch
+    fn proposer_of(&self, parent: &Header, block: &Block) -> Option<Address> {
+        let committee = self.epoch_committee(parent, committee::epoch_of(block.header.slot)).ok();
+        authorship::proposer_in(block, committee.as_ref(), self.address_scheme)
+    }
+
+    /// Check that the proposer of `block` on top of `parent` was elected for its slot by the
+    /// committee of the block's epoch.
+    pub fn verify_proposer(&self, parent: &Header, block: &Block) -> Result<(), Error> {
+        let committee = self.epoch_committee(parent, committee::epoch_of(block.header.slot))?;
+        committee.verify_proposer(block)
+            .map_err(|e| BlockChainErrorKind::InvalidAuthority.reason(e).into())
+    }
+
+    /// Whether the proposer of APoS `block` is checked on import, from the `proposer_check`
+    /// fork on
+    fn checks_proposer(&self, block: &Block) -> bool {
+        self.consensus.name() == "apos" && self.spec.forks.at(block.height()).proposer_check
+    }
+
+    /// Last slot checked for missed proposals, None before the first check
+    pub fn missed_proposal_checkpoint(&self) -> Option<u64> {
+        self.db.missed_checkpoint()
+    }
+
+    pub fn set_missed_proposal_checkpoint(&mut self, slot: u64) {
+        self.db.write_missed_checkpoint(slot).expect("can not write missed proposal checkpoint");
+    }
+
+    /// Keep a gossip block received ahead of the head on disk until the chain reaches it
+    pub fn spill_queued_block(&mut self, block: &Block) {
+        self.db.write_queued_block(block).expect("can not write queued block");
+    }
+
+    /// Remove a block kept with `spill_queued_block` and return it
+    pub fn take_queued_block(&mut self, height: u64, hash: &Hash) -> Option<Block> {
+        let block = self.db.get_queued_block(height, hash);
+        self.db.delete_queued_block(height, hash).expect("can not delete queued block");
+        block
+    }
+
+    /// A block kept with `spill_queued_block`, left in place
+    pub fn get_queued_block(&self, height: u64, hash: &Hash) -> Option<Block> {
+        self.db.get_queued_block(height, hash)
+    }
+
+    /// Heights and hashes of the blocks kept with `spill_queued_block`
+    pub fn queued_blocks(&self) -> Vec<(u64, Hash)> {
+        self.db.queued_blocks()
+    }
+
+    /// Block the chain was bootstrapped from with `import_checkpoint`, if any
+    pub fn checkpoint(&self) -> Option<Hash> {
+        self.db.state_checkpoint()
+    }
+
+    /// Check the head and the `depth` heights below it from their headers: the head block is
+    /// stored and its state root node is in the state db, each height is indexed to a stored
+    /// block of that height whose parent is the block indexed one height lower and has its fee
+    /// statistics, readable staking events and the proposer index of its epoch. The walk stops
+    /// at a checkpoint, which has no parent.
+    pub fn audit(&self, depth: u64) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let head = match self.db.head_hash().map(|hash| (hash, self.db.get_header(&hash))) {
+            Some((hash, Some(head))) if self.db.has_block(&hash) => head,
+            Some((hash, _)) => {
+                violations.push(Violation::new("head", format!("head {} is not stored", hash)));
+                return violations;
+            }
+            None => {
+                violations.push(Violation::new("head", "no head recorded".to_string()));
+                return violations;
+            }
+        };
+        if !self.has_state(head.state_root) {
+            violations.push(Violation::new("head_state", format!(
+                "state root {} of head {} is missing", head.state_root, head.height)));
+        }
+
+        let checkpoint = self.checkpoint();
+        let lowest = head.height.saturating_sub(depth).max(1);
+        let mut epochs = BTreeSet::new();
+        for height in (lowest..=head.height).rev() {
+            let hash = match self.db.get_header_hash(height) {
+                Some(hash) => hash,
+                None => {
+                    violations.push(Violation::new("height_index", format!("height {} is not indexed", height)));
+                    continue;
+                }
+            };
+            let header = match self.db.get_header(&hash) {
+                Some(header) if self.db.has_block(&hash) => header,
+                _ => {
+                    violations.push(Violation::new("height_index", format!(
+                        "height {} is indexed to missing block {}", height, hash)));
+                    continue;
+                }
+            };
+            if header.height != height {
+                violations.push(Violation::new("height_index", format!(
+                    "height {} is indexed to block {} of height {}", height, hash, header.height)));
+            }
+            if !self.db.staking_events_readable(&hash) {
+                violations.push(Violation::new("staking_index", format!(
+                    "staking events of block {} at height {} can not be read", hash, height)));
+            }
+            epochs.insert(committee::epoch_of(header.slot));
+            if checkpoint == Some(hash) {
+                break;
+            }
+            // Checkpoints are not executed here, so they have no fee statistics
+            if self.db.get_fee_stats(&hash).is_none() {
+                violations.push(Violation::new("fee_index", format!(
+                    "block {} at height {} has no fee statistics", hash, height)));
+            }
+            if self.db.get_header_hash(height - 1) != Some(header.parent_hash) {
+                violations.push(Violation::new("height_index", format!(
+                    "block {} at height {} is not on the block indexed at height {}", hash, height, height - 1)));
+            }
+        }
+
+        for epoch in epochs.iter() {
+            for proposed in self.db.get_proposed_blocks(*epoch) {
+                let heights = self.db.get_proposer_index(&proposed.validator, *epoch);
+                if heights.len() as u64 != proposed.blocks {
+                    violations.push(Violation::new("proposer_index", format!(
+                        "{} proposed {} blocks in epoch {}, {} are indexed",
+                        proposed.validator, proposed.blocks, epoch, heights.len())));
+                }
+                for height in heights.into_iter().filter(|h| *h >= lowest && *h <= head.height) {
+                    let slot = self.db.get_header_by_number(height).map(|header| header.slot);
+                    if slot.map(committee::epoch_of) != Some(*epoch) {
+                        violations.push(Violation::new("proposer_index", format!(
+                            "block of {} at height {} is indexed in epoch {} but is not in it",
+                            proposed.validator, height, epoch)));
+                    }
+                }
+            }
+        }
+        violations
+    }
+
+    /// Start a fresh chain from a trusted block instead of replaying its ancestors. The state
+    /// of the block must already be in the state db, see `map_core::state_sync`. The block
+    /// becomes the head and later blocks are imported on top of it as usual.
+    pub fn import_checkpoint(&mut self, block: &Block) -> Result<(), Error> {
+        if self.current_block().height() != 0 {
+            return Err(BlockChainErrorKind::KnownBlock.reason("chain is past genesis").into());
+        }
+        if block.height() == 0 {
+            return Err(BlockChainErrorKind::InvalidBlockHeight.into());
+        }
+        if !self.has_state(block.state_root()) {
+            return Err(BlockChainErrorKind::InvalidState.reason(format!("missing state root {}", block.state_root())).into());
+        }
+        // The ancestors electing its proposer are not known, the checkpoint is trusted for it
+        self.verify_block(None, block)?;
+
+        let hash = block.hash();
+        self.db.write_block(block).expect("can not write block");
+        self.db.write_header_hash(block.height(), &hash).expect("can not write height index");
+        self.db.write_state_checkpoint(&hash).expect("can not write checkpoint");
+        self.db.write_committed_hash(&hash).expect("can not write committed block");
+        self.db.write_head_hash(hash).expect("can not wirte head");
+        info!("import checkpoint, height={}, hash={}", block.height(), hash);
+        Ok(())
+    }
+
+    /// Lowest height from which the state of every canonical block is kept. Below it only
+    /// the genesis state is left, the rest was pruned or predates the checkpoint.
+    pub fn earliest_state_height(&self) -> u64 {
+        let checkpoint = self.checkpoint().and_then(|hash| self.get_block(hash)).map_or(0, |b| b.height());
+        checkpoint.max(self.db.pruned_height().unwrap_or(0))
+    }
+
+    /// Release the state of the heights more than `STATE_HISTORY` below the head, at most
+    /// `PRUNE_BATCH` of them: the nodes their canonical block dropped and those only the other
+    /// blocks imported there wrote. Nothing is deleted while a `StatePin` is held. Returns the
+    /// number of trie nodes deleted.
+    pub fn prune_state(&mut self) -> Result<usize, String> {
+        if self.state_readers.load(Ordering::SeqCst) > 0 {
+            return Ok(0);
+        }
+        let target = self.current_block().height().saturating_sub(STATE_HISTORY);
+        let mut height = self.earliest_state_height();
+        let last = target.min(height + PRUNE_BATCH);
+        let mut pruned = 0;
+        while height < last {
+            height += 1;
+            let canonical = self.db.get_header_hash(height);
+            let journals = self.db.state_journals(height);
+            // Recorded first, a pruning cut short leaves less state than the marker promises
+            self.db.write_pruned_height(height).map_err(|e| format!("can not write pruned height: {}", e))?;
+            let release: Vec<(StateJournal, bool)> = journals.iter()
+                .map(|(hash, journal)| (journal.clone(), Some(*hash) == canonical))
+                .collect();
+            pruned += self.state_backend.release(&release).map_err(|e| format!("can not delete state: {}", e))?;
+            for (hash, _) in journals {
+                self.db.delete_state_journal(height, &hash).map_err(|e| format!("can not delete state journal: {}", e))?;
+            }
+        }
+        Ok(pruned)
+    }
+
     pub fn apply_transactions(&self, root: Hash, b: &Block) -> Hash {
+        self.execute_block(root, b).expect("execute block transactions").0
+    }
+
+    /// Execute `b` on the state `root`. The state written is not kept beyond what other states
+    /// reference, unless pruning is off.
+    fn execute_block(&self, root: Hash, b: &Block) -> Result<(Hash, Vec<(Hash, StakingEvent)>), Error> {
+        let (executed, journal) = self.execute_journaled(root, b);
+        self.abandon_state(journal);
+        executed
+    }
+
+    /// Execute `b` on the state `root`, with the state node references it wrote
+    fn execute_journaled(&self, root: Hash, b: &Block) -> (Result<(Hash, Vec<(Hash, StakingEvent)>), Error>, StateJournal) {
         let statedb = self.state_at(root);
-        let h = Executor::exc_txs_in_block(&b, &mut Balance::new(Interpreter::new(statedb)), &Address::default()).unwrap();
-        h
+        let rules = self.spec.forks.at(b.height());
+        let executed = Executor::exc_block_with_events(&b, &mut Balance::new(Interpreter::new(statedb.clone())), &Address::default(), &rules);
+        let journal = statedb.borrow_mut().take_journal();
+        (executed, journal)
+    }
+
+    /// Take back the state node references of a state change that is not kept, deleting the
+    /// nodes no other state uses. Callers executing blocks outside the import pass the journal
+    /// of their `StateDB`.
+    pub fn abandon_state(&self, journal: StateJournal) {
+        if journal.is_empty() {
+            return;
+        }
+        if let Err(e) = self.state_backend.release(&[(journal, false)]) {
+            warn!("can not release abandoned state: {}", e);
+        }
     }
 
     pub fn insert_block(&mut self, block: Block) -> Result<(), Error> {
         self.import_block(&block)
     }
 
+    /// Checks that don't need executing the block: structure, seal and proposer against the
+    /// parent header. Gossiped blocks passing them are forwarded before being imported.
+    pub fn pre_verify(&self, block: &Block) -> Result<(), Error> {
+        if self.exits_block(block.hash(), block.height()) {
+            return Err(BlockChainErrorKind::KnownBlock.into());
+        }
+        let parent = match self.get_block(block.header.parent_hash) {
+            Some(b) => b,
+            None => return Err(BlockChainErrorKind::UnknownAncestor.into()),
+        };
+
+        self.validator.validate_header(self, &block.header)?;
+        self.consensus.verify_header(&parent.header, &block.header)?;
+        self.verify_block(Some(&parent.header), block)
+    }
+
+    /// Check the transaction and signature roots, the seal and the proposer, skipped for blocks
+    /// which already passed them, e.g. when a sync batch is retried. The proposer is checked
+    /// against the committee seen from `parent`, not at all without one.
+    fn verify_block(&self, parent: Option<&Header>, block: &Block) -> Result<(), Error> {
+        let digest = verify_cache::digest(block);
+        if self.verify_cache.is_verified(&digest) {
+            return Ok(());
+        }
+        self.validator.validate_block(self, block)?;
+        self.consensus.verify_seal(block)?;
+        if let Some(parent) = parent.filter(|_| self.checks_proposer(block)) {
+            self.verify_proposer(parent, block)?;
+        }
+        self.verify_cache.insert(digest);
+        Ok(())
+    }
+
+    /// Rebuild the data derived from the canonical blocks: the height index, fee statistics,
+    /// staking events and blocks by proposer. Blocks are streamed from the head down to genesis
+    /// and re-executed on their parent state, stopping at the checkpoint of a bootstrapped chain
+    /// or the pruned states of a full node. `progress` gets the number of blocks done and the
+    /// total.
+    pub fn reindex<F: FnMut(u64, u64)>(&mut self, mut progress: F) -> Result<(), Error> {
+        let head = self.current_block();
+        let bottom = self.earliest_state_height();
+        let total = head.height() - bottom;
+
+        // Forget heights above the head left by abandoned branches
+        self.db.delete_header_heights_above(head.height()).expect("can not delete height index");
+
+        let mut proposers = ProposerIndex::default();
+        let mut block = head;
+        let mut done = 0;
+        while block.height() > bottom {
+            let parent = self.get_block(block.header.parent_hash)
+                .ok_or_else(|| BlockChainErrorKind::UnknownAncestor.reason(format!("parent of block {}", block.height())))?;
+            let (_, events) = self.execute_block(parent.state_root(), &block)
+                .map_err(|e| BlockChainErrorKind::InvalidState.reason(e))?;
+
+            let hash = block.hash();
+            self.db.write_header_hash(block.height(), &hash).expect("can not write height index");
+            let fees = BlockFeeStats::from_block(&block);
+            self.db.write_fee_stats(&hash, &fees).expect("can not write fee stats");
+            proposers.record(self, &parent.header, &block, fees.total_fees);
+            if events.is_empty() {
+                self.db.delete_staking_events(&hash).expect("can not delete staking events");
+            } else {
+                self.db.write_staking_events(&hash, &events).expect("can not write staking events");
+            }
+
+            done += 1;
+            if done % REINDEX_PROGRESS_INTERVAL == 0 {
+                progress(done, total);
+            }
+            block = parent;
+        }
+        self.db.write_header_hash(block.height(), &block.hash()).expect("can not write height index");
+        proposers.write(&mut self.db)?;
+        progress(total, total);
+        Ok(())
+    }
+
+    /// Rebuild the blocks and block heights of each proposer from the stored canonical blocks,
+    /// without executing them. Proposers are found by their staking address in the committee of
+    /// the epoch. `progress` gets the number of blocks done and the total.
+    pub fn index_proposers<F: FnMut(u64, u64)>(&mut self, mut progress: F) -> Result<(), Error> {
+        let head = self.current_block();
+        let total = head.height();
+        let mut proposers = ProposerIndex::default();
+        let mut block = head;
+        let mut done = 0;
+        while block.height() > 0 {
+            // Bootstrapped chains start at their checkpoint
+            let parent = match self.get_block(block.header.parent_hash) {
+                Some(parent) => parent,
+                None => break,
+            };
+            let fees = BlockFeeStats::from_block(&block);
+            proposers.record(self, &parent.header, &block, fees.total_fees);
+
+            done += 1;
+            if done % REINDEX_PROGRESS_INTERVAL == 0 {
+                progress(done, total);
+            }
+            block = parent;
+        }
+        proposers.write(&mut self.db)?;
+        progress(total, total);
+        Ok(())
+    }
+
+    /// Append `block` to the canonical chain. Only children of the current head are accepted,
+    /// imports never rewind the canonical chain. Only the recovery at load rolls the head back,
+    /// within the max reorg depth unless an operator allows more.
     pub fn import_block(&mut self, block: &Block) -> Result<(), Error> {
+        if let Some(held) = &self.held_rewind {
+            return Err(BlockChainErrorKind::StateUnavailable.reason(format!(
+                "head rollback from height {} to {} held beyond the max reorg depth", held.from_height, held.to_height)).into());
+        }
+        let mut timer = ImportTimer::start(block.height(), block.hash());
         // Already in chain
         if self.exits_block(block.hash(), block.height()) {
             return Err(BlockChainErrorKind::KnownBlock.into());
@@ -155,18 +1093,108 @@ impl BlockChain {
         }
 
         self.validator.validate_header(self, &block.header)?;
-        self.validator.validate_block(self, block)?;
+        self.consensus.verify_header(&current.header, &block.header)?;
+        timer.stage(import_timer::STAGE_VERIFY_HEADER);
+        self.verify_block(Some(&current.header), block)?;
+        timer.stage(import_timer::STAGE_VERIFY_BLOCK);
 
-        if block.state_root() != self.apply_transactions(current.state_root(), block) {
-            return Err(BlockChainErrorKind::InvalidState.into());
-        }
+        // A state read over missing nodes gives a wrong root, which is no fault of the block
+        self.state_backend.take_missing();
+        let (executed, journal) = self.execute_journaled(current.state_root(), block);
+        let checked = self.check_state_nodes(current.state_root())
+            .and_then(|_| executed.map_err(|e| BlockChainErrorKind::InvalidState.reason(e).into()))
+            .and_then(|(state_root, events)| if block.state_root() == state_root {
+                Ok(events)
+            } else {
+                Err(BlockChainErrorKind::InvalidState.into())
+            });
+        let events = match checked {
+            Ok(events) => events,
+            Err(e) => {
+                self.abandon_state(journal);
+                return Err(e);
+            }
+        };
+        timer.stage(import_timer::STAGE_EXECUTE);
 
         self.db.write_block(&block).expect("can not write block");
+        if !journal.is_empty() {
+            self.db.write_state_journal(block.height(), &block.hash(), &journal).expect("can not write state journal");
+        }
+        let fees = BlockFeeStats::from_block(block);
+        self.db.write_fee_stats(&block.hash(), &fees).expect("can not write fee stats");
+        if let Some(proposer) = self.proposer_of(&current.header, block) {
+            let epoch = committee::epoch_of(block.header.slot);
+            let mut proposed = self.db.get_proposed_blocks(epoch);
+            authorship::record(&mut proposed, proposer, fees.total_fees);
+            self.db.write_proposed_blocks(epoch, &proposed).expect("can not write proposed blocks");
+            let mut heights = self.db.get_proposer_index(&proposer, epoch);
+            heights.push(block.height());
+            self.db.write_proposer_index(&proposer, epoch, &heights).expect("can not write proposer index");
+        }
+        if !events.is_empty() {
+            self.db.write_staking_events(&block.hash(), &events).expect("can not write staking events");
+        }
+        // Blocks of a new epoch keep the validators of its boundary, available after pruning
+        if committee::epoch_of(block.header.slot) > committee::epoch_of(current.header.slot) {
+            let state = self.state_at(current.state_root());
+            let members = committee::members_of(&Staking::new(Interpreter::new(state)));
+            self.db.write_committee(&current.hash(), &members).expect("can not write committee");
+        }
+        // The state was flushed when executing the block
+        self.db.write_committed_hash(&block.hash()).expect("can not write committed block");
+        timer.stage(import_timer::STAGE_COMMIT);
         self.db.write_head_hash(block.header.hash()).expect("can not wirte head");
+        timer.stage(import_timer::STAGE_CANONICAL);
+        let author = self.author(block);
+        self.filters.on_block(block, author);
         info!("insert block, height={}, hash={}, previous={}", block.height(), block.hash(), block.header.parent_hash);
+        timer.finish(self.slow_import_threshold);
+        if self.mode == NodeMode::Full {
+            match self.prune_state() {
+                Ok(pruned) => debug!("pruned {} state nodes below height={}", pruned, self.earliest_state_height()),
+                Err(e) => warn!("state pruning failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+}
+
+/// Blocks and block heights of each proposer by epoch, collected from the head down.
+#[derive(Default)]
+struct ProposerIndex {
+    proposed: BTreeMap<u64, Vec<ProposedBlocks>>,
+    heights: BTreeMap<(Address, u64), Vec<u64>>,
+    committees: BTreeMap<u64, Option<EpochCommittee>>,
+}
+
+impl ProposerIndex {
+    fn record(&mut self, chain: &BlockChain, parent: &Header, block: &Block, fees: u128) {
+        let epoch = committee::epoch_of(block.header.slot);
+        let members = self.committees.entry(epoch)
+            .or_insert_with(|| chain.epoch_committee(parent, epoch).ok());
+        if let Some(proposer) = authorship::proposer_in(block, members.as_ref(), chain.address_scheme()) {
+            authorship::record(self.proposed.entry(epoch).or_default(), proposer, fees);
+            self.heights.entry((proposer, epoch)).or_default().push(block.height());
+        }
+    }
+
+    fn write(self, db: &mut ChainDB) -> Result<(), Error> {
+        for (epoch, blocks) in self.proposed {
+            db.write_proposed_blocks(epoch, &blocks).map_err(storage_error("proposed blocks"))?;
+        }
+        for ((proposer, epoch), mut heights) in self.heights {
+            heights.reverse();
+            db.write_proposer_index(&proposer, epoch, &heights).map_err(storage_error("proposer index"))?;
+        }
         Ok(())
     }
+}
 
+/// Error of a failed chain db write of `what`
+fn storage_error(what: &'static str) -> impl Fn(std::io::Error) -> Error {
+    move |e| BlockChainErrorKind::Storage.reason(format!("can not write {}: {}", what, e)).into()
 }
 
 pub struct Validator;
@@ -201,6 +1229,18 @@ impl Validator {
         if header.time <= pre.header.time {
             return Err(BlockChainErrorKind::InvalidBlockTime.into());
         }
+
+        // Ensure block time falls in its slot, or the next one for a slow proposer
+        let start = genesis::slot_start(chain.genesis_time(), header.slot);
+        if header.time < start || header.time >= start.saturating_add(2 * genesis::SLOT_DURATION) {
+            return Err(BlockChainErrorKind::SlotTimeMismatch.into());
+        }
+
+        // Ensure block time is not ahead of the local clock
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        if header.time > now + MAX_FUTURE_DRIFT {
+            return Err(BlockChainErrorKind::FutureBlockTime.into());
+        }
         Ok(())
     }
 }
@@ -208,24 +1248,62 @@ impl Validator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use map_core::balance::Balance;
-    use std::time::SystemTime;
+    use std::collections::HashSet;
+    use std::fs;
+    use map_consensus::poa;
+    use map_store::compress::Codec;
+    use map_core::chain_spec::Rules;
+    use map_core::state_sync::StateSync;
+    use map_core::test_utils::{self, BlockBuilder, ChainBuilder};
+    use crate::audit::AUDIT_DEPTH;
+    use crate::filters::FilterKind;
+
+    fn test_chain(name: &str) -> BlockChain {
+        test_chain_with_spec(name, &ChainSpec::default())
+    }
+
+    fn test_chain_with_spec(name: &str, spec: &ChainSpec) -> BlockChain {
+        let dir = std::env::temp_dir().join(format!("map_chain_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        let mut chain = BlockChain::new(dir, spec).unwrap();
+        chain.load().unwrap();
+        chain
+    }
 
     #[test]
     fn test_init() {
-        let mut chain = BlockChain::new(PathBuf::from("./mapdata"),"".to_string());
-        let mut state = Balance::new(PathBuf::from("./balance"));
-        chain.load(&mut state);
+        let chain = test_chain("init");
         assert_eq!(chain.genesis.height(), 0);
         assert_eq!(chain.genesis.header.parent_hash, Hash::default());
         assert!(chain.get_block_by_number(0).is_some());
     }
 
+    #[test]
+    fn test_genesis_from_spec() {
+        let mainnet = test_chain("genesis_mainnet");
+        let testnet = test_chain_with_spec("genesis_testnet", &ChainSpec::testnet());
+        assert_eq!(testnet.genesis_time(), ChainSpec::testnet().genesis.time);
+        assert_eq!(testnet.spec().network_id, ChainSpec::testnet().network_id);
+        assert_ne!(mainnet.genesis_hash(), testnet.genesis_hash());
+        assert_eq!(testnet.genesis_hash(), genesis::genesis_hash(&ChainSpec::testnet().genesis));
+    }
+
+    #[test]
+    fn test_datadir_in_use() {
+        let chain = test_chain("datadir_in_use");
+        let dir = std::env::temp_dir().join("map_chain_test_datadir_in_use");
+        match BlockChain::new(dir.clone(), &ChainSpec::default()) {
+            Err(e) => assert_eq!(*e.kind(), errors::ErrorKind::Storage),
+            Ok(_) => panic!("datadir opened twice"),
+        }
+
+        drop(chain);
+        assert!(BlockChain::new(dir, &ChainSpec::default()).is_ok());
+    }
+
     #[test]
     fn test_insert_empty() {
-        let mut chain = BlockChain::new(PathBuf::from("./mapdata"),"".to_string());
-        let mut state = Balance::new(PathBuf::from("./balance"));
-        chain.load(&mut state);
+        let mut chain = test_chain("insert_empty");
         {
             let block = Block {
                 header: Header{
@@ -239,21 +1317,693 @@ mod tests {
         }
 
         {
-            let mut block = Block::default();
-            block.header.height = 1;
-            block.header.parent_hash = chain.genesis_hash();
+            // Block time not after parent
+            let genesis = chain.current_block();
+            let block = BlockBuilder::on(&genesis).time(genesis.header.time).build();
             let ret = chain.insert_block(block);
             assert!(ret.is_err());
         }
 
         {
-            let mut block = Block::default();
-            block.header.height = 1;
-            block.header.parent_hash = chain.genesis_hash();
-            block.header.time = SystemTime::now().duration_since(
-                SystemTime::UNIX_EPOCH).unwrap().as_secs();
+            let genesis = chain.current_block();
+            let block = BlockBuilder::on(&genesis).build();
             let ret = chain.insert_block(block);
-            assert!(ret.is_err());
+            assert!(ret.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_missed_proposals_persist() {
+        let validator = Address([1u8; 20]);
+        {
+            let mut chain = test_chain("missed_proposals");
+            assert_eq!(chain.missed_proposal_checkpoint(), None);
+            chain.record_missed_proposal(2, validator, 130);
+            chain.record_missed_proposal(2, validator, 130);
+            chain.set_missed_proposal_checkpoint(131);
+        }
+
+        let dir = std::env::temp_dir().join("map_chain_test_missed_proposals");
+        let chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        let missed = chain.get_missed_proposals(2);
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].slots, vec![130]);
+        assert!(chain.get_missed_proposals(1).is_empty());
+        assert_eq!(chain.missed_proposal_checkpoint(), Some(131));
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = std::env::temp_dir().join("map_chain_test_compression");
+        let _ = fs::remove_dir_all(&dir);
+        let compression = Compression { blocks: Codec::Snappy, state: Codec::Lz4 };
+        let builder = {
+            let mut chain = BlockChain::with_compression(dir.clone(), &ChainSpec::default(), compression).unwrap();
+            chain.load().unwrap();
+            let builder = ChainBuilder::new(chain.current_block()).extend(2);
+            for b in builder.descendants() {
+                chain.import_block(b).unwrap();
+            }
+            builder
+        };
+
+        // Compressed values are read back whatever the codecs in use
+        let mut chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        chain.load().unwrap();
+        assert_eq!(chain.current_block(), *builder.head());
+        let rich = test_utils::address_of(&test_utils::genesis_key());
+        let state = Balance::new(Interpreter::new(chain.state_at(builder.head().state_root())));
+        assert!(state.get_account(rich).get_balance() > 0);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let builder = ChainBuilder::new(chain.current_block()).extend(2);
+        for b in builder.descendants() {
+            chain.import_block(b).unwrap();
+        }
+        assert_eq!(chain.current_block(), *builder.head());
+        assert_eq!(chain.genesis_hash(), test_chain("in_memory").genesis_hash());
+    }
+
+    #[test]
+    fn test_audit() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let blocks = ChainBuilder::new(chain.current_block()).extend(3).descendants().to_vec();
+        for b in &blocks {
+            chain.import_block(b).unwrap();
         }
+        assert_eq!(chain.audit(AUDIT_DEPTH), vec![]);
+
+        // A proposer counted for two blocks of the epoch with one indexed
+        let epoch = committee::epoch_of(blocks[0].header.slot);
+        let validator = Address::default();
+        chain.db.write_proposed_blocks(epoch, &[ProposedBlocks { validator, blocks: 2, rewards: 0 }]).unwrap();
+        chain.db.write_proposer_index(&validator, epoch, &[1]).unwrap();
+        chain.db.write_header_hash(2, &Hash([9u8; 32])).unwrap();
+        let checks: Vec<&str> = chain.audit(AUDIT_DEPTH).iter().map(|v| v.check).collect();
+        // The height points at no block and the block above is not on it
+        assert_eq!(checks, vec!["height_index", "height_index", "proposer_index"]);
+    }
+
+    #[test]
+    fn test_filters_on_import() {
+        let mut chain = test_chain("filters_on_import");
+        let id = chain.filters().install(FilterKind::Blocks { author: None });
+        let builder = ChainBuilder::new(chain.current_block()).extend(2);
+        for b in builder.descendants() {
+            chain.import_block(b).unwrap();
+        }
+        let hashes: Vec<Hash> = builder.descendants().iter().map(|b| b.hash()).collect();
+        assert_eq!(chain.filters().poll(id), Some(hashes));
+    }
+
+    #[test]
+    fn test_recover_head() {
+        let dir = std::env::temp_dir().join("map_chain_test_recover_head");
+        let _ = fs::remove_dir_all(&dir);
+        let validator = Address([7u8; 20]);
+        let (blocks, lost) = {
+            let mut chain = BlockChain::new(dir.clone(), &ChainSpec::default()).unwrap();
+            chain.load().unwrap();
+            let blocks = ChainBuilder::new(chain.current_block()).extend(3).descendants().to_vec();
+            for b in &blocks {
+                chain.import_block(b).unwrap();
+            }
+            assert_eq!(chain.db.committed_hash(), Some(blocks[2].hash()));
+
+            // Crash after writing the head and indexes of a block whose state never reached the disk
+            let tx = test_utils::transfer(&test_utils::genesis_key(), 1, test_utils::test_address(1), 100);
+            let lost = BlockBuilder::on(&blocks[2]).txs(vec![tx]).state_root(Hash([1u8; 32])).build();
+            chain.db.write_block(&lost).unwrap();
+            let epoch = committee::epoch_of(lost.header.slot);
+            chain.db.write_fee_stats(&lost.hash(), &BlockFeeStats { tx_count: 1, total_fees: 5, ..Default::default() }).unwrap();
+            chain.db.write_proposed_blocks(epoch, &[ProposedBlocks { validator, blocks: 1, rewards: 5 }]).unwrap();
+            chain.db.write_proposer_index(&validator, epoch, &[4]).unwrap();
+            chain.db.write_head_hash(lost.hash()).unwrap();
+            // and with the marker left behind the head
+            chain.db.write_committed_hash(&blocks[0].hash()).unwrap();
+            (blocks, lost)
+        };
+
+        // Rolled back to the committed block, the stored blocks with a valid state imported again
+        let mut chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        chain.load().unwrap();
+        assert_eq!(chain.current_block(), blocks[2]);
+        assert_eq!(chain.db.committed_hash(), Some(blocks[2].hash()));
+        assert!(chain.get_block_by_number(4).is_none());
+        // The indexes of the block left out are dropped, those imported again rebuilt
+        let epoch = committee::epoch_of(lost.header.slot);
+        assert_eq!(chain.db.get_fee_stats(&lost.hash()), None);
+        assert!(chain.db.get_fee_stats(&blocks[1].hash()).is_some());
+        assert_eq!(chain.db.get_proposed_blocks(epoch), vec![]);
+        assert_eq!(chain.db.get_proposer_index(&validator, epoch), Vec::<u64>::new());
+        assert_eq!(chain.head_rewind(), Some(HeadRewind { from_height: 4, to_height: 1, head_height: 3 }));
+        assert_eq!(chain.held_rewind(), None);
+        // Only the transactions of the block left out go back to the pool
+        assert_eq!(chain.unwound_transactions(), lost.get_txs().clone());
+    }
+
+    #[test]
+    fn test_deep_rollback_held() {
+        let dir = std::env::temp_dir().join("map_chain_test_deep_rollback_held");
+        let _ = fs::remove_dir_all(&dir);
+        let blocks = {
+            let mut chain = BlockChain::new(dir.clone(), &ChainSpec::default()).unwrap();
+            chain.load().unwrap();
+            let blocks = ChainBuilder::new(chain.current_block()).extend(3).descendants().to_vec();
+            for b in &blocks {
+                chain.import_block(b).unwrap();
+            }
+            let lost = BlockBuilder::on(&blocks[2]).state_root(Hash([1u8; 32])).build();
+            chain.db.write_block(&lost).unwrap();
+            chain.db.write_head_hash(lost.hash()).unwrap();
+            chain.db.write_committed_hash(&blocks[0].hash()).unwrap();
+            blocks
+        };
+
+        let mut chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        chain.set_max_reorg_depth(2);
+        chain.load().unwrap();
+        // Left as found, refusing imports
+        assert_eq!(chain.current_block().height(), 4);
+        assert_eq!(chain.held_rewind(), Some(HeadRewind { from_height: 4, to_height: 1, head_height: 4 }));
+        assert!(chain.import_block(&BlockBuilder::on(&blocks[2]).build()).is_err());
+
+        assert_eq!(chain.allow_deep_reorg(), Some(HeadRewind { from_height: 4, to_height: 1, head_height: 3 }));
+        assert_eq!(chain.current_block(), blocks[2]);
+        assert_eq!(chain.held_rewind(), None);
+        assert_eq!(chain.allow_deep_reorg(), None);
+    }
+
+    #[test]
+    fn test_reindex() {
+        let mut chain = test_chain("reindex");
+        let genesis = chain.current_block();
+        let blocks = ChainBuilder::new(genesis).extend(3).descendants().to_vec();
+        for b in &blocks {
+            chain.import_block(b).unwrap();
+        }
+
+        // Damage the derived data
+        chain.db.write_fee_stats(&blocks[1].hash(), &BlockFeeStats { tx_count: 9, ..Default::default() }).unwrap();
+        chain.db.write_header_hash(2, &Hash([7u8; 32])).unwrap();
+        chain.db.write_header_hash(10, &Hash([7u8; 32])).unwrap();
+
+        let mut reports = Vec::new();
+        chain.reindex(|done, total| reports.push((done, total))).unwrap();
+        assert_eq!(reports, vec![(3, 3)]);
+        assert_eq!(chain.get_fee_stats(blocks[1].hash()), Some(BlockFeeStats::from_block(&blocks[1])));
+        assert_eq!(chain.get_block_by_number(2), Some(blocks[1].clone()));
+        assert_eq!(chain.db.get_header_hash(10), None);
+    }
+
+    #[test]
+    fn test_reindex_above_bottom() {
+        let mut chain = test_chain("reindex_above_bottom");
+        let blocks = ChainBuilder::new(chain.current_block()).extend(6).descendants().to_vec();
+        for b in &blocks {
+            chain.import_block(b).unwrap();
+        }
+        chain.db.write_pruned_height(4).unwrap();
+        chain.db.write_header_hash(7, &Hash([7u8; 32])).unwrap();
+        chain.db.write_header_hash(9, &Hash([7u8; 32])).unwrap();
+
+        let mut reports = Vec::new();
+        chain.reindex(|done, total| reports.push((done, total))).unwrap();
+        assert_eq!(reports, vec![(2, 2)]);
+        // Heights below the state window keep their index
+        for b in &blocks {
+            assert_eq!(chain.get_block_by_number(b.height()), Some(b.clone()));
+        }
+        assert_eq!(chain.db.get_header_hash(7), None);
+        assert_eq!(chain.db.get_header_hash(9), None);
+    }
+
+    #[test]
+    fn test_proposed_blocks() {
+        let mut chain = test_chain("proposed_blocks");
+        let key = test_utils::test_key(1);
+        let first = BlockBuilder::on(&chain.current_block()).signed_by(key).build();
+        let second = BlockBuilder::on(&first).signed_by(key).build();
+        let unsigned = BlockBuilder::on(&second).build();
+        for b in &[&first, &second, &unsigned] {
+            chain.import_block(b).unwrap();
+        }
+
+        let proposer = test_utils::address_of(&key);
+        let expected = vec![ProposedBlocks { validator: proposer, blocks: 2, rewards: 0 }];
+        assert_eq!(chain.get_proposed_blocks(0), expected);
+        assert_eq!(chain.get_blocks_by_proposer(&proposer, 0, 100), vec![1, 2]);
+        assert_eq!(chain.get_blocks_by_proposer(&proposer, 2, 3), vec![2]);
+        assert_eq!(chain.get_blocks_by_proposer(&Address([9u8; 20]), 0, 3), Vec::<u64>::new());
+
+        // Rebuilt without counting twice
+        chain.db.write_proposed_blocks(0, &[]).unwrap();
+        chain.db.write_proposer_index(&proposer, 0, &[]).unwrap();
+        chain.reindex(|_, _| {}).unwrap();
+        assert_eq!(chain.get_proposed_blocks(0), expected);
+        assert_eq!(chain.get_proposer_blocks(&proposer, 0), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_schema_migration() {
+        let mut chain = test_chain("schema_migration");
+        let genesis = chain.genesis_hash();
+        assert_eq!(chain.metadata(), Some(ChainMetadata::new(schema::SCHEMA_VERSION, genesis)));
+        let key = test_utils::test_key(1);
+        chain.import_block(&BlockBuilder::on(&chain.current_block()).signed_by(key).build()).unwrap();
+
+        // A db of a client predating the metadata and the proposer index
+        chain.db.write_metadata(&ChainMetadata::new(schema::UNVERSIONED, genesis)).unwrap();
+        let proposer = test_utils::address_of(&key);
+        chain.db.write_proposed_blocks(0, &[]).unwrap();
+        chain.db.write_proposer_index(&proposer, 0, &[]).unwrap();
+        chain.migrate().unwrap();
+        assert_eq!(chain.metadata().unwrap().schema_version, schema::SCHEMA_VERSION);
+        assert_eq!(chain.get_proposed_blocks(0).len(), 1);
+        assert_eq!(chain.get_proposer_blocks(&proposer, 0), vec![1]);
+
+        // Of another genesis
+        chain.db.write_metadata(&ChainMetadata::new(schema::SCHEMA_VERSION, Hash([1u8; 32]))).unwrap();
+        let err = chain.load().unwrap_err();
+        assert_eq!(err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone()),
+            Some(BlockChainErrorKind::GenesisMismatch));
+
+        // Written by a newer client
+        chain.db.write_metadata(&ChainMetadata::new(schema::SCHEMA_VERSION + 1, genesis)).unwrap();
+        drop(chain);
+        let dir = std::env::temp_dir().join("map_chain_test_schema_migration");
+        match BlockChain::new(dir.clone(), &ChainSpec::default()) {
+            Err(e) => assert_eq!(e.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone()),
+                Some(BlockChainErrorKind::UnsupportedSchema)),
+            Ok(_) => panic!("newer schema opened"),
+        }
+        assert!(BlockChain::open_read_only(dir).is_none());
+    }
+
+    #[test]
+    fn test_pre_verify_skips_execution() {
+        let mut chain = test_chain("pre_verify");
+        let genesis = chain.current_block();
+        let orphan = BlockBuilder::on(&BlockBuilder::on(&genesis).build()).build();
+        assert!(chain.pre_verify(&orphan).is_err());
+
+        // A wrong state root is only caught by executing the block
+        let block = BlockBuilder::on(&genesis).state_root(Hash([1u8; 32])).build();
+        chain.pre_verify(&block).unwrap();
+        let err = chain.import_block(&block).unwrap_err();
+        let kind = err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone());
+        assert_eq!(kind, Some(BlockChainErrorKind::InvalidState));
+    }
+
+    #[test]
+    fn test_state_unavailable() {
+        let chain = test_chain("state_unavailable");
+        let head = chain.current_block();
+        assert!(chain.has_state(head.state_root()));
+        assert!(chain.checked_state_at(head.state_root()).is_ok());
+
+        let missing = Hash([1u8; 32]);
+        assert!(!chain.has_state(missing));
+        let err = chain.checked_state_at(missing).err().unwrap();
+        let kind = err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone());
+        assert_eq!(kind, Some(BlockChainErrorKind::StateUnavailable));
+    }
+
+    #[test]
+    fn test_missing_state_nodes() {
+        let mut chain = test_chain("missing_state_nodes");
+        let mut source = BlockChain::new_in_memory(&ChainSpec::default());
+        source.load().unwrap();
+        let head = chain.current_block();
+        chain.state_backend.prune(&HashSet::new()).unwrap();
+
+        let block = BlockBuilder::on(&head).build();
+        let err = chain.import_block(&block).unwrap_err();
+        let kind = err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone());
+        assert_eq!(kind, Some(BlockChainErrorKind::MissingStateNodes));
+        assert_eq!(chain.unhealthy_state(), Some(head.state_root()));
+
+        // Fetch the nodes from a node of the same network and retry
+        let mut sync = StateSync::new(chain.statedb(), head.state_root());
+        while !sync.is_done() {
+            let batch = sync.next_batch(16);
+            sync.import(batch.iter().map(|key| source.statedb().get_node(key)).collect()).unwrap();
+        }
+        sync.commit();
+        chain.mark_healed(head.state_root());
+        assert_eq!(chain.unhealthy_state(), None);
+        chain.import_block(&block).unwrap();
+    }
+
+    #[test]
+    fn test_verify_cache() {
+        let mut chain = test_chain("verify_cache");
+        let genesis = chain.current_block();
+
+        // Verification outcome is kept when execution fails, a retry only re-executes
+        let block = BlockBuilder::on(&genesis).state_root(Hash([1u8; 32])).build();
+        assert!(chain.import_block(&block).is_err());
+        assert!(chain.verify_cache.is_verified(&verify_cache::digest(&block)));
+
+        // Failed checks are not cached
+        let mut bad = BlockBuilder::on(&genesis).build();
+        bad.header.tx_root = Hash([2u8; 32]);
+        assert!(chain.pre_verify(&bad).is_err());
+        assert!(!chain.verify_cache.is_verified(&verify_cache::digest(&bad)));
+
+        // A verified header with another body is checked again
+        let mut tampered = block.clone();
+        tampered.txs.push(test_utils::transfer(&test_utils::genesis_key(), 1, Address::default(), 1));
+        assert_eq!(tampered.hash(), block.hash());
+        assert!(chain.pre_verify(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_import_checkpoint() {
+        let mut source = test_chain("import_checkpoint");
+        let blocks = ChainBuilder::new(source.current_block()).extend(4).descendants().to_vec();
+        for b in &blocks[..3] {
+            source.import_block(b).unwrap();
+        }
+
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let orphan = BlockBuilder::on(&blocks[1]).state_root(Hash([1u8; 32])).build();
+        assert!(chain.import_checkpoint(&orphan).is_err());
+
+        chain.import_checkpoint(&blocks[2]).unwrap();
+        assert_eq!(chain.checkpoint(), Some(blocks[2].hash()));
+        assert_eq!(chain.get_block_by_number(3), Some(blocks[2].clone()));
+        assert!(chain.import_checkpoint(&blocks[2]).is_err());
+
+        // Sync goes on from the checkpoint, reindex stops there
+        chain.import_block(&blocks[3]).unwrap();
+        let mut reports = Vec::new();
+        chain.reindex(|done, total| reports.push((done, total))).unwrap();
+        assert_eq!(reports, vec![(1, 1)]);
+    }
+
+    #[test]
+    fn test_block_time_rules() {
+        let mut chain = test_chain("block_time_rules");
+        let genesis = chain.current_block();
+        let kind = |chain: &mut BlockChain, block: Block| {
+            let err = chain.import_block(&block).unwrap_err();
+            err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone())
+        };
+
+        let block = BlockBuilder::on(&genesis).time(genesis.header.time).build();
+        assert_eq!(kind(&mut chain, block), Some(BlockChainErrorKind::InvalidBlockTime));
+
+        // Time of a later slot than the header's
+        let block = BlockBuilder::on(&genesis).time(genesis.header.time + 3 * test_utils::BLOCK_INTERVAL).build();
+        assert_eq!(kind(&mut chain, block), Some(BlockChainErrorKind::SlotTimeMismatch));
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let slot = (now + 3600 - genesis.header.time) / genesis::SLOT_DURATION;
+        let block = BlockBuilder::on(&genesis)
+            .slot(slot)
+            .time(genesis::slot_start(genesis.header.time, slot))
+            .build();
+        assert_eq!(kind(&mut chain, block), Some(BlockChainErrorKind::FutureBlockTime));
+
+        // Produced late in the next slot
+        let block = BlockBuilder::on(&genesis).time(genesis.header.time + 2 * test_utils::BLOCK_INTERVAL - 1).build();
+        chain.import_block(&block).unwrap();
+    }
+
+    #[test]
+    fn test_import_same_slot() {
+        let mut chain = test_chain("import_same_slot");
+        let genesis = chain.current_block();
+        let block = BlockBuilder::on(&genesis).slot(genesis.header.slot).build();
+        assert!(chain.import_block(&block).is_err());
+    }
+
+    #[test]
+    fn test_import_poa_sealed() {
+        let mut chain = test_chain_with_spec("import_poa_sealed", &ChainSpec::poa("".to_string()));
+        let genesis = chain.current_block();
+        let block = BlockBuilder::on(&genesis).build();
+        assert!(chain.import_block(&block).is_err());
+
+        let sealed = chain.finalize_block(block, genesis.state_root()).unwrap();
+        chain.import_block(&sealed).unwrap();
+        assert_eq!(chain.author(&sealed), Some(poa::POA::get_default_miner(chain.address_scheme())));
+    }
+
+    #[test]
+    fn test_address_scheme_per_chain() {
+        let mut spec = ChainSpec::poa("".to_string());
+        spec.genesis.address_scheme = AddressScheme::Keccak256;
+        let mut keccak = test_chain_with_spec("address_scheme_keccak", &spec);
+        let blake = test_chain_with_spec("address_scheme_blake", &ChainSpec::poa("".to_string()));
+        // Both chains in one process derive addresses their own way
+        assert_eq!((keccak.address_scheme(), blake.address_scheme()), (AddressScheme::Keccak256, AddressScheme::Blake2b));
+
+        let genesis = keccak.current_block();
+        let sealed = keccak.finalize_block(BlockBuilder::on(&genesis).build(), genesis.state_root()).unwrap();
+        keccak.import_block(&sealed).unwrap();
+        assert_eq!(keccak.author(&sealed), Some(poa::POA::get_default_miner(AddressScheme::Keccak256)));
+        assert_ne!(keccak.author(&sealed), blake.author(&sealed));
+    }
+
+    #[test]
+    fn test_import_chain() {
+        let mut chain = test_chain("import_chain");
+        let builder = ChainBuilder::new(chain.current_block()).extend(3);
+        for b in builder.descendants() {
+            chain.import_block(b).unwrap();
+        }
+        assert_eq!(chain.current_block(), *builder.head());
+    }
+
+    #[test]
+    fn test_import_fork() {
+        let mut chain = test_chain("import_fork");
+        let main = ChainBuilder::new(chain.current_block()).extend(3);
+        for b in main.descendants() {
+            chain.import_block(b).unwrap();
+        }
+
+        let fork = main.fork_at(1).extend(1);
+        let ret = chain.import_block(&fork.blocks()[2]);
+        assert!(ret.is_err());
+        assert_eq!(chain.current_block(), *main.head());
+    }
+
+    #[test]
+    fn test_import_transfer() {
+        let mut chain = test_chain("import_transfer");
+        let (_, state) = test_utils::genesis_with_state();
+        let mut builder = ChainBuilder::new(chain.current_block())
+            .with_state_fn(Box::new(move |root, b| {
+                let statedb = Rc::new(RefCell::new(StateDB::from_existing(&state, root)));
+                Executor::exc_txs_in_block(b, &mut Balance::new(Interpreter::new(statedb)), &Address::default(), &Rules::default()).unwrap()
+            }));
+        let receiver = test_utils::test_address(1);
+        builder.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 1, receiver, 100)]);
+
+        chain.import_block(builder.head()).unwrap();
+        let stats = chain.db.get_fee_stats(&builder.head().hash()).unwrap();
+        assert_eq!(stats.tx_count, 1);
+        assert_eq!(stats.total_fees, executor::TRANSFER_FEE);
+        let state = chain.state_at(chain.current_block().state_root());
+        assert_eq!(Balance::new(Interpreter::new(state)).balance(receiver), 100);
+    }
+
+    #[test]
+    fn test_prune_state() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        chain.set_mode(NodeMode::Full).unwrap();
+        let state_fn = || {
+            let (_, state) = test_utils::genesis_with_state();
+            Box::new(move |root: Hash, b: &Block| {
+                let statedb = Rc::new(RefCell::new(StateDB::from_existing(&state, root)));
+                Executor::exc_txs_in_block(b, &mut Balance::new(Interpreter::new(statedb)), &Address::default(), &Rules::default()).unwrap()
+            })
+        };
+        let genesis = chain.current_block();
+        let mut builder = ChainBuilder::new(genesis.clone()).with_state_fn(state_fn());
+        let receiver = test_utils::test_address(1);
+        builder.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 1, receiver, 100)]);
+        builder.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 2, receiver, 100)]);
+        let builder = builder.extend(STATE_HISTORY);
+
+        // A block imported at height 1 and left off the canonical chain
+        let mut side = ChainBuilder::new(genesis.clone()).with_state_fn(state_fn());
+        side.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 1, test_utils::test_address(2), 7)]);
+        let (executed, journal) = chain.execute_journaled(genesis.state_root(), side.head());
+        let side_root = executed.unwrap().0;
+        assert!(!journal.is_empty());
+        chain.db.write_state_journal(1, &side.head().hash(), &journal).unwrap();
+
+        // Within the history every state is kept
+        let (blocks, pinned) = builder.descendants().split_at(STATE_HISTORY as usize);
+        for b in blocks {
+            chain.import_block(b).unwrap();
+        }
+        assert_eq!(chain.earliest_state_height(), 0);
+        assert!(chain.has_state(side_root));
+        // and nothing is deleted while a reader holds the state
+        let pin = chain.pin_state();
+        for b in pinned {
+            chain.import_block(b).unwrap();
+        }
+        assert_eq!(chain.earliest_state_height(), 0);
+        drop(pin);
+
+        assert!(chain.prune_state().unwrap() > 0);
+        assert_eq!(chain.earliest_state_height(), 2);
+        assert!(!chain.has_state(builder.blocks()[1].state_root()));
+        assert!(!chain.has_state(side_root));
+        assert!(chain.db.state_journals(1).is_empty());
+        assert!(chain.has_state(builder.blocks()[2].state_root()));
+        assert!(chain.has_state(chain.genesis.state_root()));
+        let state = chain.state_at(chain.current_block().state_root());
+        assert_eq!(Balance::new(Interpreter::new(state)).balance(receiver), 200);
+        // Nothing left to delete until the head moves on
+        assert_eq!(chain.prune_state().unwrap(), 0);
+
+        // Pruned states are not brought back by an archive node
+        assert!(chain.set_mode(NodeMode::Archive).is_err());
+    }
+
+    #[test]
+    fn test_staking_events() {
+        let mut spec = ChainSpec::default();
+        spec.forks.module_calls = Some(0);
+        let mut chain = test_chain_with_spec("staking_events", &spec);
+        let (_, state) = test_utils::genesis_with_state();
+        let rules = spec.forks.at(1);
+        let mut builder = ChainBuilder::new(chain.current_block())
+            .with_state_fn(Box::new(move |root, b| {
+                let statedb = Rc::new(RefCell::new(StateDB::from_existing(&state, root)));
+                Executor::exc_txs_in_block(b, &mut Balance::new(Interpreter::new(statedb)), &Address::default(), &rules).unwrap()
+            }));
+        let key = test_utils::test_key(1);
+        let addr = test_utils::test_address(1);
+        let mut deposit = map_core::transaction::Transaction::new(addr, 1, 1000, 1000,
+            b"staking.deposit".to_vec(), bincode::serialize(&100u128).unwrap());
+        deposit.sign(&key.to_bytes()).unwrap();
+        let create = map_core::staking::MsgValidatorCreate { pubkey: vec![1; 32], amount: 500 };
+        let mut validate = map_core::transaction::Transaction::new(addr, 2, 1000, 1000,
+            b"staking.validate".to_vec(), bincode::serialize(&create).unwrap());
+        validate.sign(&key.to_bytes()).unwrap();
+        builder.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 1, addr, 100000), deposit, validate.clone()]);
+
+        chain.import_block(builder.head()).unwrap();
+        let head = builder.head();
+        let created = StakingEventRecord {
+            height: head.height(),
+            block_hash: head.hash(),
+            tx_hash: validate.hash(),
+            event: StakingEvent::ValidatorCreated { address: addr, pubkey: vec![1; 32], amount: 500 },
+        };
+        // The deposit ran before the validator existed and emitted nothing
+        assert_eq!(chain.get_staking_events(0, 10, None), vec![created.clone()]);
+        assert_eq!(chain.get_staking_events(0, 10, Some(&addr)), vec![created]);
+        assert!(chain.get_staking_events(0, 10, Some(&test_utils::test_address(2))).is_empty());
+    }
+
+    #[test]
+    fn test_epoch_committee() {
+        let mut spec = ChainSpec::default();
+        spec.forks.module_calls = Some(0);
+        let mut chain = BlockChain::new_in_memory(&spec);
+        chain.load().unwrap();
+        let (_, state) = test_utils::genesis_with_state();
+        let rules = spec.forks.at(1);
+        let mut builder = ChainBuilder::new(chain.current_block())
+            .with_state_fn(Box::new(move |root, b| {
+                let statedb = Rc::new(RefCell::new(StateDB::from_existing(&state, root)));
+                Executor::exc_txs_in_block(b, &mut Balance::new(Interpreter::new(statedb)), &Address::default(), &rules).unwrap()
+            }));
+        let key = test_utils::test_key(1);
+        let addr = test_utils::test_address(1);
+        let create = map_core::staking::MsgValidatorCreate { pubkey: vec![1; 32], amount: 500 };
+        let mut validate = map_core::transaction::Transaction::new(addr, 1, 1000, 1000,
+            b"staking.validate".to_vec(), bincode::serialize(&create).unwrap());
+        validate.sign(&key.to_bytes()).unwrap();
+        builder.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 1, addr, 100000), validate]);
+        let first = builder.head().clone();
+        chain.import_block(&first).unwrap();
+
+        // First block of epoch 1, the validator created in epoch 0 joins its committee
+        let slot = genesis::EPOCH_LENGTH;
+        let mut block = BlockBuilder::on(&first).slot(slot).time(genesis::slot_start(chain.genesis_time(), slot)).build();
+        block.set_state_root(chain.apply_transactions(first.state_root(), &block));
+        chain.import_block(&block).unwrap();
+
+        let joined = |committee: &EpochCommittee| committee.members.iter().any(|m| m.pubkey == [1; 32]);
+        let epoch0 = chain.epoch_committee(&block.header, 0).unwrap();
+        assert_eq!(epoch0.boundary, chain.genesis_hash());
+        assert!(!joined(&epoch0));
+        let epoch1 = chain.epoch_committee(&block.header, 1).unwrap();
+        assert_eq!(epoch1.boundary, first.hash());
+        assert!(joined(&epoch1));
+        assert_eq!(chain.db.get_committee(&first.hash()), Some(epoch1.members.clone()));
+
+        let err = chain.verify_proposer(&first.header, &block).unwrap_err();
+        assert_eq!(err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone()), Some(BlockChainErrorKind::InvalidAuthority));
+
+        // Past the proposer_check fork the unsealed block is refused by gossip and sync alike
+        spec.forks.proposer_check = Some(1);
+        let mut forked = BlockChain::new_in_memory(&spec);
+        forked.load().unwrap();
+        for err in vec![forked.pre_verify(&first).unwrap_err(), forked.import_block(&first).unwrap_err()] {
+            assert_eq!(err.downcast_ref::<crate::BlockChainError>().map(|e| e.kind().clone()), Some(BlockChainErrorKind::InvalidAuthority));
+        }
+    }
+
+    #[test]
+    fn test_key_rotation_next_epoch() {
+        let mut spec = ChainSpec::default();
+        spec.forks.key_rotation = Some(0);
+        spec.forks.module_calls = Some(0);
+        let mut chain = BlockChain::new_in_memory(&spec);
+        chain.load().unwrap();
+        let (_, state) = test_utils::genesis_with_state();
+        let rules = spec.forks.at(1);
+        let mut builder = ChainBuilder::new(chain.current_block())
+            .with_state_fn(Box::new(move |root, b| {
+                let statedb = Rc::new(RefCell::new(StateDB::from_existing(&state, root)));
+                Executor::exc_txs_in_block(b, &mut Balance::new(Interpreter::new(statedb)), &Address::default(), &rules).unwrap()
+            }));
+        let key = test_utils::test_key(1);
+        let addr = test_utils::test_address(1);
+        let pubkey = |seed| test_utils::test_key(seed).to_pubkey().unwrap().to_bytes();
+        let create = map_core::staking::MsgValidatorCreate { pubkey: pubkey(2), amount: 500 };
+        let mut validate = map_core::transaction::Transaction::new(addr, 1, 1000, 1000,
+            b"staking.validate".to_vec(), bincode::serialize(&create).unwrap());
+        validate.sign(&key.to_bytes()).unwrap();
+        builder.push_txs(vec![test_utils::transfer(&test_utils::genesis_key(), 1, addr, 100000), validate]);
+        let first = builder.head().clone();
+        chain.import_block(&first).unwrap();
+
+        // Rotated in the first block of epoch 1, whose committee was fixed before
+        let rotate = map_core::staking::MsgRotateKey::new(&addr, 0, &test_utils::test_key(2), &test_utils::test_key(3)).unwrap();
+        let mut rotate = map_core::transaction::Transaction::new(addr, 2, 1000, 1000,
+            b"staking.rotate_key".to_vec(), bincode::serialize(&rotate).unwrap());
+        rotate.sign(&key.to_bytes()).unwrap();
+        let slot = genesis::EPOCH_LENGTH;
+        let mut second = BlockBuilder::on(&first).slot(slot).time(genesis::slot_start(chain.genesis_time(), slot)).txs(vec![rotate]).build();
+        second.set_state_root(chain.apply_transactions(first.state_root(), &second));
+        chain.import_block(&second).unwrap();
+        let slot = 2 * genesis::EPOCH_LENGTH;
+        let mut third = BlockBuilder::on(&second).slot(slot).time(genesis::slot_start(chain.genesis_time(), slot)).build();
+        third.set_state_root(chain.apply_transactions(second.state_root(), &third));
+        chain.import_block(&third).unwrap();
+
+        let has = |epoch, seed| chain.epoch_committee(&third.header, epoch).unwrap()
+            .members.iter().any(|m| m.pubkey[..] == pubkey(seed)[..]);
+        assert!(has(1, 2) && !has(1, 3));
+        assert!(has(2, 3) && !has(2, 2));
     }
 }
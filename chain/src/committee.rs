@@ -0,0 +1,197 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validators eligible to propose in an epoch, read from the state of the last block before
+//! the epoch, so that the proposers of old blocks are checked against their own epoch.
+use num_bigint::BigUint;
+use num_rational::BigRational;
+use num_traits::{cast::ToPrimitive, identities::One};
+use serde::{Deserialize, Serialize};
+
+use ed25519::pubkey::Pubkey;
+use map_core::block::Block;
+use map_core::genesis::EPOCH_LENGTH;
+use map_core::staking::Staking;
+use map_core::types::{Address, Hash};
+use map_crypto::vrf;
+
+/// Chance in a thousand that no validator is elected in a slot.
+pub const EMPTY_SLOT_CHANCE: u64 = 200;
+
+/// Validator of an epoch committee.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommitteeMember {
+    pub pubkey: [u8; 32],
+    pub stake: u128,
+    /// Staking address of the validator, unchanged when it rotates its key
+    pub address: Address,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpochCommittee {
+    pub epoch: u64,
+    /// Last block before the first slot of the epoch.
+    pub boundary: Hash,
+    /// Seed of the VRF electing the slot proposers.
+    pub seed: [u8; 32],
+    pub members: Vec<CommitteeMember>,
+}
+
+pub fn epoch_of(slot: u64) -> u64 {
+    slot / EPOCH_LENGTH
+}
+
+/// Validators of the staking state.
+pub fn members_of(staking: &Staking) -> Vec<CommitteeMember> {
+    staking.validator_set().into_iter().map(|v| {
+        let mut pubkey = [0u8; 32];
+        pubkey.copy_from_slice(&v.pubkey);
+        CommitteeMember { pubkey, stake: v.effective_balance, address: v.address }
+    }).collect()
+}
+
+/// Seed of the proposer election, zero in the first epoch.
+pub fn epoch_seed(genesis: &Hash, epoch: u64) -> [u8; 32] {
+    if epoch == 0 {
+        [0u8; 32]
+    } else {
+        hash::blake2b_256(genesis.0)
+    }
+}
+
+/// Input of the VRF electing the proposer of `slot`.
+pub fn vrf_input(seed: &[u8; 32], slot: u64) -> [u8; 32] {
+    let mut input = Vec::new();
+    input.extend_from_slice(seed);
+    input.extend_from_slice(&slot.to_be_bytes());
+    hash::blake2b_256(&input)
+}
+
+/// Return VRF threshold of epoch validator set
+pub fn calc_random_threshold(empty: u64, num: u64) -> u128 {
+    let base  = 1000u64;
+    let pskip = empty as f64 / base as f64;
+    let p = BigRational::from_float(1f64 - pskip.powf(1 as f64 / num as f64)).unwrap();
+
+    let numer = p.numer().to_biguint().unwrap();
+    let denom = p.denom().to_biguint().unwrap();
+    ((BigUint::one() << 128) * numer / denom).to_u128().unwrap_or(u128::max_value())
+}
+
+/// Returns true if the VRF value is little than the given threshold,
+pub fn cmp_random_threshold(random_value: &vrf::Value, threshold: u128) -> bool {
+    let mut b: [u8; 16] = Default::default();
+    b.copy_from_slice(&random_value.0[..16]);
+    u128::from_be_bytes(b) < threshold
+}
+
+impl EpochCommittee {
+    pub fn new(epoch: u64, boundary: Hash, genesis: &Hash, members: Vec<CommitteeMember>) -> Self {
+        EpochCommittee {
+            epoch,
+            boundary,
+            seed: epoch_seed(genesis, epoch),
+            members,
+        }
+    }
+
+    /// Staking address of the member with the consensus key `pubkey`
+    pub fn member_address(&self, pubkey: &[u8]) -> Option<Address> {
+        self.members.iter().find(|m| m.pubkey[..] == *pubkey).map(|m| m.address)
+    }
+
+    pub fn threshold(&self) -> u128 {
+        calc_random_threshold(EMPTY_SLOT_CHANCE, self.members.len() as u64)
+    }
+
+    /// Check that `block` is signed by a member elected for its slot. An epoch without
+    /// validators has no block to accept.
+    pub fn verify_proposer(&self, block: &Block) -> Result<(), String> {
+        if self.members.is_empty() {
+            return Err(format!("committee of epoch {} is empty", self.epoch));
+        }
+        let proof = block.proof_one().ok_or("block has no proposer key")?;
+        if proof.2 != 0 {
+            return Err(format!("unsupported proposer key type {}", proof.2));
+        }
+        if !self.members.iter().any(|m| m.pubkey == proof.0) {
+            return Err(format!("proposer {} not in the committee of epoch {}", Hash(proof.0), self.epoch));
+        }
+
+        let sign = block.sign_one().ok_or("block has no proposer signature")?;
+        Pubkey::from_bytes(&proof.0).verify(&sign.to_msg(), &sign.signs)
+            .map_err(|_| "invalid proposer signature".to_string())?;
+
+        let key = vrf::convert_public_key(&proof.0).ok_or("invalid proposer vrf key")?;
+        let value = vrf::Value(block.header.vrf_output);
+        if !key.is_vrf_valid(&vrf_input(&self.seed, block.header.slot), &value, &vrf::Proof(block.header.vrf_proof.bytes())) {
+            return Err(format!("invalid vrf proof of slot {}", block.header.slot));
+        }
+        if !cmp_random_threshold(&value, self.threshold()) {
+            return Err(format!("proposer not elected in slot {}", block.header.slot));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::test_utils::{self, BlockBuilder};
+
+    /// Block of the first slot from `slot` the key is elected in
+    fn elected_block(committee: &EpochCommittee, seed: u8, slot: u64) -> Block {
+        let key = test_utils::test_key(seed);
+        let secret = vrf::convert_secret_key(&key.to_bytes());
+        let parent = Block::default();
+        (slot..).find_map(|slot| {
+            let (value, proof) = secret.compute_vrf_with_proof(&vrf_input(&committee.seed, slot));
+            if !cmp_random_threshold(&value, committee.threshold()) {
+                return None;
+            }
+            Some(BlockBuilder::on(&parent).slot(slot).vrf_output(value.0, proof.0).signed_by(key).build())
+        }).unwrap()
+    }
+
+    fn member(seed: u8) -> CommitteeMember {
+        let pubkey = test_utils::test_key(seed).to_pubkey().unwrap().to_bytes();
+        let mut member = CommitteeMember { pubkey: [0u8; 32], stake: 1, address: test_utils::test_address(seed) };
+        member.pubkey.copy_from_slice(&pubkey);
+        member
+    }
+
+    #[test]
+    fn verify_proposer() {
+        let genesis = Hash([7; 32]);
+        let committee = EpochCommittee::new(1, Hash::default(), &genesis, vec![member(1), member(2)]);
+        let block = elected_block(&committee, 1, EPOCH_LENGTH);
+        assert_eq!(committee.verify_proposer(&block), Ok(()));
+
+        // Not a member
+        assert!(committee.verify_proposer(&elected_block(&committee, 3, EPOCH_LENGTH)).is_err());
+        // Elected with the seed of another epoch
+        let other = EpochCommittee::new(0, Hash::default(), &genesis, committee.members.clone());
+        assert!(other.verify_proposer(&block).is_err());
+        // Unsigned
+        assert!(committee.verify_proposer(&BlockBuilder::on(&Block::default()).build()).is_err());
+        let pubkey = test_utils::test_key(2).to_pubkey().unwrap().to_bytes();
+        assert_eq!(committee.member_address(&pubkey), Some(test_utils::test_address(2)));
+        assert_eq!(committee.member_address(&[0u8; 32]), None);
+        // Epochs without validators
+        let empty = EpochCommittee::new(1, Hash::default(), &genesis, Vec::new());
+        assert!(empty.verify_proposer(&block).is_err());
+    }
+}
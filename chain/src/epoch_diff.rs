@@ -0,0 +1,299 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Changes of the validator set and the balances between the states two epochs start from, to
+//! audit the stake and supply changes of the blocks in between.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+use serde::Serialize;
+
+use map_core::balance::{Account, Balance};
+use map_core::runtime::Interpreter;
+use map_core::staking::{Staking, Validator};
+use map_core::state::StateDB;
+use map_core::types::{Address, Hash};
+
+use crate::blockchain::BlockChain;
+use crate::lock::TimedRwLock;
+
+/// Most epochs between the two states of a diff, the blocks in between are read to name the
+/// changed accounts.
+pub const MAX_DIFF_EPOCHS: u64 = 64;
+
+/// Boundaries whose validator sets are kept.
+const CACHED_VALIDATOR_SETS: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EpochDiff {
+    pub from: EpochState,
+    pub to: EpochState,
+    /// Validators only in the `to` state
+    pub added_validators: Vec<Validator>,
+    /// Validators only in the `from` state
+    pub removed_validators: Vec<Validator>,
+    /// Validators of both states whose balance or effective balance changed
+    pub stake_changes: Vec<StakeChange>,
+    /// Accounts whose balance, locked balance or nonce changed, by address
+    pub balance_changes: Vec<BalanceChange>,
+    /// Sum of the balance and locked balance changes
+    pub total_supply_delta: i128,
+}
+
+/// Boundary block of an epoch, the last block before its first slot.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EpochState {
+    pub epoch: u64,
+    pub height: u64,
+    pub hash: Hash,
+    pub state_root: Hash,
+    /// Effective balances of the validators
+    pub total_stake: u128,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StakeChange {
+    pub address: Address,
+    pub balance_before: u128,
+    pub balance_after: u128,
+    pub effective_balance_before: u128,
+    pub effective_balance_after: u128,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BalanceChange {
+    /// Hash of the address keying the account in the state
+    pub key: Hash,
+    pub address: Address,
+    pub balance_before: u128,
+    pub balance_after: u128,
+    pub locked_before: u128,
+    pub locked_after: u128,
+    pub nonce_before: u64,
+    pub nonce_after: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffError {
+    /// The epochs are out of order or too far apart
+    InvalidRange(String),
+    /// The epoch has not started yet
+    NotStarted(u64),
+    /// The boundary state was pruned
+    StateUnavailable(Hash),
+    Failed(String),
+}
+
+/// Validator sets at the epoch boundaries and the addresses named by the blocks of each epoch,
+/// by boundary block hash. Canonical blocks are final, so entries never go stale.
+pub struct EpochCache {
+    validators: Mutex<LruCache<Hash, Arc<Vec<Validator>>>>,
+    named: Mutex<LruCache<Hash, Arc<BTreeSet<Address>>>>,
+}
+
+impl Default for EpochCache {
+    fn default() -> Self {
+        EpochCache {
+            validators: Mutex::new(LruCache::new(CACHED_VALIDATOR_SETS)),
+            named: Mutex::new(LruCache::new(MAX_DIFF_EPOCHS as usize)),
+        }
+    }
+}
+
+/// Diff of the boundary states of `from_epoch` and `to_epoch`, both already started. The chain
+/// is only locked to read the boundaries and the blocks of one epoch at a time, the states are
+/// read after, pinned against pruning.
+pub fn diff(chain: &TimedRwLock<BlockChain>, from_epoch: u64, to_epoch: u64) -> Result<EpochDiff, DiffError> {
+    if from_epoch >= to_epoch {
+        return Err(DiffError::InvalidRange("from epoch not before to epoch".to_string()));
+    }
+    if to_epoch - from_epoch > MAX_DIFF_EPOCHS {
+        return Err(DiffError::InvalidRange(format!("epoch range exceeds {}", MAX_DIFF_EPOCHS)));
+    }
+
+    let (boundaries, cache, _pin, from_state, to_state) = {
+        let chain = chain.read().expect("acquiring chain read lock");
+        let mut boundaries = BTreeMap::new();
+        for epoch in from_epoch..=to_epoch {
+            let block = chain.epoch_boundary(epoch).ok_or(DiffError::NotStarted(epoch))?;
+            boundaries.insert(epoch, (block.height(), block.hash(), block.state_root()));
+        }
+        let state = |epoch: u64| {
+            let root = boundaries[&epoch].2;
+            chain.checked_state_at(root).map_err(|_| DiffError::StateUnavailable(root))
+        };
+        let (from_state, to_state) = (state(from_epoch)?, state(to_epoch)?);
+        (boundaries, chain.epoch_cache(), chain.pin_state(), from_state, to_state)
+    };
+
+    let before_set = validator_set(&cache, boundaries[&from_epoch].1, &from_state);
+    let after_set = validator_set(&cache, boundaries[&to_epoch].1, &to_state);
+
+    let before: HashMap<Address, &Validator> = before_set.iter().map(|v| (v.address, v)).collect();
+    let after: HashMap<Address, &Validator> = after_set.iter().map(|v| (v.address, v)).collect();
+    let added_validators = after_set.iter().filter(|v| !before.contains_key(&v.address)).cloned().collect();
+    let removed_validators = before_set.iter().filter(|v| !after.contains_key(&v.address)).cloned().collect();
+    let stake_changes = after_set.iter()
+        .filter_map(|v| before.get(&v.address).map(|prev| (prev, v)))
+        .filter(|(prev, v)| prev.balance != v.balance || prev.effective_balance != v.effective_balance)
+        .map(|(prev, v)| StakeChange {
+            address: v.address,
+            balance_before: prev.balance,
+            balance_after: v.balance,
+            effective_balance_before: prev.effective_balance,
+            effective_balance_after: v.effective_balance,
+        })
+        .collect();
+
+    // Accounts only change through the transactions in between or as validators
+    let mut names = BTreeSet::new();
+    names.insert(Address::default());
+    names.extend(before_set.iter().chain(after_set.iter()).map(|v| v.address));
+    for epoch in from_epoch..to_epoch {
+        names.extend(named_in_epoch(chain, &cache, boundaries[&epoch], boundaries[&(epoch + 1)].0)?.iter());
+    }
+
+    let mut balance_changes = Vec::new();
+    for address in names {
+        let key = Balance::address_key(address);
+        let (before, after) = (account(&from_state, &key)?, account(&to_state, &key)?);
+        if before == after {
+            continue;
+        }
+        balance_changes.push(BalanceChange {
+            key,
+            address,
+            balance_before: before.get_balance(),
+            balance_after: after.get_balance(),
+            locked_before: before.get_locked_balance(),
+            locked_after: after.get_locked_balance(),
+            nonce_before: before.get_nonce(),
+            nonce_after: after.get_nonce(),
+        });
+    }
+    let total_supply_delta = balance_changes.iter()
+        .map(|c| (c.balance_after + c.locked_after) as i128 - (c.balance_before + c.locked_before) as i128)
+        .sum();
+
+    let epoch_state = |epoch: u64, validators: &[Validator]| {
+        let (height, hash, state_root) = boundaries[&epoch];
+        EpochState { epoch, height, hash, state_root, total_stake: validators.iter().map(|v| v.effective_balance).sum() }
+    };
+    Ok(EpochDiff {
+        from: epoch_state(from_epoch, &before_set),
+        to: epoch_state(to_epoch, &after_set),
+        added_validators,
+        removed_validators,
+        stake_changes,
+        balance_changes,
+        total_supply_delta,
+    })
+}
+
+fn validator_set(cache: &EpochCache, boundary: Hash, state: &Rc<RefCell<StateDB>>) -> Arc<Vec<Validator>> {
+    if let Some(set) = cache.validators.lock().expect("acquiring epoch cache lock").get(&boundary) {
+        return set.clone();
+    }
+    let set = Arc::new(Staking::new(Interpreter::new(state.clone())).validator_set());
+    cache.validators.lock().expect("acquiring epoch cache lock").put(boundary, set.clone());
+    set
+}
+
+fn account(state: &Rc<RefCell<StateDB>>, key: &Hash) -> Result<Account, DiffError> {
+    match state.borrow().get_storage(key) {
+        Some(data) => bincode::deserialize(&data).map_err(|e| DiffError::Failed(format!("invalid account {}: {}", key, e))),
+        None => Ok(Account::default()),
+    }
+}
+
+/// Senders and transfer receivers of the blocks after the `boundary` of an epoch, up to the
+/// boundary of the next one at height `end`.
+fn named_in_epoch(
+    chain: &TimedRwLock<BlockChain>,
+    cache: &EpochCache,
+    boundary: (u64, Hash, Hash),
+    end: u64,
+) -> Result<Arc<BTreeSet<Address>>, DiffError> {
+    let (start, hash, _) = boundary;
+    if let Some(named) = cache.named.lock().expect("acquiring epoch cache lock").get(&hash) {
+        return Ok(named.clone());
+    }
+    let mut named = BTreeSet::new();
+    {
+        let chain = chain.read().expect("acquiring chain read lock");
+        for height in start + 1..=end {
+            let block = chain.get_block_by_number(height).ok_or(DiffError::Failed(format!("block {} missing", height)))?;
+            for tx in block.get_txs() {
+                named.insert(tx.sender);
+                if let Some(transfer) = tx.transfer_msg() {
+                    named.insert(transfer.receiver);
+                }
+            }
+        }
+    }
+    let named = Arc::new(named);
+    cache.named.lock().expect("acquiring epoch cache lock").put(hash, named.clone());
+    Ok(named)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::genesis::{self, EPOCH_LENGTH};
+    use map_core::test_utils::{self, BlockBuilder};
+
+    #[test]
+    fn transfer_between_epochs() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+        let receiver = test_utils::test_address(1);
+        let tx = test_utils::transfer(&test_utils::genesis_key(), 1, receiver, 100);
+        let mut block = BlockBuilder::on(&genesis).txs(vec![tx.clone()]).build();
+        block.set_state_root(chain.apply_transactions(genesis.state_root(), &block));
+        chain.import_block(&block).unwrap();
+        assert!(chain.epoch_boundary(1).is_none());
+        let slot = EPOCH_LENGTH;
+        let mut next = BlockBuilder::on(&block).slot(slot).time(genesis::slot_start(chain.genesis_time(), slot)).build();
+        next.set_state_root(chain.apply_transactions(block.state_root(), &next));
+        chain.import_block(&next).unwrap();
+        assert_eq!(chain.epoch_boundary(1).map(|b| b.hash()), Some(block.hash()));
+
+        let chain = TimedRwLock::new("chain", chain);
+        assert_eq!(diff(&chain, 0, 2), Err(DiffError::NotStarted(2)));
+        assert_eq!(diff(&chain, 1, 1), Err(DiffError::InvalidRange("from epoch not before to epoch".to_string())));
+        assert_eq!(diff(&chain, 0, MAX_DIFF_EPOCHS + 1), Err(DiffError::InvalidRange(format!("epoch range exceeds {}", MAX_DIFF_EPOCHS))));
+        let first = diff(&chain, 0, 1).unwrap();
+        assert_eq!((first.from.height, first.to.height), (0, 1));
+        assert!(first.added_validators.is_empty() && first.removed_validators.is_empty());
+        let received = first.balance_changes.iter().find(|c| c.address == receiver).unwrap();
+        assert_eq!((received.balance_before, received.balance_after), (0, 100));
+        assert_eq!(received.key, Balance::address_key(receiver));
+        let sender = first.balance_changes.iter().find(|c| c.address == tx.sender).unwrap();
+        assert_eq!(sender.nonce_after, sender.nonce_before + 1);
+        let delta: i128 = first.balance_changes.iter()
+            .map(|c| (c.balance_after + c.locked_after) as i128 - (c.balance_before + c.locked_before) as i128)
+            .sum();
+        assert_eq!(first.total_supply_delta, delta);
+        // Served from the cached validator sets and names
+        assert_eq!(diff(&chain, 0, 1), Ok(first));
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Per-block fee statistics recorded at import, served by the fee history RPC.
+use serde::{Deserialize, Serialize};
+
+use map_core::block::Block;
+
+/// Aggregate fees and gas prices of the transactions in a block.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockFeeStats {
+    pub tx_count: u64,
+    /// Sum of the fees charged to the senders.
+    pub total_fees: u128,
+    pub min_gas_price: u64,
+    pub median_gas_price: u64,
+    pub max_gas_price: u64,
+    /// Ascending gas prices of all transactions, for arbitrary percentiles.
+    pub gas_prices: Vec<u64>,
+}
+
+impl BlockFeeStats {
+    pub fn from_block(block: &Block) -> Self {
+        let txs = block.get_txs();
+        let mut gas_prices: Vec<u64> = txs.iter().map(|tx| tx.get_gas_price()).collect();
+        gas_prices.sort();
+
+        let mut stats = BlockFeeStats {
+            tx_count: txs.len() as u64,
+            total_fees: executor::TRANSFER_FEE * txs.len() as u128,
+            gas_prices,
+            ..Default::default()
+        };
+        stats.min_gas_price = stats.percentile(0.0);
+        stats.median_gas_price = stats.percentile(50.0);
+        stats.max_gas_price = stats.percentile(100.0);
+        stats
+    }
+
+    /// Gas price below which `percent` of the transactions are, 0 for an empty block.
+    pub fn percentile(&self, percent: f64) -> u64 {
+        if self.gas_prices.is_empty() {
+            return 0;
+        }
+        let last = self.gas_prices.len() - 1;
+        let index = (last as f64 * percent.max(0.0).min(100.0) / 100.0) as usize;
+        self.gas_prices[index.min(last)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::transaction::Transaction;
+    use map_core::types::Address;
+
+    fn tx(price: u64) -> Transaction {
+        Transaction::transfer(Address::default(), 1, price, 1000, Address::default(), 1)
+    }
+
+    #[test]
+    fn test_block_fee_stats() {
+        assert_eq!(BlockFeeStats::from_block(&Block::default()), BlockFeeStats::default());
+
+        let mut block = Block::default();
+        block.txs = vec![tx(50), tx(10), tx(40), tx(20), tx(30)];
+        let stats = BlockFeeStats::from_block(&block);
+        assert_eq!(stats.tx_count, 5);
+        assert_eq!(stats.total_fees, executor::TRANSFER_FEE * 5);
+        assert_eq!((stats.min_gas_price, stats.median_gas_price, stats.max_gas_price), (10, 30, 50));
+        assert_eq!(stats.percentile(25.0), 20);
+        assert_eq!(stats.percentile(90.0), 40);
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Server side filters over imported blocks, matched at import time and drained by polling.
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use map_core::block::Block;
+use map_core::types::{Address, Hash};
+
+/// Max number of installed filters, the least recently polled one is evicted beyond it.
+pub const MAX_FILTERS: usize = 256;
+/// Max number of undrained matches kept per filter, older ones are dropped.
+pub const MAX_FILTER_ITEMS: usize = 1024;
+/// Filters not polled for this long are uninstalled.
+pub const FILTER_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    /// Hashes of new blocks, optionally only those proposed by `author`.
+    Blocks { author: Option<Address> },
+    /// Hashes of new transactions sent from or to `address`.
+    Transactions { address: Address },
+}
+
+impl FilterKind {
+    fn matches(&self, block: &Block, author: Option<Address>) -> Vec<Hash> {
+        match self {
+            FilterKind::Blocks { author: None } => vec![block.hash()],
+            FilterKind::Blocks { author: Some(a) } => {
+                if author == Some(*a) { vec![block.hash()] } else { Vec::new() }
+            }
+            FilterKind::Transactions { address } => block.get_txs().iter()
+                .filter(|tx| tx.get_from_address() == *address
+                    || tx.transfer_msg().map_or(false, |msg| msg.receiver == *address))
+                .map(|tx| tx.hash())
+                .collect(),
+        }
+    }
+}
+
+struct Filter {
+    kind: FilterKind,
+    items: VecDeque<Hash>,
+    last_poll: Instant,
+}
+
+#[derive(Default)]
+pub struct Filters {
+    next_id: u64,
+    filters: HashMap<u64, Filter>,
+}
+
+impl Filters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Install a filter matching blocks imported from now on, returns its id.
+    pub fn install(&mut self, kind: FilterKind) -> u64 {
+        self.expire();
+        if self.filters.len() >= MAX_FILTERS {
+            let oldest = self.filters.iter()
+                .min_by_key(|(_, f)| f.last_poll)
+                .map(|(id, _)| *id);
+            if let Some(id) = oldest {
+                self.filters.remove(&id);
+            }
+        }
+
+        self.next_id += 1;
+        self.filters.insert(self.next_id, Filter {
+            kind,
+            items: VecDeque::new(),
+            last_poll: Instant::now(),
+        });
+        self.next_id
+    }
+
+    pub fn uninstall(&mut self, id: u64) -> bool {
+        self.filters.remove(&id).is_some()
+    }
+
+    pub fn kind(&self, id: u64) -> Option<&FilterKind> {
+        self.filters.get(&id).map(|f| &f.kind)
+    }
+
+    /// Matches since the previous poll, None if the filter is unknown or expired.
+    pub fn poll(&mut self, id: u64) -> Option<Vec<Hash>> {
+        let filter = self.filters.get_mut(&id)?;
+        filter.last_poll = Instant::now();
+        Some(filter.items.drain(..).collect())
+    }
+
+    /// Match a newly imported canonical block against all filters.
+    pub fn on_block(&mut self, block: &Block, author: Option<Address>) {
+        self.expire();
+        for filter in self.filters.values_mut() {
+            for hash in filter.kind.matches(block, author) {
+                if filter.items.len() == MAX_FILTER_ITEMS {
+                    filter.items.pop_front();
+                }
+                filter.items.push_back(hash);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.filters.retain(|_, f| now.duration_since(f.last_poll) < FILTER_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::test_utils::{self, BlockBuilder};
+
+    #[test]
+    fn block_filter_by_author() {
+        let genesis = Block::default();
+        let mut filters = Filters::new();
+        let all = filters.install(FilterKind::Blocks { author: None });
+        let author = test_utils::test_address(1);
+        let by_author = filters.install(FilterKind::Blocks { author: Some(author) });
+
+        let first = BlockBuilder::on(&genesis).build();
+        let second = BlockBuilder::on(&first).build();
+        filters.on_block(&first, Some(author));
+        filters.on_block(&second, Some(test_utils::test_address(2)));
+
+        assert_eq!(filters.poll(all), Some(vec![first.hash(), second.hash()]));
+        assert_eq!(filters.poll(by_author), Some(vec![first.hash()]));
+        assert_eq!(filters.poll(by_author), Some(vec![]));
+    }
+
+    #[test]
+    fn tx_filter_by_sender_or_recipient() {
+        let key = test_utils::test_key(1);
+        let sender = test_utils::test_address(1);
+        let recipient = test_utils::test_address(2);
+        let to_sender = test_utils::transfer(&test_utils::test_key(3), 0, sender, 1);
+        let from_sender = test_utils::transfer(&key, 0, recipient, 1);
+        let unrelated = test_utils::transfer(&test_utils::test_key(3), 1, recipient, 1);
+        let block = BlockBuilder::on(&Block::default())
+            .txs(vec![to_sender.clone(), from_sender.clone(), unrelated])
+            .build();
+
+        let mut filters = Filters::new();
+        let id = filters.install(FilterKind::Transactions { address: sender });
+        filters.on_block(&block, None);
+        assert_eq!(filters.poll(id), Some(vec![to_sender.hash(), from_sender.hash()]));
+    }
+
+    #[test]
+    fn uninstall_and_limits() {
+        let mut filters = Filters::new();
+        let id = filters.install(FilterKind::Blocks { author: None });
+        let mut parent = Block::default();
+        for _ in 0..MAX_FILTER_ITEMS + 1 {
+            parent = BlockBuilder::on(&parent).build();
+            filters.on_block(&parent, None);
+        }
+        let items = filters.poll(id).unwrap();
+        assert_eq!(items.len(), MAX_FILTER_ITEMS);
+        assert_eq!(items.last(), Some(&parent.hash()));
+
+        assert!(filters.uninstall(id));
+        assert!(!filters.uninstall(id));
+        assert_eq!(filters.poll(id), None);
+    }
+}
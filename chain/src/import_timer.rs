@@ -0,0 +1,124 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Per-stage timing of the block import pipeline.
+use std::time::{Duration, Instant};
+
+use map_core::types::Hash;
+
+use crate::metrics;
+
+/// Import stages, in pipeline order.
+pub const STAGE_DECODE: &str = "decode";
+pub const STAGE_VERIFY_HEADER: &str = "verify_header";
+pub const STAGE_VERIFY_BLOCK: &str = "verify_block";
+pub const STAGE_EXECUTE: &str = "execute";
+pub const STAGE_COMMIT: &str = "commit";
+pub const STAGE_CANONICAL: &str = "canonical";
+
+/// Imports slower than this are logged with their stage breakdown.
+pub const DEFAULT_SLOW_IMPORT_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// Records a duration for a single stage, for stages timed outside of `BlockChain`.
+pub fn observe_stage(stage: &str, elapsed: Duration) {
+    if let Some(histogram) = metrics::get_histogram(&metrics::BLOCK_IMPORT_STAGE_SECONDS, &[stage]) {
+        histogram.observe(duration_secs(elapsed));
+    }
+}
+
+pub(crate) fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// Measures the stages of one block import.
+pub struct ImportTimer {
+    height: u64,
+    hash: Hash,
+    start: Instant,
+    last: Instant,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl ImportTimer {
+    pub fn start(height: u64, hash: Hash) -> Self {
+        let now = Instant::now();
+        ImportTimer {
+            height,
+            hash,
+            start: now,
+            last: now,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Ends the current stage, recording the time since the previous stage ended.
+    pub fn stage(&mut self, name: &'static str) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        self.last = now;
+        observe_stage(name, elapsed);
+        self.stages.push((name, elapsed));
+    }
+
+    pub fn stages(&self) -> &[(&'static str, Duration)] {
+        &self.stages
+    }
+
+    /// Records the total import time and logs the breakdown, at warn level if above `threshold`.
+    pub fn finish(self, threshold: Option<Duration>) -> Duration {
+        let total = self.start.elapsed();
+        metrics::observe(&metrics::BLOCK_IMPORT_SECONDS, duration_secs(total));
+
+        let breakdown = self.breakdown();
+        match threshold {
+            Some(limit) if total >= limit => {
+                warn!("slow block import height={} hash={} total={}ms {}",
+                    self.height, self.hash, total.as_millis(), breakdown);
+            }
+            _ => {
+                debug!("block import height={} hash={} total={}ms {}",
+                    self.height, self.hash, total.as_millis(), breakdown);
+            }
+        }
+        total
+    }
+
+    fn breakdown(&self) -> String {
+        self.stages
+            .iter()
+            .map(|(name, elapsed)| format!("{}={}ms", name, elapsed.as_millis()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_in_order() {
+        let mut timer = ImportTimer::start(1, Hash::default());
+        timer.stage(STAGE_VERIFY_HEADER);
+        timer.stage(STAGE_EXECUTE);
+        let names: Vec<&str> = timer.stages().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec![STAGE_VERIFY_HEADER, STAGE_EXECUTE]);
+        assert!(timer.breakdown().starts_with("verify_header="));
+
+        let total = timer.finish(None);
+        assert!(total >= Duration::from_millis(0));
+    }
+}
@@ -20,10 +20,29 @@ extern crate futures;
 extern crate errors;
 #[macro_use]
 extern crate enum_display_derive;
+#[macro_use]
+extern crate lazy_static;
 
 
 pub mod store;
 pub mod blockchain;
+pub mod fee_stats;
+pub mod missed_proposals;
+pub mod authorship;
+pub mod filters;
+pub mod import_timer;
+pub mod verify_cache;
+pub mod block_cache;
+pub mod lock;
+pub mod snapshot;
+pub mod prune;
+pub mod accounts;
+pub mod epoch_diff;
+pub mod committee;
+pub mod replay;
+pub mod schema;
+pub mod audit;
+mod metrics;
 use std::fmt::{self, Display,Debug};
 use errors::{Error,ErrorKind};
 use failure::{Backtrace,err_msg, Context, Fail};
@@ -41,9 +60,16 @@ pub enum BlockChainErrorKind {
     MismatchHash,
     InvalidBlockProof,
     InvalidBlockTime,
+    FutureBlockTime,
+    SlotTimeMismatch,
     InvalidBlockHeight,
     InvalidState,
     InvalidAuthority,
+    StateUnavailable,
+    UnsupportedSchema,
+    MissingStateNodes,
+    GenesisMismatch,
+    Storage,
 }
 
 #[derive(Debug, PartialEq)]
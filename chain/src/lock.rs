@@ -0,0 +1,175 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! `RwLock` recording how long it is waited for and held, so stalls on the shared chain show up
+//! in metrics and logs.
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use crate::import_timer;
+use crate::metrics;
+
+/// Waiting for or holding a lock longer than this is logged.
+pub const DEFAULT_LOCK_WARN_THRESHOLD: Duration = Duration::from_secs(1);
+
+const MODE_READ: &str = "read";
+const MODE_WRITE: &str = "write";
+
+/// Drop-in replacement of `RwLock` with the same `read` and `write` API. A lock whose waiters
+/// gauge stays above zero is likely deadlocked.
+pub struct TimedRwLock<T> {
+    name: &'static str,
+    threshold: Duration,
+    inner: RwLock<T>,
+}
+
+impl<T> TimedRwLock<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        TimedRwLock::with_threshold(name, value, DEFAULT_LOCK_WARN_THRESHOLD)
+    }
+
+    pub fn with_threshold(name: &'static str, value: T, threshold: Duration) -> Self {
+        TimedRwLock {
+            name,
+            threshold,
+            inner: RwLock::new(value),
+        }
+    }
+
+    pub fn read(&self) -> LockResult<TimedReadGuard<'_, T>> {
+        let start = self.start_wait();
+        let wrap = |guard| TimedReadGuard { guard, _hold: self.end_wait(MODE_READ, start) };
+        match self.inner.read() {
+            Ok(guard) => Ok(wrap(guard)),
+            Err(e) => Err(PoisonError::new(wrap(e.into_inner()))),
+        }
+    }
+
+    pub fn write(&self) -> LockResult<TimedWriteGuard<'_, T>> {
+        let start = self.start_wait();
+        let wrap = |guard| TimedWriteGuard { guard, _hold: self.end_wait(MODE_WRITE, start) };
+        match self.inner.write() {
+            Ok(guard) => Ok(wrap(guard)),
+            Err(e) => Err(PoisonError::new(wrap(e.into_inner()))),
+        }
+    }
+
+    fn start_wait(&self) -> Instant {
+        if let Some(gauge) = metrics::get_int_gauge(&metrics::LOCK_WAITERS, &[self.name]) {
+            gauge.inc();
+        }
+        Instant::now()
+    }
+
+    fn end_wait(&self, mode: &'static str, start: Instant) -> HoldTimer {
+        let waited = start.elapsed();
+        if let Some(gauge) = metrics::get_int_gauge(&metrics::LOCK_WAITERS, &[self.name]) {
+            gauge.dec();
+        }
+        if let Some(histogram) = metrics::get_histogram(&metrics::LOCK_WAIT_SECONDS, &[self.name, mode]) {
+            histogram.observe(import_timer::duration_secs(waited));
+        }
+        if waited >= self.threshold {
+            warn!("waited {}ms for {} lock of {}", waited.as_millis(), mode, self.name);
+        }
+        HoldTimer {
+            name: self.name,
+            mode,
+            threshold: self.threshold,
+            acquired: Instant::now(),
+        }
+    }
+}
+
+/// Records the hold time when the guard is released.
+struct HoldTimer {
+    name: &'static str,
+    mode: &'static str,
+    threshold: Duration,
+    acquired: Instant,
+}
+
+impl Drop for HoldTimer {
+    fn drop(&mut self) {
+        let held = self.acquired.elapsed();
+        if let Some(histogram) = metrics::get_histogram(&metrics::LOCK_HOLD_SECONDS, &[self.name, self.mode]) {
+            histogram.observe(import_timer::duration_secs(held));
+        }
+        if held >= self.threshold {
+            warn!("{} lock of {} held for {}ms", self.mode, self.name, held.as_millis());
+        }
+    }
+}
+
+pub struct TimedReadGuard<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+    // Dropped after the guard, so the hold time includes the release
+    _hold: HoldTimer,
+}
+
+impl<T> Deref for TimedReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+pub struct TimedWriteGuard<'a, T> {
+    guard: RwLockWriteGuard<'a, T>,
+    _hold: HoldTimer,
+}
+
+impl<T> Deref for TimedWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TimedWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn read_write() {
+        let lock = Arc::new(TimedRwLock::with_threshold("test_lock", 1u64, Duration::from_millis(10)));
+        {
+            let mut value = lock.write().unwrap();
+            *value += 1;
+        }
+        let reader = {
+            let lock = lock.clone();
+            thread::spawn(move || *lock.read().unwrap())
+        };
+        assert_eq!(reader.join().unwrap(), 2);
+
+        let waiters = metrics::get_int_gauge(&metrics::LOCK_WAITERS, &["test_lock"]).unwrap();
+        assert_eq!(waiters.get(), 0);
+        let holds = metrics::get_histogram(&metrics::LOCK_HOLD_SECONDS, &["test_lock", MODE_WRITE]).unwrap();
+        assert_eq!(holds.get_sample_count(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+pub use map_metrics::*;
+
+lazy_static! {
+    /*
+     * Block import
+     */
+    pub static ref BLOCK_IMPORT_STAGE_SECONDS: Result<HistogramVec> = try_create_histogram_vec(
+        "block_import_stage_seconds",
+        "Time spent in each stage of the block import pipeline",
+        &["stage"]
+    );
+    pub static ref BLOCK_IMPORT_SECONDS: Result<Histogram> = try_create_histogram(
+        "block_import_seconds",
+        "Total time to import a block, from verification to canonical head update"
+    );
+
+    /*
+     * Shared locks
+     */
+    pub static ref LOCK_WAIT_SECONDS: Result<HistogramVec> = try_create_histogram_vec(
+        "lock_wait_seconds",
+        "Time spent waiting to acquire a shared lock",
+        &["lock", "mode"]
+    );
+    pub static ref LOCK_HOLD_SECONDS: Result<HistogramVec> = try_create_histogram_vec(
+        "lock_hold_seconds",
+        "Time a shared lock was held",
+        &["lock", "mode"]
+    );
+    pub static ref LOCK_WAITERS: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "lock_waiters",
+        "Threads currently waiting for a shared lock, stuck above zero when deadlocked",
+        &["lock"]
+    );
+
+    /*
+     * Block body cache
+     */
+    pub static ref BLOCK_CACHE_HITS: Result<IntCounter> = try_create_int_counter(
+        "block_cache_hits",
+        "Block reads served from the block body cache"
+    );
+    pub static ref BLOCK_CACHE_MISSES: Result<IntCounter> = try_create_int_counter(
+        "block_cache_misses",
+        "Block reads not found in the block body cache and read from the chain db"
+    );
+
+    /*
+     * Consistency audit
+     */
+    pub static ref AUDIT_RUNS: Result<IntCounter> = try_create_int_counter(
+        "audit_runs",
+        "Runs of the background consistency audit"
+    );
+    pub static ref AUDIT_VIOLATIONS: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "audit_violations",
+        "Invariants found broken by the last audit run",
+        &["check"]
+    );
+
+    /*
+     * Head recovery
+     */
+    pub static ref HELD_REORG_DEPTH: Result<IntGauge> = try_create_int_gauge(
+        "held_reorg_depth",
+        "Blocks of a head rollback refused at load for exceeding the max reorg depth, 0 when none is held"
+    );
+
+    /*
+     * Block proposal
+     */
+    pub static ref VALIDATOR_MISSED_PROPOSALS: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "validator_missed_proposals",
+        "Slots recorded as missed since start, where the validator was elected but no block was included",
+        &["validator"]
+    );
+}
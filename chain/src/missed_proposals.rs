@@ -0,0 +1,74 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Slots where a validator was elected but no block of that slot made it into the chain.
+use serde::{Deserialize, Serialize};
+
+use map_core::types::Address;
+
+/// Missed slots of one validator in an epoch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MissedProposals {
+    pub validator: Address,
+    /// Ascending slot numbers.
+    pub slots: Vec<u64>,
+}
+
+impl MissedProposals {
+    pub fn count(&self) -> u64 {
+        self.slots.len() as u64
+    }
+}
+
+/// Add `slot` to the missed slots of `validator`. Returns false if it was already recorded.
+pub fn record(epoch: &mut Vec<MissedProposals>, validator: Address, slot: u64) -> bool {
+    let index = match epoch.iter().position(|m| m.validator == validator) {
+        Some(i) => i,
+        None => {
+            epoch.push(MissedProposals { validator, slots: Vec::new() });
+            epoch.len() - 1
+        }
+    };
+    let slots = &mut epoch[index].slots;
+    match slots.binary_search(&slot) {
+        Ok(_) => false,
+        Err(pos) => {
+            slots.insert(pos, slot);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let a = Address([1u8; 20]);
+        let b = Address([2u8; 20]);
+        let mut epoch = Vec::new();
+        assert!(record(&mut epoch, a, 7));
+        assert!(record(&mut epoch, a, 3));
+        assert!(!record(&mut epoch, a, 7));
+        assert!(record(&mut epoch, b, 5));
+
+        assert_eq!(epoch.len(), 2);
+        assert_eq!(epoch[0].slots, vec![3, 7]);
+        assert_eq!(epoch[0].count(), 2);
+        assert_eq!(epoch[1].validator, b);
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Which historical states a node keeps. An archive node keeps the state of every block, a
+//! full node only the state of the last `STATE_HISTORY` blocks and of genesis.
+//!
+//! A full node counts the references to the trie nodes it writes and journals the references
+//! each imported block added and dropped. Once a height leaves the history, the references its
+//! canonical block dropped are released, and those added by the other blocks imported there
+//! are taken back; nodes left unreferenced are deleted.
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serde::{Serialize, Deserialize};
+
+/// Recent canonical blocks whose state a full node keeps
+pub const STATE_HISTORY: u64 = 128;
+/// Heights released at most per import, a node catching up prunes over several imports
+pub const PRUNE_BATCH: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeMode {
+    Archive,
+    Full,
+}
+
+impl Default for NodeMode {
+    fn default() -> Self {
+        NodeMode::Archive
+    }
+}
+
+impl FromStr for NodeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "archive" => Ok(NodeMode::Archive),
+            "full" => Ok(NodeMode::Full),
+            _ => Err(format!("unknown mode {}, expected archive or full", s)),
+        }
+    }
+}
+
+/// Held by readers of the state outside the chain lock, pruning waits until every pin is
+/// dropped.
+pub struct StatePin(Arc<AtomicUsize>);
+
+impl StatePin {
+    pub(crate) fn new(readers: &Arc<AtomicUsize>) -> Self {
+        readers.fetch_add(1, Ordering::SeqCst);
+        StatePin(readers.clone())
+    }
+}
+
+impl Drop for StatePin {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Display for NodeMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            NodeMode::Archive => "archive",
+            NodeMode::Full => "full",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Re-execution of a stored block on its parent state one transaction at a time, tracing the
+//! account changes, to find where a node computed a divergent state root.
+use serde::Serialize;
+
+use executor::{Executor, TRANSFER_FEE};
+use map_core::balance::Balance;
+use map_core::runtime::Interpreter;
+use map_core::staking::StakingEvent;
+use map_core::types::{Address, Hash};
+
+use crate::blockchain::BlockChain;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BlockReplay {
+    pub height: u64,
+    pub hash: Hash,
+    pub parent_state_root: Hash,
+    /// State root in the block header.
+    pub expected_state_root: Hash,
+    /// State root computed by the replay, None if a transaction failed.
+    pub state_root: Option<Hash>,
+    pub matches: bool,
+    pub txs: Vec<TxTrace>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TxTrace {
+    pub index: usize,
+    pub hash: Hash,
+    pub sender: Address,
+    pub call: String,
+    pub nonce: u64,
+    /// Fee paid to the miner, 0 if the transaction failed.
+    pub fee: u128,
+    /// Sender, receiver and miner accounts that changed.
+    pub accounts: Vec<AccountChange>,
+    pub staking: Vec<StakingEvent>,
+    /// Failure aborting the execution of the block.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AccountChange {
+    pub address: Address,
+    pub before: AccountState,
+    pub after: AccountState,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AccountState {
+    pub balance: u128,
+    pub nonce: u64,
+    pub locked_balance: u128,
+}
+
+fn account_state(state: &Balance, address: Address) -> AccountState {
+    let account = state.get_account(address);
+    AccountState {
+        balance: account.get_balance(),
+        nonce: account.get_nonce(),
+        locked_balance: account.get_locked_balance(),
+    }
+}
+
+/// Replay the canonical block at `height` the way it is imported. The state of its parent
+/// must be available, a full node only keeps the recent ones.
+pub fn replay(chain: &BlockChain, height: u64) -> Result<BlockReplay, String> {
+    if height == 0 {
+        return Err("the genesis block has no transactions to replay".to_string());
+    }
+    let block = chain.get_block_by_number(height).ok_or(format!("no block at height {}", height))?;
+    let parent = chain.get_block(block.header.parent_hash)
+        .ok_or(format!("parent of block {} missing", height))?;
+    let statedb = chain.checked_state_at(parent.state_root())
+        .map_err(|_| format!("state of height {} not available", parent.height()))?;
+    let mut state = Balance::new(Interpreter::new(statedb.clone()));
+    let miner = Address::default();
+    let rules = chain.spec().forks.at(height);
+
+    let mut txs = Vec::new();
+    let mut failed = false;
+    for (index, tx) in block.get_txs().iter().enumerate() {
+        let mut touched = vec![tx.sender];
+        if let Some(transfer) = tx.transfer_msg() {
+            touched.push(transfer.receiver);
+        }
+        touched.push(miner);
+        touched.sort();
+        touched.dedup();
+        let before: Vec<AccountState> = touched.iter().map(|a| account_state(&state, *a)).collect();
+
+        let result = Executor::exc_block_tx(tx, &mut state, &miner, &rules);
+        let accounts = touched.iter().zip(before)
+            .map(|(address, before)| AccountChange { address: *address, before, after: account_state(&state, *address) })
+            .filter(|change| change.before != change.after)
+            .collect();
+        let (fee, staking, error) = match result {
+            Ok(events) => (TRANSFER_FEE, events, None),
+            Err(e) => (0, Vec::new(), Some(e.to_string())),
+        };
+        failed = error.is_some();
+        txs.push(TxTrace {
+            index,
+            hash: tx.hash(),
+            sender: tx.sender,
+            call: String::from_utf8_lossy(&tx.call).to_string(),
+            nonce: tx.get_nonce(),
+            fee,
+            accounts,
+            staking,
+            error,
+        });
+        if failed {
+            break;
+        }
+    }
+
+    let state_root = if failed { None } else { Some(state.commit()) };
+    // The replayed state is not kept
+    chain.abandon_state(statedb.borrow_mut().take_journal());
+    Ok(BlockReplay {
+        height,
+        hash: block.hash(),
+        parent_state_root: parent.state_root(),
+        expected_state_root: block.state_root(),
+        state_root,
+        matches: state_root == Some(block.state_root()),
+        txs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::test_utils::{self, BlockBuilder};
+
+    #[test]
+    fn replay_transfer() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+        let receiver = test_utils::test_address(1);
+        let tx = test_utils::transfer(&test_utils::genesis_key(), 1, receiver, 100);
+        let mut block = BlockBuilder::on(&genesis).txs(vec![tx.clone()]).build();
+        block.set_state_root(chain.apply_transactions(genesis.state_root(), &block));
+        chain.import_block(&block).unwrap();
+
+        let replayed = replay(&chain, 1).unwrap();
+        assert!(replayed.matches);
+        assert_eq!(replayed.state_root, Some(block.state_root()));
+        let trace = &replayed.txs[0];
+        assert_eq!(trace.hash, tx.hash());
+        assert_eq!(trace.fee, TRANSFER_FEE);
+        assert_eq!(trace.error, None);
+        let received = trace.accounts.iter().find(|change| change.address == receiver).unwrap();
+        assert_eq!(received.after.balance - received.before.balance, 100);
+        let sent = trace.accounts.iter().find(|change| change.address == tx.sender).unwrap();
+        assert_eq!(sent.after.nonce, sent.before.nonce + 1);
+
+        assert!(replay(&chain, 0).is_err());
+        assert!(replay(&chain, 2).is_err());
+    }
+}
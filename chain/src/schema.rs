@@ -0,0 +1,92 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Version of the chain db layout and the migrations bringing datadirs of older clients to it.
+use serde::{Deserialize, Serialize};
+
+use errors::Error;
+use map_core::types::Hash;
+
+use crate::blockchain::BlockChain;
+use crate::BlockChainErrorKind;
+
+/// Layout written by this client
+pub const SCHEMA_VERSION: u32 = 3;
+/// Version of the datadirs written before the metadata was recorded
+pub const UNVERSIONED: u32 = 1;
+pub const CLIENT_VERSION: &str = concat!("map/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChainMetadata {
+    pub schema_version: u32,
+    pub genesis_hash: Hash,
+    /// Client that last opened the db for writing
+    pub client_version: String,
+}
+
+impl ChainMetadata {
+    pub fn new(schema_version: u32, genesis_hash: Hash) -> Self {
+        ChainMetadata { schema_version, genesis_hash, client_version: CLIENT_VERSION.to_string() }
+    }
+}
+
+/// Step of the db from `version - 1` to `version`
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub run: fn(&mut BlockChain) -> Result<(), Error>,
+}
+
+/// Migrations by increasing version, each runs once and the version is recorded after it, so
+/// an interrupted migration runs again on the next start. Version 2 only indexed the blocks of
+/// each proposer, version 3 builds both indexes and replaces it.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 3, description: "index the blocks and block heights of each proposer", run: index_proposers },
+];
+
+fn index_proposers(chain: &mut BlockChain) -> Result<(), Error> {
+    chain.index_proposers(|done, total| info!("indexed {}/{} blocks", done, total))
+}
+
+/// Migrations to run on a db of `version`, in order
+pub fn pending(version: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS.iter().filter(move |m| m.version > version)
+}
+
+/// Refuse databases written by a newer client, whose layout this one doesn't know
+pub fn check_version(version: u32) -> Result<(), Error> {
+    if version > SCHEMA_VERSION {
+        return Err(BlockChainErrorKind::UnsupportedSchema.reason(format!(
+            "database schema version {} is newer than version {} of {}", version, SCHEMA_VERSION, CLIENT_VERSION)).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        assert_eq!(MIGRATIONS.last().map(|m| m.version), Some(SCHEMA_VERSION));
+        assert_eq!(pending(UNVERSIONED).count(), MIGRATIONS.len());
+        assert_eq!(pending(SCHEMA_VERSION).count(), 0);
+
+        assert!(check_version(SCHEMA_VERSION).is_ok());
+        assert!(check_version(SCHEMA_VERSION + 1).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshots of the state at a block, written to files under `datadir/snapshots` to clone
+//! a node or recover a lost datadir without syncing from genesis.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use map_core::block::Block;
+use map_core::state::ArchiveDB;
+use map_core::state_sync::{self, StateSync};
+use map_core::types::Hash;
+use crate::blockchain::BlockChain;
+use crate::lock::TimedRwLock;
+
+/// Folder of the datadir the snapshots are written to
+pub const SNAPSHOT_DIR: &str = "snapshots";
+/// Snapshots kept by default, older ones are removed
+pub const DEFAULT_SNAPSHOT_KEEP: usize = 2;
+const SNAPSHOT_VERSION: u8 = 1;
+/// How often the background task looks at the head
+const SNAPSHOT_POLL: Duration = Duration::from_secs(10);
+/// Restored nodes kept in memory before writing them to the state db
+const RESTORE_COMMIT_INTERVAL: usize = 16384;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SnapshotConfig {
+    /// Blocks between two snapshots, 0 disables them
+    pub interval: u64,
+    /// Snapshots kept on disk
+    pub keep: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            interval: 0,
+            keep: DEFAULT_SNAPSHOT_KEEP,
+        }
+    }
+}
+
+/// A block with every state trie node under its state root: the accounts, the staking
+/// records and the account storage.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u8,
+    pub genesis_hash: Hash,
+    pub block: Block,
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Collect the state of `block` from `db`
+    pub fn new(db: &ArchiveDB, genesis_hash: Hash, block: Block) -> Result<Self, String> {
+        let nodes = state_sync::collect_nodes(db, block.state_root())?;
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            genesis_hash,
+            block,
+            nodes,
+        })
+    }
+
+    pub fn height(&self) -> u64 {
+        self.block.height()
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+        let snapshot: Snapshot = bincode::deserialize_from(BufReader::new(file))
+            .map_err(|e| format!("invalid snapshot {}: {}", path.display(), e))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!("unsupported snapshot version {}", snapshot.version));
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot to `dir`, named after its height. The file only appears once
+    /// complete, an interrupted write leaves no snapshot behind.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(file_name(self.height()));
+        let tmp = path.with_extension("tmp");
+        let file = File::create(&tmp).map_err(|e| format!("failed to create {}: {}", tmp.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        bincode::serialize_into(&mut writer, self).map_err(|e| format!("failed to write snapshot: {}", e))?;
+        writer.flush().map_err(|e| format!("failed to write snapshot: {}", e))?;
+        writer.get_ref().sync_all().map_err(|e| format!("failed to write snapshot: {}", e))?;
+        fs::rename(&tmp, &path).map_err(|e| format!("failed to rename {}: {}", tmp.display(), e))?;
+        Ok(path)
+    }
+
+    /// Start the chain from the snapshot block, the chain must be at genesis
+    pub fn restore(&self, chain: &mut BlockChain) -> Result<(), String> {
+        if self.genesis_hash != chain.genesis_hash() {
+            return Err("snapshot is of another network".to_string());
+        }
+        if chain.current_block().height() != 0 {
+            return Err(format!("chain at height {}, restore needs an empty datadir", chain.current_block().height()));
+        }
+
+        let nodes: HashMap<Hash, &Vec<u8>> = self.nodes.iter()
+            .map(|node| (Hash(hash::blake2b_256(node)), node))
+            .collect();
+        let mut sync = StateSync::new(chain.statedb(), self.block.state_root());
+        let mut committed = 0;
+        while !sync.is_done() {
+            let batch = sync.next_batch(RESTORE_COMMIT_INTERVAL);
+            sync.import(batch.iter().map(|key| nodes.get(key).map(|node| node.to_vec())).collect())?;
+            if sync.imported() - committed >= RESTORE_COMMIT_INTERVAL {
+                sync.commit();
+                committed = sync.imported();
+            }
+        }
+        sync.commit();
+        chain.import_checkpoint(&self.block).map_err(|e| format!("failed to import snapshot block: {:#}", e))
+    }
+}
+
+fn file_name(height: u64) -> String {
+    format!("snapshot-{:012}.bin", height)
+}
+
+/// Heights and paths of the snapshots in `dir`, oldest first
+pub fn list(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let mut snapshots: Vec<(u64, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let height = name.strip_prefix("snapshot-")?.strip_suffix(".bin")?.parse().ok()?;
+                Some((height, entry.path()))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    snapshots.sort();
+    snapshots
+}
+
+/// Remove all but the `keep` most recent snapshots in `dir`
+pub fn prune(dir: &Path, keep: usize) -> Result<(), String> {
+    let snapshots = list(dir);
+    let stale = snapshots.len().saturating_sub(keep);
+    for (_, path) in snapshots.into_iter().take(stale) {
+        fs::remove_file(&path).map_err(|e| format!("failed to remove {}: {}", path.display(), e))?;
+        info!("removed snapshot {}", path.display());
+    }
+    Ok(())
+}
+
+/// Write a snapshot every `config.interval` blocks from a background thread until the
+/// returned sender is signalled or dropped.
+pub fn start(chain: Arc<TimedRwLock<BlockChain>>, dir: PathBuf, config: SnapshotConfig) -> mpsc::Sender<()> {
+    let (stop, signal) = mpsc::channel();
+    if config.interval == 0 {
+        return stop;
+    }
+    thread::spawn(move || {
+        let mut last = list(&dir).last().map(|(height, _)| *height).unwrap_or(0);
+        while let Err(mpsc::RecvTimeoutError::Timeout) = signal.recv_timeout(SNAPSHOT_POLL) {
+            let (genesis_hash, block, db, _pin) = {
+                let chain = chain.read().expect("acquiring block_chain read lock");
+                let height = chain.current_block().height() / config.interval * config.interval;
+                if height <= last {
+                    continue;
+                }
+                let block = match chain.get_block_by_number(height) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                if !chain.has_state(block.state_root()) {
+                    warn!("state of height {} is pruned, no snapshot written", height);
+                    last = height;
+                    continue;
+                }
+                (chain.genesis_hash(), block, chain.statedb().clone(), chain.pin_state())
+            };
+            // Committed trie nodes never change and are not pruned while pinned, the state is
+            // read without holding the chain
+            last = block.height();
+            match Snapshot::new(&db, genesis_hash, block).and_then(|snapshot| snapshot.write(&dir)) {
+                Ok(path) => info!("wrote snapshot {}", path.display()),
+                Err(e) => warn!("snapshot at height {} failed: {}", last, e),
+            }
+            if let Err(e) = prune(&dir, config.keep) {
+                warn!("snapshot cleanup failed: {}", e);
+            }
+        }
+    });
+    stop
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use map_core::chain_spec::ChainSpec;
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("map-snapshot-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn write_and_prune() {
+        let dir = temp_dir("prune");
+        let spec = ChainSpec::default();
+        let mut chain = BlockChain::new_in_memory(&spec);
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+        let snapshot = Snapshot::new(chain.statedb(), chain.genesis_hash(), genesis.clone()).unwrap();
+        assert!(!snapshot.nodes.is_empty());
+
+        let path = snapshot.write(&dir).unwrap();
+        let read = Snapshot::read(&path).unwrap();
+        assert_eq!(read.block, genesis);
+        assert_eq!(read.nodes, snapshot.nodes);
+
+        for height in &[10u64, 20, 30] {
+            fs::copy(&path, dir.join(file_name(*height))).unwrap();
+        }
+        prune(&dir, 2).unwrap();
+        let heights: Vec<u64> = list(&dir).into_iter().map(|(height, _)| height).collect();
+        assert_eq!(heights, vec![20, 30]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_checks_chain() {
+        let spec = ChainSpec::default();
+        let mut chain = BlockChain::new_in_memory(&spec);
+        chain.load().unwrap();
+        let mut snapshot = Snapshot::new(chain.statedb(), chain.genesis_hash(), chain.current_block()).unwrap();
+
+        // Restoring needs a block past genesis
+        assert!(snapshot.restore(&mut chain).is_err());
+
+        snapshot.genesis_hash = Hash([1u8; 32]);
+        assert_eq!(snapshot.restore(&mut chain).unwrap_err(), "snapshot is of another network");
+    }
+}
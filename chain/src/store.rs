@@ -14,30 +14,126 @@
 // You should have received a copy of the GNU General Public License
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use map_store::mapdb::MapDB;
+use std::io::Error;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use map_store::compress::{self, CompressedKV};
 use map_store::Config;
-use map_store::Error;
+use map_store::{KVDB, OpenError};
 use map_core::block::{Header, Block};
-use map_core::types::Hash;
+use map_core::staking::StakingEvent;
+use map_core::state::StateJournal;
+use map_core::types::{Address, Hash};
 use bincode;
+use crate::block_cache::BlockCache;
+use crate::fee_stats::BlockFeeStats;
+use crate::missed_proposals::MissedProposals;
+use crate::authorship::ProposedBlocks;
+use crate::committee::CommitteeMember;
+use crate::schema::ChainMetadata;
+use crate::prune::NodeMode;
+
+pub use map_store::compress::Codec;
+pub use map_store::DbEngine;
 
 const HEADER_PREFIX: u8 = 'h' as u8;
 const HEAD_PREFIX: u8 = 'H' as u8;
 const BLOCK_PREFIX: u8 = 'b' as u8;
 const HEADERHASH_PREFIX: u8 = 'n' as u8;
+const FEE_STATS_PREFIX: u8 = 'f' as u8;
+const STAKING_EVENTS_PREFIX: u8 = 'e' as u8;
+const MISSED_PROPOSALS_PREFIX: u8 = 'm' as u8;
+const QUEUED_BLOCK_PREFIX: u8 = 'q' as u8;
+/// Committees with the staking address of each member, those under 'c' had none and are ignored
+const COMMITTEE_PREFIX: u8 = 'C' as u8;
+const PROPOSED_BLOCKS_PREFIX: u8 = 'p' as u8;
+const PROPOSER_INDEX_PREFIX: u8 = 'a' as u8;
+const STATE_JOURNAL_PREFIX: u8 = 'j' as u8;
 const HEAD_KEY: &str = "HEAD";
+const MISSED_CHECKPOINT_KEY: &str = "MISSED";
+const STATE_CHECKPOINT_KEY: &str = "CHECKPOINT";
+const COMMITTED_KEY: &str = "COMMITTED";
+const PRUNED_KEY: &str = "PRUNED";
+const METADATA_KEY: &str = "METADATA";
+const MODE_KEY: &str = "MODE";
+
+/// Codecs of the values written to the chain and state databases of a datadir
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Compression {
+    /// Block bodies of the chain db
+    pub blocks: Codec,
+    /// Trie nodes of the state db
+    pub state: Codec,
+}
 
+/// Chain and state databases of `datadir`, values written with the codecs of `compression`.
+/// Datadirs written before values carried a format byte are used as they are, uncompressed.
+/// `engine` is the storage engine of a new datadir, existing ones keep theirs.
+pub(crate) fn open_backends(datadir: &Path, compression: Compression, engine: Option<DbEngine>, read_only: bool)
+    -> Result<(Box<dyn KVDB>, Arc<RwLock<dyn KVDB>>), OpenError>
+{
+    let state_dir = datadir.join("data");
+    let (chain_cfg, state_cfg) = if read_only {
+        (Config::new(datadir.to_path_buf()).read_only(), Config::new(state_dir.clone()).read_only())
+    } else {
+        (Config::new(datadir.to_path_buf()), Config::new(state_dir.clone()))
+    };
+    let (chain_cfg, state_cfg) = (chain_cfg.named("chain").with_engine(engine), state_cfg.named("state").with_engine(engine));
+    let chain_path = chain_cfg.path.clone();
+    let mut chain_db = map_store::open(chain_cfg)?;
+    let mut state_db = map_store::open(state_cfg)?;
+
+    let framed = compress::is_framed(chain_db.as_ref()).map_err(|e| OpenError::io(&chain_path, e))?;
+    let head = chain_db.get(&ChainDB::head_key()).map_err(|e| OpenError::io(&chain_path, e))?;
+    if !framed && head.is_some() {
+        if compression != Compression::default() {
+            warn!("datadir {} predates compression, values are kept uncompressed", datadir.display());
+        }
+        let state_kv: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(state_db));
+        return Ok((chain_db, state_kv));
+    }
+    if !framed && !read_only {
+        compress::mark_framed(chain_db.as_mut()).map_err(|e| OpenError::io(&chain_path, e))?;
+        compress::mark_framed(state_db.as_mut()).map_err(|e| OpenError::io(&state_dir, e))?;
+    }
+
+    let chain_kv: Box<dyn KVDB> = Box::new(
+        CompressedKV::new(chain_db, vec![(vec![BLOCK_PREFIX], compression.blocks)]));
+    let state_kv: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(
+        CompressedKV::new(state_db, vec![(vec![], compression.state)])));
+    Ok((chain_kv, state_kv))
+}
 
 /// Blockchain storage backend implement
 pub struct ChainDB {
-    db: MapDB,
+    db: Box<dyn KVDB>,
+    /// Encoded bodies of the recent blocks
+    block_cache: BlockCache,
 }
 
 impl ChainDB {
 
-    pub fn new(cfg: Config) -> Result<Self, Error> {
-        let m = MapDB::open(cfg).unwrap();
-        Ok(ChainDB{db: m})
+    pub fn new(cfg: Config) -> Result<Self, OpenError> {
+        Ok(ChainDB::with_backend(map_store::open(cfg)?))
+    }
+
+    /// Chain storage on top of any key-value backend, e.g. `MemoryKV` in tests
+    pub fn with_backend(db: Box<dyn KVDB>) -> Self {
+        ChainDB{db: db, block_cache: BlockCache::default()}
+    }
+
+    /// Keep the last `capacity` blocks read or written decoded, 0 disables the cache
+    pub fn set_block_cache_size(&mut self, capacity: usize) {
+        self.block_cache = BlockCache::new(capacity);
+    }
+
+    pub fn block_cache_size(&self) -> usize {
+        self.block_cache.capacity()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(key).expect("can not read chain db")
     }
 
     // Save block header by hash (hash --> blockHeader)
@@ -51,7 +147,7 @@ impl ChainDB {
     // Read block header by hash (hash --> blockHeader)
     pub fn get_header(&self, h: &Hash) -> Option<Header> {
         let key = Self::header_key(&(h.0));
-        let serialized = match self.db.get(&key.as_slice()) {
+        let serialized = match self.get(&key.as_slice()) {
             Some(s) => s,
             None => return None,
         };
@@ -81,7 +177,7 @@ impl ChainDB {
             None => return None,
         };
         let key = Self::header_key(&(header_hash.0));
-        let serialized = match self.db.get(&key.as_slice()) {
+        let serialized = match self.get(&key.as_slice()) {
             Some(s) => s,
             None => return None,
         };
@@ -91,7 +187,7 @@ impl ChainDB {
     }
 
     pub fn head_hash(&self) -> Option<Hash> {
-        let h = match self.db.get(&Self::head_key()[..]) {
+        let h = match self.get(&Self::head_key()[..]) {
             Some(h) => h,
             None => return None,
         };
@@ -108,7 +204,7 @@ impl ChainDB {
     // read block header hash to certain height (num --> hash)
     pub fn get_header_hash(&self, num: u64) -> Option<Hash> {
         let key = Self::header_hash_key(num);
-        self.db.get(&key).map(|h| {
+        self.get(&key).map(|h| {
             let mut hash: Hash = Default::default();
             hash.0.copy_from_slice(h.as_slice());
             hash
@@ -127,6 +223,17 @@ impl ChainDB {
         self.db.remove(&key)
     }
 
+    // remove the blocks assigned to every height above `num`, gaps included
+    pub fn delete_header_heights_above(&mut self, num: u64) -> Result<(), Error> {
+        let first = Self::header_hash_key(num + 1);
+        for key in self.db.keys_with_prefix(&[HEADERHASH_PREFIX])? {
+            if key.len() == first.len() && key >= first {
+                self.db.remove(&key)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn head_block(&self) -> Option<Block> {
         let hash = match self.head_hash() {
             Some(h) => h,
@@ -137,14 +244,32 @@ impl ChainDB {
     }
 
     pub fn get_block(&self, h: &Hash) -> Option<Block> {
-        let key = Self::block_key(h);
-        let serialized = match self.db.get(&key[..]) {
-            Some(s) => s,
-            None => return None,
-        };
+        self.get_shared_block(h).map(|b| (*b).clone())
+    }
+
+    // Block from the block cache, or read from disk and then cached
+    pub fn get_shared_block(&self, h: &Hash) -> Option<Arc<Block>> {
+        if let Some(block) = self.block_cache.get(h) {
+            return Some(block);
+        }
+        let serialized = self.get(&Self::block_key(h))?;
+        let block: Arc<Block> = Arc::new(bincode::deserialize(&serialized[..]).unwrap());
+        self.block_cache.insert(*h, block.clone());
+        Some(block)
+    }
 
-        let b: Block = bincode::deserialize(&serialized[..]).unwrap();
-        Some(b)
+    pub fn has_block(&self, h: &Hash) -> bool {
+        self.get(&Self::block_key(h)).is_some()
+    }
+
+    // Stored encoding of a block (hash --> bincode of the block)
+    pub fn get_raw_block(&self, h: &Hash) -> Option<Vec<u8>> {
+        self.get(&Self::block_key(h))
+    }
+
+    // Stored encoding of a block header (hash --> bincode of the header)
+    pub fn get_raw_header(&self, h: &Hash) -> Option<Vec<u8>> {
+        self.get(&Self::header_key(&h.0))
     }
 
     pub fn get_block_by_number(&self, num: u64) -> Option<Block> {
@@ -156,6 +281,10 @@ impl ChainDB {
         self.get_block(&header_hash)
     }
 
+    pub fn get_shared_block_by_number(&self, num: u64) -> Option<Arc<Block>> {
+        self.get_shared_block(&self.get_header_hash(num)?)
+    }
+
     // Seek the common ancestor of two branch
     pub fn find_ancestor(&self, mut a: Header, mut b: Header) -> Option<Hash> {
         if a.height != b.height {
@@ -196,18 +325,265 @@ impl ChainDB {
         self.write_header(&block.header)?;
         let key = Self::block_key(&block.header.hash());
         let encoded: Vec<u8> = bincode::serialize(block).unwrap();
-        self.db.put(&key, &encoded)
+        self.db.put(&key, &encoded)?;
+        // New blocks are the ones peers and clients ask for next
+        self.block_cache.insert(block.header.hash(), Arc::new(block.clone()));
+        Ok(())
     }
 
     // Delete a block with header by hash
     pub fn delete_block(&mut self, h: &Hash) -> Result<(), Error> {
         // Delete block body
+        self.block_cache.remove(h);
         let key = Self::block_key(h);
         self.db.remove(&key[..])?;
         // Delete it's header
         self.delete_header(h)
     }
 
+    // Save fee statistics of a block (hash --> stats)
+    pub fn write_fee_stats(&mut self, h: &Hash, stats: &BlockFeeStats) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(stats).unwrap();
+        self.db.put(&Self::fee_stats_key(h), &encoded)
+    }
+
+    pub fn delete_fee_stats(&mut self, h: &Hash) -> Result<(), Error> {
+        self.db.remove(&Self::fee_stats_key(h))
+    }
+
+    pub fn get_fee_stats(&self, h: &Hash) -> Option<BlockFeeStats> {
+        let serialized = self.get(&Self::fee_stats_key(h))?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    // Save staking events of a block with the emitting transaction (hash --> events)
+    pub fn write_staking_events(&mut self, h: &Hash, events: &[(Hash, StakingEvent)]) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(events).unwrap();
+        self.db.put(&Self::staking_events_key(h), &encoded)
+    }
+
+    pub fn delete_staking_events(&mut self, h: &Hash) -> Result<(), Error> {
+        self.db.remove(&Self::staking_events_key(h))
+    }
+
+    pub fn get_staking_events(&self, h: &Hash) -> Vec<(Hash, StakingEvent)> {
+        self.get(&Self::staking_events_key(h))
+            .and_then(|serialized| bincode::deserialize(&serialized[..]).ok())
+            .unwrap_or_default()
+    }
+
+    // Whether the staking events of a block decode, or none are stored
+    pub fn staking_events_readable(&self, h: &Hash) -> bool {
+        self.get(&Self::staking_events_key(h))
+            .map_or(true, |serialized| bincode::deserialize::<Vec<(Hash, StakingEvent)>>(&serialized[..]).is_ok())
+    }
+
+    // Save missed proposals of an epoch (epoch --> missed slots per validator)
+    pub fn write_missed_proposals(&mut self, epoch: u64, missed: &[MissedProposals]) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(missed).unwrap();
+        self.db.put(&Self::missed_proposals_key(epoch), &encoded)
+    }
+
+    pub fn get_missed_proposals(&self, epoch: u64) -> Vec<MissedProposals> {
+        self.get(&Self::missed_proposals_key(epoch))
+            .and_then(|serialized| bincode::deserialize(&serialized[..]).ok())
+            .unwrap_or_default()
+    }
+
+    // Save the blocks proposed in an epoch (epoch --> blocks per validator)
+    pub fn write_proposed_blocks(&mut self, epoch: u64, proposed: &[ProposedBlocks]) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(proposed).unwrap();
+        self.db.put(&Self::proposed_blocks_key(epoch), &encoded)
+    }
+
+    pub fn get_proposed_blocks(&self, epoch: u64) -> Vec<ProposedBlocks> {
+        self.get(&Self::proposed_blocks_key(epoch))
+            .and_then(|serialized| bincode::deserialize(&serialized[..]).ok())
+            .unwrap_or_default()
+    }
+
+    // Save the heights of the blocks of a proposer in an epoch (proposer, epoch --> heights)
+    pub fn write_proposer_index(&mut self, proposer: &Address, epoch: u64, heights: &[u64]) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(heights).unwrap();
+        self.db.put(&Self::proposer_index_key(proposer, epoch), &encoded)
+    }
+
+    pub fn get_proposer_index(&self, proposer: &Address, epoch: u64) -> Vec<u64> {
+        self.get(&Self::proposer_index_key(proposer, epoch))
+            .and_then(|serialized| bincode::deserialize(&serialized[..]).ok())
+            .unwrap_or_default()
+    }
+
+    // Save the validators read from the state of an epoch boundary block (hash --> members)
+    pub fn write_committee(&mut self, h: &Hash, members: &[CommitteeMember]) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(members).unwrap();
+        self.db.put(&Self::committee_key(h), &encoded)
+    }
+
+    pub fn get_committee(&self, h: &Hash) -> Option<Vec<CommitteeMember>> {
+        let serialized = self.get(&Self::committee_key(h))?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    // Last slot checked for missed proposals
+    pub fn missed_checkpoint(&self) -> Option<u64> {
+        let serialized = self.get(&Self::missed_checkpoint_key())?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn write_missed_checkpoint(&mut self, slot: u64) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(&slot).unwrap();
+        self.db.put(&Self::missed_checkpoint_key(), &encoded)
+    }
+
+    // Block the chain was bootstrapped from, its ancestors are not stored
+    pub fn state_checkpoint(&self) -> Option<Hash> {
+        let serialized = self.get(&Self::state_checkpoint_key())?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn write_state_checkpoint(&mut self, hash: &Hash) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(hash).unwrap();
+        self.db.put(&Self::state_checkpoint_key(), &encoded)
+    }
+
+    // Last block whose state was flushed to disk, the head may be ahead of it after a crash
+    pub fn committed_hash(&self) -> Option<Hash> {
+        let serialized = self.get(&Self::committed_key())?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn write_committed_hash(&mut self, hash: &Hash) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(hash).unwrap();
+        self.db.put(&Self::committed_key(), &encoded)
+    }
+
+    // Lowest height whose state a full node still keeps, states below it were pruned
+    pub fn pruned_height(&self) -> Option<u64> {
+        let serialized = self.get(&Self::pruned_key())?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn write_pruned_height(&mut self, height: u64) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(&height).unwrap();
+        self.db.put(&Self::pruned_key(), &encoded)
+    }
+
+    // State node references of an imported block, kept while its state may be pruned (height, hash --> journal)
+    pub fn write_state_journal(&mut self, height: u64, h: &Hash, journal: &StateJournal) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(journal).unwrap();
+        self.db.put(&Self::state_journal_key(height, h), &encoded)
+    }
+
+    // Journals of the blocks imported at `height`, none on backends unable to list their keys
+    pub fn state_journals(&self, height: u64) -> Vec<(Hash, StateJournal)> {
+        let prefix = Self::state_journal_key(height, &Hash::default());
+        let keys = match self.db.keys_with_prefix(&prefix[..9]) {
+            Ok(keys) => keys,
+            Err(_) => return Vec::new(),
+        };
+        keys.into_iter()
+            .filter(|key| key.len() == 41)
+            .filter_map(|key| {
+                let journal = bincode::deserialize(&self.get(&key)?[..]).ok()?;
+                Some((Hash::from_bytes(&key[9..]), journal))
+            })
+            .collect()
+    }
+
+    pub fn delete_state_journal(&mut self, height: u64, h: &Hash) -> Result<(), Error> {
+        self.db.remove(&Self::state_journal_key(height, h))
+    }
+
+    // Mode the node last ran in, none before it was recorded
+    pub fn node_mode(&self) -> Option<NodeMode> {
+        let serialized = self.get(&Self::mode_key())?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn write_node_mode(&mut self, mode: NodeMode) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(&mode).unwrap();
+        self.db.put(&Self::mode_key(), &encoded)
+    }
+
+    // Schema version, genesis and client of the db, none for dbs of clients predating it
+    pub fn metadata(&self) -> Option<ChainMetadata> {
+        let serialized = self.get(&Self::metadata_key())?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn write_metadata(&mut self, metadata: &ChainMetadata) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(metadata).unwrap();
+        self.db.put(&Self::metadata_key(), &encoded)
+    }
+
+    // Gossip block received ahead of the head, kept until the chain reaches it (height, hash --> block)
+    pub fn write_queued_block(&mut self, block: &Block) -> Result<(), Error> {
+        let encoded: Vec<u8> = bincode::serialize(block).unwrap();
+        self.db.put(&Self::queued_block_key(block.height(), &block.hash()), &encoded)
+    }
+
+    pub fn get_queued_block(&self, height: u64, h: &Hash) -> Option<Block> {
+        let serialized = self.get(&Self::queued_block_key(height, h))?;
+        bincode::deserialize(&serialized[..]).ok()
+    }
+
+    pub fn delete_queued_block(&mut self, height: u64, h: &Hash) -> Result<(), Error> {
+        self.db.remove(&Self::queued_block_key(height, h))
+    }
+
+    // Heights and hashes of the queued blocks, lowest first, none on backends unable to list their keys
+    pub fn queued_blocks(&self) -> Vec<(u64, Hash)> {
+        let keys = match self.db.keys_with_prefix(&[QUEUED_BLOCK_PREFIX]) {
+            Ok(keys) => keys,
+            Err(_) => return Vec::new(),
+        };
+        keys.into_iter()
+            .filter(|key| key.len() == 41)
+            .map(|key| {
+                let mut height = [0u8; 8];
+                height.copy_from_slice(&key[1..9]);
+                (u64::from_be_bytes(height), Hash::from_bytes(&key[9..]))
+            })
+            .collect()
+    }
+
+    fn pruned_key() -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(HEAD_PREFIX);
+        pre.extend_from_slice(PRUNED_KEY.as_bytes());
+        pre
+    }
+
+    fn mode_key() -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(HEAD_PREFIX);
+        pre.extend_from_slice(MODE_KEY.as_bytes());
+        pre
+    }
+
+    fn state_journal_key(num: u64, hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(STATE_JOURNAL_PREFIX);
+        pre.extend_from_slice(&num.to_be_bytes());
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
+    fn metadata_key() -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(HEAD_PREFIX);
+        pre.extend_from_slice(METADATA_KEY.as_bytes());
+        pre
+    }
+
+    fn committed_key() -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(HEAD_PREFIX);
+        pre.extend_from_slice(COMMITTED_KEY.as_bytes());
+        pre
+    }
+
     fn head_key() -> Vec<u8> {
         let mut pre = Vec::new();
         pre.push(HEAD_PREFIX);
@@ -230,6 +606,71 @@ impl ChainDB {
         pre
     }
 
+    fn fee_stats_key(hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(FEE_STATS_PREFIX);
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
+    fn staking_events_key(hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(STAKING_EVENTS_PREFIX);
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
+    fn missed_proposals_key(epoch: u64) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(MISSED_PROPOSALS_PREFIX);
+        pre.extend_from_slice(&epoch.to_be_bytes());
+        pre
+    }
+
+    fn proposed_blocks_key(epoch: u64) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(PROPOSED_BLOCKS_PREFIX);
+        pre.extend_from_slice(&epoch.to_be_bytes());
+        pre
+    }
+
+    fn proposer_index_key(proposer: &Address, epoch: u64) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(PROPOSER_INDEX_PREFIX);
+        pre.extend_from_slice(&proposer.0);
+        pre.extend_from_slice(&epoch.to_be_bytes());
+        pre
+    }
+
+    fn committee_key(hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(COMMITTEE_PREFIX);
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
+    fn missed_checkpoint_key() -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(HEAD_PREFIX);
+        pre.extend_from_slice(MISSED_CHECKPOINT_KEY.as_bytes());
+        pre
+    }
+
+    fn state_checkpoint_key() -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(HEAD_PREFIX);
+        pre.extend_from_slice(STATE_CHECKPOINT_KEY.as_bytes());
+        pre
+    }
+
+    fn queued_block_key(num: u64, hash: &Hash) -> Vec<u8> {
+        let mut pre = Vec::new();
+        pre.push(QUEUED_BLOCK_PREFIX);
+        pre.extend_from_slice(&num.to_be_bytes());
+        pre.extend_from_slice(hash.to_slice());
+        pre
+    }
+
     fn block_key(hash: &Hash) -> Vec<u8> {
         let mut pre = Vec::new();
         pre.push(BLOCK_PREFIX);
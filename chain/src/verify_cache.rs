@@ -0,0 +1,83 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Blocks whose signatures and seal were already checked, so retried sync batches skip them.
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use map_core::block::Block;
+use map_core::types::Hash;
+
+/// The number of verified block hashes remembered.
+pub const VERIFY_CACHE_SIZE: usize = 4096;
+
+/// Bounded set of blocks which passed `validate_block` and `verify_seal`. Both checks only depend
+/// on the block content, so the outcome holds whatever the chain state when the block is retried.
+/// Blocks are keyed by `digest`, a header sent again with another body is checked again.
+pub struct VerifyCache {
+    verified: Mutex<LruCache<Hash, ()>>,
+}
+
+impl VerifyCache {
+    pub fn new(capacity: usize) -> Self {
+        VerifyCache {
+            verified: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn is_verified(&self, hash: &Hash) -> bool {
+        self.verified.lock().expect("acquiring verify cache lock").get(hash).is_some()
+    }
+
+    pub fn insert(&self, hash: Hash) {
+        self.verified.lock().expect("acquiring verify cache lock").put(hash, ());
+    }
+
+    pub fn len(&self) -> usize {
+        self.verified.lock().expect("acquiring verify cache lock").len()
+    }
+}
+
+/// Hash of the whole encoded block. The block hash only covers the header, the transactions and
+/// signatures are bound to it through roots the cached checks verify.
+pub fn digest(block: &Block) -> Hash {
+    Hash::make_hash(&bincode::serialize(block).expect("block serializes"))
+}
+
+impl Default for VerifyCache {
+    fn default() -> Self {
+        VerifyCache::new(VERIFY_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recent() {
+        let cache = VerifyCache::new(2);
+        cache.insert(Hash([1u8; 32]));
+        cache.insert(Hash([2u8; 32]));
+        assert!(cache.is_verified(&Hash([1u8; 32])));
+
+        cache.insert(Hash([3u8; 32]));
+        assert!(cache.is_verified(&Hash([1u8; 32])));
+        assert!(!cache.is_verified(&Hash([2u8; 32])));
+        assert_eq!(cache.len(), 2);
+    }
+}
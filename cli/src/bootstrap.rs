@@ -0,0 +1,97 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Start of a fresh node from the latest finalized block of a trusted node, fetched over its
+//! http rpc, instead of syncing from genesis.
+use serde::Deserialize;
+use chain::blockchain::BlockChain;
+use map_core::runtime::Interpreter;
+use map_core::staking::Staking;
+use map_core::state_sync::StateSync;
+use map_rpc_client::RpcClient;
+use rpc::status::STATUS_PATH;
+use service::NodeConfig;
+
+/// Nodes asked in one `map_getStateNodes` call, the most a node serves
+const STATE_NODES_BATCH: usize = 256;
+/// Downloaded nodes kept in memory before writing them to the state db
+const STATE_COMMIT_INTERVAL: usize = 16384;
+
+#[derive(Deserialize)]
+struct RemoteStatus {
+    finalized_height: u64,
+}
+
+/// Import the latest finalized block of the node at `url` with its state as the checkpoint of
+/// the chain in the datadir. Chains past genesis are left alone, so the option can stay set.
+pub fn bootstrap(config: &NodeConfig, url: &str) -> Result<(), String> {
+    let mut chain = BlockChain::with_storage(config.data_dir.clone(), &config.chain_spec, config.compression, config.db_engine)
+        .map_err(|e| format!("failed to open chain data: {:#}", e))?;
+    chain.load().map_err(|e| format!("failed to load chain data: {:#}", e))?;
+    let current = chain.current_block();
+    if current.height() != 0 {
+        println!("Chain at height {}, skip bootstrap", current.height());
+        return Ok(());
+    }
+
+    let client = RpcClient::new(url)?;
+    let status: RemoteStatus = serde_json::from_slice(&client.get(STATUS_PATH)?)
+        .map_err(|e| format!("invalid node status: {}", e))?;
+    let height = status.finalized_height;
+    if height == 0 {
+        println!("Trusted node has no finalized block past genesis, skip bootstrap");
+        return Ok(());
+    }
+
+    let genesis = client.get_block_by_number(0)?;
+    if genesis.map(|b| b.hash()) != Some(chain.genesis_hash()) {
+        return Err("trusted node is on another network".to_string());
+    }
+    let block = client.get_block_by_number(height)?
+        .ok_or_else(|| format!("trusted node has no block {}", height))?;
+    let remote = client.get_validators(height)?
+        .ok_or_else(|| format!("trusted node has no validators at {}", height))?;
+    if block.height() != height || remote.state_root != block.state_root() {
+        return Err(format!("inconsistent checkpoint at {}", height));
+    }
+
+    println!("Bootstrap from block height={}, hash={}", height, block.hash());
+    let mut sync = StateSync::new(chain.statedb(), block.state_root());
+    let mut committed = 0;
+    while !sync.is_done() {
+        let batch = sync.next_batch(STATE_NODES_BATCH);
+        let nodes = client.get_state_nodes(&batch)?;
+        if nodes.is_empty() {
+            return Err("trusted node returned no state nodes".to_string());
+        }
+        sync.import(nodes)?;
+        if sync.imported() - committed >= STATE_COMMIT_INTERVAL {
+            sync.commit();
+            committed = sync.imported();
+            println!("Downloaded {} state nodes", committed);
+        }
+    }
+    sync.commit();
+    println!("Downloaded {} state nodes", sync.imported());
+
+    let validators = Staking::new(Interpreter::new(chain.state_at(block.state_root()))).validator_set();
+    if validators != remote.validators {
+        return Err("validator set does not match the downloaded state".to_string());
+    }
+    chain.import_checkpoint(&block).map_err(|e| format!("failed to import checkpoint: {:#}", e))?;
+    Ok(())
+}
@@ -17,19 +17,35 @@
 //! MAP CLI.
 extern crate ctrlc;
 
-use std::path::PathBuf;
-use clap::{App, Arg, SubCommand};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use logger::LogConfig;
-use service::{Service, NodeConfig};
+use service::{Service, NodeConfig, SignerConfig};
+use rpc::config::{check_modules, parse_modules};
+use rpc::http_server::parse_domains;
 use std::sync::Arc;
 use parking_lot::{Condvar, Mutex};
 use std::sync::mpsc;
 use ed25519::{privkey::PrivKey, generator};
-use network::{Multiaddr};
+use map_core::chain_spec::{ChainSpec, EngineSpec, SpecConstants, PRESETS};
+use map_core::genesis;
 use map_core::types::Address;
+use chain::accounts::{self, AccountDump};
+use chain::blockchain::BlockChain;
+use chain::replay;
+use chain::snapshot::{self, Snapshot};
+use chain::store::{Codec, DbEngine};
+use chain::prune::NodeMode;
+
+mod bootstrap;
+mod output;
+mod ports;
+
+use output::{ExitCode, Output};
 
 pub fn run() {
-    let matches = App::new("map")
+    let app = App::new("map")
         .version("0.0.1")
         .about("MAP Protocol - Chain-to-Chain Interoperation Protocol")
         .arg(Arg::with_name("data_dir")
@@ -38,6 +54,22 @@ pub fn run() {
             .value_name("PATH")
             .takes_value(true)
             .help("Run as if map was started in <PATH> instead of the current working directory."))
+        .arg(Arg::with_name("network")
+            .long("network")
+            .value_name("NAME")
+            .takes_value(true)
+            .possible_values(PRESETS)
+            .help("Join a bundled network, mainnet when neither --network nor --genesis is given"))
+        .arg(Arg::with_name("genesis")
+            .long("genesis")
+            .value_name("FILE")
+            .takes_value(true)
+            .conflicts_with("network")
+            .help("Load a custom chain spec from a json file"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .global(true)
+            .help("Print command results and errors as json"))
         .arg(Arg::with_name("log")
             .long("log")
             .short("l")
@@ -55,18 +87,62 @@ pub fn run() {
             .default_value("9545")
             .help("Customize RPC listening port"),
         )
+        .arg(Arg::with_name("rpc_corsdomain")
+            .long("rpc.corsdomain")
+            .value_name("DOMAINS")
+            .takes_value(true)
+            .help("Comma separated origins allowed to make cross-origin RPC requests, '*' allows any"))
+        .arg(Arg::with_name("rpc_vhosts")
+            .long("rpc.vhosts")
+            .value_name("HOSTS")
+            .takes_value(true)
+            .default_value("localhost")
+            .help("Comma separated Host header values accepted by the RPC server, '*' allows any"))
+        .arg(Arg::with_name("rpc_modules")
+            .long("rpc.modules")
+            .value_name("MODULES")
+            .takes_value(true)
+            .help("Comma separated opt-in RPC modules: admin, served on a loopback rpc_addr only, and debug"))
+        .arg(Arg::with_name("rpc_max_response_size")
+            .long("rpc.max-response-size")
+            .value_name("BYTES")
+            .takes_value(true)
+            .help("Largest RPC result sent, larger ones fail and are to be narrowed or paged"))
         .arg(Arg::with_name("single")
             .long("single")
             .short("s")
             .help("Run with single node"))
+        .arg(Arg::with_name("bootstrap_rpc")
+            .long("bootstrap-rpc")
+            .value_name("URL")
+            .takes_value(true)
+            .help("Start a fresh node from the latest finalized block and state of a trusted node's http rpc"))
         .arg(Arg::with_name("key")
             .long("key")
             .takes_value(true)
             .help("Specify private key"))
+        .arg(Arg::with_name("proposer_key")
+            .long("proposer-key")
+            .value_name("KEY")
+            .takes_value(true)
+            .help("Sign produced blocks with this key instead of --key"))
+        .arg(Arg::with_name("remote_signer")
+            .long("remote-signer")
+            .value_name("URL")
+            .takes_value(true)
+            .conflicts_with("proposer_key")
+            .help("Sign produced blocks with an external signer reached over http json rpc"))
+        .arg(Arg::with_name("next_proposer_key")
+            .long("next-proposer-key")
+            .value_name("KEY")
+            .takes_value(true)
+            .help("Key the validator rotated to with staking.rotate_key, signing from the first epoch whose committee holds it"))
         .arg(Arg::with_name("dial_addrs")
             .long("dial_addrs")
             .takes_value(true)
-            .help("One or more  multiaddrs to manually connect to a p2p peer")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Multiaddrs of p2p peers to connect to, comma separated or repeated, e.g. /dns4/host/tcp/40313")
         )
         .arg(
             Arg::with_name("p2p_port")
@@ -75,27 +151,247 @@ pub fn run() {
                 .default_value("40313")
                 .help("Customize p2p listening port"),
         )
+        .arg(Arg::with_name("ports_shift")
+            .long("ports-shift")
+            .value_name("N")
+            .takes_value(true)
+            .help("Add N to the rpc and p2p ports, to run several nodes on one host"))
+        .arg(Arg::with_name("auto_ports")
+            .long("auto-ports")
+            .help("Move the rpc and p2p ports not given explicitly to the next free ones and remember them in the datadir"))
+        .arg(Arg::with_name("max_peer_requests")
+            .long("max-peer-requests")
+            .value_name("N")
+            .takes_value(true)
+            .help("Maximum number of p2p requests a single peer may have in flight"))
+        .arg(Arg::with_name("p2p_security")
+            .long("p2p.security")
+            .value_name("PROTOCOL")
+            .takes_value(true)
+            .possible_values(&["secio", "noise"])
+            .help("Protocol encrypting p2p connections, all peers must use the same one"))
+        .arg(Arg::with_name("p2p_allow_peers")
+            .long("p2p.allow-peers")
+            .value_name("PEER_IDS")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only accept and dial these peer ids, comma separated or repeated"))
+        .arg(Arg::with_name("p2p_trace")
+            .long("p2p-trace")
+            .help("Log every p2p request, response and gossip message to network/p2p-trace.log of the datadir"))
+        .arg(Arg::with_name("sync_batch_size")
+            .long("sync.batch-size")
+            .value_name("N")
+            .takes_value(true)
+            .help("Blocks asked in the first sync batch to a peer, the next batches follow its throughput"))
+        .arg(Arg::with_name("sync_max_batch_size")
+            .long("sync.max-batch-size")
+            .value_name("N")
+            .takes_value(true)
+            .help("Largest sync batch asked to a fast peer"))
+        .arg(Arg::with_name("sync_batch_buffer")
+            .long("sync.batch-buffer")
+            .value_name("N")
+            .takes_value(true)
+            .help("Sync batches downloaded or in flight ahead of the one being imported"))
+        .arg(Arg::with_name("sync_batch_retries")
+            .long("sync.batch-retries")
+            .value_name("N")
+            .takes_value(true)
+            .help("Failed requests of a sync batch before the sync is restarted"))
+        .arg(Arg::with_name("sync_peer_requests")
+            .long("sync.peer-requests")
+            .value_name("N")
+            .takes_value(true)
+            .help("Sync batch requests in flight to a single peer"))
+        .arg(Arg::with_name("txpool_global_slots")
+            .long("txpool.global-slots")
+            .value_name("N")
+            .takes_value(true)
+            .help("Maximum number of executable transactions in the pool"))
+        .arg(Arg::with_name("txpool_account_slots")
+            .long("txpool.account-slots")
+            .value_name("N")
+            .takes_value(true)
+            .help("Maximum number of pooled transactions per sender"))
+        .arg(Arg::with_name("txpool_global_queue")
+            .long("txpool.global-queue")
+            .value_name("N")
+            .takes_value(true)
+            .help("Maximum number of queued transactions waiting for an executable slot"))
+        .arg(Arg::with_name("txpool_local_slots")
+            .long("txpool.local-slots")
+            .value_name("N")
+            .takes_value(true)
+            .help("Maximum number of local transactions kept over the pool limits"))
+        .arg(Arg::with_name("txpool_block_txs")
+            .long("txpool.block-txs")
+            .value_name("N")
+            .takes_value(true)
+            .help("Maximum number of transactions included in a block"))
+        .arg(Arg::with_name("txpool_min_gas_price")
+            .long("txpool.min-gas-price")
+            .value_name("PRICE")
+            .takes_value(true)
+            .help("Lowest gas price accepted into the pool and included in blocks, the chain spec floor by default"))
+        .arg(Arg::with_name("txpool_journal")
+            .long("txpool.journal")
+            .value_name("PATH")
+            .takes_value(true)
+            .help("File keeping the local transactions across restarts, transactions.journal in the datadir by default"))
+        .arg(Arg::with_name("db_engine")
+            .long("db-engine")
+            .value_name("ENGINE")
+            .takes_value(true)
+            .possible_values(&["rocksdb", "sled"])
+            .help("Storage engine of a new datadir, existing datadirs keep the engine they were created with. \
+                   sled has no read-only mode, export and the RPC gateway need the node stopped on sled datadirs"))
+        .arg(Arg::with_name("db_compression_blocks")
+            .long("db.compression.blocks")
+            .value_name("CODEC")
+            .takes_value(true)
+            .possible_values(&["none", "snappy", "lz4"])
+            .help("Compression of the block bodies written to the datadir"))
+        .arg(Arg::with_name("db_compression_state")
+            .long("db.compression.state")
+            .value_name("CODEC")
+            .takes_value(true)
+            .possible_values(&["none", "snappy", "lz4"])
+            .help("Compression of the state trie nodes written to the datadir"))
+        .arg(Arg::with_name("db_block_cache")
+            .long("db.block-cache")
+            .value_name("N")
+            .takes_value(true)
+            .help("Number of recent blocks kept decoded in memory for RPC and sync serving, 0 disables the cache"))
+        .arg(Arg::with_name("max_blocks_behind")
+            .long("propose.max-behind")
+            .value_name("N")
+            .takes_value(true)
+            .help("Skip block proposals while the head is more than N blocks behind the best peer head"))
+        .arg(Arg::with_name("min_proposal_peers")
+            .long("propose.min-peers")
+            .value_name("N")
+            .takes_value(true)
+            .help("Skip block proposals while fewer than N connected peers are on our chain, ignored in dev mode"))
+        .arg(Arg::with_name("snapshot_interval")
+            .long("snapshot.interval")
+            .value_name("N")
+            .takes_value(true)
+            .help("Write a state snapshot to the snapshots folder of the datadir every N blocks, 0 disables snapshots"))
+        .arg(Arg::with_name("snapshot_keep")
+            .long("snapshot.keep")
+            .value_name("N")
+            .takes_value(true)
+            .help("Number of most recent state snapshots kept on disk"))
+        .arg(Arg::with_name("audit_interval")
+            .long("audit.interval")
+            .value_name("MINUTES")
+            .takes_value(true)
+            .help("Check the chain indexes and the pool every MINUTES, 0 disables the audit"))
+        .arg(Arg::with_name("webhooks")
+            .long("webhook")
+            .value_name("URL")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("POST node events as json to the http URL, repeat for several urls"))
+        .arg(Arg::with_name("max_reorg_depth")
+            .long("reorg.max-depth")
+            .value_name("BLOCKS")
+            .takes_value(true)
+            .help("Hold a head rollback at startup deeper than BLOCKS until admin_allowDeepReorg, 64 by default"))
+        .arg(Arg::with_name("webhook_reorg_depth")
+            .long("webhook.reorg-depth")
+            .value_name("BLOCKS")
+            .takes_value(true)
+            .help("Post a reorg event when the head is rolled back at least BLOCKS at startup, 1 by default"))
+        .arg(Arg::with_name("mode")
+            .long("mode")
+            .value_name("MODE")
+            .takes_value(true)
+            .possible_values(&["archive", "full"])
+            .help("Keep the state of every block (archive) or only of the last 128 blocks (full)"))
         .arg(Arg::with_name("seal_block")
             .long("seal")
             .help("Auto generate block"))
         .subcommand(SubCommand::with_name("clean")
             .about("Remove the whole chain data"))
+        .subcommand(SubCommand::with_name("reindex")
+            .about("Rebuild the height index, fee statistics and staking events from the stored blocks"))
+        .subcommand(SubCommand::with_name("spec")
+            .about("Inspect the chain spec selected by --network or --genesis")
+            .subcommand(SubCommand::with_name("export")
+                .about("Print the effective chain spec with its genesis hash and spec hash as json")))
+        .subcommand(SubCommand::with_name("snapshot")
+            .about("Manage the state snapshots")
+            .subcommand(SubCommand::with_name("restore")
+                .about("Start an empty datadir from a state snapshot, the latest one of the datadir by default")
+                .arg(Arg::with_name("file")
+                    .value_name("FILE")
+                    .help("Snapshot file to restore"))))
+        .subcommand(SubCommand::with_name("accounts")
+            .about("Export account balances and build a genesis from them")
+            .subcommand(SubCommand::with_name("export")
+                .about("Write the accounts of a block to a json dump, also while a node is running")
+                .arg(Arg::with_name("block")
+                    .long("block")
+                    .value_name("N")
+                    .takes_value(true)
+                    .help("Height of the block, the head by default"))
+                .arg(Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Dump file to write")))
+            .subcommand(SubCommand::with_name("genesis")
+                .about("Print the chain spec with the genesis allocation of an account dump")
+                .arg(Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Dump file written by accounts export"))
+                .arg(Arg::with_name("time")
+                    .long("time")
+                    .value_name("UNIX_TIME")
+                    .takes_value(true)
+                    .help("Genesis time of the new chain, the one of the spec by default"))))
+        .subcommand(SubCommand::with_name("replay")
+            .about("Re-execute a block on its parent state and print the changes of each transaction as json")
+            .arg(Arg::with_name("block")
+                .long("block")
+                .value_name("N")
+                .takes_value(true)
+                .required(true)
+                .help("Height of the block")))
+        .subcommand(SubCommand::with_name("encode-vector")
+            .about("Print the golden encoding vectors of blocks, transactions and the status handshake"))
         .subcommand(SubCommand::with_name("keygen")
             .about("Generate key pair"))
         .subcommand(SubCommand::with_name("create_account")
-            .about("Generate key pair"))
-        .get_matches();
+            .about("Generate key pair and its address under the address scheme of --network or --genesis"))
+        .subcommand(SubCommand::with_name("status")
+            .about("Print the head and genesis of the chain in the datadir, also while a node is running"));
+
+    let matches = match app.get_matches_safe() {
+        Ok(matches) => matches,
+        // Help and version go to stdout and exit 0, argument errors exit with the config code
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => Output::new(std::env::args().any(|arg| arg == "--json")).fail(ExitCode::Config, e.message),
+    };
+    let out = Output::new(json_output(&matches));
 
     if let Some(_) = matches.subcommand_matches("keygen") {
         let (priv_key, pub_key) = generator::Generator::default().new();
-        println!("priv_key: {:}, pub_key: {:}", priv_key, pub_key);
+        out.result(serde_json::json!({ "priv_key": priv_key.to_string(), "pub_key": pub_key.to_string() }),
+            &format!("priv_key: {:}, pub_key: {:}", priv_key, pub_key));
         return;
     }
 
-    if let Some(_) = matches.subcommand_matches("create_account") {
-        let (priv_key, pub_key) = generator::Generator::default().new();
-        let addr: Address = pub_key.into();
-        println!("priv_key: {:}, address: {:}", priv_key, addr);
+    if let Some(_) = matches.subcommand_matches("encode-vector") {
+        print!("{}", network::vectors::render());
         return;
     }
 
@@ -105,6 +401,30 @@ pub fn run() {
         config.data_dir = PathBuf::from(data_dir);
     }
 
+    if let Some(path) = matches.value_of("genesis") {
+        config.chain_spec = match ChainSpec::from_file(Path::new(path)) {
+            Ok(spec) => spec,
+            Err(e) => out.fail(ExitCode::Config, format!("Failed to load chain spec: {}", e)),
+        };
+    } else if let Some(name) = matches.value_of("network") {
+        config.chain_spec = ChainSpec::preset(name).expect("network name checked by clap");
+    }
+    if !config.chain_spec.data_subdir.is_empty() {
+        config.data_dir = config.data_dir.join(&config.chain_spec.data_subdir);
+    }
+    if config.chain_spec.dev {
+        config.dev_mode = true;
+    }
+
+    if let Some(_) = matches.subcommand_matches("create_account") {
+        // Derived the way the chain of `--network` or `--genesis` derives addresses
+        let (priv_key, pub_key) = generator::Generator::default().new();
+        let addr = Address::from_pubkey(&pub_key, config.chain_spec.genesis.address_scheme);
+        out.result(serde_json::json!({ "priv_key": priv_key.to_string(), "address": addr }),
+            &format!("priv_key: {:}, address: {:}", priv_key, addr));
+        return;
+    }
+
     if let Some(log_filter) = matches.value_of("log") {
         let log_config = LogConfig {
             filter: log_filter.to_string(),
@@ -120,46 +440,200 @@ pub fn run() {
     }
     if let Some(rpc_port) = matches.value_of("rpc_port") {
         let port = rpc_port.parse::<u16>()
-            .map_err(|_| format!("Invalid rpc_port port: {}", rpc_port)).unwrap();
+            .map_err(|_| format!("Invalid rpc_port port: {}", rpc_port)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
         config.rpc_port = port;
     }
 
+    if let Some(domains) = matches.value_of("rpc_corsdomain") {
+        config.rpc_cors = parse_domains(domains);
+    }
+    if let Some(hosts) = matches.value_of("rpc_vhosts") {
+        config.rpc_vhosts = parse_domains(hosts);
+    }
+    if let Some(modules) = matches.value_of("rpc_modules") {
+        config.rpc_modules = parse_modules(modules).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    check_modules(&config.rpc_modules, &config.rpc_addr).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    if let Some(size) = matches.value_of("rpc_max_response_size") {
+        config.rpc_max_response_bytes = size.parse::<usize>()
+            .map_err(|_| format!("Invalid rpc.max-response-size: {}", size)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+
     if let Some(p2p_port) = matches.value_of("p2p_port") {
         let port = p2p_port.parse::<u16>()
-            .map_err(|_| format!("Invalid p2p_port port: {}", p2p_port)).unwrap();
+            .map_err(|_| format!("Invalid p2p_port port: {}", p2p_port)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
         config.p2p_port = port;
     }
+    if let Some(max) = matches.value_of("max_peer_requests") {
+        config.max_peer_requests = max.parse::<usize>()
+            .ok().filter(|max| *max > 0)
+            .ok_or_else(|| format!("Invalid max-peer-requests: {}", max)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(security) = matches.value_of("p2p_security") {
+        config.p2p_security = security.parse().unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(values) = matches.values_of("p2p_allow_peers") {
+        for value in values {
+            let peers = network::config::parse_peer_ids(value)
+                .unwrap_or_else(|e| out.fail(ExitCode::Config, format!("Invalid --p2p.allow-peers: {}", e)));
+            config.allowed_peers.extend(peers);
+        }
+    }
+    config.p2p_trace = matches.is_present("p2p_trace");
+    if let Some(size) = matches.value_of("sync_batch_size") {
+        config.range_sync.batch_size = size.parse::<u64>()
+            .map_err(|_| format!("Invalid sync.batch-size: {}", size)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+        config.range_sync.max_batch_size = config.range_sync.max_batch_size.max(config.range_sync.batch_size);
+    }
+    if let Some(size) = matches.value_of("sync_max_batch_size") {
+        config.range_sync.max_batch_size = size.parse::<u64>()
+            .map_err(|_| format!("Invalid sync.max-batch-size: {}", size)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(buffer) = matches.value_of("sync_batch_buffer") {
+        config.range_sync.batch_buffer = buffer.parse::<usize>()
+            .map_err(|_| format!("Invalid sync.batch-buffer: {}", buffer)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(retries) = matches.value_of("sync_batch_retries") {
+        config.range_sync.batch_retries = retries.parse::<u8>()
+            .map_err(|_| format!("Invalid sync.batch-retries: {}", retries)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(requests) = matches.value_of("sync_peer_requests") {
+        config.range_sync.peer_requests = requests.parse::<usize>()
+            .map_err(|_| format!("Invalid sync.peer-requests: {}", requests)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    config.range_sync.check().unwrap_or_else(|e| out.fail(ExitCode::Config, format!("Invalid sync options: {}", e)));
+
+    if let Some(slots) = matches.value_of("txpool_global_slots") {
+        config.txpool.global_slots = slots.parse::<usize>()
+            .map_err(|_| format!("Invalid txpool.global-slots: {}", slots)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(slots) = matches.value_of("txpool_account_slots") {
+        config.txpool.account_slots = slots.parse::<usize>()
+            .map_err(|_| format!("Invalid txpool.account-slots: {}", slots)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(queue) = matches.value_of("txpool_global_queue") {
+        config.txpool.global_queue = queue.parse::<usize>()
+            .map_err(|_| format!("Invalid txpool.global-queue: {}", queue)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(slots) = matches.value_of("txpool_local_slots") {
+        config.txpool.local_slots = slots.parse::<usize>()
+            .map_err(|_| format!("Invalid txpool.local-slots: {}", slots)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(txs) = matches.value_of("txpool_block_txs") {
+        config.txpool.block_txs = txs.parse::<usize>()
+            .map_err(|_| format!("Invalid txpool.block-txs: {}", txs)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(price) = matches.value_of("txpool_min_gas_price") {
+        config.txpool.min_gas_price = Some(price.parse::<u64>()
+            .map_err(|_| format!("Invalid txpool.min-gas-price: {}", price)).unwrap_or_else(|e| out.fail(ExitCode::Config, e)));
+    }
+    if let Some(behind) = matches.value_of("max_blocks_behind") {
+        config.max_blocks_behind = behind.parse::<u64>()
+            .map_err(|_| format!("Invalid propose.max-behind: {}", behind)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(peers) = matches.value_of("min_proposal_peers") {
+        config.min_proposal_peers = peers.parse::<usize>()
+            .map_err(|_| format!("Invalid propose.min-peers: {}", peers)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(interval) = matches.value_of("audit_interval") {
+        config.audit_interval = interval.parse::<u64>()
+            .map_err(|_| format!("Invalid audit.interval: {}", interval)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(values) = matches.values_of("webhooks") {
+        for url in values {
+            map_rpc_client::RpcClient::new(url)
+                .map_err(|e| format!("Invalid --webhook {}: {}", url, e)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+            config.webhooks.push(url.to_string());
+        }
+    }
+    if let Some(depth) = matches.value_of("max_reorg_depth") {
+        config.max_reorg_depth = depth.parse::<u64>()
+            .map_err(|_| format!("Invalid reorg.max-depth: {}", depth)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(depth) = matches.value_of("webhook_reorg_depth") {
+        config.webhook_reorg_depth = depth.parse::<u64>()
+            .map_err(|_| format!("Invalid webhook.reorg-depth: {}", depth)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(interval) = matches.value_of("snapshot_interval") {
+        config.snapshot.interval = interval.parse::<u64>()
+            .map_err(|_| format!("Invalid snapshot.interval: {}", interval)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(keep) = matches.value_of("snapshot_keep") {
+        config.snapshot.keep = keep.parse::<usize>()
+            .ok().filter(|keep| *keep > 0)
+            .ok_or_else(|| format!("Invalid snapshot.keep: {}", keep)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(engine) = matches.value_of("db_engine") {
+        config.db_engine = Some(engine.parse::<DbEngine>().expect("engine checked by clap"));
+    }
+    if let Some(codec) = matches.value_of("db_compression_blocks") {
+        config.compression.blocks = codec.parse::<Codec>().expect("codec checked by clap");
+    }
+    if let Some(codec) = matches.value_of("db_compression_state") {
+        config.compression.state = codec.parse::<Codec>().expect("codec checked by clap");
+    }
+    if let Some(size) = matches.value_of("db_block_cache") {
+        config.block_cache_size = size.parse::<usize>()
+            .map_err(|_| format!("Invalid db.block-cache: {}", size)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    }
+    if let Some(mode) = matches.value_of("mode") {
+        config.mode = mode.parse::<NodeMode>().expect("mode checked by clap");
+    }
+    config.txpool.journal = Some(matches.value_of("txpool_journal")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config.data_dir.join("transactions.journal")));
 
     if matches.is_present("key") {
         if let Some(key) = matches.value_of("key") {
             if PrivKey::from_hex(key).is_ok() {
                 config.key = key.to_string();
             } else {
-                println!("Please specify correct key");
-                return;
+                out.fail(ExitCode::Config, "Please specify correct key");
             }
         }
     }
+    if let Some(key) = matches.value_of("proposer_key") {
+        if PrivKey::from_hex(key).is_err() {
+            out.fail(ExitCode::Config, "Please specify correct proposer key");
+        }
+        config.signer = SignerConfig::Local(key.to_string());
+    }
+    if let Some(url) = matches.value_of("remote_signer") {
+        config.signer = SignerConfig::Remote(url.to_string());
+    }
+    if let Some(key) = matches.value_of("next_proposer_key") {
+        if PrivKey::from_hex(key).is_err() {
+            out.fail(ExitCode::Config, "Please specify correct next proposer key");
+        }
+        config.next_proposer_key = Some(key.to_string());
+    }
     if matches.is_present("poa_privkey") {
         if let Some(key) = matches.value_of("poa_privkey") {
             if PrivKey::from_hex(key).is_ok() {
-                config.poa_privkey = key.to_string();
+                config.chain_spec.engine = EngineSpec::Poa { validator_key: key.to_string() };
             } else {
-                println!("Please specify correct  poa_privkey");
-                // return;
+                out.fail(ExitCode::Config, "Please specify correct poa_privkey");
+            }
+        }
+    }
+    if let Some(values) = matches.values_of("dial_addrs") {
+        for value in values {
+            match network::config::parse_dial_addrs(value) {
+                Ok(addrs) => for addr in addrs {
+                    if !config.dial_addrs.contains(&addr) {
+                        config.dial_addrs.push(addr);
+                    }
+                },
+                Err(e) => out.fail(ExitCode::Config, format!("Invalid --dial_addrs: {}", e)),
             }
         }
     }
-    if matches.is_present("dial_addrs") {
-        if let Some(addresses_str) = matches.value_of("dial_addrs") {
-            config.dial_addrs = addresses_str
-                .split(',')
-                .map(|multiaddr| {
-                    multiaddr
-                        .parse()
-                        .map_err(|_| format!("Invalid Multiaddr: {}", multiaddr))
-                })
-                .collect::<Result<Vec<Multiaddr>, _>>().unwrap();
+    for bootnode in &config.chain_spec.bootnodes {
+        match network::config::parse_dial_addr(bootnode) {
+            Ok(addr) => if !config.dial_addrs.contains(&addr) {
+                config.dial_addrs.push(addr);
+            },
+            Err(e) => out.fail(ExitCode::Config, format!("Invalid bootnode in chain spec {}: {}", config.chain_spec.name, e)),
         }
     }
 
@@ -177,8 +651,70 @@ pub fn run() {
         return;
     }
 
+    if let Some(_) = matches.subcommand_matches("reindex") {
+        reindex(&config, out);
+        return;
+    }
+
+    if let Some(_) = matches.subcommand_matches("status") {
+        chain_status(&config, out);
+        return;
+    }
+
+    if let Some(spec) = matches.subcommand_matches("spec") {
+        if spec.subcommand_matches("export").is_some() {
+            export_spec(&config.chain_spec);
+        } else {
+            eprintln!("{}", spec.usage());
+        }
+        return;
+    }
+
+    if let Some(snapshot) = matches.subcommand_matches("snapshot") {
+        if let Some(restore) = snapshot.subcommand_matches("restore") {
+            restore_snapshot(&config, restore.value_of("file"), out);
+        } else {
+            eprintln!("{}", snapshot.usage());
+        }
+        return;
+    }
+
+    if let Some(accounts) = matches.subcommand_matches("accounts") {
+        if let Some(export) = accounts.subcommand_matches("export") {
+            let height = export.value_of("block").map(|n| n.parse::<u64>()
+                .unwrap_or_else(|_| out.fail(ExitCode::Config, format!("Invalid block height {}", n))));
+            export_accounts(&config, height, export.value_of("file").unwrap(), out);
+        } else if let Some(genesis) = accounts.subcommand_matches("genesis") {
+            let time = genesis.value_of("time").map(|t| t.parse::<u64>()
+                .unwrap_or_else(|_| out.fail(ExitCode::Config, format!("Invalid genesis time {}", t))));
+            accounts_genesis(&config.chain_spec, genesis.value_of("file").unwrap(), time, out);
+        } else {
+            eprintln!("{}", accounts.usage());
+        }
+        return;
+    }
+
+    if let Some(replay) = matches.subcommand_matches("replay") {
+        let block = replay.value_of("block").unwrap();
+        let height = block.parse::<u64>()
+            .unwrap_or_else(|_| out.fail(ExitCode::Config, format!("Invalid block height {}", block)));
+        replay_block(&config, height, out);
+        return;
+    }
+
+    if let Some(url) = matches.value_of("bootstrap_rpc") {
+        if let Err(e) = bootstrap::bootstrap(&config, url) {
+            out.fail(ExitCode::Runtime, format!("Bootstrap failed: {}", e));
+        }
+    }
+
+    select_ports(&mut config, &matches, out);
+
     let exit = Arc::new((Mutex::new(()), Condvar::new()));
-    let node = Service::new_service(config.clone());
+    let node = match Service::new_service(config.clone()) {
+        Ok(node) => node,
+        Err(e) => out.fail(ExitCode::Db, format!("Failed to open chain data: {:#}", e)),
+    };
     let tx = node.start(config.clone());
 
     wait_exit(exit,tx);
@@ -186,6 +722,185 @@ pub fn run() {
     // th_handle.join().unwrap();
 }
 
+/// Apply `--ports-shift` and `--auto-ports` to the ports of the node. Ports not given explicitly
+/// nor shifted default to the ones remembered in the datadir.
+fn select_ports(config: &mut NodeConfig, matches: &ArgMatches, out: Output) {
+    let fixed_rpc = matches.occurrences_of("rpc_port") > 0;
+    let fixed_p2p = matches.occurrences_of("p2p_port") > 0;
+    let mut selected = ports::Ports { rpc: config.rpc_port, p2p: config.p2p_port };
+    if let Some(shift) = matches.value_of("ports_shift") {
+        let shift = shift.parse::<u16>()
+            .map_err(|_| format!("Invalid ports-shift: {}", shift)).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+        selected = selected.shift(shift).unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+    } else if let Some(remembered) = ports::load(&config.data_dir) {
+        if !fixed_rpc {
+            selected.rpc = remembered.rpc;
+        }
+        if !fixed_p2p {
+            selected.p2p = remembered.p2p;
+        }
+    }
+
+    if matches.is_present("auto_ports") {
+        selected = selected.find_free(&config.rpc_addr, fixed_rpc, fixed_p2p)
+            .unwrap_or_else(|e| out.fail(ExitCode::Config, e));
+        if let Err(e) = ports::save(&config.data_dir, selected) {
+            out.fail(ExitCode::Config, format!("Failed to write {}: {}", ports::PORTS_FILE, e));
+        }
+    }
+    if selected.rpc != config.rpc_port || selected.p2p != config.p2p_port {
+        println!("Listening on rpc port {} and p2p port {}", selected.rpc, selected.p2p);
+    }
+    config.rpc_port = selected.rpc;
+    config.p2p_port = selected.p2p;
+}
+
+/// Whether `--json` was given, before or after the subcommand
+fn json_output(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        (_, Some(sub)) => matches.is_present("json") || json_output(sub),
+        _ => matches.is_present("json"),
+    }
+}
+
+fn open_chain(config: &NodeConfig, out: Output) -> BlockChain {
+    let mut chain = match BlockChain::with_storage(config.data_dir.clone(), &config.chain_spec, config.compression, config.db_engine) {
+        Ok(chain) => chain,
+        Err(e) => out.fail(ExitCode::Db, format!("Failed to open chain data: {:#}", e)),
+    };
+    if let Err(e) = chain.load() {
+        out.fail(ExitCode::Db, format!("Failed to load chain data: {:#}", e));
+    }
+    chain
+}
+
+fn reindex(config: &NodeConfig, out: Output) {
+    let mut chain = open_chain(config, out);
+    let mut blocks = 0;
+    let reindexed = chain.reindex(|done, total| {
+        blocks = total;
+        out.info(&format!("Reindexed {}/{} blocks", done, total));
+    });
+    if let Err(e) = reindexed {
+        out.fail(ExitCode::Db, format!("Reindex failed: {:#}", e));
+    }
+    out.result(serde_json::json!({ "blocks": blocks }), &format!("Reindex done, {} blocks", blocks));
+}
+
+/// Head of the chain in the datadir, read without taking the datadir from a running node
+fn chain_status(config: &NodeConfig, out: Output) {
+    let chain = match BlockChain::open_read_only(config.data_dir.clone()) {
+        Some(chain) => chain,
+        None => out.fail(ExitCode::Db, format!("No chain data in {}", config.data_dir.display())),
+    };
+    let head = chain.current_block();
+    let status = serde_json::json!({
+        "network": config.chain_spec.name,
+        "network_id": config.chain_spec.network_id,
+        "genesis_hash": chain.genesis_hash(),
+        "head_height": head.height(),
+        "head_hash": head.hash(),
+        "earliest_state_height": chain.earliest_state_height(),
+    });
+    out.result(status, &format!("network={} head height={} hash={} genesis={}",
+        config.chain_spec.name, head.height(), head.hash(), chain.genesis_hash()));
+}
+
+/// Import the state and block of a snapshot as the checkpoint of an empty datadir
+fn restore_snapshot(config: &NodeConfig, file: Option<&str>, out: Output) {
+    let path = match file {
+        Some(file) => PathBuf::from(file),
+        None => match snapshot::list(&config.data_dir.join(snapshot::SNAPSHOT_DIR)).pop() {
+            Some((_, path)) => path,
+            None => out.fail(ExitCode::Config,
+                format!("No snapshot in {}", config.data_dir.join(snapshot::SNAPSHOT_DIR).display())),
+        },
+    };
+    let mut chain = open_chain(config, out);
+    let snapshot = Snapshot::read(&path).unwrap_or_else(|e| out.fail(ExitCode::Runtime, format!("Restore failed: {}", e)));
+    out.info(&format!("Restore snapshot height={}, hash={}", snapshot.height(), snapshot.block.hash()));
+    if let Err(e) = snapshot.restore(&mut chain) {
+        out.fail(ExitCode::Runtime, format!("Restore failed: {}", e));
+    }
+    out.result(serde_json::json!({ "height": snapshot.height(), "hash": snapshot.block.hash() }),
+        &format!("Restored snapshot height={}", snapshot.height()));
+}
+
+/// Write the accounts at `height`, the head by default, as a json dump
+fn export_accounts(config: &NodeConfig, height: Option<u64>, file: &str, out: Output) {
+    let chain = match BlockChain::open_read_only(config.data_dir.clone()) {
+        Some(chain) => chain,
+        None => out.fail(ExitCode::Db, format!("No chain data in {}", config.data_dir.display())),
+    };
+    let height = height.unwrap_or_else(|| chain.current_block().height());
+    let dump = accounts::export(&chain, &config.chain_spec.genesis, height)
+        .unwrap_or_else(|e| out.fail(ExitCode::Db, format!("Export failed: {}", e)));
+    // Written as it is encoded rather than held as one string
+    let written = std::fs::File::create(file)
+        .map_err(|e| e.to_string())
+        .and_then(|f| {
+            let mut writer = std::io::BufWriter::new(f);
+            serde_json::to_writer_pretty(&mut writer, &dump).map_err(|e| e.to_string())?;
+            writer.flush().map_err(|e| e.to_string())
+        });
+    if let Err(e) = written {
+        out.fail(ExitCode::Runtime, format!("Write {} failed: {}", file, e));
+    }
+    out.result(serde_json::json!({ "height": dump.height, "hash": dump.hash, "accounts": dump.accounts.len() }),
+        &format!("Exported {} accounts of height={} hash={} to {}", dump.accounts.len(), dump.height, dump.hash, file));
+}
+
+/// Print the trace of the block at `height` re-executed on its parent state, the exit code
+/// is non zero if the computed state root differs from the header's
+fn replay_block(config: &NodeConfig, height: u64, out: Output) {
+    let chain = open_chain(config, out);
+    let trace = replay::replay(&chain, height)
+        .unwrap_or_else(|e| out.fail(ExitCode::Db, format!("Replay failed: {}", e)));
+    println!("{}", serde_json::to_string_pretty(&trace).expect("replay trace serializes"));
+    if !trace.matches {
+        out.fail(ExitCode::Runtime, format!("State root mismatch at height={}, expected={} computed={}",
+            height, trace.expected_state_root,
+            trace.state_root.map_or("none".to_string(), |root| root.to_string())));
+    }
+}
+
+/// Print `spec` funding the accounts of a dump instead of its own allocation, to start a new
+/// chain with --genesis. The poa signing key is left out as in `spec export`.
+fn accounts_genesis(spec: &ChainSpec, file: &str, time: Option<u64>, out: Output) {
+    let dump: AccountDump = std::fs::read_to_string(file)
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| out.fail(ExitCode::Config, format!("Invalid account dump {}: {}", file, e)));
+    if dump.version != accounts::ACCOUNT_DUMP_VERSION {
+        out.fail(ExitCode::Config, format!("Unsupported account dump version {}", dump.version));
+    }
+    let mut spec = spec.clone();
+    spec.genesis.allocation = dump.to_allocation();
+    if let Some(time) = time {
+        spec.genesis.time = time;
+    }
+    if let EngineSpec::Poa { ref mut validator_key } = spec.engine {
+        validator_key.clear();
+    }
+    println!("{}", serde_json::to_string_pretty(&spec).expect("chain spec serializes"));
+}
+
+/// Print the spec peers are checked against in the handshake. The poa signing key
+/// is a local secret and left out, the output loads back with --genesis.
+fn export_spec(spec: &ChainSpec) {
+    let mut spec = spec.clone();
+    if let EngineSpec::Poa { ref mut validator_key } = spec.engine {
+        validator_key.clear();
+    }
+    let export = serde_json::json!({
+        "genesis_hash": format!("0x{:?}", genesis::genesis_hash(&spec.genesis)),
+        "spec_hash": format!("0x{:?}", spec.spec_hash()),
+        "constants": SpecConstants::current(),
+        "spec": spec,
+    });
+    println!("{}", serde_json::to_string_pretty(&export).expect("chain spec serializes"));
+}
+
 pub fn wait_exit(exit: Arc<(Mutex<()>, Condvar)>, tx : mpsc::Sender<i32>) {
     let e = Arc::<(Mutex<()>, Condvar)>::clone(&exit);
     let _ = ctrlc::set_handler(move || {
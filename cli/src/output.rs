@@ -0,0 +1,72 @@
+//! Exit codes and output format of the command line, for scripts and provisioning tools.
+use std::fmt::Display;
+use std::process;
+use serde_json::{json, Value};
+
+/// Exit code of a failed command, 0 is success. The values are stable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitCode {
+    /// Invalid arguments, chain spec or keys
+    Config = 2,
+    /// The chain data can not be opened or is inconsistent
+    Db = 3,
+    /// A command failed while running, e.g. a bootstrap or snapshot restore
+    Runtime = 4,
+}
+
+/// Prints results as text, or as a json object per command with `--json`. Errors go to
+/// stderr in the same format.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Output {
+    pub json: bool,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Self {
+        Output { json }
+    }
+
+    /// Print the result of a command, `value` in json mode and `text` otherwise
+    pub fn result(&self, value: Value, text: &str) {
+        if self.json {
+            println!("{}", value);
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    /// Progress or informational line, left out of the json output
+    pub fn info(&self, text: &str) {
+        if !self.json {
+            println!("{}", text);
+        }
+    }
+
+    /// Report `message` and exit with `code`
+    pub fn fail<M: Display>(&self, code: ExitCode, message: M) -> ! {
+        eprintln!("{}", self.error(code, message));
+        process::exit(code as i32)
+    }
+
+    fn error<M: Display>(&self, code: ExitCode, message: M) -> String {
+        if self.json {
+            json!({ "error": message.to_string(), "exit_code": code as i32 }).to_string()
+        } else {
+            message.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_format() {
+        assert_eq!(Output::new(false).error(ExitCode::Db, "no chain data"), "no chain data");
+        let error: Value = serde_json::from_str(&Output::new(true).error(ExitCode::Db, "no chain data")).unwrap();
+        assert_eq!(error, json!({ "error": "no chain data", "exit_code": 3 }));
+        assert_eq!(ExitCode::Config as i32, 2);
+        assert_eq!(ExitCode::Runtime as i32, 4);
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Listening ports of nodes sharing a host. Ports picked by `--auto-ports` are remembered in the
+//! datadir and become its defaults, so a restarted node keeps the ports its peers dial.
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+/// File of the datadir holding the ports of its node
+pub const PORTS_FILE: &str = "ports.json";
+/// Ports tried above a taken one before giving up
+const MAX_PORT_TRIES: u16 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ports {
+    pub rpc: u16,
+    pub p2p: u16,
+}
+
+impl Ports {
+    /// Both ports moved up by `shift`, e.g. the node index of a local network
+    pub fn shift(self, shift: u16) -> Result<Ports, String> {
+        match (self.rpc.checked_add(shift), self.p2p.checked_add(shift)) {
+            (Some(rpc), Some(p2p)) => Ok(Ports { rpc, p2p }),
+            _ => Err(format!("Ports {} and {} shifted by {} are out of range", self.rpc, self.p2p, shift)),
+        }
+    }
+
+    /// Move the ports that can't be bound on the host to the next free ones, fixed ports are
+    /// left as given.
+    pub fn find_free(self, rpc_addr: &str, fixed_rpc: bool, fixed_p2p: bool) -> Result<Ports, String> {
+        let rpc = if fixed_rpc { self.rpc } else { next_free(rpc_addr, self.rpc, &[self.p2p])? };
+        let p2p = if fixed_p2p { self.p2p } else { next_free("0.0.0.0", self.p2p, &[rpc])? };
+        Ok(Ports { rpc, p2p })
+    }
+}
+
+/// First port from `port` up that can be bound on `addr`, skipping `taken`
+fn next_free(addr: &str, port: u16, taken: &[u16]) -> Result<u16, String> {
+    (port..=port.saturating_add(MAX_PORT_TRIES))
+        .find(|p| *p != 0 && !taken.contains(p) && TcpListener::bind((addr, *p)).is_ok())
+        .ok_or_else(|| format!("No free port on {} from {}", addr, port))
+}
+
+/// Ports remembered for the datadir, None if it has none
+pub fn load(data_dir: &Path) -> Option<Ports> {
+    let raw = fs::read(data_dir.join(PORTS_FILE)).ok()?;
+    serde_json::from_slice(&raw).ok()
+}
+
+pub fn save(data_dir: &Path, ports: Ports) -> io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    let raw = serde_json::to_vec_pretty(&ports).expect("ports serialize");
+    fs::write(data_dir.join(PORTS_FILE), raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_and_find_free() {
+        let ports = Ports { rpc: 9545, p2p: 40313 };
+        assert_eq!(ports.shift(2).unwrap(), Ports { rpc: 9547, p2p: 40315 });
+        assert!(ports.shift(30000).is_err());
+
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let ports = Ports { rpc: port, p2p: port };
+        let free = ports.find_free("127.0.0.1", false, false).unwrap();
+        assert!(free.rpc > port);
+        assert!(free.p2p != free.rpc && free.p2p >= port);
+        // Fixed ports are left to fail at bind
+        assert_eq!(ports.find_free("127.0.0.1", true, true).unwrap(), ports);
+    }
+
+    #[test]
+    fn remembered_in_datadir() {
+        let dir = std::env::temp_dir().join("map_cli_ports_test");
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(load(&dir), None);
+        save(&dir, Ports { rpc: 9546, p2p: 40314 }).unwrap();
+        assert_eq!(load(&dir), Some(Ports { rpc: 9546, p2p: 40314 }));
+    }
+}
@@ -26,6 +26,7 @@ pub enum ErrorKind {
     BlockChain,
     Internal,
     Consensus,
+    Storage,
 }
 
 #[derive(Debug)]
@@ -83,6 +84,7 @@ pub struct InternalError {
 pub enum InternalErrorKind {
     InvalidSignData,
     BalanceNotEnough,
+    BalanceOverflow,
     InvalidTxNonce,
     NoneSign,
     Execute,
@@ -17,6 +17,7 @@
 //! MAP HASH.
 
 pub use blake2b_rs::{Blake2b, Blake2bBuilder};
+use tiny_keccak::{Hasher, Keccak};
 
 pub const BLAKE2B_KEY: &[u8] = &[];
 pub const BLAKE2B_LEN: usize = 32;
@@ -47,6 +48,21 @@ pub fn inner_blake2b_256<T: AsRef<[u8]>>(s: T) -> [u8; 32] {
     result
 }
 
+/// Keccak-256 as used by EVM tooling, for interoperable addresses.
+pub fn keccak_256<T: AsRef<[u8]>>(s: T) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(s.as_ref());
+    keccak.finalize(&mut result);
+    result
+}
+
+#[test]
+fn empty_keccak() {
+    let actual = keccak_256([]);
+    assert_eq!(actual[..4], [0xc5, 0xd2, 0x46, 0x01]);
+}
+
 #[test]
 fn empty_blake2b() {
     let actual = inner_blake2b_256([]);
@@ -81,7 +81,7 @@ where
         let res = MapDB::open(cfg);
         match res {
             Ok(db) => Ok(Self::new(db)),
-            Err(e) => Err(MError(e).into()),
+            Err(e) => Err(Exception::new(&e.to_string())),
         }
     }
 
@@ -0,0 +1,67 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Typed calls of the node rpc methods, named after the server methods of `map-rpc`.
+use serde_json::json;
+
+use map_core::block::{Block, Header};
+use map_core::transaction_json::TransactionJson;
+use map_core::types::Hash;
+
+use crate::types::{SyncProgressJson, TxAdmissionJson, ValidatorsJson};
+use crate::RpcClient;
+
+impl RpcClient {
+    /// `map_getHeaderByNumber`
+    pub fn get_header_by_number(&self, num: u64) -> Result<Option<Header>, String> {
+        self.call("map_getHeaderByNumber", json!([num]))
+    }
+
+    /// `map_getBlock`
+    pub fn get_block(&self, hash: Hash) -> Result<Option<Block>, String> {
+        self.call("map_getBlock", json!([hash]))
+    }
+
+    /// `map_getBlockByNumber`
+    pub fn get_block_by_number(&self, num: u64) -> Result<Option<Block>, String> {
+        self.call("map_getBlockByNumber", json!([num]))
+    }
+
+    /// `map_getValidators`
+    pub fn get_validators(&self, num: u64) -> Result<Option<ValidatorsJson>, String> {
+        self.call("map_getValidators", json!([num]))
+    }
+
+    /// `map_getStateNodes`, the hex nodes decoded
+    pub fn get_state_nodes(&self, hashes: &[Hash]) -> Result<Vec<Option<Vec<u8>>>, String> {
+        let nodes: Vec<Option<String>> = self.call("map_getStateNodes", json!([hashes]))?;
+        nodes.into_iter()
+            .map(|node| node.map(|n| hex::decode(n.trim_start_matches("0x"))).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("map_getStateNodes: invalid state node {}", e))
+    }
+
+    /// `map_sendRawTransaction`
+    pub fn send_raw_transaction(&self, tx: &TransactionJson) -> Result<TxAdmissionJson, String> {
+        self.call("map_sendRawTransaction", json!([tx]))
+    }
+
+    /// `map_syncing`, `None` when the node is not syncing
+    pub fn syncing(&self) -> Result<Option<SyncProgressJson>, String> {
+        self.call("map_syncing", json!([]))
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Minimal blocking json rpc client over plain http, for talking to trusted services such as
+//! another node or a remote signer, with typed calls of the node rpc methods.
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+pub mod api;
+pub mod types;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Json rpc client, one connection per request.
+pub struct RpcClient {
+    host: String,
+    port: u16,
+    path: String,
+    next_id: AtomicU64,
+    /// Limit of connecting and of each read and write
+    timeout: Duration,
+}
+
+impl RpcClient {
+    /// Client of `url`, which must be `http://host[:port][/path]`
+    pub fn new(url: &str) -> Result<Self, String> {
+        let rest = url.trim().strip_prefix("http://")
+            .ok_or_else(|| format!("unsupported rpc url {}, expected http://host:port", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) => {
+                let port = authority[i + 1..].parse::<u16>()
+                    .map_err(|_| format!("invalid port in rpc url {}", url))?;
+                (&authority[..i], port)
+            }
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in rpc url {}", url));
+        }
+        Ok(RpcClient {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            next_id: AtomicU64::new(1),
+            timeout: RPC_TIMEOUT,
+        })
+    }
+
+    /// Give up connecting, reading or writing after `timeout` instead of the default 30s
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Body of a `GET` of `path` on the host
+    pub fn get(&self, path: &str) -> Result<Vec<u8>, String> {
+        self.request("GET", path, &[])
+    }
+
+    /// Body of the response to a `POST` of the json `body` to the url
+    pub fn post(&self, body: &[u8]) -> Result<Vec<u8>, String> {
+        self.request("POST", &self.path, body)
+    }
+
+    /// Result of calling `method` with positional `params`
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let raw = self.request("POST", &self.path, body.to_string().as_bytes())?;
+        let mut response: Value = serde_json::from_slice(&raw)
+            .map_err(|e| format!("{}: invalid response {}", method, e))?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("{}: {}", method, error));
+        }
+        serde_json::from_value(response["result"].take())
+            .map_err(|e| format!("{}: invalid result {}", method, e))
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+        let connect_err = |e: std::io::Error| format!("rpc {}:{} unreachable: {}", self.host, self.port, e);
+        let addr = (self.host.as_str(), self.port).to_socket_addrs().map_err(connect_err)?.next()
+            .ok_or_else(|| format!("rpc {}:{} unreachable: no address", self.host, self.port))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(connect_err)?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(connect_err)?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(connect_err)?;
+
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method, path, self.host, self.port, body.len());
+        stream.write_all(head.as_bytes()).map_err(connect_err)?;
+        stream.write_all(body).map_err(connect_err)?;
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).map_err(connect_err)?;
+        parse_response(&raw)
+    }
+}
+
+/// Body of a raw http response, which must have a 2xx status
+fn parse_response(raw: &[u8]) -> Result<Vec<u8>, String> {
+    let end = find(raw, b"\r\n\r\n").ok_or("truncated http response")?;
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let body = &raw[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or("");
+    if !status.split_whitespace().nth(1).map_or(false, |code| code.len() == 3 && code.starts_with('2')) {
+        return Err(format!("http error {}", status));
+    }
+    let chunked = lines.any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+    if chunked {
+        decode_chunked(body)
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let end = find(body, b"\r\n").ok_or("truncated http chunk")?;
+        let size = String::from_utf8_lossy(&body[..end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| format!("invalid http chunk size {}", size))?;
+        body = &body[end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if body.len() < size + 2 {
+            return Err("truncated http chunk".to_string());
+        }
+        out.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_url() {
+        let client = RpcClient::new("http://127.0.0.1:9545").unwrap();
+        assert_eq!((client.host.as_str(), client.port, client.path.as_str()), ("127.0.0.1", 9545, "/"));
+        let client = RpcClient::new("http://node.example/rpc").unwrap();
+        assert_eq!((client.host.as_str(), client.port, client.path.as_str()), ("node.example", 80, "/rpc"));
+        assert!(RpcClient::new("https://node.example").is_err());
+        assert!(RpcClient::new("http://:9545").is_err());
+    }
+
+    #[test]
+    fn http_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(parse_response(raw).unwrap(), b"{}".to_vec());
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\n4\r\n\":1}\r\n0\r\n\r\n";
+        assert_eq!(parse_response(raw).unwrap(), b"{\"a\":1}".to_vec());
+        assert!(parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+        assert_eq!(parse_response(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap(), Vec::<u8>::new());
+    }
+}
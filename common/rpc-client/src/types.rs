@@ -0,0 +1,66 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Client side forms of the results of the node rpc methods. Each mirrors a server type of
+//! `map-rpc`, whose tests check the server output still parses into it.
+use serde::Deserialize;
+
+use map_core::staking::Validator;
+use map_core::types::Hash;
+
+/// Result of `map_getValidators`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ValidatorsJson {
+    /// Block height the list was read at.
+    pub height: u64,
+    /// State root the list was read from.
+    pub state_root: Hash,
+    /// Sum of effective balance of all validators.
+    pub total_stake: u128,
+    /// Number of validators active at `height`.
+    pub active_count: u64,
+    /// Validators in list order.
+    pub validators: Vec<Validator>,
+}
+
+/// Result of `map_sendTransaction` and `map_sendRawTransaction`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TxAdmissionJson {
+    pub hash: Hash,
+    /// `pending` if executable, `queued` if waiting for a free pool slot.
+    pub status: String,
+    /// Transactions of the same status paying a higher gas price.
+    pub position: usize,
+    /// Transaction evicted to make room for this one.
+    pub replaced: Option<Hash>,
+}
+
+/// Result of `map_syncing` while the node is syncing.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SyncProgressJson {
+    pub syncing: bool,
+    /// Local head height when the current sync started.
+    pub starting_height: u64,
+    /// Local head height.
+    pub current_height: u64,
+    /// Sync target, the median head height advertised by the connected peers.
+    pub highest_height: u64,
+    /// Smoothed number of blocks imported per second.
+    pub blocks_per_sec: f64,
+    /// Estimated seconds left to reach `highest_height`, if the rate is known.
+    pub eta_secs: Option<u64>,
+}
@@ -0,0 +1,268 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compression of stored values, chosen per column of keys sharing a prefix. Every value written
+//! through `CompressedKV` starts with a format byte naming its codec, so values written with
+//! another codec, or none, stay readable after the configuration changes.
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::thread;
+
+use crate::{BatchOp, KVBatch, KVDB};
+
+/// Key marking a database whose values all carry the format byte
+const FORMAT_KEY: &[u8] = b"\x00format";
+/// Version of the framing stored under `FORMAT_KEY`
+const FORMAT_VERSION: u8 = 1;
+/// Batches with more operations than this are compressed on several threads
+const PARALLEL_ENCODE_MIN: usize = 1024;
+/// Threads compressing a large batch
+const ENCODE_THREADS: usize = 4;
+
+/// Codec of a stored value, written as its format byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    None = 0,
+    Snappy = 1,
+    Lz4 = 2,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::None
+    }
+}
+
+impl Codec {
+    fn from_byte(b: u8) -> io::Result<Self> {
+        match b {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Snappy),
+            2 => Ok(Codec::Lz4),
+            _ => Err(invalid_data(format!("unknown value format {}", b))),
+        }
+    }
+
+    /// `value` prefixed by the format byte and compressed
+    pub fn encode(self, value: &[u8]) -> Vec<u8> {
+        let body = match self {
+            Codec::None => value.to_vec(),
+            Codec::Snappy => snap::raw::Encoder::new().compress_vec(value).expect("snappy compress"),
+            Codec::Lz4 => lz4::block::compress(value, None, true).expect("lz4 compress"),
+        };
+        let mut out = Vec::with_capacity(body.len() + 1);
+        out.push(self as u8);
+        out.extend_from_slice(&body);
+        out
+    }
+
+    /// Value of an `encode` output, whatever codec it was written with
+    pub fn decode(raw: &[u8]) -> io::Result<Vec<u8>> {
+        let (format, body) = raw.split_first().ok_or_else(|| invalid_data("empty value".to_string()))?;
+        match Codec::from_byte(*format)? {
+            Codec::None => Ok(body.to_vec()),
+            Codec::Snappy => snap::raw::Decoder::new().decompress_vec(body)
+                .map_err(|e| invalid_data(format!("snappy: {}", e))),
+            Codec::Lz4 => lz4::block::decompress(body, None),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "none" => Ok(Codec::None),
+            "snappy" => Ok(Codec::Snappy),
+            "lz4" => Ok(Codec::Lz4),
+            _ => Err(format!("unknown compression {}, expected none, snappy or lz4", s)),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Codec::None => "none",
+            Codec::Snappy => "snappy",
+            Codec::Lz4 => "lz4",
+        };
+        f.write_str(name)
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Whether the values of `db` carry the format byte. Databases written before the framing
+/// have no marker and must be read as they are.
+pub fn is_framed(db: &dyn KVDB) -> io::Result<bool> {
+    Ok(db.get(FORMAT_KEY)?.is_some())
+}
+
+/// Mark `db` as holding framed values, done once while it is still empty
+pub fn mark_framed(db: &mut dyn KVDB) -> io::Result<()> {
+    db.put(FORMAT_KEY, &[FORMAT_VERSION])
+}
+
+/// Key-value store writing values with the codec of their column, and reading any codec.
+pub struct CompressedKV {
+    inner: Box<dyn KVDB>,
+    /// Key prefix and codec of each column, the first match wins. Other keys are not compressed.
+    columns: Vec<(Vec<u8>, Codec)>,
+}
+
+impl CompressedKV {
+    pub fn new(inner: Box<dyn KVDB>, columns: Vec<(Vec<u8>, Codec)>) -> Self {
+        CompressedKV { inner, columns }
+    }
+
+    fn codec(&self, key: &[u8]) -> Codec {
+        column_codec(&self.columns, key)
+    }
+}
+
+fn column_codec(columns: &[(Vec<u8>, Codec)], key: &[u8]) -> Codec {
+    columns.iter()
+        .find(|(prefix, _)| key.starts_with(prefix))
+        .map(|(_, codec)| *codec)
+        .unwrap_or_default()
+}
+
+fn encode_ops(columns: &[(Vec<u8>, Codec)], ops: Vec<BatchOp>) -> Vec<BatchOp> {
+    ops.into_iter()
+        .map(|op| match op {
+            BatchOp::Put(key, value) => {
+                let raw = column_codec(columns, &key).encode(&value);
+                BatchOp::Put(key, raw)
+            }
+            BatchOp::Delete(key) => BatchOp::Delete(key),
+        })
+        .collect()
+}
+
+/// Compress the values of `ops` on `ENCODE_THREADS` threads, keeping the order of the operations
+fn encode_ops_parallel(columns: &[(Vec<u8>, Codec)], mut ops: Vec<BatchOp>) -> Vec<BatchOp> {
+    let chunk = (ops.len() + ENCODE_THREADS - 1) / ENCODE_THREADS;
+    let mut workers = Vec::with_capacity(ENCODE_THREADS);
+    while !ops.is_empty() {
+        let rest = ops.split_off(chunk.min(ops.len()));
+        let part = std::mem::replace(&mut ops, rest);
+        let columns = columns.to_vec();
+        workers.push(thread::spawn(move || encode_ops(&columns, part)));
+    }
+    workers.into_iter()
+        .flat_map(|worker| worker.join().expect("encode batch values"))
+        .collect()
+}
+
+impl KVDB for CompressedKV {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.inner.get(key)?.map(|raw| Codec::decode(&raw)).transpose()
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let raw = self.codec(key).encode(value);
+        self.inner.put(key, &raw)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> io::Result<()> {
+        self.inner.remove(key)
+    }
+
+    fn write(&mut self, batch: KVBatch, sync: bool) -> io::Result<()> {
+        let ops = batch.into_ops();
+        let framed = if ops.len() > PARALLEL_ENCODE_MIN {
+            encode_ops_parallel(&self.columns, ops)
+        } else {
+            encode_ops(&self.columns, ops)
+        };
+        self.inner.write(KVBatch::from_ops(framed), sync)
+    }
+
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        self.inner.keys()
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        self.inner.keys_with_prefix(prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryKV;
+
+    #[test]
+    fn codec_roundtrip() {
+        let value = vec![7u8; 1024];
+        for codec in &[Codec::None, Codec::Snappy, Codec::Lz4] {
+            let raw = codec.encode(&value);
+            assert_eq!(raw[0], *codec as u8);
+            assert_eq!(Codec::decode(&raw).unwrap(), value);
+        }
+        assert!(Codec::Snappy.encode(&value).len() < value.len());
+        assert!(Codec::decode(&[9, 1, 2]).is_err());
+        assert_eq!("lz4".parse::<Codec>(), Ok(Codec::Lz4));
+    }
+
+    #[test]
+    fn compress_per_column() {
+        let mut db = CompressedKV::new(Box::new(MemoryKV::new()), vec![(b"b".to_vec(), Codec::Snappy)]);
+        let value = vec![1u8; 512];
+        db.put(b"b1", &value).unwrap();
+        let mut batch = KVBatch::new();
+        batch.put(b"h1", &value);
+        db.write(batch, false).unwrap();
+        assert_eq!(db.get(b"b1").unwrap(), Some(value.clone()));
+        assert_eq!(db.get(b"h1").unwrap(), Some(value.clone()));
+        assert_eq!(db.inner.get(b"h1").unwrap().unwrap()[0], Codec::None as u8);
+        assert!(db.inner.get(b"b1").unwrap().unwrap().len() < value.len());
+
+        // Values written earlier stay readable with another codec
+        db.columns = vec![(b"b".to_vec(), Codec::Lz4)];
+        assert_eq!(db.get(b"b1").unwrap(), Some(value));
+    }
+
+    #[test]
+    fn compress_large_batch() {
+        let mut db = CompressedKV::new(Box::new(MemoryKV::new()), vec![(vec![], Codec::Snappy)]);
+        let mut batch = KVBatch::new();
+        let last = PARALLEL_ENCODE_MIN as u32 * 2;
+        for i in 0..=last {
+            batch.put(&i.to_be_bytes(), &vec![i as u8; 64]);
+        }
+        // Operations keep their order across threads
+        batch.delete(&7u32.to_be_bytes());
+        db.write(batch, false).unwrap();
+        assert_eq!(db.get(&7u32.to_be_bytes()).unwrap(), None);
+        assert_eq!(db.get(&(last - 1).to_be_bytes()).unwrap(), Some(vec![(last - 1) as u8; 64]));
+        assert_eq!(db.get(&last.to_be_bytes()).unwrap(), Some(vec![last as u8; 64]));
+        assert_eq!(db.get(&(last + 1).to_be_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn format_marker() {
+        let mut db = MemoryKV::new();
+        assert!(!is_framed(&db).unwrap());
+        mark_framed(&mut db).unwrap();
+        assert!(is_framed(&db).unwrap());
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage engines a database can be opened with. Both implement `KVDB`, so the layers above
+//! don't depend on the engine, and a datadir keeps the engine it was created with.
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{Config, KVDB, OpenError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbEngine {
+    RocksDb,
+    /// Pure Rust engine, for targets where RocksDB is hard to build
+    Sled,
+}
+
+impl DbEngine {
+    pub const ALL: [DbEngine; 2] = [DbEngine::RocksDb, DbEngine::Sled];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DbEngine::RocksDb => "rocksdb",
+            DbEngine::Sled => "sled",
+        }
+    }
+
+    /// Whether the engine was compiled in by its cargo feature
+    pub fn is_compiled(self) -> bool {
+        match self {
+            DbEngine::RocksDb => cfg!(feature = "rocksdb"),
+            DbEngine::Sled => cfg!(feature = "sled"),
+        }
+    }
+
+    /// Engine of the database at `path` from the files it keeps there, None for a new one
+    pub fn detect(path: &Path) -> Option<DbEngine> {
+        if path.join("CURRENT").exists() {
+            Some(DbEngine::RocksDb)
+        } else if path.join("conf").exists() {
+            Some(DbEngine::Sled)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for DbEngine {
+    /// RocksDB unless the build left it out
+    fn default() -> Self {
+        if cfg!(feature = "rocksdb") {
+            DbEngine::RocksDb
+        } else {
+            DbEngine::Sled
+        }
+    }
+}
+
+impl fmt::Display for DbEngine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DbEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DbEngine::ALL.iter().cloned().find(|engine| engine.name() == s)
+            .ok_or_else(|| format!("unknown storage engine {}", s))
+    }
+}
+
+/// Open the database of `cfg` with the engine that wrote it, or the configured one for a new
+/// database. Asking for another engine than the one on disk fails rather than leaving the
+/// existing data unread.
+pub fn open(cfg: Config) -> Result<Box<dyn KVDB>, OpenError> {
+    let engine = match (DbEngine::detect(&cfg.path), cfg.engine) {
+        (Some(found), Some(wanted)) if found != wanted => {
+            return Err(OpenError::db(&cfg.path, format!("database written by {}, not {}", found, wanted)));
+        }
+        (Some(found), _) => found,
+        (None, wanted) => wanted.unwrap_or_default(),
+    };
+    if !engine.is_compiled() {
+        return Err(OpenError::db(&cfg.path, format!("storage engine {} is not compiled in", engine)));
+    }
+    open_engine(engine, cfg)
+}
+
+#[allow(unreachable_patterns)]
+fn open_engine(engine: DbEngine, cfg: Config) -> Result<Box<dyn KVDB>, OpenError> {
+    match engine {
+        #[cfg(feature = "rocksdb")]
+        DbEngine::RocksDb => Ok(Box::new(crate::mapdb::MapDB::open(cfg)?)),
+        #[cfg(feature = "sled")]
+        DbEngine::Sled => Ok(Box::new(crate::sleddb::SledDB::open(cfg)?)),
+        _ => Err(OpenError::db(&cfg.path, format!("storage engine {} is not compiled in", engine))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn engine_of_datadir() {
+        assert_eq!("sled".parse::<DbEngine>(), Ok(DbEngine::Sled));
+        assert!("leveldb".parse::<DbEngine>().is_err());
+
+        let dir = std::env::temp_dir().join("map_store_engine_test");
+        let _ = fs::remove_dir_all(&dir);
+        let cfg = Config::new(dir.clone()).with_engine(Some(DbEngine::default()));
+        assert_eq!(DbEngine::detect(&cfg.path), None);
+        let mut db = open(cfg.clone()).unwrap();
+        db.put(b"k1", b"v1").unwrap();
+        drop(db);
+        assert_eq!(DbEngine::detect(&cfg.path), Some(DbEngine::default()));
+
+        // Reopened with the engine on disk whatever the default, refused for another one
+        assert_eq!(open(cfg.clone().with_engine(None)).unwrap().get(b"k1").unwrap(), Some(b"v1".to_vec()));
+        let other = DbEngine::ALL.iter().cloned().find(|e| *e != DbEngine::default()).unwrap();
+        let err = open(cfg.with_engine(Some(other))).err().unwrap();
+        assert!(err.to_string().contains("written by"));
+    }
+}
@@ -14,23 +14,219 @@
 // You should have received a copy of the GNU General Public License
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
+#[cfg(feature = "rocksdb")]
 extern crate rocksdb;
+#[cfg(feature = "rocksdb")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "rocksdb")]
+#[macro_use]
+extern crate lazy_static;
+pub mod compress;
+pub mod engine;
+pub mod lock;
+#[cfg(feature = "rocksdb")]
 pub mod mapdb;
+#[cfg(feature = "sled")]
+pub mod sleddb;
+#[cfg(feature = "rocksdb")]
+pub mod stats;
+#[cfg(feature = "rocksdb")]
 pub type Error = rocksdb::Error;
+#[cfg(feature = "rocksdb")]
 pub type WriteBatch = rocksdb::WriteBatch;
 
-use std::path::PathBuf;
+pub use engine::{open, DbEngine};
+
+use std::path::{Path, PathBuf};
 use std::env;
+use std::fmt;
 use std::io;
 use std::sync::RwLock;
 use std::collections::HashMap;
 
+use failure::Fail;
+
+/// Failure to open the databases in a data directory.
+#[derive(Debug)]
+pub enum OpenError {
+    /// The directory is locked by another running process.
+    Locked { path: PathBuf, pid: Option<u32> },
+    Io { path: PathBuf, err: io::Error },
+    Db { path: PathBuf, err: String },
+}
+
+impl OpenError {
+    pub fn io(path: &Path, err: io::Error) -> Self {
+        OpenError::Io { path: path.to_path_buf(), err }
+    }
+
+    pub fn db<E: fmt::Display>(path: &Path, err: E) -> Self {
+        OpenError::Db { path: path.to_path_buf(), err: err.to_string() }
+    }
+
+    /// Suggested fix for the failure, if it has a known cause.
+    pub fn hint(&self) -> Option<&'static str> {
+        let reason = match self {
+            OpenError::Locked { .. } => return Some("stop the other process or choose another data directory"),
+            OpenError::Io { err, .. } if err.kind() == io::ErrorKind::PermissionDenied => {
+                return Some("check the data directory is writable by the current user");
+            }
+            OpenError::Io { err, .. } => err.to_string(),
+            OpenError::Db { err, .. } => err.clone(),
+        };
+        if reason.contains("Permission denied") {
+            Some("check the data directory is writable by the current user")
+        } else if reason.contains("no read-only mode") {
+            Some("stop the node first, a sled data directory can not be read while it runs")
+        } else if reason.contains("lock") {
+            Some("another process may be using the data directory")
+        } else if reason.contains("written by") {
+            Some("open it without --db-engine or sync into an empty data directory")
+        } else if reason.contains("not compiled in") {
+            Some("rebuild with the storage engine feature, e.g. --features sled")
+        } else if reason.contains("Corruption") {
+            Some("the database is corrupted, restore a backup or sync into an empty data directory")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenError::Locked { path, pid: Some(pid) } => {
+                write!(f, "{} is in use by process {}", path.display(), pid)?
+            }
+            OpenError::Locked { path, pid: None } => write!(f, "{} is in use by another process", path.display())?,
+            OpenError::Io { path, err } => write!(f, "can not access {}: {}", path.display(), err)?,
+            OpenError::Db { path, err } => write!(f, "can not open database {}: {}", path.display(), err)?,
+        }
+        match self.hint() {
+            Some(hint) => write!(f, ", {}", hint),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+impl From<OpenError> for errors::Error {
+    fn from(error: OpenError) -> Self {
+        error.context(errors::ErrorKind::Storage).into()
+    }
+}
+
+/// A single write operation in a `KVBatch`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Backend independent list of writes, applied at once by `KVDB::write`.
+#[derive(Clone, Debug, Default)]
+pub struct KVBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl KVBatch {
+    pub fn new() -> Self {
+        KVBatch {
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        KVBatch {
+            ops: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Batch of operations already built, applied in order
+    pub fn from_ops(ops: Vec<BatchOp>) -> Self {
+        KVBatch { ops }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.push(BatchOp::Put(key.into(), value.into()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.ops.push(BatchOp::Delete(key.into()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+}
+
 pub trait KVDB: Sync + Send {
     fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
 
     fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()>;
 
     fn remove(&mut self, key: &[u8]) -> io::Result<()>;
+
+    /// Apply all operations of the batch. When `sync` is set the write is flushed to disk
+    /// before returning, for backends that support it.
+    fn write(&mut self, batch: KVBatch, _sync: bool) -> io::Result<()> {
+        for op in batch.into_ops() {
+            match op {
+                BatchOp::Put(key, value) => self.put(&key, &value)?,
+                BatchOp::Delete(key) => self.remove(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Every key in the store, for backends able to list them
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        Err(io::Error::new(io::ErrorKind::Other, "listing keys is not supported"))
+    }
+
+    /// Keys starting with `prefix` in ascending order, for backends able to list them. Sorted
+    /// backends seek to the prefix instead of reading every key.
+    fn keys_with_prefix(&self, prefix: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut keys: Vec<Vec<u8>> = self.keys()?.into_iter().filter(|key| key.starts_with(prefix)).collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+impl<T: KVDB + ?Sized> KVDB for Box<T> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        (**self).put(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> io::Result<()> {
+        (**self).remove(key)
+    }
+
+    fn write(&mut self, batch: KVBatch, sync: bool) -> io::Result<()> {
+        (**self).write(batch, sync)
+    }
+
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        (**self).keys()
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        (**self).keys_with_prefix(prefix)
+    }
 }
 
 #[derive(Default)]
@@ -67,11 +263,22 @@ impl KVDB for MemoryKV {
         db.remove(key);
         Ok(())
     }
+
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        Ok(self.db.read().unwrap().keys().cloned().collect())
+    }
 }
 
 #[derive(Clone,Debug)]
 pub struct Config {
     pub path: PathBuf,
+    /// Open the database without write access
+    pub read_only: bool,
+    /// Label of the database metrics
+    pub name: &'static str,
+    /// Engine of a new database, None for the default. An existing one is opened with the
+    /// engine that wrote it.
+    pub engine: Option<DbEngine>,
 }
 
 impl Default for Config {
@@ -80,6 +287,9 @@ impl Default for Config {
         cur.push("mapdata");
         Config{
             path:   cur,
+            read_only: false,
+            name: "db",
+            engine: None,
         }
     }
 }
@@ -89,13 +299,33 @@ impl Config {
         dir.push("mapdata");
         Config {
             path: dir,
+            read_only: false,
+            name: "db",
+            engine: None,
         }
     }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn with_engine(mut self, engine: Option<DbEngine>) -> Self {
+        self.engine = engine;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{MemoryKV, KVDB};
+    use super::{KVBatch, MemoryKV, KVDB, OpenError};
+    use std::io;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn test_memdb() {
@@ -110,4 +340,41 @@ mod tests {
         db.remove(b"key1").unwrap();
         assert_eq!(db.get(b"key1").unwrap(), None);
     }
+
+    #[test]
+    fn test_memdb_batch() {
+        let mut db = MemoryKV::new();
+        db.put(b"key1", b"a").unwrap();
+
+        let mut batch = KVBatch::new();
+        batch.put(b"key2", b"b");
+        batch.delete(b"key1");
+        assert_eq!(batch.len(), 2);
+
+        db.write(batch, false).unwrap();
+        assert_eq!(db.get(b"key1").unwrap(), None);
+        assert_eq!(db.get(b"key2").unwrap().unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_memdb_prefix() {
+        let mut db = MemoryKV::new();
+        for key in &[&b"q2"[..], b"a1", b"q1", b"r1"] {
+            db.put(key, b"v").unwrap();
+        }
+        assert_eq!(db.keys_with_prefix(b"q").unwrap(), vec![b"q1".to_vec(), b"q2".to_vec()]);
+        assert!(db.keys_with_prefix(b"z").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_open_error_hint() {
+        let err = OpenError::Locked { path: PathBuf::from("/data"), pid: Some(42) };
+        assert_eq!(err.to_string(), "/data is in use by process 42, stop the other process or choose another data directory");
+
+        let err = OpenError::io(Path::new("/data"), io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(err.hint(), Some("check the data directory is writable by the current user"));
+
+        let err = OpenError::io(Path::new("/data"), io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(err.hint(), None);
+    }
 }
@@ -0,0 +1,110 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Datadir lock file preventing two node processes from opening the same databases.
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use fs2::FileExt;
+
+use crate::OpenError;
+
+const LOCK_FILENAME: &str = "map.lock";
+
+/// Exclusive use of a datadir, held with an advisory lock on the lock file for the lifetime of
+/// the process. The system releases it when the process exits, however it exits.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl DirLock {
+    /// Locks the lock file in `dir` and writes the current process id into it, which is only
+    /// used to name the holder when the lock is taken.
+    pub fn acquire(dir: &Path) -> Result<Self, OpenError> {
+        fs::create_dir_all(dir).map_err(|e| OpenError::io(dir, e))?;
+        let path = dir.join(LOCK_FILENAME);
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)
+            .map_err(|e| OpenError::io(&path, e))?;
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() == fs2::lock_contended_error().kind() {
+                return Err(OpenError::Locked { path: dir.to_path_buf(), pid: holder_pid(&path) });
+            }
+            return Err(OpenError::io(&path, e));
+        }
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| write!(file, "{}", process::id()))
+            .and_then(|_| file.flush())
+            .map_err(|e| OpenError::io(&path, e))?;
+        Ok(DirLock { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// The file is left in place, removing it would let another process lock a new file while a
+// third one still holds the old one
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn holder_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("map_dir_lock_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_lock_held() {
+        let dir = test_dir("held");
+        let lock = DirLock::acquire(&dir).unwrap();
+        match DirLock::acquire(&dir) {
+            Err(OpenError::Locked { pid, .. }) => assert_eq!(pid, Some(process::id())),
+            other => panic!("expect locked, got {:?}", other),
+        }
+
+        drop(lock);
+        assert!(DirLock::acquire(&dir).is_ok());
+    }
+
+    #[test]
+    fn test_lock_stale() {
+        let dir = test_dir("stale");
+        fs::create_dir_all(&dir).unwrap();
+        // Left by a process which exited, the pid may belong to a running process by now
+        fs::write(dir.join(LOCK_FILENAME), "1").unwrap();
+        let lock = DirLock::acquire(&dir).unwrap();
+        assert_eq!(holder_pid(lock.path()), Some(process::id()));
+    }
+}
@@ -14,68 +14,131 @@
 // You should have received a copy of the GNU General Public License
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::{Arc, RwLock};
+use std::sync::{mpsc, Arc};
 use std::io;
-use rocksdb::{DB,WriteBatch};
-use crate::{Config, KVDB};
+use std::thread;
+use std::time::Instant;
+use rocksdb::{DB, Direction, IteratorMode, Options, WriteBatch, WriteOptions};
+use crate::{BatchOp, Config, KVBatch, KVDB, OpenError};
+use crate::stats::{self, DbStats, WriteMonitor};
 use super::Error;
 
+// Writes go through `&mut self`, the handle itself is shared with the statistics thread only
 pub struct MapDB{
-    inner:     Arc<RwLock<DB>>,
+    inner:     Arc<DB>,
+    monitor:   WriteMonitor,
+    stats:     Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
 }
 
 impl MapDB {
-    pub fn open(cfg: Config) -> Result<Self, Error> {
-        let db = DB::open_default(&cfg.path).unwrap();
+    pub fn open(cfg: Config) -> Result<Self, OpenError> {
+        let db = if cfg.read_only {
+            DB::open_for_read_only(&Options::default(), &cfg.path, false)
+        } else {
+            DB::open_default(&cfg.path)
+        };
+        let db = Arc::new(db.map_err(|e| OpenError::db(&cfg.path, e))?);
         Ok(MapDB{
-            inner:     Arc::new(RwLock::new(db)),
+            stats:     Some(stats::start(cfg.name, db.clone())),
+            inner:     db,
+            monitor:   WriteMonitor::new(cfg.name),
         })
     }
 
+    /// Internal statistics of the database: compaction, write stalls and level sizes.
+    pub fn stats(&self) -> DbStats {
+        DbStats::read(&self.inner)
+    }
+
     pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(),Error> {
-        let db = self.inner.write().unwrap();
+        let db = &self.inner;
         db.put(key, value)
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let db = self.inner.read().unwrap();
+        let db = &self.inner;
         db.get(key).unwrap()
     }
 
     pub fn remove(&mut self, key: &[u8]) -> Result<(),Error> {
-        let db = self.inner.write().unwrap();
+        let db = &self.inner;
         db.delete(key)
     }
 
     pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
-        let db = self.inner.read().unwrap();
+        let db = &self.inner;
         db.get(key)
             .map_err(Into::into)
             .and_then(|val| Ok(val.is_some()))
     }
     pub fn write_batch(&mut self,wb :WriteBatch) -> Result<(),Error> {
-        let db = self.inner.write().unwrap();
+        let db = &self.inner;
         db.write(wb)
     }
 }
 
+impl Drop for MapDB {
+    // The database closes once the statistics thread is done with it
+    fn drop(&mut self) {
+        if let Some((signal, handle)) = self.stats.take() {
+            let _ = signal.send(());
+            let _ = handle.join();
+        }
+    }
+}
+
 impl KVDB for MapDB {
     fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
-        let db = self.inner.write().unwrap();
+        let db = &self.inner;
+        let start = Instant::now();
         db.put(key, value).expect("db write exception");
+        self.monitor.on_write(&db, start.elapsed());
         Ok(())
     }
 
     fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
-        let db = self.inner.read().unwrap();
+        let db = &self.inner;
         Ok(db.get(key).unwrap())
     }
 
     fn remove(&mut self, key: &[u8]) -> io::Result<()> {
-        let db = self.inner.write().unwrap();
+        let db = &self.inner;
         db.delete(key).expect("db remove exception");
         Ok(())
     }
+
+    fn write(&mut self, batch: KVBatch, sync: bool) -> io::Result<()> {
+        let mut wb = WriteBatch::default();
+        for op in batch.into_ops() {
+            let res = match op {
+                BatchOp::Put(key, value) => wb.put(key, value),
+                BatchOp::Delete(key) => wb.delete(key),
+            };
+            res.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+
+        let mut opts = WriteOptions::default();
+        opts.set_sync(sync);
+        let db = &self.inner;
+        let start = Instant::now();
+        let res = db.write_opt(wb, &opts).map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        self.monitor.on_write(&db, start.elapsed());
+        res
+    }
+
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        let db = &self.inner;
+        Ok(db.iterator(IteratorMode::Start).map(|(key, _)| key.into_vec()).collect())
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let db = &self.inner;
+        Ok(db.iterator(IteratorMode::From(prefix, Direction::Forward))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .map(|key| key.into_vec())
+            .collect())
+    }
 }
 
 
@@ -93,3 +156,19 @@ fn test_set_value() {
     assert!(m.get(b"k1").is_none());
     assert!(!m.exists(b"k1").unwrap());
 }
+
+#[test]
+fn test_stats() {
+    let dir = std::env::temp_dir().join("map_store_test_stats");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut m = MapDB::open(Config::new(dir)).unwrap();
+    KVDB::put(&mut m, b"k1", b"v1").unwrap();
+
+    let stats = m.stats();
+    assert!(!stats.write_stopped);
+    assert!(!stats.level_sizes.is_empty());
+
+    // Closed on drop, along with the statistics thread
+    drop(m);
+    assert!(MapDB::open(Config::new(std::env::temp_dir().join("map_store_test_stats"))).is_ok());
+}
@@ -0,0 +1,124 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pure Rust storage engine on sled, selected with `--db-engine sled` on builds with the `sled`
+//! feature.
+use std::io;
+
+use crate::{BatchOp, Config, KVBatch, KVDB, OpenError};
+
+pub struct SledDB {
+    db: sled::Db,
+    /// sled has no read-only mode, writes are refused instead
+    read_only: bool,
+}
+
+impl SledDB {
+    /// sled locks its files and has no secondary mode, a read-only open fails while another process
+    /// has the database open
+    pub fn open(cfg: Config) -> Result<Self, OpenError> {
+        let db = sled::open(&cfg.path).map_err(|e| match cfg.read_only {
+            true => OpenError::db(&cfg.path, format!("{}, sled has no read-only mode", e)),
+            false => OpenError::db(&cfg.path, e),
+        })?;
+        Ok(SledDB { db, read_only: cfg.read_only })
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "database opened read-only"));
+        }
+        Ok(())
+    }
+}
+
+fn io_error(e: sled::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl KVDB for SledDB {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key).map_err(io_error)?.map(|value| value.to_vec()))
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.check_writable()?;
+        self.db.insert(key, value).map_err(io_error)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> io::Result<()> {
+        self.check_writable()?;
+        self.db.remove(key).map_err(io_error)?;
+        Ok(())
+    }
+
+    /// Applied atomically like a RocksDB write batch, flushed to disk when `sync` is set
+    fn write(&mut self, batch: KVBatch, sync: bool) -> io::Result<()> {
+        self.check_writable()?;
+        let mut sled_batch = sled::Batch::default();
+        for op in batch.into_ops() {
+            match op {
+                BatchOp::Put(key, value) => sled_batch.insert(key, value),
+                BatchOp::Delete(key) => sled_batch.remove(key),
+            }
+        }
+        self.db.apply_batch(sled_batch).map_err(io_error)?;
+        if sync {
+            self.db.flush().map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        self.db.iter().keys().map(|key| key.map(|k| k.to_vec()).map_err(io_error)).collect()
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        self.db.scan_prefix(prefix).keys().map(|key| key.map(|k| k.to_vec()).map_err(io_error)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_and_keys() {
+        let dir = std::env::temp_dir().join("map_store_sled_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut db = SledDB::open(Config::new(dir.clone())).unwrap();
+        db.put(b"k1", b"v1").unwrap();
+
+        let mut batch = KVBatch::new();
+        batch.put(b"k2", b"v2");
+        batch.delete(b"k1");
+        db.write(batch, true).unwrap();
+        assert_eq!(db.get(b"k1").unwrap(), None);
+        assert_eq!(db.keys().unwrap(), vec![b"k2".to_vec()]);
+        assert_eq!(db.keys_with_prefix(b"k").unwrap(), vec![b"k2".to_vec()]);
+        drop(db);
+
+        let mut db = SledDB::open(Config::new(dir.clone()).read_only()).unwrap();
+        assert_eq!(db.get(b"k2").unwrap(), Some(b"v2".to_vec()));
+        assert!(db.put(b"k3", b"v3").is_err());
+        drop(db);
+
+        let _running = SledDB::open(Config::new(dir.clone())).unwrap();
+        let err = SledDB::open(Config::new(dir).read_only()).err().unwrap();
+        assert_eq!(err.hint(), Some("stop the node first, a sled data directory can not be read while it runs"));
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! RocksDB internal statistics exported as metrics, and warnings on writes held by the
+//! database, so that import pauses caused by compaction stand apart from network ones.
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use map_metrics::*;
+use rocksdb::DB;
+
+/// Writes taking longer are logged along with the compaction state of the database.
+pub const WRITE_STALL_WARN: Duration = Duration::from_millis(500);
+/// Time between two refreshes of the statistics metrics.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    pub static ref DB_WRITE_SECONDS: Result<HistogramVec> = try_create_histogram_vec(
+        "db_write_seconds",
+        "Time to apply a write or a write batch to the database",
+        &["db"]
+    );
+    pub static ref DB_WRITE_STALL_SECONDS: Result<GaugeVec> = try_create_float_gauge_vec(
+        "db_write_stall_seconds",
+        "Time writes were stalled or delayed by the database since it was opened",
+        &["db"]
+    );
+    pub static ref DB_COMPACTION_PENDING: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "db_compaction_pending",
+        "1 while at least one compaction is pending",
+        &["db"]
+    );
+    pub static ref DB_PENDING_COMPACTION_BYTES: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "db_pending_compaction_bytes",
+        "Estimated bytes compaction needs to rewrite to bring the levels under their target size",
+        &["db"]
+    );
+    pub static ref DB_WRITE_STOPPED: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "db_write_stopped",
+        "1 while writes are stopped until compaction catches up",
+        &["db"]
+    );
+    pub static ref DB_DELAYED_WRITE_RATE: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "db_delayed_write_rate",
+        "Bytes per second writes are slowed down to, 0 when they are not delayed",
+        &["db"]
+    );
+    pub static ref DB_LEVEL_SIZE_BYTES: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "db_level_size_bytes",
+        "Size of the sst files of each level",
+        &["db", "level"]
+    );
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DbStats {
+    pub compaction_pending: bool,
+    pub pending_compaction_bytes: u64,
+    pub write_stopped: bool,
+    /// Bytes per second writes are slowed down to, 0 when they are not delayed.
+    pub delayed_write_rate: u64,
+    /// Time writes were stalled since the database was opened.
+    pub stall_time: Duration,
+    /// Size of the sst files by level, lowest level first.
+    pub level_sizes: Vec<u64>,
+}
+
+impl DbStats {
+    pub fn read(db: &DB) -> Self {
+        let text = |name: &str| db.property_value(name).ok().and_then(|v| v).unwrap_or_default();
+        DbStats {
+            stall_time: parse_stall_time(&text("rocksdb.stats")).unwrap_or_default(),
+            level_sizes: parse_level_sizes(&text("rocksdb.levelstats")),
+            ..DbStats::read_write_state(db)
+        }
+    }
+
+    /// Only the cheap properties telling whether writes are held, without the reports.
+    fn read_write_state(db: &DB) -> Self {
+        let int = |name: &str| db.property_int_value(name).ok().and_then(|v| v).unwrap_or(0);
+        DbStats {
+            compaction_pending: int("rocksdb.compaction-pending") > 0,
+            pending_compaction_bytes: int("rocksdb.estimate-pending-compaction-bytes"),
+            write_stopped: int("rocksdb.is-write-stopped") > 0,
+            delayed_write_rate: int("rocksdb.actual-delayed-write-rate"),
+            ..DbStats::default()
+        }
+    }
+
+    /// Set the metrics of the database labelled `name`.
+    pub fn export(&self, name: &str) {
+        if let Some(gauge) = get_int_gauge(&DB_COMPACTION_PENDING, &[name]) {
+            gauge.set(self.compaction_pending as i64);
+        }
+        if let Some(gauge) = get_int_gauge(&DB_PENDING_COMPACTION_BYTES, &[name]) {
+            gauge.set(self.pending_compaction_bytes as i64);
+        }
+        if let Some(gauge) = get_int_gauge(&DB_WRITE_STOPPED, &[name]) {
+            gauge.set(self.write_stopped as i64);
+        }
+        if let Some(gauge) = get_int_gauge(&DB_DELAYED_WRITE_RATE, &[name]) {
+            gauge.set(self.delayed_write_rate as i64);
+        }
+        if let Ok(gauges) = &*DB_WRITE_STALL_SECONDS {
+            if let Ok(gauge) = gauges.get_metric_with_label_values(&[name]) {
+                gauge.set(self.stall_time.as_secs_f64());
+            }
+        }
+        for (level, size) in self.level_sizes.iter().enumerate() {
+            if let Some(gauge) = get_int_gauge(&DB_LEVEL_SIZE_BYTES, &[name, &level.to_string()]) {
+                gauge.set(*size as i64);
+            }
+        }
+    }
+}
+
+/// Times the writes of a database.
+pub struct WriteMonitor {
+    name: &'static str,
+}
+
+impl WriteMonitor {
+    pub fn new(name: &'static str) -> Self {
+        WriteMonitor { name }
+    }
+
+    /// Record a write which took `elapsed`. A write over `WRITE_STALL_WARN` is logged with
+    /// the state of the database, telling a stall from a slow disk.
+    pub fn on_write(&self, db: &DB, elapsed: Duration) {
+        if let Some(histogram) = get_histogram(&DB_WRITE_SECONDS, &[self.name]) {
+            histogram.observe(elapsed.as_secs_f64());
+        }
+        if elapsed >= WRITE_STALL_WARN {
+            let stats = DbStats::read_write_state(db);
+            warn!("slow {} db write {}ms, stopped={} delayed_rate={} compaction_pending={} pending_bytes={}",
+                self.name, elapsed.as_millis(), stats.write_stopped, stats.delayed_write_rate,
+                stats.compaction_pending, stats.pending_compaction_bytes);
+        }
+    }
+}
+
+/// Export the statistics of the database labelled `name` every `STATS_INTERVAL` from a thread
+/// of its own, so that a stall shows while writes are held. The thread exits when the returned
+/// sender is signalled or dropped.
+pub fn start(name: &'static str, db: Arc<DB>) -> (mpsc::Sender<()>, thread::JoinHandle<()>) {
+    let (signal, stop) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name(format!("{}-db-stats", name))
+        .spawn(move || {
+            DbStats::read(&db).export(name);
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stop.recv_timeout(STATS_INTERVAL) {
+                DbStats::read(&db).export(name);
+            }
+        })
+        .expect("spawning db stats thread");
+    (signal, handle)
+}
+
+/// Cumulative stall of the `rocksdb.stats` report, e.g.
+/// `Cumulative stall: 00:01:2.500 H:M:S, 0.3 percent`.
+fn parse_stall_time(stats: &str) -> Option<Duration> {
+    let line = stats.lines().find(|line| line.starts_with("Cumulative stall:"))?;
+    let time = line.trim_start_matches("Cumulative stall:").split_whitespace().next()?;
+    let mut parts = time.split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Level sizes of the `rocksdb.levelstats` table, which lists them in MB.
+fn parse_level_sizes(levelstats: &str) -> Vec<u64> {
+    levelstats.lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            match columns[..] {
+                [level, _files, size] => {
+                    level.parse::<u64>().ok()?;
+                    size.parse::<f64>().ok().map(|mb| (mb * 1024.0 * 1024.0) as u64)
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reports() {
+        let stats = "** DB Stats **\nUptime(secs): 12.0 total, 12.0 interval\n\
+            Cumulative stall: 00:01:2.500 H:M:S, 0.3 percent\n\
+            Interval stall: 00:00:0.000 H:M:S, 0.0 percent\n";
+        assert_eq!(parse_stall_time(stats), Some(Duration::from_millis(62_500)));
+        assert_eq!(parse_stall_time("** DB Stats **\n"), None);
+
+        let levelstats = "Level Files Size(MB)\n--------------------\n  0        2        3\n  1        0        0\n";
+        assert_eq!(parse_level_sizes(levelstats), vec![3 * 1024 * 1024, 0]);
+    }
+
+    #[test]
+    fn refresh_thread_stops() {
+        let dir = std::env::temp_dir().join("map_store_test_stats_thread");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Arc::new(DB::open_default(&dir).unwrap());
+        let (signal, handle) = start("test", db.clone());
+        signal.send(()).unwrap();
+        handle.join().unwrap();
+        // The thread let go of the database
+        assert_eq!(Arc::strong_count(&db), 1);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+use errors::Error;
+use map_core::block::{Block, Header};
+use map_core::types::{Address, AddressScheme, Hash};
+
+use crate::engine::ConsensusEngine;
+use crate::ConsensusErrorKind;
+
+/// Epoch proof of stake engine. Proposers are elected per slot by the block generator,
+/// headers carry the VRF output but not the proposer key.
+#[derive(Default)]
+pub struct APoS;
+
+impl APoS {
+    pub fn new() -> Self {
+        APoS
+    }
+}
+
+impl ConsensusEngine for APoS {
+    fn name(&self) -> &'static str {
+        "apos"
+    }
+
+    fn verify_header(&self, parent: &Header, header: &Header) -> Result<(), Error> {
+        // At most one block per slot
+        if header.slot <= parent.slot {
+            return Err(ConsensusErrorKind::InvalidSlot.into());
+        }
+        Ok(())
+    }
+
+    fn verify_seal(&self, _block: &Block) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn finalize(&self, mut block: Block, state_root: Hash) -> Result<Block, Error> {
+        block.set_state_root(state_root);
+        Ok(block)
+    }
+
+    fn author(&self, _block: &Block, _scheme: AddressScheme) -> Option<Address> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_slot() {
+        let engine = APoS::new();
+        let parent = Header { slot: 3, ..Default::default() };
+        assert!(engine.verify_header(&parent, &Header { slot: 4, ..Default::default() }).is_ok());
+        assert!(engine.verify_header(&parent, &Header { slot: 3, ..Default::default() }).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Engine interface used by the chain to seal and verify blocks.
+use errors::Error;
+use map_core::block::{Block, Header};
+use map_core::chain_spec::{ChainSpec, EngineSpec};
+use map_core::types::{Address, AddressScheme, Hash};
+
+use crate::apos::APoS;
+use crate::poa::POA;
+
+pub trait ConsensusEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Checks the engine specific header fields against the parent header.
+    fn verify_header(&self, parent: &Header, header: &Header) -> Result<(), Error>;
+
+    /// Checks the seal of an imported block, the signatures made by `finalize`.
+    fn verify_seal(&self, block: &Block) -> Result<(), Error>;
+
+    /// Sets the post-execution state root and seals a locally produced block.
+    fn finalize(&self, block: Block, state_root: Hash) -> Result<Block, Error>;
+
+    /// Returns the account which produced the block, if the engine records it, its address
+    /// derived with `scheme`.
+    fn author(&self, block: &Block, scheme: AddressScheme) -> Option<Address>;
+}
+
+/// Creates the engine selected by the chain spec.
+pub fn from_spec(spec: &ChainSpec) -> Box<dyn ConsensusEngine> {
+    match spec.engine {
+        EngineSpec::Poa { ref validator_key } => Box::new(POA::new_from_string(validator_key.clone())),
+        EngineSpec::APoS => Box::new(APoS::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_spec() {
+        assert_eq!(from_spec(&ChainSpec::default()).name(), "apos");
+        assert_eq!(from_spec(&ChainSpec::poa("".to_string())).name(), "poa");
+    }
+}
@@ -25,6 +25,8 @@ use failure::{Backtrace, err_msg, Context, Fail};
 use std::fmt::{self, Display, Debug};
 use errors::{Error, ErrorKind};
 
+pub mod apos;
+pub mod engine;
 pub mod poa;
 pub mod traits;
 
@@ -52,6 +54,7 @@ pub enum ConsensusErrorKind {
     NotEnoughShares,
     NotFoundSeedInfo,
     NotFetchAnyShares,
+    InvalidSlot,
 }
 
 impl fmt::Display for ConsensusError {
@@ -18,8 +18,9 @@ extern crate core;
 extern crate ed25519;
 
 use super::{traits::IConsensus,ConsensusErrorKind};
-use map_core::block::{self,Block,BlockProof,VerificationItem};
-use map_core::types::{Hash,Address};
+use super::engine::ConsensusEngine;
+use map_core::block::{self,Block,Header,BlockProof,VerificationItem};
+use map_core::types::{Hash,Address,AddressScheme};
 use map_core::genesis::{ed_genesis_priv_key,ed_genesis_pub_key};
 use ed25519::{pubkey::Pubkey,privkey::PrivKey,signature::SignatureInfo};
 use std::cmp::Ordering;
@@ -169,10 +170,35 @@ impl POA {
     pub fn get_interval() -> u64 {
         2000u64
     }
-    pub fn get_default_miner() -> Address {
+    pub fn get_default_miner(scheme: AddressScheme) -> Address {
         let mut pk = [0u8;32];
         pk[..].copy_from_slice(&ed_genesis_pub_key[..]);
-        Pubkey::from_bytes(&pk[..]).into()
+        Address::from_pubkey(&Pubkey::from_bytes(&pk[..]), scheme)
+    }
+}
+
+impl ConsensusEngine for POA {
+    fn name(&self) -> &'static str {
+        "poa"
+    }
+
+    fn verify_header(&self, _parent: &Header, _header: &Header) -> Result<(),Error> {
+        Ok(())
+    }
+
+    fn verify_seal(&self, b: &Block) -> Result<(),Error> {
+        self.verify(b)
+    }
+
+    fn finalize(&self, b: Block, h: Hash) -> Result<Block,Error> {
+        self.finalize_block(b, h)
+    }
+
+    fn author(&self, b: &Block, scheme: AddressScheme) -> Option<Address> {
+        let sign_info = b.sign_one()?;
+        let mut pk = [0u8;32];
+        pk[..].copy_from_slice(sign_info.signs.p());
+        Some(Address::from_pubkey(&Pubkey::from_bytes(&pk), scheme))
     }
 }
 
@@ -206,4 +232,11 @@ mod tests {
             Err(e) => println!("verify failed,err={:?}",e),
         }
     }
+    #[test]
+    fn test_engine_author() {
+        let f = POA::new(None);
+        let b = ConsensusEngine::finalize(&f, Block::default(), Hash([0u8;32])).unwrap();
+        assert!(f.verify_seal(&b).is_ok());
+        assert_eq!(f.author(&b, AddressScheme::default()), Some(POA::get_default_miner(AddressScheme::default())));
+    }
 }
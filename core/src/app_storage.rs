@@ -0,0 +1,131 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Key-value storage of applications, each account writes under its own namespace.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use bincode;
+use hash;
+use crate::types::{Hash, Address};
+use crate::state::StateDB;
+use crate::runtime::Interpreter;
+use crate::transaction::storage_msg::{MsgStorageSet, MsgStorageRemove};
+
+/// Max length of an application key.
+pub const MAX_KEY_LEN: usize = 64;
+/// Max length of a stored value.
+pub const MAX_VALUE_LEN: usize = 1024;
+
+pub struct AppStorage {
+    state_db: Rc<RefCell<StateDB>>,
+}
+
+impl AppStorage {
+    pub fn from_state(runner: Interpreter) -> Self {
+        AppStorage {
+            state_db: runner.statedb(),
+        }
+    }
+
+    /// Trie key of an application key in the account storage
+    pub fn slot_key(key: &[u8]) -> Hash {
+        Hash(hash::blake2b_256(key))
+    }
+
+    pub fn get(&self, addr: &Address, key: &[u8]) -> Option<Vec<u8>> {
+        self.state_db.borrow().get_account_storage(addr, &Self::slot_key(key))
+    }
+
+    pub fn storage_root(&self, addr: &Address) -> Hash {
+        self.state_db.borrow().account_storage_root(addr)
+    }
+
+    pub fn exec_set(&mut self, caller: &Address, input: Vec<u8>) {
+        let msg: MsgStorageSet = match bincode::deserialize(&input) {
+            Ok(msg) => msg,
+            Err(_) => {
+                warn!("invalid storage set message");
+                return;
+            }
+        };
+        if msg.key.is_empty() || msg.key.len() > MAX_KEY_LEN || msg.value.len() > MAX_VALUE_LEN {
+            warn!("storage entry exceeds limits key={} value={}", msg.key.len(), msg.value.len());
+            return;
+        }
+        self.state_db.borrow_mut().set_account_storage(*caller, Self::slot_key(&msg.key), &msg.value);
+    }
+
+    pub fn exec_remove(&mut self, caller: &Address, input: Vec<u8>) {
+        let msg: MsgStorageRemove = match bincode::deserialize(&input) {
+            Ok(msg) => msg,
+            Err(_) => {
+                warn!("invalid storage remove message");
+                return;
+            }
+        };
+        self.state_db.borrow_mut().remove_account_storage(*caller, Self::slot_key(&msg.key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use map_store::{MemoryKV, KVDB};
+    use crate::state::ArchiveDB;
+    use crate::trie::NULL_ROOT;
+    use super::*;
+
+    fn set(storage: &mut AppStorage, caller: &Address, key: &[u8], value: &[u8]) {
+        let input = bincode::serialize(&MsgStorageSet { key: key.to_vec(), value: value.to_vec() }).unwrap();
+        storage.exec_set(caller, input);
+    }
+
+    #[test]
+    fn namespaced_by_account() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(backend);
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut storage = AppStorage::from_state(Interpreter::new(state_db.clone()));
+        let alice = Address([1u8; 20]);
+        let bob = Address([2u8; 20]);
+
+        set(&mut storage, &alice, b"name", b"alice");
+        assert_eq!(storage.get(&alice, b"name"), Some(b"alice".to_vec()));
+        assert_eq!(storage.get(&bob, b"name"), None);
+
+        state_db.borrow_mut().commit();
+        let root = state_db.borrow().root();
+        assert_ne!(storage.storage_root(&alice), NULL_ROOT);
+        assert_eq!(storage.storage_root(&bob), NULL_ROOT);
+
+        // Reopened at the committed root
+        let reopened = Rc::new(RefCell::new(StateDB::from_existing(&db, root)));
+        let mut storage = AppStorage::from_state(Interpreter::new(reopened.clone()));
+        assert_eq!(storage.get(&alice, b"name"), Some(b"alice".to_vec()));
+
+        // Oversized values are ignored
+        set(&mut storage, &alice, b"big", &vec![0u8; MAX_VALUE_LEN + 1]);
+        assert_eq!(storage.get(&alice, b"big"), None);
+
+        let input = bincode::serialize(&MsgStorageRemove { key: b"name".to_vec() }).unwrap();
+        storage.exec_remove(&alice, input);
+        reopened.borrow_mut().commit();
+        assert_eq!(storage.get(&alice, b"name"), None);
+        assert_eq!(storage.storage_root(&alice), NULL_ROOT);
+        assert_eq!(reopened.borrow().root(), NULL_ROOT);
+    }
+}
@@ -15,6 +15,7 @@
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
 use serde::{Serialize, Deserialize};
 use bincode;
@@ -46,6 +47,32 @@ impl Account {
     pub fn get_nonce(&self) -> u64 {
         self.nonce
     }
+    pub fn get_locked_balance(&self) -> u128 {
+        self.locked_balance
+    }
+}
+
+/// Balance change that would underflow or overflow an account, the account is left unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BalanceError {
+    /// Debit larger than the available balance
+    Insufficient { balance: u128, value: u128 },
+    /// Debit larger than the locked balance
+    InsufficientLocked { locked: u128, value: u128 },
+    /// Credit beyond the max balance
+    Overflow,
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceError::Insufficient { balance, value } =>
+                write!(f, "balance {} can't cover {}", balance, value),
+            BalanceError::InsufficientLocked { locked, value } =>
+                write!(f, "locked balance {} can't cover {}", locked, value),
+            BalanceError::Overflow => write!(f, "balance overflow"),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -75,6 +102,10 @@ impl Balance {
         }
     }
 
+    pub fn interpreter(&self) -> Interpreter {
+        self.interpreter.clone()
+    }
+
     pub fn balance(&self, addr: Address) -> u128 {
         // let addr_hash = Self::address_key(addr);
         // let account = match self.cache.get(&addr_hash) {
@@ -127,84 +158,70 @@ impl Balance {
         // self.cache.insert(addr_hash, account);
     }
 
-    pub fn add_balance(&mut self, addr: Address, value: u128) {
-        // let addr_hash = Self::address_key(addr);
-        // let mut account = match self.cache.get(&addr_hash) {
-        //     Some(v) => v.clone(),
-        //     None => self.load_account(addr),
-        // };
+    pub fn add_balance(&mut self, addr: Address, value: u128) -> Result<(), BalanceError> {
         let mut account = self.load_account(addr);
-        account.balance += value;
+        account.balance = account.balance.checked_add(value).ok_or(BalanceError::Overflow)?;
         self.set_account(addr, &account);
-        // self.cache.insert(addr_hash, account);
+        Ok(())
     }
 
-    pub fn sub_balance(&mut self, addr: Address, value: u128) {
-        // let addr_hash = Self::address_key(addr);
-        // let mut account = match self.cache.get(&addr_hash) {
-        //     Some(v) => v.clone(),
-        //     None => self.load_account(addr),
-        // };
+    pub fn sub_balance(&mut self, addr: Address, value: u128) -> Result<(), BalanceError> {
         let mut account = self.load_account(addr);
-        account.balance -= value;
+        account.balance = account.balance.checked_sub(value)
+            .ok_or(BalanceError::Insufficient { balance: account.balance, value })?;
         self.set_account(addr, &account);
-        // self.cache.insert(addr_hash, account);
+        Ok(())
     }
 
-    pub fn slash(&mut self, addr: Address, value: u128) {
-        // let addr_hash = Self::address_key(addr);
-        // let mut account = match self.cache.get(&addr_hash) {
-        //     Some(v) => v.clone(),
-        //     None => self.load_account(addr),
-        // };
+    /// Burn `value` of the locked balance
+    pub fn slash(&mut self, addr: Address, value: u128) -> Result<(), BalanceError> {
         let mut account = self.load_account(addr);
-        account.locked_balance -= value;
+        account.locked_balance = account.locked_balance.checked_sub(value)
+            .ok_or(BalanceError::InsufficientLocked { locked: account.locked_balance, value })?;
         self.set_account(addr, &account);
-        // self.cache.insert(addr_hash, account);
+        Ok(())
     }
 
-    pub fn lock_balance(&mut self, addr: Address, value: u128) {
-        // let addr_hash = Self::address_key(addr);
-        // let mut account = match self.cache.get(&addr_hash) {
-        //     Some(v) => v.clone(),
-        //     None => self.load_account(addr),
-        // };
+    pub fn lock_balance(&mut self, addr: Address, value: u128) -> Result<(), BalanceError> {
         let mut account = self.load_account(addr);
-        account.balance -= value;
-        account.locked_balance += value;
+        account.balance = account.balance.checked_sub(value)
+            .ok_or(BalanceError::Insufficient { balance: account.balance, value })?;
+        account.locked_balance = account.locked_balance.checked_add(value).ok_or(BalanceError::Overflow)?;
         self.set_account(addr, &account);
-        // self.cache.insert(addr_hash, account);
+        Ok(())
     }
 
-    pub fn unlock_balance(&mut self, addr: Address, value: u128) {
-        // let addr_hash = Self::address_key(addr);
-        // let mut account = match self.cache.get(&addr_hash) {
-        //     Some(v) => v.clone(),
-        //     None => self.load_account(addr),
-        // };
+    pub fn unlock_balance(&mut self, addr: Address, value: u128) -> Result<(), BalanceError> {
         let mut account = self.load_account(addr);
-        account.balance += value;
-        account.locked_balance -= value;
+        account.locked_balance = account.locked_balance.checked_sub(value)
+            .ok_or(BalanceError::InsufficientLocked { locked: account.locked_balance, value })?;
+        account.balance = account.balance.checked_add(value).ok_or(BalanceError::Overflow)?;
         self.set_account(addr, &account);
-        // self.cache.insert(addr_hash, account);
+        Ok(())
     }
 
     // pub fn reset(&mut self) {
     //     self.cache.clear();
     // }
 
-    pub fn transfer(&mut self, from_addr: Address, to_addr: Address, amount: u128) {
-        if self.balance(from_addr) >= amount {
-            self.sub_balance(from_addr, amount);
-            self.add_balance(to_addr, amount);
-        } else {
-            // take transaction fee
+    /// Move `amount` between two accounts, neither changes if it fails.
+    pub fn transfer(&mut self, from_addr: Address, to_addr: Address, amount: u128) -> Result<(), BalanceError> {
+        self.sub_balance(from_addr, amount)?;
+        if let Err(e) = self.add_balance(to_addr, amount) {
+            self.add_balance(from_addr, amount).expect("balance restored");
+            return Err(e);
         }
+        Ok(())
     }
 
     pub fn exec_transfer(&mut self, from_addr: Address, input: Vec<u8>) {
-        let msg: transaction::balance_msg::MsgTransfer = bincode::deserialize(&input).unwrap();
-        self.transfer(from_addr, msg.receiver, msg.value);
+        let msg: transaction::balance_msg::MsgTransfer = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return warn!("invalid balance transfer message"),
+        };
+        if let Err(e) = self.transfer(from_addr, msg.receiver, msg.value) {
+            warn!("balance transfer from {} failed: {}", from_addr, e);
+        }
     }
 
     pub fn commit(&mut self) -> Hash {
@@ -287,7 +304,8 @@ mod tests {
     use crate::types::Address;
     use crate::runtime::Interpreter;
     use crate::trie::NULL_ROOT;
-    use super::{Balance, Account};
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::{Balance, Account, BalanceError};
 
     #[test]
     fn account_set() {
@@ -332,7 +350,7 @@ mod tests {
             locked_balance: 0,
         });
 
-        state.transfer(addr, receiver, 1);
+        state.transfer(addr, receiver, 1).unwrap();
         state.commit();
 
         {
@@ -359,9 +377,69 @@ mod tests {
             locked_balance: 0,
         };
         state.set_account(addr, &v1);
-        state.lock_balance(addr, lock_1);
+        state.lock_balance(addr, lock_1).unwrap();
         // state.commit();
 
         assert_eq!(state.locked(addr), lock_1);
     }
+
+    #[test]
+    fn checked_arithmetic() {
+        let mut state = new_state();
+        let addr = Address::default();
+        let receiver = Address([1; 20]);
+        state.add_balance(addr, 10).unwrap();
+        state.add_balance(receiver, u128::max_value()).unwrap();
+
+        assert_eq!(state.add_balance(receiver, 1), Err(BalanceError::Overflow));
+        assert_eq!(state.sub_balance(addr, 11), Err(BalanceError::Insufficient { balance: 10, value: 11 }));
+        assert_eq!(state.slash(addr, 1), Err(BalanceError::InsufficientLocked { locked: 0, value: 1 }));
+        assert_eq!(state.unlock_balance(addr, 1), Err(BalanceError::InsufficientLocked { locked: 0, value: 1 }));
+        // A failed transfer changes neither account
+        assert_eq!(state.transfer(addr, receiver, 5), Err(BalanceError::Overflow));
+        assert_eq!(state.balance(addr), 10);
+        assert_eq!(state.balance(receiver), u128::max_value());
+    }
+
+    /// Random transfers, locks, unlocks and slashes keep the total supply, minus the slashed
+    /// amount, and fail without changing any account.
+    #[test]
+    fn supply_conservation() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let mut state = new_state();
+            let addrs: Vec<Address> = (0..4u8).map(|i| Address([i; 20])).collect();
+            let mut supply = 0u128;
+            for addr in &addrs {
+                let value = rng.gen_range(0, 1_000_000u128);
+                state.add_balance(*addr, value).unwrap();
+                supply += value;
+            }
+
+            for _ in 0..200 {
+                let (from, to) = (addrs[rng.gen_range(0, 4)], addrs[rng.gen_range(0, 4)]);
+                let value = rng.gen_range(0, 2_000_000u128);
+                let before: Vec<Account> = addrs.iter().map(|a| state.get_account(*a)).collect();
+                let result = match rng.gen_range(0, 4) {
+                    0 => state.transfer(from, to, value),
+                    1 => state.lock_balance(from, value),
+                    2 => state.unlock_balance(from, value),
+                    _ => state.slash(from, value).map(|_| supply -= value),
+                };
+                let after: Vec<Account> = addrs.iter().map(|a| state.get_account(*a)).collect();
+                if result.is_err() {
+                    assert_eq!(before, after);
+                }
+                let total: u128 = after.iter().map(|a| a.balance + a.locked_balance).sum();
+                assert_eq!(total, supply);
+            }
+        }
+    }
+
+    fn new_state() -> Balance {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        Balance::new(Interpreter::new(state_db))
+    }
 }
@@ -21,7 +21,7 @@ use std::fmt;
 use serde::{Serialize, Deserialize};
 // use super::traits::{TxMsg};
 use super::transaction::{Transaction};
-use super::types::{Hash,Address};
+use super::types::{Hash,Address,AddressScheme};
 use ed25519::{signature::SignatureInfo,Message,pubkey::Pubkey};
 // use hash;
 use bincode;
@@ -135,9 +135,9 @@ impl BlockProof {
         }
         self.2
     }
-    pub fn to_address(&self) -> Address {
+    pub fn to_address(&self, scheme: AddressScheme) -> Address {
         if self.2 == 0u8 {
-            Pubkey::from_bytes(&self.0[..]).into()
+            Address::from_pubkey(&Pubkey::from_bytes(&self.0[..]), scheme)
         } else {
             Address([0u8;20])
         }
@@ -0,0 +1,451 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Chain parameters shared by every node of a network.
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use errors::{Error, InternalErrorKind};
+use super::genesis;
+use super::types::{self, Address, AddressScheme, Hash};
+
+/// Names accepted by `ChainSpec::preset`.
+pub const PRESETS: &[&str] = &["mainnet", "testnet", "dev"];
+
+/// Consensus engine used to seal and verify blocks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EngineSpec {
+    /// Single authority signing every block. The key is hex encoded, the genesis key is
+    /// used when it is empty or invalid.
+    Poa { validator_key: String },
+    /// Epoch proof of stake with VRF elected slot proposers.
+    APoS,
+}
+
+/// Protocol constants compiled into the node, part of the spec hash so that peers
+/// built with different values don't connect.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecConstants {
+    pub chain_id: u32,
+    pub slot_duration: u64,
+    pub epoch_length: u64,
+}
+
+impl SpecConstants {
+    /// Constants of this build.
+    pub fn current() -> Self {
+        SpecConstants {
+            chain_id: types::CHAIN_ID,
+            slot_duration: genesis::SLOT_DURATION,
+            epoch_length: genesis::EPOCH_LENGTH,
+        }
+    }
+}
+
+/// Heights consensus changes take effect at, from the block of that height on. A change left
+/// unset is not scheduled on the network and the rules before it keep applying.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Forks {
+    /// Transaction signatures cover the domain byte of their call
+    #[serde(default)]
+    pub signing_domains: Option<u64>,
+    /// `staking.rotate_key` replaces the consensus key of a validator
+    #[serde(default)]
+    pub key_rotation: Option<u64>,
+    /// Imported APoS blocks must be sealed by a member of their epoch's committee elected for
+    /// the slot, the committee being the validators of the epoch's boundary state
+    #[serde(default)]
+    pub proposer_check: Option<u64>,
+    /// `staking.*` and `storage.*` transactions run their module call instead of a transfer,
+    /// and staking stakes only the free balance. Key rotation needs it too
+    #[serde(default)]
+    pub module_calls: Option<u64>,
+}
+
+impl Forks {
+    /// Every change in force from genesis.
+    pub fn all() -> Self {
+        Forks {
+            signing_domains: Some(0),
+            key_rotation: Some(0),
+            proposer_check: Some(0),
+            module_calls: Some(0),
+        }
+    }
+
+    /// Rules a block of `height` is executed and verified under.
+    pub fn at(&self, height: u64) -> Rules {
+        let active = |fork: Option<u64>| fork.map_or(false, |from| height >= from);
+        Rules {
+            signing_domains: active(self.signing_domains),
+            key_rotation: active(self.key_rotation),
+            proposer_check: active(self.proposer_check),
+            module_calls: active(self.module_calls),
+        }
+    }
+}
+
+/// Consensus changes in force for one block, see `Forks`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub signing_domains: bool,
+    pub key_rotation: bool,
+    pub proposer_check: bool,
+    pub module_calls: bool,
+}
+
+/// Account funded in the genesis state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    pub address: String,
+    pub balance: u128,
+}
+
+/// Validator registered in the genesis state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    pub address: String,
+    pub pubkey: String,
+    pub stake: u128,
+}
+
+/// Genesis block time and initial state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    pub time: u64,
+    #[serde(default)]
+    pub allocation: Vec<GenesisAccount>,
+    #[serde(default)]
+    pub validators: Vec<GenesisValidator>,
+    /// Address derivation of the network, pinned in the genesis state
+    #[serde(default)]
+    pub address_scheme: AddressScheme,
+}
+
+impl GenesisSpec {
+    /// Check that every address and public key decodes and that the allocation fits the
+    /// max balance, so that setting up the genesis state can not fail half way.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut supply: u128 = 0;
+        for account in &self.allocation {
+            check_address("allocation address", &account.address)?;
+            supply = supply.checked_add(account.balance).ok_or_else(|| Error::from(InternalErrorKind::Other(
+                "genesis allocation overflows the max balance".to_string())))?;
+        }
+        for validator in &self.validators {
+            check_address("validator address", &validator.address)?;
+            check_hex("validator pubkey", &validator.pubkey, 32)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_address(what: &str, text: &str) -> Result<(), Error> {
+    text.parse::<Address>()
+        .map(|_| ())
+        .map_err(|e| InternalErrorKind::Other(format!("{} {}: {}", what, text, e)).into())
+}
+
+fn check_hex(what: &str, text: &str, len: usize) -> Result<(), Error> {
+    let from = text.trim_start_matches("0x").trim_start_matches("0X");
+    match hex::decode(from) {
+        Ok(ref bytes) if bytes.len() == len => Ok(()),
+        Ok(bytes) => Err(InternalErrorKind::Other(
+            format!("{} {} is {} bytes, expected {}", what, text, bytes.len(), len)).into()),
+        Err(e) => Err(InternalErrorKind::Other(format!("{} {}: {}", what, text, e)).into()),
+    }
+}
+
+/// Parameters of a network. Nodes only exchange blocks with peers using the same
+/// network id and genesis.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainSpec {
+    pub name: String,
+    pub network_id: u16,
+    pub engine: EngineSpec,
+    pub genesis: GenesisSpec,
+    /// Multiaddrs dialed on startup in addition to the configured peers
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
+    /// Folder under the datadir holding this network's data, empty for the datadir itself
+    #[serde(default)]
+    pub data_subdir: String,
+    /// Gas price floor recommended to the pools and proposers of the network. It is local
+    /// policy, blocks paying less are still valid.
+    #[serde(default)]
+    pub min_gas_price: u64,
+    /// Activation heights of consensus changes
+    #[serde(default)]
+    pub forks: Forks,
+    /// Single node development network: the node proposes every slot when the epoch has no
+    /// validators, with any peer count
+    #[serde(default)]
+    pub dev: bool,
+}
+
+impl ChainSpec {
+    pub fn mainnet() -> Self {
+        ChainSpec {
+            name: "mainnet".to_string(),
+            network_id: 31133,
+            engine: EngineSpec::APoS,
+            genesis: genesis::mainnet_genesis(),
+            bootnodes: Vec::new(),
+            // Mainnet predates network presets and keeps using the datadir directly
+            data_subdir: String::new(),
+            min_gas_price: 1,
+            forks: Forks::default(),
+            dev: false,
+        }
+    }
+
+    pub fn testnet() -> Self {
+        ChainSpec {
+            name: "testnet".to_string(),
+            network_id: 31134,
+            engine: EngineSpec::APoS,
+            genesis: genesis::testnet_genesis(),
+            bootnodes: Vec::new(),
+            data_subdir: "testnet".to_string(),
+            min_gas_price: 1,
+            forks: Forks::default(),
+            dev: false,
+        }
+    }
+
+    /// Local single node network sealed with the genesis key.
+    pub fn dev() -> Self {
+        ChainSpec {
+            name: "dev".to_string(),
+            network_id: 31135,
+            engine: EngineSpec::Poa { validator_key: String::new() },
+            genesis: genesis::dev_genesis(),
+            bootnodes: Vec::new(),
+            data_subdir: "dev".to_string(),
+            min_gas_price: 0,
+            forks: Forks::default(),
+            dev: true,
+        }
+    }
+
+    /// Mainnet parameters sealed by a single authority.
+    pub fn poa(validator_key: String) -> Self {
+        ChainSpec {
+            engine: EngineSpec::Poa { validator_key },
+            ..ChainSpec::mainnet()
+        }
+    }
+
+    /// Bundled spec by name, one of `PRESETS`.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "mainnet" => Some(ChainSpec::mainnet()),
+            "testnet" => Some(ChainSpec::testnet()),
+            "dev" => Some(ChainSpec::dev()),
+            _ => None,
+        }
+    }
+
+    /// Hash of everything peers must agree on: network id, engine, genesis, the protocol
+    /// constants and the scheduled forks. The name, bootnodes, data folder, gas price floor, dev
+    /// flag and the poa signing key are local settings and left out.
+    pub fn spec_hash(&self) -> Hash {
+        #[derive(Serialize)]
+        struct Consensus<'a> {
+            network_id: u16,
+            engine: &'static str,
+            genesis: &'a GenesisSpec,
+            constants: SpecConstants,
+        }
+
+        let engine = match self.engine {
+            EngineSpec::Poa { .. } => "poa",
+            EngineSpec::APoS => "apos",
+        };
+        let mut encoded = bincode::serialize(&Consensus {
+            network_id: self.network_id,
+            engine,
+            genesis: &self.genesis,
+            constants: SpecConstants::current(),
+        }).expect("chain spec serializes");
+        // Networks without forks keep the hash they had before forks were scheduled
+        if self.forks != Forks::default() {
+            encoded.extend(bincode::serialize(&self.forks).expect("forks serialize"));
+        }
+        Hash::make_hash(&encoded)
+    }
+
+    /// Load a custom spec from a json file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path).map_err(|e| Error::from(InternalErrorKind::Other(
+            format!("read chain spec {}: {}", path.display(), e))))?;
+        let spec: ChainSpec = serde_json::from_str(&text).map_err(|e| Error::from(InternalErrorKind::Other(
+            format!("parse chain spec {}: {}", path.display(), e))))?;
+        spec.genesis.validate()?;
+        Ok(spec)
+    }
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        ChainSpec::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_are_distinct() {
+        let specs: Vec<ChainSpec> = PRESETS.iter().map(|name| ChainSpec::preset(name).unwrap()).collect();
+        for (i, a) in specs.iter().enumerate() {
+            assert_eq!(a.name, PRESETS[i]);
+            assert!(a.genesis.validate().is_ok());
+            for b in &specs[i + 1..] {
+                assert_ne!(a.network_id, b.network_id);
+                assert_ne!(a.data_subdir, b.data_subdir);
+                assert_ne!(a.genesis.allocation, b.genesis.allocation);
+                assert_ne!(genesis::genesis_hash(&a.genesis), genesis::genesis_hash(&b.genesis));
+            }
+        }
+        assert_eq!(specs.iter().filter(|spec| spec.dev).map(|spec| spec.name.as_str()).collect::<Vec<_>>(), vec!["dev"]);
+        assert!(ChainSpec::dev().genesis.validators.is_empty());
+        assert_ne!(ChainSpec::testnet().genesis.validators, ChainSpec::mainnet().genesis.validators);
+        assert!(ChainSpec::preset("unknown").is_none());
+        assert_eq!(ChainSpec::default(), ChainSpec::mainnet());
+    }
+
+    #[test]
+    fn test_spec_file_roundtrip() {
+        let path = std::env::temp_dir().join("map_chain_spec_test.json");
+        let mut spec = ChainSpec::testnet();
+        spec.name = "custom".to_string();
+        spec.bootnodes.push("/ip4/127.0.0.1/tcp/40313".to_string());
+        fs::write(&path, serde_json::to_string(&spec).unwrap()).unwrap();
+        assert_eq!(ChainSpec::from_file(&path).unwrap(), spec);
+
+        spec.genesis.validators[0].pubkey = "0x1234".to_string();
+        fs::write(&path, serde_json::to_string(&spec).unwrap()).unwrap();
+        assert!(ChainSpec::from_file(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_allocation_overflow() {
+        let mut genesis = ChainSpec::testnet().genesis;
+        let account = GenesisAccount { address: format!("0x{}", "11".repeat(20)), balance: u128::max_value() };
+        genesis.allocation = vec![account.clone()];
+        assert!(genesis.validate().is_ok());
+        genesis.allocation.push(GenesisAccount { balance: 1, ..account });
+        assert!(genesis.validate().is_err());
+    }
+    #[test]
+    fn test_spec_hash() {
+        let spec = ChainSpec::testnet();
+        assert_eq!(spec.spec_hash(), ChainSpec::testnet().spec_hash());
+        assert_ne!(spec.spec_hash(), ChainSpec::mainnet().spec_hash());
+
+        // Local settings don't change the hash
+        let mut local = spec.clone();
+        local.name = "renamed".to_string();
+        local.bootnodes.push("/ip4/127.0.0.1/tcp/40313".to_string());
+        local.data_subdir = "elsewhere".to_string();
+        local.min_gas_price = 1000;
+        assert_eq!(local.spec_hash(), spec.spec_hash());
+        assert_eq!(ChainSpec::poa("01".repeat(32)).spec_hash(), ChainSpec::poa(String::new()).spec_hash());
+
+        let mut drift = spec.clone();
+        drift.genesis.time += 1;
+        assert_ne!(drift.spec_hash(), spec.spec_hash());
+        let mut drift = spec.clone();
+        drift.genesis.validators[0].stake += 1;
+        assert_ne!(drift.spec_hash(), spec.spec_hash());
+        let mut drift = spec.clone();
+        drift.engine = EngineSpec::Poa { validator_key: String::new() };
+        assert_ne!(drift.spec_hash(), spec.spec_hash());
+        let mut drift = spec.clone();
+        drift.forks.signing_domains = Some(100);
+        assert_ne!(drift.spec_hash(), spec.spec_hash());
+    }
+
+    #[test]
+    fn test_forks() {
+        let forks = Forks { signing_domains: Some(100), ..Forks::default() };
+        assert!(!forks.at(99).signing_domains);
+        assert!(forks.at(100).signing_domains);
+        assert!(!forks.at(100).key_rotation);
+        assert_eq!(Forks::default().at(u64::max_value()), Rules::default());
+        assert!(Forks::all().at(0).signing_domains);
+        assert!(Forks::all().at(0).proposer_check);
+        assert!(Forks::all().at(0).module_calls);
+
+        // Specs written before forks were scheduled have none
+        let mut json: serde_json::Value = serde_json::to_value(&ChainSpec::testnet()).unwrap();
+        json.as_object_mut().unwrap().remove("forks");
+        let spec: ChainSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.forks, Forks::default());
+    }
+
+    #[test]
+    fn test_address_scheme_pinned() {
+        use std::rc::Rc;
+        use std::cell::RefCell;
+        use std::sync::{Arc, RwLock};
+        use map_store::{MemoryKV, KVDB};
+        use crate::state::{ArchiveDB, StateDB};
+        use crate::trie::NULL_ROOT;
+
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(backend);
+        let mut spec = ChainSpec::dev().genesis;
+        let setup = |spec: &GenesisSpec| {
+            let state = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+            let root = genesis::setup_allocation(state.clone(), spec);
+            let scheme = genesis::pinned_address_scheme(&state.borrow());
+            (root, scheme)
+        };
+
+        let (blake_root, scheme) = setup(&spec);
+        assert_eq!(scheme, AddressScheme::Blake2b);
+
+        spec.address_scheme = AddressScheme::Keccak256;
+        let (keccak_root, scheme) = setup(&spec);
+        assert_eq!(scheme, AddressScheme::Keccak256);
+        assert_ne!(blake_root, keccak_root);
+
+        // Specs written before the field default to blake2b
+        let mut json: serde_json::Value = serde_json::to_value(&ChainSpec::dev()).unwrap();
+        json["genesis"].as_object_mut().unwrap().remove("address_scheme");
+        let spec: ChainSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.genesis.address_scheme, AddressScheme::Blake2b);
+    }
+
+    #[test]
+    fn test_genesis_addresses() {
+        let mut spec = ChainSpec::dev().genesis;
+        spec.allocation = vec![
+            GenesisAccount { address: "0x00000000000000000000000000000000000000AA".to_string(), balance: 1 },
+            GenesisAccount { address: "00000000000000000000000000000000000000bb".to_string(), balance: 1 },
+        ];
+        assert!(spec.validate().is_ok());
+
+        spec.allocation.push(GenesisAccount { address: "0x00aa".to_string(), balance: 1 });
+        assert!(spec.validate().is_err());
+    }
+}
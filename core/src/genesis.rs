@@ -16,16 +16,21 @@
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
 
+use bincode;
+use hash;
 use ed25519::pubkey::Pubkey;
 // use super::{traits::TxMsg};
-use super::types::{Hash, Address};
+use super::types::{Hash, AddressScheme};
 use super::block;
 use super::balance::Balance;
 use super::block::{Block, BlockProof};
 use super::runtime::Interpreter;
-use super::state::StateDB;
+use super::state::{ArchiveDB, StateDB};
+use super::trie::NULL_ROOT;
 use super::staking::{Validator, Staking};
+use super::chain_spec::{GenesisSpec, GenesisAccount, GenesisValidator};
 
 #[allow(non_upper_case_globals)]
 pub const ed_genesis_priv_key: [u8; 32] = [
@@ -40,6 +45,17 @@ pub const ed_genesis_pub_key: [u8; 32] = [
 ];
 
 pub const GENESIS_TIME: u64 = 1597916633;
+pub const TESTNET_GENESIS_TIME: u64 = 1609459200;
+pub const DEV_GENESIS_TIME: u64 = 1640995200;
+/// Seconds per slot, the slot of a block follows from its time
+pub const SLOT_DURATION: u64 = 6;
+/// Slots per epoch
+pub const EPOCH_LENGTH: u64 = 64;
+
+/// Unix time at which `slot` starts
+pub fn slot_start(genesis_time: u64, slot: u64) -> u64 {
+    genesis_time.saturating_add(slot.saturating_mul(SLOT_DURATION))
+}
 
 const ALLOCATION: &[(&str, u128)] = &[
     ("0xd2480451ef35ff2fdd7c69cad058719b9dc4d631", 1000000000000000000),
@@ -52,11 +68,77 @@ const VALIDATORS: &[(&str, &str, u128)] = &[
     // ("0x7411794f635cf645408cd698d5be3a964b5963e1", "0x2f4037ff722f3dcdf5e3b17f09a16c662c74deb8e2da85086b1cc935c23b64a2", 0),
 ];
 
-pub fn to_genesis() -> Block {
+const TESTNET_ALLOCATION: &[(&str, u128)] = &[
+    ("0x627669c5fc8953e164d50aa1f35971f2a78c23e2", 1000000000000000000),
+    ("0x1d2939e67e552d950ccf8e46520617941255f2fb", 1000000000000000000),
+    ("0xc8731ce187111c8c14204d0239efa8e0311ca159", 1000000000000000000),
+    // faucet
+    ("0xe8e4f1dad943e490bd860019004009b8b40bf54f", 1000000000000000000),
+];
+
+const TESTNET_VALIDATORS: &[(&str, &str, u128)] = &[
+    ("0x627669c5fc8953e164d50aa1f35971f2a78c23e2", "0x79609f414918b12912329985de61e43293304a2f91194e3b00ed7afab10ec551", 0),
+    ("0x1d2939e67e552d950ccf8e46520617941255f2fb", "0xbe8ab053f74b5209faa630d2c148001ca800594e44f338f5a09bdfe53cdf0f40", 0),
+    ("0xc8731ce187111c8c14204d0239efa8e0311ca159", "0x50ada9c9101f739369421c6facf096009ed6359b377b72f673c1aa476c2f892c", 0),
+];
+
+/// The genesis key, which also seals dev blocks, and the dev account of `DEV_ACCOUNT_KEY`
+const DEV_ALLOCATION: &[(&str, u128)] = &[
+    ("0xd2480451ef35ff2fdd7c69cad058719b9dc4d631", 1000000000000000000),
+    ("0xb0d1e9ed6d4e6cfd3ca79ab83eec245eee736559", 1000000000000000000),
+];
+
+/// Private key of the second funded account of the dev network, public on purpose
+pub const DEV_ACCOUNT_KEY: &str = "0x8cdb42c065ff4c1c0dcbb0aaa7d4f867fde7ee250c1987f25e05720e539e83ac";
+
+fn genesis_spec(time: u64, allocation: &[(&str, u128)], validators: &[(&str, &str, u128)]) -> GenesisSpec {
+    GenesisSpec {
+        time,
+        allocation: allocation.iter()
+            .map(|&(address, balance)| GenesisAccount { address: address.to_string(), balance })
+            .collect(),
+        validators: validators.iter()
+            .map(|&(address, pubkey, stake)| GenesisValidator {
+                address: address.to_string(),
+                pubkey: pubkey.to_string(),
+                stake,
+            })
+            .collect(),
+        address_scheme: AddressScheme::Blake2b,
+    }
+}
+
+/// Genesis with the bundled mainnet allocation and validators.
+pub fn mainnet_genesis() -> GenesisSpec {
+    genesis_spec(GENESIS_TIME, ALLOCATION, VALIDATORS)
+}
+
+pub fn testnet_genesis() -> GenesisSpec {
+    genesis_spec(TESTNET_GENESIS_TIME, TESTNET_ALLOCATION, TESTNET_VALIDATORS)
+}
+
+/// Dev genesis without validators, the dev node proposes every slot itself.
+pub fn dev_genesis() -> GenesisSpec {
+    genesis_spec(DEV_GENESIS_TIME, DEV_ALLOCATION, &[])
+}
+
+/// State key of the address scheme recorded at genesis
+pub fn address_scheme_key() -> Hash {
+    Hash(hash::blake2b_256(b"address_scheme"))
+}
+
+/// Address scheme recorded in the genesis state, blake2b when none is.
+pub fn pinned_address_scheme(db: &StateDB) -> AddressScheme {
+    db.get_storage(&address_scheme_key())
+        .and_then(|data| bincode::deserialize(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn to_genesis(spec: &GenesisSpec) -> Block {
     let zore_hash = [0u8;32];
     let mut b = Block::default();
     b.header.height = 0;
-    b.header.time = GENESIS_TIME;
+    b.header.time = spec.time;
     b.header.parent_hash = Hash(zore_hash);
     b.proofs.push(BlockProof(ed_genesis_pub_key,[0u8;32],0));
     b.header.tx_root = block::get_hash_from_txs(&b.txs);
@@ -64,24 +146,25 @@ pub fn to_genesis() -> Block {
     return b
 }
 
-pub fn setup_allocation(db: Rc<RefCell<StateDB>>) -> Hash {
+pub fn setup_allocation(db: Rc<RefCell<StateDB>>, spec: &GenesisSpec) -> Hash {
     {
         let interpreter = Interpreter::new(db.clone());
         let mut state = Balance::new(interpreter);
-        for &(addr, value) in ALLOCATION {
-            state.add_balance(Address::from_hex(addr).unwrap(), value);
+        for account in &spec.allocation {
+            state.add_balance(account.address.parse().expect("genesis validated"), account.balance)
+                .expect("genesis validated");
         }
         state.commit();
     }
     {
         let interpreter = Interpreter::new(db.clone());
         let mut state = Staking::new(interpreter);
-        for &(addr, pk, value) in VALIDATORS.iter().rev() {
+        for member in spec.validators.iter().rev() {
             let validator = Validator {
-                address: Address::from_hex(addr).unwrap(),
-                pubkey: Pubkey::from_hex(pk).to_bytes(),
+                address: member.address.parse().expect("genesis validated"),
+                pubkey: Pubkey::from_hex(&member.pubkey).to_bytes(),
                 balance: 0,
-                effective_balance: value,
+                effective_balance: member.stake,
                 activate_height: 0,
                 exit_height: 0,
                 deposit_queue: Vec::new(),
@@ -90,6 +173,21 @@ pub fn setup_allocation(db: Rc<RefCell<StateDB>>) -> Hash {
             state.insert(&validator);
         }
     }
+    // Only other schemes are recorded, existing blake2b networks keep their genesis
+    if spec.address_scheme != AddressScheme::Blake2b {
+        let data = bincode::serialize(&spec.address_scheme).unwrap();
+        db.borrow_mut().set_storage(address_scheme_key(), &data);
+    }
     db.borrow_mut().commit();
     db.borrow().root()
 }
+
+/// Hash of the genesis block of `spec`, with the state set up in a throwaway memory db.
+pub fn genesis_hash(spec: &GenesisSpec) -> Hash {
+    let backend: Arc<RwLock<dyn map_store::KVDB>> = Arc::new(RwLock::new(map_store::MemoryKV::new()));
+    let db = ArchiveDB::new(backend);
+    let state = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+    let mut block = to_genesis(spec);
+    block.set_state_root(setup_allocation(state, spec));
+    block.hash()
+}
@@ -19,11 +19,17 @@ extern crate log;
 pub mod types;
 pub mod block;
 pub mod genesis;
+pub mod chain_spec;
 pub mod transaction;
+pub mod transaction_json;
 pub mod balance;
 pub mod staking;
+pub mod app_storage;
 pub mod storage;
 pub mod trie;
 pub mod state;
+pub mod state_sync;
 pub mod runtime;
 pub mod traits;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
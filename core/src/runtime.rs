@@ -18,8 +18,9 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::state::{StateDB};
-use crate::staking::Staking;
+use crate::staking::{Staking, StakingEvent};
 use crate::balance::Balance;
+use crate::app_storage::AppStorage;
 use crate::types::Address;
 
 // pub trait Contract: {
@@ -31,12 +32,15 @@ use crate::types::Address;
 #[derive(Clone)]
 pub struct Interpreter {
     state_db: Rc<RefCell<StateDB>>,
+    // Shared by clones, modules emit into the interpreter which created them
+    events: Rc<RefCell<Vec<StakingEvent>>>,
 }
 
 impl Interpreter {
     pub fn new(backend: Rc<RefCell<StateDB>>) -> Self {
         Interpreter {
             state_db: backend.clone(),
+            events: Rc::new(RefCell::new(Vec::new())),
         }
     }
 
@@ -44,13 +48,22 @@ impl Interpreter {
         self.state_db.clone()
     }
 
+    pub fn emit(&self, event: StakingEvent) {
+        self.events.borrow_mut().push(event);
+    }
+
+    /// Returns the events emitted since the last call
+    pub fn take_events(&self) -> Vec<StakingEvent> {
+        self.events.replace(Vec::new())
+    }
+
     pub fn call(&mut self, caller: &Address, msg: Vec<u8>, input: Vec<u8>) {
         let sep = msg.iter().position(|&x| x == '.' as u8);
         if sep.is_none() {
             warn!("invalid msg in transaction");
             return
         }
-        let (module, func) = msg.split_at(sep.unwrap());
+        let (module, func) = (&msg[..sep.unwrap()], &msg[sep.unwrap() + 1..]);
 
         if module == b"balance" {
             let mut state = Balance::from_state(self.clone());
@@ -63,8 +76,17 @@ impl Interpreter {
             match func {
                 b"validate" => state.exec_validate(caller, input),
                 b"deposit" => state.exec_deposit(caller, input),
+                b"exit" => state.exec_exit(caller, input),
+                b"rotate_key" => state.exec_rotate_key(caller, input),
                 _ => warn!("invalid staking call"),
             }
+        } else if module == b"storage" {
+            let mut state = AppStorage::from_state(self.clone());
+            match func {
+                b"set" => state.exec_set(caller, input),
+                b"remove" => state.exec_remove(caller, input),
+                _ => warn!("invalid storage call"),
+            }
         } else {
             warn!("unsupport msg call");
         }
@@ -93,6 +115,8 @@ mod tests {
     use crate::state::{ArchiveDB, StateDB};
     use crate::types::Address;
     use crate::trie::NULL_ROOT;
+    use crate::balance::Balance;
+    use crate::staking::{MsgValidatorCreate, StakingEvent};
     use super::{Interpreter};
 
     #[test]
@@ -103,4 +127,30 @@ mod tests {
         let mut runner = Interpreter::new(state_db.clone());
         runner.call(&Address::default(), b"staking.deposit".to_vec(), bincode::serialize(&1u128).unwrap());
     }
+
+    #[test]
+    fn interpreter_staking_events() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let mut runner = Interpreter::new(state_db.clone());
+        let addr = Address::from_low_u64_be(1);
+        Balance::from_state(runner.clone()).add_balance(addr, 100).unwrap();
+
+        let create = MsgValidatorCreate { pubkey: vec![1; 32], amount: 60 };
+        runner.call(&addr, b"staking.validate".to_vec(), bincode::serialize(&create).unwrap());
+        runner.call(&addr, b"staking.deposit".to_vec(), bincode::serialize(&30u128).unwrap());
+        // Not enough balance left
+        runner.call(&addr, b"staking.deposit".to_vec(), bincode::serialize(&30u128).unwrap());
+        runner.call(&addr, b"staking.exit".to_vec(), Vec::new());
+
+        let events = runner.take_events();
+        assert_eq!(events, vec![
+            StakingEvent::ValidatorCreated { address: addr, pubkey: vec![1; 32], amount: 60 },
+            StakingEvent::DepositAdded { address: addr, amount: 30 },
+            StakingEvent::ValidatorExited { address: addr, exit_height: 0 },
+        ]);
+        assert!(runner.take_events().is_empty());
+        assert_eq!(Balance::from_state(runner.clone()).locked(addr), 90);
+    }
 }
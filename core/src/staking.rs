@@ -20,16 +20,23 @@ use std::rc::Rc;
 use serde::{Serialize, Deserialize};
 use bincode;
 use hash;
+use ed25519::{privkey::PrivKey, pubkey::Pubkey, signature::SignatureInfo};
+use errors::Error;
 use crate::types::{Hash, Address};
 use crate::storage::{List, ListEntry};
 use crate::state::StateDB;
 use crate::balance::Balance;
 use crate::runtime::Interpreter;
 
+/// Message call replacing the consensus key of a validator
+pub const ROTATE_KEY_CALL: &[u8] = b"staking.rotate_key";
+
 #[derive(Copy, Clone)]
 enum StatePrefix {
     /// Validators list key
     Validator = 2,
+    /// Number of key rotations of a validator
+    Rotations = 3,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -52,6 +59,36 @@ pub struct Validator {
     pub unlocked_queue: Vec<LockingBalance>,
 }
 
+/// Change made by the staking module, recorded per block for external tooling.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StakingEvent {
+    ValidatorCreated { address: Address, pubkey: Vec<u8>, amount: u128 },
+    DepositAdded { address: Address, amount: u128 },
+    ValidatorExited { address: Address, exit_height: u64 },
+    KeyRotated { address: Address, pubkey: Vec<u8> },
+}
+
+impl StakingEvent {
+    pub fn address(&self) -> &Address {
+        match self {
+            StakingEvent::ValidatorCreated { address, .. } => address,
+            StakingEvent::DepositAdded { address, .. } => address,
+            StakingEvent::ValidatorExited { address, .. } => address,
+            StakingEvent::KeyRotated { address, .. } => address,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StakingEvent::ValidatorCreated { .. } => "ValidatorCreated",
+            StakingEvent::DepositAdded { .. } => "DepositAdded",
+            StakingEvent::ValidatorExited { .. } => "ValidatorExited",
+            StakingEvent::KeyRotated { .. } => "KeyRotated",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[derive(Clone, Debug, PartialEq)]
 pub struct MsgValidatorCreate {
@@ -59,6 +96,76 @@ pub struct MsgValidatorCreate {
     pub amount: u128,
 }
 
+/// Replace the consensus key of the sender's validator. Both keys sign the change: the old one so
+/// the account key alone can't hand the validator to another key, the new one to prove it is
+/// held. The signed message binds the current key and the number of rotations done, so a
+/// signature is good for one rotation only.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MsgRotateKey {
+    pub new_pubkey: Vec<u8>,
+    /// Ed25519 signature, `r` then `s`, of `rotation_hash` by the current key
+    pub signature_by_old_key: Vec<u8>,
+    /// Ed25519 signature, `r` then `s`, of `rotation_hash` by the new key
+    pub signature_by_new_key: Vec<u8>,
+}
+
+impl MsgRotateKey {
+    /// Rotation number `rotation` of the validator of `addr`, from `old_key` to `new_key`
+    pub fn new(addr: &Address, rotation: u64, old_key: &PrivKey, new_key: &PrivKey) -> Result<Self, Error> {
+        let old_pubkey = old_key.to_pubkey()?.to_bytes();
+        let new_pubkey = new_key.to_pubkey()?.to_bytes();
+        let message = Self::rotation_hash(addr, &old_pubkey, &new_pubkey, rotation);
+        Ok(MsgRotateKey {
+            new_pubkey,
+            signature_by_old_key: signature_bytes(&old_key.sign(message.to_slice())?),
+            signature_by_new_key: signature_bytes(&new_key.sign(message.to_slice())?),
+        })
+    }
+
+    /// Message signed by both keys, binding the change to the validator, its current key and
+    /// its rotation count
+    pub fn rotation_hash(addr: &Address, old_pubkey: &[u8], new_pubkey: &[u8], rotation: u64) -> Hash {
+        let mut raw = ROTATE_KEY_CALL.to_vec();
+        raw.extend_from_slice(addr.as_slice());
+        raw.extend_from_slice(old_pubkey);
+        raw.extend_from_slice(new_pubkey);
+        raw.extend_from_slice(&rotation.to_be_bytes());
+        Hash(hash::blake2b_256(&raw))
+    }
+
+    /// Whether both `old_pubkey` and the new key signed rotation number `rotation` of the
+    /// validator of `addr`
+    pub fn verify(&self, addr: &Address, old_pubkey: &[u8], rotation: u64) -> bool {
+        let message = Self::rotation_hash(addr, old_pubkey, &self.new_pubkey, rotation);
+        signed_by(&message, old_pubkey, &self.signature_by_old_key)
+            && signed_by(&message, &self.new_pubkey, &self.signature_by_new_key)
+    }
+}
+
+fn signature_bytes(signature: &SignatureInfo) -> Vec<u8> {
+    let mut bytes = signature.r().to_vec();
+    bytes.extend_from_slice(signature.s());
+    bytes
+}
+
+/// Whether `signature`, `r` then `s`, is the signature of `message` by `pubkey`
+fn signed_by(message: &Hash, pubkey: &[u8], signature: &[u8]) -> bool {
+    if pubkey.len() != 32 || signature.len() != 64 {
+        return false;
+    }
+    let (mut r, mut s, mut p) = ([0u8; 32], [0u8; 32], [0u8; 32]);
+    r.copy_from_slice(&signature[..32]);
+    s.copy_from_slice(&signature[32..]);
+    p.copy_from_slice(pubkey);
+    let signature = SignatureInfo::make(r, s, p);
+    // An undecodable signature would panic in verify
+    if signature.to_signature().is_err() {
+        return false;
+    }
+    Pubkey::from_bytes(pubkey).verify(&message.to_msg(), &signature).is_ok()
+}
+
 impl Validator {
     pub fn create(addr: Address) -> Self {
         Validator {
@@ -82,6 +189,11 @@ impl Validator {
         Hash(hash::blake2b_256(&raw))
     }
 
+    /// Returns true if the validator takes part in block production at the given height.
+    pub fn is_active(&self, height: u64) -> bool {
+        self.activate_height <= height && (self.exit_height == 0 || self.exit_height > height)
+    }
+
     pub fn key_index(addr: &Address) -> Hash {
         let mut raw = vec![];
         raw.extend_from_slice(Hash::from_bytes(addr.as_slice()).as_bytes());
@@ -90,6 +202,15 @@ impl Validator {
 
         Hash(hash::blake2b_256(&raw))
     }
+
+    pub fn rotations_index(addr: &Address) -> Hash {
+        let mut raw = vec![];
+        raw.extend_from_slice(Hash::from_bytes(addr.as_slice()).as_bytes());
+        let position = Hash::from_bytes(&(StatePrefix::Rotations as u64).to_be_bytes()[..]);
+        raw.extend_from_slice(position.as_bytes());
+
+        Hash(hash::blake2b_256(&raw))
+    }
 }
 
 pub struct Staking {
@@ -231,12 +352,19 @@ impl Staking {
             // the address already joined the validator
             return
         }
+        if Balance::from_state(self.interpreter.clone()).balance(*addr) < amount {
+            return
+        }
+        // self.interpreter.lock_balance(*addr, amount);
+        if let Err(e) = Balance::from_state(self.interpreter.clone()).lock_balance(*addr, amount) {
+            return warn!("validator stake of {} not locked: {}", addr, e);
+        }
         // mark the epoch in which validator take effect
         let activate: u64 = 0;
         // create and initialize validator
         let validator = Validator {
             address: *addr,
-            pubkey: pubkey,
+            pubkey: pubkey.clone(),
             balance: amount,
             effective_balance: amount,
             activate_height: 0,
@@ -245,12 +373,7 @@ impl Staking {
             unlocked_queue: Vec::new(),
         };
         self.insert(&validator);
-
-        // self.interpreter.lock_balance(*addr, amount);
-        {
-            let mut state = Balance::from_state(self.interpreter.clone());
-            state.lock_balance(*addr, amount);
-        }
+        self.interpreter.emit(StakingEvent::ValidatorCreated { address: *addr, pubkey, amount });
     }
 
     pub fn deposit(&mut self, addr: &Address, amount: u128) {
@@ -258,15 +381,17 @@ impl Staking {
             Some(i) => i,
             None => return,
         };
-        validator.deposit_queue.push(LockingBalance{amount: amount, height: 0});
-        validator.balance += amount;
-        self.set_item(&validator);
-
+        if Balance::from_state(self.interpreter.clone()).balance(*addr) < amount {
+            return
+        }
         // self.interpreter.lock_balance(*addr, amount);
-        {
-            let mut state = Balance::from_state(self.interpreter.clone());
-            state.lock_balance(*addr, amount);
+        if let Err(e) = Balance::from_state(self.interpreter.clone()).lock_balance(*addr, amount) {
+            return warn!("deposit of {} not locked: {}", addr, e);
         }
+        validator.deposit_queue.push(LockingBalance{amount: amount, height: 0});
+        validator.balance = validator.balance.saturating_add(amount);
+        self.set_item(&validator);
+        self.interpreter.emit(StakingEvent::DepositAdded { address: *addr, amount });
     }
 
     pub fn activate_deposit(&mut self, addr: &Address) {
@@ -296,6 +421,39 @@ impl Staking {
         // mark the epoch in which validator exit make block
         validator.exit_height = 0;
         self.set_item(&validator);
+        self.interpreter.emit(StakingEvent::ValidatorExited { address: *addr, exit_height: validator.exit_height });
+    }
+
+    /// Number of key rotations of the validator of `addr`, the next rotation signs it
+    pub fn rotations(&self, addr: &Address) -> u64 {
+        self.state_db.borrow().get_storage(&Validator::rotations_index(addr))
+            .and_then(|encoded| bincode::deserialize(&encoded).ok())
+            .unwrap_or(0)
+    }
+
+    /// Replace the consensus key of the validator of `addr` by a key no validator uses. The
+    /// committee of an epoch is read from the state before its first slot, so the new key
+    /// proposes from the next epoch on and the old one until then.
+    pub fn rotate_key(&mut self, addr: &Address, msg: MsgRotateKey) {
+        let mut validator = match self.get_validator(addr) {
+            Some(i) => i,
+            None => return,
+        };
+        if msg.new_pubkey.len() != 32 {
+            return warn!("key rotation of {} to a key of {} bytes", addr, msg.new_pubkey.len());
+        }
+        if self.validator_set().iter().any(|v| v.pubkey == msg.new_pubkey) {
+            return warn!("key rotation of {} to the key of a validator", addr);
+        }
+        let rotation = self.rotations(addr);
+        if !msg.verify(addr, &validator.pubkey, rotation) {
+            return warn!("key rotation {} of {} not signed by its current and new keys", rotation, addr);
+        }
+        validator.pubkey = msg.new_pubkey.clone();
+        self.set_item(&validator);
+        self.state_db.borrow_mut().set_storage(
+            Validator::rotations_index(addr), &bincode::serialize(&(rotation + 1)).unwrap());
+        self.interpreter.emit(StakingEvent::KeyRotated { address: *addr, pubkey: msg.new_pubkey });
     }
 
     pub fn exec_validate(&mut self, addr: &Address, input: Vec<u8>) {
@@ -314,6 +472,14 @@ impl Staking {
         self.deposit(addr, msg);
     }
 
+    pub fn exec_rotate_key(&mut self, addr: &Address, input: Vec<u8>) {
+        let msg: MsgRotateKey = match bincode::deserialize(&input) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+        self.rotate_key(addr, msg);
+    }
+
     #[allow(unused_variables)]
     pub fn exec_exit(&mut self, addr: &Address, input: Vec<u8>) {
         self.exit(addr);
@@ -331,7 +497,8 @@ mod tests {
     use crate::state::{ArchiveDB, StateDB};
     use crate::types::Address;
     use crate::trie::NULL_ROOT;
-    use super::{Validator, Staking};
+    use crate::test_utils;
+    use super::{MsgRotateKey, Validator, Staking, StakingEvent};
 
     #[test]
     fn validator_insert() {
@@ -445,4 +612,65 @@ mod tests {
         assert_eq!(stake.get_validator(&addr), None);
         assert_eq!(stake.get_validator(&addr_1), None);
     }
+
+    #[test]
+    fn key_rotation() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let db = ArchiveDB::new(Arc::clone(&backend));
+        let state_db = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+        let key = |seed| test_utils::test_key(seed).to_pubkey().unwrap().to_bytes();
+        let addr = Address::default();
+        let other = Address::from_hex("0x0000000000000000000000000000000000000001").unwrap();
+
+        let mut stake = Staking::new(Interpreter::new(state_db.clone()));
+        let mut validator = Validator::create(addr);
+        validator.pubkey = key(1);
+        stake.insert(&validator);
+        let mut validator_1 = Validator::create(other);
+        validator_1.pubkey = key(2);
+        stake.insert(&validator_1);
+
+        let rotate = |rotation, old, new| MsgRotateKey::new(
+            &addr, rotation, &test_utils::test_key(old), &test_utils::test_key(new)).unwrap();
+
+        // Signed by another key, to the key of another validator, for another validator, for
+        // another rotation
+        stake.rotate_key(&addr, rotate(0, 2, 3));
+        stake.rotate_key(&addr, rotate(0, 1, 2));
+        stake.rotate_key(&addr, MsgRotateKey::new(&other, 0, &test_utils::test_key(1), &test_utils::test_key(3)).unwrap());
+        stake.rotate_key(&addr, rotate(1, 1, 3));
+        // Not signed by the new key
+        let mut unproven = rotate(0, 1, 3);
+        unproven.signature_by_new_key = rotate(0, 1, 4).signature_by_new_key;
+        stake.rotate_key(&addr, unproven);
+        assert_eq!(stake.get_validator(&addr).unwrap().pubkey, key(1));
+        assert!(stake.interpreter.take_events().is_empty());
+
+        let first = rotate(0, 1, 3);
+        stake.rotate_key(&addr, first.clone());
+        assert_eq!(stake.get_validator(&addr).unwrap().pubkey, key(3));
+        assert_eq!(stake.get_validator(&other).unwrap(), validator_1);
+        assert_eq!(stake.rotations(&addr), 1);
+        assert_eq!(stake.interpreter.take_events(), vec![StakingEvent::KeyRotated { address: addr, pubkey: key(3) }]);
+
+        // Back to the first key, the first rotation can't be replayed then
+        stake.rotate_key(&addr, rotate(1, 3, 1));
+        assert_eq!(stake.get_validator(&addr).unwrap().pubkey, key(1));
+        stake.rotate_key(&addr, first);
+        assert_eq!(stake.get_validator(&addr).unwrap().pubkey, key(1));
+    }
+
+    #[test]
+    fn validator_active() {
+        let mut validator = Validator::create(Address::default());
+        validator.activate_height = 2;
+        validator.exit_height = 10;
+
+        assert!(!validator.is_active(1));
+        assert!(validator.is_active(2));
+        assert!(!validator.is_active(10));
+
+        validator.exit_height = 0;
+        assert!(validator.is_active(100));
+    }
 }
@@ -14,20 +14,63 @@
 // You should have received a copy of the GNU General Public License
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
 use serde::{Serialize, Deserialize};
 use bincode;
 use hash_db::{HashDB, HashDBRef, AsHashDB, Prefix};
-use trie_db::{DBValue, Trie, TrieMut};
-use map_store::KVDB;
-use crate::types::Hash;
+use trie_db::{DBValue, Trie, TrieError, TrieMut};
+use map_store::{KVBatch, KVDB};
+use hash;
+use crate::types::{Address, Hash};
 use crate::trie::{MemoryDB, EMPTY_TRIE, Blake2Hasher, TrieDBMut, TrieDB, NULL_ROOT};
 
+/// Prefix of the reference count of a committed node, the node hash follows
+const REFS_PREFIX: u8 = b'r';
+
+/// Trie node references a state change added and dropped, in counts per node. Only nodes
+/// written with a reference count are listed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateJournal {
+    /// Added on commit, taken back if the change is abandoned
+    pub inserted: Vec<(Hash, u32)>,
+    /// Dropped once the change is final
+    pub removed: Vec<(Hash, u32)>,
+}
+
+impl StateJournal {
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn refs_key(key: &Hash) -> Vec<u8> {
+    let mut refs = Vec::with_capacity(33);
+    refs.push(REFS_PREFIX);
+    refs.extend_from_slice(key.as_bytes());
+    refs
+}
+
+fn decode_refs(raw: &[u8]) -> u32 {
+    let mut count = [0u8; 4];
+    count.copy_from_slice(&raw[..4]);
+    u32::from_be_bytes(count)
+}
+
 #[derive(Clone)]
 pub struct ArchiveDB {
     backend: Arc<RwLock<dyn KVDB>>,
     cached: MemoryDB,
+    /// Flush committed state to disk before returning
+    sync: bool,
+    /// Nodes found missing while reading or writing the state, shared by the clones
+    missing: Arc<Mutex<HashSet<Hash>>>,
+    /// Count the references of the nodes committed, so that `release` can delete them
+    counted: bool,
+    /// References committed through this handle since `take_journal`
+    journal: StateJournal,
 }
 
 impl AsHashDB<Blake2Hasher, DBValue> for ArchiveDB {
@@ -46,24 +89,161 @@ impl ArchiveDB {
         ArchiveDB {
             backend: backend,
             cached: MemoryDB::new(EMPTY_TRIE),
+            sync: false,
+            missing: Arc::new(Mutex::new(HashSet::new())),
+            counted: false,
+            journal: StateJournal::default(),
         }
     }
 
+    /// Count node references on commit, for handles cloned from now on. Nodes committed
+    /// before are never deleted.
+    pub fn set_counted(&mut self, counted: bool) {
+        self.counted = counted;
+    }
+
+    /// References committed through this handle since the last call
+    pub fn take_journal(&mut self) -> StateJournal {
+        std::mem::replace(&mut self.journal, StateJournal::default())
+    }
+
+    /// Enable or disable fsync on state commit
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
     fn payload(&self, key: &Hash) -> Option<DBValue> {
         trace!("load payload {:}", key);
         self.backend.read().unwrap().get(key.as_bytes()).expect("get diskdb payload failed")
     }
 
-    /// Write memory changes to backend db
+    /// Load a committed trie node by hash
+    pub fn get_node(&self, key: &Hash) -> Option<DBValue> {
+        self.payload(key)
+    }
+
+    /// Load committed trie nodes by hash, skipping unknown ones.
+    /// Stops once `max_count` nodes are loaded or the next node would exceed `max_bytes`.
+    pub fn get_nodes(&self, keys: &[Hash], max_count: usize, max_bytes: usize) -> Vec<DBValue> {
+        let mut nodes = Vec::new();
+        let mut size = 0;
+        for key in keys.iter() {
+            if nodes.len() >= max_count {
+                break;
+            }
+            if let Some(node) = self.payload(key) {
+                if size + node.len() > max_bytes {
+                    break;
+                }
+                size += node.len();
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+
+    /// Nodes found missing since the last call. A state read or written over missing nodes
+    /// gives wrong results, its caller must discard them.
+    pub fn take_missing(&self) -> Vec<Hash> {
+        self.missing.lock().unwrap().drain().collect()
+    }
+
+    /// Record the node a trie error is about when it is missing, any other error is a node
+    /// that doesn't decode and can't be worked around.
+    fn report<E: fmt::Debug>(&self, e: &TrieError<Hash, E>) {
+        match missing_node(e) {
+            Some(hash) => {
+                error!("missing state node {}", hash);
+                self.missing.lock().unwrap().insert(hash);
+            }
+            None => panic!("corrupt state trie: {:?}", e),
+        }
+    }
+
+    /// Write memory changes to backend db in a single batch. Only the nodes inserted since the
+    /// last commit are in the cache, unchanged subtrees are never written again. When counted,
+    /// the references added are counted at once and the dropped ones journaled. A node already
+    /// stored without a count stays uncounted.
     pub fn commit(&mut self) {
-        for i in self.cached.drain() {
-            let (key, (value, rc)) = i;
+        let nodes = self.cached.drain();
+        let mut batch = KVBatch::with_capacity(nodes.len());
+        let mut backend = self.backend.write().unwrap();
+        for (key, (value, rc)) in nodes {
             if rc > 0 {
-                let mut backend = self.backend.write().unwrap();
                 trace!("db set key={:}, value={:x?}", key, value);
-                backend.put(key.as_bytes(), &value).expect("wirte backend");
+                if self.counted {
+                    let refs = backend.get(&refs_key(&key)).expect("get diskdb refs failed");
+                    let stored = refs.is_some() || backend.get(key.as_bytes()).expect("get diskdb payload failed").is_some();
+                    if refs.is_some() || !stored {
+                        let count = refs.map_or(0, |raw| decode_refs(&raw)) + rc as u32;
+                        batch.put(&refs_key(&key), &count.to_be_bytes());
+                        self.journal.inserted.push((key, rc as u32));
+                    }
+                }
+                batch.put(key.as_bytes(), &value);
+            } else if rc < 0 && self.counted {
+                self.journal.removed.push((key, (-rc) as u32));
             }
         }
+        if batch.is_empty() {
+            return;
+        }
+        backend.write(batch, self.sync).expect("wirte backend");
+    }
+
+    /// Drop the references of journals, the removed ones of final changes and the inserted
+    /// ones of abandoned changes. Nodes left without references are deleted, returns their
+    /// number. Uncounted nodes are kept.
+    pub fn release(&self, journals: &[(StateJournal, bool)]) -> io::Result<usize> {
+        let mut backend = self.backend.write().unwrap();
+        let mut counts: HashMap<Hash, u32> = HashMap::new();
+        for (journal, is_final) in journals {
+            let refs = if *is_final { &journal.removed } else { &journal.inserted };
+            for (key, dropped) in refs {
+                let count = match counts.get(key) {
+                    Some(count) => Some(*count),
+                    None => backend.get(&refs_key(key))?.map(|raw| decode_refs(&raw)),
+                };
+                // Nodes gone or uncounted are left alone
+                if let Some(count) = count {
+                    counts.insert(*key, count.saturating_sub(*dropped));
+                }
+            }
+        }
+
+        let mut batch = KVBatch::with_capacity(counts.len());
+        let mut pruned = 0;
+        for (key, count) in counts {
+            if count == 0 {
+                batch.delete(key.as_bytes());
+                batch.delete(&refs_key(&key));
+                pruned += 1;
+            } else {
+                batch.put(&refs_key(&key), &count.to_be_bytes());
+            }
+        }
+        if !batch.is_empty() {
+            backend.write(batch, self.sync)?;
+        }
+        Ok(pruned)
+    }
+
+    /// Delete the committed nodes not in `keep`, returns the number deleted. Keys other than
+    /// node hashes, like the format marker, are left alone.
+    pub fn prune(&mut self, keep: &HashSet<Hash>) -> io::Result<usize> {
+        let mut backend = self.backend.write().unwrap();
+        let mut batch = KVBatch::new();
+        let mut pruned = 0;
+        for key in backend.keys()? {
+            if key.len() == 32 && !keep.contains(&Hash::from(&key[..])) {
+                batch.delete(&key);
+                pruned += 1;
+            }
+        }
+        if pruned > 0 {
+            backend.write(batch, self.sync)?;
+        }
+        Ok(pruned)
     }
 }
 
@@ -108,6 +288,14 @@ impl HashDBRef<Blake2Hasher, DBValue> for ArchiveDB {
     }
 }
 
+/// Hash of the node a trie error is about, if the node is not in the db
+fn missing_node<E>(e: &TrieError<Hash, E>) -> Option<Hash> {
+    match e {
+        TrieError::InvalidStateRoot(hash) | TrieError::IncompleteDatabase(hash) => Some(*hash),
+        _ => None,
+    }
+}
+
 #[derive(Clone)]
 pub struct CachingDB {
     backend: Arc<RwLock<dyn KVDB>>,
@@ -247,6 +435,15 @@ pub struct StateDB {
     db: ArchiveDB,
     state_root: Hash,
     local_changes: HashMap<Hash, Option<Vec<u8>>>,
+    /// Pending writes to the storage trie of each account
+    account_changes: HashMap<Address, HashMap<Hash, Option<Vec<u8>>>>,
+}
+
+/// Key of the storage trie root of an account in the state trie
+pub fn storage_root_key(addr: &Address) -> Hash {
+    let mut raw = b"storage_root".to_vec();
+    raw.extend_from_slice(addr.as_slice());
+    Hash(hash::blake2b_256(&raw))
 }
 
 impl StateDB {
@@ -255,6 +452,7 @@ impl StateDB {
             db: db.clone(),
             state_root: NULL_ROOT,
             local_changes: HashMap::new(),
+            account_changes: HashMap::new(),
         }
     }
 
@@ -263,6 +461,7 @@ impl StateDB {
             db: db.clone(),
             state_root: root,
             local_changes: HashMap::new(),
+            account_changes: HashMap::new(),
         }
     }
 
@@ -278,32 +477,118 @@ impl StateDB {
         if let Some(data) = self.local_changes.get(key) {
             return data.as_ref().map(|d| d.clone());
         }
-        let t = match TrieDB::new(&self.db, &self.state_root) {
+        self.trie_get(self.state_root, key)
+    }
+
+    /// Value of `key` in the trie of `root`, None if a node on the way is missing
+    fn trie_get(&self, root: Hash, key: &Hash) -> Option<Vec<u8>> {
+        if root == NULL_ROOT {
+            return None;
+        }
+        let t = match TrieDB::new(&self.db, &root) {
             Ok(trie) => trie,
-            Err(_) => return None,
+            Err(e) => {
+                self.db.report(&e);
+                return None;
+            }
         };
-        t.get(key.as_bytes()).expect("state get key")
+        t.get(key.as_bytes()).unwrap_or_else(|e| {
+            self.db.report(&e);
+            None
+        })
+    }
+
+    /// Every key and value of the state trie as of the last commit, in key order
+    pub fn entries(&self) -> Result<Vec<(Hash, Vec<u8>)>, String> {
+        let t = TrieDB::new(&self.db, &self.state_root).map_err(|e| format!("open state trie: {:?}", e))?;
+        let mut entries = Vec::new();
+        for item in t.iter().map_err(|e| format!("iterate state trie: {:?}", e))? {
+            let (key, value) = item.map_err(|e| format!("read state trie: {:?}", e))?;
+            entries.push((Hash::from_bytes(&key), value));
+        }
+        Ok(entries)
     }
 
     pub fn remove_storage(&mut self, key: Hash) {
         self.local_changes.insert(key, None);
     }
 
+    /// Root of the storage trie of `addr` as of the last commit, NULL_ROOT if it has none
+    pub fn account_storage_root(&self, addr: &Address) -> Hash {
+        match self.get_storage(&storage_root_key(addr)) {
+            Some(root) => Hash::from_bytes(&root),
+            None => NULL_ROOT,
+        }
+    }
+
+    pub fn set_account_storage(&mut self, addr: Address, key: Hash, value: &[u8]) {
+        self.account_changes.entry(addr).or_insert_with(HashMap::new).insert(key, Some(value.to_vec()));
+    }
+
+    pub fn remove_account_storage(&mut self, addr: Address, key: Hash) {
+        self.account_changes.entry(addr).or_insert_with(HashMap::new).insert(key, None);
+    }
+
+    pub fn get_account_storage(&self, addr: &Address, key: &Hash) -> Option<Vec<u8>> {
+        if let Some(data) = self.account_changes.get(addr).and_then(|changes| changes.get(key)) {
+            return data.clone();
+        }
+        self.trie_get(self.account_storage_root(addr), key)
+    }
+
+    /// Apply the changes since the last commit to the tries and write the new nodes. Changes
+    /// are applied in key order so that neighbouring keys share the walk down the trie, and
+    /// dropped once applied, later commits only touch what changed after them. Changes over
+    /// missing nodes are skipped and reported by `ArchiveDB::take_missing`.
     pub fn commit(&mut self) {
-        {
-            let mut t = TrieDBMut::from_existing(&mut self.db, &mut self.state_root).expect("open trie error");
-            for (key, data) in self.local_changes.iter() {
-                // delete keys with None value
-                if let Some(d) = data {
-                    t.insert(key.as_bytes(), &d).unwrap();
-                } else {
-                    t.remove(key.as_bytes()).unwrap();
-                }
+        // Account storage tries first, their roots go into the state trie
+        let mut changes: Vec<_> = self.account_changes.drain().collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        for (addr, entries) in changes {
+            let mut root = self.account_storage_root(&addr);
+            write_changes(&mut self.db, &mut root, sorted_changes(entries));
+            if root == NULL_ROOT {
+                self.local_changes.insert(storage_root_key(&addr), None);
+            } else {
+                self.local_changes.insert(storage_root_key(&addr), Some(root.to_slice().to_vec()));
             }
-
         }
+
+        let changes = sorted_changes(self.local_changes.drain());
+        write_changes(&mut self.db, &mut self.state_root, changes);
         self.db.commit();
     }
+
+    /// Node references committed since the last call, see `ArchiveDB::take_journal`
+    pub fn take_journal(&mut self) -> StateJournal {
+        self.db.take_journal()
+    }
+}
+
+/// Apply `changes` to the trie of `root`, deleting keys with a None value
+fn write_changes(db: &mut ArchiveDB, root: &mut Hash, changes: Vec<(Hash, Option<Vec<u8>>)>) {
+    let reporter = db.clone();
+    let mut t = match TrieDBMut::from_existing(db, root) {
+        Ok(trie) => trie,
+        Err(e) => return reporter.report(&e),
+    };
+    for (key, data) in changes {
+        let written = match data {
+            Some(d) => t.insert(key.as_bytes(), &d).map(|_| ()),
+            None => t.remove(key.as_bytes()).map(|_| ()),
+        };
+        if let Err(e) = written {
+            reporter.report(&e);
+        }
+    }
+}
+
+fn sorted_changes<I>(changes: I) -> Vec<(Hash, Option<Vec<u8>>)>
+    where I: IntoIterator<Item = (Hash, Option<Vec<u8>>)>
+{
+    let mut changes: Vec<_> = changes.into_iter().collect();
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    changes
 }
 
 #[cfg(test)]
@@ -313,7 +598,7 @@ mod tests {
     use map_store::{MemoryKV, KVDB};
     use hash_db::{EMPTY_PREFIX, HashDB};
     use trie_db::TrieMut;
-    use crate::types::Hash;
+    use crate::types::{Address, Hash};
     use crate::trie::{TrieDBMut, TrieDB, Blake2Hasher, EMPTY_TRIE};
     use super::{CachingDB, ArchiveDB, StateDB};
 
@@ -375,6 +660,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_nodes() {
+        let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+        let mut db = ArchiveDB::new(Arc::clone(&backend));
+        let foo = db.insert(EMPTY_PREFIX, b"foo");
+        let bar = db.insert(EMPTY_PREFIX, b"bar");
+        let baz = db.insert(EMPTY_PREFIX, b"bazz");
+        // Uncommitted nodes are not served
+        assert!(db.get_nodes(&[foo], 10, 1024).is_empty());
+        db.commit();
+
+        let keys = [foo, Hash::default(), bar, baz];
+        assert_eq!(db.get_nodes(&keys, 10, 1024), vec![b"foo".to_vec(), b"bar".to_vec(), b"bazz".to_vec()]);
+        assert_eq!(db.get_nodes(&keys, 2, 1024).len(), 2);
+        assert_eq!(db.get_nodes(&keys, 10, 7).len(), 2);
+    }
+
     #[test]
     fn test_triedb_reload() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -432,4 +734,97 @@ mod tests {
             assert_eq!(state.get_storage(&key_null).unwrap(), b"foo");
         }
     }
+
+    #[test]
+    fn test_commit_changes_once() {
+        let db = ArchiveDB::new(Arc::new(RwLock::new(MemoryKV::new())));
+        let mut state = StateDB::new(&db);
+        for i in 0..64u8 {
+            state.set_storage(Hash([i; 32]), &[i]);
+        }
+        state.set_account_storage(Address([1; 20]), Hash([1; 32]), b"bar");
+        state.commit();
+        let root = state.root();
+        assert!(state.local_changes.is_empty() && state.account_changes.is_empty());
+
+        // Nothing changed, nothing to apply
+        state.commit();
+        assert_eq!(state.root(), root);
+        state.remove_storage(Hash([3; 32]));
+        state.commit();
+        assert_ne!(state.root(), root);
+
+        let state = StateDB::from_existing(&db, state.root());
+        assert_eq!(state.get_storage(&Hash([2; 32])), Some(vec![2]));
+        assert_eq!(state.get_storage(&Hash([3; 32])), None);
+        assert_eq!(state.get_account_storage(&Address([1; 20]), &Hash([1; 32])), Some(b"bar".to_vec()));
+    }
+
+    #[test]
+    fn test_release() {
+        let mut db = ArchiveDB::new(Arc::new(RwLock::new(MemoryKV::new())));
+        db.set_counted(true);
+        let mut state = StateDB::new(&db);
+        for i in 0..16u8 {
+            state.set_storage(Hash([i; 32]), &[i; 40]);
+        }
+        state.commit();
+        let first = state.root();
+        let base = state.take_journal();
+        assert!(!base.inserted.is_empty() && base.removed.is_empty());
+
+        // A change made final and a competing one abandoned, both on the first state
+        state.set_storage(Hash([1; 32]), &[0xff; 40]);
+        state.commit();
+        let second = state.root();
+        let change = state.take_journal();
+        let mut other = StateDB::from_existing(&db, first);
+        other.set_storage(Hash([2; 32]), &[0xee; 40]);
+        other.commit();
+        let abandoned = other.take_journal();
+
+        assert!(db.release(&[(change, true), (abandoned, false)]).unwrap() > 0);
+        assert!(db.get_node(&first).is_none());
+        assert!(db.get_node(&other.root()).is_none());
+        let state = StateDB::from_existing(&db, second);
+        assert_eq!(state.get_storage(&Hash([1; 32])), Some(vec![0xff; 40]));
+        assert_eq!(state.get_storage(&Hash([2; 32])), Some(vec![2; 40]));
+    }
+
+    #[test]
+    fn test_entries() {
+        let db = ArchiveDB::new(Arc::new(RwLock::new(MemoryKV::new())));
+        let mut state = StateDB::new(&db);
+        state.set_storage(Hash([2; 32]), b"two");
+        state.set_storage(Hash([1; 32]), b"one");
+        state.commit();
+        assert_eq!(state.entries().unwrap(), vec![(Hash([1; 32]), b"one".to_vec()), (Hash([2; 32]), b"two".to_vec())]);
+    }
+
+    #[test]
+    fn test_missing_nodes() {
+        let db = ArchiveDB::new(Arc::new(RwLock::new(MemoryKV::new())));
+        let mut state = StateDB::new(&db);
+        for i in 0..16u8 {
+            state.set_storage(Hash([i; 32]), &[i; 40]);
+        }
+        state.commit();
+        let root = state.root();
+
+        let empty = ArchiveDB::new(Arc::new(RwLock::new(MemoryKV::new())));
+        assert_eq!(StateDB::from_existing(&empty, root).get_storage(&Hash([1; 32])), None);
+        assert_eq!(empty.take_missing(), vec![root]);
+        assert!(empty.take_missing().is_empty());
+
+        // Only the root node is there, the walk stops at its child
+        let mut partial = ArchiveDB::new(Arc::new(RwLock::new(MemoryKV::new())));
+        partial.insert(EMPTY_PREFIX, &db.get_node(&root).unwrap());
+        partial.commit();
+        let mut state = StateDB::from_existing(&partial, root);
+        assert_eq!(state.get_storage(&Hash([1; 32])), None);
+        state.set_storage(Hash([2; 32]), b"two");
+        state.commit();
+        let missing = partial.take_missing();
+        assert!(!missing.is_empty() && !missing.contains(&root));
+    }
 }
@@ -0,0 +1,341 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Download of a state trie from a trusted source, node by node.
+use std::collections::{HashMap, HashSet, VecDeque};
+use hash_db::{HashDB, EMPTY_PREFIX};
+use trie_db::NodeCodec;
+use trie_db::node::{NodePlan, NodeHandlePlan};
+use hash;
+use crate::state::ArchiveDB;
+use crate::trie::{BinNodeCodec, Blake2Hasher, NULL_ROOT};
+use crate::types::Hash;
+
+/// Fetches the trie nodes under a state root which are missing in the local state db.
+/// Account storage tries hang off the state trie as 32 byte leaf values, so every such
+/// value is requested too, but is not required to exist on the source.
+pub struct StateSync {
+    db: ArchiveDB,
+    /// Nodes still to fetch, with whether the source must have them
+    queue: VecDeque<(Hash, bool)>,
+    /// Nodes of the last batch, in request order
+    in_flight: Vec<(Hash, bool)>,
+    /// Nodes queued once, shared subtrees are walked a single time
+    seen: HashSet<Hash>,
+    imported: usize,
+}
+
+impl StateSync {
+    pub fn new(db: &ArchiveDB, root: Hash) -> Self {
+        let mut sync = StateSync {
+            db: db.clone(),
+            queue: VecDeque::new(),
+            in_flight: Vec::new(),
+            seen: HashSet::new(),
+            imported: 0,
+        };
+        sync.enqueue(root, true);
+        sync
+    }
+
+    /// Number of nodes written so far
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Hashes of the next nodes to request, at most `max`. Nodes already in the local db are
+    /// walked instead, so an interrupted download resumes where it stopped.
+    pub fn next_batch(&mut self, max: usize) -> Vec<Hash> {
+        while self.in_flight.len() < max {
+            let (key, required) = match self.queue.pop_front() {
+                Some(item) => item,
+                None => break,
+            };
+            match self.db.get_node(&key) {
+                Some(node) => {
+                    // Walk failures were caught when the node was imported
+                    let _ = self.walk(&node);
+                }
+                None => self.in_flight.push((key, required)),
+            }
+        }
+        self.in_flight.iter().map(|(key, _)| *key).collect()
+    }
+
+    /// Import the answer to the last batch, `nodes[i]` being the node of the i-th hash or
+    /// None if the source does not know it. Hashes past the end of `nodes` are requested again.
+    pub fn import(&mut self, nodes: Vec<Option<Vec<u8>>>) -> Result<(), String> {
+        if nodes.len() > self.in_flight.len() {
+            return Err(format!("got {} nodes for {} requested", nodes.len(), self.in_flight.len()));
+        }
+        let requested: Vec<_> = self.in_flight.drain(..).collect();
+        for (i, (key, required)) in requested.iter().enumerate() {
+            match nodes.get(i) {
+                Some(Some(node)) => {
+                    if Hash(hash::blake2b_256(node)) != *key {
+                        return Err(format!("node {} does not match its hash", key));
+                    }
+                    self.walk(node)?;
+                    self.db.insert(EMPTY_PREFIX, node);
+                    self.imported += 1;
+                }
+                Some(None) => {
+                    if *required {
+                        return Err(format!("source is missing node {}", key));
+                    }
+                }
+                None => self.queue.push_front((*key, *required)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Import the nodes a peer returned for the last batch, in any order and skipping the ones
+    /// it doesn't have, like the `StateNodes` protocol does. Nodes not returned are requested
+    /// again. Returns the number of nodes imported. A bad answer imports nothing and leaves the
+    /// batch to ask elsewhere.
+    pub fn import_found(&mut self, nodes: Vec<Vec<u8>>) -> Result<usize, String> {
+        let mut found: HashMap<Hash, Vec<u8>> = nodes.into_iter()
+            .map(|node| (Hash(hash::blake2b_256(&node)), node))
+            .collect();
+        if let Some(key) = found.keys().find(|key| !self.in_flight.iter().any(|(k, _)| k == *key)) {
+            return Err(format!("node {} was not requested", key));
+        }
+        let mut children = Vec::new();
+        for node in found.values() {
+            node_children(node, &mut children)?;
+        }
+
+        let requested: Vec<_> = self.in_flight.drain(..).collect();
+        let mut imported = 0;
+        for (key, required) in requested.into_iter().rev() {
+            match found.remove(&key) {
+                Some(node) => {
+                    self.db.insert(EMPTY_PREFIX, &node);
+                    imported += 1;
+                }
+                None => self.queue.push_front((key, required)),
+            }
+        }
+        for (key, required) in children {
+            self.enqueue(key, required);
+        }
+        self.imported += imported;
+        Ok(imported)
+    }
+
+    /// Write the imported nodes to the backend
+    pub fn commit(&mut self) {
+        self.db.commit();
+    }
+
+    fn enqueue(&mut self, key: Hash, required: bool) {
+        if key != NULL_ROOT && self.seen.insert(key) {
+            self.queue.push_back((key, required));
+        }
+    }
+
+    /// Queue the children of an encoded node
+    fn walk(&mut self, node: &[u8]) -> Result<(), String> {
+        let mut children = Vec::new();
+        node_children(node, &mut children)?;
+        for (key, required) in children {
+            self.enqueue(key, required);
+        }
+        Ok(())
+    }
+}
+
+/// Every node of the state trie under `root` in `db` along with the account storage tries,
+/// each once. Fails when a node of the state trie is missing.
+pub fn collect_nodes(db: &ArchiveDB, root: Hash) -> Result<Vec<Vec<u8>>, String> {
+    let mut nodes = Vec::new();
+    visit_nodes(db, root, &mut HashSet::new(), |_, node| nodes.push(node))?;
+    Ok(nodes)
+}
+
+/// Hashes of the nodes under each of `roots`, the nodes pruning must keep
+pub fn reachable_nodes(db: &ArchiveDB, roots: &[Hash]) -> Result<HashSet<Hash>, String> {
+    let mut seen = HashSet::new();
+    let mut nodes = HashSet::new();
+    for root in roots {
+        visit_nodes(db, *root, &mut seen, |key, _| { nodes.insert(key); })?;
+    }
+    Ok(nodes)
+}
+
+/// Call `visit` with every node under `root` not in `seen` yet, adding them to it
+fn visit_nodes<F>(db: &ArchiveDB, root: Hash, seen: &mut HashSet<Hash>, mut visit: F) -> Result<(), String>
+    where F: FnMut(Hash, Vec<u8>)
+{
+    let mut queue = VecDeque::new();
+    queue.push_back((root, true));
+    while let Some((key, required)) = queue.pop_front() {
+        if key == NULL_ROOT || !seen.insert(key) {
+            continue;
+        }
+        let node = match db.get_node(&key) {
+            Some(node) => node,
+            None if required => return Err(format!("missing state node {}", key)),
+            None => continue,
+        };
+        let mut children = Vec::new();
+        node_children(&node, &mut children)?;
+        queue.extend(children);
+        visit(key, node);
+    }
+    Ok(())
+}
+
+/// Hashes referenced by an encoded node, with whether they must be trie nodes. Inline
+/// children are walked in place.
+fn node_children(node: &[u8], out: &mut Vec<(Hash, bool)>) -> Result<(), String> {
+    let plan = <BinNodeCodec<Blake2Hasher> as NodeCodec>::decode_plan(node)
+        .map_err(|e| format!("invalid trie node {:?}", e))?;
+    let (value, children) = match plan {
+        NodePlan::Empty => (None, Vec::new()),
+        NodePlan::Leaf { value, .. } => (Some(value), Vec::new()),
+        NodePlan::Extension { child, .. } => (None, vec![child]),
+        NodePlan::Branch { value, children } |
+        NodePlan::NibbledBranch { value, children, .. } => {
+            (value, children.iter().cloned().filter_map(|c| c).collect())
+        }
+    };
+
+    if let Some(range) = value {
+        if range.len() == 32 {
+            out.push((Hash::from(&node[range]), false));
+        }
+    }
+    for child in children {
+        match child {
+            NodeHandlePlan::Hash(range) => out.push((Hash::from(&node[range]), true)),
+            NodeHandlePlan::Inline(range) => node_children(&node[range], out)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hash_db::{HashDB, EMPTY_PREFIX};
+    use super::{collect_nodes, StateSync};
+    use crate::state::{ArchiveDB, StateDB};
+    use crate::test_utils::memory_backend;
+    use crate::types::{Address, Hash};
+
+    fn source_state() -> (ArchiveDB, Hash) {
+        let db = memory_backend();
+        let mut state = StateDB::new(&db);
+        for i in 0..64u8 {
+            state.set_storage(Hash([i; 32]), &[i; 40]);
+        }
+        state.set_account_storage(Address([1u8; 20]), Hash([2u8; 32]), b"value");
+        state.commit();
+        let root = state.root();
+        (db, root)
+    }
+
+    fn serve(source: &ArchiveDB, keys: &[Hash], max: usize) -> Vec<Option<Vec<u8>>> {
+        keys.iter().take(max).map(|key| source.get_node(key)).collect()
+    }
+
+    #[test]
+    fn download_state() {
+        let (source, root) = source_state();
+        let local = memory_backend();
+        let mut sync = StateSync::new(&local, root);
+        while !sync.is_done() {
+            let batch = sync.next_batch(16);
+            // Truncated answers are requested again
+            sync.import(serve(&source, &batch, 10)).unwrap();
+        }
+        sync.commit();
+
+        let state = StateDB::from_existing(&local, root);
+        assert_eq!(state.get_storage(&Hash([5u8; 32])), Some(vec![5u8; 40]));
+        assert_eq!(state.get_account_storage(&Address([1u8; 20]), &Hash([2u8; 32])), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn collect_state() {
+        let (source, root) = source_state();
+        let nodes = collect_nodes(&source, root).unwrap();
+        let local = memory_backend();
+        let mut sync = StateSync::new(&local, root);
+        while !sync.is_done() {
+            let batch = sync.next_batch(16);
+            let answer = batch.iter()
+                .map(|key| nodes.iter().find(|n| Hash(hash::blake2b_256(n)) == *key).cloned())
+                .collect();
+            sync.import(answer).unwrap();
+        }
+        assert_eq!(sync.imported(), nodes.len());
+
+        assert!(collect_nodes(&memory_backend(), root).is_err());
+    }
+
+    #[test]
+    fn heal_missing_nodes() {
+        let (source, root) = source_state();
+        let local = memory_backend();
+        // The root is already there, only the nodes under it are fetched
+        let mut partial = local.clone();
+        partial.insert(EMPTY_PREFIX, &source.get_node(&root).unwrap());
+        partial.commit();
+
+        let mut sync = StateSync::new(&local, root);
+        let batch = sync.next_batch(16);
+        assert!(batch.len() > 1 && !batch.contains(&root));
+        // The source skips what it doesn't know and returns the rest in any order
+        let mut nodes: Vec<Vec<u8>> = batch.iter().skip(1).filter_map(|key| source.get_node(key)).collect();
+        nodes.reverse();
+        assert_eq!(sync.import_found(nodes).unwrap(), batch.len() - 1);
+        assert_eq!(sync.next_batch(1), vec![batch[0]]);
+        while !sync.is_done() {
+            let batch = sync.next_batch(16);
+            sync.import_found(batch.iter().filter_map(|key| source.get_node(key)).collect()).unwrap();
+        }
+        sync.commit();
+        assert_eq!(StateDB::from_existing(&local, root).get_storage(&Hash([5u8; 32])), Some(vec![5u8; 40]));
+
+        let mut sync = StateSync::new(&memory_backend(), root);
+        let batch = sync.next_batch(16);
+        assert!(sync.import_found(vec![b"unrequested".to_vec()]).is_err());
+        // The batch survives a bad answer
+        assert_eq!(sync.next_batch(16), batch);
+        assert_eq!(sync.import_found(vec![source.get_node(&root).unwrap()]), Ok(1));
+    }
+
+    #[test]
+    fn reject_bad_node() {
+        let (source, root) = source_state();
+        let mut sync = StateSync::new(&memory_backend(), root);
+        let batch = sync.next_batch(16);
+        let mut nodes = serve(&source, &batch, 16);
+        nodes[0].as_mut().unwrap().push(0);
+        assert!(sync.import(nodes).is_err());
+
+        let mut sync = StateSync::new(&memory_backend(), root);
+        let batch = sync.next_batch(16);
+        assert!(sync.import(vec![None; batch.len()]).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deterministic fixtures for building keys, transactions and chains in memory.
+//!
+//! Available to the crate's own tests and to other crates through the `test-utils` feature.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+use ed25519::privkey::PrivKey;
+use map_store::{KVDB, MemoryKV};
+use crate::block::{self, Block, BlockProof, Header, VRFProof, VerificationItem};
+use crate::genesis;
+use crate::chain_spec::ChainSpec;
+use crate::state::{ArchiveDB, StateDB};
+use crate::transaction::{balance_msg, Transaction};
+use crate::trie::NULL_ROOT;
+use crate::types::{Address, AddressScheme, Hash};
+
+/// Seconds between two blocks built by `BlockBuilder`.
+pub const BLOCK_INTERVAL: u64 = genesis::SLOT_DURATION;
+
+/// Returns a private key derived from `seed`, the same seed always gives the same key.
+pub fn test_key(seed: u8) -> PrivKey {
+    PrivKey::from_bytes(&hash::blake2b_256(&[seed]))
+}
+
+/// Returns the address of the key derived from `seed`.
+pub fn test_address(seed: u8) -> Address {
+    address_of(&test_key(seed))
+}
+
+/// Returns the address of `key` under the default scheme of the test chains.
+pub fn address_of(key: &PrivKey) -> Address {
+    Address::from_pubkey(&key.to_pubkey().unwrap(), AddressScheme::default())
+}
+
+/// Key holding the genesis allocation.
+pub fn genesis_key() -> PrivKey {
+    PrivKey::from_bytes(&genesis::ed_genesis_priv_key)
+}
+
+/// Returns a signed `balance.transfer` transaction.
+pub fn transfer(key: &PrivKey, nonce: u64, to: Address, value: u128) -> Transaction {
+    let input = bincode::serialize(&balance_msg::MsgTransfer {
+        receiver: to,
+        value: value,
+    }).unwrap();
+    let sender = address_of(key);
+    let mut tx = Transaction::new(sender, nonce, 1000, 1000, b"balance.transfer".to_vec(), input);
+    tx.sign(&key.to_bytes()).unwrap();
+    tx
+}
+
+/// Returns an in-memory state backend.
+pub fn memory_backend() -> ArchiveDB {
+    let backend: Arc<RwLock<dyn KVDB>> = Arc::new(RwLock::new(MemoryKV::new()));
+    ArchiveDB::new(backend)
+}
+
+/// Returns the genesis block along with an in-memory state holding the genesis allocation.
+pub fn genesis_with_state() -> (Block, ArchiveDB) {
+    let db = memory_backend();
+    let state = Rc::new(RefCell::new(StateDB::from_existing(&db, NULL_ROOT)));
+    let spec = ChainSpec::default().genesis;
+    let mut genesis = genesis::to_genesis(&spec);
+    genesis.set_state_root(genesis::setup_allocation(state, &spec));
+    (genesis, db)
+}
+
+/// Builds a block on top of a parent block.
+pub struct BlockBuilder {
+    header: Header,
+    txs: Vec<Transaction>,
+    signer: Option<PrivKey>,
+}
+
+impl BlockBuilder {
+    /// Child of `parent` with the parent's state root, one slot and `BLOCK_INTERVAL` later.
+    pub fn on(parent: &Block) -> Self {
+        let header = Header {
+            height: parent.height() + 1,
+            parent_hash: parent.hash(),
+            slot: parent.header.slot + 1,
+            state_root: parent.state_root(),
+            time: parent.header.time + BLOCK_INTERVAL,
+            ..Default::default()
+        };
+        BlockBuilder {
+            header,
+            txs: Vec::new(),
+            signer: None,
+        }
+    }
+
+    pub fn slot(mut self, slot: u64) -> Self {
+        self.header.slot = slot;
+        self
+    }
+
+    pub fn time(mut self, time: u64) -> Self {
+        self.header.time = time;
+        self
+    }
+
+    pub fn state_root(mut self, root: Hash) -> Self {
+        self.header.state_root = root;
+        self
+    }
+
+    /// Set a VRF output and proof stub derived from `seed`.
+    pub fn vrf(mut self, seed: u8) -> Self {
+        self.header.vrf_output = hash::blake2b_256(&[seed]);
+        let mut proof = [0u8; 64];
+        proof[..32].copy_from_slice(&hash::blake2b_256(&[seed, seed]));
+        self.header.vrf_proof = VRFProof::new(proof);
+        self
+    }
+
+    /// Set the VRF output and proof of the proposer.
+    pub fn vrf_output(mut self, value: [u8; 32], proof: [u8; 64]) -> Self {
+        self.header.vrf_output = value;
+        self.header.vrf_proof = VRFProof::new(proof);
+        self
+    }
+
+    pub fn txs(mut self, txs: Vec<Transaction>) -> Self {
+        self.txs = txs;
+        self
+    }
+
+    /// Sign the block the same way the proposer does.
+    pub fn signed_by(mut self, key: PrivKey) -> Self {
+        self.signer = Some(key);
+        self
+    }
+
+    pub fn build(self) -> Block {
+        let mut b = Block::new(self.header, self.txs, Vec::new(), Vec::new());
+        if let Some(key) = self.signer {
+            let h = b.hash();
+            let signs = key.sign(h.to_slice()).unwrap();
+            b.add_proof(BlockProof::new(0, &key.to_pubkey().unwrap().to_bytes()));
+            b.add_verify_item(VerificationItem::new(h, signs));
+            b.set_sign_hash(block::get_hash_from_signs(b.get_signs()));
+        }
+        b
+    }
+}
+
+/// Computes the post state root of a block applied on the given parent root.
+pub type StateFn = Box<dyn FnMut(Hash, &Block) -> Hash>;
+
+/// Builds a linear chain of blocks, and forks of it.
+pub struct ChainBuilder {
+    blocks: Vec<Block>,
+    state_fn: Option<StateFn>,
+}
+
+impl ChainBuilder {
+    pub fn new(genesis: Block) -> Self {
+        ChainBuilder {
+            blocks: vec![genesis],
+            state_fn: None,
+        }
+    }
+
+    /// Compute state roots of blocks with transactions, empty blocks keep the parent root.
+    pub fn with_state_fn(mut self, f: StateFn) -> Self {
+        self.state_fn = Some(f);
+        self
+    }
+
+    pub fn head(&self) -> &Block {
+        self.blocks.last().unwrap()
+    }
+
+    /// All blocks including genesis, ordered by height.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Blocks after genesis, ordered by height.
+    pub fn descendants(&self) -> &[Block] {
+        &self.blocks[1..]
+    }
+
+    /// Append a block with the given transactions.
+    pub fn push_txs(&mut self, txs: Vec<Transaction>) -> &Block {
+        let parent = self.head().clone();
+        let mut b = BlockBuilder::on(&parent).txs(txs).build();
+        if let Some(f) = self.state_fn.as_mut() {
+            if !b.txs.is_empty() {
+                let root = f(parent.state_root(), &b);
+                b.set_state_root(root);
+            }
+        }
+        self.blocks.push(b);
+        self.head()
+    }
+
+    /// Append `n` empty blocks.
+    pub fn extend(mut self, n: u64) -> Self {
+        for _ in 0..n {
+            self.push_txs(Vec::new());
+        }
+        self
+    }
+
+    /// Start a branch sharing blocks up to `height`. Its blocks use a different slot from
+    /// this chain so the two branches never share a hash.
+    pub fn fork_at(&self, height: u64) -> ChainBuilder {
+        let mut blocks = self.blocks[..=height as usize].to_vec();
+        let parent = blocks.last().unwrap().clone();
+        blocks.push(BlockBuilder::on(&parent)
+            .slot(parent.header.slot + 2)
+            .time(parent.header.time + 2 * BLOCK_INTERVAL)
+            .build());
+        ChainBuilder {
+            blocks,
+            state_fn: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_linear_chain() {
+        let (genesis, _) = genesis_with_state();
+        let chain = ChainBuilder::new(genesis.clone()).extend(3);
+
+        assert_eq!(chain.blocks().len(), 4);
+        assert_eq!(chain.head().height(), 3);
+        for pair in chain.blocks().windows(2) {
+            assert_eq!(pair[1].header.parent_hash, pair[0].hash());
+            assert!(pair[1].header.time > pair[0].header.time);
+        }
+    }
+
+    #[test]
+    fn build_fork() {
+        let (genesis, _) = genesis_with_state();
+        let main = ChainBuilder::new(genesis).extend(3);
+        let fork = main.fork_at(1).extend(2);
+
+        assert_eq!(fork.head().height(), 4);
+        assert_eq!(fork.blocks()[1], main.blocks()[1]);
+        assert_ne!(fork.blocks()[2].hash(), main.blocks()[2].hash());
+    }
+
+    #[test]
+    fn signed_block() {
+        let (genesis, _) = genesis_with_state();
+        let b = BlockBuilder::on(&genesis).signed_by(genesis_key()).build();
+        assert_eq!(b.header.sign_root, block::get_hash_from_signs(b.get_signs()));
+        assert!(b.proof_one().is_some());
+    }
+
+    #[test]
+    fn deterministic_keys() {
+        assert_eq!(test_address(1), test_address(1));
+        assert_ne!(test_address(1), test_address(2));
+        let tx = transfer(&test_key(1), 1, test_address(2), 10);
+        assert!(tx.verify_sign().is_ok());
+    }
+}
@@ -4,16 +4,55 @@ extern crate errors;
 #[allow(unused_imports)]
 use errors::{Error,InternalErrorKind};
 
-use super::types::{Address};
+use super::types::{Address, AddressScheme};
 use ed25519::{signature::SignatureInfo, privkey::PrivKey, pubkey::Pubkey};
 use serde::{Deserialize, Serialize};
 use bincode;
 
 use super::types::{Hash, CHAIN_ID};
+use super::chain_spec::Rules;
 
 /// Message call identifer length
 pub const MSGID_LENGTH: usize = 4;
 
+/// Message call of a balance transfer
+pub const TRANSFER_METHOD: &[u8] = b"balance.transfer";
+/// Message call prefix of the staking module
+pub const STAKING_MODULE: &[u8] = b"staking.";
+pub const STORAGE_MODULE: &[u8] = b"storage.";
+/// Message call prefix reserved for the governance module
+pub const GOVERNANCE_MODULE: &[u8] = b"governance.";
+
+/// Kind of action a transaction signature authorizes. From the `signing_domains` fork on its byte
+/// starts the signed pre-image, so signers can tell the kind of call from the first byte. The call
+/// is hashed along with the other fields either way, the byte adds no replay protection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxDomain {
+	Transfer = 1,
+	Staking = 2,
+	Storage = 3,
+	Governance = 4,
+	/// Calls of no known module
+	Other = 0xff,
+}
+
+impl TxDomain {
+	/// Domain of the message call `call`
+	pub fn of_call(call: &[u8]) -> Self {
+		if call == TRANSFER_METHOD {
+			TxDomain::Transfer
+		} else if call.starts_with(STAKING_MODULE) {
+			TxDomain::Staking
+		} else if call.starts_with(STORAGE_MODULE) {
+			TxDomain::Storage
+		} else if call.starts_with(GOVERNANCE_MODULE) {
+			TxDomain::Governance
+		} else {
+			TxDomain::Other
+		}
+	}
+}
+
 /// Represents a transaction
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Hash, Deserialize)]
 pub struct Transaction {
@@ -54,6 +93,24 @@ pub mod staking_msg {
     }
 }
 
+pub mod storage_msg {
+    use serde::{Deserialize, Serialize};
+
+    /// Write `value` at `key` in the sender's storage
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct MsgStorageSet {
+        pub key: Vec<u8>,
+        pub value: Vec<u8>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct MsgStorageRemove {
+        pub key: Vec<u8>,
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct TxHashType {
 	chainid: 	u32,
@@ -95,9 +152,9 @@ impl Transaction {
 		self.gas_price
 	}
 
+	/// Transferred value, zero for module calls other than transfers
 	pub fn get_value(&self) -> u128 {
-        let input: balance_msg::MsgTransfer = bincode::deserialize(&self.data).unwrap();
-        input.value
+        self.transfer_msg().map_or(0, |msg| msg.value)
 	}
 	pub fn get_sign_data(&self) -> SignatureInfo {
 		SignatureInfo::make(self.sign_data.0,self.sign_data.1,self.sign_data.2)
@@ -116,26 +173,92 @@ impl Transaction {
         }
     }
 
+	/// Build an unsigned `balance.transfer` transaction
+	pub fn transfer(sender: Address, nonce: u64, gas_price: u64, gas: u64,
+		to: Address, value: u128) -> Transaction {
+		let input = bincode::serialize(&balance_msg::MsgTransfer {
+			receiver: to,
+			value: value,
+		}).unwrap();
+		Transaction::new(sender, nonce, gas_price, gas, TRANSFER_METHOD.to_vec(), input)
+	}
+
+	/// Decode the transfer message, None if the transaction is not a transfer
+	pub fn transfer_msg(&self) -> Option<balance_msg::MsgTransfer> {
+		if self.call.as_slice() != TRANSFER_METHOD {
+			return None;
+		}
+		bincode::deserialize(&self.data).ok()
+	}
+
+	/// Canonical binary encoding, the format transactions are gossiped and stored in
+	pub fn encode(&self) -> Vec<u8> {
+		bincode::serialize(self).unwrap()
+	}
+
+	pub fn decode(data: &[u8]) -> Result<Transaction, Error> {
+		bincode::deserialize(data).map_err(|e| InternalErrorKind::Other(e.to_string()).into())
+	}
+
 	pub fn hash(&self) -> Hash {
 		let data = TxHashType::new(self);
 		let encoded: Vec<u8> = bincode::serialize(&data).unwrap();
         Hash(hash::blake2b_256(encoded))
 	}
+	/// Domain of the transaction call
+	pub fn domain(&self) -> TxDomain {
+		TxDomain::of_call(&self.call)
+	}
+
+	/// Message signed by the sender from the `signing_domains` fork on, the hash of the domain
+	/// byte followed by the hashed fields
+	pub fn signing_hash(&self, domain: TxDomain) -> Hash {
+		let mut pre_image = vec![domain as u8];
+		pre_image.extend(bincode::serialize(&TxHashType::new(self)).unwrap());
+		Hash(hash::blake2b_256(pre_image))
+	}
 	fn set_sign_data(&mut self,data: &SignatureInfo) {
 		self.sign_data.0[..].copy_from_slice(data.r());
 		self.sign_data.1[..].copy_from_slice(data.s());
 		self.sign_data.2[..].copy_from_slice(data.p());
 	}
+	/// Message signed by the sender under `rules`
+	fn message_at(&self, rules: &Rules) -> Hash {
+		if rules.signing_domains {
+			self.signing_hash(self.domain())
+		} else {
+			self.hash()
+		}
+	}
+	/// Sign the transaction and set the sender to the address of the signing key under `scheme`
+	pub fn sign_with(&mut self, key: &PrivKey, scheme: AddressScheme) -> Result<(),Error> {
+		self.sender = Address::from_pubkey(&key.to_pubkey()?, scheme);
+		self.sign(&key.to_bytes())
+	}
+	/// Sign the hash of the transaction, the message of blocks before the `signing_domains` fork
 	pub fn sign(&mut self,priv_data: &[u8]) -> Result<(),Error> {
-		let h = self.hash();
+		self.sign_at(priv_data, &Rules::default())
+	}
+	/// Sign the message of a block executed under `rules`
+	pub fn sign_at(&mut self, priv_data: &[u8], rules: &Rules) -> Result<(),Error> {
+		let h = self.message_at(rules);
 		let priv_key = PrivKey::from_bytes(priv_data);
 		let data = priv_key.sign(h.to_slice())?;
 		self.set_sign_data(&data);
 		Ok(())
 	}
 	pub fn verify_sign(&self) -> Result<(),Error> {
+		self.verify_sign_at(&Rules::default())
+	}
+	/// Check the signature against the message of a block executed under `rules`
+	pub fn verify_sign_at(&self, rules: &Rules) -> Result<(),Error> {
+		let pk = Pubkey::from_bytes(&self.sign_data.2[..]);
+		pk.verify(&self.message_at(rules).to_msg(), &self.get_sign_data())
+	}
+	/// Check the signature was given for a message of `domain`
+	pub fn verify_sign_in(&self, domain: TxDomain) -> Result<(),Error> {
 		let pk = Pubkey::from_bytes(&self.sign_data.2[..]);
-		pk.verify(&self.hash().to_msg(), &self.get_sign_data())
+		pk.verify(&self.signing_hash(domain).to_msg(), &self.get_sign_data())
 	}
 }
 
@@ -154,4 +277,44 @@ mod tests {
         let tx: balance_msg::MsgTransfer = bincode::deserialize(&encoded).unwrap();
         assert_eq!(tx.value, 1);
     }
+
+    #[test]
+    fn encode_signed_transfer() {
+        let key = PrivKey::from_bytes(&crate::genesis::ed_genesis_priv_key);
+        let to = Address::from_low_u64_be(1);
+        let mut tx = Transaction::transfer(Address::default(), 1, 1000, 1000, to, 10);
+        tx.sign_with(&key, AddressScheme::default()).unwrap();
+        assert_eq!(tx.sender, Address::from_pubkey(&key.to_pubkey().unwrap(), AddressScheme::default()));
+        assert!(tx.verify_sign().is_ok());
+
+        let decoded = Transaction::decode(&tx.encode()).unwrap();
+        assert_eq!(decoded, tx);
+        assert_eq!(decoded.hash(), tx.hash());
+        let msg = decoded.transfer_msg().unwrap();
+        assert_eq!((msg.receiver, msg.value), (to, 10));
+        assert!(Transaction::decode(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn signature_bound_to_domain() {
+        let key = PrivKey::from_bytes(&crate::genesis::ed_genesis_priv_key);
+        let forked = Rules { signing_domains: true, ..Rules::default() };
+        let mut tx = Transaction::transfer(Address::from_pubkey(&key.to_pubkey().unwrap(), AddressScheme::default()), 1, 1000, 1000, Address::from_low_u64_be(1), 10);
+        tx.sign_at(&key.to_bytes(), &forked).unwrap();
+        assert_eq!(tx.domain(), TxDomain::Transfer);
+        assert!(tx.verify_sign_in(TxDomain::Transfer).is_ok());
+        assert!(tx.verify_sign_in(TxDomain::Staking).is_err());
+        assert!(tx.verify_sign_at(&forked).is_ok());
+        // Blocks before the fork keep verifying the hash of the transaction
+        assert!(tx.verify_sign().is_err());
+        tx.sign(&key.to_bytes()).unwrap();
+        assert!(tx.verify_sign().is_ok());
+        assert!(tx.verify_sign_at(&forked).is_err());
+        assert_ne!(tx.signing_hash(TxDomain::Transfer), tx.signing_hash(TxDomain::Governance));
+
+        assert_eq!(TxDomain::of_call(b"staking.deposit"), TxDomain::Staking);
+        assert_eq!(TxDomain::of_call(b"storage.set"), TxDomain::Storage);
+        assert_eq!(TxDomain::of_call(b"governance.vote"), TxDomain::Governance);
+        assert_eq!(TxDomain::of_call(b"balance.mint"), TxDomain::Other);
+    }
 }
\ No newline at end of file
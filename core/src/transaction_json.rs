@@ -0,0 +1,162 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hex encoded JSON form of a transaction, as accepted and returned by the RPC.
+use std::convert::TryFrom;
+
+use errors::{Error, InternalErrorKind};
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::Transaction;
+use crate::types::{Address, Hash};
+
+/// Ed25519 signature of the transaction hash along with the signing public key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignatureJson {
+    pub r: String,
+    pub s: String,
+    pub pubkey: String,
+}
+
+/// Transaction with binary fields as 0x-prefixed hex strings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionJson {
+    /// Transaction hash, checked against the other fields when decoding.
+    pub hash: Hash,
+    pub from: Address,
+    pub nonce: u64,
+    pub gas_price: u64,
+    pub gas: u64,
+    /// Message call, e.g. `balance.transfer`.
+    pub call: String,
+    pub data: String,
+    /// Receiver and amount of a `balance.transfer`, informational only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<Address>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u128>,
+    pub signature: SignatureJson,
+}
+
+fn to_hex(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+fn from_hex(field: &str, text: &str) -> Result<Vec<u8>, Error> {
+    let raw = if text.starts_with("0x") || text.starts_with("0X") {
+        &text[2..]
+    } else {
+        text
+    };
+    hex::decode(raw).map_err(|e| InternalErrorKind::Other(format!("invalid {}: {}", field, e)).into())
+}
+
+fn to_array(field: &str, text: &str) -> Result<[u8; 32], Error> {
+    let data = from_hex(field, text)?;
+    if data.len() != 32 {
+        return Err(InternalErrorKind::Other(format!("invalid {} length {}", field, data.len())).into());
+    }
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&data);
+    Ok(out)
+}
+
+impl From<&Transaction> for TransactionJson {
+    fn from(tx: &Transaction) -> Self {
+        let transfer = tx.transfer_msg();
+        TransactionJson {
+            hash: tx.hash(),
+            from: tx.sender,
+            nonce: tx.nonce,
+            gas_price: tx.gas_price,
+            gas: tx.gas,
+            call: String::from_utf8_lossy(&tx.call).into_owned(),
+            data: to_hex(&tx.data),
+            to: transfer.as_ref().map(|msg| msg.receiver),
+            value: transfer.map(|msg| msg.value),
+            signature: SignatureJson {
+                r: to_hex(&tx.sign_data.0),
+                s: to_hex(&tx.sign_data.1),
+                pubkey: to_hex(&tx.sign_data.2),
+            },
+        }
+    }
+}
+
+impl TryFrom<TransactionJson> for Transaction {
+    type Error = Error;
+
+    fn try_from(json: TransactionJson) -> Result<Self, Self::Error> {
+        let tx = Transaction {
+            sender: json.from,
+            nonce: json.nonce,
+            gas_price: json.gas_price,
+            gas: json.gas,
+            call: json.call.into_bytes(),
+            data: from_hex("data", &json.data)?,
+            sign_data: (
+                to_array("signature r", &json.signature.r)?,
+                to_array("signature s", &json.signature.s)?,
+                to_array("signature pubkey", &json.signature.pubkey)?,
+            ),
+        };
+        if tx.hash() != json.hash {
+            return Err(InternalErrorKind::Other(format!("hash mismatch, expect {}", tx.hash())).into());
+        }
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519::privkey::PrivKey;
+    use crate::genesis;
+    use crate::types::AddressScheme;
+
+    fn signed_transfer() -> Transaction {
+        let key = PrivKey::from_bytes(&genesis::ed_genesis_priv_key);
+        let mut tx = Transaction::transfer(Address::default(), 1, 1000, 1000, Address::from_low_u64_be(7), 100);
+        tx.sign_with(&key, AddressScheme::default()).unwrap();
+        tx
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let tx = signed_transfer();
+        let json = TransactionJson::from(&tx);
+        assert_eq!(json.call, "balance.transfer");
+        assert_eq!(json.value, Some(100));
+        assert_eq!(json.to, Some(Address::from_low_u64_be(7)));
+
+        let decoded = Transaction::try_from(json).unwrap();
+        assert_eq!(decoded, tx);
+        assert!(decoded.verify_sign().is_ok());
+    }
+
+    #[test]
+    fn json_rejects_tampering() {
+        let mut json = TransactionJson::from(&signed_transfer());
+        json.nonce += 1;
+        assert!(Transaction::try_from(json).is_err());
+
+        let signer = PrivKey::from_bytes(&genesis::ed_genesis_priv_key).to_pubkey().unwrap();
+        let mut json = serde_json::to_value(TransactionJson::from(&signed_transfer())).unwrap();
+        assert_eq!(json["from"], serde_json::json!(format!("{}", Address::from_pubkey(&signer, AddressScheme::default()))));
+        json["from"] = serde_json::json!("0x1234");
+        assert!(serde_json::from_value::<TransactionJson>(json).is_err());
+    }
+}
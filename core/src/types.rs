@@ -16,7 +16,9 @@
 
 use std::fmt;
 use std::io::Cursor;
+use std::str::FromStr;
 use serde::{Serialize, Deserialize,Deserializer, Serializer};
+use serde::de::Error as _;
 use hex;
 pub use hex::FromHexError as HexError;
 use ed25519::Message;
@@ -60,14 +62,11 @@ impl Hash {
         h
     }
 
+    /// Parse 64 hex digits, with or without 0x prefix
     pub fn from_hex(text: &str) -> Result<Self, HexError> {
-        let mut from = text;
-        if text.starts_with("0x") || text.starts_with("0X") {
-            from = &text[2..];
-        }
-        let b = hex::decode(from)?;
-
-        Ok(Hash::from_bytes(&b))
+        let mut h = Self::default();
+        hex::decode_to_slice(strip_hex_prefix(text), &mut h.0)?;
+        Ok(h)
     }
     pub fn make_hash(data: &[u8]) -> Self {
         Hash(hash::blake2b_256(data))
@@ -137,6 +136,15 @@ impl From<&[u8]> for Hash {
     }
 }
 
+impl FromStr for Hash {
+    type Err = HexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Hash::from_hex(s)
+    }
+}
+
+/// The hex string is also the binary encoding, stored blocks and the wire format depend on it
 impl Serialize for Hash {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
@@ -151,12 +159,18 @@ impl<'a> Deserialize<'a> for Hash {
         where D: Deserializer<'a>
     {
         let s = String::deserialize(deserializer)?;
-        let hash = Hash::from_hex(s.as_str()).expect("Hash decode error");
-        Ok(hash)
+        Hash::from_hex(&s).map_err(|e| D::Error::custom(format!("invalid hash {}: {}", s, e)))
+    }
+}
+
+fn strip_hex_prefix(text: &str) -> &str {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        &text[2..]
+    } else {
+        text
     }
 }
 
-#[derive(Serialize, Deserialize)]
 #[derive(Default, Copy, Clone, Eq, Ord, PartialEq, PartialOrd, Hash)]
 pub struct Address(pub [u8; 20]);
 
@@ -172,14 +186,10 @@ impl Address {
     pub fn zero() -> Self {
         return Address::default();
     }
+    /// Parse 40 hex digits, with or without 0x prefix
     pub fn from_hex(text: &str) -> Result<Self, HexError> {
         let mut addr = Self::default();
-        let mut from = text;
-        if text.starts_with("0x") || text.starts_with("0X") {
-            from = &text[2..];
-        }
-        let b = hex::decode(from)?;
-        addr.0.copy_from_slice(&b);
+        hex::decode_to_slice(strip_hex_prefix(text), &mut addr.0)?;
         Ok(addr)
     }
     pub fn from_low_u64_le(val: u64) -> Self {
@@ -215,6 +225,7 @@ impl Address {
 
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x")?;
         for i in self.0.iter() {
             write!(f, "{:02x}", i)?;
         }
@@ -231,11 +242,66 @@ impl fmt::Debug for Address {
     }
 }
 
-impl From<Pubkey> for Address {
-    fn from(pk: Pubkey) -> Self {
+impl FromStr for Address {
+    type Err = HexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Address::from_hex(s)
+    }
+}
+
+/// 0x-prefixed hex in json and other human readable formats, the 20 raw bytes in bincode
+impl Serialize for Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            serializer.serialize_newtype_struct("Address", &self.0)
+        }
+    }
+}
+
+impl<'a> Deserialize<'a> for Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'a>
+    {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            Address::from_hex(&s).map_err(|e| D::Error::custom(format!("invalid address {}: {}", s, e)))
+        } else {
+            <[u8; 20]>::deserialize(deserializer).map(Address)
+        }
+    }
+}
+
+/// Hash deriving an account address from its public key, the last 20 bytes are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressScheme {
+    Blake2b,
+    /// Same derivation as EVM tooling
+    Keccak256,
+}
+
+impl Default for AddressScheme {
+    fn default() -> Self {
+        AddressScheme::Blake2b
+    }
+}
+
+impl Address {
+    /// Address of `pk` under `scheme`, the one pinned by the genesis of the chain
+    pub fn from_pubkey(pk: &Pubkey, scheme: AddressScheme) -> Self {
         let raw = pk.to_bytes();
+        let digest = match scheme {
+            AddressScheme::Blake2b => hash::blake2b_256(&raw),
+            AddressScheme::Keccak256 => hash::keccak_256(&raw),
+        };
         let mut addr = Address::default();
-        addr.0.copy_from_slice(&(hash::blake2b_256(&raw)[12..]));
+        addr.0.copy_from_slice(&digest[12..]);
         addr
     }
 }
@@ -264,4 +330,54 @@ mod tests {
         }
 
     }
+
+    #[test]
+    fn test_parse_mixed_inputs() {
+        let expected = Address::from_low_u64_be(0xabcd);
+        for text in &["0x000000000000000000000000000000000000abcd", "0X000000000000000000000000000000000000ABCD",
+                      "000000000000000000000000000000000000AbCd"] {
+            assert_eq!(text.parse::<Address>().unwrap(), expected);
+        }
+        assert!("0xabcd".parse::<Address>().is_err());
+        assert!("0x000000000000000000000000000000000000abcg".parse::<Address>().is_err());
+        assert!("0x000000000000000000000000000000000000abcd00".parse::<Address>().is_err());
+
+        let hash = Hash([0xab; 32]);
+        assert_eq!(format!("0X{}", "AB".repeat(32)).parse::<Hash>().unwrap(), hash);
+        assert_eq!("ab".repeat(32).parse::<Hash>().unwrap(), hash);
+        assert!("0xabab".parse::<Hash>().is_err());
+    }
+
+    #[test]
+    fn test_serde_formats() {
+        let addr = Address::from_low_u64_be(1);
+        assert_eq!(addr.to_string(), "0x0000000000000000000000000000000000000001");
+        let json = serde_json::to_string(&addr).unwrap();
+        assert_eq!(json, "\"0x0000000000000000000000000000000000000001\"");
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), addr);
+        assert_eq!(serde_json::from_str::<Address>("\"0000000000000000000000000000000000000001\"").unwrap(), addr);
+        assert!(serde_json::from_str::<Address>("\"0x01\"").is_err());
+        // Stored and gossiped data keep the raw bytes
+        assert_eq!(bincode::serialize(&addr).unwrap(), addr.0.to_vec());
+        assert_eq!(bincode::deserialize::<Address>(&addr.0).unwrap(), addr);
+
+        let hash = Hash([0x0f; 32]);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"0x{}\"", "0f".repeat(32)));
+        assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<Hash>("\"0xzz\"").is_err());
+        assert_eq!(bincode::deserialize::<Hash>(&bincode::serialize(&hash).unwrap()).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_address_scheme() {
+        let pk = Pubkey::from_bytes(&[7u8; 32]);
+        let blake = Address::from_pubkey(&pk, AddressScheme::Blake2b);
+        let keccak = Address::from_pubkey(&pk, AddressScheme::Keccak256);
+        assert_ne!(blake, keccak);
+        assert_eq!(&keccak.0[..], &hash::keccak_256(&pk.to_bytes())[12..]);
+
+        let scheme: AddressScheme = serde_json::from_str("\"keccak256\"").unwrap();
+        assert_eq!(scheme, AddressScheme::Keccak256);
+    }
 }
@@ -18,95 +18,253 @@
 extern crate log;
 extern crate errors;
 
-use core::transaction::Transaction;
-use core::balance::Balance;
+use core::chain_spec::Rules;
+use core::transaction::{self, Transaction, TxDomain};
+use core::staking::{self, StakingEvent};
+use core::balance::{Balance, BalanceError};
 use core::types::{Hash, Address};
 use core::block::{Block};
 use errors::{Error,InternalErrorKind};
 
-#[allow(non_upper_case_globals)]
-const transfer_fee: u128 = 10000;
+/// Flat fee charged for each transfer transaction
+pub const TRANSFER_FEE: u128 = 10000;
 
 
 pub struct Executor;
 
 impl Executor {
-    pub fn exc_txs_in_block(b: &Block, state: &mut Balance, miner_addr: &Address) -> Result<Hash,Error> {
+    pub fn exc_txs_in_block(b: &Block, state: &mut Balance, miner_addr: &Address, rules: &Rules) -> Result<Hash,Error> {
+        Executor::exc_block_with_events(b, state, miner_addr, rules).map(|(root, _)| root)
+    }
+
+    /// Execute the block under the `rules` of its height, returns the state root and the
+    /// staking events along with the hash of the transaction emitting them.
+    pub fn exc_block_with_events(b: &Block, state: &mut Balance, miner_addr: &Address, rules: &Rules)
+        -> Result<(Hash, Vec<(Hash, StakingEvent)>), Error> {
         let txs = b.get_txs();
-        // let mut h = Hash([0u8;32]);
+        let mut events = Vec::new();
         for tx in txs {
-            Executor::exc_transfer_tx(tx,state)?;
-            state.add_balance(*miner_addr, transfer_fee);
+            let tx_hash = tx.hash();
+            events.extend(Executor::exc_block_tx(tx, state, miner_addr, rules)?.into_iter().map(|e| (tx_hash, e)));
         }
 
-        Ok(state.commit())
+        Ok((state.commit(), events))
+    }
+
+    /// Execute a transaction of a block and pay its fee to the miner, returns the staking
+    /// events it emitted. The state is left uncommitted. Module calls are executed as transfers
+    /// before the `module_calls` fork.
+    pub fn exc_block_tx(tx: &Transaction, state: &mut Balance, miner_addr: &Address, rules: &Rules) -> Result<Vec<StakingEvent>, Error> {
+        let module_call = tx.call.starts_with(transaction::STAKING_MODULE) || tx.call.starts_with(transaction::STORAGE_MODULE);
+        if module_call && rules.module_calls {
+            Executor::exc_module_tx(tx, state, rules)?;
+        } else {
+            Executor::exc_transfer_tx(tx, state, rules)?;
+        }
+        state.add_balance(*miner_addr, TRANSFER_FEE).map_err(balance_error)?;
+        Ok(state.interpreter().take_events())
     }
 
     // handle the state for the tx,caller handle the gas of tx
-    pub fn exc_transfer_tx(tx: &Transaction, state: &mut Balance) -> Result<Hash, Error> {
+    pub fn exc_transfer_tx(tx: &Transaction, state: &mut Balance, rules: &Rules) -> Result<Hash, Error> {
         let from_addr = tx.get_from_address();
+
+        Executor::verify_tx_sign(&tx, TxDomain::Transfer, rules)?;
         let to_addr = tx.get_to_address();
 
-        Executor::verify_tx_sign(&tx)?;
         // Ensure balance and nance field available
         let from_account = state.get_account(from_addr);
-        if tx.get_nonce() != from_account.get_nonce() + 1 {
-            return Err(InternalErrorKind::InvalidTxNonce.into());
-        }
-        if tx.get_value() + transfer_fee > from_account.get_balance() {
-            return Err(InternalErrorKind::BalanceNotEnough.into());
-        }
+        let fee = Executor::check_transfer(
+            from_account.get_nonce(), from_account.get_balance(), tx.get_nonce(), tx.get_value())?;
 
-        state.sub_balance(from_addr, transfer_fee);
+        state.sub_balance(from_addr, fee).map_err(balance_error)?;
         state.inc_nonce(from_addr);
 
-        state.transfer(from_addr, to_addr, tx.get_value());
+        state.transfer(from_addr, to_addr, tx.get_value()).map_err(balance_error)?;
         debug!("Apply transaction send={}", from_addr);
         Ok(Hash::default())
     }
 
+    // charge the fee and run the staking or storage module call
+    pub fn exc_module_tx(tx: &Transaction, state: &mut Balance, rules: &Rules) -> Result<Hash, Error> {
+        let from_addr = tx.get_from_address();
+
+        Executor::verify_tx_sign(&tx, TxDomain::of_call(&tx.call), rules)?;
+        let from_account = state.get_account(from_addr);
+        let fee = Executor::check_transfer(
+            from_account.get_nonce(), from_account.get_balance(), tx.get_nonce(), 0)?;
+
+        state.sub_balance(from_addr, fee).map_err(balance_error)?;
+        state.inc_nonce(from_addr);
+
+        // Unknown to the staking module before the fork, the fee is charged and nothing done
+        if tx.call == staking::ROTATE_KEY_CALL && !rules.key_rotation {
+            debug!("Skip key rotation before its fork send={}", from_addr);
+            return Ok(Hash::default());
+        }
+        state.interpreter().call(&from_addr, tx.call.clone(), tx.data.clone());
+        debug!("Apply module transaction send={}", from_addr);
+        Ok(Hash::default())
+    }
+
+    /// Ensure a transfer of `value` with `nonce` applies on top of the sender's account nonce
+    /// and balance, returns the fee charged for it.
+    pub fn check_transfer(account_nonce: u64, balance: u128, nonce: u64, value: u128) -> Result<u128, Error> {
+        if nonce != account_nonce + 1 {
+            return Err(InternalErrorKind::InvalidTxNonce.into());
+        }
+        match value.checked_add(TRANSFER_FEE) {
+            Some(cost) if cost <= balance => Ok(TRANSFER_FEE),
+            Some(_) => Err(InternalErrorKind::BalanceNotEnough.into()),
+            None => Err(InternalErrorKind::BalanceOverflow.into()),
+        }
+    }
+
     // handle the state for the contract
     pub fn exc_contract_tx() -> Result<(),Error> {
         Ok(())
     }
-    fn verify_tx_sign(tx: &Transaction) -> Result<(),Error> {
-        tx.verify_sign()
+    /// The signature must be given for a message of `domain`, the kind of call executed. Blocks
+    /// before the `signing_domains` fork sign the hash of the transaction.
+    fn verify_tx_sign(tx: &Transaction, domain: TxDomain, rules: &Rules) -> Result<(),Error> {
+        if rules.signing_domains {
+            tx.verify_sign_in(domain)
+        } else {
+            tx.verify_sign()
+        }
+    }
+}
+
+/// Failed balance change of a transaction, aborting the execution of its block
+fn balance_error(e: BalanceError) -> Error {
+    match e {
+        BalanceError::Overflow => InternalErrorKind::BalanceOverflow.into(),
+        _ => InternalErrorKind::BalanceNotEnough.into(),
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    extern crate ed25519;
-    use ed25519::{privkey::PrivKey,pubkey::Pubkey,generator::Generator};
+    use std::cell::RefCell;
+    use std::rc::Rc;
     use core::balance::Balance;
-    use core::types::{Hash, Address};
+    use core::runtime::Interpreter;
+    use core::state::StateDB;
+    use core::block::Block;
+    use core::chain_spec::Rules;
+    use core::staking::{MsgRotateKey, MsgValidatorCreate, Staking, StakingEvent};
+    use core::test_utils;
     use core::transaction::Transaction;
-    use std::path::PathBuf;
-    use super::Executor;
-    use bytes::Bytes;
+    use core::types::Address;
+    use super::{Executor, TRANSFER_FEE};
 
-    pub fn get_pair() -> (PrivKey,Pubkey) {
-        Generator::default().new()
+    fn genesis_state() -> Balance {
+        let (genesis, db) = test_utils::genesis_with_state();
+        let statedb = Rc::new(RefCell::new(StateDB::from_existing(&db, genesis.state_root())));
+        Balance::new(Interpreter::new(statedb))
     }
+
     #[test]
     pub fn test_tx_execute() {
-        let path = PathBuf::from("testdb_04".to_string());
-        let mut state = Balance::new(path);
-        let user1 = get_pair();
-        let addr1 = user1.1.into();
-        let const_value = 100000u128;
+        let mut state = genesis_state();
+        let sender = test_utils::genesis_key();
+        let receiver = test_utils::test_address(1);
         let tval = 100u128;
-        state.add_balance(addr1, const_value);
-        state.inc_nonce(addr1);
-        let hex_addr = "0000000000000000000000000000000000000001";
-        let addr2 = Address::from_hex(hex_addr).unwrap();
-        let tx = Transaction::new(addr1, addr2, 2,
-             10, 10, tval, Bytes::new());
-        match Executor::exc_transfer_tx(&tx, &mut state) {
-            Ok(h) => println!("root:{:?}",h),
-            Err(e) => {println!("err:{:?}",e);return;},
-        };
-        let val2 = state.balance(addr2);
-        assert_eq!(val2,tval);
+
+        let tx = test_utils::transfer(&sender, 1, receiver, tval);
+        // Signed over the transaction hash, not valid once signing domains are in force
+        let forked = Rules { signing_domains: true, ..Rules::default() };
+        assert!(Executor::exc_transfer_tx(&tx, &mut genesis_state(), &forked).is_err());
+        Executor::exc_transfer_tx(&tx, &mut state, &Rules::default()).unwrap();
+        assert_eq!(state.balance(receiver), tval);
+
+        // Replayed nonce is rejected
+        assert!(Executor::exc_transfer_tx(&tx, &mut state, &Rules::default()).is_err());
+
+        let mut tx = test_utils::transfer(&sender, 2, receiver, tval);
+        tx.sign_at(&sender.to_bytes(), &forked).unwrap();
+        Executor::exc_transfer_tx(&tx, &mut state, &forked).unwrap();
+        assert_eq!(state.balance(receiver), 2 * tval);
+    }
+
+    #[test]
+    pub fn test_staking_events() {
+        let key = test_utils::test_key(1);
+        let addr = test_utils::test_address(1);
+        let create = MsgValidatorCreate { pubkey: key.to_pubkey().unwrap().to_bytes(), amount: 50000 };
+        let mut validate = Transaction::new(addr, 1, 1000, 1000, b"staking.validate".to_vec(),
+            bincode::serialize(&create).unwrap());
+        validate.sign(&key.to_bytes()).unwrap();
+
+        let mut block = Block::default();
+        block.txs = vec![test_utils::transfer(&test_utils::genesis_key(), 1, addr, 100000), validate.clone()];
+        let rules = Rules { module_calls: true, ..Rules::default() };
+        let mut state = genesis_state();
+        let (_, events) = Executor::exc_block_with_events(&block, &mut state, &Address::default(), &rules).unwrap();
+        assert_eq!(events, vec![(validate.hash(), StakingEvent::ValidatorCreated {
+            address: addr,
+            pubkey: create.pubkey.clone(),
+            amount: 50000,
+        })]);
+        assert_eq!(state.balance(addr), 100000 - TRANSFER_FEE - 50000);
+
+        // Executed as a transfer of nothing before the fork
+        let mut state = genesis_state();
+        let (_, events) = Executor::exc_block_with_events(&block, &mut state, &Address::default(), &Rules::default()).unwrap();
+        assert!(events.is_empty());
+        assert_eq!(state.balance(addr), 100000 - TRANSFER_FEE);
+        assert!(Staking::from_state(state.interpreter()).get_validator(&addr).is_none());
+    }
+
+    #[test]
+    pub fn test_key_rotation_fork() {
+        let key = test_utils::test_key(1);
+        let addr = test_utils::test_address(1);
+        let pubkey = |seed| test_utils::test_key(seed).to_pubkey().unwrap().to_bytes();
+        let create = MsgValidatorCreate { pubkey: pubkey(2), amount: 50000 };
+        let mut validate = Transaction::new(addr, 1, 1000, 1000, b"staking.validate".to_vec(),
+            bincode::serialize(&create).unwrap());
+        validate.sign(&key.to_bytes()).unwrap();
+        let rotate = MsgRotateKey::new(&addr, 0, &test_utils::test_key(2), &test_utils::test_key(3)).unwrap();
+        let mut rotate = Transaction::new(addr, 2, 1000, 1000, b"staking.rotate_key".to_vec(),
+            bincode::serialize(&rotate).unwrap());
+        rotate.sign(&key.to_bytes()).unwrap();
+        let mut block = Block::default();
+        block.txs = vec![test_utils::transfer(&test_utils::genesis_key(), 1, addr, 100000), validate, rotate];
+
+        // Only charged before the fork, as by nodes without key rotation
+        let modules = Rules { module_calls: true, ..Rules::default() };
+        for (rules, seed) in vec![(modules, 2), (Rules { key_rotation: true, ..modules }, 3)] {
+            let mut state = genesis_state();
+            Executor::exc_block_with_events(&block, &mut state, &Address::default(), &rules).unwrap();
+            let staking = Staking::from_state(state.interpreter());
+            assert_eq!(staking.get_validator(&addr).unwrap().pubkey, pubkey(seed));
+            assert_eq!(state.get_account(addr).get_nonce(), 2);
+        }
+    }
+
+    #[test]
+    pub fn test_balance_overflow() {
+        let mut state = genesis_state();
+        let receiver = test_utils::test_address(1);
+        state.add_balance(receiver, u128::max_value()).unwrap();
+        let tx = test_utils::transfer(&test_utils::genesis_key(), 1, receiver, 100);
+        let err = Executor::exc_transfer_tx(&tx, &mut state, &Rules::default()).unwrap_err();
+        assert!(format!("{}", err).contains("BalanceOverflow"));
+
+        // Nor can the miner fee overflow
+        let mut state = genesis_state();
+        state.add_balance(receiver, u128::max_value()).unwrap();
+        let mut block = Block::default();
+        block.txs = vec![test_utils::transfer(&test_utils::genesis_key(), 1, Address::default(), 100)];
+        assert!(Executor::exc_block_with_events(&block, &mut state, &receiver, &Rules::default()).is_err());
+    }
+
+    #[test]
+    pub fn test_check_transfer() {
+        assert_eq!(Executor::check_transfer(1, 20000, 2, 100).unwrap(), TRANSFER_FEE);
+        assert!(Executor::check_transfer(1, 20000, 3, 100).is_err());
+        assert!(Executor::check_transfer(1, 10099, 2, 100).is_err());
+        assert!(Executor::check_transfer(1, 20000, 2, u128::max_value()).is_err());
     }
 }
@@ -17,21 +17,23 @@
 use std::collections::HashMap;
 // use std::cell::RefCell;
 // use std::rc::Rc;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
-use num_traits::{cast::ToPrimitive, identities::One};
-use num_rational::BigRational;
-use num_bigint::BigUint;
 use hash;
-// use map_consensus::ConsensusErrorKind;
+use map_consensus::ConsensusErrorKind;
 use map_crypto::vrf;
-use map_core::staking::Staking;
 // use map_core::state::StateDB;
-use map_core::runtime::Interpreter;
 use chain::blockchain::BlockChain;
+pub use chain::committee::{calc_random_threshold, cmp_random_threshold};
+use chain::committee;
+use chain::lock::TimedRwLock;
+use map_core::runtime::Interpreter;
+use map_core::staking::Staking;
+use map_core::types::Address;
 #[allow(unused_imports)]
 use crate::types::{ValidatorStake, RngSeed};
 use crate::epoch::EPOCH_LENGTH;
+use crate::signer::BlockSigner;
 #[allow(unused_imports)]
 use ed25519::{privkey::PrivKey, pubkey::Pubkey};
 #[allow(unused_imports)]
@@ -45,22 +47,34 @@ pub struct EpochInfo {
     pub validators: Vec<ValidatorStake>,
 }
 
-/// Return VRF threshold of epoch validator set
-pub fn calc_random_threshold(empty: u64, num: u64) -> u128 {
-    let base  = 1000u64;
-    let pskip = empty as f64 / base as f64;
-    let p = BigRational::from_float(1f64 - pskip.powf(1 as f64 / num as f64)).unwrap();
-
-    let numer = p.numer().to_biguint().unwrap();
-    let denom = p.denom().to_biguint().unwrap();
-    ((BigUint::one() << 128) * numer / denom).to_u128().unwrap_or(u128::max_value())
+/// Vrf input of a slot and the threshold its output must be under to propose the slot
+pub struct SlotLottery {
+    sid: u64,
+    input: [u8; 32],
+    /// None when the node proposes every slot, in dev mode without validators
+    threshold: Option<u128>,
 }
 
-/// Returns true if the VRF value is little than the given threshold,
-pub(super) fn cmp_random_threshold(random_value: &vrf::Value, threshold: u128) -> bool {
-    let mut b: [u8; 16] = Default::default();
-    b.copy_from_slice(&random_value.0[..16]);
-    u128::from_be_bytes(b) < threshold
+impl SlotLottery {
+    /// Evaluate the vrf with `signer`, the output and its proof if they elect it.
+    pub fn draw(&self, signer: &dyn BlockSigner) -> Option<(vrf::Value, vrf::Proof)> {
+        let (vrf_value, vrf_proof) = match signer.vrf_evaluate(&self.input) {
+            Ok(output) => output,
+            Err(e) => {
+                error!("vrf evaluation failed, slot={}: {}", self.sid, e);
+                return None;
+            }
+        };
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return Some((vrf_value, vrf_proof)),
+        };
+        info!("Calc vrf value={:?}, threshold={:x}", vrf_value, threshold);
+        if cmp_random_threshold(&vrf_value, threshold) {
+            return Some((vrf_value, vrf_proof));
+        }
+        None
+    }
 }
 
 pub struct EpochPoS {
@@ -68,13 +82,13 @@ pub struct EpochPoS {
     eid: u64, // current epoch id
     #[allow(dead_code)]
     dev_mode: bool,
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
     // node_key: Pubkey,
     // genesis_block: Block,
 }
 
 impl EpochPoS {
-    pub fn new(chain: Arc<RwLock<BlockChain>>, dev_mode: bool) -> Self {
+    pub fn new(chain: Arc<TimedRwLock<BlockChain>>, dev_mode: bool) -> Self {
         EpochPoS {
             epoch_infos: HashMap::default(),
             eid: 0,
@@ -84,13 +98,25 @@ impl EpochPoS {
         }
     }
 
-    fn genesis_epoch(&self) -> Option<EpochInfo> {
+    /// Committee of epoch `eid` for blocks on top of the head. From the `proposer_check` fork on
+    /// these are the validators of the state the epoch starts on, as block importers check it,
+    /// before it the validators of the head state.
+    fn epoch_committee(&self, eid: u64) -> Option<EpochInfo> {
         let chain = self.chain.read().unwrap();
-        let cur_block = chain.current_block();
-        let statedb = chain.state_at(cur_block.state_root());
-        let state = Staking::new(Interpreter::new(statedb.clone()));
-
-        let validators = state.validator_set();
+        let head = chain.current_block();
+        let committee = if chain.spec().forks.at(head.height() + 1).proposer_check {
+            match chain.epoch_committee(&head.header, eid) {
+                Ok(committee) => committee,
+                Err(e) => {
+                    error!("committee of epoch={} unavailable: {}", eid, e);
+                    return None;
+                }
+            }
+        } else {
+            let staking = Staking::new(Interpreter::new(chain.state_at(head.state_root())));
+            committee::EpochCommittee::new(eid, head.hash(), &chain.genesis_hash(), committee::members_of(&staking))
+        };
+        let validators = committee.members;
         let mut holders: Vec<ValidatorStake> = Vec::new();
 
         if self.dev_mode {
@@ -106,23 +132,18 @@ impl EpochPoS {
 
             // Devmode single validator
             if validators.len() > 0 {
-                let mut pk: [u8; 32] = [0; 32];
-                pk.copy_from_slice(&validators[0].pubkey);
                 holders.push(ValidatorStake {
-                    pubkey: pk,
-                    stake_amount: validators[0].effective_balance,
+                    pubkey: validators[0].pubkey,
+                    stake_amount: validators[0].stake,
                     sid: 0,
                     validator: true,
                 });
             }
         } else {
             for v in validators {
-                let mut pk: [u8; 32] = [0; 32];
-                pk.copy_from_slice(&v.pubkey);
-
                 holders.push(ValidatorStake {
-                    pubkey: pk,
-                    stake_amount: v.effective_balance,
+                    pubkey: v.pubkey,
+                    stake_amount: v.stake,
                     sid: 0,
                     validator: true,
                 });
@@ -133,7 +154,7 @@ impl EpochPoS {
         Some(EpochInfo {
             seed: 0,
             validators: holders,
-            rng_seed: [0; 32],
+            rng_seed: committee.seed,
         })
     }
 
@@ -156,11 +177,7 @@ impl EpochPoS {
             return Some(v.clone());
         }
 
-        // Genesis epoch committee at start
-        let mut epoch = self.genesis_epoch().unwrap().clone();
-        if eid > 0 {
-            epoch.rng_seed = self.compute_epoch_seed(eid).unwrap();
-        }
+        let epoch = self.epoch_committee(eid)?;
 
         // sid not used in epoch validators
         // for (i, val) in epoch.validators.iter_mut().enumerate() {
@@ -186,6 +203,7 @@ impl EpochPoS {
 
     pub fn get_slot_proposer(&self, index: u64, eid: u64) -> Option<ValidatorStake> {
         match self.get_epoch_info(eid) {
+            Some(ref epoch) if epoch.validators.is_empty() => None,
             Some(epoch) => {
                 let i = index % EPOCH_LENGTH;
                 let proposer = self.get_proposer_index(&epoch, i, epoch.rng_seed);
@@ -221,34 +239,58 @@ impl EpochPoS {
 
     /// Compute if node is propser of the slot by apply vrf
     pub fn calc_epoch_threshold(&self, _eid: u64, epoch: &EpochInfo) -> u128 {
-        calc_random_threshold(200, epoch.validators.len() as u64)
+        calc_random_threshold(committee::EMPTY_SLOT_CHANCE, epoch.validators.len() as u64)
     }
 
-    /// Compute if node is propser of the slot by apply vrf
-    pub fn make_slot_proposer(&self, sid: u64, private_key: PrivKey) -> Option<(vrf::Value, vrf::Proof)> {
+    /// Whether the consensus key `pubkey` is in the committee of epoch `eid`
+    pub fn is_member(&self, eid: u64, pubkey: &Pubkey) -> bool {
+        self.get_epoch_info(eid).map_or(false, |epoch| {
+            epoch.validators.iter().any(|v| Pubkey::from_bytes(&v.pubkey).equal(pubkey))
+        })
+    }
+
+    /// Staking address of the member of the committee of epoch `eid` with the consensus key
+    /// `pubkey`, which stays the same when the validator rotates its key
+    pub fn member_address(&self, eid: u64, pubkey: &Pubkey) -> Option<Address> {
+        let chain = self.chain.read().unwrap();
+        chain.epoch_committee(&chain.current_block().header, eid).ok()?.member_address(&pubkey.to_bytes())
+    }
+
+    /// Whether epoch `eid` has validators to propose its blocks, always true in dev mode
+    /// where the node proposes alone.
+    pub fn has_validators(&self, eid: u64) -> bool {
+        self.dev_mode || self.get_epoch_info(eid).map_or(false, |epoch| !epoch.validators.is_empty())
+    }
+
+    /// Compute if node is propser of the slot by apply vrf. Fails with `NoValidatorsInEpoch`
+    /// on an empty validator set, except in dev mode where the node proposes every slot.
+    pub fn make_slot_proposer(&self, sid: u64, signer: &dyn BlockSigner) -> Result<Option<(vrf::Value, vrf::Proof)>, Error> {
+        Ok(self.slot_lottery(sid, &signer.pubkey())?.and_then(|lottery| lottery.draw(signer)))
+    }
+
+    /// Lottery of slot `sid` for the consensus key `pubkey`, None if it is not in the committee.
+    /// A remote signer evaluates the vrf over http, callers holding the staking lock release it
+    /// before the `draw`.
+    pub fn slot_lottery(&self, sid: u64, pubkey: &Pubkey) -> Result<Option<SlotLottery>, Error> {
         let eid: u64 = sid / EPOCH_LENGTH;
         let epoch_data = match self.get_epoch_info(eid) {
             Some(epoch) => epoch,
-            None => return None,
+            None => return Ok(None),
         };
 
-        if epoch_data.validators.iter().find(
-            |&x| Pubkey::from_bytes(&x.pubkey).equal(&private_key.to_pubkey().unwrap())).is_none() {
-            return None;
+        let self_propose = epoch_data.validators.is_empty();
+        if self_propose && !self.dev_mode {
+            return Err(ConsensusErrorKind::NoValidatorsInEpoch.into());
         }
-
-        let mut seed = Vec::new();
-        seed.extend_from_slice(&epoch_data.rng_seed);
-        seed.extend_from_slice(&sid.to_be_bytes());
-
-        let secret_key = vrf::convert_secret_key(&private_key.to_bytes());
-        let (vrf_value, vrf_proof) = secret_key.compute_vrf_with_proof(&hash::blake2b_256(&seed));
-        let threshold = self.calc_epoch_threshold(eid, &epoch_data);
-        info!("Calc vrf value={:?}, threshold={:x}", vrf_value, threshold);
-        if cmp_random_threshold(&vrf_value, threshold) {
-            return Some((vrf_value, vrf_proof));
+        if !self_propose && epoch_data.validators.iter().find(
+            |&x| Pubkey::from_bytes(&x.pubkey).equal(pubkey)).is_none() {
+            return Ok(None);
         }
-        None
+        Ok(Some(SlotLottery {
+            sid,
+            input: committee::vrf_input(&epoch_data.rng_seed, sid),
+            threshold: if self_propose { None } else { Some(self.calc_epoch_threshold(eid, &epoch_data)) },
+        }))
     }
 
     pub fn get_seed_by_epochid(&self, eid: u64) -> u64 {
@@ -259,19 +301,8 @@ impl EpochPoS {
         }
     }
 
-    #[allow(unused_variables)]
     pub fn compute_epoch_seed(&self, epoch: u64) -> Option<RngSeed> {
-        let num = epoch * EPOCH_LENGTH;
-        let num = 0u64;
-        let block = self.chain.read().unwrap().get_block_by_number(num);
-        let mut seed = match block {
-            Some(b)  => b.hash().0,
-            None    => return None,
-        };
-        if epoch == 0 {
-            seed = [0; 32];
-        }
-        Some(hash::blake2b_256(seed))
+        Some(committee::epoch_seed(&self.chain.read().unwrap().genesis_hash(), epoch))
     }
 
     // fn from_genesis(&mut self,genesis: &Block,state: &Balance) {
@@ -309,3 +340,36 @@ impl EpochPoS {
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::chain_spec::ChainSpec;
+    use ed25519::generator::create_key;
+    use crate::signer::LocalSigner;
+
+    fn empty_pos(dev_mode: bool) -> EpochPoS {
+        let mut spec = ChainSpec::default();
+        spec.genesis.validators.clear();
+        let mut chain = BlockChain::new_in_memory(&spec);
+        chain.load().unwrap();
+        EpochPoS::new(Arc::new(TimedRwLock::new("chain", chain)), dev_mode)
+    }
+
+    #[test]
+    fn no_validators() {
+        let pos = empty_pos(false);
+        let signer = LocalSigner::new(create_key().0);
+        assert!(!pos.has_validators(0));
+        assert!(pos.get_slot_proposer(1, 0).is_none());
+        assert!(pos.make_slot_proposer(1, &signer).is_err());
+    }
+
+    #[test]
+    fn dev_mode_self_propose() {
+        let pos = empty_pos(true);
+        let signer = LocalSigner::new(create_key().0);
+        assert!(pos.has_validators(0));
+        assert!(pos.make_slot_proposer(1, &signer).unwrap().is_some());
+    }
+}
@@ -14,14 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 // use std::thread::{self, JoinHandle};
 use std::time::{Duration, SystemTime, Instant};
 
 #[allow(unused_imports)]
-use crate::{apos::{self, EpochPoS}, types};
+use crate::{apos::{self, EpochPoS}, missed, types};
+use crate::signer::BlockSigner;
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use pool::snapshot::PoolSnapshot;
 use pool::tx_pool::TxPoolManager;
+use futures::future;
 use tokio::prelude::*;
 use tokio::timer::{self, Delay};
 use tokio::sync::oneshot;
@@ -35,19 +39,26 @@ use executor::Executor;
 use map_crypto::vrf;
 #[allow(unused_imports)]
 use map_consensus::ConsensusErrorKind;
+use map_network::import_queue::{ImportQueue, ImportSource};
 use map_network::manager::{self, NetworkMessage};
+use map_network::sync::{ProposalState, SharedSyncProgress};
 #[allow(unused_imports)]
-use map_core::block::{Block, VRFProof, Header, BlockProof, VerificationItem};
+use map_core::block::{self, Block, VRFProof, Header, BlockProof, VerificationItem};
 use map_core::balance::Balance;
-use map_core::transaction::Transaction;
 use map_core::runtime::Interpreter;
 use map_core::types::{Hash, Address};
-use map_core::genesis::GENESIS_TIME;
 // use super::fts;
 
-/// Slots per epoch constant
-pub const EPOCH_LENGTH: u64 = 64;
-pub const SLOT_DURATION: u64 = 6;
+pub use map_core::genesis::{EPOCH_LENGTH, SLOT_DURATION};
+
+/// Number of slots between two broadcasts of the local transactions not yet included
+const LOCAL_REBROADCAST_SLOTS: u64 = 10;
+
+/// Blocks the head may be behind the best peer head and still propose on top of it
+pub const DEFAULT_MAX_BLOCKS_BEHIND: u64 = 3;
+
+/// Peers on our chain required to propose outside dev mode
+pub const DEFAULT_MIN_PROPOSAL_PEERS: usize = 1;
 
 // type TypeNewBlockEvent = Receiver<Block>;
 // type TypeNewTimerIntervalEvent = Receiver<Instant>;
@@ -57,48 +68,54 @@ pub const SLOT_DURATION: u64 = 6;
 // Chain bulder to make proposer block
 #[derive(Clone)]
 pub struct Builder {
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
     tx_pool : Arc<RwLock<TxPoolManager>>,
 }
 
 impl Builder {
-    pub fn new(chain: Arc<RwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>) -> Self {
+    pub fn new(chain: Arc<TimedRwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>) -> Self {
         Builder {
             chain: chain,
             tx_pool: tx_pool,
         }
     }
     // Proposal new block from certain slot
-    pub fn produce_block(&self, slot: u64, parent: Hash, vrf_output: vrf::Value, vrf_proof: vrf::Proof) -> Block {
+    // Transactions are taken from `pool`, not the live pool which keeps changing during the build
+    pub fn produce_block(&self, slot: u64, parent: Hash, pool: &PoolSnapshot, vrf_output: vrf::Value, vrf_proof: vrf::Proof) -> Result<Block, Error> {
         let pre = self.chain.read().unwrap().get_block(parent).unwrap();
 
-        let txs = self.prepare_transactions();
+        let txs = pool.pending_for_block();
         let tx_len = txs.len();
         let mut block = Block::new(Header::default(), txs, Vec::new(), Vec::new());
-        let state_root = self.apply_block(pre.state_root(), &block);
-
         block.header.parent_hash = parent;
+        // Executed under the rules of its height
         block.header.height = pre.height() + 1;
+        let state_root = self.apply_block(pre.state_root(), &block);
+
         block.header.slot = slot;
         block.header.vrf_output = vrf_output.0;
         block.header.vrf_proof = VRFProof::new(vrf_proof.0);
-        block.header.state_root = state_root;
         block.header.time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
         info!("Seal state root pre={}, post={} tx={}", pre.state_root(), state_root, tx_len);
-        block
+        self.chain.read().unwrap().finalize_block(block, state_root)
     }
 
     pub fn apply_block(&self, root: Hash, b: &Block) -> Hash {
-        let statedb = self.chain.read().unwrap().state_at(root);
-        let h = Executor::exc_txs_in_block(&b, &mut Balance::new(Interpreter::new(statedb)), &Address::default()).unwrap();
+        let (statedb, rules) = {
+            let chain = self.chain.read().unwrap();
+            (chain.state_at(root), chain.spec().forks.at(b.height()))
+        };
+        let h = Executor::exc_txs_in_block(&b, &mut Balance::new(Interpreter::new(statedb.clone())), &Address::default(), &rules).unwrap();
+        // The state is written again when the block is imported
+        self.chain.read().unwrap().abandon_state(statedb.borrow_mut().take_journal());
         h
     }
 
-    pub fn prepare_transactions(&self) -> Vec<Transaction> {
-        self.tx_pool.read().unwrap().get_pending()
+    pub fn snapshot_pool(&self) -> PoolSnapshot {
+        self.tx_pool.read().unwrap().snapshot()
     }
 
     pub fn get_current_height(&self) -> u64 {
@@ -122,7 +139,7 @@ impl Builder {
         Some(Block::default())
     }
 
-    pub fn get_blockchain(&self) ->  Arc<RwLock<BlockChain>> {
+    pub fn get_blockchain(&self) ->  Arc<TimedRwLock<BlockChain>> {
         // self.chain.write().unwrap().import_block(block);
         self.chain.clone()
     }
@@ -204,16 +221,6 @@ impl Stream for SlotTick {
     type Error = timer::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        // let delay = self.delay.as_mut().unwrap();
-        // self.delay = match self.delay.take() {
-        //     Some(d) => Some(d),
-        //     None => {
-        //         let timeout = Instant::now() + Duration::from_secs(self.slot_duration);
-        //         info!("new slot deadline: timeout={:?}", timeout);
-        //         Some(Delay::new(timeout))
-        //     }
-        // };
-
         let _ = try_ready!(self.delay.poll());
 
         let timeout = Delay::deadline(&self.delay);
@@ -244,7 +251,7 @@ impl Stream for SlotTick {
     // pub fn new(
     //     mid: Pubkey,
     //     eid: u64,
-    //     chain: Arc<RwLock<BlockChain>>,
+    //     chain: Arc<TimedRwLock<BlockChain>>,
     //     p2p: NetworkExecutor,
     //     tx_pool: Arc<RwLock<TxPoolManager>>,
     //     exit: Receiver<i32>
@@ -506,39 +513,72 @@ impl Stream for SlotTick {
     // }
 // }
 
+/// Whether the proposer takes part in the slots
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProposerState {
+    Active,
+    /// The epoch had no validators, slots are skipped until the next epoch transition
+    Idle(u64),
+}
+
 #[derive(Clone)]
 pub struct EpochProposal {
     executor: runtime::TaskExecutor,
-    myid: PrivKey,
+    signer: Arc<dyn BlockSigner>,
     pubkey: Pubkey,
-    chain: Arc<RwLock<BlockChain>>,
+    /// Key the validator rotates to, signing from the first epoch whose committee holds it
+    next_signer: Option<Arc<dyn BlockSigner>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
     block_chain: Builder,
     stake: Arc<RwLock<EpochPoS>>,
     tx_pool: Arc<RwLock<TxPoolManager>>,
+    /// Imports proposed blocks in turn with those of the network
+    importer: ImportQueue,
     network_send: mpsc::UnboundedSender<NetworkMessage>,
+    sync_progress: SharedSyncProgress,
+    max_blocks_behind: u64,
+    /// Zero in dev mode
+    min_peers: usize,
+    state: ProposerState,
 }
 
 impl EpochProposal {
     pub fn new(
-        mid: PrivKey,
-        chain: Arc<RwLock<BlockChain>>,
+        signer: Arc<dyn BlockSigner>,
+        chain: Arc<TimedRwLock<BlockChain>>,
         stake: Arc<RwLock<EpochPoS>>,
         tx_pool: Arc<RwLock<TxPoolManager>>,
+        importer: ImportQueue,
         network_send: mpsc::UnboundedSender<NetworkMessage>,
+        sync_progress: SharedSyncProgress,
+        max_blocks_behind: u64,
+        min_peers: usize,
         executor: runtime::TaskExecutor
     ) -> Self {
         EpochProposal {
-            myid: mid,
-            pubkey: mid.to_pubkey().unwrap(),
+            pubkey: signer.pubkey(),
+            signer: signer,
+            next_signer: None,
             chain: chain.clone(),
             block_chain: Builder::new(chain.clone(), tx_pool.clone()),
             stake: stake,
             tx_pool: tx_pool.clone(),
+            importer: importer,
             network_send: network_send,
+            sync_progress: sync_progress,
+            max_blocks_behind: max_blocks_behind,
+            min_peers: min_peers,
+            state: ProposerState::Active,
             executor: executor,
         }
     }
 
+    /// Hand block signing over to `signer` once a `staking.rotate_key` to its key takes effect
+    pub fn with_next_signer(mut self, signer: Arc<dyn BlockSigner>) -> Self {
+        self.next_signer = Some(signer);
+        self
+    }
+
     /// Run block proposal service
     pub fn start(&self) -> oneshot::Sender<()> {
         let (exit_signal, exit_rx) = oneshot::channel();
@@ -558,13 +598,10 @@ impl EpochProposal {
 
     /// Make block proposal from random validator
     fn make_proposal(&self) -> impl Future<Item = (), Error = ()> {
-        // future::ok::<Duration, ()>(now)
-        //     .and_then(move |_| {
-        //         Ok(())
-        //     })
-        let genesis_duration = Duration::from_secs(GENESIS_TIME);
-        let mut proposal = self.clone();
-        let tick = SlotTick::new(SLOT_DURATION, genesis_duration)
+        let genesis_time = self.block_chain.get_blockchain().read().unwrap().genesis_time();
+        let genesis_duration = Duration::from_secs(genesis_time);
+        let proposal = Arc::new(Mutex::new(self.clone()));
+        SlotTick::new(SLOT_DURATION, genesis_duration)
             .map_err(move |_| {
                 error!("tick error");
             })
@@ -572,16 +609,32 @@ impl EpochProposal {
                 info!("slot tick instant {:?}", slot);
                 let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).ok().unwrap();
                 info!("time current {:?}", now);
-                // let walk_pos = proposal.block_chain.get_current_height() + 1;
-                proposal.on_slot(slot);
-                Ok(())
-            });
-
-        tick
+                // Slots take the chain and pool locks and may wait on a remote signer, they run
+                // as blocking sections so the other tasks of the pool keep going meanwhile
+                let proposal = proposal.clone();
+                future::poll_fn(move || {
+                    tokio_threadpool::blocking(|| proposal.lock().unwrap().on_slot(slot))
+                }).map_err(move |e| {
+                    error!("slot {} failed: {}", slot, e);
+                })
+            })
     }
 
     fn on_slot(&mut self, sid: u64) {
         info!("new slot id={}", sid);
+        if sid % LOCAL_REBROADCAST_SLOTS == 0 {
+            self.rebroadcast_locals();
+        }
+        if !self.on_epoch(EpochId::epoch_from_id(sid)) {
+            return;
+        }
+        self.check_key_handover(EpochId::epoch_from_id(sid));
+        self.check_missed_slots(sid);
+        if !self.check_peers() {
+            return;
+        }
+        // Transactions arriving after the slot start go into the next block
+        let pool = self.block_chain.snapshot_pool();
         // match self.stake.read().unwrap().make_slot_proposer(sid, self.myid) {
         //     Some((value, proof)) => {
         //         info!("VRF value hash={:?}", value);
@@ -592,28 +645,132 @@ impl EpochProposal {
         // }
         // if self.is_proposer(sid, self.stake.clone()) {
 
-        if let Some((value, proof)) =  self.stake.read().unwrap().make_slot_proposer(sid, self.myid) {
+        // The staking lock is released before a remote signer evaluates the vrf
+        let lottery = self.stake.read().unwrap().slot_lottery(sid, &self.pubkey);
+        if let Some((value, proof)) = lottery.ok().flatten().and_then(|lottery| lottery.draw(self.signer.as_ref())) {
             info!("Make proposer vrf value={:?} pk={}", value, self.pubkey);
             let current = self.block_chain.get_head_block();
-            let b = self
-                .block_chain
-                .produce_block(sid, current.hash(), value, proof);
-
-            info!("make new block hash={} num={}", b.hash(), b.height());
-            {
-                let block_chain = self.block_chain.get_blockchain();
-                let mut chain = block_chain.write().unwrap();
-                if let Err(e) = chain.insert_block(b.clone()) {
-                    error!("insert_block Error: {:?}", e);
+            let behind = self.sync_progress.read().unwrap().blocks_behind(current.height(), sid);
+            if behind > self.max_blocks_behind {
+                // A block on a stale head would fork from the chain the peers are on
+                warn!("Skip proposal of slot {}, head {} is {} blocks behind peers", sid, current.height(), behind);
+                return;
+            }
+            let b = match self.block_chain.produce_block(sid, current.hash(), &pool, value, proof) {
+                Ok(b) => b,
+                Err(e) => {
+                    error!("finalize block error: {:?}", e);
+                    return;
+                }
+            };
+            let b = match self.sign_block(b) {
+                Ok(b) => b,
+                Err(e) => {
+                    error!("sign block error: {}", e);
                     return;
                 }
+            };
+
+            info!("make new block hash={} num={}", b.hash(), b.height());
+            // The import resets the pool to the new head
+            if let Err(e) = self.importer.import(ImportSource::Local, b.clone()) {
+                error!("import block error: {}", e);
+                return;
             }
-            self.tx_pool.write().unwrap().reset_pool(&b);
             // boradcast and import the block
             manager::publish_block(&mut self.network_send, b);
         }
     }
 
+    /// Whether to take part in the slots of epoch `eid`. Without validators the proposer
+    /// idles and looks at the validator set again on the next epoch transition.
+    fn on_epoch(&mut self, eid: u64) -> bool {
+        if self.state == ProposerState::Idle(eid) {
+            return false;
+        }
+        let active = self.stake.read().unwrap().has_validators(eid);
+        match self.state {
+            ProposerState::Active if !active => warn!("No validators in epoch {}, block proposal idle until validators join", eid),
+            ProposerState::Idle(_) if active => info!("Validators joined in epoch {}, block proposal resumed", eid),
+            _ => {}
+        }
+        self.state = if active { ProposerState::Active } else { ProposerState::Idle(eid) };
+        active
+    }
+
+    /// Switch to the next signer in the first epoch whose committee holds its key instead of the
+    /// current one. The rotation changes the state at once but the committee of an epoch is
+    /// fixed before it starts, so the old key signs until then.
+    fn check_key_handover(&mut self, eid: u64) {
+        let next = match self.next_signer {
+            Some(ref next) => next.clone(),
+            None => return,
+        };
+        {
+            let stake = self.stake.read().unwrap();
+            if stake.is_member(eid, &self.pubkey) || !stake.is_member(eid, &next.pubkey()) {
+                return;
+            }
+        }
+        info!("Consensus key rotated in epoch {}, signing with pk={} instead of pk={}", eid, next.pubkey(), self.pubkey);
+        self.pubkey = next.pubkey();
+        self.signer = next;
+        self.next_signer = None;
+    }
+
+    /// Whether enough peers on our chain are connected to propose. An isolated validator, e.g. with
+    /// a wrong bootnode or network id, would otherwise seal a fork no other node follows.
+    fn check_peers(&self) -> bool {
+        let mut progress = self.sync_progress.write().unwrap();
+        let state = proposal_state(progress.chain_peers, self.min_peers);
+        if state != progress.proposal {
+            match state {
+                ProposalState::InsufficientPeers => warn!(
+                    "Not proposing: insufficient peers, {} of {} required on our chain", progress.chain_peers, self.min_peers),
+                ProposalState::Active => info!("Block proposal resumed with {} peers on our chain", progress.chain_peers),
+            }
+            progress.proposal = state;
+        }
+        state == ProposalState::Active
+    }
+
+    /// Record the slots since the last check this node was elected for and that have no block.
+    fn check_missed_slots(&self, sid: u64) {
+        let stake = self.stake.clone();
+        let signer = self.signer.clone();
+        // Missed slots are counted for the staking address, the one blocks are credited to
+        let validator = stake.read().unwrap().member_address(EpochId::epoch_from_id(sid), &self.pubkey)
+            .unwrap_or_else(|| Address::from_pubkey(&self.pubkey, self.chain.read().unwrap().address_scheme()));
+        missed::record_missed(&self.chain, validator, sid, |slot| {
+            let lottery = stake.read().unwrap().slot_lottery(slot, &signer.pubkey());
+            lottery.ok().flatten().map_or(false, |lottery| lottery.draw(signer.as_ref()).is_some())
+        });
+    }
+
+    /// Publish again the transactions submitted to this node, in case peers missed or dropped them
+    fn rebroadcast_locals(&mut self) {
+        let locals = self.tx_pool.read().unwrap().pending_locals();
+        if !locals.is_empty() {
+            info!("Rebroadcast {} local transactions", locals.len());
+        }
+        for tx in locals {
+            manager::publish_transaction(&mut self.network_send, tx);
+        }
+    }
+
+    /// Add the proposer signature to blocks the consensus engine left unsigned
+    fn sign_block(&self, mut b: Block) -> Result<Block, Error> {
+        if !b.get_signs().is_empty() {
+            return Ok(b);
+        }
+        let h = b.hash();
+        let signs = self.signer.sign_header(&b.header)?;
+        b.add_proof(BlockProof::new(0, &self.pubkey.to_bytes()));
+        b.add_verify_item(VerificationItem::new(h, signs));
+        b.set_sign_hash(block::get_hash_from_signs(b.get_signs()));
+        Ok(b)
+    }
+
     #[allow(dead_code)]
     fn is_proposer(&self, sid: u64, state: Arc<RwLock<EpochPoS>>) -> bool {
         if state.read().unwrap().dev_node() {
@@ -626,8 +783,8 @@ impl EpochProposal {
             .get_slot_proposer(sid, sid / EPOCH_LENGTH)
         {
             let pk: Pubkey = Pubkey::from_bytes(&item.pubkey);
-            let is_proposer = self.myid.to_pubkey().unwrap().equal(&item.into());
-            info!("is_proposer:{}, my={} proposer={}", is_proposer, self.myid.to_pubkey().unwrap(), pk);
+            let is_proposer = self.pubkey.equal(&item.into());
+            info!("is_proposer:{}, my={} proposer={}", is_proposer, self.pubkey, pk);
             is_proposer
         } else {
             false
@@ -635,25 +792,37 @@ impl EpochProposal {
     }
 }
 
-
+fn proposal_state(chain_peers: usize, min_peers: usize) -> ProposalState {
+    if chain_peers < min_peers {
+        ProposalState::InsufficientPeers
+    } else {
+        ProposalState::Active
+    }
+}
 
 #[cfg(test)]
 pub mod tests {
     use tokio::prelude::*;
-    use tokio;
-    use super::{SlotTick, duration_now};
+    use map_network::sync::ProposalState;
+    use super::{SlotTick, duration_now, proposal_state};
+
+    #[test]
+    fn min_peers() {
+        assert_eq!(proposal_state(0, 1), ProposalState::InsufficientPeers);
+        assert_eq!(proposal_state(1, 1), ProposalState::Active);
+        // Dev mode requires none
+        assert_eq!(proposal_state(0, 0), ProposalState::Active);
+    }
 
     #[test]
     fn slot_tick() {
-        let tick = SlotTick::new(1, duration_now())
+        let slots = SlotTick::new(1, duration_now())
             .take(2)
+            .collect()
             .map_err(move |_| {
                 println!("tick error");
-            })
-            .for_each(|instant| {
-                println!("tick instant {:?}", instant);
-                Ok(())
             });
-        tokio::run(tick);
+        let slots = tokio::runtime::Runtime::new().unwrap().block_on(slots).unwrap();
+        assert_eq!(slots[1], slots[0] + 1);
     }
 }
@@ -24,4 +24,6 @@ extern crate futures;
 
 pub mod apos;
 pub mod epoch;
+pub mod missed;
+pub mod signer;
 pub mod types;
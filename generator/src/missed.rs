@@ -0,0 +1,110 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of slots the local validator was elected for but no block appeared.
+//!
+//! Slot election is a private VRF draw, so only the validator itself knows it was elected. Each
+//! node checks its own key, including the slots it was offline for once it comes back.
+use std::cmp;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use map_core::types::Address;
+use crate::epoch::EPOCH_LENGTH;
+
+/// Slots checked after a restart at most, older downtime isn't backfilled.
+pub const MAX_MISSED_SLOT_BACKFILL: u64 = 4 * EPOCH_LENGTH;
+
+/// Slots of the canonical blocks with a slot not below `from`.
+pub fn included_slots(chain: &BlockChain, from: u64) -> HashSet<u64> {
+    let mut slots = HashSet::new();
+    let mut height = chain.current_block().height();
+    while height > 0 {
+        let header = match chain.get_header_by_number(height) {
+            Some(h) => h,
+            None => break,
+        };
+        if header.slot < from {
+            break;
+        }
+        slots.insert(header.slot);
+        height -= 1;
+    }
+    slots
+}
+
+/// Slots of `from..=to` without a block for which `elected` holds.
+pub fn missed_slots<F: Fn(u64) -> bool>(from: u64, to: u64, included: &HashSet<u64>, elected: F) -> Vec<u64> {
+    (from..=to).filter(|slot| !included.contains(slot) && elected(*slot)).collect()
+}
+
+/// First slot to check when slot `current` starts, given the last checked one.
+pub fn check_from(checkpoint: Option<u64>, current: u64) -> u64 {
+    let last = current.saturating_sub(1);
+    match checkpoint {
+        Some(checked) => cmp::max(checked + 1, last.saturating_sub(MAX_MISSED_SLOT_BACKFILL)),
+        // Nothing known about earlier slots on the first run
+        None => last,
+    }
+}
+
+/// Check the slots up to the one before `current` and record those `validator` missed.
+/// `elected` may lock the chain itself, so it's evaluated without holding the chain lock.
+pub fn record_missed<F: Fn(u64) -> bool>(chain: &Arc<TimedRwLock<BlockChain>>, validator: Address, current: u64, elected: F) {
+    if current == 0 {
+        return;
+    }
+    let to = current - 1;
+    let (from, included) = {
+        let chain = chain.read().expect("acquiring chain read lock");
+        let from = check_from(chain.missed_proposal_checkpoint(), current);
+        (from, included_slots(&chain, from))
+    };
+    if from > to {
+        return;
+    }
+
+    let missed = missed_slots(from, to, &included, elected);
+    let mut chain = chain.write().expect("acquiring chain write lock");
+    for slot in missed {
+        warn!("missed proposal slot={} epoch={} validator={}", slot, slot / EPOCH_LENGTH, validator);
+        chain.record_missed_proposal(slot / EPOCH_LENGTH, validator, slot);
+    }
+    chain.set_missed_proposal_checkpoint(to);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missed_slots() {
+        let included: HashSet<u64> = vec![3, 5].into_iter().collect();
+        let elected = |slot: u64| slot % 2 == 1;
+        assert_eq!(missed_slots(1, 8, &included, elected), vec![1, 7]);
+    }
+
+    #[test]
+    fn test_check_from() {
+        assert_eq!(check_from(None, 10), 9);
+        assert_eq!(check_from(Some(7), 10), 8);
+        assert_eq!(check_from(Some(9), 10), 10);
+        let current = 10 * EPOCH_LENGTH;
+        assert_eq!(check_from(Some(1), current), current - 1 - MAX_MISSED_SLOT_BACKFILL);
+    }
+}
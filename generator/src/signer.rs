@@ -0,0 +1,167 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Keys of the block proposer. The proposer only needs signatures and VRF proofs, so the key can
+//! be kept off the node by an external signer.
+use std::fmt::Display;
+use serde::Deserialize;
+use serde_json::json;
+use ed25519::{privkey::PrivKey, pubkey::Pubkey, signature::SignatureInfo};
+use errors::{Error, InternalErrorKind};
+use map_core::block::Header;
+use map_crypto::vrf;
+use map_rpc_client::RpcClient;
+
+/// Signs for the block proposer.
+pub trait BlockSigner: Send + Sync {
+    /// Public key of the validator, the one registered in the staking state.
+    fn pubkey(&self) -> Pubkey;
+
+    /// Sign the hash of a produced header.
+    fn sign_header(&self, header: &Header) -> Result<SignatureInfo, Error>;
+
+    /// VRF output of `input` along with its proof, electing the slot proposers.
+    fn vrf_evaluate(&self, input: &[u8]) -> Result<(vrf::Value, vrf::Proof), Error>;
+}
+
+/// Signer with the key in the node's memory.
+pub struct LocalSigner {
+    key: PrivKey,
+    pubkey: Pubkey,
+}
+
+impl LocalSigner {
+    pub fn new(key: PrivKey) -> Self {
+        LocalSigner {
+            key,
+            pubkey: key.to_pubkey().expect("pub key ok"),
+        }
+    }
+}
+
+impl BlockSigner for LocalSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign_header(&self, header: &Header) -> Result<SignatureInfo, Error> {
+        self.key.sign(header.hash().to_slice())
+    }
+
+    fn vrf_evaluate(&self, input: &[u8]) -> Result<(vrf::Value, vrf::Proof), Error> {
+        let secret_key = vrf::convert_secret_key(&self.key.to_bytes());
+        Ok(secret_key.compute_vrf_with_proof(&input))
+    }
+}
+
+#[derive(Deserialize)]
+struct VrfJson {
+    value: String,
+    proof: String,
+}
+
+/// Signer reached over http json rpc. It answers `signer_pubkey`, `signer_signHeader` with the
+/// header hash, height and slot, and `signer_vrf` with the hex input. Answers are checked
+/// against the public key loaded on connect.
+pub struct RemoteSigner {
+    client: RpcClient,
+    pubkey: Pubkey,
+}
+
+impl RemoteSigner {
+    /// Connect to the signer at `url` and load its public key.
+    pub fn connect(url: &str) -> Result<Self, Error> {
+        let client = RpcClient::new(url).map_err(remote_error)?;
+        let pubkey: String = client.call("signer_pubkey", json!([])).map_err(remote_error)?;
+        let pubkey = Pubkey::from_bytes(&decode_bytes(&pubkey, 32)?);
+        info!("using remote signer url={} pk={}", url, pubkey);
+        Ok(RemoteSigner { client, pubkey })
+    }
+}
+
+impl BlockSigner for RemoteSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign_header(&self, header: &Header) -> Result<SignatureInfo, Error> {
+        let hash = header.hash();
+        let signs: String = self.client.call("signer_signHeader", json!([hash, header.height, header.slot]))
+            .map_err(remote_error)?;
+        let signs = SignatureInfo::from_slice(&decode_bytes(&signs, 96)?)?;
+        self.pubkey.verify(&hash.to_msg(), &signs)
+            .map_err(|_| remote_error("header signed with another key"))?;
+        Ok(signs)
+    }
+
+    fn vrf_evaluate(&self, input: &[u8]) -> Result<(vrf::Value, vrf::Proof), Error> {
+        let output: VrfJson = self.client.call("signer_vrf", json!([format!("0x{}", hex::encode(input))]))
+            .map_err(remote_error)?;
+        let mut value = vrf::Value([0u8; 32]);
+        value.0.copy_from_slice(&decode_bytes(&output.value, 32)?);
+        let mut proof = vrf::Proof([0u8; 64]);
+        proof.0.copy_from_slice(&decode_bytes(&output.proof, 64)?);
+
+        let valid = vrf::convert_public_key(&self.pubkey.to_bytes())
+            .map_or(false, |pk| pk.is_vrf_valid(&input, &value, &proof));
+        if !valid {
+            return Err(remote_error("invalid vrf proof"));
+        }
+        Ok((value, proof))
+    }
+}
+
+fn decode_bytes(text: &str, len: usize) -> Result<Vec<u8>, Error> {
+    let bytes = hex::decode(text.trim_start_matches("0x"))
+        .map_err(|e| remote_error(format!("invalid hex {}", e)))?;
+    if bytes.len() != len {
+        return Err(remote_error(format!("expected {} bytes, got {}", len, bytes.len())));
+    }
+    Ok(bytes)
+}
+
+fn remote_error<S: Display>(e: S) -> Error {
+    InternalErrorKind::Other(format!("remote signer: {}", e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519::generator::create_key;
+
+    #[test]
+    fn local_signer() {
+        let (key, pubkey) = create_key();
+        let signer = LocalSigner::new(key);
+        assert_eq!(signer.pubkey(), pubkey);
+
+        let header = Header { height: 3, ..Default::default() };
+        let signs = signer.sign_header(&header).unwrap();
+        assert!(pubkey.verify(&header.hash().to_msg(), &signs).is_ok());
+
+        let (value, proof) = signer.vrf_evaluate(b"seed").unwrap();
+        let vrf_key = vrf::convert_public_key(&pubkey.to_bytes()).unwrap();
+        assert!(vrf_key.is_vrf_valid(&&b"seed"[..], &value, &proof));
+    }
+
+    #[test]
+    fn decode_signer_bytes() {
+        assert_eq!(decode_bytes("0x0102", 2).unwrap(), vec![1, 2]);
+        assert!(decode_bytes("0x0102", 3).is_err());
+        assert!(decode_bytes("0xzz", 1).is_err());
+    }
+}
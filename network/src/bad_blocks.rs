@@ -0,0 +1,158 @@
+//! Bookkeeping for gossiped blocks that were forwarded before execution and turned out invalid.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use lru::LruCache;
+
+use map_core::types::{Address, Hash};
+
+/// The number of invalid and reported block hashes remembered.
+const BAD_BLOCK_CACHE_SIZE: usize = 1024;
+/// How long blocks from the proposer of an invalid block are dropped.
+pub const PROPOSER_BAN_DURATION: Duration = Duration::from_secs(3600);
+/// Reports of blocks we imported after which the reporting peer is banned.
+pub const MAX_FALSE_REPORTS: u32 = 3;
+/// How long a peer reporting valid blocks as bad is banned.
+pub const FALSE_REPORT_BAN_DURATION: Duration = Duration::from_secs(3600);
+
+/// Outcome of a peer reporting a block as bad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BadBlockReport {
+    /// We rejected the block too and had not forwarded a report for it yet.
+    Confirmed,
+    /// Already forwarded, or not executed here. Not forwarded.
+    Ignored,
+    /// We imported the block, the report is false.
+    False,
+    /// False, and the peer reached `MAX_FALSE_REPORTS` and is to be banned.
+    Ban,
+}
+
+pub struct BadBlocks {
+    /// Blocks that failed local execution, never imported or forwarded again.
+    invalid: LruCache<Hash, ()>,
+    /// Blocks advertised as bad by peers, only forwarded once executed locally.
+    reported: LruCache<Hash, ()>,
+    /// Invalid blocks a peer report was forwarded for.
+    forwarded: LruCache<Hash, ()>,
+    /// Reports of imported blocks by each peer.
+    false_reports: LruCache<PeerId, u32>,
+    /// Proposers of invalid blocks and the end of their ban.
+    banned_proposers: HashMap<Address, Instant>,
+}
+
+impl BadBlocks {
+    pub fn new() -> Self {
+        BadBlocks {
+            invalid: LruCache::new(BAD_BLOCK_CACHE_SIZE),
+            reported: LruCache::new(BAD_BLOCK_CACHE_SIZE),
+            forwarded: LruCache::new(BAD_BLOCK_CACHE_SIZE),
+            false_reports: LruCache::new(BAD_BLOCK_CACHE_SIZE),
+            banned_proposers: HashMap::new(),
+        }
+    }
+
+    /// Record a block failing execution, banning its proposer if known.
+    pub fn insert_invalid(&mut self, hash: Hash, proposer: Option<Address>) {
+        self.invalid.put(hash, ());
+        if let Some(proposer) = proposer {
+            self.banned_proposers.insert(proposer, Instant::now() + PROPOSER_BAN_DURATION);
+        }
+    }
+
+    /// Record a block advertised as bad by `peer_id`, `imported` telling whether we executed it
+    /// successfully. Only reports of blocks we rejected ourselves are forwarded, once.
+    pub fn report(&mut self, peer_id: &PeerId, hash: Hash, imported: bool) -> BadBlockReport {
+        if self.invalid.contains(&hash) {
+            if self.forwarded.contains(&hash) {
+                return BadBlockReport::Ignored;
+            }
+            self.forwarded.put(hash, ());
+            return BadBlockReport::Confirmed;
+        }
+        if imported {
+            let reports = self.false_reports.get(peer_id).cloned().unwrap_or(0) + 1;
+            if reports >= MAX_FALSE_REPORTS {
+                // Counting starts over should the peer come back after its ban
+                self.false_reports.pop(peer_id);
+                return BadBlockReport::Ban;
+            }
+            self.false_reports.put(peer_id.clone(), reports);
+            return BadBlockReport::False;
+        }
+        self.reported.put(hash, ());
+        BadBlockReport::Ignored
+    }
+
+    pub fn is_invalid(&self, hash: &Hash) -> bool {
+        self.invalid.contains(hash)
+    }
+
+    pub fn is_reported(&self, hash: &Hash) -> bool {
+        self.reported.contains(hash)
+    }
+
+    /// Whether blocks from `proposer` are dropped, expired bans are lifted.
+    pub fn is_banned(&mut self, proposer: &Address) -> bool {
+        match self.banned_proposers.get(proposer) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                self.banned_proposers.remove(proposer);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_block_bans_proposer() {
+        let mut bad = BadBlocks::new();
+        let hash = Hash([1u8; 32]);
+        let proposer = Address([2u8; 20]);
+        bad.insert_invalid(hash, Some(proposer));
+        assert!(bad.is_invalid(&hash));
+        assert!(bad.is_banned(&proposer));
+        assert!(!bad.is_banned(&Address([3u8; 20])));
+    }
+
+    #[test]
+    fn forwarded_once() {
+        let mut bad = BadBlocks::new();
+        let peer = PeerId::random();
+        let hash = Hash([1u8; 32]);
+        // Not executed here, deferred but not forwarded
+        assert_eq!(bad.report(&peer, hash, false), BadBlockReport::Ignored);
+        assert!(bad.is_reported(&hash));
+        assert!(!bad.is_invalid(&hash));
+
+        bad.insert_invalid(hash, None);
+        assert_eq!(bad.report(&peer, hash, false), BadBlockReport::Confirmed);
+        assert_eq!(bad.report(&peer, hash, false), BadBlockReport::Ignored);
+    }
+
+    #[test]
+    fn false_reports_ban() {
+        let mut bad = BadBlocks::new();
+        let peer = PeerId::random();
+        for i in 1..MAX_FALSE_REPORTS {
+            assert_eq!(bad.report(&peer, Hash([i as u8; 32]), true), BadBlockReport::False);
+        }
+        assert_eq!(bad.report(&peer, Hash([0u8; 32]), true), BadBlockReport::Ban);
+        assert!(bad.false_reports.is_empty());
+    }
+
+    #[test]
+    fn ban_expires() {
+        let mut bad = BadBlocks::new();
+        let proposer = Address([2u8; 20]);
+        bad.banned_proposers.insert(proposer, Instant::now());
+        assert!(!bad.is_banned(&proposer));
+        assert!(bad.banned_proposers.is_empty());
+    }
+}
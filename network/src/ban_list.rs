@@ -0,0 +1,158 @@
+//! Persists peer bans in the network directory so they survive a restart.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn};
+
+const BAN_LIST_FILENAME: &str = "banned_peers";
+
+/// A ban as stored on disk.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BanEntry {
+    /// Base58 encoded peer id.
+    pub peer_id: String,
+    pub reason: String,
+    /// Unix time in seconds at which the ban expires.
+    pub until: u64,
+}
+
+pub struct BanList {
+    path: PathBuf,
+    entries: HashMap<PeerId, BanEntry>,
+    log: slog::Logger,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl BanList {
+    /// Loads the ban list from `network_dir`, dropping expired and malformed entries.
+    pub fn load(network_dir: &Path, log: slog::Logger) -> Self {
+        let path = network_dir.join(BAN_LIST_FILENAME);
+        let stored: Vec<BanEntry> = match fs::read(&path) {
+            Ok(data) => bincode::deserialize(&data).unwrap_or_else(|e| {
+                warn!(log, "Could not decode ban list"; "file" => format!("{:?}", path), "error" => format!("{}", e));
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        let now = unix_now();
+        let mut entries = HashMap::new();
+        for entry in stored {
+            if entry.until <= now {
+                continue;
+            }
+            match entry.peer_id.parse::<PeerId>() {
+                Ok(peer_id) => {
+                    entries.insert(peer_id, entry);
+                }
+                Err(_) => debug!(log, "Skipping invalid banned peer id"; "peer_id" => &entry.peer_id),
+            }
+        }
+
+        BanList { path, entries, log }
+    }
+
+    /// Records a ban of `peer_id` for `duration` and writes the list to disk.
+    pub fn insert(&mut self, peer_id: PeerId, reason: &str, duration: Duration) {
+        let entry = BanEntry {
+            peer_id: peer_id.to_base58(),
+            reason: reason.to_string(),
+            until: unix_now() + duration.as_secs(),
+        };
+        self.entries.insert(peer_id, entry);
+        self.save();
+    }
+
+    /// Removes the ban of `peer_id`, returns false if the peer was not banned.
+    pub fn remove(&mut self, peer_id: &PeerId) -> bool {
+        if self.entries.remove(peer_id).is_none() {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.entries.contains_key(peer_id)
+    }
+
+    /// Returns the banned peers along with the time left on their ban.
+    pub fn active(&self) -> Vec<(PeerId, Duration)> {
+        let now = unix_now();
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.until > now)
+            .map(|(peer_id, entry)| (peer_id.clone(), Duration::from_secs(entry.until - now)))
+            .collect()
+    }
+
+    fn save(&self) {
+        let entries: Vec<&BanEntry> = self.entries.values().collect();
+        let data = bincode::serialize(&entries).expect("serialize ban list");
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = fs::write(&self.path, data) {
+            warn!(self.log, "Could not write ban list"; "file" => format!("{:?}", self.path), "error" => format!("{}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::o;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("map_ban_list_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn null_log() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    #[test]
+    fn ban_list_reload() {
+        let dir = test_dir("reload");
+        let banned = PeerId::random();
+        let unbanned = PeerId::random();
+        {
+            let mut list = BanList::load(&dir, null_log());
+            list.insert(banned.clone(), "manual", Duration::from_secs(3600));
+            list.insert(unbanned.clone(), "manual", Duration::from_secs(3600));
+            assert!(list.remove(&unbanned));
+            assert!(!list.remove(&unbanned));
+        }
+
+        let list = BanList::load(&dir, null_log());
+        assert!(list.contains(&banned));
+        assert!(!list.contains(&unbanned));
+        let active = list.active();
+        assert_eq!(active.len(), 1);
+        assert!(active[0].1 <= Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn ban_list_drops_expired() {
+        let dir = test_dir("expired");
+        let peer_id = PeerId::random();
+        {
+            let mut list = BanList::load(&dir, null_log());
+            list.insert(peer_id.clone(), "goodbye", Duration::from_secs(0));
+        }
+        let list = BanList::load(&dir, null_log());
+        assert!(!list.contains(&peer_id));
+    }
+}
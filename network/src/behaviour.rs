@@ -18,9 +18,10 @@ use lru::LruCache;
 use sha2::{Digest, Sha256};
 use slog::{debug, o};
 
-use crate::{error};
+use crate::{error, NetworkConfig, AGENT_VERSION};
 use crate::{GossipTopic, Topic, TopicHash};
 use crate::p2p::{P2P, P2PEvent, P2PMessage};
+use crate::topics::{MAP_BAD_BLOCK_TOPIC, MAP_BLOCK_TOPIC, MAP_TRANSACTION_TOPIC};
 
 const MAX_IDENTIFY_ADDRESSES: usize = 20;
 
@@ -55,6 +56,8 @@ pub struct Behaviour<TSubstream: AsyncRead + AsyncWrite> {
 impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
     pub fn new(
         local_key: &Keypair,
+        net_conf: &NetworkConfig,
+        protocol_version: String,
         log: &slog::Logger,
     ) -> error::Result<Self> {
         let local_peer_id = local_key.public().into_peer_id();
@@ -66,8 +69,8 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
             .with_keep_alive(false);
 
         let identify = Identify::new(
-            "map/p2p".into(),
-            "0.1".to_string(),
+            protocol_version,
+            AGENT_VERSION.to_string(),
             local_key.public(),
         );
 
@@ -96,7 +99,7 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
                 .message_id_fn(gossip_message_id)
                 .heartbeat_interval(Duration::from_secs(20))
                 .build()),
-            p2p: P2P::new(log.clone()),
+            p2p: P2P::new(net_conf.max_inbound_requests, log.clone()),
             ping: Ping::new(ping_config),
             mdns: Mdns::new().expect("Failed to create mDNS service"),
             kademlia,
@@ -136,13 +139,13 @@ for Behaviour<TSubstream>
             }
 
             GossipsubEvent::Subscribed { peer_id, topic } => {
-                println!(
-                    "gossipsub: peer_id {} topic {:?}",
-                    peer_id.to_base58(),
-                    topic
-                );
+                debug!(self.log, "Peer subscribed"; "peer" => format!("{}", peer_id), "topic" => topic.as_str());
+                self.events.push(BehaviourEvent::Subscribed { peer_id, topic });
+            }
+            GossipsubEvent::Unsubscribed { peer_id, topic } => {
+                debug!(self.log, "Peer unsubscribed"; "peer" => format!("{}", peer_id), "topic" => topic.as_str());
+                self.events.push(BehaviourEvent::Unsubscribed { peer_id, topic });
             }
-            GossipsubEvent::Unsubscribed { .. } => {}
         }
     }
 }
@@ -295,6 +298,11 @@ for Behaviour<TSubstream>
                 "observed_address" => format!("{:?}", observed_addr),
                 "protocols" => format!("{:?}", info.protocols)
                 );
+                self.events.push(BehaviourEvent::Identified {
+                    peer_id,
+                    agent_version: info.agent_version,
+                    protocol_version: info.protocol_version,
+                });
             }
             IdentifyEvent::Sent { .. } => {}
             IdentifyEvent::Error { .. } => {}
@@ -367,6 +375,22 @@ pub enum BehaviourEvent {
         peer_id: PeerId,
         addrs: Addresses,
     },
+    /// A peer subscribed to a gossip topic.
+    Subscribed {
+        peer_id: PeerId,
+        topic: TopicHash,
+    },
+    /// A peer unsubscribed from a gossip topic.
+    Unsubscribed {
+        peer_id: PeerId,
+        topic: TopicHash,
+    },
+    /// A peer answered the identify protocol.
+    Identified {
+        peer_id: PeerId,
+        agent_version: String,
+        protocol_version: String,
+    },
 }
 
 /// Messages that are passed to and from the pubsub (Gossipsub) behaviour. These are encoded and
@@ -377,6 +401,8 @@ pub enum PubsubMessage {
     Block(Vec<u8>),
     /// Transaction message providing notification of a new external transaction.
     Transaction(Vec<u8>),
+    /// Hash of a block that was forwarded before execution and failed it.
+    BadBlock(Vec<u8>),
     /// Gossipsub message from an unknown topic.
     Unknown(Vec<u8>),
 }
@@ -393,6 +419,7 @@ impl PubsubMessage {
             match GossipTopic::from(topic.as_str()) {
                 GossipTopic::MapBlock => return PubsubMessage::Block(data),
                 GossipTopic::Transaction => return PubsubMessage::Transaction(data),
+                GossipTopic::BadBlock => return PubsubMessage::BadBlock(data),
                 GossipTopic::Shard => return PubsubMessage::Unknown(data),
                 GossipTopic::Unknown(_) => continue,
             }
@@ -400,10 +427,31 @@ impl PubsubMessage {
         PubsubMessage::Unknown(data)
     }
 
+    /// Encoded message as received.
+    pub fn data(&self) -> &[u8] {
+        match self {
+            PubsubMessage::Block(data)
+            | PubsubMessage::Transaction(data)
+            | PubsubMessage::BadBlock(data)
+            | PubsubMessage::Unknown(data) => data,
+        }
+    }
+
+    /// Name of the topic, used as metrics label.
+    pub fn topic_name(&self) -> &'static str {
+        match self {
+            PubsubMessage::Block(_) => MAP_BLOCK_TOPIC,
+            PubsubMessage::Transaction(_) => MAP_TRANSACTION_TOPIC,
+            PubsubMessage::BadBlock(_) => MAP_BAD_BLOCK_TOPIC,
+            PubsubMessage::Unknown(_) => "unknown",
+        }
+    }
+
     fn into_data(self) -> Vec<u8> {
         match self {
             PubsubMessage::Block(data)
             | PubsubMessage::Transaction(data)
+            | PubsubMessage::BadBlock(data)
             | PubsubMessage::Unknown(data) => data,
         }
     }
@@ -0,0 +1,298 @@
+//! Gossip blocks received ahead of the head, imported once the chain reaches their height.
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use chain::blockchain::BlockChain;
+use map_core::block::{Block, Header};
+use map_core::types::Hash;
+
+/// Heights above the head a gossip block is queued for, further ones are left to sync.
+pub const QUEUE_HEIGHT_WINDOW: u64 = 512;
+/// How long a queued block waits for the chain to reach it.
+pub const QUEUED_BLOCK_TTL: Duration = Duration::from_secs(600);
+/// Queued blocks held in memory, the furthest ones beyond it wait in the chain db.
+pub const MAX_QUEUED_IN_MEMORY: usize = 64;
+/// Queued blocks in memory and in the chain db, the furthest one makes room for a nearer block.
+pub const MAX_QUEUED_BLOCKS: usize = 1024;
+/// Competing blocks queued at one height.
+pub const MAX_QUEUED_PER_HEIGHT: usize = 4;
+/// Recent competing blocks at or below the head kept to be shown by `debug_chainTree`.
+pub const MAX_FORK_HEADERS: usize = 64;
+
+pub type SharedBlockQueue = Arc<RwLock<BlockQueue>>;
+
+struct Queued {
+    received: Instant,
+    /// None once spilled to the chain db.
+    block: Option<Block>,
+}
+
+pub struct BlockQueue {
+    /// Queued blocks by height and hash, lowest first.
+    blocks: BTreeMap<(u64, Hash), Queued>,
+    /// Queued blocks not spilled.
+    in_memory: usize,
+    /// Headers of the latest non-canonical blocks received at or below the head, oldest first.
+    forks: VecDeque<Header>,
+}
+
+impl BlockQueue {
+    /// Queue holding the blocks spilled before a restart, within the limits.
+    pub fn new(chain: &mut BlockChain) -> Self {
+        let now = Instant::now();
+        let mut queue = BlockQueue { blocks: BTreeMap::new(), in_memory: 0, forks: VecDeque::new() };
+        for key in chain.queued_blocks() {
+            if queue.has_room(key.0) {
+                queue.blocks.insert(key, Queued { received: now, block: None });
+            } else {
+                chain.take_queued_block(key.0, &key.1);
+            }
+        }
+        queue
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Headers of the queued blocks held in memory and of the recent competing blocks at or
+    /// below the head.
+    pub fn headers(&self) -> Vec<Header> {
+        self.blocks.values()
+            .filter_map(|queued| queued.block.as_ref().map(|block| block.header.clone()))
+            .chain(self.forks.iter().cloned())
+            .collect()
+    }
+
+    /// Height and hash of the queued blocks spilled to the chain db.
+    pub fn spilled(&self) -> Vec<(u64, Hash)> {
+        self.blocks.iter().filter(|(_, queued)| queued.block.is_none()).map(|(key, _)| *key).collect()
+    }
+
+    /// Queue a block above the head, false if it is already queued, outside the window or its
+    /// height is full.
+    pub fn push(&mut self, chain: &mut BlockChain, block: Block) -> bool {
+        self.push_at(chain, block, Instant::now())
+    }
+
+    fn push_at(&mut self, chain: &mut BlockChain, block: Block, now: Instant) -> bool {
+        let head = chain.current_block().height();
+        let height = block.height();
+        if height <= head {
+            self.note_fork(chain, block.header);
+            return false;
+        }
+        if height > head + QUEUE_HEIGHT_WINDOW {
+            return false;
+        }
+        let key = (height, block.hash());
+        if self.blocks.contains_key(&key) || !self.has_room(height) {
+            return false;
+        }
+        if self.blocks.len() >= MAX_QUEUED_BLOCKS {
+            let furthest = *self.blocks.keys().next_back().expect("queue is full");
+            self.remove(chain, &furthest);
+        }
+        self.blocks.insert(key, Queued { received: now, block: Some(block) });
+        self.in_memory += 1;
+
+        if self.in_memory > MAX_QUEUED_IN_MEMORY {
+            // The nearest blocks are imported first and stay at hand
+            if let Some(furthest) = self.blocks.values_mut().rev().find_map(|queued| queued.block.take()) {
+                chain.spill_queued_block(&furthest);
+                self.in_memory -= 1;
+            }
+        }
+        true
+    }
+
+    /// Next block to import on top of the head. Blocks the head went past or queued for longer
+    /// than `QUEUED_BLOCK_TTL` are dropped.
+    pub fn pop_next(&mut self, chain: &mut BlockChain) -> Option<Block> {
+        self.pop_next_at(chain, Instant::now())
+    }
+
+    fn pop_next_at(&mut self, chain: &mut BlockChain, now: Instant) -> Option<Block> {
+        let head = chain.current_block().height();
+        let stale: Vec<(u64, Hash)> = self.blocks
+            .iter()
+            .filter(|((height, _), queued)| *height <= head || now.duration_since(queued.received) >= QUEUED_BLOCK_TTL)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in stale {
+            if let Some(block) = self.remove(chain, &key) {
+                if key.0 <= head {
+                    self.note_fork(chain, block.header);
+                }
+            }
+        }
+
+        loop {
+            let key = *self.blocks.keys().next()?;
+            if key.0 != head + 1 {
+                return None;
+            }
+            // A spilled block missing from the db is skipped
+            if let Some(block) = self.remove(chain, &key) {
+                return Some(block);
+            }
+        }
+    }
+
+    /// Whether a block of `height` fits: its height is not full, and the queue is not full or
+    /// holds a block above it to give way.
+    fn has_room(&self, height: u64) -> bool {
+        let at_height = self.blocks.range((height, Hash::default())..=(height, Hash([0xff; 32]))).count();
+        let furthest = self.blocks.keys().next_back().map_or(0, |key| key.0);
+        at_height < MAX_QUEUED_PER_HEIGHT && (self.blocks.len() < MAX_QUEUED_BLOCKS || furthest > height)
+    }
+
+    /// Keep the header of a block at or below the head unless it is the canonical one.
+    fn note_fork(&mut self, chain: &BlockChain, header: Header) {
+        let hash = header.hash();
+        let canonical = chain.get_header_by_number(header.height).map(|h| h.hash());
+        if canonical == Some(hash) || self.forks.iter().any(|fork| fork.hash() == hash) {
+            return;
+        }
+        if self.forks.len() >= MAX_FORK_HEADERS {
+            self.forks.pop_front();
+        }
+        self.forks.push_back(header);
+    }
+
+    fn remove(&mut self, chain: &mut BlockChain, key: &(u64, Hash)) -> Option<Block> {
+        let queued = self.blocks.remove(key)?;
+        match queued.block {
+            Some(block) => {
+                self.in_memory -= 1;
+                Some(block)
+            }
+            None => chain.take_queued_block(key.0, &key.1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::test_utils::BlockBuilder;
+
+    fn chain() -> BlockChain {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        chain
+    }
+
+    /// Blocks at heights 1 to `count` on top of the head
+    fn blocks(chain: &BlockChain, count: usize) -> Vec<Block> {
+        let mut parent = chain.current_block();
+        (0..count).map(|_| {
+            parent = BlockBuilder::on(&parent).build();
+            parent.clone()
+        }).collect()
+    }
+
+    #[test]
+    fn window_and_order() {
+        let mut chain = chain();
+        let blocks = blocks(&chain, 2);
+        let mut queue = BlockQueue::new(&mut chain);
+        assert!(queue.push(&mut chain, blocks[1].clone()));
+        assert!(queue.push(&mut chain, blocks[0].clone()));
+        assert!(!queue.push(&mut chain, blocks[0].clone()));
+        let genesis = chain.current_block();
+        assert!(!queue.push(&mut chain, genesis));
+        let mut far = BlockBuilder::on(&blocks[1]).build();
+        far.header.height = QUEUE_HEIGHT_WINDOW + 1;
+        assert!(!queue.push(&mut chain, far));
+
+        assert_eq!(queue.pop_next(&mut chain), Some(blocks[0].clone()));
+        // The head is still at genesis, height 2 waits
+        assert_eq!(queue.pop_next(&mut chain), None);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn forks() {
+        let mut chain = chain();
+        let blocks = blocks(&chain, 1);
+        let mut queue = BlockQueue::new(&mut chain);
+        let genesis = chain.current_block();
+        assert!(queue.push(&mut chain, blocks[0].clone()));
+        assert_eq!(queue.headers(), vec![blocks[0].header.clone()]);
+
+        // A block competing with the head is not queued but stays visible, the canonical one is not kept
+        let mut competing = BlockBuilder::on(&genesis).slot(2).build();
+        competing.header.height = 0;
+        assert!(!queue.push(&mut chain, competing.clone()));
+        assert!(!queue.push(&mut chain, competing.clone()));
+        assert!(!queue.push(&mut chain, genesis));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.headers(), vec![blocks[0].header.clone(), competing.header.clone()]);
+        assert!(queue.spilled().is_empty());
+    }
+
+    #[test]
+    fn spill_and_restore() {
+        let mut chain = chain();
+        let blocks = blocks(&chain, MAX_QUEUED_IN_MEMORY + 1);
+        let mut queue = BlockQueue::new(&mut chain);
+        for block in blocks.iter().rev() {
+            assert!(queue.push(&mut chain, block.clone()));
+        }
+        let furthest = blocks.last().unwrap();
+        assert_eq!(queue.in_memory, MAX_QUEUED_IN_MEMORY);
+        assert_eq!(chain.queued_blocks(), vec![(furthest.height(), furthest.hash())]);
+        assert_eq!(queue.spilled(), chain.queued_blocks());
+
+        let mut restored = BlockQueue::new(&mut chain);
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored.remove(&mut chain, &(furthest.height(), furthest.hash())), Some(furthest.clone()));
+        assert!(chain.queued_blocks().is_empty());
+    }
+
+    #[test]
+    fn caps() {
+        let mut chain = chain();
+        let mut queue = BlockQueue::new(&mut chain);
+        let genesis = chain.current_block();
+        let competing: Vec<Block> = (0..MAX_QUEUED_PER_HEIGHT as u64 + 1)
+            .map(|slot| BlockBuilder::on(&genesis).slot(slot + 1).build())
+            .collect();
+        for block in &competing[..MAX_QUEUED_PER_HEIGHT] {
+            assert!(queue.push(&mut chain, block.clone()));
+        }
+        assert!(!queue.push(&mut chain, competing[MAX_QUEUED_PER_HEIGHT].clone()));
+
+        // A full queue gives up its furthest block for a nearer one only, height 2 is left free
+        let at = |height: u64, slot: u64| {
+            let mut block = BlockBuilder::on(&genesis).slot(slot).build();
+            block.header.height = height;
+            block
+        };
+        let mut i = 0;
+        while queue.len() < MAX_QUEUED_BLOCKS {
+            let height = 3 + i / MAX_QUEUED_PER_HEIGHT as u64;
+            assert!(queue.push(&mut chain, at(height, 1 + i % MAX_QUEUED_PER_HEIGHT as u64)));
+            i += 1;
+        }
+        let furthest = queue.blocks.keys().next_back().unwrap().0;
+        assert!(!queue.push(&mut chain, at(furthest + 1, 1)));
+        assert!(queue.push(&mut chain, at(2, 1)));
+        assert_eq!(queue.len(), MAX_QUEUED_BLOCKS);
+        assert_eq!(queue.blocks.keys().filter(|key| key.0 == furthest).count(), MAX_QUEUED_PER_HEIGHT - 1);
+    }
+
+    #[test]
+    fn expire() {
+        let mut chain = chain();
+        let blocks = blocks(&chain, 1);
+        let mut queue = BlockQueue::new(&mut chain);
+        let now = Instant::now();
+        assert!(queue.push_at(&mut chain, blocks[0].clone(), now));
+        assert_eq!(queue.pop_next_at(&mut chain, now + QUEUED_BLOCK_TTL), None);
+        assert_eq!(queue.len(), 0);
+    }
+}
@@ -3,13 +3,17 @@ use std::{
     net::Ipv4Addr,
     path::{PathBuf},
 };
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::str::FromStr;
 
-use libp2p::{identity::Keypair};
+use libp2p::{identity::Keypair, PeerId};
 use libp2p::{multiaddr, multiaddr::Multiaddr};
 use slog::{info};
 
+use crate::p2p::MAX_INBOUND_REQUESTS_PER_PEER;
+
 const NODE_KEY_FILENAME: &str = "nodekey";
 
 #[derive(Clone, Debug)]
@@ -26,6 +30,99 @@ pub struct Config {
 
     /// The cli dial addr.
     pub dial_addrs: Vec<Multiaddr>,
+
+    /// Connect over the in-process memory transport instead of TCP.
+    pub memory_transport: bool,
+
+    /// Maximum number of inbound p2p requests a single peer may have in flight.
+    pub max_inbound_requests: usize,
+
+    /// Protocol encrypting and authenticating the connections.
+    pub security: TransportSecurity,
+
+    /// Only peers of this list may connect or be dialed, empty for any peer.
+    pub allowed_peers: Vec<PeerId>,
+
+    /// Trace every p2p message to a file of the network dir from startup.
+    pub p2p_trace: bool,
+
+    /// Batch sizes and parallelism of the block downloads of range sync.
+    pub range_sync: RangeSyncConfig,
+}
+
+/// Download of the blocks of range sync, in batches of consecutive blocks asked to the peers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeSyncConfig {
+    /// Blocks asked in the first batch to a peer, the next ones follow its measured throughput.
+    pub batch_size: u64,
+    /// Largest batch asked to a fast peer.
+    pub max_batch_size: u64,
+    /// Batches downloaded or in flight ahead of the one being imported.
+    pub batch_buffer: usize,
+    /// Failed requests of a batch before the sync of the chain is abandoned.
+    pub batch_retries: u8,
+    /// Batch requests in flight to a single peer.
+    pub peer_requests: usize,
+}
+
+impl Default for RangeSyncConfig {
+    fn default() -> Self {
+        RangeSyncConfig {
+            batch_size: 5,
+            max_batch_size: 64,
+            batch_buffer: 5,
+            batch_retries: 5,
+            peer_requests: 1,
+        }
+    }
+}
+
+impl RangeSyncConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.batch_size == 0 || self.batch_buffer == 0 || self.peer_requests == 0 {
+            return Err("sync batch size, buffer and peer requests must be at least 1".to_string());
+        }
+        if self.max_batch_size < self.batch_size {
+            return Err(format!("sync max batch size {} is below the batch size {}", self.max_batch_size, self.batch_size));
+        }
+        Ok(())
+    }
+}
+
+/// Protocol encrypting and authenticating p2p connections. Both ends must use the same one,
+/// a connection to a peer on the other protocol fails its handshake.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportSecurity {
+    Secio,
+    /// Noise XX handshake over X25519 keys signed by the node key
+    Noise,
+}
+
+impl Default for TransportSecurity {
+    fn default() -> Self {
+        TransportSecurity::Secio
+    }
+}
+
+impl FromStr for TransportSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "secio" => Ok(TransportSecurity::Secio),
+            "noise" => Ok(TransportSecurity::Noise),
+            _ => Err(format!("unknown transport security {}, expected secio or noise", s)),
+        }
+    }
+}
+
+impl fmt::Display for TransportSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportSecurity::Secio => write!(f, "secio"),
+            TransportSecurity::Noise => write!(f, "noise"),
+        }
+    }
 }
 
 /// Generates a default Config.
@@ -42,6 +139,12 @@ impl Config {
             .chain(iter::once(multiaddr::Protocol::Tcp(p2p_port))).collect();
         Ok(())
     }
+
+    /// Listen on `/memory/<port>`, reachable only by nodes of the same process.
+    pub fn use_memory_transport(&mut self, port: u64) {
+        self.memory_transport = true;
+        self.listen_address = iter::once(multiaddr::Protocol::Memory(port)).collect();
+    }
 }
 
 impl Default for Config {
@@ -57,10 +160,67 @@ impl Default for Config {
             port: 40313,
             dial_addrs: vec![],
             listen_address,
+            memory_transport: false,
+            max_inbound_requests: MAX_INBOUND_REQUESTS_PER_PEER,
+            security: TransportSecurity::default(),
+            allowed_peers: vec![],
+            p2p_trace: false,
+            range_sync: RangeSyncConfig::default(),
         }
     }
 }
 
+/// Parses a peer address given on the command line or in a chain spec.
+///
+/// Accepts `/ip4`, `/ip6`, `/dns4` and `/dns6` hosts followed by `/tcp/<port>` and an optional
+/// `/p2p/<peer id>`, or a `/memory/<port>` address. Surrounding whitespace and a trailing `/` are
+/// dropped. Dns names are resolved by the transport when the address is dialed.
+pub fn parse_dial_addr(text: &str) -> Result<Multiaddr, String> {
+    let text = text.trim().trim_end_matches('/');
+    if text.is_empty() {
+        return Err("empty address".to_string());
+    }
+    let addr: Multiaddr = text.parse()
+        .map_err(|e| format!("invalid multiaddr {}: {}, expected e.g. /ip4/1.2.3.4/tcp/40313 or /dns4/host/tcp/40313", text, e))?;
+
+    let mut protocols = addr.iter();
+    match protocols.next() {
+        Some(multiaddr::Protocol::Memory(_)) => return Ok(addr),
+        Some(multiaddr::Protocol::Ip4(_)) | Some(multiaddr::Protocol::Ip6(_)) |
+        Some(multiaddr::Protocol::Dns4(_)) | Some(multiaddr::Protocol::Dns6(_)) => {}
+        _ => return Err(format!("unsupported address {}, must start with /ip4, /ip6, /dns4 or /dns6", text)),
+    }
+    match protocols.next() {
+        Some(multiaddr::Protocol::Tcp(_)) => {}
+        _ => return Err(format!("unsupported address {}, host must be followed by /tcp/<port>", text)),
+    }
+    match protocols.next() {
+        None | Some(multiaddr::Protocol::P2p(_)) => {}
+        Some(p) => return Err(format!("unsupported address {}, unexpected protocol {}", text, p)),
+    }
+    if protocols.next().is_some() {
+        return Err(format!("unsupported address {}, trailing protocols after /p2p", text));
+    }
+    Ok(addr)
+}
+
+/// Parses a comma separated list of peer addresses, see `parse_dial_addr`.
+pub fn parse_dial_addrs(text: &str) -> Result<Vec<Multiaddr>, String> {
+    text.split(',')
+        .filter(|addr| !addr.trim().is_empty())
+        .map(parse_dial_addr)
+        .collect()
+}
+
+/// Parses a comma separated list of base58 peer ids.
+pub fn parse_peer_ids(text: &str) -> Result<Vec<PeerId>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse::<PeerId>().map_err(|_| format!("invalid peer id {}", id)))
+        .collect()
+}
+
 /// Loads a private key from disk. If this fails, a new key is
 /// generated and is then saved to disk.
 ///
@@ -103,3 +263,36 @@ pub fn load_private_key(config: &Config, log: slog::Logger) -> Keypair {
     }
     node_private_key
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dial_addrs() {
+        let addrs = parse_dial_addrs(" /ip4/127.0.0.1/tcp/40313/, /dns4/boot.example.org/tcp/40313,").unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[0], "/ip4/127.0.0.1/tcp/40313".parse::<Multiaddr>().unwrap());
+        assert_eq!(addrs[1], "/dns4/boot.example.org/tcp/40313".parse::<Multiaddr>().unwrap());
+
+        assert!(parse_dial_addr("/memory/10").is_ok());
+        assert!(parse_dial_addr("/dns6/boot.example.org/tcp/1/p2p/QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC").is_ok());
+        assert!(parse_dial_addr("127.0.0.1:40313").is_err());
+        assert!(parse_dial_addr("/ip4/127.0.0.1").is_err());
+        assert!(parse_dial_addr("/tcp/40313").is_err());
+        assert!(parse_dial_addr("/ip4/127.0.0.1/udp/40313").is_err());
+        assert!(parse_dial_addr("").is_err());
+    }
+
+    #[test]
+    fn test_parse_security_and_peers() {
+        assert_eq!("noise".parse::<TransportSecurity>(), Ok(TransportSecurity::Noise));
+        assert_eq!(TransportSecurity::Secio.to_string(), "secio");
+        assert!("tls".parse::<TransportSecurity>().is_err());
+
+        let peers = parse_peer_ids("QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC, ").unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].to_base58(), "QmcgpsyWgH8Y8ajJz1Cu72KnS5uo2Aa2LpzU7kinSupNKC");
+        assert!(parse_peer_ids("not-a-peer").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Size and rate limits of the gossip messages received from each peer.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+
+use crate::behaviour::PubsubMessage;
+
+/// Window over which the messages of a peer are counted.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Violations after which a peer is banned.
+pub const MAX_GOSSIP_STRIKES: u32 = 10;
+/// How long a peer flooding gossip is banned.
+pub const GOSSIP_BAN_DURATION: Duration = Duration::from_secs(600);
+
+/// Limits of one topic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TopicLimit {
+    /// Largest encoded message accepted.
+    pub max_size: usize,
+    /// Messages accepted from one peer per second.
+    pub max_rate: u32,
+}
+
+/// Limit of each topic, messages of unknown topics share `unknown`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GossipLimits {
+    pub block: TopicLimit,
+    pub transaction: TopicLimit,
+    pub bad_block: TopicLimit,
+    pub unknown: TopicLimit,
+}
+
+impl Default for GossipLimits {
+    fn default() -> Self {
+        GossipLimits {
+            block: TopicLimit { max_size: 1_048_576, max_rate: 8 },
+            transaction: TopicLimit { max_size: 131_072, max_rate: 500 },
+            bad_block: TopicLimit { max_size: 32, max_rate: 8 },
+            unknown: TopicLimit { max_size: 1024, max_rate: 8 },
+        }
+    }
+}
+
+impl GossipLimits {
+    fn limit(&self, message: &PubsubMessage) -> TopicLimit {
+        match message {
+            PubsubMessage::Block(_) => self.block,
+            PubsubMessage::Transaction(_) => self.transaction,
+            PubsubMessage::BadBlock(_) => self.bad_block,
+            PubsubMessage::Unknown(_) => self.unknown,
+        }
+    }
+}
+
+/// Outcome of checking a gossip message against the limits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GossipCheck {
+    Accept,
+    /// Dropped for exceeding the size limit of its topic.
+    Oversized,
+    /// Dropped for exceeding the rate limit of its topic.
+    RateLimited,
+    /// Dropped, and the peer reached `MAX_GOSSIP_STRIKES` and is to be banned.
+    Ban,
+}
+
+/// Messages of a peer on a topic in the current window.
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+pub struct GossipLimiter {
+    limits: GossipLimits,
+    windows: HashMap<(PeerId, &'static str), Window>,
+    /// Violations of each peer while connected.
+    strikes: HashMap<PeerId, u32>,
+}
+
+impl GossipLimiter {
+    pub fn new(limits: GossipLimits) -> Self {
+        GossipLimiter {
+            limits,
+            windows: HashMap::new(),
+            strikes: HashMap::new(),
+        }
+    }
+
+    /// Check a message from `peer_id` before it is decoded.
+    pub fn check(&mut self, peer_id: &PeerId, message: &PubsubMessage) -> GossipCheck {
+        self.check_at(peer_id, message, Instant::now())
+    }
+
+    fn check_at(&mut self, peer_id: &PeerId, message: &PubsubMessage, now: Instant) -> GossipCheck {
+        let limit = self.limits.limit(message);
+        if message.data().len() > limit.max_size {
+            return self.strike(peer_id, GossipCheck::Oversized);
+        }
+
+        let window = self.windows
+            .entry((peer_id.clone(), message.topic_name()))
+            .or_insert(Window { start: now, count: 0 });
+        if now.duration_since(window.start) >= RATE_WINDOW {
+            window.start = now;
+            window.count = 0;
+        }
+        window.count += 1;
+        if window.count > limit.max_rate {
+            return self.strike(peer_id, GossipCheck::RateLimited);
+        }
+        GossipCheck::Accept
+    }
+
+    fn strike(&mut self, peer_id: &PeerId, check: GossipCheck) -> GossipCheck {
+        let strikes = self.strikes.entry(peer_id.clone()).or_insert(0);
+        *strikes += 1;
+        if *strikes >= MAX_GOSSIP_STRIKES {
+            // Counting starts over should the peer come back after its ban
+            self.remove_peer(peer_id);
+            return GossipCheck::Ban;
+        }
+        check
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.strikes.remove(peer_id);
+        self.windows.retain(|(peer, _), _| peer != peer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> GossipLimiter {
+        let mut limits = GossipLimits::default();
+        limits.transaction = TopicLimit { max_size: 4, max_rate: 2 };
+        GossipLimiter::new(limits)
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut limiter = limiter();
+        let peer = PeerId::random();
+        assert_eq!(limiter.check(&peer, &PubsubMessage::Transaction(vec![0; 4])), GossipCheck::Accept);
+        assert_eq!(limiter.check(&peer, &PubsubMessage::Transaction(vec![0; 5])), GossipCheck::Oversized);
+        assert_eq!(limiter.check(&peer, &PubsubMessage::BadBlock(vec![0; 33])), GossipCheck::Oversized);
+    }
+
+    #[test]
+    fn rate_limit_per_peer_and_topic() {
+        let mut limiter = limiter();
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        let tx = PubsubMessage::Transaction(vec![0]);
+        assert_eq!(limiter.check_at(&peer, &tx, now), GossipCheck::Accept);
+        assert_eq!(limiter.check_at(&peer, &tx, now), GossipCheck::Accept);
+        assert_eq!(limiter.check_at(&peer, &tx, now), GossipCheck::RateLimited);
+        // Other peers and topics have their own budget
+        assert_eq!(limiter.check_at(&other, &tx, now), GossipCheck::Accept);
+        assert_eq!(limiter.check_at(&peer, &PubsubMessage::Block(vec![0]), now), GossipCheck::Accept);
+        // The budget is renewed with the window
+        assert_eq!(limiter.check_at(&peer, &tx, now + RATE_WINDOW), GossipCheck::Accept);
+    }
+
+    #[test]
+    fn ban_after_strikes() {
+        let mut limiter = limiter();
+        let peer = PeerId::random();
+        let oversized = PubsubMessage::Transaction(vec![0; 5]);
+        for _ in 1..MAX_GOSSIP_STRIKES {
+            assert_eq!(limiter.check(&peer, &oversized), GossipCheck::Oversized);
+        }
+        assert_eq!(limiter.check(&peer, &oversized), GossipCheck::Ban);
+        assert_eq!(limiter.check(&peer, &oversized), GossipCheck::Oversized);
+    }
+}
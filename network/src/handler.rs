@@ -1,6 +1,8 @@
 #![allow(clippy::unit_arg)]
 
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use futures::future::Future;
 use futures::stream::Stream;
@@ -10,10 +12,23 @@ use tokio::sync::mpsc;
 
 use pool::tx_pool::TxPoolManager;
 use chain::blockchain::BlockChain;
-use map_core::transaction::Transaction;
+use chain::lock::TimedRwLock;
+use chain::import_timer;
+use map_core::block::Block;
+use map_core::types::Hash;
 use crate::{behaviour::PubsubMessage, manager::NetworkMessage};
+use crate::config::RangeSyncConfig;
+use crate::sync::SharedSyncProgress;
+use crate::block_queue::SharedBlockQueue;
+use crate::import_queue::ImportQueue;
+use crate::propagation::{self, SharedPropagation};
+use crate::time_sync::SharedClockOffsets;
 use crate::error;
+use crate::wire::{self, WireError};
 use crate::MessageProcessor;
+use crate::handler_processor::GossipBlockCheck;
+use crate::gossip_limits::{GossipCheck, GossipLimiter, GossipLimits, GOSSIP_BAN_DURATION};
+use crate::metrics;
 use crate::p2p::{P2PError, P2PErrorResponse, P2PEvent, P2PRequest, P2PResponse, RequestId, ResponseTermination};
 
 /// Handles messages received from the network and client and organises syncing. This
@@ -26,6 +41,8 @@ pub struct MessageHandler {
     /// Processes validated and decoded messages from the network. Has direct access to the
     /// sync manager.
     message_processor: MessageProcessor,
+    /// Drops oversized gossip and gossip floods before decoding.
+    gossip_limiter: GossipLimiter,
     /// The `MessageHandler` logger.
     pub log: slog::Logger,
 }
@@ -47,9 +64,16 @@ pub enum HandlerMessage {
 impl MessageHandler {
     /// Initializes and runs the MessageHandler.
     pub fn spawn(
-        block_chain: Arc<RwLock<BlockChain>>,
+        block_chain: Arc<TimedRwLock<BlockChain>>,
+        importer: ImportQueue,
         network_send: mpsc::UnboundedSender<NetworkMessage>,
         tx_pool: Arc<RwLock<TxPoolManager>>,
+        sync_progress: SharedSyncProgress,
+        range_sync: RangeSyncConfig,
+        network_dir: PathBuf,
+        propagation: SharedPropagation,
+        clock_offsets: SharedClockOffsets,
+        block_queue: SharedBlockQueue,
         executor: &tokio::runtime::TaskExecutor,
         log: slog::Logger,
     ) -> error::Result<mpsc::UnboundedSender<HandlerMessage>> {
@@ -59,12 +83,13 @@ impl MessageHandler {
 
         // Initialise a message instance, which itself spawns the syncing thread.
         let message_processor =
-            MessageProcessor::new(executor, block_chain, tx_pool, network_send.clone(), &log);
+            MessageProcessor::new(executor, block_chain, importer, tx_pool, network_send.clone(), sync_progress, range_sync, network_dir, propagation, clock_offsets, block_queue, &log);
 
         // generate the Message handler
         let mut handler = MessageHandler {
             network_send,
             message_processor,
+            gossip_limiter: GossipLimiter::new(GossipLimits::default()),
             log:log.clone(),
         };
 
@@ -89,6 +114,7 @@ impl MessageHandler {
             }
             // A peer has disconnected
             HandlerMessage::PeerDisconnected(peer_id) => {
+                self.gossip_limiter.remove_peer(&peer_id);
                 self.message_processor.on_disconnect(peer_id);
             }
             // An RPC message request/response has been received
@@ -134,6 +160,18 @@ impl MessageHandler {
             P2PRequest::BlocksByRoot(request) => {
                 self.message_processor.on_blocks_by_root_request(peer_id, request_id, request);
             }
+            P2PRequest::StateNodes(request) => {
+                self.message_processor.on_state_nodes_request(peer_id, request_id, request);
+            }
+            P2PRequest::PooledTransactionHashes(request) => {
+                self.message_processor.on_pooled_transaction_hashes(peer_id, request);
+            }
+            P2PRequest::PooledTransactions(request) => {
+                self.message_processor.on_pooled_transactions_request(peer_id, request_id, request);
+            }
+            P2PRequest::TimeSync(request) => {
+                self.message_processor.on_time_sync_request(peer_id, request_id, request);
+            }
         }
     }
 
@@ -155,6 +193,10 @@ impl MessageHandler {
                 warn!(self.log, "Peer internal server error";"peer_id" => format!("{:?}", peer_id), "error" => error.as_string());
                 self.handle_rpc_error(peer_id, request_id, P2PError::P2PErrorResponse);
             }
+            P2PErrorResponse::RateLimited(error) => {
+                debug!(self.log, "Peer rate limited request";"peer_id" => format!("{:?}", peer_id), "error" => error.as_string());
+                self.handle_rpc_error(peer_id, request_id, P2PError::P2PErrorResponse);
+            }
             P2PErrorResponse::Unknown(error) => {
                 warn!(self.log, "Unknown peer error";"peer" => format!("{:?}", peer_id), "error" => error.as_string());
                 self.handle_rpc_error(peer_id, request_id, P2PError::P2PErrorResponse);
@@ -166,7 +208,7 @@ impl MessageHandler {
                             .on_status_response(peer_id, status_message);
                     }
                     P2PResponse::BlocksByRange(response) => {
-                        match bincode::deserialize(&response[..]) {
+                        match decode_block(&response[..]) {
                             Ok(block) => {
                                 self.message_processor.on_blocks_by_range_response(
                                     peer_id,
@@ -181,7 +223,7 @@ impl MessageHandler {
                         }
                     }
                     P2PResponse::BlocksByRoot(response) => {
-                        match bincode::deserialize(&response[..]) {
+                        match decode_block(&response[..]) {
                             Ok(block) => {
                                 self.message_processor.on_blocks_by_root_response(
                                     peer_id,
@@ -196,6 +238,22 @@ impl MessageHandler {
                             }
                         }
                     }
+                    P2PResponse::StateNodes(node) => {
+                        self.message_processor
+                            .on_state_nodes_response(peer_id, request_id, Some(node));
+                    }
+                    P2PResponse::PooledTransactions(response) => {
+                        match wire::decode_transaction(&response) {
+                            Ok(tx) => self.message_processor.on_pooled_transaction_response(peer_id, tx),
+                            Err(e) => {
+                                warn!(self.log, "Peer sent invalid POOLED_TRANSACTIONS response";
+                                    "peer" => format!("{:?}", peer_id), "error" => e.to_string());
+                            }
+                        }
+                    }
+                    P2PResponse::TimeSync(response) => {
+                        self.message_processor.on_time_sync_response(peer_id, response);
+                    }
                 }
             }
             P2PErrorResponse::StreamTermination(response_type) => {
@@ -209,6 +267,13 @@ impl MessageHandler {
                         self.message_processor
                             .on_blocks_by_root_response(peer_id, request_id, None);
                     }
+                    ResponseTermination::StateNodes => {
+                        self.message_processor
+                            .on_state_nodes_response(peer_id, request_id, None);
+                    }
+                    ResponseTermination::PooledTransactions => {
+                        trace!(self.log, "Pooled transactions received"; "peer" => format!("{:?}", peer_id));
+                    }
                 }
             }
         }
@@ -221,21 +286,37 @@ impl MessageHandler {
 
     /// Handle RPC messages
     fn handle_gossip(&mut self, id: MessageId, peer_id: PeerId, gossip_message: PubsubMessage) {
+        if !self.check_gossip_limits(&peer_id, &gossip_message) {
+            return;
+        }
         match gossip_message {
-            PubsubMessage::Block(message) => match bincode::deserialize(&message[..]) {
+            PubsubMessage::Block(message) => match decode_block(&message[..]) {
                 Ok(block) => {
-                    let should_forward_on = self
-                        .message_processor
-                        .on_block_gossip(peer_id.clone(), block);
-                    if should_forward_on {
-                        self.propagate_message(id, peer_id);
+                    let received_ms = propagation::now_ms();
+                    match self.message_processor.check_gossip_block(&block) {
+                        GossipBlockCheck::Propagate => {
+                            // Forward as soon as the cheap checks pass, execution failures are
+                            // handled by the processor
+                            self.propagate_message(id, peer_id.clone());
+                            self.message_processor.on_block_arrival(&peer_id, &block, received_ms);
+                            self.message_processor.on_block_gossip(peer_id, block);
+                        }
+                        GossipBlockCheck::Defer => {
+                            let should_forward_on = self
+                                .message_processor
+                                .on_block_gossip(peer_id.clone(), block);
+                            if should_forward_on {
+                                self.propagate_message(id, peer_id);
+                            }
+                        }
+                        GossipBlockCheck::Reject => {}
                     }
                 }
                 Err(e) => {
                     debug!(self.log, "Invalid gossiped block"; "peer_id" => format!("{}", peer_id), "Error" => format!("{:?}", e));
                 }
             },
-            PubsubMessage::Transaction(message) => match bincode::deserialize::<Transaction>(&message) {
+            PubsubMessage::Transaction(message) => match wire::decode_transaction(&message) {
                 Ok(tx) => {
                     // Received new transaction
                     debug!(self.log, "Gossip transaction received"; "peer_id" => format!("{}", peer_id),
@@ -244,9 +325,16 @@ impl MessageHandler {
                 },
                 Err(e) => {
                     // Received new transaction
-                    warn!(self.log, "Gossip transaction decoded error"; "peer_id" => format!("{}", peer_id),);
+                    warn!(self.log, "Gossip transaction decoded error"; "peer_id" => format!("{}", peer_id), "error" => e.to_string());
                 },
             },
+            PubsubMessage::BadBlock(message) => {
+                if message.len() != 32 {
+                    debug!(self.log, "Invalid bad block message"; "peer_id" => format!("{}", peer_id));
+                } else if self.message_processor.on_bad_block_gossip(peer_id.clone(), Hash::from_bytes(&message)) {
+                    self.propagate_message(id, peer_id);
+                }
+            },
             PubsubMessage::Unknown(message) => {
                 // Received a message from an unknown topic. Ignore for now
                 debug!(self.log, "Unknown Gossip Message"; "peer_id" => format!("{}", peer_id), "Message" => format!("{:?}", message));
@@ -254,6 +342,33 @@ impl MessageHandler {
         }
     }
 
+    /// Whether a gossip message is within the size and rate limits of its topic. Peers
+    /// exceeding them too often are banned.
+    fn check_gossip_limits(&mut self, peer_id: &PeerId, message: &PubsubMessage) -> bool {
+        let reason = match self.gossip_limiter.check(peer_id, message) {
+            GossipCheck::Accept => return true,
+            GossipCheck::Oversized => "oversized",
+            GossipCheck::RateLimited => "rate_limited",
+            GossipCheck::Ban => {
+                warn!(self.log, "Banning peer flooding gossip"; "peer_id" => format!("{}", peer_id),
+                    "topic" => message.topic_name());
+                metrics::inc_counter(&metrics::GOSSIP_PEERS_BANNED);
+                self.network_send
+                    .try_send(NetworkMessage::BanPeer {
+                        peer_id: peer_id.clone(),
+                        duration: GOSSIP_BAN_DURATION,
+                        reason: "gossip limits exceeded".to_string(),
+                    })
+                    .unwrap_or_else(|_| warn!(self.log, "Could not send ban request to the network service"));
+                "ban"
+            }
+        };
+        debug!(self.log, "Dropped gossip message"; "peer_id" => format!("{}", peer_id),
+            "topic" => message.topic_name(), "size" => message.data().len(), "reason" => reason);
+        metrics::inc_counter_vec_by(&metrics::GOSSIP_MESSAGES_DROPPED, &[message.topic_name(), reason], 1);
+        false
+    }
+
     /// Informs the network service that the message should be forwarded to other peers.
     fn propagate_message(&mut self, message_id: MessageId, propagation_source: PeerId) {
         self.network_send
@@ -269,3 +384,11 @@ impl MessageHandler {
             });
     }
 }
+
+/// Decodes a block received from the network, recording the time as the import decode stage.
+fn decode_block(data: &[u8]) -> Result<Block, WireError> {
+    let start = Instant::now();
+    let block = wire::decode_block(data);
+    import_timer::observe_stage(import_timer::STAGE_DECODE, start.elapsed());
+    block
+}
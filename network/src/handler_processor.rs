@@ -1,23 +1,37 @@
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use libp2p::PeerId;
 use slog::{debug, info, error, trace, warn};
 use tokio::sync::{mpsc, oneshot};
 
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use chain::{BlockChainError, BlockChainErrorKind};
 use pool::tx_pool::TxPoolManager;
 use map_core::block::Block;
+use map_core::genesis;
 use map_core::types::Hash;
 use map_core::transaction::Transaction;
 
+use crate::bad_blocks::{BadBlocks, BadBlockReport, FALSE_REPORT_BAN_DURATION};
+use crate::block_queue::SharedBlockQueue;
+use crate::config::RangeSyncConfig;
+use crate::import_queue::{ImportQueue, ImportSource};
+use crate::propagation::{self, SharedPropagation};
+use crate::state_heal::HealQueue;
+use crate::time_sync::{SharedClockOffsets, SkewChange, MAX_CLOCK_SKEW_MS};
+use crate::wire;
 use crate::manager::NetworkMessage;
 use crate::p2p::{methods::*, P2PEvent, P2PRequest, P2PResponse, RequestId};
-use crate::sync::SyncMessage;
-use priority_queue::PriorityQueue;
+use crate::sync::{SharedSyncProgress, SyncMessage};
 use crate::{
 	{behaviour::{PubsubMessage}
 	},
 	GossipTopic,
+	AGENT_VERSION,
+	GENESIS_MISMATCH_BAN,
 };
 
 /// If a block is more than `FUTURE_SLOT_TOLERANCE` slots ahead of our slot clock, we drop it.
@@ -30,7 +44,19 @@ const SLOT_IMPORT_TOLERANCE: u64 = 20;
 
 const SHOULD_FORWARD_GOSSIP_BLOCK: bool = true;
 const SHOULD_NOT_FORWARD_GOSSIP_BLOCK: bool = false;
-const QUEUE_GOSSIP_BLOCK: usize = 512;
+/// The maximum total size in bytes of trie nodes served for a single `StateNodes` request.
+const MAX_STATE_NODES_BYTES: usize = 2 * 1024 * 1024;
+
+/// Outcome of the checks run on a gossiped block before it is executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GossipBlockCheck {
+    /// Structure, seal and proposer are valid, forward it before executing.
+    Propagate,
+    /// Can't be checked yet or was reported bad by a peer, forward only once imported.
+    Defer,
+    /// Known, invalid or from a banned proposer, drop it.
+    Reject,
+}
 
 /// Keeps track of syncing information for known connected peers.
 #[derive(Clone, Copy, Debug)]
@@ -48,6 +74,9 @@ pub struct PeerSyncInfo {
 
     /// The fork version of the chain we are broadcasting.
     pub network_id: u16,
+
+    /// Hash of the consensus parameters of the chain spec.
+    pub spec_hash: Hash,
 }
 
 impl From<StatusMessage> for PeerSyncInfo {
@@ -58,12 +87,13 @@ impl From<StatusMessage> for PeerSyncInfo {
             finalized_number: status.finalized_number,
             head_root: status.head_root,
             genesis_hash: status.genesis_hash,
+            spec_hash: status.spec_hash,
         }
     }
 }
 
 impl PeerSyncInfo {
-    pub fn from_chain(chain: Arc<RwLock<BlockChain>>) -> Option<PeerSyncInfo> {
+    pub fn from_chain(chain: Arc<TimedRwLock<BlockChain>>) -> Option<PeerSyncInfo> {
         Some(Self::from(status_message(chain)?))
     }
 }
@@ -72,7 +102,7 @@ impl PeerSyncInfo {
 /// and processes blocks from the pubsub network.
 pub struct MessageProcessor {
     /// A reference to the underlying beacon chain.
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
     /// Transaction pool
     tx_pool: Arc<RwLock<TxPoolManager>>,
     /// A channel to the syncing thread.
@@ -83,27 +113,60 @@ pub struct MessageProcessor {
     network: HandlerNetworkContext,
     /// The `RPCHandler` logger.
     log: slog::Logger,
-    pub queue :PriorityQueue<Block,i64>,
+    /// Gossip blocks received ahead of the head.
+    queue: SharedBlockQueue,
+    /// Blocks that failed execution after being forwarded, and their proposers.
+    bad_blocks: BadBlocks,
+    /// Arrival and import delays of gossiped blocks.
+    propagation: SharedPropagation,
+    /// Unix time of genesis, the origin of the slot clock.
+    genesis_time: u64,
+    /// Offsets of the peer clocks to ours.
+    clock_offsets: SharedClockOffsets,
+    /// Imports blocks for gossip and sync one at a time.
+    importer: ImportQueue,
+    /// Refetches the state trie nodes found missing on import.
+    healer: HealQueue,
+    /// Genesis of our chain, checked first in the status handshake.
+    genesis_hash: Hash,
 }
 
 impl MessageProcessor {
     /// Instantiate a `MessageProcessor` instance
     pub fn new(
         executor: &tokio::runtime::TaskExecutor,
-        block_chain: Arc<RwLock<BlockChain>>,
+        block_chain: Arc<TimedRwLock<BlockChain>>,
+        importer: ImportQueue,
         tx_pool: Arc<RwLock<TxPoolManager>>,
         network_send: mpsc::UnboundedSender<NetworkMessage>,
+        sync_progress: SharedSyncProgress,
+        range_sync: RangeSyncConfig,
+        network_dir: PathBuf,
+        propagation: SharedPropagation,
+        clock_offsets: SharedClockOffsets,
+        queue: SharedBlockQueue,
         log: &slog::Logger,
     ) -> Self {
 
+        let healer = HealQueue::spawn(block_chain.clone(), importer.clone(), network_send.clone(), log.clone());
+
         // spawn the sync thread
         let (sync_send, _sync_exit) = crate::sync::manager::spawn(
             executor,
             block_chain.clone(),
+            importer.clone(),
+            healer.clone(),
             network_send.clone(),
+            sync_progress,
+            range_sync,
+            network_dir,
             log.clone(),
         );
 
+        let (genesis_time, genesis_hash) = {
+            let chain = block_chain.read().expect("acquiring chain read lock");
+            (chain.genesis_time(), chain.genesis_hash())
+        };
         MessageProcessor {
             chain: block_chain,
             tx_pool: tx_pool,
@@ -111,7 +174,14 @@ impl MessageProcessor {
             _sync_exit,
             network: HandlerNetworkContext::new(network_send, log.clone()),
             log: log.clone(),
-            queue:PriorityQueue::with_capacity(QUEUE_GOSSIP_BLOCK),
+            queue,
+            bad_blocks: BadBlocks::new(),
+            propagation,
+            genesis_time,
+            clock_offsets,
+            importer,
+            healer,
+            genesis_hash,
         }
     }
 
@@ -128,12 +198,16 @@ impl MessageProcessor {
     ///
     /// Removes the peer from the manager.
     pub fn on_disconnect(&mut self, peer_id: PeerId) {
+        self.propagation.write().expect("acquiring propagation lock").remove_peer(&peer_id);
+        self.clock_offsets.write().expect("acquiring clock offsets lock").remove_peer(&peer_id);
+        self.healer.on_disconnect(peer_id.clone());
         self.send_to_sync(SyncMessage::Disconnect(peer_id));
     }
 
     /// An error occurred during an RPC request. The state is maintained by the sync manager, so
     /// this function notifies the sync manager of the error.
     pub fn on_rpc_error(&mut self, peer_id: PeerId, request_id: RequestId) {
+        self.healer.on_rpc_error(peer_id.clone());
         self.send_to_sync(SyncMessage::RPCError(peer_id, request_id));
     }
 
@@ -195,6 +269,18 @@ impl MessageProcessor {
     /// Disconnects the peer if required.
     fn process_status(&mut self, peer_id: PeerId, status: StatusMessage) {
         let remote = PeerSyncInfo::from(status);
+        if remote.genesis_hash != self.genesis_hash {
+            // Another chain, rejected without taking the chain lock and not dialed again
+            warn!(
+                self.log, "Handshake Failure";
+                "peer" => format!("{:?}", peer_id),
+                "reason" => "genesis",
+                "local" => format!("{:?}", self.genesis_hash),
+                "remote" => format!("{:?}", remote.genesis_hash)
+            );
+            self.network.disconnect_and_ban(peer_id, GoodbyeReason::GenesisMismatch, GENESIS_MISMATCH_BAN);
+            return;
+        }
         let local = match PeerSyncInfo::from_chain(self.chain.clone()) {
             Some(local) => local,
             None => {
@@ -216,7 +302,33 @@ impl MessageProcessor {
 
             self.network
                 .disconnect(peer_id, GoodbyeReason::IrrelevantNetwork);
-        } else if remote.finalized_number < local.finalized_number {
+            return;
+        }
+
+        if local.spec_hash != remote.spec_hash {
+            // Same network but a different genesis, engine or protocol constants, the chains
+            // would diverge at the first block.
+            warn!(
+                self.log, "Handshake Failure";
+                "peer" => format!("{:?}", peer_id),
+                "reason" => "spec_hash",
+                "local" => format!("{:?}", local.spec_hash),
+                "remote" => format!("{:?}", remote.spec_hash)
+            );
+
+            self.network
+                .disconnect(peer_id, GoodbyeReason::IrrelevantNetwork);
+            return;
+        }
+
+        // On our chain, whether it is ahead or behind, it counts for the block producer
+        self.send_to_sync(SyncMessage::ChainPeer(peer_id.clone()));
+
+        // Both sides of the handshake announce their pool, each pulls what it misses
+        self.send_pooled_transaction_hashes(peer_id.clone());
+        self.send_time_sync(peer_id.clone());
+
+        if remote.finalized_number < local.finalized_number {
             // The node has a lower finalized epoch, their chain is not useful to us. There are two
             // cases where a node can have a lower finalized epoch:
             //
@@ -276,21 +388,21 @@ impl MessageProcessor {
         }
 
         let mut blocks = vec![];
-        let block_chain = self.chain.write().unwrap();
+        let block_chain = self.chain.read().unwrap();
         let current_block = block_chain.current_block();
         let mut start = req.start_slot;
         loop {
             if current_block.height() > start && blocks.len() == req.count as usize {
                 break;
             }
-            let block = block_chain.get_block_by_number(start);
+            let block = block_chain.get_shared_block_by_number(start);
             match block {
                 Some(b) => {
                     blocks.push(b.clone());
                     self.network.send_rpc_response(
                         peer_id.clone(),
                         request_id,
-                        P2PResponse::BlocksByRange(bincode::serialize(&b).unwrap()),
+                        P2PResponse::BlocksByRange(wire::encode_block(&b)),
                     );
                 }
                 None => {
@@ -365,11 +477,11 @@ impl MessageProcessor {
         let block_chain = self.chain.read().unwrap();
 
         for root in request.block_roots.iter() {
-            if let Some(b) = block_chain.get_block(*root) {
+            if let Some(b) = block_chain.get_shared_block(*root) {
                 self.network.send_rpc_response(
                     peer_id.clone(),
                     request_id,
-                    P2PResponse::BlocksByRoot(bincode::serialize(&b).unwrap()),
+                    P2PResponse::BlocksByRoot(wire::encode_block(&b)),
                 );
             } else {
                 debug!(
@@ -388,6 +500,255 @@ impl MessageProcessor {
         );
     }
 
+    /// Response to `StateNodes` request from a peer.
+    ///
+    /// Unknown nodes are skipped and the response is truncated once the count or size limit is
+    /// reached, the requester retries the remainder.
+    pub fn on_state_nodes_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        request: StateNodesRequest,
+    ) {
+        let nodes = self.chain.read().unwrap().statedb().get_nodes(
+            &request.node_hashes,
+            MAX_STATE_NODES_PER_REQUEST,
+            MAX_STATE_NODES_BYTES,
+        );
+
+        debug!(
+            self.log,
+            "Received StateNodes Request";
+            "peer" => format!("{:?}", peer_id),
+            "requested" => request.node_hashes.len(),
+            "returned" => nodes.len(),
+        );
+
+        for node in nodes {
+            self.network.send_rpc_response(
+                peer_id.clone(),
+                request_id,
+                P2PResponse::StateNodes(node),
+            );
+        }
+        // Notify streamd terminator
+        self.network.send_rpc_error_response(
+            peer_id,
+            request_id,
+            P2PErrorResponse::StreamTermination(ResponseTermination::StateNodes),
+        );
+    }
+
+    /// Handle a `StateNodes` response from the peer.
+    /// A `node` behaves as a stream which is terminated on a `None` response.
+    pub fn on_state_nodes_response(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        node: Option<Vec<u8>>,
+    ) {
+        trace!(
+            self.log,
+            "Received StateNodes Response";
+            "peer" => format!("{:?}", peer_id),
+            "request_id" => request_id,
+            "size" => node.as_ref().map_or(0, |n| n.len()),
+        );
+        self.healer.on_response(peer_id, node);
+    }
+
+    /// Announce the hashes of our pooled transactions to a peer which passed the handshake.
+    fn send_pooled_transaction_hashes(&mut self, peer_id: PeerId) {
+        let tx_hashes = self.tx_pool.read().expect("acquiring tx_pool read lock").tx_hashes(MAX_POOLED_TX_HASHES);
+        if tx_hashes.is_empty() {
+            return;
+        }
+        debug!(self.log, "Sending PooledTransactionHashes"; "peer" => format!("{:?}", peer_id),
+            "count" => tx_hashes.len());
+        self.network.send_rpc_request(
+            peer_id,
+            P2PRequest::PooledTransactionHashes(PooledTransactionHashesRequest { tx_hashes }),
+        );
+    }
+
+    /// Handle the pool announcement of a peer, requesting the transactions we don't have.
+    pub fn on_pooled_transaction_hashes(&mut self, peer_id: PeerId, request: PooledTransactionHashesRequest) {
+        let tx_hashes: Vec<Hash> = {
+            let pool = self.tx_pool.read().expect("acquiring tx_pool read lock");
+            request.tx_hashes.into_iter()
+                .filter(|hash| !pool.contains(hash))
+                .take(MAX_POOLED_TXS_PER_REQUEST)
+                .collect()
+        };
+
+        debug!(self.log, "Received PooledTransactionHashes"; "peer" => format!("{:?}", peer_id),
+            "missing" => tx_hashes.len());
+        if !tx_hashes.is_empty() {
+            self.network.send_rpc_request(
+                peer_id,
+                P2PRequest::PooledTransactions(PooledTransactionsRequest { tx_hashes }),
+            );
+        }
+    }
+
+    /// Response to `PooledTransactions` request from a peer, unknown hashes are skipped.
+    pub fn on_pooled_transactions_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        request: PooledTransactionsRequest,
+    ) {
+        let txs: Vec<Transaction> = {
+            let pool = self.tx_pool.read().expect("acquiring tx_pool read lock");
+            request.tx_hashes.iter()
+                .take(MAX_POOLED_TXS_PER_REQUEST)
+                .filter_map(|hash| pool.get_tx(hash))
+                .collect()
+        };
+
+        debug!(
+            self.log,
+            "Received PooledTransactions Request";
+            "peer" => format!("{:?}", peer_id),
+            "requested" => request.tx_hashes.len(),
+            "returned" => txs.len(),
+        );
+
+        for tx in txs.iter() {
+            self.network.send_rpc_response(
+                peer_id.clone(),
+                request_id,
+                P2PResponse::PooledTransactions(wire::encode_transaction(tx)),
+            );
+        }
+        // Notify streamd terminator
+        self.network.send_rpc_error_response(
+            peer_id,
+            request_id,
+            P2PErrorResponse::StreamTermination(ResponseTermination::PooledTransactions),
+        );
+    }
+
+    /// Add a transaction pulled from a peer's pool, validated like a gossiped one.
+    pub fn on_pooled_transaction_response(&mut self, peer_id: PeerId, tx: Transaction) {
+        trace!(self.log, "Received PooledTransactions Response"; "peer" => format!("{:?}", peer_id),
+            "hash" => format!("{}", tx.hash()));
+        self.on_transaction_gossip(peer_id, tx);
+    }
+
+    /// Run the checks of a gossiped block that don't need executing it, deciding whether it may
+    /// be forwarded before import.
+    pub fn check_gossip_block(&mut self, block: &Block) -> GossipBlockCheck {
+        let hash = block.hash();
+        if self.bad_blocks.is_invalid(&hash) {
+            return GossipBlockCheck::Reject;
+        }
+
+        let chain = self.chain.read().expect("acquiring chain read lock");
+        if let Some(proposer) = chain.author(block) {
+            if self.bad_blocks.is_banned(&proposer) {
+                debug!(self.log, "Dropping block of banned proposer"; "hash" => format!("{}", hash),
+                    "proposer" => format!("{}", proposer));
+                return GossipBlockCheck::Reject;
+            }
+        }
+        if self.bad_blocks.is_reported(&hash) {
+            return GossipBlockCheck::Defer;
+        }
+
+        match chain.pre_verify(block) {
+            Ok(()) => GossipBlockCheck::Propagate,
+            Err(e) => match block_error_kind(&e) {
+                // Parent not imported yet, the block is queued and forwarded on import
+                Some(BlockChainErrorKind::UnknownAncestor) => GossipBlockCheck::Defer,
+                _ => {
+                    debug!(self.log, "Gossip block rejected"; "hash" => format!("{}", hash), "error" => format!("{}", e));
+                    GossipBlockCheck::Reject
+                }
+            },
+        }
+    }
+
+    /// Record the arrival at `received_ms` of a gossiped block that passed `check_gossip_block`,
+    /// so that forged blocks don't count in the delays.
+    pub fn on_block_arrival(&mut self, peer_id: &PeerId, block: &Block, received_ms: u64) {
+        let slot_start = genesis::slot_start(self.genesis_time, block.header.slot);
+        self.propagation.write().expect("acquiring propagation lock").on_arrival(peer_id, block, slot_start, received_ms);
+        let resync = self.clock_offsets.read().expect("acquiring clock offsets lock").sync_due(peer_id, propagation::now_ms());
+        if resync {
+            self.send_time_sync(peer_id.clone());
+        }
+    }
+
+    /// Send our time to a peer to estimate the offset of its clock.
+    fn send_time_sync(&mut self, peer_id: PeerId) {
+        let request = TimeSyncRequest { sent_ms: propagation::now_ms() };
+        self.clock_offsets.write().expect("acquiring clock offsets lock").on_request(&peer_id, request.sent_ms);
+        self.network.send_rpc_request(peer_id, P2PRequest::TimeSync(request));
+    }
+
+    /// Handle a `TimeSync` request, answering with the times of our clock.
+    pub fn on_time_sync_request(&mut self, peer_id: PeerId, request_id: RequestId, request: TimeSyncRequest) {
+        let received_ms = propagation::now_ms();
+        let response = TimeSyncResponse {
+            request_sent_ms: request.sent_ms,
+            received_ms,
+            sent_ms: propagation::now_ms(),
+        };
+        self.network.send_rpc_response(peer_id, request_id, P2PResponse::TimeSync(response));
+    }
+
+    /// Handle a `TimeSync` response, updating the offset of the peer's clock and warning when
+    /// ours drifts away from the network.
+    pub fn on_time_sync_response(&mut self, peer_id: PeerId, response: TimeSyncResponse) {
+        let received_ms = propagation::now_ms();
+        let change = self.clock_offsets.write().expect("acquiring clock offsets lock")
+            .on_response(&peer_id, &response, received_ms);
+        trace!(self.log, "TimeSyncResponse"; "peer" => format!("{:?}", peer_id));
+        match change {
+            Some(SkewChange::Skewed(offset)) => warn!(
+                self.log, "Local clock drifted from the peers, slots may be missed";
+                "offset_ms" => offset, "max_skew_ms" => MAX_CLOCK_SKEW_MS,
+            ),
+            Some(SkewChange::Recovered(offset)) => info!(
+                self.log, "Local clock back in line with the peers"; "offset_ms" => offset,
+            ),
+            None => {}
+        }
+    }
+
+    /// Handle the hash of a block a peer failed to execute. Returns true if we rejected the
+    /// block too and the report should be forwarded. Peers reporting blocks we imported are
+    /// banned after a few reports.
+    pub fn on_bad_block_gossip(&mut self, peer_id: PeerId, hash: Hash) -> bool {
+        debug!(self.log, "Bad block reported"; "peer_id" => format!("{}", peer_id), "hash" => format!("{}", hash));
+        let imported = self.chain.read().expect("acquiring chain read lock").get_raw_header(hash).is_some();
+        match self.bad_blocks.report(&peer_id, hash, imported) {
+            BadBlockReport::Confirmed => true,
+            BadBlockReport::Ignored => false,
+            BadBlockReport::False => {
+                debug!(self.log, "Imported block reported as bad"; "peer_id" => format!("{}", peer_id),
+                    "hash" => format!("{}", hash));
+                false
+            }
+            BadBlockReport::Ban => {
+                self.network.disconnect_and_ban(peer_id, GoodbyeReason::Fault, FALSE_REPORT_BAN_DURATION);
+                false
+            }
+        }
+    }
+
+    /// Record a block failing execution: it is never imported again, its proposer's blocks are
+    /// dropped for a while and peers are told about it.
+    fn on_invalid_block(&mut self, block: &Block, error: &errors::Error) {
+        let hash = block.hash();
+        let proposer = self.chain.read().expect("acquiring chain read lock").author(block);
+        warn!(self.log, "Gossip block failed execution"; "height" => block.height(), "hash" => format!("{}", hash),
+            "proposer" => format!("{:?}", proposer), "error" => format!("{}", error));
+        self.bad_blocks.insert_invalid(hash, proposer);
+        self.network.broadcast_bad_block(&hash);
+    }
+
     /// Process a gossip message declaring a new block.
     ///
     /// Attempts to apply to block to the beacon chain. May queue the block for later processing.
@@ -400,42 +761,53 @@ impl MessageProcessor {
     ) -> bool {
         let current_block = self.chain.read().unwrap().current_block();
 		// when start, receive first block height than local, start sync meanwhile cache latest block. Due to when sync over, if no cache block
-		// will repeat again with syner need more time. so check receive block and cache block. if it match current + 1 will insert.
-		// if lower will lost, if greater will cache, this reduce frequency.
+		// will repeat again with syner need more time. so queue blocks above the head and import them as the head reaches them.
 		let height :u64= block.height();
-		let current_height = current_block.height();
-		debug!(self.log, "Gossip block received: {:?} {:?} current: {:?} {:?}", height, block.hash(), current_height, current_block.hash());
+		let hash = block.hash();
+		debug!(self.log, "Gossip block received: {:?} {:?} current: {:?} {:?}", height, hash, current_block.height(), current_block.hash());
 
 		let mut find = false;
-		if height - current_height < QUEUE_GOSSIP_BLOCK as u64 {
-			self.queue.push(block.clone(),-(height as i64));
-
-			while !self.queue.is_empty() {
-				let (block_low,height_low_neg) = self.queue.pop().unwrap();
-				let height_low = (-height_low_neg) as u64;
-
-				if  height_low > current_height + 1 {
-					self.queue.push(block_low.clone(),height_low_neg);
-
-					if  height > current_height + SLOT_IMPORT_TOLERANCE {
-						warn!(self.log, "unknown gossip parent: {:?} {:?}", height, current_height);
-						self.send_to_sync(SyncMessage::OrphanBlock(peer_id.clone(), Box::new(block.clone())));
+		self.queue.write().expect("acquiring block queue lock").push(&mut self.chain.write().expect("acquiring chain write lock"), block.clone());
+		loop {
+			let next = self.queue.write().expect("acquiring block queue lock").pop_next(&mut self.chain.write().expect("acquiring chain write lock"));
+			let block_low = match next {
+				Some(block_low) => block_low,
+				None => break,
+			};
+			// Blocks off the head wait behind sync, they don't move it
+			let head = self.chain.read().expect("acquiring chain read lock").current_block().hash();
+			let source = if block_low.header.parent_hash == head { ImportSource::Gossip } else { ImportSource::Orphan };
+			let imported = self.importer.import(source, block_low.clone());
+			match imported {
+				Ok(()) => {
+					let slot_start = genesis::slot_start(self.genesis_time, block_low.header.slot);
+					self.propagation.write().expect("acquiring propagation lock").on_import(&block_low.hash(), slot_start);
+					if block_low.hash() == hash {
+						find = true;
+					} else {
+						debug!(self.log, "Broadcast block received: {:?} {:?} height_low: {:?} {:?} queue {:?}", height, hash, block_low.height(), block_low.hash(), self.queue.read().expect("acquiring block queue lock").len());
+						self.network.broadcast_block(&block_low);
 					}
-					break
-				} else if height_low == current_height + 1 {
-					if import_block(self.chain.clone(),&block_low) {
-						if height_low == height {
-							find = true;
-						} else {
-							debug!(self.log, "Broadcast block received: {:?} {:?} height_low: {:?} {:?} queue {:?}", block.height(), block.hash(), height_low, block_low.hash(),self.queue.len());
-							self.network.broadcast_block(&block_low);
+				}
+				Err(e) => {
+					match block_error_kind(&e) {
+						Some(BlockChainErrorKind::InvalidState) => self.on_invalid_block(&block_low, &e),
+						Some(BlockChainErrorKind::MissingStateNodes) => self.healer.start(peer_id.clone(), block_low.clone()),
+						_ => {
+							debug!(self.log, "Gossip block import failed"; "hash" => format!("{}", block_low.hash()), "error" => format!("{}", e));
 						}
-					} else {
-						break
-					};
+					}
 				}
 			}
 		}
+		// Peers gossiping blocks take over a healing that stalled on others
+		self.healer.offer(peer_id.clone());
+
+		let current_height = self.chain.read().expect("acquiring chain read lock").current_block().height();
+		if !find && height > current_height + SLOT_IMPORT_TOLERANCE {
+			warn!(self.log, "unknown gossip parent: {:?} {:?}", height, current_height);
+			self.send_to_sync(SyncMessage::OrphanBlock(peer_id, Box::new(block)));
+		}
 
 		find
 	}
@@ -445,7 +817,7 @@ impl MessageProcessor {
         peer_id: PeerId,
         tx: Transaction,
     ) -> bool {
-        if self.tx_pool.write().expect("acquiring tx_pool write_lock").add_tx(tx.clone()) {
+        if self.tx_pool.write().expect("acquiring tx_pool write_lock").add_tx(tx.clone()).is_ok() {
             return true;
         }
 
@@ -454,30 +826,24 @@ impl MessageProcessor {
 
 }
 
-fn import_block(chain: Arc<RwLock<BlockChain>>, block: &Block) -> bool{
-	let broadcast = match chain.write().expect("").import_block(block) {
-		Ok(_) => {
-			true
-		}
-		Err(e) => {
-			println!("network insert_block,Error: {:?}", e);
-			false
-		}
-	};
-	return broadcast
+fn block_error_kind(error: &errors::Error) -> Option<BlockChainErrorKind> {
+    error.downcast_ref::<BlockChainError>().map(|e| e.kind().clone())
 }
 
 /// Build a `StatusMessage` representing the state of the given `block_chain`.
 pub(crate) fn status_message(
-    block_chain: Arc<RwLock<BlockChain>>,
+    block_chain: Arc<TimedRwLock<BlockChain>>,
 ) -> Option<StatusMessage> {
-    let block = block_chain.read().unwrap().current_block();
+    let chain = block_chain.read().unwrap();
+    let block = chain.current_block();
     Some(StatusMessage {
-        genesis_hash: block_chain.read().unwrap().genesis_hash(),
+        genesis_hash: chain.genesis_hash(),
         finalized_root: block.hash(),
         finalized_number: block.height(),
         head_root: block.hash(),
-        network_id: 31133,
+        network_id: chain.spec().network_id,
+        spec_hash: chain.spec().spec_hash(),
+        agent: AGENT_VERSION.to_string(),
     })
 }
 
@@ -514,6 +880,26 @@ impl HandlerNetworkContext {
             });
     }
 
+    /// Say goodbye to the peer and ban it for `duration`, the ban is persisted.
+    pub fn disconnect_and_ban(&mut self, peer_id: PeerId, reason: GoodbyeReason, duration: Duration) {
+        warn!(
+            &self.log,
+            "Banning peer (RPC)";
+            "reason" => format!("{:?}", reason),
+            "peer_id" => format!("{:?}", peer_id),
+        );
+        let ban_reason = reason.to_string();
+        self.send_rpc_request(peer_id.clone(), P2PRequest::Goodbye(reason));
+        self.network_send
+            .try_send(NetworkMessage::BanPeer { peer_id, duration, reason: ban_reason })
+            .unwrap_or_else(|_| {
+                warn!(
+                    self.log,
+                    "Could not send a BanPeer to the network service"
+                )
+            });
+    }
+
     pub fn send_rpc_request(&mut self, peer_id: PeerId, rpc_request: P2PRequest) {
         // the message handler cannot send requests with ids. Id's are managed by the sync
         // manager.
@@ -558,7 +944,7 @@ impl HandlerNetworkContext {
 	pub fn broadcast_block(&mut self, data: &Block) {
 		// Broadcast sealed block to the network
 		let topic = GossipTopic::MapBlock;
-		let message = PubsubMessage::Block(bincode::serialize(data).unwrap());
+		let message = PubsubMessage::Block(wire::encode_block(data));
 		self.network_send
 			.try_send(NetworkMessage::Publish {
 				topics: vec![topic.into()],
@@ -567,4 +953,16 @@ impl HandlerNetworkContext {
 			.unwrap_or_else(|_| warn!(self.log, "Could not send gossip sealed block."));
 	}
 
+	/// Tell peers a forwarded block failed execution.
+	pub fn broadcast_bad_block(&mut self, hash: &Hash) {
+		let topic = GossipTopic::BadBlock;
+		let message = PubsubMessage::BadBlock(hash.to_slice().to_vec());
+		self.network_send
+			.try_send(NetworkMessage::Publish {
+				topics: vec![topic.into()],
+				message,
+			})
+			.unwrap_or_else(|_| warn!(self.log, "Could not send gossip bad block."));
+	}
+
 }
@@ -0,0 +1,289 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Single thread importing the blocks proposed locally and those of gossip, range sync and orphan
+//! lookups in priority order, so that they don't race for the chain write lock and a block
+//! extending the head isn't held behind a sync batch. The pool is reset on each new head.
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
+use std::thread;
+
+use slog::{debug, trace};
+
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use errors::InternalErrorKind;
+use map_core::block::Block;
+use pool::tx_pool::TxPoolManager;
+
+use crate::metrics;
+
+/// Where a block to import comes from, by decreasing priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportSource {
+    /// Block proposed by this node.
+    Local = 0,
+    /// Gossip block on top of the head.
+    Gossip = 1,
+    /// Block of a range sync batch.
+    Sync = 2,
+    /// Blocks whose parents were looked up by hash, and gossip blocks off the head.
+    Orphan = 3,
+}
+
+impl ImportSource {
+    const ALL: [ImportSource; 4] = [ImportSource::Local, ImportSource::Gossip, ImportSource::Sync, ImportSource::Orphan];
+
+    fn name(self) -> &'static str {
+        match self {
+            ImportSource::Local => "local",
+            ImportSource::Gossip => "gossip",
+            ImportSource::Sync => "sync",
+            ImportSource::Orphan => "orphan",
+        }
+    }
+}
+
+pub type ImportResult = Result<(), errors::Error>;
+
+struct Job {
+    source: ImportSource,
+    /// Imported in order, one at a time so that higher priorities get in between.
+    blocks: VecDeque<Block>,
+    results: Vec<ImportResult>,
+    /// Waiting for the results, None if nobody is.
+    done: Option<mpsc::Sender<Vec<ImportResult>>>,
+}
+
+/// Jobs waiting, by priority and then arrival.
+#[derive(Default)]
+struct Lanes {
+    lanes: [VecDeque<Job>; 4],
+    /// Set on shutdown, the import thread exits and no job is taken any more.
+    stopped: bool,
+}
+
+impl Lanes {
+    fn push(&mut self, job: Job) {
+        self.lanes[job.source as usize].push_back(job);
+    }
+
+    /// Put back a job started but not finished, ahead of its lane.
+    fn resume(&mut self, job: Job) {
+        self.lanes[job.source as usize].push_front(job);
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        self.lanes.iter_mut().find_map(|lane| lane.pop_front())
+    }
+
+    fn export(&self) {
+        for (lane, source) in self.lanes.iter().zip(&ImportSource::ALL) {
+            let blocks: usize = lane.iter().map(|job| job.blocks.len()).sum();
+            if let Some(gauge) = metrics::get_int_gauge(&metrics::IMPORT_QUEUE_BLOCKS, &[source.name()]) {
+                gauge.set(blocks as i64);
+            }
+        }
+    }
+}
+
+/// Handle to the import thread, which runs until `stop` is called.
+#[derive(Clone)]
+pub struct ImportQueue {
+    shared: Arc<(Mutex<Lanes>, Condvar)>,
+}
+
+impl ImportQueue {
+    /// Start the import thread of `chain`, resetting `tx_pool` to each block that becomes the head.
+    pub fn spawn(chain: Arc<TimedRwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>, log: slog::Logger) -> Self {
+        let queue = ImportQueue { shared: Arc::new((Mutex::new(Lanes::default()), Condvar::new())) };
+        let worker = queue.clone();
+        thread::Builder::new()
+            .name("block-import".to_string())
+            .spawn(move || {
+                while let Some(job) = worker.next_job() {
+                    worker.run(&chain, &tx_pool, job, &log);
+                }
+                debug!(log, "Block import stopped");
+            })
+            .expect("spawning block import thread");
+        queue
+    }
+
+    /// Stop the import thread once the block being imported is done, releasing the chain.
+    /// Blocks still waiting are dropped and their waiters get an error.
+    pub fn stop(&self) {
+        let (lanes, ready) = &*self.shared;
+        let mut lanes = lanes.lock().expect("acquiring import queue lock");
+        lanes.stopped = true;
+        for lane in lanes.lanes.iter_mut() {
+            lane.clear();
+        }
+        lanes.export();
+        ready.notify_all();
+    }
+
+    /// Import a block and wait for the result.
+    pub fn import(&self, source: ImportSource, block: Block) -> ImportResult {
+        let (done, result) = mpsc::channel();
+        self.push(source, vec![block], Some(done));
+        match result.recv() {
+            Ok(mut results) => results.remove(0),
+            Err(_) => Err(InternalErrorKind::Other("block import stopped".to_string()).into()),
+        }
+    }
+
+    /// Queue blocks to import in order without waiting, failures are logged.
+    pub fn submit(&self, source: ImportSource, blocks: Vec<Block>) {
+        if !blocks.is_empty() {
+            self.push(source, blocks, None);
+        }
+    }
+
+    fn push(&self, source: ImportSource, blocks: Vec<Block>, done: Option<mpsc::Sender<Vec<ImportResult>>>) {
+        let (lanes, ready) = &*self.shared;
+        let mut lanes = lanes.lock().expect("acquiring import queue lock");
+        if lanes.stopped {
+            return;
+        }
+        lanes.push(Job { source, blocks: blocks.into(), results: Vec::new(), done });
+        lanes.export();
+        ready.notify_one();
+    }
+
+    /// Wait for the next job, None once stopped.
+    fn next_job(&self) -> Option<Job> {
+        let (lanes, ready) = &*self.shared;
+        let mut lanes = lanes.lock().expect("acquiring import queue lock");
+        loop {
+            if lanes.stopped {
+                return None;
+            }
+            if let Some(job) = lanes.pop() {
+                return Some(job);
+            }
+            lanes = ready.wait(lanes).expect("acquiring import queue lock");
+        }
+    }
+
+    /// Import the next block of `job`, then hand the job back to the queue or to its waiter.
+    fn run(&self, chain: &Arc<TimedRwLock<BlockChain>>, tx_pool: &Arc<RwLock<TxPoolManager>>, mut job: Job, log: &slog::Logger) {
+        if let Some(block) = job.blocks.pop_front() {
+            let (result, head) = {
+                let mut chain = chain.write().expect("acquiring chain write lock");
+                let result = chain.import_block(&block);
+                (result, chain.current_block().hash() == block.hash())
+            };
+            trace!(log, "Block imported"; "source" => job.source.name(), "height" => block.height(),
+                "ok" => result.is_ok());
+            if result.is_ok() && head {
+                tx_pool.write().expect("acquiring tx_pool write lock").reset_pool(&block);
+            }
+            job.results.push(result);
+        }
+        if !job.blocks.is_empty() {
+            let (lanes, _) = &*self.shared;
+            let mut lanes = lanes.lock().expect("acquiring import queue lock");
+            if lanes.stopped {
+                return;
+            }
+            lanes.resume(job);
+            lanes.export();
+            return;
+        }
+        self.shared.0.lock().expect("acquiring import queue lock").export();
+        match job.done {
+            Some(done) => {
+                let _ = done.send(job.results);
+            }
+            None => {
+                for error in job.results.iter().filter_map(|r| r.as_ref().err()) {
+                    debug!(log, "Queued block import failed"; "source" => job.source.name(), "error" => format!("{}", error));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::test_utils::BlockBuilder;
+    use slog::o;
+
+    fn job(source: ImportSource, blocks: usize) -> Job {
+        Job { source, blocks: vec![Block::default(); blocks].into(), results: Vec::new(), done: None }
+    }
+
+    #[test]
+    fn lanes_by_priority() {
+        let mut lanes = Lanes::default();
+        lanes.push(job(ImportSource::Orphan, 1));
+        lanes.push(job(ImportSource::Sync, 1));
+        lanes.push(job(ImportSource::Sync, 2));
+        lanes.push(job(ImportSource::Gossip, 1));
+        lanes.push(job(ImportSource::Local, 1));
+
+        assert_eq!(lanes.pop().unwrap().source, ImportSource::Local);
+        assert_eq!(lanes.pop().unwrap().source, ImportSource::Gossip);
+        let mut started = lanes.pop().unwrap();
+        assert_eq!(started.blocks.len(), 1);
+        // A gossip block arriving mid-job goes first, the job resumes ahead of the later one
+        started.blocks.pop_front();
+        started.blocks.push_back(Block::default());
+        lanes.push(job(ImportSource::Gossip, 1));
+        lanes.resume(started);
+        assert_eq!(lanes.pop().unwrap().source, ImportSource::Gossip);
+        assert_eq!(lanes.pop().unwrap().blocks.len(), 1);
+        assert_eq!(lanes.pop().unwrap().blocks.len(), 2);
+        assert_eq!(lanes.pop().unwrap().source, ImportSource::Orphan);
+        assert!(lanes.pop().is_none());
+    }
+
+    #[test]
+    fn import_in_order() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+        let chain = Arc::new(TimedRwLock::new("chain", chain));
+        let tx_pool = Arc::new(RwLock::new(TxPoolManager::new(chain.clone())));
+        let queue = ImportQueue::spawn(chain.clone(), tx_pool, slog::Logger::root(slog::Discard, o!()));
+
+        let first = BlockBuilder::on(&genesis).build();
+        let second = BlockBuilder::on(&first).build();
+        let third = BlockBuilder::on(&second).build();
+        queue.submit(ImportSource::Orphan, vec![first.clone(), second]);
+        // Jobs of a lane run in arrival order, waiting on a later one drains the earlier
+        assert!(queue.import(ImportSource::Orphan, third.clone()).is_ok());
+        assert_eq!(chain.read().unwrap().current_block().hash(), third.hash());
+
+        assert!(queue.import(ImportSource::Gossip, first).is_err());
+        assert!(queue.import(ImportSource::Sync, BlockBuilder::on(&genesis).time(1).build()).is_err());
+        let fourth = BlockBuilder::on(&third).build();
+        assert!(queue.import(ImportSource::Gossip, fourth.clone()).is_ok());
+        assert_eq!(chain.read().unwrap().current_block().hash(), fourth.hash());
+
+        // Stopped, the thread lets go of the chain and imports fail
+        queue.stop();
+        assert!(queue.import(ImportSource::Local, BlockBuilder::on(&fourth).build()).is_err());
+        let start = std::time::Instant::now();
+        while Arc::strong_count(&chain) > 1 {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
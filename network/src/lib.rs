@@ -1,3 +1,6 @@
+#[macro_use]
+extern crate lazy_static;
+
 pub use libp2p::{
     gossipsub::{GossipsubConfig, GossipsubConfigBuilder},
     PeerId,
@@ -10,22 +13,76 @@ pub use config::Config as NetworkConfig;
 pub use topics::GossipTopic;
 pub use handler_processor::MessageProcessor;
 
+/// Name and version of this node announced to peers in the identify protocol and the status
+/// handshake.
+pub const AGENT_VERSION: &str = concat!("map/", env!("CARGO_PKG_VERSION"));
+/// Identify protocol version of the clients that don't announce their genesis.
+pub const PROTOCOL_VERSION: &str = "map/p2p";
+/// Leading bytes of the genesis hash announced in the identify protocol version.
+const GENESIS_PREFIX_BYTES: usize = 8;
+/// Ban of the peers following a chain of another genesis, they never become useful.
+pub const GENESIS_MISMATCH_BAN: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+
+/// Identify protocol version naming the genesis of our chain, e.g. `map/p2p/0123456789abcdef`,
+/// so peers of another chain are dropped before the status handshake.
+pub fn protocol_version(genesis_hash: &map_core::types::Hash) -> String {
+    format!("{}/{}", PROTOCOL_VERSION, hex::encode(&genesis_hash.0[..GENESIS_PREFIX_BYTES]))
+}
+
+/// Whether a peer announcing `remote` follows another genesis than `local`. Peers of older
+/// clients announce none and are left to the status handshake.
+pub fn is_other_genesis(local: &str, remote: &str) -> bool {
+    let prefix = format!("{}/", PROTOCOL_VERSION);
+    match (local.strip_prefix(&prefix), remote.strip_prefix(&prefix)) {
+        (Some(local), Some(remote)) => local != remote,
+        _ => false,
+    }
+}
+
 pub mod service;
+pub mod ban_list;
+pub mod bad_blocks;
+pub mod block_queue;
+pub mod import_queue;
+pub mod gossip_limits;
 pub mod transport;
+pub mod wire;
+pub mod vectors;
 pub mod behaviour;
 pub mod config;
 pub mod manager;
 pub mod error;
 pub mod p2p;
+pub mod p2p_trace;
 pub mod topics;
 pub mod handler;
 pub mod handler_processor;
 pub mod sync;
+pub mod peer_stats;
+pub mod propagation;
+pub mod state_heal;
+pub mod time_sync;
+pub mod topic_peers;
+mod metrics;
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use map_core::types::Hash;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn genesis_in_protocol_version() {
+        let local = protocol_version(&Hash([1; 32]));
+        assert_eq!(local, "map/p2p/0101010101010101");
+        assert!(!is_other_genesis(&local, &protocol_version(&Hash([1; 32]))));
+        assert!(is_other_genesis(&local, &protocol_version(&Hash([2; 32]))));
+        // Older clients are checked by the status handshake
+        assert!(!is_other_genesis(&local, PROTOCOL_VERSION));
+        assert!(!is_other_genesis(&local, "ipfs/0.1.0"));
+    }
 }
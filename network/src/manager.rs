@@ -1,5 +1,6 @@
 use std::{thread};
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::AtomicUsize;
 use std::time::{Duration, Instant};
 
 use futures::{Future, Stream};
@@ -18,6 +19,7 @@ use tokio::timer::Delay;
 
 use pool::tx_pool::TxPoolManager;
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
 use map_core::block::Block;
 use map_core::transaction::Transaction;
 
@@ -30,8 +32,17 @@ use crate::{
     service::{Libp2pEvent, Service},
 };
 use crate::error;
+use crate::wire;
 use crate::handler::{HandlerMessage, MessageHandler};
-use crate::p2p::{P2PEvent, P2PRequest};
+use crate::sync::{SharedSyncProgress, SyncProgress};
+use crate::peer_stats::{PeerStats, SharedPeerStats};
+use crate::block_queue::{BlockQueue, SharedBlockQueue};
+use crate::import_queue::ImportQueue;
+use crate::propagation::{Propagation, SharedPropagation};
+use crate::time_sync::{ClockOffsets, SharedClockOffsets};
+use crate::topic_peers::{MeshChange, SharedTopicPeers, TopicPeers, MESH_GRACE_PERIOD, MIN_BLOCK_TOPIC_PEERS};
+use crate::p2p::{methods::GoodbyeReason, P2PEvent, P2PRequest};
+use crate::p2p_trace::{Direction, P2PTrace, SharedP2PTrace};
 
 /// The time in seconds that a peer will be banned and prevented from reconnecting.
 const BAN_PEER_TIMEOUT: u64 = 30;
@@ -40,13 +51,31 @@ pub struct NetworkExecutor {
     service: Arc<Mutex<Service>>,
     pub exit_signal: oneshot::Sender<i32>,
     pub network_send: mpsc::UnboundedSender<NetworkMessage>,
+    /// Progress of the long-range sync, shared with the RPC service.
+    pub sync_progress: SharedSyncProgress,
+    /// Number of connected peers, shared with the RPC service.
+    pub peer_count: Arc<AtomicUsize>,
+    /// Message statistics of the connected peers, shared with the RPC service.
+    pub peer_stats: SharedPeerStats,
+    /// Arrival and import delays of gossiped blocks, shared with the RPC service.
+    pub propagation: SharedPropagation,
+    /// Offsets of the peer clocks to ours, shared with the RPC service.
+    pub clock_offsets: SharedClockOffsets,
+    /// Gossip topics of the connected peers, shared with the RPC service.
+    pub topic_peers: SharedTopicPeers,
+    /// Trace of the p2p messages, switched on and off by the RPC service.
+    pub p2p_trace: SharedP2PTrace,
+    /// Gossip blocks received ahead of the head, read by the RPC service.
+    pub block_queue: SharedBlockQueue,
+    /// Import thread of the chain, shared with block proposal and stopped on shutdown.
+    pub importer: ImportQueue,
     log: slog::Logger,
 }
 
 impl NetworkExecutor {
     pub fn new(
         cfg: NetworkConfig,
-        block_chain: Arc<RwLock<BlockChain>>,
+        block_chain: Arc<TimedRwLock<BlockChain>>,
         tx_pool: Arc<RwLock<TxPoolManager>>,
         executor: &tokio::runtime::TaskExecutor,
         log_level: String
@@ -70,15 +99,44 @@ impl NetworkExecutor {
 
         let log = slog::Logger::root(drain.fuse(), o!());
 
+        let sync_progress = Arc::new(RwLock::new(SyncProgress::default()));
+        let propagation = Arc::new(RwLock::new(Propagation::default()));
+        let clock_offsets = Arc::new(RwLock::new(ClockOffsets::default()));
+        let block_queue = {
+            let mut chain = block_chain.write().expect("acquiring chain write lock");
+            Arc::new(RwLock::new(BlockQueue::new(&mut chain)))
+        };
+        let importer = ImportQueue::spawn(block_chain.clone(), tx_pool.clone(), log.clone());
         let message_handler_send = MessageHandler::spawn(
             block_chain.clone(),
+            importer.clone(),
             network_send.clone(),
             tx_pool,
+            sync_progress.clone(),
+            cfg.range_sync.clone(),
+            cfg.network_dir.clone(),
+            propagation.clone(),
+            clock_offsets.clone(),
+            block_queue.clone(),
             executor,
             log.clone(),
         )?;
 
-        let service = Arc::new(Mutex::new(Service::new(cfg, log.clone())?));
+        let mut trace = P2PTrace::new(cfg.network_dir.clone());
+        if cfg.p2p_trace {
+            match trace.enable() {
+                Ok(()) => info!(log, "Tracing p2p messages"; "path" => trace.status().path),
+                Err(e) => warn!(log, "Could not open the p2p trace file"; "error" => format!("{}", e)),
+            }
+        }
+        let p2p_trace = Arc::new(RwLock::new(trace));
+
+        let genesis_hash = block_chain.read().expect("acquiring chain read lock").genesis_hash();
+        let service = Service::new(cfg, crate::protocol_version(&genesis_hash), log.clone())?;
+        let peer_count = service.peer_count();
+        let service = Arc::new(Mutex::new(service));
+        let peer_stats = Arc::new(RwLock::new(PeerStats::default()));
+        let topic_peers = Arc::new(RwLock::new(TopicPeers::default()));
 
         // A delay used to initialise code after the network has started
         // This is currently used to obtain the listening addresses from the libp2p service.
@@ -89,6 +147,9 @@ impl NetworkExecutor {
             network_recv,
             message_handler_send,
             block_chain,
+            peer_stats.clone(),
+            topic_peers.clone(),
+            p2p_trace.clone(),
             initial_delay,
             log.clone(),
         )?;
@@ -97,6 +158,15 @@ impl NetworkExecutor {
             service,
             exit_signal,
             network_send,
+            sync_progress,
+            peer_count,
+            peer_stats,
+            propagation,
+            clock_offsets,
+            topic_peers,
+            p2p_trace,
+            block_queue,
+            importer,
             log,
         };
 
@@ -106,7 +176,7 @@ impl NetworkExecutor {
     pub fn publish_block(&mut self, data: Block) {
         // Publish sealed block to the network
         let topic = GossipTopic::MapBlock;
-        let message = PubsubMessage::Block(bincode::serialize(&data).unwrap());
+        let message = PubsubMessage::Block(wire::encode_block(&data));
         self.network_send
             .try_send(NetworkMessage::Publish {
                 topics: vec![topic.into()],
@@ -118,7 +188,7 @@ impl NetworkExecutor {
     pub fn publish_transaction(&mut self, data: Transaction) {
         // Publish collected transaction to the network
         let topic = GossipTopic::Transaction;
-        let message = PubsubMessage::Transaction(bincode::serialize(&data).unwrap());
+        let message = PubsubMessage::Transaction(wire::encode_transaction(&data));
         self.network_send
             .try_send(NetworkMessage::Publish {
                 topics: vec![topic.into()],
@@ -131,7 +201,7 @@ impl NetworkExecutor {
 pub fn publish_transaction(network_send: &mut mpsc::UnboundedSender<NetworkMessage>, data: Transaction) {
     // Publish collected transaction to the network
     let topic = GossipTopic::Transaction;
-    let message = PubsubMessage::Transaction(bincode::serialize(&data).unwrap());
+    let message = PubsubMessage::Transaction(wire::encode_transaction(&data));
     network_send
         .try_send(NetworkMessage::Publish {
             topics: vec![topic.into()],
@@ -143,7 +213,7 @@ pub fn publish_transaction(network_send: &mut mpsc::UnboundedSender<NetworkMessa
 pub fn publish_block(network_send: &mut mpsc::UnboundedSender<NetworkMessage>, data: Block) {
     // Publish sealed block to the network
     let topic = GossipTopic::MapBlock;
-    let message = PubsubMessage::Block(bincode::serialize(&data).unwrap());
+    let message = PubsubMessage::Block(wire::encode_block(&data));
     network_send
         .try_send(NetworkMessage::Publish {
             topics: vec![topic.into()],
@@ -156,7 +226,10 @@ fn start_service(
     libp2p_service: Arc<Mutex<Service>>,
     network_recv: mpsc::UnboundedReceiver<NetworkMessage>,
     message_handler_send: mpsc::UnboundedSender<HandlerMessage>,
-    block_chain: Arc<RwLock<BlockChain>>,
+    block_chain: Arc<TimedRwLock<BlockChain>>,
+    peer_stats: SharedPeerStats,
+    topic_peers: SharedTopicPeers,
+    p2p_trace: SharedP2PTrace,
 	initial_delay: Delay,
     log: slog::Logger,
 ) -> error::Result<tokio::sync::oneshot::Sender<i32>> {
@@ -170,6 +243,9 @@ fn start_service(
                 network_recv,
                 message_handler_send,
                 block_chain,
+                peer_stats,
+                topic_peers,
+                p2p_trace,
                 initial_delay,
                 log.clone(),
             )
@@ -189,10 +265,15 @@ fn network_service(
     libp2p_service: Arc<Mutex<Service>>,
     mut network_recv: mpsc::UnboundedReceiver<NetworkMessage>,
     mut message_handler_send: mpsc::UnboundedSender<HandlerMessage>,
-    block_chain: Arc<RwLock<BlockChain>>,
+    block_chain: Arc<TimedRwLock<BlockChain>>,
+    peer_stats: SharedPeerStats,
+    topic_peers: SharedTopicPeers,
+    p2p_trace: SharedP2PTrace,
     mut initial_delay: Delay,
     log: slog::Logger,
 ) -> impl futures::Future<Item=(), Error=()> {
+    let local_protocol = libp2p_service.lock().protocol_version.clone();
+    let mut mesh_grace = Some(Delay::new(Instant::now() + MESH_GRACE_PERIOD));
     futures::future::poll_fn(move || -> Result<_, ()> {
         if !initial_delay.is_elapsed() {
             if let Ok(Async::Ready(_)) = initial_delay.poll() {
@@ -200,6 +281,12 @@ fn network_service(
                 println!("multi_addrs {:?}", multi_addrs)
             }
         }
+        // a node isolated from the start is reported once it had time to find its peers
+        if let Some(Ok(Async::Ready(_))) = mesh_grace.as_mut().map(|delay| delay.poll()) {
+            mesh_grace = None;
+            let change = topic_peers.write().unwrap().end_grace();
+            log_mesh_change(&log, change);
+        }
 
         loop {
             // poll the network channel
@@ -207,10 +294,13 @@ fn network_service(
                 Ok(Async::Ready(Some(message))) => match message {
                     NetworkMessage::Publish { topics, message } => {
                         debug!(log, "Sending pubsub message"; "topics" => format!("{:?}",topics));
+                        p2p_trace.write().unwrap().on_gossip(Direction::Out, None, &message);
                         libp2p_service.lock().swarm.publish(&topics, message.clone());
                     }
                     NetworkMessage::P2P(peer_id, rpc_event) => {
                         trace!(log, "Sending RPC"; "rpc" => format!("{}", rpc_event));
+                        peer_stats.write().unwrap().on_sent(&peer_id, &rpc_event);
+                        p2p_trace.write().unwrap().on_p2p(Direction::Out, &peer_id, &rpc_event);
                         libp2p_service.lock().swarm.send_rpc(peer_id, rpc_event);
                     }
                     NetworkMessage::Propagate {
@@ -229,8 +319,15 @@ fn network_service(
                         libp2p_service.lock().disconnect_and_ban_peer(
                             peer_id,
                             std::time::Duration::from_secs(BAN_PEER_TIMEOUT),
+                            "disconnect",
                         );
                     }
+                    NetworkMessage::BanPeer { peer_id, duration, reason } => {
+                        libp2p_service.lock().disconnect_and_ban_peer(peer_id, duration, &reason);
+                    }
+                    NetworkMessage::UnbanPeer { peer_id } => {
+                        libp2p_service.lock().unban_peer(peer_id);
+                    }
                 },
                 Ok(Async::NotReady) => break,
                 Ok(Async::Ready(None)) => {
@@ -245,16 +342,23 @@ fn network_service(
         }
 
         let mut peers_to_ban = Vec::new();
+        let mut other_genesis = Vec::new();
         loop {
             // poll the swarm
             match libp2p_service.lock().poll() {
                 Ok(Async::Ready(Some(event))) => match event {
                     Libp2pEvent::RPC(peer_id, rpc_event) => {
                         // trace!(log, "Received RPC"; "rpc" => format!("{}", rpc_event));
+                        peer_stats.write().unwrap().on_received(&peer_id, &rpc_event);
+                        p2p_trace.write().unwrap().on_p2p(Direction::In, &peer_id, &rpc_event);
 
                         // if we received a Goodbye message, drop and ban the peer
-                        if let P2PEvent::Request(_, P2PRequest::Goodbye(_)) = rpc_event {
-                            peers_to_ban.push(peer_id.clone());
+                        match rpc_event {
+                            P2PEvent::Request(_, P2PRequest::Goodbye(GoodbyeReason::GenesisMismatch)) => {
+                                other_genesis.push(peer_id.clone());
+                            }
+                            P2PEvent::Request(_, P2PRequest::Goodbye(_)) => peers_to_ban.push(peer_id.clone()),
+                            _ => {}
                         };
                         message_handler_send
                             .try_send(HandlerMessage::RPC(peer_id, rpc_event))
@@ -266,6 +370,7 @@ fn network_service(
                         message,
                         ..
                     } => {
+                        p2p_trace.write().unwrap().on_gossip(Direction::In, Some(&source), &message);
                         message_handler_send
                             .try_send(HandlerMessage::PubsubMessage(id, source, message))
                             .map_err(|_| { debug!(log, "Failed to send pubsub message to handler"); })?;
@@ -276,8 +381,27 @@ fn network_service(
                             .try_send(HandlerMessage::PeerDialed(peer_id))
                             .map_err(|_| { debug!(log, "Failed to send peer dialed to handler"); })?;
                     }
+                    Libp2pEvent::PeerIdentified { peer_id, agent_version, protocol_version } => {
+                        if crate::is_other_genesis(&local_protocol, &protocol_version) {
+                            warn!(log, "Handshake Failure"; "peer" => format!("{:?}", peer_id),
+                                "reason" => "genesis", "local" => local_protocol.as_str(), "remote" => protocol_version.as_str());
+                            other_genesis.push(peer_id.clone());
+                        }
+                        peer_stats.write().unwrap().on_identified(&peer_id, agent_version, protocol_version);
+                    }
+                    Libp2pEvent::PeerSubscribed { peer_id, topic } => {
+                        let change = topic_peers.write().unwrap().on_subscribed(&peer_id, topic.as_str());
+                        log_mesh_change(&log, change);
+                    }
+                    Libp2pEvent::PeerUnsubscribed { peer_id, topic } => {
+                        let change = topic_peers.write().unwrap().on_unsubscribed(&peer_id, topic.as_str());
+                        log_mesh_change(&log, change);
+                    }
                     Libp2pEvent::PeerDisconnected(peer_id) => {
                         debug!(log, "Peer Disconnected";  "peer_id" => format!("{:?}", peer_id));
+                        peer_stats.write().unwrap().remove_peer(&peer_id);
+                        let change = topic_peers.write().unwrap().remove_peer(&peer_id);
+                        log_mesh_change(&log, change);
                         message_handler_send
                             .try_send(HandlerMessage::PeerDisconnected(peer_id))
                             .map_err(|_| { debug!(log, "Failed to send peer disconnect to handler"); })?;
@@ -289,11 +413,23 @@ fn network_service(
             }
         }
 
+        // peers of another genesis are told why and not dialed again for a long while
+        while let Some(peer_id) = other_genesis.pop() {
+            let goodbye = P2PEvent::Request(0, P2PRequest::Goodbye(GoodbyeReason::GenesisMismatch));
+            let mut service = libp2p_service.lock();
+            if service.peers.contains(&peer_id) {
+                service.swarm.send_rpc(peer_id.clone(), goodbye);
+            }
+            service.disconnect_and_ban_peer(
+                peer_id, crate::GENESIS_MISMATCH_BAN, &GoodbyeReason::GenesisMismatch.to_string());
+        }
+
         // ban and disconnect any peers that sent Goodbye requests
         while let Some(peer_id) = peers_to_ban.pop() {
             libp2p_service.lock().disconnect_and_ban_peer(
                 peer_id.clone(),
                 std::time::Duration::from_secs(BAN_PEER_TIMEOUT),
+                "goodbye",
             );
         }
 
@@ -301,6 +437,19 @@ fn network_service(
     })
 }
 
+/// Warn when the block topic runs short of subscribers, blocks then stop arriving by gossip
+/// while the peers stay connected.
+fn log_mesh_change(log: &slog::Logger, change: Option<MeshChange>) {
+    match change {
+        Some(MeshChange::Thin(peers)) => warn!(
+            log, "Few peers on the block topic, gossiped blocks may stop arriving";
+            "peers" => peers, "min_peers" => MIN_BLOCK_TOPIC_PEERS,
+        ),
+        Some(MeshChange::Recovered(peers)) => info!(log, "Block topic peers recovered"; "peers" => peers),
+        None => {}
+    }
+}
+
 //Future<Item=Foo, Error=Bar>
 //Future<Output=Result<Foo, Bar>>
 /// Types of messages that the network Network can receive.
@@ -320,4 +469,12 @@ pub enum NetworkMessage {
     },
     /// Disconnect and bans a peer id.
     Disconnect { peer_id: PeerId },
+    /// Disconnect and ban a peer for the given duration, the ban is persisted.
+    BanPeer {
+        peer_id: PeerId,
+        duration: Duration,
+        reason: String,
+    },
+    /// Lift the ban of a peer.
+    UnbanPeer { peer_id: PeerId },
 }
@@ -0,0 +1,110 @@
+pub use map_metrics::*;
+
+lazy_static! {
+    /*
+     * P2P protocol
+     */
+    pub static ref P2P_INBOUND_REQUESTS_RATE_LIMITED: Result<IntCounter> = try_create_int_counter(
+        "p2p_inbound_requests_rate_limited_total",
+        "Inbound p2p requests refused because the peer had too many requests in flight"
+    );
+    pub static ref P2P_MESSAGES: Result<IntCounterVec> = try_create_int_counter_vec(
+        "p2p_messages_total",
+        "P2P requests and response chunks by protocol and direction",
+        &["protocol", "direction"]
+    );
+    pub static ref P2P_MESSAGE_BYTES: Result<IntCounterVec> = try_create_int_counter_vec(
+        "p2p_message_bytes_total",
+        "Encoded size of the p2p requests and response chunks by protocol and direction",
+        &["protocol", "direction"]
+    );
+    pub static ref P2P_MESSAGE_ERRORS: Result<IntCounterVec> = try_create_int_counter_vec(
+        "p2p_message_errors_total",
+        "P2P error responses and failed requests by protocol",
+        &["protocol"]
+    );
+    pub static ref P2P_REQUEST_LATENCY: Result<HistogramVec> = try_create_histogram_vec(
+        "p2p_request_latency_seconds",
+        "Time from sending a p2p request to its first response chunk",
+        &["protocol"]
+    );
+
+    /*
+     * Gossip
+     */
+    pub static ref GOSSIP_MESSAGES_DROPPED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "gossip_messages_dropped_total",
+        "Gossip messages dropped before decoding by topic and limit exceeded",
+        &["topic", "reason"]
+    );
+    pub static ref GOSSIP_TOPIC_PEERS: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "gossip_topic_peers",
+        "Connected peers subscribed to each gossip topic, the most the topic mesh can hold",
+        &["topic"]
+    );
+    pub static ref GOSSIP_PEERS_BANNED: Result<IntCounter> = try_create_int_counter(
+        "gossip_peers_banned_total",
+        "Peers banned for repeatedly exceeding the gossip size or rate limits"
+    );
+
+    /*
+     * Block propagation
+     */
+    pub static ref BLOCK_ARRIVAL_DELAY: Result<Histogram> = try_create_histogram(
+        "block_propagation_arrival_seconds",
+        "Time from the slot start of a gossiped block to its first arrival"
+    );
+    pub static ref BLOCK_IMPORT_DELAY: Result<Histogram> = try_create_histogram(
+        "block_propagation_import_seconds",
+        "Time from the slot start of a gossiped block to its import"
+    );
+
+    /*
+     * Block import
+     */
+    pub static ref IMPORT_QUEUE_BLOCKS: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "import_queue_blocks",
+        "Blocks waiting for the import thread by source",
+        &["source"]
+    );
+
+    /*
+     * State healing
+     */
+    pub static ref STATE_HEAL_STALLED: Result<IntGauge> = try_create_int_gauge(
+        "state_heal_stalled",
+        "1 while a state root is being healed and no connected peer has the missing nodes"
+    );
+
+    /*
+     * Clock
+     */
+    pub static ref CLOCK_OFFSET_MS: Result<IntGauge> = try_create_int_gauge(
+        "clock_offset_milliseconds",
+        "Median offset of the peer clocks to the local clock, positive when the local clock is behind"
+    );
+    pub static ref CLOCK_SKEWED: Result<IntGauge> = try_create_int_gauge(
+        "clock_skewed",
+        "1 while the median peer clock offset exceeds the allowed skew"
+    );
+
+    /*
+     * Range sync
+     */
+    pub static ref SYNC_HIGHEST_PEER_HEIGHT: Result<IntGauge> = try_create_int_gauge(
+        "sync_highest_peer_height",
+        "Median block height advertised by the connected peers"
+    );
+    pub static ref SYNC_PROCESSED_HEIGHT: Result<IntGauge> = try_create_int_gauge(
+        "sync_processed_height",
+        "Height of the local head while syncing"
+    );
+    pub static ref SYNC_BLOCKS_PER_SECOND: Result<Gauge> = try_create_float_gauge(
+        "sync_blocks_per_second",
+        "Rate of blocks imported by range sync"
+    );
+    pub static ref SYNC_ETA_SECONDS: Result<IntGauge> = try_create_int_gauge(
+        "sync_eta_seconds",
+        "Estimated seconds until the local head reaches the median peer height"
+    );
+}
@@ -1,9 +1,14 @@
 use crate::p2p::{
     codec::base::OutboundCodec,
     protocol::{
-        ProtocolId, P2PError, RPC_BLOCKS_BY_RANGE, RPC_BLOCKS_BY_ROOT, RPC_GOODBYE, RPC_STATUS,
+        ProtocolId, P2PError, RPC_BLOCKS_BY_RANGE, RPC_BLOCKS_BY_ROOT, RPC_GOODBYE, RPC_STATE_NODES,
+        RPC_STATUS, RPC_POOLED_TX_HASHES, RPC_POOLED_TXS, RPC_TIME_SYNC,
     },
 };
+use crate::p2p::methods::{
+    PooledTransactionHashesRequest, PooledTransactionsRequest, StateNodesRequest,
+    MAX_POOLED_TXS_PER_REQUEST, MAX_POOLED_TX_HASHES, MAX_STATE_NODES_PER_REQUEST,
+};
 use crate::p2p::{ErrorMessage, P2PErrorResponse, P2PRequest, P2PResponse};
 use libp2p::bytes::{BufMut, Bytes, BytesMut};
 use tokio::codec::{Decoder, Encoder};
@@ -43,10 +48,14 @@ impl Encoder for BINInboundCodec {
                     P2PResponse::Status(res) => bincode::serialize(&res).unwrap(),
                     P2PResponse::BlocksByRange(res) => res, // already raw bytes
                     P2PResponse::BlocksByRoot(res) => res,  // already raw bytes
+                    P2PResponse::StateNodes(res) => res,    // already raw bytes
+                    P2PResponse::PooledTransactions(res) => res, // already raw bytes
+                    P2PResponse::TimeSync(res) => bincode::serialize(&res).unwrap(),
                 }
             }
             P2PErrorResponse::InvalidRequest(err) => bincode::serialize(&err).unwrap(),
             P2PErrorResponse::ServerError(err) => bincode::serialize(&err).unwrap(),
+            P2PErrorResponse::RateLimited(err) => bincode::serialize(&err).unwrap(),
             P2PErrorResponse::Unknown(err) => bincode::serialize(&err).unwrap(),
             P2PErrorResponse::StreamTermination(_) => {
                 unreachable!("Code error - attempting to encode a stream termination")
@@ -77,19 +86,47 @@ impl Decoder for BINInboundCodec {
 
             Ok(Some(packet)) => match self.protocol.message_name.as_str() {
                 RPC_STATUS => match self.protocol.version.as_str() {
-                    "1" => Ok(Some(P2PRequest::Status(bincode::deserialize(&packet[..]).unwrap()))),
+                    "2" => Ok(Some(P2PRequest::Status(bincode::deserialize(&packet[..])?))),
                     _ => unreachable!("Cannot negotiate an unknown version"),
                 },
                 RPC_GOODBYE => match self.protocol.version.as_str() {
-                    "1" => Ok(Some(P2PRequest::Goodbye(bincode::deserialize(&packet[..]).unwrap()))),
+                    "1" => Ok(Some(P2PRequest::Goodbye(bincode::deserialize(&packet[..])?))),
                     _ => unreachable!("Cannot negotiate an unknown version"),
                 },
                 RPC_BLOCKS_BY_RANGE => match self.protocol.version.as_str() {
-                    "1" => Ok(Some(P2PRequest::BlocksByRange(bincode::deserialize(&packet[..]).unwrap()))),
+                    "1" => Ok(Some(P2PRequest::BlocksByRange(bincode::deserialize(&packet[..])?))),
                     _ => unreachable!("Cannot negotiate an unknown version"),
                 },
                 RPC_BLOCKS_BY_ROOT => match self.protocol.version.as_str() {
-                    "1" => Ok(Some(P2PRequest::BlocksByRoot(bincode::deserialize(&packet[..]).unwrap()))),
+                    "1" => Ok(Some(P2PRequest::BlocksByRoot(bincode::deserialize(&packet[..])?))),
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
+                RPC_STATE_NODES => match self.protocol.version.as_str() {
+                    "1" => {
+                        let request: StateNodesRequest = bincode::deserialize(&packet[..])?;
+                        check_len(request.node_hashes.len(), MAX_STATE_NODES_PER_REQUEST, RPC_STATE_NODES)?;
+                        Ok(Some(P2PRequest::StateNodes(request)))
+                    }
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
+                RPC_POOLED_TX_HASHES => match self.protocol.version.as_str() {
+                    "1" => {
+                        let request: PooledTransactionHashesRequest = bincode::deserialize(&packet[..])?;
+                        check_len(request.tx_hashes.len(), MAX_POOLED_TX_HASHES, RPC_POOLED_TX_HASHES)?;
+                        Ok(Some(P2PRequest::PooledTransactionHashes(request)))
+                    }
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
+                RPC_POOLED_TXS => match self.protocol.version.as_str() {
+                    "1" => {
+                        let request: PooledTransactionsRequest = bincode::deserialize(&packet[..])?;
+                        check_len(request.tx_hashes.len(), MAX_POOLED_TXS_PER_REQUEST, RPC_POOLED_TXS)?;
+                        Ok(Some(P2PRequest::PooledTransactions(request)))
+                    }
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
+                RPC_TIME_SYNC => match self.protocol.version.as_str() {
+                    "1" => Ok(Some(P2PRequest::TimeSync(bincode::deserialize(&packet[..])?))),
                     _ => unreachable!("Cannot negotiate an unknown version"),
                 },
                 _ => unreachable!("Cannot negotiate an unknown protocol"),
@@ -100,6 +137,14 @@ impl Decoder for BINInboundCodec {
     }
 }
 
+/// Refuse requests listing more hashes than a peer may ask for at once.
+fn check_len(len: usize, max: usize, message_name: &str) -> Result<(), P2PError> {
+    if len > max {
+        return Err(P2PError::Custom(format!("{} request of {} hashes, at most {}", message_name, len, max)));
+    }
+    Ok(())
+}
+
 /* Outbound Codec: Codec for initiating P2P requests */
 
 pub struct BINOutboundCodec {
@@ -133,6 +178,10 @@ impl Encoder for BINOutboundCodec {
             P2PRequest::Goodbye(req) => bincode::serialize(&req).unwrap(),
             P2PRequest::BlocksByRange(req) => bincode::serialize(&req).unwrap(),
             P2PRequest::BlocksByRoot(req) => bincode::serialize(&req.block_roots).unwrap(),
+            P2PRequest::StateNodes(req) => bincode::serialize(&req).unwrap(),
+            P2PRequest::PooledTransactionHashes(req) => bincode::serialize(&req).unwrap(),
+            P2PRequest::PooledTransactions(req) => bincode::serialize(&req).unwrap(),
+            P2PRequest::TimeSync(req) => bincode::serialize(&req).unwrap(),
         };
         // length-prefix
         self.inner
@@ -157,7 +206,7 @@ impl Decoder for BINOutboundCodec {
             src.clear();
             match self.protocol.message_name.as_str() {
                 RPC_STATUS => match self.protocol.version.as_str() {
-                    "1" => Err(P2PError::Custom(
+                    "2" => Err(P2PError::Custom(
                         "Status stream terminated unexpectedly".into(),
                     )), // cannot have an empty HELLO message. The stream has terminated unexpectedly
                     _ => unreachable!("Cannot negotiate an unknown version"),
@@ -171,6 +220,21 @@ impl Decoder for BINOutboundCodec {
                     "1" => Ok(Some(P2PResponse::BlocksByRoot(Vec::new()))),
                     _ => unreachable!("Cannot negotiate an unknown version"),
                 },
+                RPC_STATE_NODES => match self.protocol.version.as_str() {
+                    "1" => Ok(Some(P2PResponse::StateNodes(Vec::new()))),
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
+                RPC_POOLED_TX_HASHES => Err(P2PError::InvalidProtocol("POOLED_TX_HASHES doesn't have a response")),
+                RPC_POOLED_TXS => match self.protocol.version.as_str() {
+                    "1" => Ok(Some(P2PResponse::PooledTransactions(Vec::new()))),
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
+                RPC_TIME_SYNC => match self.protocol.version.as_str() {
+                    "1" => Err(P2PError::Custom(
+                        "TimeSync stream terminated unexpectedly".into(),
+                    )),
+                    _ => unreachable!("Cannot negotiate an unknown version"),
+                },
                 _ => unreachable!("Cannot negotiate an unknown protocol"),
             }
         } else {
@@ -181,7 +245,7 @@ impl Decoder for BINOutboundCodec {
 
                     match self.protocol.message_name.as_str() {
                         RPC_STATUS => match self.protocol.version.as_str() {
-                            "1" => Ok(Some(P2PResponse::Status(bincode::deserialize(&raw_bytes[..]).unwrap()))),
+                            "2" => Ok(Some(P2PResponse::Status(bincode::deserialize(&raw_bytes[..])?))),
                             _ => unreachable!("Cannot negotiate an unknown version"),
                         },
                         RPC_GOODBYE => {
@@ -195,6 +259,21 @@ impl Decoder for BINOutboundCodec {
                             "1" => Ok(Some(P2PResponse::BlocksByRoot(raw_bytes.to_vec()))),
                             _ => unreachable!("Cannot negotiate an unknown version"),
                         },
+                        RPC_STATE_NODES => match self.protocol.version.as_str() {
+                            "1" => Ok(Some(P2PResponse::StateNodes(raw_bytes.to_vec()))),
+                            _ => unreachable!("Cannot negotiate an unknown version"),
+                        },
+                        RPC_POOLED_TX_HASHES => {
+                            Err(P2PError::InvalidProtocol("POOLED_TX_HASHES doesn't have a response"))
+                        }
+                        RPC_POOLED_TXS => match self.protocol.version.as_str() {
+                            "1" => Ok(Some(P2PResponse::PooledTransactions(raw_bytes.to_vec()))),
+                            _ => unreachable!("Cannot negotiate an unknown version"),
+                        },
+                        RPC_TIME_SYNC => match self.protocol.version.as_str() {
+                            "1" => Ok(Some(P2PResponse::TimeSync(bincode::deserialize(&raw_bytes[..])?))),
+                            _ => unreachable!("Cannot negotiate an unknown version"),
+                        },
                         _ => unreachable!("Cannot negotiate an unknown protocol"),
                     }
                 }
@@ -210,9 +289,47 @@ impl OutboundCodec for BINOutboundCodec {
 
     fn decode_error(&mut self, src: &mut BytesMut) -> Result<Option<Self::ErrorType>, P2PError> {
         match self.inner.decode(src).map_err(P2PError::from) {
-            Ok(Some(packet)) => Ok(Some(bincode::deserialize(&packet[..]).unwrap())),
+            Ok(Some(packet)) => Ok(Some(bincode::deserialize(&packet[..])?)),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::types::Hash;
+
+    fn decode_request(message_name: &str, request: P2PRequest) -> Result<Option<P2PRequest>, P2PError> {
+        let protocol = ProtocolId::new(message_name, "1", "bin");
+        let mut buf = BytesMut::new();
+        BINOutboundCodec::new(protocol.clone(), 4_194_304).encode(request, &mut buf).unwrap();
+        BINInboundCodec::new(protocol, 4_194_304).decode(&mut buf)
+    }
+
+    #[test]
+    fn request_limits() {
+        let hashes = |n| vec![Hash::default(); n];
+        let request = P2PRequest::PooledTransactionHashes(PooledTransactionHashesRequest { tx_hashes: hashes(MAX_POOLED_TX_HASHES) });
+        assert!(decode_request(RPC_POOLED_TX_HASHES, request).unwrap().is_some());
+        let request = P2PRequest::PooledTransactionHashes(PooledTransactionHashesRequest { tx_hashes: hashes(MAX_POOLED_TX_HASHES + 1) });
+        assert!(decode_request(RPC_POOLED_TX_HASHES, request).is_err());
+        let request = P2PRequest::PooledTransactions(PooledTransactionsRequest { tx_hashes: hashes(MAX_POOLED_TXS_PER_REQUEST + 1) });
+        assert!(decode_request(RPC_POOLED_TXS, request).is_err());
+        let request = P2PRequest::StateNodes(StateNodesRequest { node_hashes: hashes(MAX_STATE_NODES_PER_REQUEST + 1) });
+        assert!(decode_request(RPC_STATE_NODES, request).is_err());
+    }
+
+    #[test]
+    fn malformed_request() {
+        // Truncated, too short for any of the requests
+        let mut packet = BytesMut::new();
+        UviBytes::default().encode(Bytes::from(vec![5u8, 0, 0]), &mut packet).unwrap();
+        for message_name in vec![RPC_STATE_NODES, RPC_POOLED_TX_HASHES, RPC_POOLED_TXS, RPC_TIME_SYNC, RPC_GOODBYE] {
+            let mut buf = packet.clone();
+            let mut codec = BINInboundCodec::new(ProtocolId::new(message_name, "1", "bin"), 4_194_304);
+            assert!(codec.decode(&mut buf).is_err(), "{}", message_name);
+        }
+    }
+}
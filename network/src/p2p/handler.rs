@@ -4,6 +4,7 @@
 use super::methods::{ErrorMessage, P2PErrorResponse, RequestId, ResponseTermination};
 use super::protocol::{P2PError, P2PProtocol, P2PRequest};
 use super::P2PEvent;
+use crate::metrics;
 use crate::p2p::protocol::{InboundFramed, OutboundFramed};
 use core::marker::PhantomData;
 use fnv::FnvHashMap;
@@ -25,6 +26,9 @@ use tokio::timer::{delay_queue, DelayQueue};
 /// The time (in seconds) before a substream that is awaiting a response from the user times out.
 pub const RESPONSE_TIMEOUT: u64 = 10;
 
+/// The default number of inbound requests a single peer may have in flight at once.
+pub const MAX_INBOUND_REQUESTS_PER_PEER: usize = 8;
+
 /// The number of times to retry an outbound upgrade in the case of IO errors.
 const IO_ERROR_RETRIES: u8 = 3;
 
@@ -79,6 +83,10 @@ where
     /// Maximum number of concurrent outbound substreams being opened. Value is never modified.
     max_dial_negotiated: u32,
 
+    /// Maximum number of inbound substreams the peer may have open. Requests beyond this are
+    /// answered with a `RateLimited` error and never reach the application.
+    max_inbound_requests: usize,
+
     /// Value to return from `connection_keep_alive`.
     keep_alive: KeepAlive,
 
@@ -192,6 +200,7 @@ where
     pub fn new(
         listen_protocol: SubstreamProtocol<P2PProtocol>,
         inactive_timeout: Duration,
+        max_inbound_requests: usize,
         log: &slog::Logger,
     ) -> Self {
         P2PHandler {
@@ -207,6 +216,7 @@ where
             outbound_substreams_delay: DelayQueue::new(),
             current_inbound_substream_id: 1,
             max_dial_negotiated: 8,
+            max_inbound_requests,
             keep_alive: KeepAlive::Yes,
             inactive_timeout,
             outbound_io_error_retries: 0,
@@ -276,6 +286,24 @@ where
             return;
         }
 
+        // Refuse the request if the peer already has too many in flight. The substream is kept
+        // only until the error has been written, the application never sees the request.
+        if self.inbound_substreams.len() >= self.max_inbound_requests {
+            debug!(self.log, "Peer exceeded inbound request limit"; "request" => format!("{}", req), "limit" => self.max_inbound_requests);
+            metrics::inc_counter(&metrics::P2P_INBOUND_REQUESTS_RATE_LIMITED);
+            let error = P2PErrorResponse::RateLimited(ErrorMessage {
+                error_message: b"Too many concurrent requests".to_vec(),
+            });
+            let rejected_stream = InboundSubstreamState::ResponsePendingSend {
+                substream: substream.send(error),
+                closing: true,
+            };
+            self.inbound_substreams
+                .insert(self.current_inbound_substream_id, (rejected_stream, None));
+            self.current_inbound_substream_id += 1;
+            return;
+        }
+
         // New inbound request. Store the stream and tag the output.
         let delay_key = self.inbound_substreams_delay.insert(
             self.current_inbound_substream_id,
@@ -3,12 +3,23 @@
 use serde::{Serialize, Deserialize};
 use map_core::types::Hash;
 
+use super::protocol::{
+    RPC_BLOCKS_BY_RANGE, RPC_BLOCKS_BY_ROOT, RPC_POOLED_TXS, RPC_STATE_NODES, RPC_STATUS, RPC_TIME_SYNC,
+};
+
 /* Request/Response data structures for P2P methods */
 
 /* Requests */
 
 pub type RequestId = usize;
 
+/// The maximum number of trie nodes requested or served for a single `StateNodes` request.
+pub const MAX_STATE_NODES_PER_REQUEST: usize = 384;
+/// The maximum number of pooled transaction hashes announced to a new peer.
+pub const MAX_POOLED_TX_HASHES: usize = 4096;
+/// The maximum number of transactions requested or served for a single `PooledTransactions` request.
+pub const MAX_POOLED_TXS_PER_REQUEST: usize = 256;
+
 /// The STATUS request/response handshake message.
 #[derive(Serialize, Deserialize,Clone, Debug, PartialEq)]
 pub struct StatusMessage {
@@ -26,6 +37,12 @@ pub struct StatusMessage {
 
     /// The slot associated with the latest block root.
     pub network_id: u16,
+
+    /// Hash of the consensus parameters of the chain spec.
+    pub spec_hash: Hash,
+
+    /// Name and version of the node software, e.g. `map/0.1.0`.
+    pub agent: String,
 }
 
 /// The reason given for a `Goodbye` message.
@@ -44,6 +61,9 @@ pub enum GoodbyeReason {
     /// Error/fault in the P2P.
     Fault = 3,
 
+    /// The peer follows a chain of another genesis.
+    GenesisMismatch = 4,
+
     /// Unknown reason.
     Unknown = 0,
 }
@@ -54,6 +74,7 @@ impl From<u64> for GoodbyeReason {
             1 => GoodbyeReason::ClientShutdown,
             2 => GoodbyeReason::IrrelevantNetwork,
             3 => GoodbyeReason::Fault,
+            4 => GoodbyeReason::GenesisMismatch,
             _ => GoodbyeReason::Unknown,
         }
     }
@@ -92,6 +113,45 @@ pub struct BlocksByRootRequest {
     pub block_roots: Vec<Hash>,
 }
 
+/// Request a number of state trie nodes from a peer.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StateNodesRequest {
+    /// The hashes of the trie nodes being requested.
+    pub node_hashes: Vec<Hash>,
+}
+
+/// Announce the transactions in our pool to a peer after the handshake.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PooledTransactionHashesRequest {
+    /// Hashes of pooled transactions, pending ones first.
+    pub tx_hashes: Vec<Hash>,
+}
+
+/// Request pooled transactions missing from our pool.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PooledTransactionsRequest {
+    /// The hashes of the transactions being requested.
+    pub tx_hashes: Vec<Hash>,
+}
+
+/// Request the time of a peer's clock to estimate the offset to ours.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimeSyncRequest {
+    /// Unix time in milliseconds the request was sent at.
+    pub sent_ms: u64,
+}
+
+/// The TIME_SYNC response, times are unix milliseconds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimeSyncResponse {
+    /// The `sent_ms` of the request, on the requester's clock.
+    pub request_sent_ms: u64,
+    /// Time the request was received at, on the responder's clock.
+    pub received_ms: u64,
+    /// Time the response was sent at, on the responder's clock.
+    pub sent_ms: u64,
+}
+
 /* P2P Handling and Grouping */
 // Collection of enums and structs used by the Codecs to encode/decode P2P messages
 
@@ -106,6 +166,15 @@ pub enum P2PResponse {
 
     /// A response to a get BLOCKS_BY_ROOT request.
     BlocksByRoot(Vec<u8>),
+
+    /// A response to a get STATE_NODES request, one raw trie node per chunk.
+    StateNodes(Vec<u8>),
+
+    /// A response to a get POOLED_TRANSACTIONS request, one encoded transaction per chunk.
+    PooledTransactions(Vec<u8>),
+
+    /// A response to a TIME_SYNC request.
+    TimeSync(TimeSyncResponse),
 }
 
 /// Indicates which response is being terminated by a stream termination response.
@@ -116,6 +185,12 @@ pub enum ResponseTermination {
 
     /// Blocks by root stream termination.
     BlocksByRoot,
+
+    /// State nodes stream termination.
+    StateNodes,
+
+    /// Pooled transactions stream termination.
+    PooledTransactions,
 }
 
 #[derive(Debug)]
@@ -129,6 +204,9 @@ pub enum P2PErrorResponse {
     /// The response indicates a server error.
     ServerError(ErrorMessage),
 
+    /// The peer has too many requests in flight and refused this one.
+    RateLimited(ErrorMessage),
+
     /// There was an unknown response.
     Unknown(ErrorMessage),
 
@@ -136,6 +214,20 @@ pub enum P2PErrorResponse {
     StreamTermination(ResponseTermination),
 }
 
+impl P2PResponse {
+    /// Name of the protocol of the response, e.g. `status`.
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            P2PResponse::Status(_) => RPC_STATUS,
+            P2PResponse::BlocksByRange(_) => RPC_BLOCKS_BY_RANGE,
+            P2PResponse::BlocksByRoot(_) => RPC_BLOCKS_BY_ROOT,
+            P2PResponse::StateNodes(_) => RPC_STATE_NODES,
+            P2PResponse::PooledTransactions(_) => RPC_POOLED_TXS,
+            P2PResponse::TimeSync(_) => RPC_TIME_SYNC,
+        }
+    }
+}
+
 impl P2PErrorResponse {
     /// Used to encode the response in the codec.
     pub fn as_u8(&self) -> Option<u8> {
@@ -143,6 +235,7 @@ impl P2PErrorResponse {
             P2PErrorResponse::Success(_) => Some(0),
             P2PErrorResponse::InvalidRequest(_) => Some(1),
             P2PErrorResponse::ServerError(_) => Some(2),
+            P2PErrorResponse::RateLimited(_) => Some(139),
             P2PErrorResponse::Unknown(_) => Some(255),
             P2PErrorResponse::StreamTermination(_) => None,
         }
@@ -161,6 +254,7 @@ impl P2PErrorResponse {
         match response_code {
             1 => P2PErrorResponse::InvalidRequest(err),
             2 => P2PErrorResponse::ServerError(err),
+            139 => P2PErrorResponse::RateLimited(err),
             _ => P2PErrorResponse::Unknown(err),
         }
     }
@@ -172,9 +266,13 @@ impl P2PErrorResponse {
                 P2PResponse::Status(_) => false,
                 P2PResponse::BlocksByRange(_) => true,
                 P2PResponse::BlocksByRoot(_) => true,
+                P2PResponse::StateNodes(_) => true,
+                P2PResponse::PooledTransactions(_) => true,
+                P2PResponse::TimeSync(_) => false,
             },
             P2PErrorResponse::InvalidRequest(_) => true,
             P2PErrorResponse::ServerError(_) => true,
+            P2PErrorResponse::RateLimited(_) => true,
             P2PErrorResponse::Unknown(_) => true,
             // Stream terminations are part of responses that have chunks
             P2PErrorResponse::StreamTermination(_) => true,
@@ -206,7 +304,7 @@ impl ErrorMessage {
 
 impl std::fmt::Display for StatusMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Status Message: Genesis hash: {:?}, Finalized Root: {}, Finalized number: {}, Head Root: {}, Network ID: {}", self.genesis_hash, self.finalized_root, self.finalized_number, self.head_root, self.network_id)
+        write!(f, "Status Message: Genesis hash: {:?}, Finalized Root: {}, Finalized number: {}, Head Root: {}, Network ID: {}, Spec hash: {}, Agent: {}", self.genesis_hash, self.finalized_root, self.finalized_number, self.head_root, self.network_id, self.spec_hash, self.agent)
     }
 }
 
@@ -216,6 +314,9 @@ impl std::fmt::Display for P2PResponse {
             P2PResponse::Status(status) => write!(f, "{}", status),
             P2PResponse::BlocksByRange(_) => write!(f, "<BlocksByRange>"),
             P2PResponse::BlocksByRoot(_) => write!(f, "<BlocksByRoot>"),
+            P2PResponse::StateNodes(_) => write!(f, "<StateNodes>"),
+            P2PResponse::PooledTransactions(_) => write!(f, "<PooledTransactions>"),
+            P2PResponse::TimeSync(res) => write!(f, "Time sync: received {} sent {}", res.received_ms, res.sent_ms),
         }
     }
 }
@@ -226,6 +327,7 @@ impl std::fmt::Display for P2PErrorResponse {
             P2PErrorResponse::Success(res) => write!(f, "{}", res),
             P2PErrorResponse::InvalidRequest(err) => write!(f, "Invalid Request: {:?}", err),
             P2PErrorResponse::ServerError(err) => write!(f, "Server Error: {:?}", err),
+            P2PErrorResponse::RateLimited(err) => write!(f, "Rate Limited: {:?}", err),
             P2PErrorResponse::Unknown(err) => write!(f, "Unknown Error: {:?}", err),
             P2PErrorResponse::StreamTermination(_) => write!(f, "Stream Termination"),
         }
@@ -238,6 +340,7 @@ impl std::fmt::Display for GoodbyeReason {
             GoodbyeReason::ClientShutdown => write!(f, "Client Shutdown"),
             GoodbyeReason::IrrelevantNetwork => write!(f, "Irrelevant Network"),
             GoodbyeReason::Fault => write!(f, "Fault"),
+            GoodbyeReason::GenesisMismatch => write!(f, "Genesis Mismatch"),
             GoodbyeReason::Unknown => write!(f, "Unknown Reason"),
         }
     }
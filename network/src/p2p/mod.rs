@@ -18,6 +18,7 @@ use slog::o;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use handler::P2PHandler;
+pub use handler::MAX_INBOUND_REQUESTS_PER_PEER;
 pub use methods::{
     ErrorMessage, RequestId, ResponseTermination, P2PErrorResponse, P2PResponse, StatusMessage,
 };
@@ -69,16 +70,19 @@ pub struct P2P<TSubstream> {
     events: Vec<NetworkBehaviourAction<P2PEvent, P2PMessage>>,
     /// Pins the generic substream.
     marker: PhantomData<TSubstream>,
+    /// Maximum number of inbound requests a peer may have in flight.
+    max_inbound_requests: usize,
     /// Slog logger for P2P behaviour.
     log: slog::Logger,
 }
 
 impl<TSubstream> P2P<TSubstream> {
-    pub fn new(log: slog::Logger) -> Self {
+    pub fn new(max_inbound_requests: usize, log: slog::Logger) -> Self {
         let log = log.new(o!("service" => "libp2p_p2p"));
         P2P {
             events: Vec::new(),
             marker: PhantomData,
+            max_inbound_requests,
             log,
         }
     }
@@ -105,6 +109,7 @@ impl<TSubstream> NetworkBehaviour for P2P<TSubstream>
         P2PHandler::new(
             SubstreamProtocol::new(P2PProtocol),
             Duration::from_secs(30),
+            self.max_inbound_requests,
             &self.log,
         )
     }
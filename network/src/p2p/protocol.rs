@@ -47,6 +47,14 @@ pub const RPC_GOODBYE: &str = "goodbye";
 pub const RPC_BLOCKS_BY_RANGE: &str = "map_blocks_by_range";
 /// The `BlocksByRoot` protocol name.
 pub const RPC_BLOCKS_BY_ROOT: &str = "map_blocks_by_root";
+/// The `StateNodes` protocol name.
+pub const RPC_STATE_NODES: &str = "map_state_nodes";
+/// The `PooledTransactionHashes` protocol name.
+pub const RPC_POOLED_TX_HASHES: &str = "map_pooled_tx_hashes";
+/// The `PooledTransactions` protocol name.
+pub const RPC_POOLED_TXS: &str = "map_pooled_txs";
+/// The `TimeSync` protocol name.
+pub const RPC_TIME_SYNC: &str = "map_time_sync";
 
 #[derive(Debug, Clone)]
 pub struct P2PProtocol;
@@ -57,10 +65,14 @@ impl UpgradeInfo for P2PProtocol {
 
     fn protocol_info(&self) -> Self::InfoIter {
         vec![
-            ProtocolId::new(RPC_STATUS, "1", "bin"),
+            ProtocolId::new(RPC_STATUS, "2", "bin"),
             ProtocolId::new(RPC_GOODBYE, "1", "bin"),
             ProtocolId::new(RPC_BLOCKS_BY_RANGE, "1", "bin"),
             ProtocolId::new(RPC_BLOCKS_BY_ROOT, "1", "bin"),
+            ProtocolId::new(RPC_STATE_NODES, "1", "bin"),
+            ProtocolId::new(RPC_POOLED_TX_HASHES, "1", "bin"),
+            ProtocolId::new(RPC_POOLED_TXS, "1", "bin"),
+            ProtocolId::new(RPC_TIME_SYNC, "1", "bin"),
         ]
     }
 }
@@ -171,6 +183,10 @@ pub enum P2PRequest {
     Goodbye(GoodbyeReason),
     BlocksByRange(BlocksByRangeRequest),
     BlocksByRoot(BlocksByRootRequest),
+    StateNodes(StateNodesRequest),
+    PooledTransactionHashes(PooledTransactionHashesRequest),
+    PooledTransactions(PooledTransactionsRequest),
+    TimeSync(TimeSyncRequest),
 }
 
 impl UpgradeInfo for P2PRequest {
@@ -188,23 +204,45 @@ impl P2PRequest {
     pub fn supported_protocols(&self) -> Vec<ProtocolId> {
         match self {
             // add more protocols when versions/encodings are supported
-            P2PRequest::Status(_) => vec![ProtocolId::new(RPC_STATUS, "1", "bin")],
+            P2PRequest::Status(_) => vec![ProtocolId::new(RPC_STATUS, "2", "bin")],
             P2PRequest::Goodbye(_) => vec![ProtocolId::new(RPC_GOODBYE, "1", "bin")],
             P2PRequest::BlocksByRange(_) => vec![ProtocolId::new(RPC_BLOCKS_BY_RANGE, "1", "bin")],
             P2PRequest::BlocksByRoot(_) => vec![ProtocolId::new(RPC_BLOCKS_BY_ROOT, "1", "bin")],
+            P2PRequest::StateNodes(_) => vec![ProtocolId::new(RPC_STATE_NODES, "1", "bin")],
+            P2PRequest::PooledTransactionHashes(_) => vec![ProtocolId::new(RPC_POOLED_TX_HASHES, "1", "bin")],
+            P2PRequest::PooledTransactions(_) => vec![ProtocolId::new(RPC_POOLED_TXS, "1", "bin")],
+            P2PRequest::TimeSync(_) => vec![ProtocolId::new(RPC_TIME_SYNC, "1", "bin")],
+        }
+    }
+
+    /// Name of the protocol of the request, e.g. `status`.
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            P2PRequest::Status(_) => RPC_STATUS,
+            P2PRequest::Goodbye(_) => RPC_GOODBYE,
+            P2PRequest::BlocksByRange(_) => RPC_BLOCKS_BY_RANGE,
+            P2PRequest::BlocksByRoot(_) => RPC_BLOCKS_BY_ROOT,
+            P2PRequest::StateNodes(_) => RPC_STATE_NODES,
+            P2PRequest::PooledTransactionHashes(_) => RPC_POOLED_TX_HASHES,
+            P2PRequest::PooledTransactions(_) => RPC_POOLED_TXS,
+            P2PRequest::TimeSync(_) => RPC_TIME_SYNC,
         }
     }
 
     /* These functions are used in the handler for stream management */
 
     /// This specifies whether a stream should remain open and await a response, given a request.
-    /// A GOODBYE request has no response.
+    /// GOODBYE and POOLED_TX_HASHES requests have no response.
     pub fn expect_response(&self) -> bool {
         match self {
             P2PRequest::Status(_) => true,
             P2PRequest::Goodbye(_) => false,
             P2PRequest::BlocksByRange(_) => true,
             P2PRequest::BlocksByRoot(_) => true,
+            P2PRequest::StateNodes(_) => true,
+            P2PRequest::PooledTransactionHashes(_) => false,
+            P2PRequest::PooledTransactions(_) => true,
+            P2PRequest::TimeSync(_) => true,
         }
     }
 
@@ -216,6 +254,10 @@ impl P2PRequest {
             P2PRequest::Goodbye(_) => false,
             P2PRequest::BlocksByRange(_) => true,
             P2PRequest::BlocksByRoot(_) => true,
+            P2PRequest::StateNodes(_) => true,
+            P2PRequest::PooledTransactionHashes(_) => false,
+            P2PRequest::PooledTransactions(_) => true,
+            P2PRequest::TimeSync(_) => false,
         }
     }
 
@@ -227,8 +269,12 @@ impl P2PRequest {
             // variants that have `multiple_responses()` can have values.
             P2PRequest::BlocksByRange(_) => ResponseTermination::BlocksByRange,
             P2PRequest::BlocksByRoot(_) => ResponseTermination::BlocksByRoot,
+            P2PRequest::StateNodes(_) => ResponseTermination::StateNodes,
+            P2PRequest::PooledTransactions(_) => ResponseTermination::PooledTransactions,
             P2PRequest::Status(_) => unreachable!(),
             P2PRequest::Goodbye(_) => unreachable!(),
+            P2PRequest::PooledTransactionHashes(_) => unreachable!(),
+            P2PRequest::TimeSync(_) => unreachable!(),
         }
     }
 }
@@ -302,6 +348,12 @@ impl From<()> for P2PError {
     }
 }
 
+impl From<bincode::Error> for P2PError {
+    fn from(err: bincode::Error) -> Self {
+        P2PError::Custom(format!("Decoding error: {}", err))
+    }
+}
+
 impl From<io::Error> for P2PError {
     fn from(err: io::Error) -> Self {
         P2PError::IoError(err)
@@ -342,6 +394,10 @@ impl std::fmt::Display for P2PRequest {
             P2PRequest::Goodbye(reason) => write!(f, "Goodbye: {}", reason),
             P2PRequest::BlocksByRange(req) => write!(f, "Blocks by range: {}", req),
             P2PRequest::BlocksByRoot(req) => write!(f, "Blocks by root: {:?}", req),
+            P2PRequest::StateNodes(req) => write!(f, "State nodes: {} hashes", req.node_hashes.len()),
+            P2PRequest::PooledTransactionHashes(req) => write!(f, "Pooled transaction hashes: {}", req.tx_hashes.len()),
+            P2PRequest::PooledTransactions(req) => write!(f, "Pooled transactions: {} hashes", req.tx_hashes.len()),
+            P2PRequest::TimeSync(req) => write!(f, "Time sync: sent {}", req.sent_ms),
         }
     }
 }
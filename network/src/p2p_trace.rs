@@ -0,0 +1,258 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opt-in trace of the p2p messages sent and received, one line per request, response chunk and
+//! gossip message, written to rotating files of the network dir to reconstruct protocol bugs.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::behaviour::PubsubMessage;
+use crate::p2p::methods::P2PErrorResponse;
+use crate::p2p::P2PEvent;
+use crate::PeerId;
+
+/// File of the network dir the trace is appended to.
+pub const TRACE_FILE: &str = "p2p-trace.log";
+/// Size of the trace file before it is rotated.
+pub const MAX_TRACE_FILE_BYTES: u64 = 64 * 1024 * 1024;
+/// Rotated files kept besides the current one, `p2p-trace.log.1` being the newest.
+pub const TRACE_FILES_KEPT: usize = 3;
+/// Leading bytes of the payload hash written, enough to match a message across nodes.
+const HASH_BYTES: usize = 8;
+
+pub type SharedP2PTrace = Arc<RwLock<P2PTrace>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+/// Whether tracing is on, served by `debug_p2pTrace`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TraceStatus {
+    pub enabled: bool,
+    pub path: String,
+    /// Messages traced since the node started
+    pub messages: u64,
+}
+
+pub struct P2PTrace {
+    path: PathBuf,
+    max_bytes: u64,
+    /// Open while tracing
+    file: Option<File>,
+    /// Size of the current file
+    written: u64,
+    messages: u64,
+}
+
+impl P2PTrace {
+    /// Trace to `TRACE_FILE` in `dir`, off until enabled
+    pub fn new(dir: PathBuf) -> Self {
+        P2PTrace {
+            path: dir.join(TRACE_FILE),
+            max_bytes: MAX_TRACE_FILE_BYTES,
+            file: None,
+            written: 0,
+            messages: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Start appending to the trace file, after the lines of earlier runs
+    pub fn enable(&mut self) -> io::Result<()> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    pub fn disable(&mut self) {
+        self.file = None;
+    }
+
+    pub fn status(&self) -> TraceStatus {
+        TraceStatus {
+            enabled: self.is_enabled(),
+            path: self.path.display().to_string(),
+            messages: self.messages,
+        }
+    }
+
+    /// Trace a request, response chunk or error exchanged with `peer`
+    pub fn on_p2p(&mut self, direction: Direction, peer: &PeerId, event: &P2PEvent) {
+        if self.file.is_none() {
+            return;
+        }
+        let (kind, id, payload) = match event {
+            P2PEvent::Request(id, request) => (
+                format!("request/{}", request.protocol_name()),
+                *id,
+                bincode::serialize(request).unwrap_or_default(),
+            ),
+            P2PEvent::Response(id, response) => {
+                let payload = match response {
+                    P2PErrorResponse::Success(chunk) => bincode::serialize(chunk).unwrap_or_default(),
+                    _ => Vec::new(),
+                };
+                (format!("response/{}", response_kind(response)), *id, payload)
+            }
+            P2PEvent::Error(id, _) => ("error".to_string(), *id, Vec::new()),
+        };
+        self.write(direction, &peer.to_string(), &kind, Some(id), &payload);
+    }
+
+    /// Trace a gossip message, `peer` is None for the messages we publish
+    pub fn on_gossip(&mut self, direction: Direction, peer: Option<&PeerId>, message: &PubsubMessage) {
+        if self.file.is_none() {
+            return;
+        }
+        let peer = peer.map_or_else(|| "-".to_string(), |peer| peer.to_string());
+        self.write(direction, &peer, &format!("gossip/{}", message.topic_name()), None, message.data());
+    }
+
+    fn write(&mut self, direction: Direction, peer: &str, kind: &str, id: Option<usize>, payload: &[u8]) {
+        let time_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let line = format_line(time_ms, direction, peer, kind, id, payload);
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            if self.rotate().is_err() {
+                // Stop rather than fail on every message, the trace can be enabled again
+                self.file = None;
+            }
+        }
+        let written = match self.file.as_mut() {
+            Some(file) => file.write_all(line.as_bytes()),
+            None => return,
+        };
+        match written {
+            Ok(()) => {
+                self.written += line.len() as u64;
+                self.messages += 1;
+            }
+            Err(_) => self.file = None,
+        }
+    }
+
+    /// Move each rotated file one number up, dropping the oldest, and start an empty file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        for n in (1..TRACE_FILES_KEPT).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.written = 0;
+        self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), n))
+    }
+}
+
+/// `<unix ms> <in|out> <peer> <type> id=<request id> size=<bytes> hash=<payload sha256 prefix>`
+fn format_line(time_ms: u128, direction: Direction, peer: &str, kind: &str, id: Option<usize>, payload: &[u8]) -> String {
+    let hash = Sha256::digest(payload);
+    format!(
+        "{} {} {} {} id={} size={} hash={}\n",
+        time_ms,
+        direction.name(),
+        peer,
+        kind,
+        id.map_or_else(|| "-".to_string(), |id| id.to_string()),
+        payload.len(),
+        hex::encode(&hash[..HASH_BYTES]),
+    )
+}
+
+fn response_kind(response: &P2PErrorResponse) -> &'static str {
+    match response {
+        P2PErrorResponse::Success(chunk) => chunk.protocol_name(),
+        P2PErrorResponse::StreamTermination(_) => "end",
+        P2PErrorResponse::InvalidRequest(_) => "invalid_request",
+        P2PErrorResponse::ServerError(_) => "server_error",
+        P2PErrorResponse::RateLimited(_) => "rate_limited",
+        P2PErrorResponse::Unknown(_) => "unknown_error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_and_rotate() {
+        let dir = std::env::temp_dir().join("map_network_p2p_trace_test");
+        let _ = fs::remove_dir_all(&dir);
+        let mut trace = P2PTrace::new(dir.clone());
+        let peer = PeerId::random();
+        let message = PubsubMessage::Block(vec![1, 2, 3]);
+        // Nothing is written while off
+        trace.on_gossip(Direction::In, Some(&peer), &message);
+        assert_eq!(trace.status().messages, 0);
+
+        trace.enable().unwrap();
+        trace.on_gossip(Direction::In, Some(&peer), &message);
+        trace.on_gossip(Direction::Out, None, &message);
+        let lines = fs::read_to_string(dir.join(TRACE_FILE)).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[0].split(' ').collect();
+        assert_eq!(&fields[1..6], &["in", &peer.to_string()[..], "gossip/block", "id=-", "size=3"]);
+        assert_eq!(fields[6].len(), "hash=".len() + 2 * HASH_BYTES);
+        assert!(lines[1].contains(" out - "));
+
+        trace.max_bytes = 1;
+        for _ in 0..TRACE_FILES_KEPT + 2 {
+            trace.on_gossip(Direction::In, Some(&peer), &message);
+        }
+        assert!(trace.rotated(TRACE_FILES_KEPT).exists());
+        assert!(!trace.rotated(TRACE_FILES_KEPT + 1).exists());
+        assert_eq!(fs::read_to_string(dir.join(TRACE_FILE)).unwrap().lines().count(), 1);
+
+        trace.disable();
+        assert!(!trace.status().enabled);
+        assert_eq!(trace.status().messages, TRACE_FILES_KEPT as u64 + 4);
+    }
+}
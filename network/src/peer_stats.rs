@@ -0,0 +1,310 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Per peer counts, sizes and latency of the p2p messages of each protocol, telling the peers
+//! serving useful data from dead weight. Totals over all peers go to the metrics. Also keeps the
+//! software each peer announced, to survey the versions run on the network.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use serde::Serialize;
+
+use crate::metrics;
+use crate::p2p::{P2PErrorResponse, P2PEvent, P2PRequest, P2PResponse, RequestId};
+
+/// Protocol name of responses to requests no longer tracked.
+const UNKNOWN_PROTOCOL: &str = "unknown";
+
+pub type SharedPeerStats = Arc<RwLock<PeerStats>>;
+
+/// Messages exchanged with one peer over one protocol.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MessageStats {
+    /// Requests sent to the peer.
+    pub requests_sent: u64,
+    /// Requests received from the peer.
+    pub requests_received: u64,
+    /// Response chunks sent to the peer.
+    pub responses_sent: u64,
+    /// Response chunks received from the peer.
+    pub responses_received: u64,
+    /// Error responses and failed requests, in both directions.
+    pub errors: u64,
+    /// Encoded size of the requests and response chunks sent.
+    pub bytes_sent: u64,
+    /// Encoded size of the requests and response chunks received.
+    pub bytes_received: u64,
+    /// Mean time from sending a request to its first response chunk, in milliseconds.
+    pub avg_latency_ms: Option<u64>,
+    #[serde(skip)]
+    latency_total: Duration,
+    #[serde(skip)]
+    latency_samples: u32,
+}
+
+impl MessageStats {
+    fn add_latency(&mut self, latency: Duration) {
+        self.latency_total += latency;
+        self.latency_samples += 1;
+        self.avg_latency_ms = Some((self.latency_total / self.latency_samples).as_millis() as u64);
+    }
+}
+
+/// Statistics of a connected peer, by protocol name.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PeerMessageStats {
+    pub peer: String,
+    pub protocols: BTreeMap<&'static str, MessageStats>,
+}
+
+/// Software announced by a connected peer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerAgentInfo {
+    pub peer: String,
+    /// Agent of the status handshake, e.g. `map/0.1.0`.
+    pub agent: Option<String>,
+    /// Agent of the identify protocol, the same as `agent` for map nodes.
+    pub identify_agent: Option<String>,
+    /// Version of the identify protocol, e.g. `map/p2p`.
+    pub protocol_version: Option<String>,
+}
+
+/// Message statistics and announced software of the connected peers.
+#[derive(Default)]
+pub struct PeerStats {
+    peers: HashMap<PeerId, BTreeMap<&'static str, MessageStats>>,
+    agents: HashMap<PeerId, PeerAgentInfo>,
+    /// Protocol of the requests sent and awaiting the end of their response, with the send time
+    /// until the first chunk arrives.
+    outbound: HashMap<(PeerId, RequestId), (&'static str, Option<Instant>)>,
+    /// Protocol of the requests received and not answered to the end.
+    inbound: HashMap<(PeerId, RequestId), &'static str>,
+}
+
+impl PeerStats {
+    /// Record a request or response sent to `peer`.
+    pub fn on_sent(&mut self, peer: &PeerId, event: &P2PEvent) {
+        match event {
+            P2PEvent::Request(id, request) => {
+                let protocol = request.protocol_name();
+                let size = bincode::serialized_size(request).unwrap_or(0);
+                let stats = self.stats(peer, protocol);
+                stats.requests_sent += 1;
+                stats.bytes_sent += size;
+                record_message(protocol, "sent", size);
+                if request.expect_response() {
+                    self.outbound.insert((peer.clone(), *id), (protocol, Some(Instant::now())));
+                }
+            }
+            P2PEvent::Response(id, response) => {
+                let key = (peer.clone(), *id);
+                let protocol = self.inbound.get(&key).cloned().unwrap_or(UNKNOWN_PROTOCOL);
+                match response {
+                    P2PErrorResponse::Success(chunk) => {
+                        let size = bincode::serialized_size(chunk).unwrap_or(0);
+                        let stats = self.stats(peer, protocol);
+                        stats.responses_sent += 1;
+                        stats.bytes_sent += size;
+                        record_message(protocol, "sent", size);
+                    }
+                    P2PErrorResponse::StreamTermination(_) => {}
+                    _ => self.record_error(peer, protocol),
+                }
+                if !response.multiple_responses() || response.is_error() {
+                    self.inbound.remove(&key);
+                }
+            }
+            P2PEvent::Error(id, _) => {
+                let protocol = self.inbound.remove(&(peer.clone(), *id)).unwrap_or(UNKNOWN_PROTOCOL);
+                self.record_error(peer, protocol);
+            }
+        }
+    }
+
+    /// Record a request or response received from `peer`.
+    pub fn on_received(&mut self, peer: &PeerId, event: &P2PEvent) {
+        match event {
+            P2PEvent::Request(id, request) => {
+                if let P2PRequest::Status(status) = request {
+                    self.agent(peer).agent = Some(status.agent.clone());
+                }
+                let protocol = request.protocol_name();
+                let size = bincode::serialized_size(request).unwrap_or(0);
+                let stats = self.stats(peer, protocol);
+                stats.requests_received += 1;
+                stats.bytes_received += size;
+                record_message(protocol, "received", size);
+                if request.expect_response() {
+                    self.inbound.insert((peer.clone(), *id), protocol);
+                }
+            }
+            P2PEvent::Response(id, response) => {
+                let key = (peer.clone(), *id);
+                let (protocol, sent_at) = match self.outbound.get_mut(&key) {
+                    Some((protocol, sent_at)) => (*protocol, sent_at.take()),
+                    None => (UNKNOWN_PROTOCOL, None),
+                };
+                if let Some(sent_at) = sent_at {
+                    let latency = sent_at.elapsed();
+                    self.stats(peer, protocol).add_latency(latency);
+                    if let Some(histogram) = metrics::get_histogram(&metrics::P2P_REQUEST_LATENCY, &[protocol]) {
+                        histogram.observe(latency.as_secs_f64());
+                    }
+                }
+                match response {
+                    P2PErrorResponse::Success(chunk) => {
+                        if let P2PResponse::Status(status) = chunk {
+                            self.agent(peer).agent = Some(status.agent.clone());
+                        }
+                        let size = bincode::serialized_size(chunk).unwrap_or(0);
+                        let stats = self.stats(peer, protocol);
+                        stats.responses_received += 1;
+                        stats.bytes_received += size;
+                        record_message(protocol, "received", size);
+                    }
+                    P2PErrorResponse::StreamTermination(_) => {}
+                    _ => self.record_error(peer, protocol),
+                }
+                if !response.multiple_responses() || response.is_error() {
+                    self.outbound.remove(&key);
+                }
+            }
+            P2PEvent::Error(id, _) => {
+                let protocol = self.outbound.remove(&(peer.clone(), *id))
+                    .map(|(protocol, _)| protocol)
+                    .unwrap_or(UNKNOWN_PROTOCOL);
+                self.record_error(peer, protocol);
+            }
+        }
+    }
+
+    /// Record the software `peer` announced in the identify protocol.
+    pub fn on_identified(&mut self, peer: &PeerId, agent_version: String, protocol_version: String) {
+        let info = self.agent(peer);
+        info.identify_agent = Some(agent_version);
+        info.protocol_version = Some(protocol_version);
+    }
+
+    /// Forget a disconnected peer.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.agents.remove(peer);
+        self.outbound.retain(|(p, _), _| p != peer);
+        self.inbound.retain(|(p, _), _| p != peer);
+    }
+
+    /// Statistics of every connected peer that exchanged messages.
+    pub fn snapshot(&self) -> Vec<PeerMessageStats> {
+        let mut peers: Vec<PeerMessageStats> = self.peers.iter()
+            .map(|(peer, protocols)| PeerMessageStats {
+                peer: peer.to_base58(),
+                protocols: protocols.clone(),
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+        peers
+    }
+
+    /// Software announced by every connected peer that sent a status or identify message.
+    pub fn agents(&self) -> Vec<PeerAgentInfo> {
+        let mut agents: Vec<PeerAgentInfo> = self.agents.values().cloned().collect();
+        agents.sort_by(|a, b| a.peer.cmp(&b.peer));
+        agents
+    }
+
+    fn agent(&mut self, peer: &PeerId) -> &mut PeerAgentInfo {
+        self.agents.entry(peer.clone()).or_insert_with(|| PeerAgentInfo {
+            peer: peer.to_base58(),
+            ..Default::default()
+        })
+    }
+
+    fn stats(&mut self, peer: &PeerId, protocol: &'static str) -> &mut MessageStats {
+        self.peers.entry(peer.clone()).or_insert_with(BTreeMap::new)
+            .entry(protocol).or_insert_with(MessageStats::default)
+    }
+
+    fn record_error(&mut self, peer: &PeerId, protocol: &'static str) {
+        self.stats(peer, protocol).errors += 1;
+        if let Some(counter) = metrics::get_int_counter(&metrics::P2P_MESSAGE_ERRORS, &[protocol]) {
+            counter.inc();
+        }
+    }
+}
+
+fn record_message(protocol: &str, direction: &str, size: u64) {
+    metrics::inc_counter_vec_by(&metrics::P2P_MESSAGES, &[protocol, direction], 1);
+    metrics::inc_counter_vec_by(&metrics::P2P_MESSAGE_BYTES, &[protocol, direction], size as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::types::Hash;
+    use crate::p2p::{ResponseTermination, StatusMessage};
+    use crate::p2p::methods::BlocksByRootRequest;
+
+    #[test]
+    fn count_by_protocol() {
+        let peer = PeerId::random();
+        let mut stats = PeerStats::default();
+        let request = P2PRequest::BlocksByRoot(BlocksByRootRequest { block_roots: vec![Hash::default()] });
+        stats.on_sent(&peer, &P2PEvent::Request(7, request));
+        stats.on_received(&peer, &P2PEvent::Response(7, P2PErrorResponse::Success(P2PResponse::BlocksByRoot(vec![0; 100]))));
+        stats.on_received(&peer, &P2PEvent::Response(7, P2PErrorResponse::StreamTermination(ResponseTermination::BlocksByRoot)));
+        // Chunks after the end of the stream are not matched to a request
+        stats.on_received(&peer, &P2PEvent::Response(7, P2PErrorResponse::Success(P2PResponse::BlocksByRoot(vec![0; 10]))));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        let blocks = &snapshot[0].protocols["map_blocks_by_root"];
+        assert_eq!((blocks.requests_sent, blocks.responses_received), (1, 1));
+        assert!(blocks.bytes_received >= 100);
+        assert!(blocks.avg_latency_ms.is_some());
+        assert_eq!(snapshot[0].protocols["unknown"].responses_received, 1);
+        assert!(stats.outbound.is_empty());
+
+        stats.remove_peer(&peer);
+        assert!(stats.snapshot().is_empty());
+    }
+
+    #[test]
+    fn record_agents() {
+        let peer = PeerId::random();
+        let mut stats = PeerStats::default();
+        stats.on_identified(&peer, "map/0.1.0".to_string(), "map/p2p".to_string());
+        let status = StatusMessage {
+            genesis_hash: Hash::default(),
+            finalized_root: Hash::default(),
+            finalized_number: 0,
+            head_root: Hash::default(),
+            network_id: 1,
+            spec_hash: Hash::default(),
+            agent: "map/0.2.0".to_string(),
+        };
+        stats.on_received(&peer, &P2PEvent::Request(1, P2PRequest::Status(status)));
+
+        let agents = stats.agents();
+        assert_eq!(agents.len(), 1);
+        assert_eq!(agents[0].agent, Some("map/0.2.0".to_string()));
+        assert_eq!(agents[0].identify_agent, Some("map/0.1.0".to_string()));
+
+        stats.remove_peer(&peer);
+        assert!(stats.agents().is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Delay from the slot start of each gossiped block to its arrival and import here, by the
+//! peer it came from first, to measure how fast blocks travel through the network.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libp2p::PeerId;
+use serde::Serialize;
+
+use map_core::block::Block;
+use map_core::types::Hash;
+
+use crate::metrics;
+
+/// Recent blocks listed by `debug_blockPropagation`.
+const RECENT_BLOCKS: usize = 64;
+
+pub type SharedPropagation = Arc<RwLock<Propagation>>;
+
+/// Arrival of a gossiped block.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BlockArrival {
+    pub height: u64,
+    pub hash: Hash,
+    /// Peer the block was first received from.
+    pub peer: String,
+    /// From the start of the block's slot to its arrival.
+    pub slot_delay_ms: u64,
+    /// From the header timestamp to the arrival.
+    pub header_delay_ms: u64,
+    /// From the start of the block's slot to its import, None until imported.
+    pub import_delay_ms: Option<u64>,
+}
+
+/// Blocks first received from a connected peer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerPropagation {
+    pub peer: String,
+    pub blocks: u64,
+    /// Mean delay from the slot start to the arrival.
+    pub avg_delay_ms: u64,
+    pub max_delay_ms: u64,
+    #[serde(skip)]
+    total_delay_ms: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PropagationReport {
+    pub peers: Vec<PeerPropagation>,
+    /// Latest blocks, newest first.
+    pub recent: Vec<BlockArrival>,
+}
+
+#[derive(Default)]
+pub struct Propagation {
+    peers: HashMap<PeerId, PeerPropagation>,
+    recent: VecDeque<BlockArrival>,
+}
+
+impl Propagation {
+    /// Record a gossiped block received from `peer` at `now_ms`, the first arrival of a block
+    /// counts. `slot_start` is the unix time its slot started.
+    pub fn on_arrival(&mut self, peer: &PeerId, block: &Block, slot_start: u64, now_ms: u64) {
+        let hash = block.hash();
+        if self.recent.iter().any(|arrival| arrival.hash == hash) {
+            return;
+        }
+        let slot_delay_ms = now_ms.saturating_sub(slot_start.saturating_mul(1000));
+        let header_delay_ms = now_ms.saturating_sub(block.header.time.saturating_mul(1000));
+        metrics::observe(&metrics::BLOCK_ARRIVAL_DELAY, slot_delay_ms as f64 / 1000.0);
+
+        let stats = self.peers.entry(peer.clone()).or_insert_with(|| PeerPropagation {
+            peer: peer.to_base58(),
+            ..Default::default()
+        });
+        stats.blocks += 1;
+        stats.total_delay_ms += slot_delay_ms;
+        stats.avg_delay_ms = stats.total_delay_ms / stats.blocks;
+        stats.max_delay_ms = stats.max_delay_ms.max(slot_delay_ms);
+
+        if self.recent.len() == RECENT_BLOCKS {
+            self.recent.pop_back();
+        }
+        self.recent.push_front(BlockArrival {
+            height: block.height(),
+            hash,
+            peer: peer.to_base58(),
+            slot_delay_ms,
+            header_delay_ms,
+            import_delay_ms: None,
+        });
+    }
+
+    /// Record the import of a gossiped block whose slot started at `slot_start`.
+    pub fn on_import(&mut self, hash: &Hash, slot_start: u64) {
+        self.on_import_at(hash, slot_start, now_ms());
+    }
+
+    fn on_import_at(&mut self, hash: &Hash, slot_start: u64, now_ms: u64) {
+        let import_delay_ms = now_ms.saturating_sub(slot_start.saturating_mul(1000));
+        metrics::observe(&metrics::BLOCK_IMPORT_DELAY, import_delay_ms as f64 / 1000.0);
+        if let Some(arrival) = self.recent.iter_mut().find(|arrival| &arrival.hash == hash) {
+            arrival.import_delay_ms = Some(import_delay_ms);
+        }
+    }
+
+    /// Forget a disconnected peer.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    pub fn report(&self) -> PropagationReport {
+        let mut peers: Vec<PeerPropagation> = self.peers.values().cloned().collect();
+        peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+        PropagationReport {
+            peers,
+            recent: self.recent.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Local unix time in milliseconds.
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::test_utils::BlockBuilder;
+
+    #[test]
+    fn delays_by_peer() {
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let block = BlockBuilder::on(&Block::default()).time(100).build();
+        let next = BlockBuilder::on(&block).time(106).build();
+        let mut propagation = Propagation::default();
+
+        propagation.on_arrival(&peer, &block, 99, 100_400);
+        // Later arrivals of the same block don't count
+        propagation.on_arrival(&other, &block, 99, 100_900);
+        propagation.on_arrival(&peer, &next, 105, 106_800);
+        propagation.on_import_at(&block.hash(), 99, 101_000);
+
+        let report = propagation.report();
+        assert_eq!(report.peers.len(), 1);
+        assert_eq!(report.peers[0].blocks, 2);
+        assert_eq!(report.peers[0].avg_delay_ms, 1600);
+        assert_eq!(report.peers[0].max_delay_ms, 1800);
+        assert_eq!(report.recent[0].hash, next.hash());
+        assert_eq!(report.recent[1].header_delay_ms, 400);
+        assert_eq!(report.recent[1].import_delay_ms, Some(2000));
+
+        propagation.remove_peer(&peer);
+        assert!(propagation.report().peers.is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::io::{Error};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 use futures::prelude::*;
@@ -14,9 +16,11 @@ use libp2p::core::{
 };
 use parking_lot::Mutex;
 use slog::{debug, error, info, warn};
-use tokio::timer::{DelayQueue, Interval};
+use tokio::timer::{delay_queue, DelayQueue, Interval};
 
 use crate::{behaviour::{Behaviour, BehaviourEvent, PubsubMessage}, config, GossipTopic, NetworkConfig, transport};
+use crate::ban_list::BanList;
+use crate::config::TransportSecurity;
 use crate::error;
 use crate::p2p::P2PEvent;
 
@@ -39,10 +43,20 @@ pub struct Service {
 
     /// A list of timeouts after which peers become unbanned.
     peer_ban_timeout: DelayQueue<PeerId>,
+    /// Keys into `peer_ban_timeout`, to extend or lift a ban early.
+    peer_ban_keys: HashMap<PeerId, delay_queue::Key>,
+    /// Bans persisted in the network directory.
+    ban_list: BanList,
     pub peers: HashSet<PeerId>,
+    /// Number of connected peers, shared with the RPC service.
+    peer_count: Arc<AtomicUsize>,
     nodes: HashMap<PeerId, DialNode>,
     /// Interval for dial queries.
     dial_interval: Interval,
+    /// Protocol securing every connection.
+    security: TransportSecurity,
+    /// Identify protocol version announced to peers, naming our genesis.
+    pub protocol_version: String,
     pub log: slog::Logger,
     mutex: Mutex<()>,
 }
@@ -64,18 +78,24 @@ pub enum DialStatus {
 }
 
 impl Service {
-    pub fn new(cfg: NetworkConfig, log: slog::Logger) -> error::Result<Self> {
+    pub fn new(cfg: NetworkConfig, protocol_version: String, log: slog::Logger) -> error::Result<Self> {
         // Load the private key from CLI disk or generate a new random PeerId
         let local_key = config::load_private_key(&cfg, log.clone());
         let local_peer_id = PeerId::from(local_key.public());
         info!(log, "Local peer id: {:?}", local_peer_id);
+        let ban_list = BanList::load(&cfg.network_dir, log.clone());
 
         // Create a Swarm to manage peers and events
         let mut swarm = {
             // Set up a an encrypted DNS-enabled TCP Transport over the Mplex and Yamux protocols
-            let transport = transport::build_transport(local_key.clone());
+            let transport = if cfg.memory_transport {
+                transport::build_memory_transport(local_key.clone(), cfg.security, &cfg.allowed_peers)
+            } else {
+                transport::build_transport(local_key.clone(), cfg.security, &cfg.allowed_peers)
+            };
+            info!(log, "Transport security"; "protocol" => cfg.security.to_string(), "allowed_peers" => cfg.allowed_peers.len());
             // network behaviour
-            let behaviour = Behaviour::new(&local_key, &log)?;
+            let behaviour = Behaviour::new(&local_key, &cfg, protocol_version.clone(), &log)?;
             Swarm::new(transport, behaviour, local_peer_id.clone())
         };
 
@@ -106,6 +126,7 @@ impl Service {
         let topics = vec![
             GossipTopic::MapBlock,
             GossipTopic::Transaction,
+            GossipTopic::BadBlock,
         ];
 
         let mut subscribed_topics: Vec<String> = vec![];
@@ -124,33 +145,73 @@ impl Service {
             println!("Listening on {:?}", a);
         }
 
+        // restore bans from the previous run
+        let mut peer_ban_timeout = DelayQueue::new();
+        let mut peer_ban_keys = HashMap::new();
+        for (peer_id, remaining) in ban_list.active() {
+            debug!(log, "Restoring peer ban"; "peer_id" => format!("{:?}", peer_id), "remaining" => format!("{:?}", remaining));
+            Swarm::ban_peer_id(&mut swarm, peer_id.clone());
+            let key = peer_ban_timeout.insert(peer_id.clone(), remaining);
+            peer_ban_keys.insert(peer_id, key);
+        }
+
         Ok(Service {
             local_peer_id,
             swarm,
             peers_to_ban: DelayQueue::new(),
-            peer_ban_timeout: DelayQueue::new(),
+            peer_ban_timeout,
+            peer_ban_keys,
+            ban_list,
             peers: HashSet::new(),
+            peer_count: Arc::new(AtomicUsize::new(0)),
             nodes: HashMap::new(),
             dial_interval: Interval::new(Instant::now(), Duration::from_secs(15)),
+            security: cfg.security,
+            protocol_version,
             log,
             mutex: Mutex::new(()),
         })
     }
 
+    /// Returns a counter of connected peers that follows connects and disconnects.
+    pub fn peer_count(&self) -> Arc<AtomicUsize> {
+        self.peer_count.clone()
+    }
+
     /// Adds a peer to be banned for a period of time, specified by a timeout.
-    pub fn disconnect_and_ban_peer(&mut self, peer_id: PeerId, timeout: Duration) {
-        error!(self.log, "Disconnecting and banning peer"; "peer_id" => format!("{:?}", peer_id), "timeout" => format!("{:?}", timeout));
+    pub fn disconnect_and_ban_peer(&mut self, peer_id: PeerId, timeout: Duration, reason: &str) {
+        error!(self.log, "Disconnecting and banning peer"; "peer_id" => format!("{:?}", peer_id), "timeout" => format!("{:?}", timeout), "reason" => reason);
         self.peers_to_ban.insert(
             peer_id.clone(),
             Duration::from_millis(BAN_PEER_WAIT_TIMEOUT),
         );
-        self.peer_ban_timeout.insert(peer_id, timeout);
+        match self.peer_ban_keys.get(&peer_id) {
+            Some(key) => self.peer_ban_timeout.reset(key, timeout),
+            None => {
+                let key = self.peer_ban_timeout.insert(peer_id.clone(), timeout);
+                self.peer_ban_keys.insert(peer_id.clone(), key);
+            }
+        }
+        self.ban_list.insert(peer_id, reason, timeout);
+    }
+
+    /// Lifts the ban of a peer, returns false if the peer was not banned.
+    pub fn unban_peer(&mut self, peer_id: PeerId) -> bool {
+        if let Some(key) = self.peer_ban_keys.remove(&peer_id) {
+            self.peer_ban_timeout.remove(&key);
+        }
+        if !self.ban_list.remove(&peer_id) {
+            return false;
+        }
+        info!(self.log, "Peer has been unbanned"; "peer_id" => format!("{:?}", peer_id));
+        Swarm::unban_peer_id(&mut self.swarm, peer_id);
+        true
     }
 
     pub fn dial_peer(&mut self) {
         self.mutex.lock();
         for (peer, node) in self.nodes.iter_mut() {
-            if self.peers.contains(peer) {
+            if self.peers.contains(peer) || self.ban_list.contains(peer) {
                 continue;
             }
             if node.state != DialStatus::Unknown && node.state != DialStatus::Disconnected {
@@ -199,20 +260,37 @@ impl Stream for Service {
                     }
                     BehaviourEvent::InjectConnect(peer_id,connected_point) => {
                         self.peers.insert(peer_id.clone());
+                        self.peer_count.store(self.peers.len(), Ordering::Relaxed);
 						if let Some(v) = self.nodes.get_mut(&peer_id) {
 							v.state  = DialStatus::Connected;
 						}
                         match connected_point {
                             ConnectedPoint::Listener { local_addr, send_back_addr } => {
-                                debug!(self.log, "Peer Connect"; "peer" => format!("{:?}", peer_id),"local" => format!("{:?}", local_addr),"remote" => format!("{:?}", send_back_addr));
+                                debug!(self.log, "Peer Connect"; "peer" => format!("{:?}", peer_id),"local" => format!("{:?}", local_addr),"remote" => format!("{:?}", send_back_addr), "security" => self.security.to_string());
                             },
-                            ConnectedPoint::Dialer { .. } =>
-                                return Ok(Async::Ready(Some(Libp2pEvent::PeerDialed(peer_id)))),
+                            ConnectedPoint::Dialer { address } => {
+                                debug!(self.log, "Peer Dialed"; "peer" => format!("{:?}", peer_id), "remote" => format!("{:?}", address), "security" => self.security.to_string());
+                                return Ok(Async::Ready(Some(Libp2pEvent::PeerDialed(peer_id))));
+                            }
                         }
                     }
+                    BehaviourEvent::Identified { peer_id, agent_version, protocol_version } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerIdentified {
+                            peer_id,
+                            agent_version,
+                            protocol_version,
+                        })));
+                    }
+                    BehaviourEvent::Subscribed { peer_id, topic } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerSubscribed { peer_id, topic })));
+                    }
+                    BehaviourEvent::Unsubscribed { peer_id, topic } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerUnsubscribed { peer_id, topic })));
+                    }
                     BehaviourEvent::PeerDisconnected(peer_id) => {
                         self.nodes.get_mut(&peer_id).unwrap().state = DialStatus::Disconnected;
                         self.peers.remove(&peer_id);
+                        self.peer_count.store(self.peers.len(), Ordering::Relaxed);
                         return Ok(Async::Ready(Some(Libp2pEvent::PeerDisconnected(peer_id))));
                     }
                     BehaviourEvent::FindPeers { peer_id, addrs } => {
@@ -254,6 +332,10 @@ impl Stream for Service {
             match self.peers_to_ban.poll() {
                 Ok(Async::Ready(Some(peer_id))) => {
                     let peer_id = peer_id.into_inner();
+                    // the ban may have been lifted while waiting
+                    if !self.ban_list.contains(&peer_id) {
+                        continue;
+                    }
                     Swarm::ban_peer_id(&mut self.swarm, peer_id.clone());
                     let dummy_connected_point = ConnectedPoint::Dialer {
                         address: "/ip4/0.0.0.0"
@@ -277,6 +359,8 @@ impl Stream for Service {
                 Ok(Async::Ready(Some(peer_id))) => {
                     let peer_id = peer_id.into_inner();
                     debug!(self.log, "Peer has been unbanned"; "peer" => format!("{:?}", peer_id));
+                    self.peer_ban_keys.remove(&peer_id);
+                    self.ban_list.remove(&peer_id);
                     Swarm::unban_peer_id(&mut self.swarm, peer_id);
                 }
                 Ok(Async::NotReady) | Ok(Async::Ready(None)) => break,
@@ -298,6 +382,22 @@ pub enum Libp2pEvent {
     PeerDialed(PeerId),
     /// A peer has disconnected.
     PeerDisconnected(PeerId),
+    /// A peer announced its software in the identify protocol.
+    PeerIdentified {
+        peer_id: PeerId,
+        agent_version: String,
+        protocol_version: String,
+    },
+    /// A peer subscribed to a gossip topic.
+    PeerSubscribed {
+        peer_id: PeerId,
+        topic: TopicHash,
+    },
+    /// A peer unsubscribed from a gossip topic.
+    PeerUnsubscribed {
+        peer_id: PeerId,
+        topic: TopicHash,
+    },
     /// Received pubsub message.
     PubsubMessage {
         id: MessageId,
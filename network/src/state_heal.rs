@@ -0,0 +1,400 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Healing of a state root whose trie nodes went missing from the local db. The nodes are
+//! fetched from the peers gossiping or syncing blocks over the `StateNodes` protocol, one peer at
+//! a time, and the blocks that failed on them are imported again once the trie is whole. The
+//! local trie is walked on a thread of its own, off the network message thread.
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use slog::{debug, error, info, warn};
+use tokio::sync::mpsc as tokio_mpsc;
+
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use map_core::block::Block;
+use map_core::state::ArchiveDB;
+use map_core::state_sync::StateSync;
+use map_core::types::Hash;
+
+use crate::handler_processor::HandlerNetworkContext;
+use crate::import_queue::{ImportQueue, ImportSource};
+use crate::manager::NetworkMessage;
+use crate::metrics;
+use crate::p2p::{methods::StateNodesRequest, P2PRequest};
+
+/// Nodes asked from a peer in one `StateNodes` request.
+pub const HEAL_BATCH: usize = 256;
+/// A peer that had none of the requested nodes or returned bad ones is asked again after this.
+pub const HEAL_RETRY: Duration = Duration::from_secs(60);
+/// How often the healing thread retries the known peers while stalled.
+const HEAL_TICK: Duration = Duration::from_secs(5);
+
+/// What to do after a healing event.
+#[derive(Debug, PartialEq)]
+pub enum HealStep {
+    /// Ask the peer for the nodes of these hashes
+    Request(PeerId, Vec<Hash>),
+    /// The trie under the root is whole again, import the blocks that failed on it
+    Healed(Hash, Vec<Block>),
+    /// Wait for a request in flight or for another peer
+    Idle,
+}
+
+#[derive(Default)]
+pub struct StateHealer {
+    root: Option<Hash>,
+    sync: Option<StateSync>,
+    /// Peer of the request in flight and the nodes it returned so far
+    peer: Option<PeerId>,
+    received: Vec<Vec<u8>>,
+    /// Peers offered for healing and still connected
+    peers: HashSet<PeerId>,
+    /// Peers that had none of the requested nodes or returned bad ones, and since when
+    exhausted: HashMap<PeerId, Instant>,
+    /// Blocks that failed on the missing nodes
+    blocks: Vec<Block>,
+}
+
+impl StateHealer {
+    /// Root being healed, if any
+    pub fn root(&self) -> Option<Hash> {
+        self.root
+    }
+
+    /// Heal `root`, keeping `block` to import again once done. A root already being healed
+    /// keeps its progress.
+    pub fn start(&mut self, db: &ArchiveDB, root: Hash, block: Block) {
+        if self.root != Some(root) {
+            let peers = std::mem::replace(&mut self.peers, HashSet::new());
+            *self = StateHealer {
+                root: Some(root),
+                sync: Some(StateSync::new(db, root)),
+                peers,
+                ..Default::default()
+            };
+        }
+        if !self.blocks.iter().any(|b| b.hash() == block.hash()) {
+            self.blocks.push(block);
+        }
+    }
+
+    /// Ask `peer` for the next batch, unless a request is in flight or the peer has nothing
+    /// to give.
+    pub fn next(&mut self, peer: &PeerId) -> HealStep {
+        self.peers.insert(peer.clone());
+        if self.peer.is_some() || self.exhausted.contains_key(peer) {
+            return HealStep::Idle;
+        }
+        let sync = match self.sync.as_mut() {
+            Some(sync) => sync,
+            None => return HealStep::Idle,
+        };
+        let batch = sync.next_batch(HEAL_BATCH);
+        if sync.is_done() {
+            return self.finish();
+        }
+        self.peer = Some(peer.clone());
+        HealStep::Request(peer.clone(), batch)
+    }
+
+    /// Ask the first known peer with nodes left to give for the next batch.
+    pub fn next_any(&mut self) -> HealStep {
+        let peers: Vec<_> = self.peers.iter()
+            .filter(|peer| !self.exhausted.contains_key(*peer))
+            .cloned()
+            .collect();
+        for peer in peers {
+            match self.next(&peer) {
+                HealStep::Idle => continue,
+                step => return step,
+            }
+        }
+        HealStep::Idle
+    }
+
+    pub fn on_node(&mut self, peer: &PeerId, node: Vec<u8>) {
+        if self.peer.as_ref() == Some(peer) {
+            self.received.push(node);
+        }
+    }
+
+    /// The response of `peer` ended, import what it returned and continue with it while it
+    /// has nodes. A bad response imports nothing, its batch goes to the next peer.
+    pub fn on_end(&mut self, peer: &PeerId) -> Result<HealStep, String> {
+        if self.peer.as_ref() != Some(peer) {
+            return Ok(HealStep::Idle);
+        }
+        self.peer = None;
+        let nodes = std::mem::replace(&mut self.received, Vec::new());
+        let sync = match self.sync.as_mut() {
+            Some(sync) => sync,
+            None => return Ok(HealStep::Idle),
+        };
+        match sync.import_found(nodes) {
+            Ok(0) => {
+                self.exhausted.insert(peer.clone(), Instant::now());
+                Ok(self.next_any())
+            }
+            Ok(_) => Ok(self.next(peer)),
+            Err(e) => {
+                self.exhausted.insert(peer.clone(), Instant::now());
+                Err(e)
+            }
+        }
+    }
+
+    /// The request to `peer` failed, its batch goes to the next peer.
+    pub fn on_peer_gone(&mut self, peer: &PeerId) {
+        if self.peer.as_ref() == Some(peer) {
+            self.peer = None;
+            self.received.clear();
+        }
+    }
+
+    /// `peer` disconnected, it is not asked again.
+    pub fn on_disconnect(&mut self, peer: &PeerId) {
+        self.on_peer_gone(peer);
+        self.peers.remove(peer);
+        self.exhausted.remove(peer);
+    }
+
+    /// Ask again the peers exhausted for longer than `HEAL_RETRY` at `now`.
+    pub fn expire(&mut self, now: Instant) {
+        self.exhausted.retain(|_, since| now < *since + HEAL_RETRY);
+    }
+
+    /// A root is being healed but no connected peer has nodes left to give.
+    pub fn stalled(&self) -> bool {
+        self.sync.is_some() && self.peer.is_none()
+            && self.peers.iter().all(|peer| self.exhausted.contains_key(peer))
+    }
+
+    fn finish(&mut self) -> HealStep {
+        let peers = std::mem::replace(&mut self.peers, HashSet::new());
+        let healer = std::mem::replace(self, StateHealer { peers, ..Default::default() });
+        match (healer.sync, healer.root) {
+            (Some(mut sync), Some(root)) => {
+                sync.commit();
+                HealStep::Healed(root, healer.blocks)
+            }
+            _ => HealStep::Idle,
+        }
+    }
+}
+
+enum HealEvent {
+    /// A block failed on missing state nodes, `PeerId` sent it.
+    Start(PeerId, Block),
+    /// A peer that can be asked for nodes.
+    Offer(PeerId),
+    Node(PeerId, Vec<u8>),
+    End(PeerId),
+    Failed(PeerId),
+    Disconnected(PeerId),
+}
+
+/// Handle to the healing thread, which runs as long as the node.
+#[derive(Clone)]
+pub struct HealQueue {
+    send: mpsc::Sender<HealEvent>,
+}
+
+impl HealQueue {
+    /// Start the healing thread of `chain`, importing the healed blocks with `importer`.
+    pub fn spawn(
+        chain: Arc<TimedRwLock<BlockChain>>,
+        importer: ImportQueue,
+        network_send: tokio_mpsc::UnboundedSender<NetworkMessage>,
+        log: slog::Logger,
+    ) -> Self {
+        let (send, recv) = mpsc::channel();
+        let mut network = HandlerNetworkContext::new(network_send, log.clone());
+        thread::Builder::new()
+            .name("state-heal".to_string())
+            .spawn(move || {
+                let mut healer = StateHealer::default();
+                let mut alerted = false;
+                loop {
+                    let step = match recv.recv_timeout(HEAL_TICK) {
+                        Ok(event) => on_event(&mut healer, &chain, event, &log),
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            healer.expire(Instant::now());
+                            healer.next_any()
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    };
+                    on_step(step, &chain, &importer, &mut network, &log);
+
+                    let stalled = healer.stalled();
+                    if stalled && !alerted {
+                        error!(log, "No peer can serve the missing state nodes, retrying every minute. Restore a snapshot if it persists";
+                            "root" => format!("{}", healer.root().unwrap_or_default()));
+                    }
+                    if stalled != alerted {
+                        metrics::set_gauge(&metrics::STATE_HEAL_STALLED, stalled as i64);
+                        alerted = stalled;
+                    }
+                }
+            })
+            .expect("spawning state heal thread");
+        HealQueue { send }
+    }
+
+    /// Heal the parent state of `block`, refused on missing state nodes, starting with `peer`.
+    pub fn start(&self, peer: PeerId, block: Block) {
+        self.send(HealEvent::Start(peer, block));
+    }
+
+    /// `peer` has the chain, ask it for nodes if a healing waits for one.
+    pub fn offer(&self, peer: PeerId) {
+        self.send(HealEvent::Offer(peer));
+    }
+
+    /// A `StateNodes` response chunk, `None` ending the response.
+    pub fn on_response(&self, peer: PeerId, node: Option<Vec<u8>>) {
+        match node {
+            Some(node) => self.send(HealEvent::Node(peer, node)),
+            None => self.send(HealEvent::End(peer)),
+        }
+    }
+
+    pub fn on_rpc_error(&self, peer: PeerId) {
+        self.send(HealEvent::Failed(peer));
+    }
+
+    pub fn on_disconnect(&self, peer: PeerId) {
+        self.send(HealEvent::Disconnected(peer));
+    }
+
+    fn send(&self, event: HealEvent) {
+        // The thread only stops with the node
+        let _ = self.send.send(event);
+    }
+}
+
+fn on_event(healer: &mut StateHealer, chain: &Arc<TimedRwLock<BlockChain>>, event: HealEvent, log: &slog::Logger) -> HealStep {
+    match event {
+        HealEvent::Start(peer, block) => {
+            let (root, db) = {
+                let chain = chain.read().expect("acquiring chain read lock");
+                (chain.unhealthy_state(), chain.statedb().clone())
+            };
+            if let Some(root) = root {
+                if healer.root() != Some(root) {
+                    warn!(log, "State trie nodes missing, healing from peers"; "root" => format!("{}", root));
+                }
+                healer.start(&db, root, block);
+            }
+            healer.next(&peer)
+        }
+        HealEvent::Offer(peer) => healer.next(&peer),
+        HealEvent::Node(peer, node) => {
+            healer.on_node(&peer, node);
+            HealStep::Idle
+        }
+        HealEvent::End(peer) => healer.on_end(&peer).unwrap_or_else(|e| {
+            warn!(log, "Bad state nodes for healing"; "peer" => format!("{:?}", peer), "error" => e);
+            healer.next_any()
+        }),
+        HealEvent::Failed(peer) => {
+            healer.on_peer_gone(&peer);
+            healer.next_any()
+        }
+        HealEvent::Disconnected(peer) => {
+            healer.on_disconnect(&peer);
+            healer.next_any()
+        }
+    }
+}
+
+fn on_step(
+    step: HealStep,
+    chain: &Arc<TimedRwLock<BlockChain>>,
+    importer: &ImportQueue,
+    network: &mut HandlerNetworkContext,
+    log: &slog::Logger,
+) {
+    match step {
+        HealStep::Request(peer_id, node_hashes) => {
+            debug!(log, "Requesting missing state nodes"; "peer" => format!("{:?}", peer_id),
+                "count" => node_hashes.len());
+            network.send_rpc_request(peer_id, P2PRequest::StateNodes(StateNodesRequest { node_hashes }));
+        }
+        HealStep::Healed(root, blocks) => {
+            chain.write().expect("acquiring chain write lock").mark_healed(root);
+            info!(log, "State trie healed"; "root" => format!("{}", root), "blocks" => blocks.len());
+            importer.submit(ImportSource::Gossip, blocks);
+        }
+        HealStep::Idle => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::state::StateDB;
+    use map_core::test_utils::memory_backend;
+
+    #[test]
+    fn heal_from_peers() {
+        let source = memory_backend();
+        let mut state = StateDB::new(&source);
+        for i in 0..64u8 {
+            state.set_storage(Hash([i; 32]), &[i; 40]);
+        }
+        state.commit();
+        let root = state.root();
+
+        let local = memory_backend();
+        let block = Block::default();
+        let mut healer = StateHealer::default();
+        healer.start(&local, root, block.clone());
+        healer.start(&local, root, block.clone());
+        assert_eq!(healer.root(), Some(root));
+
+        let (empty, bad, full) = (PeerId::random(), PeerId::random(), PeerId::random());
+        assert_eq!(healer.next(&empty), HealStep::Request(empty.clone(), vec![root]));
+        // One request in flight at a time, an exhausted peer hands the batch to the next one
+        assert_eq!(healer.next(&bad), HealStep::Idle);
+        assert_eq!(healer.on_end(&empty), Ok(HealStep::Request(bad.clone(), vec![root])));
+        assert_eq!(healer.next(&empty), HealStep::Idle);
+        // A bad answer keeps the batch for the next peer
+        healer.on_node(&bad, b"unrequested".to_vec());
+        assert!(healer.on_end(&bad).is_err());
+        assert!(healer.stalled());
+        healer.expire(Instant::now() + HEAL_RETRY);
+        assert!(!healer.stalled());
+        healer.on_disconnect(&empty);
+        healer.on_disconnect(&bad);
+
+        let mut step = healer.next(&full);
+        assert_eq!(step, HealStep::Request(full.clone(), vec![root]));
+        while let HealStep::Request(peer, batch) = step {
+            for key in batch.iter() {
+                healer.on_node(&peer, source.get_node(key).unwrap());
+            }
+            step = healer.on_end(&peer).unwrap();
+        }
+        assert_eq!(step, HealStep::Healed(root, vec![block]));
+        assert_eq!(healer.root(), None);
+        assert_eq!(StateDB::from_existing(&local, root).get_storage(&Hash([5u8; 32])), Some(vec![5u8; 40]));
+    }
+}
@@ -1,10 +1,15 @@
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
+use libp2p::PeerId;
 use slog::{debug};
 use tokio::sync::mpsc;
 
 use chain::blockchain::BlockChain;
+use chain::{BlockChainError, BlockChainErrorKind};
+use chain::lock::TimedRwLock;
 
+use crate::import_queue::{ImportQueue, ImportSource};
+use crate::state_heal::HealQueue;
 use crate::sync::manager::SyncMessage;
 use crate::sync::range_sync::BatchId;
 use map_core::block::Block;
@@ -28,8 +33,11 @@ pub enum BatchProcessResult {
 
 /// Spawns a thread handling the block processing of a request: range syncing or parent lookup.
 pub fn spawn_block_processor(
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
+    importer: ImportQueue,
+    healer: HealQueue,
     process_id: ProcessId,
+    peer: PeerId,
     downloaded_blocks: Vec<Block>,
     mut sync_send: mpsc::UnboundedSender<SyncMessage>,
     log: slog::Logger,
@@ -39,7 +47,7 @@ pub fn spawn_block_processor(
             // this a request from the range sync
             ProcessId::RangeBatchId(batch_id) => {
                 debug!(log, "Processing batch"; "id" => *batch_id, "blocks" => downloaded_blocks.len());
-                let result = match process_blocks(chain, downloaded_blocks.iter(), &log) {
+                let result = match process_blocks(chain, &importer, &healer, peer, downloaded_blocks.iter(), &log) {
                     Ok(_) => {
                         debug!(log, "Batch processed"; "id" => *batch_id );
                         BatchProcessResult::Success
@@ -71,18 +79,30 @@ fn process_blocks<
     'a,
     I: Iterator<Item=&'a Block>,
 >(
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
+    importer: &ImportQueue,
+    healer: &HealQueue,
+    peer: PeerId,
     downloaded_blocks: I,
     log: &slog::Logger,
 ) -> Result<(), String> {
     let current = chain.read().unwrap().current_block().height();
     for block in downloaded_blocks {
         println!("processor block block={}, local={}", block.height(), current);
-        match chain.write().expect("block processor").import_block(block) {
+        match importer.import(ImportSource::Sync, block.clone()) {
             Ok(_) => {
                 true
             }
             Err(e) => {
+                // A batch with an ineligible proposer fails
+                match e.downcast_ref::<BlockChainError>().map(|e| e.kind()) {
+                    Some(BlockChainErrorKind::InvalidAuthority) => {
+                        return Err(format!("block {} proposer rejected: {}", block.height(), e));
+                    }
+                    // The parent state lost trie nodes, the batch peer serves them
+                    Some(BlockChainErrorKind::MissingStateNodes) => healer.start(peer.clone(), block.clone()),
+                    _ => {}
+                }
                 println!("process_blocks error");
                 break
             }
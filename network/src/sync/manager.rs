@@ -1,7 +1,11 @@
 use super::block_processor::{BatchProcessResult};
 use super::network_context::SyncNetworkContext;
+use super::progress::{ProgressReporter, SharedSyncProgress, PROGRESS_REPORT_INTERVAL};
 use super::range_sync::{BatchId, RangeSync};
+use crate::config::RangeSyncConfig;
 use crate::handler_processor::PeerSyncInfo;
+use crate::import_queue::{ImportQueue, ImportSource};
+use crate::state_heal::HealQueue;
 use crate::manager::NetworkMessage;
 use crate::p2p::RequestId;
 use crate::p2p::methods;
@@ -11,9 +15,13 @@ use slog::{debug, error, info, trace, Logger};
 use std::boxed::Box;
 use std::collections::{HashSet, HashMap};
 use std::ops::Sub;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
+use tokio::timer::Interval;
 use chain::blockchain::BlockChain;
-use std::sync::{Arc, RwLock};
+use chain::lock::TimedRwLock;
+use std::sync::Arc;
 use map_core::block::Block;
 use map_core::types::Hash;
 
@@ -31,6 +39,9 @@ const PARENT_DEPTH_TOLERANCE: u64 = SLOT_IMPORT_TOLERANCE * 2;
 #[derive(Debug)]
 /// A message than can be sent to the sync manager thread.
 pub enum SyncMessage {
+    /// A peer passed the status handshake, it is on our chain whatever its head.
+    ChainPeer(PeerId),
+
     /// A useful peer has been discovered.
     AddPeer(PeerId, PeerSyncInfo),
 
@@ -117,7 +128,7 @@ enum ManagerState {
 /// look-up of blocks.
 pub struct SyncManager {
     /// A weak reference to the underlying beacon chain.
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
 
     /// The current state of the import manager.
     state: ManagerState,
@@ -134,9 +145,18 @@ pub struct SyncManager {
     /// Pool of pending Orphan blocks
     pool: OrphanPool,
 
+    /// Imports the resolved orphan blocks.
+    importer: ImportQueue,
+
     /// The collection of known, connected, fully-sync'd peers.
     full_peers: HashSet<PeerId>,
 
+    /// Reports the long-range sync progress.
+    progress: ProgressReporter,
+
+    /// Periodic timer driving the progress report.
+    progress_interval: Interval,
+
     /// The logger for the import manager.
     log: Logger,
 
@@ -149,8 +169,13 @@ pub struct SyncManager {
 /// dropped during the syncing process which will gracefully end the `SyncManager`.
 pub fn spawn(
     executor: &tokio::runtime::TaskExecutor,
-    block_chain: Arc<RwLock<BlockChain>>,
+    block_chain: Arc<TimedRwLock<BlockChain>>,
+    importer: ImportQueue,
+    healer: HealQueue,
     network_send: mpsc::UnboundedSender<NetworkMessage>,
+    sync_progress: SharedSyncProgress,
+    range_sync: RangeSyncConfig,
+    network_dir: PathBuf,
     log: slog::Logger,
 ) -> (
     mpsc::UnboundedSender<SyncMessage>,
@@ -167,9 +192,15 @@ pub fn spawn(
         state: ManagerState::Stalled,
         input_channel: sync_recv,
         network: SyncNetworkContext::new(network_send, log.clone()),
-        range_sync: RangeSync::new(block_chain, sync_send.clone(), log.clone()),
+        range_sync: RangeSync::new(block_chain, importer.clone(), healer, sync_send.clone(), range_sync, network_dir, log.clone()),
         pool: OrphanPool::new(),
+        importer,
         full_peers: HashSet::new(),
+        progress: ProgressReporter::new(sync_progress, log.clone()),
+        progress_interval: Interval::new(
+            Instant::now() + Duration::from_secs(PROGRESS_REPORT_INTERVAL),
+            Duration::from_secs(PROGRESS_REPORT_INTERVAL),
+        ),
         log: log.clone(),
         sync_send: sync_send.clone(),
     };
@@ -222,6 +253,7 @@ impl SyncManager {
             );
             self.add_full_peer(peer_id.clone());
         }
+        self.progress.on_peer_height(peer_id.clone(), remote.finalized_number);
 
         // Add the peer to our RangeSync
         self.range_sync.add_peer(&mut self.network, peer_id, remote);
@@ -231,6 +263,7 @@ impl SyncManager {
     fn peer_disconnect(&mut self, peer_id: &PeerId) {
         self.range_sync.peer_disconnect(&mut self.network, peer_id);
         self.full_peers.remove(peer_id);
+        self.progress.remove_chain_peer(peer_id);
         self.update_state();
     }
 
@@ -254,6 +287,12 @@ impl SyncManager {
             }
         };
         if self.state != previous_state {
+            let height = self.chain.read().unwrap().current_block().height();
+            if self.state == ManagerState::Syncing {
+                self.progress.start(height);
+            } else if previous_state == ManagerState::Syncing {
+                self.progress.stop(height);
+            }
             info!(self.log, "Syncing state updated";
                 "old_state" => format!("{:?}", previous_state),
                 "new_state" => format!("{:?}", self.state),
@@ -325,23 +364,12 @@ impl SyncManager {
 
         let head = self.chain.read().unwrap().current_block();
         if block.header.parent_hash == head.header.hash() {
-            let mut chain = self.chain.write().unwrap();
-            match chain.import_block(&block) {
-                Ok(_) => {
-                }
-                Err(e) => {
-                    println!("block root insert_block, Error: {:?}", e);
-                }
-            }
+            // The block, then the descendants fetched before it
+            let mut blocks = vec![block];
             while let Some(block) = self.pool.parents.downloaded_blocks.pop() {
-                match chain.import_block(&block) {
-                    Ok(_) => {
-                    }
-                    Err(e) => {
-                        println!("block root insert_block, Error: {:?}", e);
-                    }
-                }
+                blocks.push(block);
             }
+            self.importer.submit(ImportSource::Orphan, blocks);
         } else {
             let parent = block.header.parent_hash;
             self.pool.parents.downloaded_blocks.push(block);
@@ -359,6 +387,9 @@ impl Future for SyncManager {
         loop {
             match self.input_channel.poll() {
                 Ok(Async::Ready(Some(message))) => match message {
+                    SyncMessage::ChainPeer(peer_id) => {
+                        self.progress.add_chain_peer(peer_id);
+                    }
                     SyncMessage::AddPeer(peer_id, info) => {
                         self.add_peer(peer_id, info);
                     }
@@ -386,7 +417,7 @@ impl Future for SyncManager {
                         self.peer_disconnect(&peer_id);
                     }
                     SyncMessage::RPCError(peer_id, request_id) => {
-                        println!("RPCError");
+                        self.range_sync.inject_error(&mut self.network, peer_id, request_id);
                     }
                     SyncMessage::OrphanBlock(peer_id, block) => {
 
@@ -420,6 +451,12 @@ impl Future for SyncManager {
             self.update_state();
         }
 
+        // report the sync progress
+        while let Ok(Async::Ready(Some(_))) = self.progress_interval.poll() {
+            let height = self.chain.read().unwrap().current_block().height();
+            self.progress.report(height);
+        }
+
         Ok(Async::NotReady)
     }
 }
@@ -4,6 +4,7 @@
 mod block_processor;
 pub mod manager;
 mod network_context;
+pub mod progress;
 mod range_sync;
 
 /// Currently implemented sync methods.
@@ -12,3 +13,4 @@ pub enum SyncMethod {
 }
 
 pub use manager::SyncMessage;
+pub use progress::{ProposalState, SharedSyncProgress, SyncProgress};
@@ -1,13 +1,14 @@
 //! Provides network functionality for the Syncing thread. This fundamentally wraps a network
 //! channel and stores a global P2P ID to perform requests.
 
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use libp2p::PeerId;
 use slog::{debug, trace, warn};
 use tokio::sync::mpsc;
 
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
 
 use crate::handler_processor::status_message;
 use crate::manager::NetworkMessage;
@@ -35,7 +36,7 @@ impl SyncNetworkContext {
 
     pub fn status_peer(
         &mut self,
-        chain: Arc<RwLock<BlockChain>>,
+        chain: Arc<TimedRwLock<BlockChain>>,
         peer_id: PeerId,
     ) {
         if let Some(status_message) = status_message(chain) {
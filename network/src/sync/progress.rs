@@ -0,0 +1,276 @@
+//! Tracks the progress of a long-range sync and reports it to the log, metrics and RPC.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use serde::Serialize;
+use slog::{info, Logger};
+
+use crate::metrics;
+
+/// The interval in seconds between two progress log lines while syncing.
+pub const PROGRESS_REPORT_INTERVAL: u64 = 10;
+
+/// Weight given to the latest sample when smoothing the import rate.
+const RATE_SMOOTHING: f64 = 0.3;
+
+/// Whether the block producer proposes in the slots it is elected for.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalState {
+    Active,
+    /// Fewer peers on our chain than required, a block would likely start a fork of its own
+    InsufficientPeers,
+}
+
+impl Default for ProposalState {
+    fn default() -> Self {
+        ProposalState::Active
+    }
+}
+
+/// A snapshot of the sync progress, shared with the RPC service.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SyncProgress {
+    /// Whether a long-range sync is in progress.
+    pub syncing: bool,
+    /// Local head height when the current sync started.
+    pub starting_height: u64,
+    /// Local head height.
+    pub current_height: u64,
+    /// Sync target, the median head height advertised by the connected peers.
+    pub highest_height: u64,
+    /// Smoothed number of blocks imported per second.
+    pub blocks_per_sec: f64,
+    /// Estimated seconds left to reach `highest_height`, if the rate is known.
+    pub eta_secs: Option<u64>,
+    /// Connected peers whose status matched our chain.
+    #[serde(skip)]
+    pub chain_peers: usize,
+    /// Set by the block producer on each slot.
+    #[serde(skip)]
+    pub proposal: ProposalState,
+}
+
+impl SyncProgress {
+    /// Number of blocks the local `head` is behind the peers in the given `slot`.
+    ///
+    /// A chain holds at most one block per slot, so a target above the current slot is capped
+    /// there rather than trusted.
+    pub fn blocks_behind(&self, head: u64, slot: u64) -> u64 {
+        self.highest_height.min(slot).saturating_sub(head)
+    }
+}
+
+pub type SharedSyncProgress = Arc<RwLock<SyncProgress>>;
+
+/// Samples the local head and updates the shared `SyncProgress`.
+pub struct ProgressReporter {
+    progress: SharedSyncProgress,
+    /// Height and time of the previous sample.
+    last_sample: Option<(u64, Instant)>,
+    /// Connected peers whose status matched our chain.
+    chain_peers: HashSet<PeerId>,
+    /// Head height advertised by each connected peer.
+    peer_heights: HashMap<PeerId, u64>,
+    log: Logger,
+}
+
+impl ProgressReporter {
+    pub fn new(progress: SharedSyncProgress, log: Logger) -> Self {
+        ProgressReporter {
+            progress,
+            last_sample: None,
+            chain_peers: HashSet::new(),
+            peer_heights: HashMap::new(),
+            log,
+        }
+    }
+
+    /// Records the head height advertised by a peer.
+    pub fn on_peer_height(&mut self, peer_id: PeerId, height: u64) {
+        self.peer_heights.insert(peer_id, height);
+        self.update_target();
+    }
+
+    /// Counts a peer which passed the status handshake, ahead or behind us.
+    pub fn add_chain_peer(&mut self, peer_id: PeerId) {
+        self.chain_peers.insert(peer_id);
+        self.progress.write().expect("acquiring sync progress lock").chain_peers = self.chain_peers.len();
+    }
+
+    /// Stops counting a disconnected peer and drops its height from the target.
+    pub fn remove_chain_peer(&mut self, peer_id: &PeerId) {
+        self.chain_peers.remove(peer_id);
+        self.progress.write().expect("acquiring sync progress lock").chain_peers = self.chain_peers.len();
+        if self.peer_heights.remove(peer_id).is_some() {
+            self.update_target();
+        }
+    }
+
+    /// Recomputes the sync target from the heights of the connected peers.
+    fn update_target(&mut self) {
+        let target = median_height(self.peer_heights.values().cloned().collect());
+        self.progress.write().expect("acquiring sync progress lock").highest_height = target;
+        metrics::set_gauge(&metrics::SYNC_HIGHEST_PEER_HEIGHT, target as i64);
+    }
+
+    /// Marks the start of a long-range sync from the given local height.
+    pub fn start(&mut self, height: u64) {
+        let mut progress = self.progress.write().expect("acquiring sync progress lock");
+        progress.syncing = true;
+        progress.starting_height = height;
+        progress.current_height = height;
+        progress.blocks_per_sec = 0.0;
+        progress.eta_secs = None;
+        self.last_sample = Some((height, Instant::now()));
+    }
+
+    /// Marks the end of a long-range sync.
+    pub fn stop(&mut self, height: u64) {
+        let mut progress = self.progress.write().expect("acquiring sync progress lock");
+        progress.syncing = false;
+        progress.current_height = height;
+        progress.eta_secs = None;
+        self.last_sample = None;
+        metrics::set_gauge(&metrics::SYNC_PROCESSED_HEIGHT, height as i64);
+        metrics::set_gauge(&metrics::SYNC_ETA_SECONDS, 0);
+    }
+
+    /// Takes a new sample of the local head and logs the progress if a sync is running.
+    pub fn report(&mut self, height: u64) {
+        let now = Instant::now();
+        let mut progress = self.progress.write().expect("acquiring sync progress lock");
+        progress.current_height = height;
+        metrics::set_gauge(&metrics::SYNC_PROCESSED_HEIGHT, height as i64);
+
+        if !progress.syncing {
+            return;
+        }
+
+        if let Some((last_height, last_instant)) = self.last_sample {
+            let elapsed = duration_secs(now.duration_since(last_instant));
+            if elapsed > 0.0 {
+                let rate = height.saturating_sub(last_height) as f64 / elapsed;
+                progress.blocks_per_sec = smooth_rate(progress.blocks_per_sec, rate);
+            }
+        }
+        self.last_sample = Some((height, now));
+
+        let remaining = progress.highest_height.saturating_sub(height);
+        progress.eta_secs = estimate_eta(remaining, progress.blocks_per_sec);
+
+        metrics::set_float_gauge(&metrics::SYNC_BLOCKS_PER_SECOND, progress.blocks_per_sec);
+        metrics::maybe_set_gauge(&metrics::SYNC_ETA_SECONDS, progress.eta_secs.map(|eta| eta as i64));
+
+        info!(self.log, "Syncing";
+            "height" => height,
+            "target" => progress.highest_height,
+            "remaining" => remaining,
+            "blocks/s" => format!("{:.2}", progress.blocks_per_sec),
+            "eta" => progress.eta_secs.map_or("unknown".to_string(), format_eta),
+        );
+    }
+}
+
+/// The lower median, at least half of the peers are at or above it. A single peer advertising
+/// a far-off height does not move it.
+fn median_height(mut heights: Vec<u64>) -> u64 {
+    if heights.is_empty() {
+        return 0;
+    }
+    heights.sort_unstable();
+    heights[(heights.len() - 1) / 2]
+}
+
+fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_millis()) / 1000.0
+}
+
+fn smooth_rate(previous: f64, sample: f64) -> f64 {
+    if previous == 0.0 {
+        sample
+    } else {
+        previous * (1.0 - RATE_SMOOTHING) + sample * RATE_SMOOTHING
+    }
+}
+
+fn estimate_eta(remaining: u64, blocks_per_sec: f64) -> Option<u64> {
+    if remaining == 0 {
+        Some(0)
+    } else if blocks_per_sec > 0.0 {
+        Some((remaining as f64 / blocks_per_sec).ceil() as u64)
+    } else {
+        None
+    }
+}
+
+fn format_eta(secs: u64) -> String {
+    format!("{}h{:02}m{:02}s", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_estimate() {
+        assert_eq!(estimate_eta(0, 0.0), Some(0));
+        assert_eq!(estimate_eta(100, 0.0), None);
+        assert_eq!(estimate_eta(100, 3.0), Some(34));
+    }
+
+    #[test]
+    fn rate_smoothing() {
+        assert_eq!(smooth_rate(0.0, 10.0), 10.0);
+        assert!((smooth_rate(10.0, 20.0) - 13.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn behind_peers() {
+        let progress = SyncProgress { highest_height: 120, ..Default::default() };
+        assert_eq!(progress.blocks_behind(100, 200), 20);
+        assert_eq!(progress.blocks_behind(130, 200), 0);
+        assert_eq!(progress.blocks_behind(100, 110), 10);
+    }
+
+    #[test]
+    fn peer_target() {
+        assert_eq!(median_height(vec![]), 0);
+        assert_eq!(median_height(vec![100, u64::max_value()]), 100);
+        assert_eq!(median_height(vec![100, 90, u64::max_value()]), 100);
+
+        let progress = SharedSyncProgress::default();
+        let mut reporter = ProgressReporter::new(progress.clone(), Logger::root(slog::Discard, slog::o!()));
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        reporter.on_peer_height(a.clone(), 100);
+        reporter.on_peer_height(b.clone(), 102);
+        reporter.on_peer_height(c.clone(), 1_000_000);
+        assert_eq!(progress.read().unwrap().highest_height, 102);
+        reporter.remove_chain_peer(&c);
+        reporter.remove_chain_peer(&b);
+        assert_eq!(progress.read().unwrap().highest_height, 100);
+        reporter.remove_chain_peer(&a);
+        assert_eq!(progress.read().unwrap().highest_height, 0);
+    }
+
+    #[test]
+    fn chain_peers() {
+        let progress = SharedSyncProgress::default();
+        let mut reporter = ProgressReporter::new(progress.clone(), Logger::root(slog::Discard, slog::o!()));
+        let (a, b) = (PeerId::random(), PeerId::random());
+        reporter.add_chain_peer(a.clone());
+        reporter.add_chain_peer(a.clone());
+        reporter.add_chain_peer(b.clone());
+        assert_eq!(progress.read().unwrap().chain_peers, 2);
+        reporter.remove_chain_peer(&a);
+        reporter.remove_chain_peer(&a);
+        assert_eq!(progress.read().unwrap().chain_peers, 1);
+    }
+
+    #[test]
+    fn eta_format() {
+        assert_eq!(format_eta(3725), "1h02m05s");
+    }
+}
@@ -1,4 +1,3 @@
-use super::chain::BLOCKS_PER_BATCH;
 use crate::p2p::methods::*;
 use crate::p2p::RequestId;
 use libp2p::PeerId;
@@ -7,6 +6,7 @@ use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::time::Instant;
 use map_core::block::Block;
 use map_core::types::Hash as Hash256;
 
@@ -57,6 +57,8 @@ pub struct Batch {
     pub original_hash: Option<u64>,
     /// The blocks that have been downloaded.
     pub downloaded_blocks: Vec<Block>,
+    /// When the batch was last requested, to measure the throughput of the peer.
+    pub requested_at: Instant,
 }
 
 impl Eq for Batch {}
@@ -80,6 +82,7 @@ impl Batch {
             reprocess_retries: 0,
             original_hash: None,
             downloaded_blocks: Vec::new(),
+            requested_at: Instant::now(),
         }
     }
 
@@ -87,7 +90,7 @@ impl Batch {
         BlocksByRangeRequest {
             head_block_root: self.head_root,
             start_slot: self.start_numer.into(),
-            count: std::cmp::min(BLOCKS_PER_BATCH, (self.end_number - self.start_numer).into()),
+            count: self.end_number - self.start_numer,
             step: 1,
         }
     }
@@ -178,6 +181,11 @@ impl PendingBatches {
         self.peer_requests.get(peer_id).is_none()
     }
 
+    /// The number of pending batch requests to the peer.
+    pub fn peer_requests(&self, peer_id: &PeerId) -> usize {
+        self.peer_requests.get(peer_id).map_or(0, |requests| requests.len())
+    }
+
     /// Removes a batch for a given peer.
     pub fn remove_batch_by_peer(&mut self, peer_id: &PeerId) -> Option<Batch> {
         let request_ids = self.peer_requests.get(peer_id)?;
@@ -186,3 +194,35 @@ impl PendingBatches {
         self.remove(request_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::test_utils::{self, ChainBuilder};
+
+    #[test]
+    fn batch_hash_detects_fork() {
+        let (genesis, _) = test_utils::genesis_with_state();
+        let main = ChainBuilder::new(genesis).extend(4);
+        let fork = main.fork_at(1).extend(2);
+        let peer = PeerId::random();
+
+        let mut pending = PendingBatches::new();
+        pending.insert(1, Batch::new(BatchId(1), 1, 5, main.head().hash(), peer.clone()));
+        pending.insert(2, Batch::new(BatchId(2), 1, 5, fork.head().hash(), peer.clone()));
+        for b in main.descendants() {
+            pending.add_block(1, b.clone()).unwrap();
+        }
+        for b in fork.descendants() {
+            pending.add_block(2, b.clone()).unwrap();
+        }
+        assert!(!pending.peer_is_idle(&peer));
+        assert_eq!(pending.peer_requests(&peer), 2);
+
+        let main_batch = pending.remove(1).unwrap();
+        let fork_batch = pending.remove(2).unwrap();
+        assert_eq!(main_batch.downloaded_blocks.len(), 4);
+        assert_ne!(main_batch.hash(), fork_batch.hash());
+        assert!(pending.peer_is_idle(&peer));
+    }
+}
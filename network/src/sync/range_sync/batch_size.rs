@@ -0,0 +1,95 @@
+//! Batch sizes adapted to the throughput of each peer, so a batch takes about
+//! `TARGET_BATCH_TIME` to download on a weak connection as on a strong one.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use libp2p::PeerId;
+
+use crate::config::RangeSyncConfig;
+
+/// Time the download of a batch should take.
+pub const TARGET_BATCH_TIME: Duration = Duration::from_secs(2);
+
+/// Smallest batch asked, the slowest peer still makes progress.
+const MIN_BATCH_SIZE: u64 = 1;
+
+pub struct BatchSizer {
+    initial: u64,
+    max: u64,
+    /// Size of the next batch of the peers measured so far.
+    sizes: HashMap<PeerId, u64>,
+}
+
+impl BatchSizer {
+    pub fn new(config: &RangeSyncConfig) -> Self {
+        BatchSizer {
+            initial: config.batch_size,
+            max: config.max_batch_size,
+            sizes: HashMap::new(),
+        }
+    }
+
+    /// Blocks to ask `peer` in its next batch.
+    pub fn size(&self, peer_id: &PeerId) -> u64 {
+        self.sizes.get(peer_id).cloned().unwrap_or(self.initial)
+    }
+
+    /// `peer` returned `blocks` in `elapsed`, move its size halfway to the blocks it would return
+    /// in the target time.
+    pub fn on_downloaded(&mut self, peer_id: &PeerId, blocks: usize, elapsed: Duration) {
+        if blocks == 0 {
+            return;
+        }
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let target = (blocks as f64 / secs * TARGET_BATCH_TIME.as_secs_f64()) as u64;
+        let size = (self.size(peer_id) + target) / 2;
+        self.set(peer_id, size);
+    }
+
+    /// A request to `peer` failed or timed out, halve its size.
+    pub fn on_failed(&mut self, peer_id: &PeerId) {
+        let size = self.size(peer_id) / 2;
+        self.set(peer_id, size);
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.sizes.remove(peer_id);
+    }
+
+    fn set(&mut self, peer_id: &PeerId, size: u64) {
+        self.sizes.insert(peer_id.clone(), size.max(MIN_BATCH_SIZE).min(self.max));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_peer_throughput() {
+        let mut sizer = BatchSizer::new(&RangeSyncConfig::default());
+        let (fast, slow) = (PeerId::random(), PeerId::random());
+        assert_eq!(sizer.size(&fast), 5);
+
+        // 5 blocks in 100ms reach 100 blocks in the target time, capped at the max
+        for _ in 0..10 {
+            let size = sizer.size(&fast) as usize;
+            sizer.on_downloaded(&fast, size, Duration::from_millis(100));
+        }
+        assert_eq!(sizer.size(&fast), 64);
+
+        // 1 block every 2s stays at 1
+        for _ in 0..10 {
+            let size = sizer.size(&slow) as usize;
+            sizer.on_downloaded(&slow, size, Duration::from_secs(2 * size as u64));
+        }
+        assert_eq!(sizer.size(&slow), 1);
+
+        sizer.on_failed(&fast);
+        assert_eq!(sizer.size(&fast), 32);
+        sizer.on_failed(&slow);
+        assert_eq!(sizer.size(&slow), 1);
+        sizer.remove_peer(&fast);
+        assert_eq!(sizer.size(&fast), 5);
+    }
+}
@@ -1,5 +1,5 @@
 use std::collections::HashSet;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use libp2p::PeerId;
 use rand::prelude::*;
@@ -7,28 +7,20 @@ use slog::{crit, info, debug, warn};
 use tokio::sync::mpsc;
 
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
 use map_core::block::Block;
 use map_core::types::Hash as Hash256;
 
+use crate::config::RangeSyncConfig;
+use crate::import_queue::ImportQueue;
+use crate::state_heal::HealQueue;
 use crate::p2p::RequestId;
 use crate::sync::block_processor::{BatchProcessResult, ProcessId, spawn_block_processor};
 use crate::sync::network_context::SyncNetworkContext;
 use crate::sync::SyncMessage;
 
 use super::batch::{Batch, BatchId, PendingBatches};
-
-/// Blocks are downloaded in batches from peers. This constant specifies how many blocks per batch
-/// is requested. There is a timeout for each batch request. If this value is too high, we will
-/// downvote peers with poor bandwidth. This can be set arbitrarily high, in which case the
-/// responder will fill the response up to the max request size, assuming they have the bandwidth
-/// to do so.
-pub const BLOCKS_PER_BATCH: u64 = 5;
-
-/// The number of times to retry a batch before the chain is considered failed and removed.
-const MAX_BATCH_RETRIES: u8 = 5;
-
-/// The maximum number of batches to queue before requesting more.
-const BATCH_BUFFER_SIZE: u8 = 5;
+use super::batch_size::BatchSizer;
 
 /// Invalid batches are attempted to be re-downloaded from other peers. If they cannot be processed
 /// after `INVALID_BATCH_LOOKUP_ATTEMPTS` times, the chain is considered faulty and all peers will
@@ -76,6 +68,19 @@ pub struct SyncingChain {
     /// The next batch id that needs to be processed.
     to_be_processed_id: BatchId,
 
+    /// The first block of the next batch to download. Batches differ in size, so the ranges are
+    /// tracked rather than derived from the batch ids.
+    next_batch_start: u64,
+
+    /// The last block of the processed batches.
+    processed_number: u64,
+
+    /// Batch sizes, buffer, retries and parallelism of the downloads.
+    config: RangeSyncConfig,
+
+    /// Batch size of each peer, following its throughput.
+    sizer: BatchSizer,
+
     /// The current state of the chain.
     pub state: ChainSyncingState,
 
@@ -87,7 +92,13 @@ pub struct SyncingChain {
     /// back once batch processing has completed.
     sync_send: mpsc::UnboundedSender<SyncMessage>,
 
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
+
+    /// Imports the blocks of the processed batches.
+    importer: ImportQueue,
+
+    /// Heals the state of the batch blocks refused on missing state nodes.
+    healer: HealQueue,
 
     /// A reference to the sync logger.
     log: slog::Logger,
@@ -107,7 +118,10 @@ impl SyncingChain {
         target_head_slot: u64,
         target_head_root: Hash256,
         sync_send: mpsc::UnboundedSender<SyncMessage>,
-        block_chain: Arc<RwLock<BlockChain>>,
+        block_chain: Arc<TimedRwLock<BlockChain>>,
+        importer: ImportQueue,
+        healer: HealQueue,
+        config: RangeSyncConfig,
         log: slog::Logger,
     ) -> Self {
         let peer_pool = HashSet::new();
@@ -122,19 +136,23 @@ impl SyncingChain {
             peer_pool,
             to_be_downloaded_id: BatchId(1),
             to_be_processed_id: BatchId(1),
+            next_batch_start: start_numer + 1,
+            processed_number: start_numer,
+            sizer: BatchSizer::new(&config),
+            config,
             state: ChainSyncingState::Stopped,
             current_processing_batch: None,
             sync_send,
             chain: block_chain,
+            importer,
+            healer,
             log,
         }
     }
 
     /// Returns the latest slot number that has been processed.
-    fn current_processed_slot(&self) -> u64 {
-        // println!("current_processed_slot {:?}",self.to_be_processed_id);
-        self.start_numer
-            .saturating_add(self.to_be_processed_id.saturating_sub(1u64) * BLOCKS_PER_BATCH)
+    pub fn current_processed_slot(&self) -> u64 {
+        self.processed_number
     }
 
     /// A batch of blocks has been received. This function gets run on all chains and should
@@ -187,6 +205,7 @@ impl SyncingChain {
                 return;
             }
         }
+        self.sizer.on_downloaded(&batch.current_peer, batch.downloaded_blocks.len(), batch.requested_at.elapsed());
 
         // Add this completed batch to the list of completed batches. This list will then need to
         // be checked if any batches can be processed and verified for errors or invalid responses
@@ -246,10 +265,14 @@ impl SyncingChain {
     fn process_batch(&mut self, mut batch: Batch) {
         let downloaded_blocks = std::mem::replace(&mut batch.downloaded_blocks, Vec::new());
         let batch_id = ProcessId::RangeBatchId(batch.id.clone());
+        let peer = batch.current_peer.clone();
         self.current_processing_batch = Some(batch);
         spawn_block_processor(
             self.chain.clone(),
+            self.importer.clone(),
+            self.healer.clone(),
             batch_id,
+            peer,
             downloaded_blocks,
             self.sync_send.clone(),
             self.log.clone(),
@@ -299,6 +322,7 @@ impl SyncingChain {
         let res = match result {
             BatchProcessResult::Success => {
                 *self.to_be_processed_id += 1;
+                self.processed_number = batch.end_number.saturating_sub(1);
 
                 // If the processed batch was not empty, we can validate previous invalidated
                 // blocks
@@ -408,15 +432,20 @@ impl SyncingChain {
     }
 
     pub fn start_syncing(&mut self, network: &mut SyncNetworkContext, local_finalized_number: u64) {
-        if local_finalized_number > self.current_processed_slot() {
+        // A stopped chain may have dropped batches, download again from the local head
+        if local_finalized_number > self.current_processed_slot() || self.state == ChainSyncingState::Stopped {
             debug!(self.log, "Updating chain's progress";
                 "prev_completed_slot" => self.current_processed_slot(),
                 "new_completed_slot" => local_finalized_number);
             // Re-index batches
             *self.to_be_downloaded_id = 1;
             *self.to_be_processed_id = 1;
+            self.start_numer = local_finalized_number;
+            self.next_batch_start = local_finalized_number + 1;
+            self.processed_number = local_finalized_number;
 
-            // remove any completed or processed batches
+            // remove any pending, completed or processed batches
+            self.pending_batches = PendingBatches::new();
             self.completed_batches.clear();
             self.processed_batches.clear();
         }
@@ -431,6 +460,55 @@ impl SyncingChain {
         self.request_batches(network);
     }
 
+    /// Remove a peer from the chain, its pending batches are requested from the other peers.
+    pub fn remove_peer(&mut self, network: &mut SyncNetworkContext, peer_id: &PeerId) -> ProcessingResult {
+        self.peer_pool.remove(peer_id);
+        self.sizer.remove_peer(peer_id);
+        while let Some(batch) = self.pending_batches.remove_batch_by_peer(peer_id) {
+            if let ProcessingResult::RemoveChain = self.retry_batch(network, batch) {
+                return ProcessingResult::RemoveChain;
+            }
+        }
+        ProcessingResult::KeepChain
+    }
+
+    /// A batch request failed. Returns None if the request is not one of this chain.
+    pub fn inject_error(
+        &mut self,
+        network: &mut SyncNetworkContext,
+        peer_id: &PeerId,
+        request_id: RequestId,
+    ) -> Option<ProcessingResult> {
+        let batch = self.pending_batches.remove(request_id)?;
+        self.sizer.on_failed(peer_id);
+        Some(self.retry_batch(network, batch))
+    }
+
+    /// Request a failed batch again, from another peer of the pool when there is one. The chain
+    /// is removed once the batch failed more than the configured retries.
+    fn retry_batch(&mut self, network: &mut SyncNetworkContext, mut batch: Batch) -> ProcessingResult {
+        batch.retries += 1;
+        let mut rng = rand::thread_rng();
+        let peer = self.peer_pool.iter()
+            .filter(|peer| **peer != batch.current_peer)
+            .choose(&mut rng)
+            .or_else(|| self.peer_pool.iter().next())
+            .cloned();
+        let peer = match peer {
+            Some(peer) if batch.retries <= self.config.batch_retries => peer,
+            _ => {
+                warn!(self.log, "Batch failed to download. Dropping chain"; "id" => *batch.id,
+                    "retries" => batch.retries, "peers" => self.peer_pool.len());
+                return ProcessingResult::RemoveChain;
+            }
+        };
+        debug!(self.log, "Retrying batch"; "id" => *batch.id, "retries" => batch.retries,
+            "peer" => format!("{}", peer));
+        batch.current_peer = peer;
+        self.send_batch(network, batch);
+        ProcessingResult::KeepChain
+    }
+
     /// Sends a STATUS message to all peers in the peer pool.
     pub fn status_peers(&self, network: &mut SyncNetworkContext) {
         for peer_id in self.peer_pool.iter() {
@@ -466,7 +544,7 @@ impl SyncingChain {
         false
     }
 
-    /// Returns a peer if there exists a peer which does not currently have a pending request.
+    /// Returns a peer if there exists a peer with fewer pending requests than allowed.
     ///
     /// This is used to create the next request.
     fn get_next_peer(&self) -> Option<PeerId> {
@@ -476,7 +554,7 @@ impl SyncingChain {
         let mut peers = self.peer_pool.iter().collect::<Vec<_>>();
         peers.shuffle(&mut rng);
         for peer in peers {
-            if self.pending_batches.peer_is_idle(peer) {
+            if self.pending_batches.peer_requests(peer) < self.config.peer_requests {
                 return Some(peer.clone());
             }
         }
@@ -492,24 +570,23 @@ impl SyncingChain {
             .completed_batches
             .len()
             .saturating_add(self.pending_batches.len())
-            > BATCH_BUFFER_SIZE as usize
+            > self.config.batch_buffer
         {
             return None;
         }
 
-        // println!("get_next_batch {:?}",self.to_be_downloaded_id);
         // don't request batches beyond the target head slot
-        let batch_start_numer =
-            self.start_numer + self.to_be_downloaded_id.saturating_sub(1) * BLOCKS_PER_BATCH + 1;
+        let batch_start_numer = self.next_batch_start;
         if batch_start_numer > self.target_head_slot {
             return None;
         }
 
         // truncate the batch to the target head of the chain
         let batch_end_number = std::cmp::min(
-            batch_start_numer + BLOCKS_PER_BATCH,
+            batch_start_numer + self.sizer.size(&peer_id),
             self.target_head_slot.saturating_add(1u64),
         );
+        self.next_batch_start = batch_end_number;
 
         let batch_id = self.to_be_downloaded_id;
 
@@ -537,7 +614,8 @@ impl SyncingChain {
     }
 
     /// Requests the provided batch from the provided peer.
-    fn send_batch(&mut self, network: &mut SyncNetworkContext, batch: Batch) {
+    fn send_batch(&mut self, network: &mut SyncNetworkContext, mut batch: Batch) {
+        batch.requested_at = std::time::Instant::now();
         let request = batch.to_blocks_by_range_request();
         if let Ok(request_id) = network.blocks_by_range_request(batch.current_peer.clone(), request)
         {
@@ -0,0 +1,90 @@
+//! Target of the range sync, written to the network directory so a restarted node resumes
+//! towards the same target instead of waiting for its peers to agree on one again. The target is
+//! only a hint, dropped once the connected peers all report lower heads.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use map_core::types::Hash as Hash256;
+
+const SYNC_CHECKPOINT_FILENAME: &str = "sync_checkpoint";
+const SYNC_CHECKPOINT_TMP_FILENAME: &str = "sync_checkpoint.tmp";
+
+/// Older checkpoints are ignored, the peers have moved on since.
+pub const MAX_CHECKPOINT_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyncCheckpoint {
+    pub target_root: Hash256,
+    pub target_height: u64,
+    /// Unix time in seconds of the save.
+    pub saved_at: u64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn checkpoint_path(network_dir: &Path) -> PathBuf {
+    network_dir.join(SYNC_CHECKPOINT_FILENAME)
+}
+
+impl SyncCheckpoint {
+    pub fn new(target_root: Hash256, target_height: u64) -> Self {
+        SyncCheckpoint { target_root, target_height, saved_at: unix_now() }
+    }
+
+    /// The checkpoint saved in `network_dir`, None if missing, unreadable or too old.
+    pub fn load(network_dir: &Path) -> Option<Self> {
+        let data = fs::read(checkpoint_path(network_dir)).ok()?;
+        let checkpoint: SyncCheckpoint = bincode::deserialize(&data).ok()?;
+        if unix_now().saturating_sub(checkpoint.saved_at) > MAX_CHECKPOINT_AGE.as_secs() {
+            return None;
+        }
+        Some(checkpoint)
+    }
+
+    /// Written to a temporary file first, a crash leaves the previous checkpoint whole.
+    pub fn save(&self, network_dir: &Path) -> io::Result<()> {
+        let data = bincode::serialize(self).expect("serialize sync checkpoint");
+        fs::create_dir_all(network_dir)?;
+        let tmp = network_dir.join(SYNC_CHECKPOINT_TMP_FILENAME);
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, checkpoint_path(network_dir))
+    }
+
+    /// Forget the checkpoint once the sync completed.
+    pub fn remove(network_dir: &Path) {
+        let _ = fs::remove_file(checkpoint_path(network_dir));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_load_remove() {
+        let dir = std::env::temp_dir().join("map_sync_checkpoint");
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(SyncCheckpoint::load(&dir), None);
+
+        let checkpoint = SyncCheckpoint::new(Hash256([7; 32]), 500);
+        checkpoint.save(&dir).unwrap();
+        assert_eq!(SyncCheckpoint::load(&dir), Some(checkpoint.clone()));
+        assert!(!dir.join(SYNC_CHECKPOINT_TMP_FILENAME).exists());
+
+        let stale = SyncCheckpoint { saved_at: checkpoint.saved_at - MAX_CHECKPOINT_AGE.as_secs() - 1, ..checkpoint };
+        stale.save(&dir).unwrap();
+        assert_eq!(SyncCheckpoint::load(&dir), None);
+
+        SyncCheckpoint::remove(&dir);
+        assert!(!checkpoint_path(&dir).exists());
+    }
+}
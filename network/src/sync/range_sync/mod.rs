@@ -2,7 +2,9 @@
 //! peers.
 
 mod batch;
+mod batch_size;
 mod chain;
+mod checkpoint;
 mod range;
 
 pub use batch::Batch;
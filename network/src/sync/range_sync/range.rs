@@ -1,14 +1,19 @@
-use std::collections::HashSet;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use libp2p::PeerId;
-use slog::{debug};
+use slog::{debug, info, warn};
 use tokio::sync::mpsc;
 
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
 use map_core::types::Hash as Hash256;
 
+use crate::config::RangeSyncConfig;
 use crate::handler_processor::PeerSyncInfo;
+use crate::import_queue::ImportQueue;
+use crate::state_heal::HealQueue;
 use crate::p2p::RequestId;
 use crate::sync::block_processor::BatchProcessResult;
 use crate::sync::manager::SyncMessage;
@@ -17,13 +22,14 @@ use crate::sync::network_context::SyncNetworkContext;
 use super::BatchId;
 use super::chain::{SyncingChain, ChainSyncingState};
 use super::chain::ProcessingResult;
+use super::checkpoint::SyncCheckpoint;
 use map_core::block::Block;
 
 /// The primary object dealing with long range/batch syncing. This contains all the active and
 /// non-active chains that need to be processed before the syncing is considered complete. This
 /// holds the current state of the long range sync.
 pub struct RangeSync {
-    chain: Arc<RwLock<BlockChain>>,
+    chain: Arc<TimedRwLock<BlockChain>>,
     /// A collection of chains that need to be downloaded. This stores any head or finalized chains
     /// that need to be downloaded.
     chains: SyncingChain,
@@ -32,22 +38,42 @@ pub struct RangeSync {
     /// finalized chain(s) complete, these peer's get STATUS'ed to update their head slot before
     /// the head chains are formed and downloaded.
     awaiting_head_peers: HashSet<PeerId>,
+    /// Where the target and progress of the sync are saved for a restart.
+    network_dir: PathBuf,
+    /// The target was restored from a checkpoint, peers with a lower head don't lower it while a
+    /// connected peer reaches it.
+    resumed: bool,
+    /// Heads reported by the peers added to the sync.
+    peer_heads: HashMap<PeerId, (u64, Hash256)>,
     /// The syncing logger.
     log: slog::Logger,
 }
 
 impl RangeSync {
     pub fn new(
-        block_chain: Arc<RwLock<BlockChain>>,
+        block_chain: Arc<TimedRwLock<BlockChain>>,
+        importer: ImportQueue,
+        healer: HealQueue,
         sync_send: mpsc::UnboundedSender<SyncMessage>,
+        config: RangeSyncConfig,
+        network_dir: PathBuf,
         log: slog::Logger,
     ) -> Self {
         let current = block_chain.read().unwrap().current_block().height();
-        let h = Hash256([0u8; 32]);
+        let (target_height, target_root) = match SyncCheckpoint::load(&network_dir) {
+            Some(checkpoint) if checkpoint.target_height > current => {
+                info!(log, "Resuming range sync"; "target_slot" => checkpoint.target_height, "local_slot" => current);
+                (checkpoint.target_height, checkpoint.target_root)
+            }
+            _ => (0, Hash256([0u8; 32])),
+        };
         RangeSync {
             chain: block_chain.clone(),
-            chains: SyncingChain::new(current, 0, h, sync_send.clone(), block_chain, log.clone()),
+            chains: SyncingChain::new(current, target_height, target_root, sync_send.clone(), block_chain, importer, healer, config, log.clone()),
             awaiting_head_peers: HashSet::new(),
+            network_dir,
+            resumed: target_height > 0,
+            peer_heads: HashMap::new(),
             log,
         }
     }
@@ -75,9 +101,13 @@ impl RangeSync {
         // earlier finalized chain from reaching here).
         debug!(self.log, "New peer added for sync"; "head_root" => format!("{}",remote.head_root), "head_slot" => remote.finalized_number, "peer_id" => format!("{:?}", peer_id));
 
-        // add the peer to the head's pool
-        self.chains.target_head_slot = remote.finalized_number;
-        self.chains.target_head_root = remote.finalized_root;
+        // add the peer to the head's pool, a resumed target is only raised
+        self.peer_heads.insert(peer_id.clone(), (remote.finalized_number, remote.finalized_root));
+        self.check_resumed_target();
+        if !self.resumed || remote.finalized_number > self.chains.target_head_slot {
+            self.chains.target_head_slot = remote.finalized_number;
+            self.chains.target_head_root = remote.finalized_root;
+        }
         self.chains.add_peer(network, peer_id);
         let local = self.chain.read().unwrap().current_block().height();
         self.chains.start_syncing(network, local);
@@ -126,9 +156,11 @@ impl RangeSync {
                 // the chain is complete, re-status it's peers
                 self.chains.status_peers(network);
                 self.chains.state = ChainSyncingState::Stopped;
+                self.resumed = false;
+                SyncCheckpoint::remove(&self.network_dir);
                 debug!(self.log, "remove chain"; "id" => *batch_id);
             }
-            Some(ProcessingResult::KeepChain) => {}
+            Some(ProcessingResult::KeepChain) => self.save_checkpoint(),
             None => {
                 match self.chains.on_batch_process_result(
                     network,
@@ -170,11 +202,29 @@ impl RangeSync {
         self.chains.start_syncing(network, local);
     }
 
+    /// A `BlocksByRange` request failed, its batch is requested again from another peer.
+    pub fn inject_error(&mut self, network: &mut SyncNetworkContext, peer_id: PeerId, request_id: RequestId) {
+        match self.chains.inject_error(network, &peer_id, request_id) {
+            Some(ProcessingResult::RemoveChain) => self.stop_chain(network),
+            Some(ProcessingResult::KeepChain) => {}
+            None => {
+                debug!(self.log, "Range error without matching request"; "peer" => format!("{:?}", peer_id), "request_id" => request_id);
+            }
+        }
+    }
+
     /// A peer has disconnected. This removes the peer from any ongoing chains and mappings. A
     /// disconnected peer could remove a chain
     pub fn peer_disconnect(&mut self, network: &mut SyncNetworkContext, peer_id: &PeerId) {
         // if the peer is in the awaiting head mapping, remove it
         self.awaiting_head_peers.remove(&peer_id);
+        self.peer_heads.remove(peer_id);
+        if self.check_resumed_target() {
+            if let Some((height, root)) = self.peer_heads.values().max_by_key(|(height, _)| *height) {
+                self.chains.target_head_slot = *height;
+                self.chains.target_head_root = *root;
+            }
+        }
 
         // remove the peer from any peer pool
         self.remove_peer(network, peer_id);
@@ -183,5 +233,46 @@ impl RangeSync {
     /// When a peer gets removed, both the head and finalized chains need to be searched to check which pool the peer is in. The chain may also have a batch or batches awaiting
     /// for this peer. If so we mark the batch as failed. The batch may then hit it's maximum
     /// retries. In this case, we need to remove the chain and re-status all the peers.
-    fn remove_peer(&mut self, network: &mut SyncNetworkContext, peer_id: &PeerId) {}
+    fn remove_peer(&mut self, network: &mut SyncNetworkContext, peer_id: &PeerId) {
+        if let ProcessingResult::RemoveChain = self.chains.remove_peer(network, peer_id) {
+            self.stop_chain(network);
+        }
+    }
+
+    /// Stop a chain whose batches can't be downloaded and re-status its peers, the sync starts
+    /// over from the local head with the peers that answer.
+    fn stop_chain(&mut self, network: &mut SyncNetworkContext) {
+        self.chains.status_peers(network);
+        self.chains.state = ChainSyncingState::Stopped;
+    }
+
+    /// A resumed target is only a hint, forget it once no connected peer reaches it. Returns
+    /// whether it was dropped.
+    fn check_resumed_target(&mut self) -> bool {
+        let target = self.chains.target_head_slot;
+        if !self.resumed || self.peer_heads.values().any(|(height, _)| *height >= target) {
+            return false;
+        }
+        info!(self.log, "Dropping the resumed sync target, no connected peer reaches it"; "target_slot" => target);
+        self.resumed = false;
+        SyncCheckpoint::remove(&self.network_dir);
+        true
+    }
+
+    /// Save the target, to resume after a restart.
+    fn save_checkpoint(&self) {
+        let checkpoint = SyncCheckpoint::new(self.chains.target_head_root, self.chains.target_head_slot);
+        if let Err(e) = checkpoint.save(&self.network_dir) {
+            warn!(self.log, "Could not save the sync checkpoint"; "error" => format!("{}", e));
+        }
+    }
+}
+
+/// The sync manager is dropped on shutdown, the last processed batches are kept.
+impl Drop for RangeSync {
+    fn drop(&mut self) {
+        if self.is_syncing() {
+            self.save_checkpoint();
+        }
+    }
 }
@@ -0,0 +1,252 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offset of the local clock to the clock of each peer, estimated from `TimeSync` exchanges the
+//! way NTP does, and their median over the peers, to tell slots missed because of a drifting
+//! clock from slots missed because of the network.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use libp2p::PeerId;
+use serde::Serialize;
+
+use crate::metrics;
+use crate::p2p::methods::TimeSyncResponse;
+
+/// An aggregated offset larger than this, in either direction, is reported as clock skew.
+pub const MAX_CLOCK_SKEW_MS: u64 = 500;
+/// Least peers with an estimate before the aggregated offset is trusted.
+pub const MIN_PEERS: usize = 3;
+/// Time after which the estimate of a peer is refreshed.
+pub const RESYNC_INTERVAL_MS: u64 = 5 * 60 * 1000;
+/// Wait before asking again a peer that didn't answer, doubled on each unanswered request up to
+/// `MAX_RETRY_MS`, e.g. for peers without `map_time_sync`.
+pub const RETRY_MS: u64 = 30 * 1000;
+pub const MAX_RETRY_MS: u64 = 60 * 60 * 1000;
+/// Samples kept per peer, the one with the lowest round trip is the estimate.
+const SAMPLES: usize = 8;
+
+pub type SharedClockOffsets = Arc<RwLock<ClockOffsets>>;
+
+/// One `TimeSync` exchange.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sample {
+    offset_ms: i64,
+    rtt_ms: u64,
+}
+
+impl Sample {
+    /// Estimate from the request sent at `t1`, received by the peer at `t2`, answered at `t3`
+    /// and the response received at `t4`, `t2` and `t3` being times of the peer's clock.
+    fn new(t1: u64, t2: u64, t3: u64, t4: u64) -> Self {
+        let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+        Sample {
+            offset_ms: ((t2 - t1) + (t3 - t4)) / 2,
+            rtt_ms: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+        }
+    }
+}
+
+/// Clock estimate of a connected peer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerClock {
+    pub peer: String,
+    /// Peer clock minus the local clock, of the sample with the lowest round trip.
+    pub offset_ms: i64,
+    pub rtt_ms: u64,
+    pub samples: u64,
+    /// Local unix time of the latest sample.
+    pub updated_ms: u64,
+    #[serde(skip)]
+    recent: VecDeque<Sample>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ClockReport {
+    /// Median offset of the peers, None until `MIN_PEERS` have an estimate. A positive offset
+    /// means the local clock is behind.
+    pub offset_ms: Option<i64>,
+    pub skewed: bool,
+    pub peers: Vec<PeerClock>,
+}
+
+/// Change of the aggregated offset against `MAX_CLOCK_SKEW_MS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkewChange {
+    Skewed(i64),
+    Recovered(i64),
+}
+
+/// `TimeSync` requests sent to a peer since its last response.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Pending {
+    sent_ms: u64,
+    unanswered: u32,
+}
+
+impl Pending {
+    fn retry_ms(&self) -> u64 {
+        match self.unanswered {
+            0 => 0,
+            n => RETRY_MS.saturating_mul(1 << (n - 1).min(32)).min(MAX_RETRY_MS),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ClockOffsets {
+    peers: HashMap<PeerId, PeerClock>,
+    requests: HashMap<PeerId, Pending>,
+    skewed: bool,
+}
+
+impl ClockOffsets {
+    /// Record the response of `peer` to a `TimeSync` request, received at `received_ms`.
+    /// Returns the change if the aggregated offset crossed `MAX_CLOCK_SKEW_MS`.
+    pub fn on_response(&mut self, peer: &PeerId, response: &TimeSyncResponse, received_ms: u64) -> Option<SkewChange> {
+        self.requests.remove(peer);
+        let sample = Sample::new(response.request_sent_ms, response.received_ms, response.sent_ms, received_ms);
+        let clock = self.peers.entry(peer.clone()).or_insert_with(|| PeerClock {
+            peer: peer.to_base58(),
+            ..Default::default()
+        });
+        if clock.recent.len() == SAMPLES {
+            clock.recent.pop_front();
+        }
+        clock.recent.push_back(sample);
+        let best = clock.recent.iter().min_by_key(|s| s.rtt_ms).copied().unwrap_or(sample);
+        clock.offset_ms = best.offset_ms;
+        clock.rtt_ms = best.rtt_ms;
+        clock.samples += 1;
+        clock.updated_ms = received_ms;
+        self.check_skew()
+    }
+
+    /// Record a `TimeSync` request sent to `peer` at `now_ms`.
+    pub fn on_request(&mut self, peer: &PeerId, now_ms: u64) {
+        let pending = self.requests.entry(peer.clone()).or_default();
+        pending.sent_ms = now_ms;
+        pending.unanswered = pending.unanswered.saturating_add(1);
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.requests.remove(peer);
+    }
+
+    /// True if `peer` has no estimate or it is older than `RESYNC_INTERVAL_MS`, and it answered
+    /// our last request or we waited long enough since it didn't.
+    pub fn sync_due(&self, peer: &PeerId, now_ms: u64) -> bool {
+        let fresh = self.peers.get(peer).map_or(false, |clock| now_ms.saturating_sub(clock.updated_ms) < RESYNC_INTERVAL_MS);
+        let waiting = self.requests.get(peer).map_or(false, |pending| now_ms.saturating_sub(pending.sent_ms) < pending.retry_ms());
+        !fresh && !waiting
+    }
+
+    /// Median of the peer offsets.
+    pub fn offset_ms(&self) -> Option<i64> {
+        if self.peers.len() < MIN_PEERS {
+            return None;
+        }
+        let mut offsets: Vec<i64> = self.peers.values().map(|clock| clock.offset_ms).collect();
+        offsets.sort();
+        Some(offsets[offsets.len() / 2])
+    }
+
+    fn check_skew(&mut self) -> Option<SkewChange> {
+        let offset = self.offset_ms()?;
+        metrics::set_gauge(&metrics::CLOCK_OFFSET_MS, offset);
+        let skewed = offset.abs() as u64 > MAX_CLOCK_SKEW_MS;
+        metrics::set_gauge(&metrics::CLOCK_SKEWED, skewed as i64);
+        if skewed == self.skewed {
+            return None;
+        }
+        self.skewed = skewed;
+        Some(if skewed { SkewChange::Skewed(offset) } else { SkewChange::Recovered(offset) })
+    }
+
+    pub fn report(&self) -> ClockReport {
+        let mut peers: Vec<PeerClock> = self.peers.values().cloned().collect();
+        peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+        ClockReport {
+            offset_ms: self.offset_ms(),
+            skewed: self.skewed,
+            peers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(t1: u64, offset: u64, one_way: u64) -> TimeSyncResponse {
+        TimeSyncResponse {
+            request_sent_ms: t1,
+            received_ms: t1 + one_way + offset,
+            sent_ms: t1 + one_way + offset + 1,
+        }
+    }
+
+    #[test]
+    fn estimate_and_skew() {
+        let peers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        let mut offsets = ClockOffsets::default();
+
+        // Peer clock 1000ms ahead, 40ms each way
+        assert_eq!(offsets.on_response(&peers[0], &response(10_000, 1000, 40), 10_081), None);
+        let clock = &offsets.report().peers[0];
+        assert_eq!((clock.offset_ms, clock.rtt_ms), (1000, 80));
+        // A slower exchange doesn't replace the estimate
+        offsets.on_response(&peers[0], &response(20_000, 1000, 300), 20_601);
+        assert_eq!(offsets.report().peers[0].rtt_ms, 80);
+        assert_eq!(offsets.offset_ms(), None);
+
+        assert_eq!(offsets.on_response(&peers[1], &response(10_000, 900, 10), 10_021), None);
+        assert_eq!(offsets.on_response(&peers[2], &response(10_000, 0, 10), 10_021), Some(SkewChange::Skewed(900)));
+        assert!(offsets.report().skewed);
+
+        offsets.remove_peer(&peers[0]);
+        let fourth = PeerId::random();
+        assert_eq!(offsets.on_response(&fourth, &response(10_000, 0, 10), 10_021), Some(SkewChange::Recovered(0)));
+
+        assert!(!offsets.sync_due(&fourth, 10_021 + RESYNC_INTERVAL_MS - 1));
+        assert!(offsets.sync_due(&fourth, 10_021 + RESYNC_INTERVAL_MS));
+        assert!(offsets.sync_due(&peers[0], 0));
+    }
+
+    #[test]
+    fn unanswered_backoff() {
+        let peer = PeerId::random();
+        let mut offsets = ClockOffsets::default();
+        offsets.on_request(&peer, 0);
+        assert!(!offsets.sync_due(&peer, RETRY_MS - 1));
+        assert!(offsets.sync_due(&peer, RETRY_MS));
+        offsets.on_request(&peer, RETRY_MS);
+        assert!(!offsets.sync_due(&peer, 3 * RETRY_MS - 1));
+        assert!(offsets.sync_due(&peer, 3 * RETRY_MS));
+        for _ in 0..40 {
+            offsets.on_request(&peer, 0);
+        }
+        assert!(!offsets.sync_due(&peer, MAX_RETRY_MS - 1));
+        assert!(offsets.sync_due(&peer, MAX_RETRY_MS));
+
+        // An answer ends the backoff
+        offsets.on_response(&peer, &response(0, 0, 10), 21);
+        assert!(offsets.sync_due(&peer, 21 + RESYNC_INTERVAL_MS));
+        offsets.remove_peer(&peer);
+        assert!(offsets.sync_due(&peer, 0));
+    }
+}
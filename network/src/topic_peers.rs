@@ -0,0 +1,212 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Gossip topics each connected peer subscribed to. Gossipsub builds the mesh of a topic from
+//! its subscribers, so their count bounds the mesh, and a block topic left without subscribers
+//! means no block reaches us by gossip however healthy the connections look.
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use libp2p::PeerId;
+use serde::Serialize;
+
+use crate::metrics;
+use crate::topics::GossipTopic;
+
+/// Fewer block topic subscribers than this is reported as a thin mesh.
+pub const MIN_BLOCK_TOPIC_PEERS: usize = 2;
+
+/// Time after the start a node has to find its block topic peers before a thin mesh is reported.
+pub const MESH_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+pub type SharedTopicPeers = Arc<RwLock<TopicPeers>>;
+
+/// Change of the block topic subscribers against `MIN_BLOCK_TOPIC_PEERS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshChange {
+    Thin(usize),
+    Recovered(usize),
+}
+
+/// Connected subscribers of a topic.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TopicReport {
+    pub topic: String,
+    pub peers: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TopicPeersReport {
+    /// True while the block topic has fewer than `MIN_BLOCK_TOPIC_PEERS` subscribers, after it
+    /// had enough of them once or the grace period after the start ended.
+    pub block_mesh_thin: bool,
+    pub topics: Vec<TopicReport>,
+}
+
+#[derive(Default)]
+pub struct TopicPeers {
+    topics: BTreeMap<String, BTreeSet<PeerId>>,
+    /// None until the block topic first reaches `MIN_BLOCK_TOPIC_PEERS` or `end_grace`, so a
+    /// node still finding its peers isn't reported.
+    thin: Option<bool>,
+}
+
+/// Only the topics of this node are tracked, peers pick the names of the others freely.
+fn is_known(topic: &str) -> bool {
+    match GossipTopic::from(topic) {
+        GossipTopic::Unknown(_) => false,
+        _ => true,
+    }
+}
+
+impl TopicPeers {
+    pub fn on_subscribed(&mut self, peer: &PeerId, topic: &str) -> Option<MeshChange> {
+        if !is_known(topic) {
+            return None;
+        }
+        self.topics.entry(topic.to_string()).or_insert_with(BTreeSet::new).insert(peer.clone());
+        self.update(topic)
+    }
+
+    pub fn on_unsubscribed(&mut self, peer: &PeerId, topic: &str) -> Option<MeshChange> {
+        if let Some(peers) = self.topics.get_mut(topic) {
+            peers.remove(peer);
+        }
+        self.update(topic)
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) -> Option<MeshChange> {
+        let topics: Vec<String> = self.topics.iter_mut()
+            .filter_map(|(topic, peers)| if peers.remove(peer) { Some(topic.clone()) } else { None })
+            .collect();
+        topics.iter().fold(None, |change, topic| self.update(topic).or(change))
+    }
+
+    /// End of the grace period after the start, a block topic which never had enough
+    /// subscribers is reported as thin from now on.
+    pub fn end_grace(&mut self) -> Option<MeshChange> {
+        if self.thin.is_some() {
+            return None;
+        }
+        let block: String = GossipTopic::MapBlock.into();
+        let count = self.peer_count(&block);
+        let thin = count < MIN_BLOCK_TOPIC_PEERS;
+        self.thin = Some(thin);
+        if thin {
+            Some(MeshChange::Thin(count))
+        } else {
+            None
+        }
+    }
+
+    /// Connected subscribers of `topic`.
+    pub fn peer_count(&self, topic: &str) -> usize {
+        self.topics.get(topic).map_or(0, |peers| peers.len())
+    }
+
+    fn update(&mut self, topic: &str) -> Option<MeshChange> {
+        let count = self.peer_count(topic);
+        if let Some(gauge) = metrics::get_int_gauge(&metrics::GOSSIP_TOPIC_PEERS, &[topic]) {
+            gauge.set(count as i64);
+        }
+        if count == 0 {
+            self.topics.remove(topic);
+        }
+        if let GossipTopic::MapBlock = GossipTopic::from(topic) {
+            let thin = count < MIN_BLOCK_TOPIC_PEERS;
+            match (self.thin, thin) {
+                (None, true) => {}
+                (None, false) => self.thin = Some(false),
+                (Some(was), _) if was == thin => {}
+                (Some(_), true) => {
+                    self.thin = Some(true);
+                    return Some(MeshChange::Thin(count));
+                }
+                (Some(_), false) => {
+                    self.thin = Some(false);
+                    return Some(MeshChange::Recovered(count));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn report(&self) -> TopicPeersReport {
+        TopicPeersReport {
+            block_mesh_thin: self.thin == Some(true),
+            topics: self.topics.iter()
+                .map(|(topic, peers)| TopicReport {
+                    topic: topic.clone(),
+                    peers: peers.iter().map(|peer| peer.to_base58()).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_topic_thins() {
+        let block: String = GossipTopic::MapBlock.into();
+        let tx: String = GossipTopic::Transaction.into();
+        let peers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        let mut topics = TopicPeers::default();
+
+        // Not reported before the mesh first had enough peers
+        assert_eq!(topics.on_subscribed(&peers[0], &block), None);
+        assert_eq!(topics.on_unsubscribed(&peers[0], &block), None);
+        assert_eq!(topics.on_subscribed(&peers[0], &block), None);
+        assert_eq!(topics.on_subscribed(&peers[1], &block), None);
+        assert_eq!(topics.on_subscribed(&peers[1], &tx), None);
+        assert_eq!(topics.peer_count(&block), 2);
+
+        assert_eq!(topics.remove_peer(&peers[1]), Some(MeshChange::Thin(1)));
+        assert_eq!(topics.peer_count(&tx), 0);
+        assert!(topics.report().block_mesh_thin);
+        assert_eq!(topics.on_subscribed(&peers[2], &block), Some(MeshChange::Recovered(2)));
+
+        let report = topics.report();
+        assert!(!report.block_mesh_thin);
+        assert_eq!(report.topics.len(), 1);
+        assert_eq!(report.topics[0].topic, block);
+        assert_eq!(report.topics[0].peers.len(), 2);
+    }
+
+    #[test]
+    fn unknown_topics() {
+        let peer = PeerId::random();
+        let mut topics = TopicPeers::default();
+        assert_eq!(topics.on_subscribed(&peer, "/map/spam/bin"), None);
+        assert_eq!(topics.on_subscribed(&peer, "anything"), None);
+        assert_eq!(topics.peer_count("anything"), 0);
+        assert!(topics.report().topics.is_empty());
+    }
+
+    #[test]
+    fn isolated_from_start() {
+        let block: String = GossipTopic::MapBlock.into();
+        let mut topics = TopicPeers::default();
+        assert_eq!(topics.on_subscribed(&PeerId::random(), &block), None);
+        assert_eq!(topics.end_grace(), Some(MeshChange::Thin(1)));
+        assert!(topics.report().block_mesh_thin);
+        assert_eq!(topics.end_grace(), None);
+        assert_eq!(topics.on_subscribed(&PeerId::random(), &block), Some(MeshChange::Recovered(2)));
+    }
+}
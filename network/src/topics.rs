@@ -8,6 +8,7 @@ pub const TOPIC_PREFIX: &str = "map";
 pub const TOPIC_ENCODING_POSTFIX: &str = "bin";
 pub const MAP_BLOCK_TOPIC: &str = "block";
 pub const MAP_TRANSACTION_TOPIC: &str = "transaction";
+pub const MAP_BAD_BLOCK_TOPIC: &str = "bad_block";
 pub const SHARD_TOPIC_PREFIX: &str = "shard";
 
 /// Enum that brings these topics into the rust type system.
@@ -15,6 +16,8 @@ pub const SHARD_TOPIC_PREFIX: &str = "shard";
 pub enum GossipTopic {
     MapBlock,
     Transaction,
+    /// Hashes of gossiped blocks that failed execution.
+    BadBlock,
     Shard,
     Unknown(String),
 }
@@ -29,6 +32,7 @@ impl From<&str > for GossipTopic {
             match topic_parts[2] {
                 MAP_BLOCK_TOPIC => GossipTopic::MapBlock,
                 MAP_TRANSACTION_TOPIC => GossipTopic::Transaction,
+                MAP_BAD_BLOCK_TOPIC => GossipTopic::BadBlock,
                 unknown_topic => GossipTopic::Unknown(unknown_topic.into()),
             }
         } else {
@@ -48,6 +52,7 @@ impl Into<String> for GossipTopic {
         match self {
             GossipTopic::MapBlock => topic_builder(MAP_BLOCK_TOPIC),
             GossipTopic::Transaction => topic_builder(MAP_TRANSACTION_TOPIC),
+            GossipTopic::BadBlock => topic_builder(MAP_BAD_BLOCK_TOPIC),
             GossipTopic::Shard => topic_builder(SHARD_TOPIC_PREFIX),
             GossipTopic::Unknown(topic) => topic,
         }
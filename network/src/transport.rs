@@ -1,16 +1,57 @@
 use std::{{io::{Error, ErrorKind}}, time::Duration};
+use std::collections::HashSet;
+use std::sync::Arc;
 
 use libp2p::core::{
     identity::Keypair,
     muxing::StreamMuxerBox,
-    transport::boxed::Boxed,
+    transport::{boxed::Boxed, MemoryTransport},
 };
-use libp2p::{core, PeerId, secio, Transport};
+use libp2p::{core, noise, PeerId, secio, Transport};
+
+use crate::config::TransportSecurity;
+
+/// Peers allowed to connect, None for any peer.
+type Allowlist = Option<Arc<HashSet<PeerId>>>;
+
+/// Secures, multiplexes and filters the connections of a raw transport with the protocol
+/// selected by `$security`.
+macro_rules! upgrade {
+    ($transport:expr, $private_key:expr, $security:expr, $allowed:expr) => {{
+        let allowed: Allowlist = $allowed;
+        let check = move |(peer, muxer), _| check_allowed(&allowed, peer, muxer);
+        match $security {
+            TransportSecurity::Secio => $transport
+                .upgrade(core::upgrade::Version::V1)
+                .authenticate(secio::SecioConfig::new($private_key))
+                .multiplex(core::upgrade::SelectUpgrade::new(
+                    libp2p::yamux::Config::default(),
+                    libp2p::mplex::MplexConfig::new(),
+                ))
+                .map(|(peer, muxer), _| (peer, core::muxing::StreamMuxerBox::new(muxer)))
+                .and_then(check)
+                .timeout(Duration::from_secs(20))
+                .map_err(|err| Error::new(ErrorKind::Other, err))
+                .boxed(),
+            TransportSecurity::Noise => $transport
+                .upgrade(core::upgrade::Version::V1)
+                .authenticate(noise::NoiseConfig::xx(noise_keys(&$private_key)).into_authenticated())
+                .multiplex(core::upgrade::SelectUpgrade::new(
+                    libp2p::yamux::Config::default(),
+                    libp2p::mplex::MplexConfig::new(),
+                ))
+                .map(|(peer, muxer), _| (peer, core::muxing::StreamMuxerBox::new(muxer)))
+                .and_then(check)
+                .timeout(Duration::from_secs(20))
+                .map_err(|err| Error::new(ErrorKind::Other, err))
+                .boxed(),
+        }
+    }};
+}
 
 /// Builds the transport that serves as a common ground for all connections.
-pub fn build_transport(private_key: Keypair) -> Boxed<(PeerId, StreamMuxerBox), Error> {
-    // TODO: The Wire protocol currently doesn't specify encryption and this will need to be customised
-    // in the future.
+pub fn build_transport(private_key: Keypair, security: TransportSecurity, allowed_peers: &[PeerId])
+    -> Boxed<(PeerId, StreamMuxerBox), Error> {
     let transport = libp2p::tcp::TcpConfig::new().nodelay(true);
     let transport = libp2p::dns::DnsConfig::new(transport);
     #[cfg(feature = "libp2p-websocket")]
@@ -18,16 +59,51 @@ pub fn build_transport(private_key: Keypair) -> Boxed<(PeerId, StreamMuxerBox),
         let trans_clone = transport.clone();
         transport.or_transport(websocket::WsConfig::new(trans_clone))
     };
-    transport
-        .upgrade(core::upgrade::Version::V1)
-        .authenticate(secio::SecioConfig::new(private_key))
-        .multiplex(core::upgrade::SelectUpgrade::new(
-            libp2p::yamux::Config::default(),
-            libp2p::mplex::MplexConfig::new(),
-        ))
-        .map(|(peer, muxer), _| (peer, core::muxing::StreamMuxerBox::new(muxer)))
-        .timeout(Duration::from_secs(20))
-        .timeout(Duration::from_secs(20))
-        .map_err(|err| Error::new(ErrorKind::Other, err))
-        .boxed()
+    upgrade!(transport, private_key, security, allowlist(allowed_peers))
+}
+
+/// Builds a transport connecting nodes of the same process over `/memory/<port>` addresses.
+pub fn build_memory_transport(private_key: Keypair, security: TransportSecurity, allowed_peers: &[PeerId])
+    -> Boxed<(PeerId, StreamMuxerBox), Error> {
+    upgrade!(MemoryTransport::default(), private_key, security, allowlist(allowed_peers))
+}
+
+fn allowlist(allowed_peers: &[PeerId]) -> Allowlist {
+    if allowed_peers.is_empty() {
+        None
+    } else {
+        Some(Arc::new(allowed_peers.iter().cloned().collect()))
+    }
+}
+
+/// Static X25519 keys of the noise handshake, signed by the node key to prove the peer id.
+fn noise_keys(private_key: &Keypair) -> noise::AuthenticKeypair<noise::X25519> {
+    noise::Keypair::<noise::X25519>::new()
+        .into_authentic(private_key)
+        .expect("signing noise keys with the node key")
+}
+
+/// Drop an authenticated connection of a peer missing from the allowlist.
+fn check_allowed<T>(allowed: &Allowlist, peer: PeerId, muxer: T) -> Result<(PeerId, T), Error> {
+    match allowed {
+        Some(allowed) if !allowed.contains(&peer) =>
+            Err(Error::new(ErrorKind::PermissionDenied, format!("peer {} is not in the allowlist", peer.to_base58()))),
+        _ => Ok((peer, muxer)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_check() {
+        let (allowed, other) = (PeerId::random(), PeerId::random());
+        let list = allowlist(&[allowed.clone()]);
+        assert!(allowlist(&[]).is_none());
+        assert!(check_allowed(&list, allowed, ()).is_ok());
+        let err = check_allowed(&list, other, ()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(check_allowed(&None, PeerId::random(), ()).is_ok());
+    }
 }
@@ -0,0 +1,149 @@
+//! Golden vectors of the encodings peers and existing datadirs depend on.
+//!
+//! Each vector is the encoding of a canonical value, pinned as hex in `vectors/encoding.txt`.
+//! A test fails when an encoding no longer matches its fixture, so format changes are caught
+//! before they reach the network. After an intended change, add a new wire version if peers
+//! are affected and regenerate the fixture with `map encode-vector`.
+use ed25519::signature::SignatureInfo;
+use map_core::block::{Block, BlockProof, Header, VerificationItem, VRFProof};
+use map_core::transaction::Transaction;
+use map_core::types::{Address, Hash};
+
+use crate::p2p::StatusMessage;
+use crate::wire;
+
+/// Transaction of the vectors, with every field set.
+pub fn canonical_transaction() -> Transaction {
+    Transaction {
+        sender: Address([0x11; 20]),
+        nonce: 7,
+        gas_price: 1000,
+        gas: 21000,
+        call: vec![0x01, 0x02],
+        data: vec![0xaa, 0xbb, 0xcc],
+        sign_data: ([0x21; 32], [0x22; 32], [0x23; 32]),
+    }
+}
+
+/// Block of the vectors, holding one signature, transaction and proof.
+pub fn canonical_block() -> Block {
+    let mut vrf_proof = [0x03; 64];
+    vrf_proof[32..].copy_from_slice(&[0x04; 32]);
+    Block {
+        header: Header {
+            height: 42,
+            parent_hash: Hash([0x01; 32]),
+            slot: 45,
+            vrf_output: [0x02; 32],
+            vrf_proof: VRFProof::new(vrf_proof),
+            tx_root: Hash([0x05; 32]),
+            sign_root: Hash([0x06; 32]),
+            state_root: Hash([0x07; 32]),
+            time: 1_600_000_000,
+        },
+        signs: vec![VerificationItem::new(Hash([0x08; 32]), SignatureInfo::make([0x09; 32], [0x0a; 32], [0x0b; 32]))],
+        txs: vec![canonical_transaction()],
+        proofs: vec![BlockProof([0x0c; 32], [0x0d; 32], 1)],
+    }
+}
+
+/// Status handshake of the vectors.
+pub fn canonical_status() -> StatusMessage {
+    StatusMessage {
+        genesis_hash: Hash([0x31; 32]),
+        finalized_root: Hash([0x32; 32]),
+        finalized_number: 42,
+        head_root: Hash([0x33; 32]),
+        network_id: 31133,
+        spec_hash: Hash([0x34; 32]),
+        agent: "map/0.1.0".to_string(),
+    }
+}
+
+/// Name and bytes of each vector: the wire format sent to peers and the bincode written to
+/// the chain db and the transaction journal.
+pub fn vectors() -> Vec<(&'static str, Vec<u8>)> {
+    let block = canonical_block();
+    let tx = canonical_transaction();
+    vec![
+        ("block/wire", wire::encode_block(&block)),
+        ("block/store", bincode::serialize(&block).expect("serialize block")),
+        ("transaction/wire", wire::encode_transaction(&tx)),
+        ("transaction/store", bincode::serialize(&tx).expect("serialize transaction")),
+        ("status", bincode::serialize(&canonical_status()).expect("serialize status")),
+    ]
+}
+
+/// The fixture file contents, one `name hex` line per vector.
+pub fn render() -> String {
+    let mut out = String::from("# Generated by `map encode-vector`, see network/src/vectors.rs\n");
+    for (name, bytes) in vectors() {
+        out.push_str(&format!("{} {}\n", name, hex::encode(bytes)));
+    }
+    out
+}
+
+/// Parse the fixture file into name and hex pairs, skipping comments.
+pub fn parse(fixture: &str) -> Vec<(String, String)> {
+    fixture.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../vectors/encoding.txt");
+
+    fn fixture_hex(name: &str) -> String {
+        parse(FIXTURE).into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, hex)| hex)
+            .unwrap_or_else(|| panic!("no vector {} in the fixture", name))
+    }
+
+    fn fixture_bytes(name: &str) -> Vec<u8> {
+        hex::decode(fixture_hex(name)).unwrap()
+    }
+
+    #[test]
+    fn encodings_match_fixture() {
+        let fixture = parse(FIXTURE);
+        assert_eq!(fixture.len(), vectors().len());
+        for (name, bytes) in vectors() {
+            assert_eq!(hex::encode(bytes), fixture_hex(name), "encoding of {} changed, see network/src/vectors.rs", name);
+        }
+    }
+
+    #[test]
+    fn fixture_decodes() {
+        let block = canonical_block();
+        assert_eq!(wire::decode_block(&fixture_bytes("block/wire")).unwrap(), block);
+        assert_eq!(bincode::deserialize::<Block>(&fixture_bytes("block/store")).unwrap(), block);
+
+        let tx = canonical_transaction();
+        assert_eq!(wire::decode_transaction(&fixture_bytes("transaction/wire")).unwrap(), tx);
+        assert_eq!(bincode::deserialize::<Transaction>(&fixture_bytes("transaction/store")).unwrap(), tx);
+
+        assert_eq!(bincode::deserialize::<StatusMessage>(&fixture_bytes("status")).unwrap(), canonical_status());
+    }
+
+    #[test]
+    fn fixture_is_rendered() {
+        // Written by `map encode-vector` as is, never edited by hand
+        assert_eq!(render(), FIXTURE, "regenerate with `map encode-vector > network/vectors/encoding.txt`");
+    }
+
+    #[test]
+    fn render_parses_back() {
+        let parsed = parse(&render());
+        let names: Vec<&str> = parsed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["block/wire", "block/store", "transaction/wire", "transaction/store", "status"]);
+    }
+}
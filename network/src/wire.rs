@@ -0,0 +1,255 @@
+//! Versioned wire format of blocks and transactions.
+//!
+//! Payloads start with a version byte followed by the bincode of the wire struct of that version.
+//! Wire structs are independent of the core types, so changing a core struct doesn't change
+//! what is sent to peers. A new version adds its own structs and a branch in the decoders.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use ed25519::signature::SignatureInfo;
+use map_core::block::{Block, BlockProof, Header, VerificationItem, VRFProof};
+use map_core::transaction::Transaction;
+use map_core::types::{Address, Hash};
+
+pub const WIRE_V1: u8 = 1;
+/// Version used for encoding.
+pub const WIRE_VERSION: u8 = WIRE_V1;
+
+#[derive(Debug)]
+pub enum WireError {
+    Empty,
+    UnsupportedVersion(u8),
+    Decode(bincode::Error),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::Empty => write!(f, "empty payload"),
+            WireError::UnsupportedVersion(v) => write!(f, "unsupported wire version {}", v),
+            WireError::Decode(e) => write!(f, "malformed payload: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeaderV1 {
+    pub height: u64,
+    pub parent_hash: [u8; 32],
+    pub slot: u64,
+    pub vrf_output: [u8; 32],
+    pub vrf_proof: ([u8; 32], [u8; 32]),
+    pub tx_root: [u8; 32],
+    pub sign_root: [u8; 32],
+    pub state_root: [u8; 32],
+    pub time: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignatureV1 {
+    pub msg: [u8; 32],
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    pub pubkey: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockProofV1 {
+    pub kind: u8,
+    pub data: ([u8; 32], [u8; 32]),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionV1 {
+    pub sender: [u8; 20],
+    pub nonce: u64,
+    pub gas_price: u64,
+    pub gas: u64,
+    pub call: Vec<u8>,
+    pub data: Vec<u8>,
+    pub signature: ([u8; 32], [u8; 32], [u8; 32]),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockV1 {
+    pub header: HeaderV1,
+    pub signs: Vec<SignatureV1>,
+    pub txs: Vec<TransactionV1>,
+    pub proofs: Vec<BlockProofV1>,
+}
+
+fn array32(bytes: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(bytes);
+    out
+}
+
+impl From<&Header> for HeaderV1 {
+    fn from(h: &Header) -> Self {
+        let proof = h.vrf_proof.bytes();
+        HeaderV1 {
+            height: h.height,
+            parent_hash: h.parent_hash.0,
+            slot: h.slot,
+            vrf_output: h.vrf_output,
+            vrf_proof: (array32(&proof[..32]), array32(&proof[32..])),
+            tx_root: h.tx_root.0,
+            sign_root: h.sign_root.0,
+            state_root: h.state_root.0,
+            time: h.time,
+        }
+    }
+}
+
+impl From<HeaderV1> for Header {
+    fn from(h: HeaderV1) -> Self {
+        let mut proof = [0u8; 64];
+        proof[..32].copy_from_slice(&h.vrf_proof.0);
+        proof[32..].copy_from_slice(&h.vrf_proof.1);
+        Header {
+            height: h.height,
+            parent_hash: Hash(h.parent_hash),
+            slot: h.slot,
+            vrf_output: h.vrf_output,
+            vrf_proof: VRFProof::new(proof),
+            tx_root: Hash(h.tx_root),
+            sign_root: Hash(h.sign_root),
+            state_root: Hash(h.state_root),
+            time: h.time,
+        }
+    }
+}
+
+impl From<&Transaction> for TransactionV1 {
+    fn from(tx: &Transaction) -> Self {
+        TransactionV1 {
+            sender: tx.sender.0,
+            nonce: tx.nonce,
+            gas_price: tx.gas_price,
+            gas: tx.gas,
+            call: tx.call.clone(),
+            data: tx.data.clone(),
+            signature: tx.sign_data,
+        }
+    }
+}
+
+impl From<TransactionV1> for Transaction {
+    fn from(tx: TransactionV1) -> Self {
+        Transaction {
+            sender: Address(tx.sender),
+            nonce: tx.nonce,
+            gas_price: tx.gas_price,
+            gas: tx.gas,
+            call: tx.call,
+            data: tx.data,
+            sign_data: tx.signature,
+        }
+    }
+}
+
+impl From<&Block> for BlockV1 {
+    fn from(b: &Block) -> Self {
+        BlockV1 {
+            header: HeaderV1::from(&b.header),
+            signs: b.signs.iter().map(|item| SignatureV1 {
+                msg: item.msg.0,
+                r: array32(item.signs.r()),
+                s: array32(item.signs.s()),
+                pubkey: array32(item.signs.p()),
+            }).collect(),
+            txs: b.txs.iter().map(TransactionV1::from).collect(),
+            proofs: b.proofs.iter().map(|p| BlockProofV1 { kind: p.2, data: (p.0, p.1) }).collect(),
+        }
+    }
+}
+
+impl From<BlockV1> for Block {
+    fn from(b: BlockV1) -> Self {
+        Block {
+            header: Header::from(b.header),
+            signs: b.signs.into_iter()
+                .map(|s| VerificationItem::new(Hash(s.msg), SignatureInfo::make(s.r, s.s, s.pubkey)))
+                .collect(),
+            txs: b.txs.into_iter().map(Transaction::from).collect(),
+            proofs: b.proofs.into_iter().map(|p| BlockProof(p.data.0, p.data.1, p.kind)).collect(),
+        }
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut out = vec![WIRE_VERSION];
+    out.extend(bincode::serialize(value).expect("serialize wire message"));
+    out
+}
+
+fn split_version(data: &[u8]) -> Result<(u8, &[u8]), WireError> {
+    match data.split_first() {
+        Some((version, payload)) => Ok((*version, payload)),
+        None => Err(WireError::Empty),
+    }
+}
+
+pub fn encode_block(block: &Block) -> Vec<u8> {
+    encode(&BlockV1::from(block))
+}
+
+pub fn decode_block(data: &[u8]) -> Result<Block, WireError> {
+    match split_version(data)? {
+        (WIRE_V1, payload) => bincode::deserialize::<BlockV1>(payload)
+            .map(Block::from)
+            .map_err(WireError::Decode),
+        (version, _) => Err(WireError::UnsupportedVersion(version)),
+    }
+}
+
+pub fn encode_transaction(tx: &Transaction) -> Vec<u8> {
+    encode(&TransactionV1::from(tx))
+}
+
+pub fn decode_transaction(data: &[u8]) -> Result<Transaction, WireError> {
+    match split_version(data)? {
+        (WIRE_V1, payload) => bincode::deserialize::<TransactionV1>(payload)
+            .map(Transaction::from)
+            .map_err(WireError::Decode),
+        (version, _) => Err(WireError::UnsupportedVersion(version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::test_utils::{self, BlockBuilder};
+
+    #[test]
+    fn block_round_trip() {
+        let tx = test_utils::transfer(&test_utils::test_key(1), 3, test_utils::test_address(2), 10);
+        let block = BlockBuilder::on(&Block::default())
+            .vrf(7)
+            .txs(vec![tx.clone()])
+            .signed_by(test_utils::test_key(1))
+            .build();
+
+        let encoded = encode_block(&block);
+        assert_eq!(encoded[0], WIRE_V1);
+        let decoded = decode_block(&encoded).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+        assert_eq!(decoded.signs, block.signs);
+        assert_eq!(decoded.proofs, block.proofs);
+        assert_eq!(decoded.txs, vec![tx.clone()]);
+        assert_eq!(decode_transaction(&encode_transaction(&tx)).unwrap(), tx);
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut encoded = encode_block(&Block::default());
+        encoded[0] = 2;
+        match decode_block(&encoded) {
+            Err(WireError::UnsupportedVersion(2)) => {}
+            other => panic!("unexpected {:?}", other.map(|b| b.hash())),
+        }
+        assert!(match decode_transaction(&[]) { Err(WireError::Empty) => true, _ => false });
+        assert!(decode_transaction(&[WIRE_V1, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Lifecycle events of the pool transactions, sent to in-process subscribers and collected
+//! for polling filters, so that indexers can follow transactions without diffing the pool.
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+use map_core::types::Hash;
+
+use crate::tx_pool::TxStatus;
+
+/// Events buffered per subscriber or filter, a subscriber further behind misses the newer ones
+/// and a filter drops the oldest ones.
+pub const MAX_PENDING_EVENTS: usize = 1024;
+/// Max number of installed filters, no more are installed until one is removed or expires.
+pub const MAX_EVENT_FILTERS: usize = 64;
+/// Filters not polled for this long are uninstalled.
+pub const EVENT_FILTER_TIMEOUT: Duration = Duration::from_secs(300);
+/// Max number of filters installed per `FILTER_INSTALL_WINDOW`, so that a client reinstalling
+/// filters in a loop can't keep the slots of the others busy.
+pub const MAX_FILTER_INSTALLS: usize = 16;
+pub const FILTER_INSTALL_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PoolEvent {
+    /// Accepted into the pool
+    Added { hash: Hash, status: TxStatus },
+    /// Evicted to make room for the better priced transaction `by`
+    Replaced { hash: Hash, by: Hash },
+    /// Left the pool without being included, `nonce_used` when another transaction of the
+    /// sender took its nonce
+    Dropped { hash: Hash, reason: &'static str },
+    /// Left the pool in a block imported as the head, at `index` of its transactions
+    Included { hash: Hash, block: Hash, height: u64, index: usize },
+}
+
+#[derive(Clone)]
+struct EventFilter {
+    events: VecDeque<PoolEvent>,
+    last_poll: Instant,
+}
+
+#[derive(Clone, Default)]
+pub struct PoolEvents {
+    subscribers: Vec<SyncSender<PoolEvent>>,
+    next_id: u64,
+    filters: HashMap<u64, EventFilter>,
+    /// Install times within the last `FILTER_INSTALL_WINDOW`
+    installs: VecDeque<Instant>,
+}
+
+impl PoolEvents {
+    /// Channel receiving the events from now on, dropped from the pool with its receiver.
+    pub fn subscribe(&mut self) -> Receiver<PoolEvent> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_EVENTS);
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Install a filter collecting the events from now on, returns its id. None when all the
+    /// filter slots are taken or too many filters were installed lately.
+    pub fn install(&mut self) -> Option<u64> {
+        self.expire();
+        let now = Instant::now();
+        while self.installs.front().map_or(false, |at| now.duration_since(*at) >= FILTER_INSTALL_WINDOW) {
+            self.installs.pop_front();
+        }
+        if self.filters.len() >= MAX_EVENT_FILTERS || self.installs.len() >= MAX_FILTER_INSTALLS {
+            return None;
+        }
+        self.installs.push_back(now);
+        self.next_id += 1;
+        self.filters.insert(self.next_id, EventFilter {
+            events: VecDeque::new(),
+            last_poll: now,
+        });
+        Some(self.next_id)
+    }
+
+    pub fn uninstall(&mut self, id: u64) -> bool {
+        self.filters.remove(&id).is_some()
+    }
+
+    /// Events since the previous poll, None if the filter is unknown or expired.
+    pub fn poll(&mut self, id: u64) -> Option<Vec<PoolEvent>> {
+        let filter = self.filters.get_mut(&id)?;
+        filter.last_poll = Instant::now();
+        Some(filter.events.drain(..).collect())
+    }
+
+    pub fn emit(&mut self, event: PoolEvent) {
+        self.subscribers.retain(|sender| match sender.try_send(event.clone()) {
+            Err(TrySendError::Disconnected(_)) => false,
+            Err(TrySendError::Full(_)) => {
+                debug!("Pool event subscriber lagging, event dropped");
+                true
+            }
+            Ok(()) => true,
+        });
+        self.expire();
+        for filter in self.filters.values_mut() {
+            if filter.events.len() == MAX_PENDING_EVENTS {
+                filter.events.pop_front();
+            }
+            filter.events.push_back(event.clone());
+        }
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.filters.retain(|_, f| now.duration_since(f.last_poll) < EVENT_FILTER_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribers_and_filters() {
+        let mut events = PoolEvents::default();
+        let receiver = events.subscribe();
+        let dropped = events.subscribe();
+        drop(dropped);
+        let id = events.install().unwrap();
+
+        let added = PoolEvent::Added { hash: Hash([1; 32]), status: TxStatus::Pending };
+        let replaced = PoolEvent::Replaced { hash: Hash([1; 32]), by: Hash([2; 32]) };
+        events.emit(added.clone());
+        events.emit(replaced.clone());
+
+        assert_eq!(events.subscribers.len(), 1);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![added.clone(), replaced.clone()]);
+        assert_eq!(events.poll(id), Some(vec![added, replaced]));
+        assert_eq!(events.poll(id), Some(vec![]));
+        assert!(events.uninstall(id));
+        assert_eq!(events.poll(id), None);
+    }
+
+    #[test]
+    fn bounded_buffers() {
+        let mut events = PoolEvents::default();
+        let receiver = events.subscribe();
+        let id = events.install().unwrap();
+        for i in 0..=MAX_PENDING_EVENTS as u64 {
+            events.emit(PoolEvent::Included { hash: Hash::default(), block: Hash::default(), height: i, index: 0 });
+        }
+        assert_eq!(receiver.try_iter().count(), MAX_PENDING_EVENTS);
+        let polled = events.poll(id).unwrap();
+        assert_eq!(polled.len(), MAX_PENDING_EVENTS);
+        assert_eq!(polled.last(), Some(&PoolEvent::Included {
+            hash: Hash::default(),
+            block: Hash::default(),
+            height: MAX_PENDING_EVENTS as u64,
+            index: 0,
+        }));
+    }
+
+    #[test]
+    fn limited_installs() {
+        let mut events = PoolEvents::default();
+        let ids: Vec<u64> = (0..MAX_FILTER_INSTALLS).map(|_| events.install().unwrap()).collect();
+        assert_eq!(events.install(), None);
+        // Uninstalling frees a slot but not the install budget of the window
+        assert!(events.uninstall(ids[0]));
+        assert_eq!(events.install(), None);
+        events.installs.clear();
+        assert!(events.install().is_some());
+    }
+}
@@ -16,5 +16,10 @@
 
 #[macro_use]
 extern crate log;
+pub mod events;
+pub mod nonce_reservations;
+pub mod pending_state;
+pub mod snapshot;
 pub mod tx_pool;
+pub mod tx_tracker;
 mod transaction_pool;
@@ -0,0 +1,120 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Nonces handed out ahead of the transactions of senders submitting concurrently from one key,
+//! so two clients asking at the same time get disjoint ranges.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use map_core::types::Address;
+
+/// Time a reservation holds, the nonces left unused are handed out again after it.
+pub const RESERVATION_TTL: Duration = Duration::from_secs(120);
+
+struct Reservation {
+    /// First and highest nonce reserved
+    first: u64,
+    last: u64,
+    expires: Instant,
+}
+
+#[derive(Default)]
+pub struct NonceReservations {
+    /// Live ranges of each address, ordered by nonce
+    reservations: HashMap<Address, Vec<Reservation>>,
+}
+
+impl NonceReservations {
+    /// Reserve `count` nonces of `addr` after its pending nonce, in the first gap of its live
+    /// reservations they fit in, returns the first and the last. Ranges of expired reservations
+    /// are gaps handed out again. None if the range would end more than `max_ahead` nonces
+    /// after the pending one.
+    pub fn reserve(&mut self, addr: Address, count: u64, pending_nonce: u64, max_ahead: u64, now: Instant) -> Option<(u64, u64)> {
+        self.reservations.retain(|_, ranges| {
+            ranges.retain(|r| r.expires > now && r.last > pending_nonce);
+            !ranges.is_empty()
+        });
+        let count = count.max(1);
+        if count > max_ahead {
+            return None;
+        }
+        let ranges = self.reservations.entry(addr).or_insert_with(Vec::new);
+        let mut first = pending_nonce + 1;
+        let mut index = 0;
+        for r in ranges.iter() {
+            if first + count <= r.first {
+                break;
+            }
+            first = first.max(r.last + 1);
+            index += 1;
+        }
+        let last = first + count - 1;
+        if last - pending_nonce > max_ahead {
+            if ranges.is_empty() {
+                self.reservations.remove(&addr);
+            }
+            return None;
+        }
+        ranges.insert(index, Reservation { first, last, expires: now + RESERVATION_TTL });
+        Some((first, last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disjoint_ranges() {
+        let mut reservations = NonceReservations::default();
+        let (a, b) = (Address([1; 20]), Address([2; 20]));
+        let now = Instant::now();
+        assert_eq!(reservations.reserve(a, 3, 4, 16, now), Some((5, 7)));
+        assert_eq!(reservations.reserve(a, 2, 4, 16, now), Some((8, 9)));
+        assert_eq!(reservations.reserve(b, 1, 0, 16, now), Some((1, 1)));
+        // The pool passed the reserved range
+        assert_eq!(reservations.reserve(a, 1, 12, 16, now), Some((13, 13)));
+        // Unused nonces are handed out again once the reservation expired
+        assert_eq!(reservations.reserve(a, 1, 4, 16, now + RESERVATION_TTL), Some((5, 5)));
+    }
+
+    #[test]
+    fn outstanding_limit() {
+        let mut reservations = NonceReservations::default();
+        let a = Address([1; 20]);
+        let now = Instant::now();
+        assert_eq!(reservations.reserve(a, 3, 0, 4, now), Some((1, 3)));
+        assert_eq!(reservations.reserve(a, 1, 0, 4, now), Some((4, 4)));
+        // Repeated calls stop at the limit of the outstanding range
+        assert_eq!(reservations.reserve(a, 1, 0, 4, now), None);
+        assert_eq!(reservations.reserve(a, 1, 2, 4, now), Some((5, 5)));
+    }
+
+    #[test]
+    fn expired_gaps() {
+        let mut reservations = NonceReservations::default();
+        let a = Address([1; 20]);
+        let now = Instant::now();
+        assert_eq!(reservations.reserve(a, 3, 0, 16, now), Some((1, 3)));
+        let later = now + RESERVATION_TTL / 2;
+        assert_eq!(reservations.reserve(a, 2, 0, 16, later), Some((4, 5)));
+        // The first range expired while the second still holds, its nonces are handed out again
+        let expired = now + RESERVATION_TTL;
+        assert_eq!(reservations.reserve(a, 2, 0, 16, expired), Some((1, 2)));
+        assert_eq!(reservations.reserve(a, 2, 0, 16, expired), Some((6, 7)));
+        assert_eq!(reservations.reserve(a, 1, 0, 16, expired), Some((3, 3)));
+    }
+}
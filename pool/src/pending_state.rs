@@ -0,0 +1,137 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Accounts of the head state with the pool transactions applied, read from the state once per
+//! head and then updated in memory as transactions arrive.
+use std::collections::HashMap;
+
+use executor::Executor;
+use map_core::balance::{Account, Balance};
+use map_core::transaction::Transaction;
+use map_core::types::{Address, Hash};
+
+/// Nonce and balance of an account once its pool transactions execute.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PendingAccount {
+    pub nonce: u64,
+    pub balance: u128,
+}
+
+impl From<Account> for PendingAccount {
+    fn from(account: Account) -> Self {
+        PendingAccount {
+            nonce: account.get_nonce(),
+            balance: account.get_balance(),
+        }
+    }
+}
+
+/// Copy-on-write overlay of the head state, accounts are copied from the state on first use and
+/// never written back.
+#[derive(Default)]
+pub struct PendingState {
+    /// State root the transactions are applied on, None once stale
+    root: Option<Hash>,
+    accounts: HashMap<Address, PendingAccount>,
+}
+
+impl PendingState {
+    /// True if the overlay applies on `root` and is up to date with the pool
+    pub fn is_current(&self, root: Hash) -> bool {
+        self.root == Some(root)
+    }
+
+    /// Rebuild on next use, after a transaction left the pool
+    pub fn invalidate(&mut self) {
+        self.root = None;
+    }
+
+    /// Drop the accounts and apply `txs` on top of `state`, each sender's in nonce order
+    pub fn rebuild(&mut self, root: Hash, state: &Balance, mut txs: Vec<Transaction>) {
+        self.accounts.clear();
+        self.root = Some(root);
+        txs.sort_by_key(|tx| (tx.sender, tx.get_nonce()));
+        for tx in txs.iter() {
+            self.apply(tx, state);
+        }
+    }
+
+    pub fn account(&mut self, addr: Address, state: &Balance) -> PendingAccount {
+        *self.accounts.entry(addr).or_insert_with(|| state.get_account(addr).into())
+    }
+
+    /// Debit the sender of a transaction the way the executor would, false if it can't execute
+    /// next. Receivers are not credited: the pooled transfer may never be included, and spending
+    /// its value would admit transactions unable to execute on the head.
+    pub fn apply(&mut self, tx: &Transaction, state: &Balance) -> bool {
+        let sender = self.account(tx.sender, state);
+        let value = tx.get_value();
+        let fee = match Executor::check_transfer(sender.nonce, sender.balance, tx.get_nonce(), value) {
+            Ok(fee) => fee,
+            Err(_) => return false,
+        };
+        self.accounts.insert(tx.sender, PendingAccount {
+            nonce: sender.nonce + 1,
+            balance: sender.balance - value - fee,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::blockchain::BlockChain;
+    use executor::TRANSFER_FEE;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::runtime::Interpreter;
+    use map_core::test_utils;
+
+    #[test]
+    fn apply_on_head() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let root = chain.current_block().state_root();
+        let state = Balance::new(Interpreter::new(chain.state_at(root)));
+        let key = test_utils::genesis_key();
+        let sender = test_utils::address_of(&key);
+        let receiver = test_utils::test_address(1);
+        let head = state.get_account(sender);
+
+        let mut pending = PendingState::default();
+        assert!(!pending.is_current(root));
+        // The gap at nonce 3 leaves nonce 4 out
+        pending.rebuild(root, &state, vec![
+            test_utils::transfer(&key, 4, receiver, 10),
+            test_utils::transfer(&key, 2, receiver, 10),
+            test_utils::transfer(&key, 1, receiver, 10),
+        ]);
+        assert!(pending.is_current(root));
+        assert_eq!(pending.account(sender, &state), PendingAccount {
+            nonce: head.get_nonce() + 2,
+            balance: head.get_balance() - 2 * (10 + TRANSFER_FEE),
+        });
+        assert_eq!(pending.account(receiver, &state).balance, state.get_account(receiver).get_balance());
+
+        assert!(pending.apply(&test_utils::transfer(&key, 3, receiver, 10), &state));
+        assert!(!pending.apply(&test_utils::transfer(&key, 3, receiver, 10), &state));
+        assert!(!pending.apply(&test_utils::transfer(&key, 4, receiver, head.get_balance()), &state));
+        assert_eq!(pending.account(sender, &state).nonce, head.get_nonce() + 3);
+
+        pending.invalidate();
+        assert!(!pending.is_current(root));
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Pending transactions of the pool at one instant, taken by the proposer at the start of a slot
+//! so transactions arriving while the block is built don't change the selection.
+use std::cmp;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use map_core::transaction::Transaction;
+use map_core::types::{Address, Hash};
+
+/// Shares the pending set with the pool, which copies it on its next change while a snapshot is
+/// alive.
+#[derive(Clone)]
+pub struct PoolSnapshot {
+    pending: Arc<HashMap<Hash, Transaction>>,
+    min_gas_price: u64,
+    block_txs: usize,
+}
+
+impl PoolSnapshot {
+    pub(crate) fn new(pending: Arc<HashMap<Hash, Transaction>>, min_gas_price: u64, block_txs: usize) -> Self {
+        PoolSnapshot { pending, min_gas_price, block_txs }
+    }
+
+    /// Number of pending transactions when the snapshot was taken
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Pending transactions to include in the next block, at most `block_txs`. The best priced
+    /// transactions come first, each sender's transactions in nonce order, ties are broken by
+    /// sender address so every node builds the same selection. A sender's transactions stop at
+    /// the first one below the gas price floor.
+    pub fn pending_for_block(&self) -> Vec<Transaction> {
+        let mut by_sender: HashMap<Address, Vec<Transaction>> = HashMap::new();
+        for tx in self.pending.values() {
+            by_sender.entry(tx.sender).or_insert_with(Vec::new).push(tx.clone());
+        }
+
+        // Candidates are the lowest nonce transaction of each sender
+        let mut heads = BinaryHeap::new();
+        for (sender, txs) in by_sender.iter_mut() {
+            txs.sort_by(|a, b| b.get_nonce().cmp(&a.get_nonce()));
+            if let Some(cut) = txs.iter().rposition(|tx| tx.get_gas_price() < self.min_gas_price) {
+                txs.drain(..=cut);
+            }
+            if let Some(head) = txs.last() {
+                heads.push((head.get_gas_price(), cmp::Reverse(*sender)));
+            }
+        }
+
+        let mut selected = Vec::new();
+        while selected.len() < self.block_txs {
+            let sender = match heads.pop() {
+                Some((_, cmp::Reverse(sender))) => sender,
+                None => break,
+            };
+            let txs = by_sender.get_mut(&sender).expect("candidate sender has transactions");
+            if let Some(tx) = txs.pop() {
+                selected.push(tx);
+            }
+            if let Some(next) = txs.last() {
+                heads.push((next.get_gas_price(), cmp::Reverse(sender)));
+            }
+        }
+        selected
+    }
+}
@@ -1,40 +1,206 @@
-use std::collections::{HashMap, BinaryHeap};
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::cmp;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use map_core::balance::Balance;
 use map_core::block::Block;
-use map_core::transaction::Transaction;
-use map_core::types::{Address, Hash};
+use map_core::chain_spec::Rules;
+use map_core::transaction::{Transaction, TxDomain};
+use map_core::types::{Address, AddressScheme, Hash};
 use map_core::runtime::Interpreter;
+use chain::audit::Violation;
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use crate::events::{PoolEvent, PoolEvents};
+use crate::nonce_reservations::NonceReservations;
+use crate::pending_state::{PendingAccount, PendingState};
+use crate::snapshot::PoolSnapshot;
+use crate::tx_tracker::{TrackedStatus, TxTracker};
 
 /// Max of block transactin limit
 const MAX_BLOCK_TX: u32 = 500;
 /// Max transaction pool limit
 const MAX_QUEUE_TX: u32 = 2048;
+/// Default max of executable transactions
+const GLOBAL_SLOTS: usize = 4096;
+/// Default max of transactions from a single sender
+const ACCOUNT_SLOTS: usize = 16;
+/// Default max of local transactions, kept over the pool limits
+const LOCAL_SLOTS: usize = 1024;
+/// Seconds between two writes of the journal while it changes
+pub const JOURNAL_FLUSH_INTERVAL: u64 = 5;
+
+/// Size limits of the transaction pool
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolConfig {
+    /// Max of executable transactions across all senders
+    pub global_slots: usize,
+    /// Max of transactions, executable or queued, from a single sender
+    pub account_slots: usize,
+    /// Max of queued transactions waiting for a free executable slot
+    pub global_queue: usize,
+    /// Max of local transactions, further ones are admitted like remote ones
+    pub local_slots: usize,
+    /// Max of transactions included in a block, the rest is left for the next blocks
+    pub block_txs: usize,
+    /// File keeping the local transactions across restarts, none to keep them in memory only.
+    /// Their statuses are kept next to it, with the `status` extension.
+    pub journal: Option<PathBuf>,
+    /// Lowest gas price accepted and packed into blocks, the chain spec floor if none
+    pub min_gas_price: Option<u64>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            global_slots: GLOBAL_SLOTS,
+            account_slots: ACCOUNT_SLOTS,
+            global_queue: MAX_QUEUE_TX as usize,
+            local_slots: LOCAL_SLOTS,
+            block_txs: MAX_BLOCK_TX as usize,
+            journal: None,
+            min_gas_price: None,
+        }
+    }
+}
+
+/// Reason a transaction was not accepted into the pool
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxPoolError {
+    /// Same transaction is already in the pool
+    AlreadyKnown(Hash),
+    /// Sender balance after its pool transactions can't cover the transfer value and fee
+    InsufficientFunds { balance: u128, value: u128 },
+    /// Nonce is not the next one of the sender
+    InvalidNonce { expected: u64, got: u64 },
+    /// Sender already has `account_slots` transactions in the pool
+    AccountLimit(usize),
+    /// Pool is full and the transaction doesn't pay more than the cheapest one
+    Underpriced(u64),
+    /// Transfer value plus fee is above the max balance
+    ValueOverflow(u128),
+    /// Gas price is below the floor of the pool
+    BelowMinGasPrice(u64),
+    /// Call is neither a transfer nor of a module blocks execute
+    UnknownCall(String),
+    /// Signature doesn't verify under the rules of the next block
+    InvalidSignature,
+}
+
+impl TxPoolError {
+    /// Stable name of the rejection reason
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TxPoolError::AlreadyKnown(_) => "already_known",
+            TxPoolError::InsufficientFunds { .. } => "insufficient_funds",
+            TxPoolError::InvalidNonce { .. } => "invalid_nonce",
+            TxPoolError::AccountLimit(_) => "account_limit",
+            TxPoolError::Underpriced(_) => "underpriced",
+            TxPoolError::ValueOverflow(_) => "value_overflow",
+            TxPoolError::BelowMinGasPrice(_) => "below_min_gas_price",
+            TxPoolError::UnknownCall(_) => "unknown_call",
+            TxPoolError::InvalidSignature => "invalid_signature",
+        }
+    }
+
+    /// Transaction field the rejection is about
+    pub fn field(&self) -> &'static str {
+        match self {
+            TxPoolError::AlreadyKnown(_) => "hash",
+            TxPoolError::InsufficientFunds { .. } | TxPoolError::ValueOverflow(_) => "value",
+            TxPoolError::InvalidNonce { .. } => "nonce",
+            TxPoolError::AccountLimit(_) => "from",
+            TxPoolError::Underpriced(_) | TxPoolError::BelowMinGasPrice(_) => "gas_price",
+            TxPoolError::UnknownCall(_) => "call",
+            TxPoolError::InvalidSignature => "sign_data",
+        }
+    }
+}
+
+/// Set of the pool an accepted transaction was placed in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxStatus {
+    /// Executable, picked for the next blocks
+    Pending,
+    /// Waiting for a free executable slot
+    Queued,
+}
+
+/// Placement of a transaction accepted into the pool
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxAdmission {
+    pub hash: Hash,
+    pub status: TxStatus,
+    /// Number of transactions of the same set paying a higher gas price
+    pub position: usize,
+    /// Cheapest transaction evicted to make room, if the pool was full
+    pub replaced: Option<Hash>,
+}
+
+impl fmt::Display for TxPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxPoolError::AlreadyKnown(hash) => write!(f, "already known transaction {}", hash),
+            TxPoolError::InsufficientFunds { balance, value } =>
+                write!(f, "not sufficient funds {}, tx value {}", balance, value),
+            TxPoolError::InvalidNonce { expected, got } if got < expected =>
+                write!(f, "nonce {} too low, expect {}", got, expected),
+            TxPoolError::InvalidNonce { expected, got } =>
+                write!(f, "invalid nonce {}, expect {}", got, expected),
+            TxPoolError::AccountLimit(limit) => write!(f, "account exceeds pool limit {}", limit),
+            TxPoolError::Underpriced(price) =>
+                write!(f, "pool is full, gas price must be above {}", price),
+            TxPoolError::ValueOverflow(value) => write!(f, "value {} plus fee overflows", value),
+            TxPoolError::BelowMinGasPrice(floor) => write!(f, "gas price below the minimum {}", floor),
+            TxPoolError::UnknownCall(call) => write!(f, "unknown call {:?}", call),
+            TxPoolError::InvalidSignature => write!(f, "invalid signature"),
+        }
+    }
+}
 
-#[derive(Clone)]
 pub struct TxPoolManager {
-    pending: HashMap<Hash, Transaction>,
+    /// Executable transactions, shared copy-on-write with the snapshots
+    pending: Arc<HashMap<Hash, Transaction>>,
     pool: HashMap<Hash, Transaction>,
-    blockchain: Arc<RwLock<BlockChain>>,
+    blockchain: Arc<TimedRwLock<BlockChain>>,
     ordered_queue: BinaryHeap<PriorityRef>,
-    block_limit: usize,
-    queue_limit: usize,
+    config: PoolConfig,
+    /// Insertion counter, older transactions are evicted first at equal price
+    sequence: u64,
+    /// Transactions submitted through the local RPC, never evicted for price
+    locals: HashSet<Hash>,
+    events: PoolEvents,
+    /// Head state with the pool transactions applied, for admission checks and fee estimates
+    pending_state: Mutex<PendingState>,
+    /// Lowest gas price admitted and packed, from the config or else the chain spec
+    min_gas_price: u64,
+    /// Nonces reserved by concurrent senders of one key
+    reservations: NonceReservations,
+    /// Status of the local transactions, followed from the events
+    tracker: TxTracker,
+    /// Locals or statuses changed since the journal was last written
+    journal_dirty: bool,
+    /// State root of the block the pool was last reset to, which the audit checks against
+    reset_root: Hash,
 }
 
 #[derive(Clone)]
 pub struct PriorityRef {
     tx_hash: Hash,
     price: u64,
+    sequence: u64,
     // tx: Arc<Transaction>,
 }
 
 impl Ord for PriorityRef {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        // self.tx.get_gas_price().cmp(&other.tx.get_gas_price())
-        self.price.cmp(&other.price).reverse()
+        // The heap top is the cheapest and then oldest transaction
+        (self.price, self.sequence).cmp(&(other.price, other.sequence)).reverse()
     }
 }
 
@@ -46,8 +212,7 @@ impl PartialOrd for PriorityRef {
 
 impl PartialEq for PriorityRef {
     fn eq(&self, other: &Self) -> bool {
-        // self.tx.get_gas_price() == other.tx.get_gas_price()
-        self.price == other.price
+        self.cmp(other) == cmp::Ordering::Equal
     }
 }
 
@@ -55,79 +220,317 @@ impl Eq for PriorityRef {}
 
 
 impl TxPoolManager {
-    pub fn add_tx(&mut self, tx: Transaction) -> bool {
-        match self.validate_tx(&tx) {
-            Ok(_) => self.pending.insert(tx.hash(), tx.clone()),
-            Err(e) => {
-                error!("Submit tx {}", e.as_str());
-                return false
-            }
-        };
-        // let mut send = self.network_send.as_mut().unwrap();
-        // manager::publish_transaction(&mut send, tx)
-        true
+    pub fn add_tx(&mut self, tx: Transaction) -> Result<TxAdmission, TxPoolError> {
+        if let Err(e) = self.validate_tx(&tx) {
+            error!("Submit tx {}", e);
+            return Err(e);
+        }
+        self.admit(tx)
+    }
+
+    /// Add a transaction submitted through the local RPC. It is kept until included even when
+    /// the pool is full of better priced ones, and saved to the journal. Once `local_slots` are
+    /// taken it is admitted like a remote one.
+    pub fn add_local_tx(&mut self, tx: Transaction) -> Result<TxAdmission, TxPoolError> {
+        let admission = self.add_local(tx)?;
+        self.save_journal();
+        Ok(admission)
+    }
+
+    fn add_local(&mut self, tx: Transaction) -> Result<TxAdmission, TxPoolError> {
+        if let Err(e) = self.validate_tx(&tx) {
+            error!("Submit local tx {}", e);
+            return Err(e);
+        }
+        let local = self.locals.len() < self.config.local_slots;
+        let admission = self.admit_as(tx, local)?;
+        if local {
+            self.locals.insert(admission.hash);
+        }
+        self.tracker.track(admission.hash);
+        Ok(admission)
     }
 
     pub fn insert_tx(&mut self, tx: Transaction) {
-        match self.validate_tx(&tx) {
-            Err(e) => {
-                return info!("Submit tx {}", e.as_str());
-            },
-            _ => {},
-        };
+        let tx_hash = tx.hash();
+        if let Err(e) = self.add_tx(tx) {
+            info!("Reject transaction {} {}", tx_hash, e);
+        }
+    }
 
-        if self.all_transactions().len() > self.block_limit + self.queue_limit {
-            // Replace or drop new transaction
-            info!("Reject transaction {}", tx.hash());
-            if let Some(removed) = self.pop_back() {
-                if self.pending.remove(&removed).is_some() {
-                    info!("Dequeu pending transaction {}", removed);
-                } else {
-                    self.pool.remove(&removed);
-                    info!("Dequeu queued transaction {}", removed);
+    /// Enforce the pool limits and insert a validated transaction
+    fn admit(&mut self, tx: Transaction) -> Result<TxAdmission, TxPoolError> {
+        self.admit_as(tx, false)
+    }
+
+    /// Local transactions replace the cheapest remote one whatever its price, and go over the
+    /// limit if there is none.
+    fn admit_as(&mut self, tx: Transaction, local: bool) -> Result<TxAdmission, TxPoolError> {
+        let tx_hash = tx.hash();
+        if self.pending.contains_key(&tx_hash) || self.pool.contains_key(&tx_hash) {
+            return Err(TxPoolError::AlreadyKnown(tx_hash));
+        }
+        if tx.get_gas_price() < self.min_gas_price {
+            return Err(TxPoolError::BelowMinGasPrice(self.min_gas_price));
+        }
+
+        let sender_count = self.pending.values().chain(self.pool.values())
+            .filter(|t| t.sender == tx.sender)
+            .count();
+        if sender_count >= self.config.account_slots {
+            return Err(TxPoolError::AccountLimit(self.config.account_slots));
+        }
+
+        let mut replaced = None;
+        let capacity = self.config.global_slots + self.config.global_queue;
+        if self.pending.len() + self.pool.len() >= capacity {
+            // Replace the cheapest transaction or drop the new one
+            match self.peek_back() {
+                Some(lowest) if local || lowest.price < tx.get_gas_price() => {
+                    if let Some(removed) = self.pop_back() {
+                        info!("Evict transaction {}", removed);
+                        replaced = Some(removed);
+                    }
                 }
+                Some(lowest) => return Err(TxPoolError::Underpriced(lowest.price)),
+                None if local => {}
+                None => return Err(TxPoolError::Underpriced(0)),
             }
         }
 
-        let tx_hash = tx.hash();
-        let tx_price = tx.get_gas_price();
-        if self.pending.len() > self.block_limit {
-            self.pool.insert(tx.hash(), tx);
+        let price = tx.get_gas_price();
+        // Locals stay out of the eviction queue
+        if !local {
+            self.sequence += 1;
+            self.ordered_queue.push(PriorityRef{
+                tx_hash: tx_hash,
+                price: price,
+                sequence: self.sequence,
+            });
+        }
+        // An eviction already left the overlay stale
+        self.apply_pending(&tx);
+        let (status, set) = if self.pending.len() < self.config.global_slots {
+            (TxStatus::Pending, Arc::make_mut(&mut self.pending))
         } else {
-            self.pending.insert(tx.hash(), tx);
+            (TxStatus::Queued, &mut self.pool)
+        };
+        let position = set.values().filter(|t| t.get_gas_price() > price).count();
+        set.insert(tx_hash, tx);
+        self.emit(PoolEvent::Added { hash: tx_hash, status });
+        if let Some(removed) = replaced {
+            self.emit(PoolEvent::Replaced { hash: removed, by: tx_hash });
         }
-
-        self.ordered_queue.push(PriorityRef{
-            tx_hash: tx_hash,
-            price: tx_price,
-        });
+        Ok(TxAdmission { hash: tx_hash, status, position, replaced })
     }
 
-    fn pop_back(&mut self) -> Option<Hash> {
-        if self.ordered_queue.len() == 0 {
-            return None;
+    /// Cheapest transaction still in the pool, dropping refs of removed ones
+    fn peek_back(&mut self) -> Option<PriorityRef> {
+        while let Some(last) = self.ordered_queue.peek() {
+            if self.pending.contains_key(&last.tx_hash) || self.pool.contains_key(&last.tx_hash) {
+                return Some(last.clone());
+            }
+            self.ordered_queue.pop();
         }
+        None
+    }
 
-        let last = self.ordered_queue.pop().unwrap();
+    fn pop_back(&mut self) -> Option<Hash> {
+        let last = self.peek_back()?;
+        self.ordered_queue.pop();
 
         if self.pool.remove(&last.tx_hash).is_none() {
-            self.pending.remove(&last.tx_hash).unwrap();
+            Arc::make_mut(&mut self.pending).remove(&last.tx_hash);
         }
+        self.pending_state.get_mut().unwrap().invalidate();
 
         Some(last.tx_hash)
     }
 
+    /// Move the local and then best priced queued transactions into free executable slots
+    fn promote(&mut self) {
+        while self.pending.len() < self.config.global_slots {
+            let locals = &self.locals;
+            let best = match self.pool.values().max_by_key(|tx| (locals.contains(&tx.hash()), tx.get_gas_price())) {
+                Some(tx) => tx.hash(),
+                None => break,
+            };
+            if let Some(tx) = self.pool.remove(&best) {
+                Arc::make_mut(&mut self.pending).insert(best, tx);
+            }
+        }
+    }
 
     pub fn get_pending(&self) -> Vec<Transaction> {
         self.pending.values().cloned().collect()
     }
 
+    /// Pending transactions to include in the next block, see `PoolSnapshot::pending_for_block`
+    pub fn pending_for_block(&self) -> Vec<Transaction> {
+        self.snapshot().pending_for_block()
+    }
+
+    /// Pending set as of now, unaffected by later changes of the pool. Cheap to take, the pool
+    /// only copies its pending set when it changes while the snapshot is alive.
+    pub fn snapshot(&self) -> PoolSnapshot {
+        PoolSnapshot::new(self.pending.clone(), self.min_gas_price, self.config.block_txs)
+    }
+
+    /// Pending transactions sent by the address, ordered by nonce
+    pub fn pending_by_sender(&self, addr: &Address) -> Vec<Transaction> {
+        let mut txs: Vec<Transaction> = self.pending.values()
+            .filter(|tx| tx.sender == *addr)
+            .cloned()
+            .collect();
+        txs.sort_by_key(|tx| tx.get_nonce());
+        txs
+    }
+
+    /// Number of transactions waiting to be included in a block
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of pending and queued transactions
+    pub fn size(&self) -> usize {
+        self.pending.len() + self.pool.len()
+    }
+
+    /// Max number of transactions included in a block
+    pub fn block_limit(&self) -> usize {
+        self.config.block_txs
+    }
+
+    /// Lowest gas price admitted into the pool and packed into blocks
+    pub fn min_gas_price(&self) -> u64 {
+        self.min_gas_price
+    }
+
     pub fn remove_tx(&mut self, tx_hash: Hash) {
-        if self.pending.remove(&tx_hash).is_some() {
+        if self.pending.contains_key(&tx_hash) {
+            Arc::make_mut(&mut self.pending).remove(&tx_hash);
         } else {
             info!("Clean stale transaction {}", tx_hash);
             self.pool.remove(&tx_hash);
         }
+        self.pending_state.get_mut().unwrap().invalidate();
+        if self.locals.remove(&tx_hash) {
+            self.save_journal();
+        }
+        self.promote();
+    }
+
+    /// Local transactions not yet included at the head, to rebroadcast. Included ones are left
+    /// to `reset_pool` with the block including them.
+    pub fn pending_locals(&self) -> Vec<Transaction> {
+        if self.locals.is_empty() {
+            return Vec::new();
+        }
+        let chain = self.blockchain.read().unwrap();
+        let runtime = Balance::new(Interpreter::new(chain.state_at(chain.current_block().state_root())));
+        let mut live: Vec<Transaction> = self.locals.iter()
+            .filter_map(|hash| self.get_tx(hash))
+            .filter(|tx| tx.get_nonce() > runtime.get_account(tx.sender).get_nonce())
+            .collect();
+        live.sort_by_key(|tx| (tx.sender, tx.get_nonce()));
+        live
+    }
+
+    /// Status of a transaction submitted to this node, None if it is not tracked
+    pub fn tx_status(&self, hash: &Hash) -> Option<TrackedStatus> {
+        self.tracker.status(hash)
+    }
+
+    /// Re-add the transactions of the journal as local ones, dropping those no longer valid,
+    /// and the statuses saved with it, then those of the blocks unwound at load
+    pub fn load_journal(&mut self) {
+        let path = match self.config.journal {
+            Some(ref path) => path.clone(),
+            None => return self.reinject_unwound(),
+        };
+        self.tracker = TxTracker::load(&status_path(&path));
+        self.reinject_unwound();
+        if !path.exists() {
+            return;
+        }
+        let txs: Vec<Transaction> = match fs::read(&path).map(|raw| bincode::deserialize(&raw)) {
+            Ok(Ok(txs)) => txs,
+            Ok(Err(e)) => return warn!("Invalid transaction journal {}: {}", path.display(), e),
+            Err(e) => return warn!("Failed to read transaction journal {}: {}", path.display(), e),
+        };
+        let total = txs.len();
+        let loaded = txs.into_iter().filter(|tx| self.add_local(tx.clone()).is_ok()).count();
+        info!("Loaded {} of {} local transactions from journal", loaded, total);
+        self.save_journal();
+        self.flush_journal();
+    }
+
+    /// Add again the transactions of blocks no longer canonical, such as the blocks rolled back
+    /// at startup and not imported again. Tracked ones are added as local or marked dropped if
+    /// no longer valid, the others are admitted like remote ones if still valid.
+    pub fn reinject_unwound(&mut self) {
+        let unwound: Vec<(Hash, Option<Transaction>)> = {
+            let chain = self.blockchain.read().unwrap();
+            self.tracker.included().into_iter()
+                .filter(|(_, block, height, _)| chain.get_header_by_number(*height).map(|header| header.hash()) != Some(*block))
+                .map(|(hash, block, _, index)| {
+                    let tx = chain.get_block(block).and_then(|b| b.get_txs().get(index).cloned()).filter(|tx| tx.hash() == hash);
+                    (hash, tx)
+                })
+                .collect()
+        };
+        for (hash, tx) in unwound {
+            self.tracker.unwind(&hash);
+            if tx.map_or(true, |tx| self.add_local(tx).is_err()) {
+                self.tracker.on_event(&PoolEvent::Dropped { hash, reason: "unwound" });
+            }
+        }
+        let remote = self.blockchain.read().unwrap().unwound_transactions();
+        let (total, mut added) = (remote.len(), 0);
+        for tx in remote {
+            if !self.contains(&tx.hash()) && self.validate_tx(&tx).is_ok() && self.admit(tx).is_ok() {
+                added += 1;
+            }
+        }
+        if total > 0 {
+            info!("Added back {} of {} transactions of unwound blocks", added, total);
+        }
+    }
+
+    /// Mark the journal for the next `flush_journal`
+    fn save_journal(&mut self) {
+        self.journal_dirty = self.config.journal.is_some();
+    }
+
+    /// Write the local transactions to the journal and their statuses next to it, if they
+    /// changed since the last write
+    pub fn flush_journal(&mut self) {
+        let path = match self.config.journal {
+            Some(ref path) if self.journal_dirty => path,
+            _ => return,
+        };
+        self.journal_dirty = false;
+        self.tracker.save(&status_path(path));
+        let mut txs: Vec<Transaction> = self.locals.iter().filter_map(|hash| self.get_tx(hash)).collect();
+        txs.sort_by_key(|tx| (tx.sender, tx.get_nonce()));
+        let raw = bincode::serialize(&txs).expect("transactions serialize");
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp, raw).and_then(|_| fs::rename(&tmp, path)) {
+            warn!("Failed to write transaction journal {}: {}", path.display(), e);
+        }
+    }
+
+    /// Hashes of at most `max` transactions, pending ones first
+    pub fn tx_hashes(&self, max: usize) -> Vec<Hash> {
+        self.pending.keys().chain(self.pool.keys()).take(max).cloned().collect()
+    }
+
+    pub fn contains(&self, tx_hash: &Hash) -> bool {
+        self.pending.contains_key(tx_hash) || self.pool.contains_key(tx_hash)
+    }
+
+    pub fn get_tx(&self, tx_hash: &Hash) -> Option<Transaction> {
+        self.pending.get(tx_hash).or_else(|| self.pool.get(tx_hash)).cloned()
     }
 
     pub fn all_transactions(&self) -> Vec<Transaction> {
@@ -137,52 +540,626 @@ impl TxPoolManager {
         all
     }
 
+    /// Transactions whose nonce the state of the last `reset_pool` already used, which it
+    /// should have removed, and tracked transactions included in a block that is not the
+    /// canonical one of its height or has fewer transactions than their index. Blocks imported
+    /// since the last reset are not held against the pool until it hears of them.
+    pub fn audit(&self) -> Vec<Violation> {
+        let chain = self.blockchain.read().unwrap();
+        let runtime = Balance::new(Interpreter::new(chain.state_at(self.reset_root)));
+        let mut violations: Vec<Violation> = self.pending.values().chain(self.pool.values())
+            .filter_map(|tx| {
+                let nonce = runtime.get_account(tx.sender).get_nonce();
+                if tx.get_nonce() > nonce {
+                    return None;
+                }
+                Some(Violation::new("pool_nonce", format!(
+                    "transaction {} of {} has nonce {}, the pool state is at {}", tx.hash(), tx.sender, tx.get_nonce(), nonce)))
+            })
+            .collect();
+        for (hash, block, height, index) in self.tracker.included() {
+            if chain.get_header_by_number(height).map(|header| header.hash()) != Some(block) {
+                violations.push(Violation::new("tx_index", format!(
+                    "transaction {} is tracked in block {} which is not canonical at height {}", hash, block, height)));
+            } else if chain.get_fee_stats(block).map_or(true, |stats| stats.tx_count <= index as u64) {
+                violations.push(Violation::new("tx_index", format!(
+                    "transaction {} is tracked at index {} of block {} which has fewer transactions", hash, index, block)));
+            }
+        }
+        violations.sort_by(|a, b| a.detail.cmp(&b.detail));
+        violations
+    }
+
     pub fn reset_pool(&mut self, b: &Block) {
-        let state = self.blockchain.read().unwrap().state_at(b.state_root());
+        let (state, rules, forked) = {
+            let chain = self.blockchain.read().unwrap();
+            let rules = chain.spec().forks.at(b.height() + 1);
+            (chain.state_at(b.state_root()), rules, rules != chain.spec().forks.at(b.height()))
+        };
         let runtime = Balance::new(Interpreter::new(state));
-        self.pending.retain(|_, tx| {
+        self.reset_root = b.state_root();
+        let before: Vec<Hash> = self.pending.keys().chain(self.pool.keys()).cloned().collect();
+        // Signatures only need checking again when the next block changes the signed message
+        let resigned: HashSet<Hash> = if forked {
+            self.pending.iter().chain(self.pool.iter())
+                .filter(|(_, tx)| tx.verify_sign_at(&rules).is_err())
+                .map(|(hash, _)| *hash)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        Arc::make_mut(&mut self.pending).retain(|hash, tx| {
             let account = runtime.get_account(tx.sender);
-            tx.get_nonce() > account.get_nonce()
+            tx.get_nonce() > account.get_nonce() && !resigned.contains(hash)
         });
+        self.pool.retain(|hash, tx| {
+            let account = runtime.get_account(tx.sender);
+            tx.get_nonce() > account.get_nonce() && !resigned.contains(hash)
+        });
+        let included: HashMap<Hash, usize> = b.get_txs().iter().enumerate().map(|(index, tx)| (tx.hash(), index)).collect();
+        let removed: Vec<Hash> = before.into_iter().filter(|hash| !self.contains(hash)).collect();
+        for hash in removed {
+            self.emit(if let Some(&index) = included.get(&hash) {
+                PoolEvent::Included { hash, block: b.hash(), height: b.height(), index }
+            } else if resigned.contains(&hash) {
+                PoolEvent::Dropped { hash, reason: "invalid_signature" }
+            } else {
+                PoolEvent::Dropped { hash, reason: "nonce_used" }
+            });
+        }
+        self.promote();
+
+        let (pending, pool) = (&self.pending, &self.pool);
+        let gone: Vec<Hash> = self.locals.iter()
+            .filter(|hash| !pending.contains_key(hash) && !pool.contains_key(hash))
+            .cloned()
+            .collect();
+        if !gone.is_empty() {
+            for hash in gone.iter() {
+                self.locals.remove(hash);
+            }
+            self.save_journal();
+        }
+        let (pending, pool) = (&self.pending, &self.pool);
+        let live: BinaryHeap<PriorityRef> = self.ordered_queue.drain()
+            .filter(|r| pending.contains_key(&r.tx_hash) || pool.contains_key(&r.tx_hash))
+            .collect();
+        self.ordered_queue = live;
+    }
+
+    pub fn new(chain: Arc<TimedRwLock<BlockChain>>) -> Self {
+        Self::with_config(chain, PoolConfig::default())
     }
 
-    pub fn new(chain: Arc<RwLock<BlockChain>>) -> Self {
+    pub fn with_config(chain: Arc<TimedRwLock<BlockChain>>, config: PoolConfig) -> Self {
+        let (min_gas_price, reset_root) = {
+            let chain = chain.read().unwrap();
+            (config.min_gas_price.unwrap_or_else(|| chain.spec().min_gas_price), chain.current_block().state_root())
+        };
         TxPoolManager {
-            pending: HashMap::new(),
+            pending: Arc::new(HashMap::new()),
             pool: HashMap::new(),
             blockchain: chain,
             ordered_queue: BinaryHeap::new(),
-            block_limit: MAX_BLOCK_TX as usize,
-            queue_limit: MAX_QUEUE_TX as usize,
+            config: config,
+            sequence: 0,
+            locals: HashSet::new(),
+            events: PoolEvents::default(),
+            pending_state: Mutex::new(PendingState::default()),
+            min_gas_price,
+            reservations: NonceReservations::default(),
+            tracker: TxTracker::new(),
+            journal_dirty: false,
+            reset_root,
         }
     }
 
+    /// Lifecycle events of the pool transactions
+    pub fn events(&mut self) -> &mut PoolEvents {
+        &mut self.events
+    }
+
+    fn emit(&mut self, event: PoolEvent) {
+        self.tracker.on_event(&event);
+        if !self.tracker.is_empty() {
+            // Statuses are saved with the journal
+            self.save_journal();
+        }
+        self.events.emit(event);
+    }
+
     // pub fn start(&mut self, network: mpsc::UnboundedSender<NetworkMessage>) {
     //     self.network_send = Some(network);
     // }
 
-    fn validate_tx(&self, tx: &Transaction) -> Result<(), String> {
-        let chain = self.blockchain.read().unwrap();
-        let state = chain.state_at(chain.current_block().state_root());
-        let runtime = Balance::new(Interpreter::new(state));
-        let account = runtime.get_account(tx.sender);
+    /// Check the transaction executes in the next block and follows the sender's pool transactions
+    fn validate_tx(&self, tx: &Transaction) -> Result<(), TxPoolError> {
+        match tx.domain() {
+            TxDomain::Transfer | TxDomain::Staking | TxDomain::Storage => {}
+            _ => return Err(TxPoolError::UnknownCall(String::from_utf8_lossy(&tx.call).into_owned())),
+        }
+        tx.verify_sign_at(&self.next_rules()).map_err(|_| TxPoolError::InvalidSignature)?;
+
+        let account = self.pending_account(&tx.sender);
+        let cost = tx.get_value().checked_add(executor::TRANSFER_FEE)
+            .ok_or(TxPoolError::ValueOverflow(tx.get_value()))?;
 
-        if account.get_balance() < tx.get_value() {
-            return Err(format!("not sufficient funds {}, tx value {}", account.get_balance(), tx.get_value()));
+        if account.balance < cost {
+            return Err(TxPoolError::InsufficientFunds { balance: account.balance, value: cost });
         }
 
-        if account.get_nonce() + 1 != tx.get_nonce() {
-            return Err(format!("invalid nonce {}, tx value {}", account.get_nonce(), tx.get_nonce()));
+        if account.nonce + 1 != tx.get_nonce() {
+            return Err(TxPoolError::InvalidNonce { expected: account.nonce + 1, got: tx.get_nonce() });
         }
         Ok(())
     }
 
+    /// Rules of the next block, the one pool transactions are packed into
+    pub fn next_rules(&self) -> Rules {
+        let chain = self.blockchain.read().unwrap();
+        chain.spec().forks.at(chain.current_block().height() + 1)
+    }
+
+    /// Derivation of the addresses of the chain from public keys
+    pub fn address_scheme(&self) -> AddressScheme {
+        self.blockchain.read().unwrap().address_scheme()
+    }
+
+    /// Nonce of the address once its pool transactions execute
     pub fn get_nonce(&self, addr: &Address) -> u64 {
+        self.pending_account(addr).nonce
+    }
+
+    /// Reserve `count` consecutive nonces of `addr`, after its pool transactions and the nonces
+    /// reserved before, for senders submitting concurrently from one key. The reserved nonces
+    /// end at most `account_slots` after the pool ones, no more than a sender may have in the pool.
+    pub fn reserve_nonces(&mut self, addr: &Address, count: u64) -> Result<(u64, u64), TxPoolError> {
+        let nonce = self.get_nonce(addr);
+        let slots = self.config.account_slots;
+        self.reservations.reserve(*addr, count, nonce, slots as u64, Instant::now())
+            .ok_or(TxPoolError::AccountLimit(slots))
+    }
+
+    /// Account of the address with the pool transactions applied on top of the head state. The
+    /// overlay is rebuilt when the head moves or a transaction leaves the pool.
+    pub fn pending_account(&self, addr: &Address) -> PendingAccount {
         let chain = self.blockchain.read().unwrap();
-        let state = chain.state_at(chain.current_block().state_root());
-        let runtime = Balance::new(Interpreter::new(state));
-        let account = runtime.get_account(addr.clone());
+        let root = chain.current_block().state_root();
+        let state = Balance::new(Interpreter::new(chain.state_at(root)));
+        let mut pending = self.pending_state.lock().unwrap();
+        if !pending.is_current(root) {
+            pending.rebuild(root, &state, self.all_transactions());
+        }
+        pending.account(*addr, &state)
+    }
+
+    /// Apply an admitted transaction to the overlay, a stale one picks it up on rebuild
+    fn apply_pending(&self, tx: &Transaction) {
+        let chain = self.blockchain.read().unwrap();
+        let root = chain.current_block().state_root();
+        let mut pending = self.pending_state.lock().unwrap();
+        if pending.is_current(root) {
+            pending.apply(tx, &Balance::new(Interpreter::new(chain.state_at(root))));
+        }
+    }
+}
+
+/// Write the journal of the pool every `interval` it changed from a background thread, and a
+/// last time when the returned sender is signalled or dropped.
+pub fn start_journal(pool: Arc<RwLock<TxPoolManager>>, interval: Duration) -> mpsc::Sender<()> {
+    let (stop, signal) = mpsc::channel();
+    thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = signal.recv_timeout(interval) {
+            pool.write().expect("acquiring tx_pool write lock").flush_journal();
+        }
+        pool.write().expect("acquiring tx_pool write lock").flush_journal();
+    });
+    stop
+}
+
+/// File of the local transaction statuses kept with `journal`
+fn status_path(journal: &Path) -> PathBuf {
+    journal.with_extension("status")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use map_core::chain_spec::ChainSpec;
+    use map_core::test_utils::{self, BlockBuilder};
+
+    fn test_pool(name: &str, config: PoolConfig) -> TxPoolManager {
+        let dir = std::env::temp_dir().join(format!("map_pool_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        let mut chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        chain.load().unwrap();
+        TxPoolManager::with_config(Arc::new(TimedRwLock::new("chain", chain)), config)
+    }
+
+    /// Unsigned transfer of `sender` to itself. The hash leaves out the sender, the receiver
+    /// keeps the hashes of different senders apart.
+    fn tx(sender: u8, nonce: u64, price: u64) -> Transaction {
+        let address = test_utils::test_address(sender);
+        Transaction::transfer(address, nonce, price, 1000, address, 1)
+    }
+
+    #[test]
+    fn block_selection() {
+        let mut pool = test_pool("block_selection", PoolConfig {
+            block_txs: 3,
+            ..PoolConfig::default()
+        });
+        // Sender 1 pays more on its second transaction, which must still follow the first
+        pool.admit(tx(1, 2, 500)).unwrap();
+        pool.admit(tx(1, 1, 100)).unwrap();
+        // Same nonce and price from two senders, two transactions
+        assert_ne!(tx(2, 1, 300).hash(), tx(3, 1, 300).hash());
+        pool.admit(tx(2, 1, 300)).unwrap();
+        pool.admit(tx(3, 1, 300)).unwrap();
+
+        let first = if test_utils::test_address(2) < test_utils::test_address(3) { 2 } else { 3 };
+        let second = 5 - first;
+        assert_eq!(pool.pending_for_block(), vec![tx(first, 1, 300), tx(second, 1, 300), tx(1, 1, 100)]);
+
+        // Leftovers are picked for the next block
+        pool.remove_tx(tx(first, 1, 300).hash());
+        pool.remove_tx(tx(second, 1, 300).hash());
+        pool.remove_tx(tx(1, 1, 100).hash());
+        assert_eq!(pool.pending_for_block(), vec![tx(1, 2, 500)]);
+    }
+
+    #[test]
+    fn snapshot_unaffected_by_pool_changes() {
+        let mut pool = test_pool("snapshot", PoolConfig::default());
+        pool.admit(tx(1, 1, 100)).unwrap();
+        let snapshot = pool.snapshot();
+
+        pool.admit(tx(2, 1, 500)).unwrap();
+        pool.remove_tx(tx(1, 1, 100).hash());
+        assert_eq!(snapshot.pending_for_block(), vec![tx(1, 1, 100)]);
+        assert_eq!(pool.pending_for_block(), vec![tx(2, 1, 500)]);
+    }
+
+    #[test]
+    fn gas_price_floor() {
+        let pool = test_pool("gas_price_floor", PoolConfig::default());
+        assert_eq!(pool.min_gas_price(), ChainSpec::default().min_gas_price);
+
+        let mut pool = test_pool("gas_price_floor", PoolConfig {
+            min_gas_price: Some(100),
+            ..PoolConfig::default()
+        });
+        assert_eq!(pool.admit(tx(1, 1, 99)), Err(TxPoolError::BelowMinGasPrice(100)));
+        pool.admit(tx(1, 1, 100)).unwrap();
+        pool.admit(tx(1, 2, 300)).unwrap();
+        pool.admit(tx(2, 1, 200)).unwrap();
+
+        // A raised floor holds back the sender's later transactions too
+        pool.min_gas_price = 150;
+        assert_eq!(pool.pending_for_block(), vec![tx(2, 1, 200)]);
+    }
+
+    #[test]
+    fn lookup_by_hash() {
+        let mut pool = test_pool("lookup_by_hash", PoolConfig {
+            global_slots: 1,
+            ..PoolConfig::default()
+        });
+        pool.admit(tx(1, 1, 200)).unwrap();
+        pool.admit(tx(2, 1, 100)).unwrap();
+
+        // The pending transaction is listed first
+        assert_eq!(pool.tx_hashes(1), vec![tx(1, 1, 200).hash()]);
+        assert_eq!(pool.tx_hashes(10).len(), 2);
+        assert!(pool.contains(&tx(2, 1, 100).hash()));
+        assert_eq!(pool.get_tx(&tx(2, 1, 100).hash()), Some(tx(2, 1, 100)));
+        assert_eq!(pool.get_tx(&tx(2, 2, 100).hash()), None);
+        assert_eq!(pool.get_tx(&tx(3, 1, 100).hash()), None);
+    }
 
-        account.get_nonce()
+    #[test]
+    fn account_limit() {
+        let mut pool = test_pool("account_limit", PoolConfig {
+            account_slots: 2,
+            ..PoolConfig::default()
+        });
+        assert!(pool.admit(tx(1, 1, 1000)).is_ok());
+        assert_eq!(pool.admit(tx(1, 1, 1000)), Err(TxPoolError::AlreadyKnown(tx(1, 1, 1000).hash())));
+        assert!(pool.admit(tx(1, 2, 1000)).is_ok());
+        assert_eq!(pool.admit(tx(1, 3, 1000)), Err(TxPoolError::AccountLimit(2)));
+        assert!(pool.admit(tx(2, 1, 1000)).is_ok());
+    }
+
+    #[test]
+    fn evict_lowest_priced() {
+        let mut pool = test_pool("evict", PoolConfig {
+            global_slots: 2,
+            account_slots: 16,
+            global_queue: 1,
+            ..PoolConfig::default()
+        });
+        let cheap = tx(1, 1, 100);
+        let old = tx(2, 1, 200);
+        let young = tx(3, 1, 200);
+        pool.admit(cheap.clone()).unwrap();
+        pool.admit(old.clone()).unwrap();
+        pool.admit(young.clone()).unwrap();
+        assert_eq!(pool.pending_len(), 2);
+        assert_eq!(pool.all_transactions().len(), 3);
+
+        assert_eq!(pool.admit(tx(4, 1, 100)), Err(TxPoolError::Underpriced(100)));
+
+        // The cheapest goes first, then the oldest at equal price
+        pool.admit(tx(4, 1, 300)).unwrap();
+        assert!(!pool.all_transactions().contains(&cheap));
+        pool.admit(tx(5, 1, 300)).unwrap();
+        let all = pool.all_transactions();
+        assert!(!all.contains(&old));
+        assert!(all.contains(&young));
+    }
+
+    #[test]
+    fn admission_placement() {
+        let mut pool = test_pool("admission", PoolConfig {
+            global_slots: 2,
+            account_slots: 16,
+            global_queue: 1,
+            ..PoolConfig::default()
+        });
+        let first = pool.admit(tx(1, 1, 100)).unwrap();
+        assert_eq!(first, TxAdmission { hash: tx(1, 1, 100).hash(), status: TxStatus::Pending, position: 0, replaced: None });
+        assert_eq!(pool.admit(tx(2, 1, 50)).unwrap().position, 1);
+
+        let queued = pool.admit(tx(3, 1, 200)).unwrap();
+        assert_eq!((queued.status, queued.position), (TxStatus::Queued, 0));
+
+        let replacing = pool.admit(tx(4, 1, 300)).unwrap();
+        assert_eq!(replacing.replaced, Some(tx(2, 1, 50).hash()));
+        assert_eq!(pool.admit(tx(4, 1, 300)).unwrap_err().reason(), "already_known");
+    }
+
+    #[test]
+    fn locals_not_evicted() {
+        let mut pool = test_pool("locals", PoolConfig {
+            global_slots: 1,
+            account_slots: 16,
+            global_queue: 1,
+            ..PoolConfig::default()
+        });
+        pool.admit(tx(1, 1, 500)).unwrap();
+        pool.admit(tx(2, 1, 400)).unwrap();
+
+        // A cheap local transaction still replaces the cheapest remote one
+        let local = pool.admit_as(tx(3, 1, 10), true).unwrap();
+        assert_eq!(local.replaced, Some(tx(2, 1, 400).hash()));
+
+        // and is never picked for eviction
+        let remote = pool.admit(tx(4, 1, 1000)).unwrap();
+        assert_eq!(remote.replaced, Some(tx(1, 1, 500).hash()));
+        assert_eq!(pool.admit(tx(5, 1, 1)), Err(TxPoolError::Underpriced(1000)));
+        assert!(pool.contains(&tx(3, 1, 10).hash()));
+    }
+
+    #[test]
+    fn local_slots() {
+        let mut pool = test_pool("local_slots", PoolConfig {
+            global_slots: 1,
+            global_queue: 1,
+            local_slots: 1,
+            ..PoolConfig::default()
+        });
+        let key = test_utils::genesis_key();
+        let first = test_utils::transfer(&key, 1, test_utils::test_address(1), 10);
+        let second = test_utils::transfer(&key, 2, test_utils::test_address(1), 10);
+        pool.add_local_tx(first.clone()).unwrap();
+        pool.add_local_tx(second.clone()).unwrap();
+        assert!(pool.locals.contains(&first.hash()));
+        assert!(!pool.locals.contains(&second.hash()));
+
+        // Over the local slots a transaction competes on price like a remote one
+        let remote = pool.admit(tx(3, 1, 1_000_000)).unwrap();
+        assert_eq!(remote.replaced, Some(second.hash()));
+    }
+
+    #[test]
+    fn local_journal() {
+        let journal = std::env::temp_dir().join("map_pool_test_transactions.journal");
+        let _ = fs::remove_file(&journal);
+        let config = PoolConfig {
+            journal: Some(journal.clone()),
+            ..PoolConfig::default()
+        };
+        let local = test_utils::transfer(&test_utils::genesis_key(), 1, test_utils::test_address(1), 10);
+        let mut pool = test_pool("journal", config.clone());
+        pool.add_local_tx(local.clone()).unwrap();
+        // Written on the next flush only
+        assert!(!journal.exists());
+        pool.flush_journal();
+
+        let mut restarted = test_pool("journal_restarted", config);
+        restarted.load_journal();
+        assert_eq!(restarted.pending_locals(), vec![local.clone()]);
+
+        restarted.remove_tx(local.hash());
+        restarted.flush_journal();
+        let mut emptied = test_pool("journal_emptied", restarted.config.clone());
+        emptied.load_journal();
+        assert!(emptied.pending_locals().is_empty());
+    }
+
+    #[test]
+    fn track_locals() {
+        let journal = std::env::temp_dir().join("map_pool_test_track_locals.journal");
+        let _ = fs::remove_file(&journal);
+        let _ = fs::remove_file(status_path(&journal));
+        let config = PoolConfig { journal: Some(journal), ..PoolConfig::default() };
+        let mut pool = test_pool("track_locals", config.clone());
+        let key = test_utils::genesis_key();
+        let included = test_utils::transfer(&key, 1, test_utils::test_address(1), 10);
+        let replaced = test_utils::transfer(&key, 2, test_utils::test_address(1), 10);
+        pool.add_local_tx(included.clone()).unwrap();
+        pool.add_local_tx(replaced.clone()).unwrap();
+        assert_eq!(pool.tx_status(&included.hash()), Some(TrackedStatus::Pending));
+
+        // Another transaction with the nonce of `replaced` makes it into the block
+        let other = test_utils::transfer(&key, 2, test_utils::test_address(2), 10);
+        let block = {
+            let mut chain = pool.blockchain.write().unwrap();
+            let head = chain.current_block();
+            let mut block = BlockBuilder::on(&head).txs(vec![included.clone(), other]).build();
+            block.set_state_root(chain.apply_transactions(head.state_root(), &block));
+            chain.import_block(&block).unwrap();
+            block
+        };
+        pool.reset_pool(&block);
+        pool.flush_journal();
+
+        let included_status = TrackedStatus::Included { block: block.hash(), height: 1, index: 0 };
+        let dropped_status = TrackedStatus::Dropped { reason: "nonce_used".to_string() };
+        assert_eq!(pool.tx_status(&included.hash()), Some(included_status.clone()));
+        assert_eq!(pool.tx_status(&replaced.hash()), Some(dropped_status.clone()));
+
+        // The statuses outlive a restart
+        let mut restarted = TxPoolManager::with_config(pool.blockchain.clone(), config.clone());
+        restarted.load_journal();
+        assert_eq!(restarted.tx_status(&included.hash()), Some(included_status));
+        assert_eq!(restarted.tx_status(&replaced.hash()), Some(dropped_status.clone()));
+
+        // On a chain without the block, the transaction can't be added again
+        let mut unwound = test_pool("track_locals_unwound", config);
+        unwound.load_journal();
+        assert_eq!(unwound.tx_status(&included.hash()), Some(TrackedStatus::Dropped { reason: "unwound".to_string() }));
+        assert_eq!(unwound.tx_status(&replaced.hash()), Some(dropped_status));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pool_events() {
+        let mut pool = test_pool("pool_events", PoolConfig {
+            global_slots: 1,
+            account_slots: 16,
+            global_queue: 0,
+            ..PoolConfig::default()
+        });
+        let events = pool.events().subscribe();
+        let key = test_utils::genesis_key();
+        let included = test_utils::transfer(&key, 1, test_utils::test_address(1), 10);
+        let cheap = tx(2, 1, 1);
+        pool.admit(cheap.clone()).unwrap();
+        pool.admit(included.clone()).unwrap();
+
+        let block = {
+            let mut chain = pool.blockchain.write().unwrap();
+            let head = chain.current_block();
+            let mut block = BlockBuilder::on(&head).txs(vec![included.clone()]).build();
+            block.set_state_root(chain.apply_transactions(head.state_root(), &block));
+            chain.import_block(&block).unwrap();
+            block
+        };
+        pool.reset_pool(&block);
+
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![
+            PoolEvent::Added { hash: cheap.hash(), status: TxStatus::Pending },
+            PoolEvent::Added { hash: included.hash(), status: TxStatus::Pending },
+            PoolEvent::Replaced { hash: cheap.hash(), by: included.hash() },
+            PoolEvent::Included { hash: included.hash(), block: block.hash(), height: 1, index: 0 },
+        ]);
+    }
+
+    #[test]
+    fn reserve_after_pool_nonces() {
+        let mut pool = test_pool("reserve_after_pool_nonces", PoolConfig::default());
+        let sender = test_utils::address_of(&test_utils::genesis_key());
+        pool.admit(test_utils::transfer(&test_utils::genesis_key(), 1, test_utils::test_address(1), 10)).unwrap();
+        assert_eq!(pool.reserve_nonces(&sender, 3), Ok((2, 4)));
+        assert_eq!(pool.reserve_nonces(&sender, 1), Ok((5, 5)));
+        assert_eq!(pool.reserve_nonces(&sender, ACCOUNT_SLOTS as u64 + 1), Err(TxPoolError::AccountLimit(ACCOUNT_SLOTS)));
+        // The outstanding range is capped, not just a single call
+        assert_eq!(pool.reserve_nonces(&sender, ACCOUNT_SLOTS as u64 - 5), Ok((6, 16)));
+        assert_eq!(pool.reserve_nonces(&sender, 1), Ok((17, 17)));
+        assert_eq!(pool.reserve_nonces(&sender, 1), Err(TxPoolError::AccountLimit(ACCOUNT_SLOTS)));
+    }
+
+    #[test]
+    fn audit_used_nonces() {
+        let mut pool = test_pool("audit_used_nonces", PoolConfig::default());
+        let included = test_utils::transfer(&test_utils::genesis_key(), 1, test_utils::test_address(1), 10);
+        pool.add_local_tx(included.clone()).unwrap();
+        assert_eq!(pool.audit(), vec![]);
+
+        let block = {
+            let mut chain = pool.blockchain.write().unwrap();
+            let head = chain.current_block();
+            let mut block = BlockBuilder::on(&head).txs(vec![included.clone()]).build();
+            block.set_state_root(chain.apply_transactions(head.state_root(), &block));
+            chain.import_block(&block).unwrap();
+            block
+        };
+        // Not held against the pool until it hears of the block
+        assert_eq!(pool.audit(), vec![]);
+        pool.reset_pool(&block);
+        assert_eq!(pool.audit(), vec![]);
+
+        // A transaction left behind by the reset, and a status past the block's transactions
+        pool.pool.insert(included.hash(), included.clone());
+        pool.tracker.on_event(&PoolEvent::Included { hash: included.hash(), block: block.hash(), height: 1, index: 1 });
+        assert_eq!(pool.audit().iter().map(|v| v.check).collect::<Vec<_>>(), vec!["tx_index", "pool_nonce"]);
+    }
+
+    #[test]
+    fn pending_nonces() {
+        let mut pool = test_pool("pending_nonces", PoolConfig::default());
+        let key = test_utils::genesis_key();
+        let sender = test_utils::address_of(&key);
+        let first = test_utils::transfer(&key, 1, test_utils::test_address(1), 10);
+        pool.add_tx(first.clone()).unwrap();
+        pool.add_tx(test_utils::transfer(&key, 2, test_utils::test_address(1), 10)).unwrap();
+        assert_eq!(pool.get_nonce(&sender), 2);
+        assert_eq!(pool.add_tx(test_utils::transfer(&key, 4, test_utils::test_address(1), 10)),
+            Err(TxPoolError::InvalidNonce { expected: 3, got: 4 }));
+
+        let balance = pool.pending_account(&sender).balance;
+        assert_eq!(pool.add_tx(test_utils::transfer(&key, 3, test_utils::test_address(1), balance)).unwrap_err().reason(),
+            "insufficient_funds");
+
+        // The second transaction can't execute once the first left the pool
+        pool.remove_tx(first.hash());
+        assert_eq!(pool.get_nonce(&sender), 0);
+    }
+
+    #[test]
+    fn reject_unexecutable() {
+        let mut pool = test_pool("reject_unexecutable", PoolConfig::default());
+        let key = test_utils::genesis_key();
+        let sender = test_utils::address_of(&key);
+        for call in vec![b"governance.vote".to_vec(), Vec::new()] {
+            let mut tx = Transaction::new(sender, 1, 1000, 1000, call, Vec::new());
+            tx.sign(&key.to_bytes()).unwrap();
+            assert_eq!(pool.add_tx(tx).unwrap_err().reason(), "unknown_call");
+        }
+
+        // Signed for the domain of its call before the network scheduled signing domains
+        let mut tx = test_utils::transfer(&key, 1, test_utils::test_address(1), 10);
+        tx.sign_at(&key.to_bytes(), &Rules { signing_domains: true, ..Rules::default() }).unwrap();
+        assert_eq!(pool.add_tx(tx), Err(TxPoolError::InvalidSignature));
+        assert!(pool.add_tx(test_utils::transfer(&key, 1, test_utils::test_address(1), 10)).is_ok());
+    }
+
+    #[test]
+    fn promote_queued() {
+        let mut pool = test_pool("promote", PoolConfig {
+            global_slots: 1,
+            account_slots: 16,
+            global_queue: 4,
+            ..PoolConfig::default()
+        });
+        let first = tx(1, 1, 100);
+        let queued = tx(2, 1, 100);
+        pool.admit(first.clone()).unwrap();
+        pool.admit(queued.clone()).unwrap();
+        assert_eq!(pool.get_pending(), vec![first.clone()]);
+
+        pool.remove_tx(first.hash());
+        assert_eq!(pool.get_pending(), vec![queued]);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Status of the transactions submitted to this node, followed from the pool events so that
+//! wallets can ask once instead of polling the chain.
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use map_core::types::Hash;
+use crate::events::PoolEvent;
+
+/// Max number of tracked transactions, the oldest submitted is forgotten beyond it.
+pub const MAX_TRACKED_TXS: usize = 16384;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TrackedStatus {
+    /// In the pool, not yet in a canonical block
+    Pending,
+    /// In a canonical block, at `index` of its transactions, until the block is rolled back
+    Included { block: Hash, height: u64, index: usize },
+    /// Left the pool without being included, for the reason of its `PoolEvent::Dropped`
+    Dropped { reason: String },
+}
+
+#[derive(Default)]
+pub struct TxTracker {
+    status: HashMap<Hash, TrackedStatus>,
+    /// Tracked hashes, oldest first
+    order: VecDeque<Hash>,
+}
+
+impl TxTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a transaction accepted into the pool.
+    pub fn track(&mut self, hash: Hash) {
+        if self.status.insert(hash, TrackedStatus::Pending).is_some() {
+            return;
+        }
+        if self.order.len() == MAX_TRACKED_TXS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.status.remove(&oldest);
+            }
+        }
+        self.order.push_back(hash);
+    }
+
+    /// Follow a tracked transaction leaving the pool.
+    pub fn on_event(&mut self, event: &PoolEvent) {
+        match event {
+            PoolEvent::Included { hash, block, height, index } => {
+                if let Some(status) = self.status.get_mut(hash) {
+                    *status = TrackedStatus::Included { block: *block, height: *height, index: *index };
+                }
+            }
+            PoolEvent::Dropped { hash, reason } => self.drop_tx(hash, reason),
+            PoolEvent::Replaced { hash, .. } => self.drop_tx(hash, "replaced"),
+            PoolEvent::Added { .. } => {}
+        }
+    }
+
+    /// Track an included transaction as pending again, its block having left the canonical chain.
+    pub fn unwind(&mut self, hash: &Hash) {
+        if let Some(status) = self.status.get_mut(hash) {
+            *status = TrackedStatus::Pending;
+        }
+    }
+
+    /// Mark a pending transaction as dropped, transactions already included stay so.
+    fn drop_tx(&mut self, hash: &Hash, reason: &str) {
+        if let Some(status) = self.status.get_mut(hash) {
+            if *status == TrackedStatus::Pending {
+                *status = TrackedStatus::Dropped { reason: reason.to_string() };
+            }
+        }
+    }
+
+    /// Status of a transaction, None if it was not submitted to this node or is forgotten.
+    pub fn status(&self, hash: &Hash) -> Option<TrackedStatus> {
+        self.status.get(hash).cloned()
+    }
+
+    /// Hashes of the included transactions with their block, height and index
+    pub fn included(&self) -> Vec<(Hash, Hash, u64, usize)> {
+        self.status.iter().filter_map(|(hash, status)| match status {
+            TrackedStatus::Included { block, height, index } => Some((*hash, *block, *height, *index)),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.status.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.status.is_empty()
+    }
+
+    /// Tracker saved by `save`, empty if there is none or it can't be read.
+    pub fn load(path: &Path) -> Self {
+        let tracked: Vec<(Hash, TrackedStatus)> = match fs::read(path).map(|raw| bincode::deserialize(&raw)) {
+            Ok(Ok(tracked)) => tracked,
+            Ok(Err(e)) => {
+                warn!("Invalid transaction status file {}: {}", path.display(), e);
+                return Self::new();
+            }
+            Err(_) => return Self::new(),
+        };
+        let mut tracker = Self::new();
+        for (hash, status) in tracked {
+            tracker.track(hash);
+            tracker.status.insert(hash, status);
+        }
+        tracker
+    }
+
+    /// Write the statuses oldest first, replacing the file at once.
+    pub fn save(&self, path: &Path) {
+        let tracked: Vec<(Hash, TrackedStatus)> = self.order.iter()
+            .filter_map(|hash| self.status.get(hash).map(|status| (*hash, status.clone())))
+            .collect();
+        let raw = bincode::serialize(&tracked).expect("transaction statuses serialize");
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        if let Err(e) = fs::write(&tmp, raw).and_then(|_| fs::rename(&tmp, path)) {
+            warn!("Failed to write transaction status file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn included_and_dropped() {
+        let (tx, other, replaced) = (Hash([1; 32]), Hash([2; 32]), Hash([3; 32]));
+        let mut tracker = TxTracker::new();
+        tracker.track(tx);
+        tracker.track(other);
+        tracker.track(replaced);
+        assert_eq!(tracker.status(&tx), Some(TrackedStatus::Pending));
+
+        let block = Hash([7; 32]);
+        tracker.on_event(&PoolEvent::Included { hash: tx, block, height: 4, index: 1 });
+        tracker.on_event(&PoolEvent::Dropped { hash: tx, reason: "nonce_used" });
+        tracker.on_event(&PoolEvent::Dropped { hash: other, reason: "invalid_signature" });
+        tracker.on_event(&PoolEvent::Replaced { hash: replaced, by: tx });
+        assert_eq!(tracker.status(&tx), Some(TrackedStatus::Included { block, height: 4, index: 1 }));
+        assert_eq!(tracker.status(&other), Some(TrackedStatus::Dropped { reason: "invalid_signature".to_string() }));
+        assert_eq!(tracker.status(&replaced), Some(TrackedStatus::Dropped { reason: "replaced".to_string() }));
+        assert_eq!(tracker.status(&Hash([9; 32])), None);
+
+        let path = std::env::temp_dir().join("map_pool_test_tracker.status");
+        tracker.save(&path);
+        let loaded = TxTracker::load(&path);
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.status(&tx), tracker.status(&tx));
+        assert_eq!(loaded.order, tracker.order);
+    }
+
+    #[test]
+    fn forget_oldest() {
+        let mut tracker = TxTracker::new();
+        for i in 0..=MAX_TRACKED_TXS as u32 {
+            let mut hash = [0u8; 32];
+            hash[..4].copy_from_slice(&i.to_be_bytes());
+            tracker.track(Hash(hash));
+        }
+        assert_eq!(tracker.len(), MAX_TRACKED_TXS);
+        assert_eq!(tracker.status(&Hash([0; 32])), None);
+    }
+}
@@ -1,129 +1,278 @@
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use jsonrpc_core::Result;
+use jsonrpc_core::{Error, ErrorCode, Result, Value};
 use jsonrpc_derive::rpc;
-use bincode;
 use tokio::sync::mpsc;
 
-use pool::tx_pool::TxPoolManager;
+use pool::nonce_reservations::RESERVATION_TTL;
+use pool::tx_pool::{TxPoolError, TxPoolManager};
 use network::manager::{self, NetworkMessage};
-use ed25519::{privkey::PrivKey};
-use map_core::transaction::{Transaction, balance_msg};
-use map_core::types::Address;
+use ed25519::{privkey::PrivKey, pubkey::Pubkey, signature::SignatureInfo};
+use map_core::transaction::Transaction;
+use map_core::transaction_json::TransactionJson;
+use map_core::types::{Address, AddressScheme, Hash};
+
+use crate::types::admission::{NonceRangeJson, TxAdmissionJson, TxInputError, TxRejectionJson};
 
 /// AccountManager rpc interface.
 #[rpc(server)]
 pub trait AccountManager {
     /// Send transaction.
     /// curl -d '{"id": 2, "jsonrpc": "2.0", "method":"map_sendTransaction","params": ["0xd2480451ef35ff2fdd7c69cad058719b9dc4d631","0x0000000000000000000000000000000000000011",100000]}' -H 'content-type:application/json' 'http://localhost:9545'
+    /// Parameters are decoded by the method to report the offending one, `value` is a number or
+    /// a decimal string.
     #[rpc(name = "map_sendTransaction")]
-    fn send_transaction(&self, from: String, to: String, value: u128) -> Result<String>;
+    fn send_transaction(&self, from: Value, to: Value, value: Value) -> Result<TxAdmissionJson>;
+
+    /// Submit a transaction signed by the caller, in the JSON form of `map_core::transaction_json`.
+    #[rpc(name = "map_sendRawTransaction")]
+    fn send_raw_transaction(&self, tx: TransactionJson) -> Result<TxAdmissionJson>;
+
+    /// Reserve `count` consecutive nonces of `addr` after its pool transactions and the nonces
+    /// reserved before, so clients sending concurrently from one key don't collide. The request
+    /// is signed by the key of `addr` and valid once, until `expires`.
+    #[rpc(name = "map_reserveNonces")]
+    fn reserve_nonces(&self, addr: Address, count: u64, expires: u64, signature: String) -> Result<NonceRangeJson>;
 }
 
 /// AccountManager rpc implementation.
 pub struct AccountManagerImpl {
     tx_pool: Arc<RwLock<TxPoolManager>>,
     accounts: HashMap<Address, PrivKey>,
+    address_scheme: AddressScheme,
     network_send: mpsc::UnboundedSender<NetworkMessage>,
+    /// Signed reservation requests already served, until they expire
+    reservation_requests: Mutex<HashMap<Hash, u64>>,
 }
 
 impl AccountManagerImpl {
     /// Creates new AccountManagerImpl.
     pub fn new(tx_pool: Arc<RwLock<TxPoolManager>>, key: String, network_send: mpsc::UnboundedSender<NetworkMessage>) -> Self {
         let mut accounts = HashMap::new();
+        let address_scheme = tx_pool.read().expect("acquiring tx_pool read lock").address_scheme();
 
         if key != "" {
             let priv_key = PrivKey::from_hex(key.as_str()).expect("private ok");
             let pubkey = priv_key.to_pubkey().expect("pub key ok");
-            let address = Address::from(pubkey);
+            let address = Address::from_pubkey(&pubkey, address_scheme);
             accounts.insert(address, priv_key);
         }
 
         AccountManagerImpl {
             tx_pool,
             accounts,
+            address_scheme,
             network_send: network_send,
+            reservation_requests: Mutex::new(HashMap::new()),
         }
     }
 }
 
 impl AccountManager for AccountManagerImpl {
-    fn send_transaction(&self, from: String, to: String, value: u128) -> Result<String> {
-        if !is_hex(from.as_str()).is_ok() {
-            return Ok(format!("from address is not hex {}", from));
-        }
-        if !is_hex(to.as_str()).is_ok() {
-            return Ok(format!("to address is not hex {}", to));
-        }
-
-        let from = match Address::from_hex(&from) {
-            Ok(v) => v,
-            Err(e) => return Ok(format!("convert address err  {} {}", &from, e))
-        };
-
-        let to = match Address::from_hex(&to) {
-            Ok(v) => v,
-            Err(e) => return Ok(format!("convert address err  {} {}", &to, e))
-        };
-
+    fn send_transaction(&self, from: Value, to: Value, value: Value) -> Result<TxAdmissionJson> {
+        let from = parse_address("from", &from).map_err(input_error)?;
+        let to = parse_address("to", &to).map_err(input_error)?;
+        let value = parse_value(&value).map_err(input_error)?;
         let priv_key = match self.accounts.get(&from) {
             Some(v) => v,
-            None => return Ok(format!("account no exist {}", from)),
+            None => return Err(input_error(TxInputError::UnknownAccount)),
         };
 
-        let nonce = self.tx_pool.read().expect("acquiring tx pool read lock").get_nonce(&from);
-        let input: Vec<u8> = bincode::serialize(&balance_msg::MsgTransfer{
-            receiver: to,
-            value: value}).unwrap();
+        let (nonce, floor, rules) = {
+            let pool = self.tx_pool.read().expect("acquiring tx pool read lock");
+            (pool.get_nonce(&from), pool.min_gas_price(), pool.next_rules())
+        };
+        let mut tx = Transaction::transfer(from, nonce + 1, floor.max(1000), 1000, to, value);
 
-        let mut tx = Transaction::new(from, nonce + 1, 1000, 1000, b"balance.transfer".to_vec(), input);
+        tx.sign_at(&priv_key.to_bytes(), &rules).expect("sign ok");
+        let admission = self.tx_pool.write().expect("acquiring tx_pool write_lock")
+            .add_local_tx(tx.clone())
+            .map_err(pool_error)?;
+        manager::publish_transaction(&mut self.network_send.clone(), tx);
+        Ok(admission.into())
+    }
 
-        tx.sign(&priv_key.to_bytes()).expect("sign ok");
-        if self.tx_pool.write().expect("acquiring tx_pool write_lock").add_tx(tx.clone()) {
-            manager::publish_transaction(&mut self.network_send.clone(), tx.clone())
+    fn send_raw_transaction(&self, tx: TransactionJson) -> Result<TxAdmissionJson> {
+        let tx = Transaction::try_from(tx)
+            .map_err(|e| Error::invalid_params(format!("invalid transaction {}", e)))?;
+        let rules = self.tx_pool.read().expect("acquiring tx_pool read lock").next_rules();
+        tx.verify_sign_at(&rules)
+            .map_err(|e| Error::invalid_params(format!("invalid signature {}", e)))?;
+        if Address::from_pubkey(&Pubkey::from_bytes(&tx.sign_data.2), self.address_scheme) != tx.sender {
+            return Err(Error::invalid_params("signer does not match sender"));
         }
-        Ok(format!("{}", tx.hash()))
+
+        let admission = self.tx_pool.write().expect("acquiring tx_pool write_lock")
+            .add_local_tx(tx.clone())
+            .map_err(pool_error)?;
+        manager::publish_transaction(&mut self.network_send.clone(), tx);
+        Ok(admission.into())
     }
-}
 
-fn is_hex(hex: &str) -> core::result::Result<(), String> {
-    let tmp = hex.as_bytes();
-    if tmp.len() < 2 {
-        Err("Must be a 0x-prefix hex string".to_string())
-    } else if tmp.len() & 1 != 0 {
-        Err("Hex strings must be of even length".to_string())
-    } else if tmp[..2] == b"0x"[..] {
-        for byte in &tmp[2..] {
-            match byte {
-                b'A'..=b'F' | b'a'..=b'f' | b'0'..=b'9' => continue,
-                invalid_char => {
-                    return Err(format!("Hex has invalid char: {}", invalid_char));
-                }
+    fn reserve_nonces(&self, addr: Address, count: u64, expires: u64, signature: String) -> Result<NonceRangeJson> {
+        if count == 0 {
+            return Err(Error::invalid_params("count must be at least 1"));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let request = verify_reservation(&addr, count, expires, &signature, now, self.address_scheme)?;
+        {
+            let mut served = self.reservation_requests.lock().expect("acquiring reservation requests lock");
+            served.retain(|_, expires| *expires >= now);
+            if served.insert(request, expires).is_some() {
+                return Err(Error::invalid_params("reservation request already served"));
             }
         }
-        Ok(())
-    } else {
-        Err("Must 0x-prefix hex string".to_string())
+        let (first, last) = self.tx_pool.write().expect("acquiring tx_pool write_lock")
+            .reserve_nonces(&addr, count)
+            .map_err(pool_error)?;
+        Ok(NonceRangeJson { first, last, expires_in: RESERVATION_TTL.as_secs() })
+    }
+}
+
+/// Message signed by the key of `addr` to reserve `count` of its nonces until `expires`: the
+/// blake2b-256 hash of `map_reserveNonces`, the address, count and expiry as little endian u64.
+pub fn reservation_message(addr: &Address, count: u64, expires: u64) -> Hash {
+    let mut pre_image = b"map_reserveNonces".to_vec();
+    pre_image.extend_from_slice(addr.as_slice());
+    pre_image.extend_from_slice(&count.to_le_bytes());
+    pre_image.extend_from_slice(&expires.to_le_bytes());
+    Hash::make_hash(&pre_image)
+}
+
+/// Check a reservation request is signed by the key of `addr` under `scheme` and expires within
+/// the reservation time from `now`, returns its message.
+fn verify_reservation(addr: &Address, count: u64, expires: u64, signature: &str, now: u64, scheme: AddressScheme) -> Result<Hash> {
+    if expires < now || expires > now + RESERVATION_TTL.as_secs() {
+        return Err(Error::invalid_params(format!(
+            "expires must be within {} seconds from now", RESERVATION_TTL.as_secs())));
+    }
+    let raw = hex::decode(signature.trim_start_matches("0x"))
+        .map_err(|e| Error::invalid_params(format!("invalid signature {}", e)))?;
+    if raw.len() != 96 {
+        return Err(Error::invalid_params("signature must be 96 bytes of r, s and the public key"));
+    }
+    let sign = SignatureInfo::from_slice(&raw)
+        .map_err(|e| Error::invalid_params(format!("invalid signature {}", e)))?;
+    let signer = Pubkey::from_bytes(sign.p());
+    if Address::from_pubkey(&signer, scheme) != *addr {
+        return Err(Error::invalid_params("signer does not match address"));
+    }
+    let message = reservation_message(addr, count, expires);
+    signer.verify(&message.to_msg(), &sign)
+        .map_err(|e| Error::invalid_params(format!("invalid signature {}", e)))?;
+    Ok(message)
+}
+
+fn parse_address(field: &'static str, param: &Value) -> std::result::Result<Address, TxInputError> {
+    let text = param.as_str().ok_or_else(|| TxInputError::InvalidAddress {
+        field,
+        detail: format!("expected a hex string, got {}", param),
+    })?;
+    text.parse::<Address>().map_err(|e| TxInputError::InvalidAddress { field, detail: e.to_string() })
+}
+
+/// Amount as a json integer or a decimal string, numbers above u64 lose precision in json
+fn parse_value(param: &Value) -> std::result::Result<u128, TxInputError> {
+    match param {
+        Value::Number(n) => match n.as_u64() {
+            Some(value) => Ok(value as u128),
+            None if n.as_f64().map_or(false, |f| f >= 1.0 && f.fract() == 0.0) =>
+                Err(TxInputError::InvalidValue(format!("{} above {} must be a decimal string", n, u64::max_value()))),
+            None => Err(TxInputError::InvalidValue(n.to_string())),
+        },
+        Value::String(text) if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) =>
+            text.parse::<u128>().map_err(|_| TxInputError::ValueOverflow(text.clone())),
+        _ => Err(TxInputError::InvalidValue(param.to_string())),
+    }
+}
+
+/// Maps an undecodable parameter to an invalid params error, the details are in `data`.
+fn input_error(e: TxInputError) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message: e.to_string(),
+        data: serde_json::to_value(TxRejectionJson::from(&e)).ok(),
+    }
+}
+
+/// Maps a pool rejection to a json rpc error with a code per reason, the details are in `data`.
+fn pool_error(e: TxPoolError) -> Error {
+    let code = match e {
+        TxPoolError::AlreadyKnown(_) => -32010,
+        TxPoolError::InsufficientFunds { .. } => -32011,
+        TxPoolError::InvalidNonce { .. } => -32012,
+        TxPoolError::AccountLimit(_) => -32013,
+        TxPoolError::Underpriced(_) => -32014,
+        TxPoolError::ValueOverflow(_) => -32015,
+        TxPoolError::BelowMinGasPrice(_) => -32016,
+        TxPoolError::UnknownCall(_) => -32017,
+        TxPoolError::InvalidSignature => -32018,
+    };
+    Error {
+        code: ErrorCode::ServerError(code),
+        message: e.to_string(),
+        data: serde_json::to_value(TxRejectionJson::from(&e)).ok(),
     }
 }
 
 #[cfg(test)]
 mod account {
     use super::*;
+    use serde_json::json;
     use ed25519::{privkey::PrivKey, pubkey::Pubkey};
     use map_core::genesis::{ed_genesis_priv_key, ed_genesis_pub_key};
 
     #[test]
-    fn test_is_hex() {
+    fn test_genesis_address() {
         {
             let pkey = PrivKey::from_bytes(&ed_genesis_priv_key);
             let pk = Pubkey::from_bytes(&ed_genesis_pub_key);
-            let address = Address::from(pk);
+            let address = Address::from_pubkey(&pk, AddressScheme::Blake2b);
             println!("{}", pkey.to_string());
             println!("decode {}", PrivKey::from_hex("0xf9cb7ea173840aeba4fc8146743464cdae3e5527414872155fe331bd2a3454a2").unwrap().to_string());
-            assert_eq!("d2480451ef35ff2fdd7c69cad058719b9dc4d631", address.to_string().as_str());
-            assert!(is_hex("0xd2480451ef35ff2fdd7c69cad058719b9dc4d631").is_ok())
+            assert_eq!("0xd2480451ef35ff2fdd7c69cad058719b9dc4d631", address.to_string().as_str());
+            assert_eq!("0xd2480451ef35ff2fdd7c69cad058719b9dc4d631".parse::<Address>().unwrap(), address);
         }
     }
+
+    #[test]
+    fn test_input_errors() {
+        assert!(parse_address("to", &json!("0xd2480451ef35ff2fdd7c69cad058719b9dc4d631")).is_ok());
+        let e = parse_address("to", &json!("0xd2480451ef35ff2fdd7c69cad058719b9dc4d6")).unwrap_err();
+        assert_eq!((e.reason(), e.field()), ("invalid_address", "to"));
+        assert_eq!(parse_address("from", &json!(1)).unwrap_err().field(), "from");
+
+        assert_eq!(parse_value(&json!(100000)), Ok(100000));
+        assert_eq!(parse_value(&json!("340282366920938463463374607431768211455")), Ok(u128::max_value()));
+        assert_eq!(parse_value(&json!("340282366920938463463374607431768211456")).unwrap_err().reason(), "value_overflow");
+        assert_eq!(parse_value(&json!(-1)).unwrap_err().reason(), "invalid_value");
+        assert_eq!(parse_value(&json!(1.5)).unwrap_err().reason(), "invalid_value");
+
+        let error = input_error(TxInputError::UnknownAccount);
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert_eq!(error.data.unwrap()["field"], "from");
+    }
+
+    #[test]
+    fn signed_reservation() {
+        let key = PrivKey::from_bytes(&ed_genesis_priv_key);
+        let addr = Address::from_pubkey(&key.to_pubkey().unwrap(), AddressScheme::default());
+        let now = 1_000_000;
+        let expires = now + 60;
+        let sign = key.sign(reservation_message(&addr, 4, expires).to_slice()).unwrap();
+        let signature = hex::encode([sign.r(), sign.s(), sign.p()].concat());
+
+        assert_eq!(verify_reservation(&addr, 4, expires, &signature, now, AddressScheme::default()), Ok(reservation_message(&addr, 4, expires)));
+        // Signed for another count, address or time
+        assert!(verify_reservation(&addr, 5, expires, &signature, now, AddressScheme::default()).is_err());
+        assert!(verify_reservation(&Address([1; 20]), 4, expires, &signature, now, AddressScheme::default()).is_err());
+        assert!(verify_reservation(&addr, 4, expires, &signature, expires + 1, AddressScheme::default()).is_err());
+        assert!(verify_reservation(&addr, 4, now + 3600, &signature, now, AddressScheme::default()).is_err());
+        assert!(verify_reservation(&addr, 4, expires, "0x00", now, AddressScheme::default()).is_err());
+        // The key derives another address on a keccak256 chain
+        assert!(verify_reservation(&addr, 4, expires, &signature, now, AddressScheme::Keccak256).is_err());
+    }
 }
@@ -0,0 +1,128 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use jsonrpc_core::{Error, Result};
+use jsonrpc_derive::rpc;
+use tokio::sync::mpsc;
+
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use network::manager::NetworkMessage;
+use network::PeerId;
+use network::peer_stats::{PeerAgentInfo, PeerMessageStats, SharedPeerStats};
+use network::time_sync::{ClockReport, SharedClockOffsets};
+use network::topic_peers::{SharedTopicPeers, TopicPeersReport};
+use pool::tx_pool::TxPoolManager;
+
+use crate::http_server::{RpcReload, RpcRestarter};
+use crate::types::rewind::HeadRewindJson;
+
+#[rpc(server)]
+pub trait AdminRpc {
+    /// Disconnect a peer and refuse its connections for `duration` seconds.
+    /// The ban is kept in the network directory across restarts.
+    #[rpc(name = "admin_banPeer")]
+    fn ban_peer(&self, peer: String, duration: u64) -> Result<bool>;
+
+    /// Lift the ban of a peer.
+    #[rpc(name = "admin_unbanPeer")]
+    fn unban_peer(&self, peer: String) -> Result<bool>;
+
+    /// Requests, responses, errors, bytes and latency exchanged with each connected peer, by
+    /// p2p protocol.
+    #[rpc(name = "admin_peerStats")]
+    fn peer_stats(&self) -> Result<Vec<PeerMessageStats>>;
+
+    /// Node software announced by each connected peer in the handshake and identify protocol.
+    #[rpc(name = "admin_peers")]
+    fn peers(&self) -> Result<Vec<PeerAgentInfo>>;
+
+    /// Offset and round trip of the clock of each connected peer to ours, and their median,
+    /// to tell missed slots caused by a drifting clock.
+    #[rpc(name = "admin_clockOffsets")]
+    fn clock_offsets(&self) -> Result<ClockReport>;
+
+    /// Connected peers subscribed to each gossip topic, which bound the gossipsub mesh of the
+    /// topic, and whether the block topic runs short of them.
+    #[rpc(name = "admin_gossipTopics")]
+    fn gossip_topics(&self) -> Result<TopicPeersReport>;
+
+    /// Move the rpc server to a new address, port, cors or vhosts settings without stopping the
+    /// node. Returns the new listen url, the server is unchanged if it can't be started.
+    #[rpc(name = "admin_restartRpc")]
+    fn restart_rpc(&self, config: RpcReload) -> Result<String>;
+
+    /// Do the head rollback held at startup for exceeding the max reorg depth. Returns the
+    /// rollback done, or null if none was held.
+    #[rpc(name = "admin_allowDeepReorg")]
+    fn allow_deep_reorg(&self) -> Result<Option<HeadRewindJson>>;
+}
+
+pub(crate) struct AdminRpcImpl {
+    pub network_send: mpsc::UnboundedSender<NetworkMessage>,
+    pub peer_stats: SharedPeerStats,
+    pub clock_offsets: SharedClockOffsets,
+    pub topic_peers: SharedTopicPeers,
+    pub rpc_restarter: RpcRestarter,
+    pub block_chain: Arc<TimedRwLock<BlockChain>>,
+    pub tx_pool: Arc<RwLock<TxPoolManager>>,
+}
+
+impl AdminRpcImpl {
+    fn send(&self, message: NetworkMessage) -> Result<bool> {
+        self.network_send
+            .clone()
+            .try_send(message)
+            .map_err(|e| Error::invalid_params(format!("network unavailable {}", e)))?;
+        Ok(true)
+    }
+}
+
+fn parse_peer(peer: &str) -> Result<PeerId> {
+    peer.parse::<PeerId>()
+        .map_err(|_| Error::invalid_params(format!("invalid peer id {}", peer)))
+}
+
+impl AdminRpc for AdminRpcImpl {
+    fn ban_peer(&self, peer: String, duration: u64) -> Result<bool> {
+        let peer_id = parse_peer(&peer)?;
+        self.send(NetworkMessage::BanPeer {
+            peer_id,
+            duration: Duration::from_secs(duration),
+            reason: "admin".to_string(),
+        })
+    }
+
+    fn unban_peer(&self, peer: String) -> Result<bool> {
+        let peer_id = parse_peer(&peer)?;
+        self.send(NetworkMessage::UnbanPeer { peer_id })
+    }
+
+    fn peer_stats(&self) -> Result<Vec<PeerMessageStats>> {
+        Ok(self.peer_stats.read().unwrap().snapshot())
+    }
+
+    fn peers(&self) -> Result<Vec<PeerAgentInfo>> {
+        Ok(self.peer_stats.read().unwrap().agents())
+    }
+
+    fn clock_offsets(&self) -> Result<ClockReport> {
+        Ok(self.clock_offsets.read().unwrap().report())
+    }
+
+    fn gossip_topics(&self) -> Result<TopicPeersReport> {
+        Ok(self.topic_peers.read().unwrap().report())
+    }
+
+    fn restart_rpc(&self, config: RpcReload) -> Result<String> {
+        self.rpc_restarter.restart(config).map_err(Error::invalid_params)
+    }
+
+    fn allow_deep_reorg(&self) -> Result<Option<HeadRewindJson>> {
+        let rewind = self.block_chain.write().expect("acquiring block_chain write lock").allow_deep_reorg();
+        if rewind.is_some() {
+            self.tx_pool.write().expect("acquiring tx_pool write lock").reinject_unwound();
+        }
+        Ok(rewind.map(HeadRewindJson::from))
+    }
+}
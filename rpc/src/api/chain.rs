@@ -1,38 +1,185 @@
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use jsonrpc_core::Result;
+use jsonrpc_core::{Error, ErrorCode, Result};
 use jsonrpc_derive::rpc;
 
 use chain::blockchain::BlockChain;
+use chain::epoch_diff::{self, DiffError};
+use chain::lock::{TimedRwLock, TimedReadGuard};
 use map_core::block::{Block, Header};
-use map_core::types::Hash;
+use map_core::balance::Balance;
+use map_core::runtime::Interpreter;
+use map_core::staking::Staking;
+use map_core::app_storage::AppStorage;
+use map_core::types::{Address, AddressScheme, Hash};
+use map_core::genesis::{self, SLOT_DURATION};
+use pool::tx_pool::TxPoolManager;
+
+use crate::types::account::AccountJson;
+use crate::types::block_number::BlockNumber;
+use crate::types::block_txs::{BlockTransactionsJson, BlockTxsJson};
+use crate::types::chain_params::{ChainParamsJson, GenesisJson};
+use crate::types::missed::MissedProposalsJson;
+use crate::types::performance::{EpochPerformanceJson, ValidatorPerformanceJson};
+use crate::request_log::{self, ResponseBudget};
+use crate::types::cursor;
+use crate::types::epoch_diff::EpochDiffJson;
+use crate::types::staking::{StakingEventJson, StakingEventsJson};
+use crate::types::tx_status::TxStatusJson;
+use crate::types::validators::ValidatorsJson;
+
+/// Max number of blocks scanned by `map_getStakingEvents`.
+const MAX_EVENT_BLOCK_RANGE: u64 = 10000;
+
+/// Events of `map_getStakingEventsPage` after which a page is ended.
+const MAX_EVENTS_PAGE: usize = 1000;
+
+/// Max number of epochs summed by `map_getValidatorPerformance`.
+const MAX_PERFORMANCE_EPOCHS: u64 = 1000;
+
+/// Max number of blocks searched by `map_getBlocksByProposer`.
+const MAX_PROPOSER_BLOCK_RANGE: u64 = 100000;
+
+/// Max number of transactions returned by one `map_getBlockTransactions` call.
+const MAX_BLOCK_TXS_PAGE: usize = 1000;
+
+/// Max number of accounts resolved by one `map_getAccounts` call.
+const MAX_ACCOUNTS: usize = 256;
+
+/// Max number of trie nodes asked in one `map_getStateNodes` call.
+const MAX_STATE_NODES: usize = 256;
+
+/// Max total size of the nodes returned by one `map_getStateNodes` call.
+const MAX_STATE_NODES_BYTES: usize = 2 * 1024 * 1024;
+
+/// Json rpc error code of calls reading a state no longer in the state db.
+const STATE_UNAVAILABLE: i64 = -32020;
 
 #[rpc(server)]
 pub trait ChainRpc {
     #[rpc(name = "map_getHeaderByNumber")]
     fn get_header_by_number(&self, num: u64) -> Result<Option<Header>>;
 
+    /// Block by hash, with only the transaction hashes when `full_tx` is false.
     #[rpc(name = "map_getBlock")]
-    fn get_block(&self, hash: Hash) -> Result<Option<Block>>;
+    fn get_block(&self, hash: Hash, full_tx: Option<bool>) -> Result<Option<BlockTxsJson>>;
 
+    /// Canonical block at a height, the head for "latest". "pending" gives the unsealed block the
+    /// pool would fill next on top of the head, without its state root. Only the transaction
+    /// hashes are returned when `full_tx` is false.
     #[rpc(name = "map_getBlockByNumber")]
-    fn get_block_by_number(&self, num: u64) -> Result<Option<Block>>;
+    fn get_block_by_number(&self, num: BlockNumber, full_tx: Option<bool>) -> Result<Option<BlockTxsJson>>;
+
+    /// Up to `limit` transactions of a block from position `offset`, with their total count and
+    /// the position of the next page. A page ends early before the response size limit.
+    #[rpc(name = "map_getBlockTransactions")]
+    fn get_block_transactions(&self, hash: Hash, offset: usize, limit: usize) -> Result<Option<BlockTransactionsJson>>;
 
     #[rpc(name = "map_getTransaction")]
     fn get_transaction(&self, hash: Hash) -> Result<Option<String>>;
+
+    /// Whether a transaction submitted to this node is pending, included or dropped, null if
+    /// it was not submitted here or is too old to be remembered.
+    #[rpc(name = "map_getTransactionStatus")]
+    fn get_transaction_status(&self, hash: Hash) -> Result<Option<TxStatusJson>>;
+
+    #[rpc(name = "map_getValidators")]
+    fn get_validators(&self, num: u64) -> Result<Option<ValidatorsJson>>;
+
+    /// Staking events of blocks `from_block` to `to_block` inclusive, optionally of one validator.
+    #[rpc(name = "map_getStakingEvents")]
+    fn get_staking_events(&self, from_block: u64, to_block: u64, addr: Option<Address>) -> Result<Vec<StakingEventJson>>;
+
+    /// Staking events as `map_getStakingEvents`, a page at a time continued with the
+    /// `next_cursor` of the previous page.
+    #[rpc(name = "map_getStakingEventsPage")]
+    fn get_staking_events_page(&self, from_block: u64, to_block: u64, addr: Option<Address>, cursor: Option<String>) -> Result<StakingEventsJson>;
+
+    /// Slots of `epoch` where a validator was elected and no block was included, optionally of one validator.
+    #[rpc(name = "map_getMissedProposals")]
+    fn get_missed_proposals(&self, epoch: u64, addr: Option<Address>) -> Result<Vec<MissedProposalsJson>>;
+
+    /// Blocks proposed, slots missed and fees collected by `addr` in epochs `from_epoch` to
+    /// `to_epoch` inclusive, in total and per epoch.
+    #[rpc(name = "map_getValidatorPerformance")]
+    fn get_validator_performance(&self, addr: Address, from_epoch: u64, to_epoch: u64) -> Result<ValidatorPerformanceJson>;
+
+    /// Validators added and removed, stake and balance changes and the total supply delta
+    /// between the states `from_epoch` and `to_epoch` start from, null until `to_epoch` starts.
+    /// The balance changes are paged from position `offset`, continued with `next_offset`.
+    #[rpc(name = "map_getEpochDiff")]
+    fn get_epoch_diff(&self, from_epoch: u64, to_epoch: u64, offset: Option<usize>) -> Result<Option<EpochDiffJson>>;
+
+    /// Heights of the canonical blocks sealed by `addr` from `from_block` to `to_block` inclusive.
+    #[rpc(name = "map_getBlocksByProposer")]
+    fn get_blocks_by_proposer(&self, addr: Address, from_block: u64, to_block: u64) -> Result<Vec<u64>>;
+
+    /// Value at hex `key` in the app storage of `addr`, at `block` or the head.
+    #[rpc(name = "map_getStorageAt")]
+    fn get_storage_at(&self, addr: Address, key: String, block: Option<u64>) -> Result<Option<String>>;
+
+    /// Root of the app storage trie of `addr`, at `block` or the head.
+    #[rpc(name = "map_getStorageRoot")]
+    fn get_storage_root(&self, addr: Address, block: Option<u64>) -> Result<Option<Hash>>;
+
+    /// Balance and nonce of each of `addrs` read from one state, at `block` or the head.
+    #[rpc(name = "map_getAccounts")]
+    fn get_accounts(&self, addrs: Vec<Address>, block: Option<u64>) -> Result<Option<Vec<AccountJson>>>;
+
+    /// Raw state trie nodes by hash as hex, null if unknown. Cut short at the size limit.
+    #[rpc(name = "map_getStateNodes")]
+    fn get_state_nodes(&self, hashes: Vec<Hash>) -> Result<Vec<Option<String>>>;
+
+    /// Hash deriving account addresses from public keys, pinned by the genesis.
+    #[rpc(name = "map_addressScheme")]
+    fn address_scheme(&self) -> Result<AddressScheme>;
+
+    /// Network, slot clock, epoch and fee parameters of the chain.
+    #[rpc(name = "map_chainParams")]
+    fn chain_params(&self) -> Result<ChainParamsJson>;
+
+    /// Genesis block with its transactions, the genesis allocation and validators, and the
+    /// chain parameters.
+    #[rpc(name = "map_getGenesis")]
+    fn get_genesis(&self) -> Result<GenesisJson>;
 }
 
 pub(crate) struct ChainRpcImpl {
-    pub block_chain: Arc<RwLock<BlockChain>>,
+    pub block_chain: Arc<TimedRwLock<BlockChain>>,
+    /// Pool the pending block is filled from, None on the query only server
+    pub tx_pool: Option<Arc<RwLock<TxPoolManager>>>,
+    /// Response size limit the paged calls end their pages before
+    pub max_response_bytes: usize,
 }
 
 impl ChainRpc for ChainRpcImpl {
-    fn get_block(&self, hash: Hash) -> Result<Option<Block>> {
-        Ok(self.get_blockchain().get_block(hash))
+    fn get_block(&self, hash: Hash, full_tx: Option<bool>) -> Result<Option<BlockTxsJson>> {
+        Ok(self.get_blockchain().get_block(hash).map(|b| BlockTxsJson::new(b, full_tx.unwrap_or(true))))
+    }
+
+    fn get_block_by_number(&self, num: BlockNumber, full_tx: Option<bool>) -> Result<Option<BlockTxsJson>> {
+        let block = match num {
+            BlockNumber::Num(num) => self.get_blockchain().get_block_by_number(num),
+            BlockNumber::Latest => Some(self.get_blockchain().current_block()),
+            BlockNumber::Pending => Some(self.pending_block()?),
+        };
+        Ok(block.map(|b| BlockTxsJson::new(b, full_tx.unwrap_or(true))))
     }
 
-    fn get_block_by_number(&self, num: u64) -> Result<Option<Block>> {
-        Ok(self.get_blockchain().get_block_by_number(num))
+    fn get_block_transactions(&self, hash: Hash, offset: usize, limit: usize) -> Result<Option<BlockTransactionsJson>> {
+        if limit > MAX_BLOCK_TXS_PAGE {
+            return Err(Error::invalid_params(format!("limit exceeds {}", MAX_BLOCK_TXS_PAGE)));
+        }
+        let block = match self.get_blockchain().get_block(hash) {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let page = BlockTransactionsJson::new(&block, offset, limit, &mut ResponseBudget::new(self.max_response_bytes));
+        if page.transactions.is_empty() && page.next_offset.is_some() && limit > 0 {
+            return Err(request_log::too_large(self.max_response_bytes));
+        }
+        Ok(Some(page))
     }
 
     fn get_header_by_number(&self, num: u64) -> Result<Option<Header>> {
@@ -42,10 +189,335 @@ impl ChainRpc for ChainRpcImpl {
     fn get_transaction(&self, _hash: Hash) -> Result<Option<String>> {
         Ok(Some(format!("{}", "Success")))
     }
+
+    fn get_transaction_status(&self, hash: Hash) -> Result<Option<TxStatusJson>> {
+        Ok(self.tx_pool.as_ref()
+            .and_then(|pool| pool.read().expect("acquiring tx_pool read lock").tx_status(&hash))
+            .map(TxStatusJson::from))
+    }
+
+    fn get_validators(&self, num: u64) -> Result<Option<ValidatorsJson>> {
+        let chain = self.get_blockchain();
+        let block = match chain.get_block_by_number(num) {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let state = chain.checked_state_at(block.state_root())
+            .map_err(|_| state_unavailable(block.state_root()))?;
+        let validators = Staking::new(Interpreter::new(state)).validator_set();
+        Ok(Some(ValidatorsJson::new(block.height(), block.state_root(), validators)))
+    }
+
+    fn get_staking_events(&self, from_block: u64, to_block: u64, addr: Option<Address>) -> Result<Vec<StakingEventJson>> {
+        check_event_range(from_block, to_block)?;
+        let records = self.get_blockchain().get_staking_events(from_block, to_block, addr.as_ref());
+        Ok(records.into_iter().map(StakingEventJson::from).collect())
+    }
+
+    fn get_staking_events_page(&self, from_block: u64, to_block: u64, addr: Option<Address>, cursor: Option<String>) -> Result<StakingEventsJson> {
+        check_event_range(from_block, to_block)?;
+        let query = (from_block, to_block, addr);
+        let start = match cursor {
+            Some(cursor) => cursor::decode(&cursor, &query)
+                .filter(|start| (from_block..=to_block).contains(start))
+                .ok_or_else(|| Error::invalid_params("cursor of another query"))?,
+            None => from_block,
+        };
+
+        // Pages end at a block boundary, the events of one block are never split
+        let chain = self.get_blockchain();
+        let last = std::cmp::min(to_block, chain.current_block().height());
+        let mut budget = ResponseBudget::new(self.max_response_bytes);
+        let mut events = Vec::new();
+        for num in start..=last {
+            if events.len() >= MAX_EVENTS_PAGE {
+                return Ok(StakingEventsJson { events, next_cursor: Some(cursor::encode(num, &query)) });
+            }
+            let block_events: Vec<StakingEventJson> = chain.get_staking_events(num, num, addr.as_ref())
+                .into_iter()
+                .map(StakingEventJson::from)
+                .collect();
+            if !block_events.iter().all(|event| budget.take(event)) {
+                if events.is_empty() {
+                    return Err(request_log::too_large(self.max_response_bytes));
+                }
+                return Ok(StakingEventsJson { events, next_cursor: Some(cursor::encode(num, &query)) });
+            }
+            events.extend(block_events);
+        }
+        Ok(StakingEventsJson { events, next_cursor: None })
+    }
+
+    fn get_missed_proposals(&self, epoch: u64, addr: Option<Address>) -> Result<Vec<MissedProposalsJson>> {
+        Ok(self.get_blockchain().get_missed_proposals(epoch).into_iter()
+            .filter(|missed| addr.map_or(true, |a| missed.validator == a))
+            .map(|missed| MissedProposalsJson::new(epoch, missed))
+            .collect())
+    }
+
+    fn get_validator_performance(&self, addr: Address, from_epoch: u64, to_epoch: u64) -> Result<ValidatorPerformanceJson> {
+        if from_epoch > to_epoch {
+            return Err(Error::invalid_params("from epoch after to epoch"));
+        }
+        if to_epoch - from_epoch >= MAX_PERFORMANCE_EPOCHS {
+            return Err(Error::invalid_params(format!("epoch range exceeds {}", MAX_PERFORMANCE_EPOCHS)));
+        }
+
+        let chain = self.get_blockchain();
+        let epochs = (from_epoch..=to_epoch)
+            .map(|epoch| {
+                let mut performance = EpochPerformanceJson { epoch, ..Default::default() };
+                if let Some(proposed) = chain.get_proposed_blocks(epoch).into_iter().find(|p| p.validator == addr) {
+                    performance.proposed = proposed.blocks;
+                    performance.rewards = proposed.rewards;
+                }
+                if let Some(missed) = chain.get_missed_proposals(epoch).into_iter().find(|m| m.validator == addr) {
+                    performance.missed = missed.count();
+                }
+                performance
+            })
+            .filter(|performance| performance.proposed > 0 || performance.missed > 0)
+            .collect();
+        Ok(ValidatorPerformanceJson::new(addr, from_epoch, to_epoch, epochs))
+    }
+
+    fn get_epoch_diff(&self, from_epoch: u64, to_epoch: u64, offset: Option<usize>) -> Result<Option<EpochDiffJson>> {
+        match epoch_diff::diff(&self.block_chain, from_epoch, to_epoch) {
+            Ok(diff) => {
+                let offset = offset.unwrap_or(0);
+                let page = EpochDiffJson::page(diff, offset, &mut ResponseBudget::new(self.max_response_bytes));
+                if page.diff.balance_changes.is_empty() && page.next_offset == Some(offset) {
+                    return Err(request_log::too_large(self.max_response_bytes));
+                }
+                Ok(Some(page))
+            }
+            Err(DiffError::InvalidRange(e)) => Err(Error::invalid_params(e)),
+            Err(DiffError::NotStarted(_)) => Ok(None),
+            Err(DiffError::StateUnavailable(root)) => Err(state_unavailable(root)),
+            Err(DiffError::Failed(e)) => Err(Error { code: ErrorCode::InternalError, message: e, data: None }),
+        }
+    }
+
+    fn get_blocks_by_proposer(&self, addr: Address, from_block: u64, to_block: u64) -> Result<Vec<u64>> {
+        if from_block > to_block {
+            return Err(Error::invalid_params("from block after to block"));
+        }
+        if to_block - from_block >= MAX_PROPOSER_BLOCK_RANGE {
+            return Err(Error::invalid_params(format!("block range exceeds {}", MAX_PROPOSER_BLOCK_RANGE)));
+        }
+
+        Ok(self.get_blockchain().get_blocks_by_proposer(&addr, from_block, to_block))
+    }
+
+    fn get_storage_at(&self, addr: Address, key: String, block: Option<u64>) -> Result<Option<String>> {
+        let key = hex::decode(key.trim_start_matches("0x"))
+            .map_err(|e| Error::invalid_params(format!("invalid key {}", e)))?;
+
+        Ok(self.storage_at(block)?.and_then(|storage| storage.get(&addr, &key))
+            .map(|value| format!("0x{}", hex::encode(value))))
+    }
+
+    fn get_storage_root(&self, addr: Address, block: Option<u64>) -> Result<Option<Hash>> {
+        Ok(self.storage_at(block)?.map(|storage| storage.storage_root(&addr)))
+    }
+
+    fn get_accounts(&self, addrs: Vec<Address>, block: Option<u64>) -> Result<Option<Vec<AccountJson>>> {
+        if addrs.len() > MAX_ACCOUNTS {
+            return Err(Error::invalid_params(format!("more than {} accounts", MAX_ACCOUNTS)));
+        }
+
+        let chain = self.get_blockchain();
+        let block = match block {
+            Some(num) => match chain.get_block_by_number(num) {
+                Some(b) => b,
+                None => return Ok(None),
+            },
+            None => chain.current_block(),
+        };
+        let state = chain.checked_state_at(block.state_root())
+            .map_err(|_| state_unavailable(block.state_root()))?;
+        let balance = Balance::from_state(Interpreter::new(state));
+        Ok(Some(addrs.into_iter().map(|addr| AccountJson::new(addr, balance.get_account(addr))).collect()))
+    }
+
+    fn get_state_nodes(&self, hashes: Vec<Hash>) -> Result<Vec<Option<String>>> {
+        if hashes.len() > MAX_STATE_NODES {
+            return Err(Error::invalid_params(format!("more than {} nodes", MAX_STATE_NODES)));
+        }
+
+        let chain = self.get_blockchain();
+        let mut nodes = Vec::new();
+        let mut size = 0;
+        for hash in hashes.iter() {
+            let node = chain.statedb().get_node(hash);
+            if let Some(ref n) = node {
+                if size + n.len() > MAX_STATE_NODES_BYTES && !nodes.is_empty() {
+                    break;
+                }
+                size += n.len();
+            }
+            nodes.push(node.map(|n| format!("0x{}", hex::encode(n))));
+        }
+        Ok(nodes)
+    }
+
+    fn address_scheme(&self) -> Result<AddressScheme> {
+        Ok(self.get_blockchain().address_scheme())
+    }
+
+    fn chain_params(&self) -> Result<ChainParamsJson> {
+        let min_gas_price = self.pool_min_gas_price();
+        Ok(Self::params(&self.get_blockchain(), min_gas_price))
+    }
+
+    fn get_genesis(&self) -> Result<GenesisJson> {
+        let min_gas_price = self.pool_min_gas_price();
+        let chain = self.get_blockchain();
+        let genesis = chain.get_block_by_number(0)
+            .ok_or_else(|| Error::invalid_params("genesis block not found"))?;
+        Ok(GenesisJson::new(genesis, chain.spec().genesis.clone(), Self::params(&chain, min_gas_price)))
+    }
 }
 
 impl ChainRpcImpl {
-    fn get_blockchain(&self) -> RwLockReadGuard<BlockChain> {
+    fn get_blockchain(&self) -> TimedReadGuard<BlockChain> {
         self.block_chain.read().expect("acquiring block_chain read lock")
     }
+
+    /// Gas price floor of the pool, read before taking the chain lock: the pool locks the chain
+    /// while holding its own lock.
+    fn pool_min_gas_price(&self) -> Option<u64> {
+        self.tx_pool.as_ref()
+            .map(|pool| pool.read().expect("acquiring tx_pool read lock").min_gas_price())
+    }
+
+    fn params(chain: &BlockChain, min_gas_price: Option<u64>) -> ChainParamsJson {
+        ChainParamsJson::new(chain.spec(), chain.address_scheme(), chain.genesis_hash(), chain.genesis_time(), min_gas_price)
+    }
+
+    /// Block on top of the head holding the transactions the pool selects for the next block, in
+    /// the current slot. It is not executed, which would write its state to the db, so its state
+    /// root is zero, and it carries no seal.
+    fn pending_block(&self) -> Result<Block> {
+        let tx_pool = self.tx_pool.as_ref()
+            .ok_or_else(|| Error::invalid_params("pending block needs the transaction pool"))?;
+        let txs = tx_pool.read().expect("acquiring tx_pool read lock").pending_for_block();
+        let chain = self.get_blockchain();
+        let head = chain.current_block();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let now_slot = now.saturating_sub(chain.genesis_time()) / SLOT_DURATION;
+
+        let mut header = Header::default();
+        header.parent_hash = head.hash();
+        header.height = head.height() + 1;
+        header.slot = std::cmp::max(head.header.slot + 1, now_slot);
+        header.time = std::cmp::max(now, genesis::slot_start(chain.genesis_time(), header.slot));
+        Ok(Block::new(header, txs, Vec::new(), Vec::new()))
+    }
+
+    fn storage_at(&self, block: Option<u64>) -> Result<Option<AppStorage>> {
+        let chain = self.get_blockchain();
+        let block = match block {
+            Some(num) => match chain.get_block_by_number(num) {
+                Some(b) => b,
+                None => return Ok(None),
+            },
+            None => chain.current_block(),
+        };
+        let state = chain.checked_state_at(block.state_root())
+            .map_err(|_| state_unavailable(block.state_root()))?;
+        Ok(Some(AppStorage::from_state(Interpreter::new(state))))
+    }
+}
+
+fn check_event_range(from_block: u64, to_block: u64) -> Result<()> {
+    if from_block > to_block {
+        return Err(Error::invalid_params("from block after to block"));
+    }
+    if to_block - from_block >= MAX_EVENT_BLOCK_RANGE {
+        return Err(Error::invalid_params(format!("block range exceeds {}", MAX_EVENT_BLOCK_RANGE)));
+    }
+    Ok(())
+}
+
+/// Error of a known block whose state was pruned or lost, the state root is in `data`.
+pub(super) fn state_unavailable(root: Hash) -> Error {
+    Error {
+        code: ErrorCode::ServerError(STATE_UNAVAILABLE),
+        message: format!("state unavailable {}", root),
+        data: Some(serde_json::json!({ "state_root": root })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_log::DEFAULT_MAX_RESPONSE_BYTES;
+    use crate::types::block_txs::BlockTxs;
+
+    #[test]
+    fn validator_performance() {
+        let mut chain = BlockChain::new_in_memory(&map_core::chain_spec::ChainSpec::default());
+        chain.load().unwrap();
+        let key = map_core::test_utils::test_key(1);
+        let validator = map_core::test_utils::address_of(&key);
+        let block = map_core::test_utils::BlockBuilder::on(&chain.current_block()).signed_by(key).build();
+        chain.import_block(&block).unwrap();
+        chain.record_missed_proposal(0, validator, 5);
+        chain.record_missed_proposal(2, validator, 2 * map_core::genesis::EPOCH_LENGTH);
+        let rpc = ChainRpcImpl { block_chain: Arc::new(TimedRwLock::new("chain", chain)), tx_pool: None, max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES };
+
+        let performance = rpc.get_validator_performance(validator, 0, 3).unwrap();
+        assert_eq!((performance.proposed, performance.missed, performance.rewards), (1, 2, 0));
+        assert_eq!(performance.epochs.iter().map(|e| e.epoch).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(performance.epochs[0].proposed, 1);
+        assert!(rpc.get_validator_performance(validator, 3, 0).is_err());
+        assert!(rpc.get_validator_performance(validator, 0, MAX_PERFORMANCE_EPOCHS).is_err());
+
+        assert_eq!(rpc.get_blocks_by_proposer(validator, 0, 10).unwrap(), vec![1]);
+        assert_eq!(rpc.get_staking_events(0, 10, None).unwrap(), Vec::new());
+        assert_eq!(rpc.get_staking_events_page(0, 10, None, None).unwrap().next_cursor, None);
+        assert!(rpc.get_staking_events_page(0, 10, None, Some("00".to_string())).is_err());
+        assert!(rpc.get_blocks_by_proposer(validator, 0, MAX_PROPOSER_BLOCK_RANGE).is_err());
+    }
+
+    #[test]
+    fn pending_block() {
+        let mut chain = BlockChain::new_in_memory(&map_core::chain_spec::ChainSpec::default());
+        chain.load().unwrap();
+        let block_chain = Arc::new(TimedRwLock::new("chain", chain));
+        let rpc = ChainRpcImpl { block_chain: block_chain.clone(), tx_pool: None, max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES };
+        assert!(rpc.get_block_by_number(BlockNumber::Pending, None).is_err());
+
+        let tx_pool = Arc::new(RwLock::new(TxPoolManager::new(block_chain.clone())));
+        let tx = map_core::test_utils::transfer(&map_core::test_utils::genesis_key(), 1, Address::default(), 10);
+        tx_pool.write().unwrap().add_tx(tx.clone()).unwrap();
+        let rpc = ChainRpcImpl { block_chain, tx_pool: Some(tx_pool), max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES };
+        let head = rpc.get_block_by_number(BlockNumber::Latest, None).unwrap().unwrap();
+        let pending = rpc.get_block_by_number(BlockNumber::Pending, None).unwrap().unwrap();
+        assert_eq!(pending.header.parent_hash, head.header.hash());
+        assert_eq!(pending.header.height, head.header.height + 1);
+        assert!(pending.header.slot > head.header.slot);
+        assert_eq!(pending.txs, BlockTxs::Full(vec![tx.clone()]));
+        let pending = rpc.get_block_by_number(BlockNumber::Pending, Some(false)).unwrap().unwrap();
+        assert_eq!(pending.txs, BlockTxs::Hashes(vec![tx.hash()]));
+        assert_eq!(rpc.get_block_by_number(BlockNumber::Num(5), None).unwrap(), None);
+
+        let genesis = rpc.get_genesis().unwrap();
+        assert_eq!(genesis.hash, rpc.block_chain.read().unwrap().genesis_hash());
+        assert_eq!(genesis.block.header.height, 0);
+        assert_eq!(genesis.params, rpc.chain_params().unwrap());
+        assert!(genesis.params.fees.pool_min_gas_price.is_some());
+
+        let page = rpc.get_block_transactions(head.header.hash(), 0, 10).unwrap().unwrap();
+        assert_eq!((page.total, page.transactions.len()), (0, 0));
+        assert!(rpc.get_block_transactions(head.header.hash(), 0, MAX_BLOCK_TXS_PAGE + 1).is_err());
+    }
+
+    #[test]
+    fn state_unavailable_error() {
+        let err = state_unavailable(Hash([1u8; 32]));
+        assert_eq!(err.code, ErrorCode::ServerError(-32020));
+        assert_eq!(err.data, Some(serde_json::json!({ "state_root": Hash([1u8; 32]) })));
+    }
 }
\ No newline at end of file
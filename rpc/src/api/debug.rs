@@ -0,0 +1,172 @@
+use std::sync::{Arc, RwLock};
+
+use jsonrpc_core::{Error, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+
+use chain::blockchain::BlockChain;
+use chain::lock::{TimedRwLock, TimedReadGuard};
+use map_core::types::Hash;
+use network::block_queue::SharedBlockQueue;
+use network::p2p_trace::{SharedP2PTrace, TraceStatus};
+use network::propagation::{Propagation, PropagationReport};
+
+use crate::request_log::ResponseBudget;
+use crate::types::chain_tree::ChainTreeJson;
+
+/// Heights below the head shown by `debug_chainTree` when not given.
+const DEFAULT_TREE_DEPTH: u64 = 16;
+/// Most heights below the head `debug_chainTree` reads.
+const MAX_TREE_DEPTH: u64 = 256;
+
+/// Raw store reads to compare the data of two nodes without access to their datadir, and
+/// network measurements.
+#[rpc(server)]
+pub trait DebugRpc {
+    /// Bincode of a block as stored in the chain db, as hex, null if unknown.
+    #[rpc(name = "debug_getRawBlock")]
+    fn get_raw_block(&self, hash: Hash) -> Result<Option<String>>;
+
+    /// Bincode of a block header as stored in the chain db, as hex, null if unknown.
+    #[rpc(name = "debug_getRawHeader")]
+    fn get_raw_header(&self, hash: Hash) -> Result<Option<String>>;
+
+    /// Encoded state trie node with this hash, as hex, null if unknown.
+    #[rpc(name = "debug_getTrieNode")]
+    fn get_trie_node(&self, hash: Hash) -> Result<Option<String>>;
+
+    /// Delay from the slot start of the recent gossiped blocks to their arrival and import,
+    /// and the mean and max arrival delay of the blocks first received from each peer.
+    #[rpc(name = "debug_blockPropagation")]
+    fn block_propagation(&self) -> Result<PropagationReport>;
+
+    /// Turn the trace of the p2p messages on or off, or leave it as is without `enable`.
+    #[rpc(name = "debug_p2pTrace")]
+    fn p2p_trace(&self, enable: Option<bool>) -> Result<TraceStatus>;
+
+    /// Canonical blocks of the last `depth` heights, the queued blocks above them and the
+    /// recent competing blocks below the head, with the children of each block so forks show up.
+    #[rpc(name = "debug_chainTree")]
+    fn chain_tree(&self, depth: Option<u64>) -> Result<ChainTreeJson>;
+}
+
+pub(crate) struct DebugRpcImpl {
+    pub block_chain: Arc<TimedRwLock<BlockChain>>,
+    pub propagation: Arc<RwLock<Propagation>>,
+    pub p2p_trace: SharedP2PTrace,
+    pub block_queue: SharedBlockQueue,
+    /// Response size limit the tree is cut to
+    pub max_response_bytes: usize,
+}
+
+impl DebugRpc for DebugRpcImpl {
+    fn get_raw_block(&self, hash: Hash) -> Result<Option<String>> {
+        Ok(self.get_blockchain().get_raw_block(hash).map(to_hex))
+    }
+
+    fn get_raw_header(&self, hash: Hash) -> Result<Option<String>> {
+        Ok(self.get_blockchain().get_raw_header(hash).map(to_hex))
+    }
+
+    fn get_trie_node(&self, hash: Hash) -> Result<Option<String>> {
+        Ok(self.get_blockchain().statedb().get_node(&hash).map(to_hex))
+    }
+
+    fn block_propagation(&self) -> Result<PropagationReport> {
+        Ok(self.propagation.read().unwrap().report())
+    }
+
+    fn p2p_trace(&self, enable: Option<bool>) -> Result<TraceStatus> {
+        let mut trace = self.p2p_trace.write().unwrap();
+        match enable {
+            Some(true) => trace.enable().map_err(|e| Error {
+                code: ErrorCode::InternalError,
+                message: format!("could not open the trace file: {}", e),
+                data: None,
+            })?,
+            Some(false) => trace.disable(),
+            None => {}
+        }
+        Ok(trace.status())
+    }
+
+    fn chain_tree(&self, depth: Option<u64>) -> Result<ChainTreeJson> {
+        let depth = depth.unwrap_or(DEFAULT_TREE_DEPTH);
+        if depth == 0 || depth > MAX_TREE_DEPTH {
+            return Err(Error::invalid_params(format!("depth must be between 1 and {}", MAX_TREE_DEPTH)));
+        }
+        // The queue is read before the chain, the order the gossip handler takes both locks in
+        let (queued, spilled) = {
+            let queue = self.block_queue.read().expect("acquiring block queue lock");
+            (queue.headers(), queue.spilled())
+        };
+        let chain = self.get_blockchain();
+        let head = chain.current_block().header;
+        let lowest = (head.height + 1).saturating_sub(depth);
+
+        let mut blocks: Vec<_> = (lowest..=head.height)
+            .filter_map(|height| chain.get_header_by_number(height))
+            .map(|header| (header, true))
+            .collect();
+        // Blocks received ahead of the head waiting in the queue, and competing ones it dropped
+        blocks.extend(queued.into_iter()
+            .filter(|header| header.height >= lowest)
+            .map(|header| (header, false)));
+        blocks.extend(spilled.into_iter()
+            .filter_map(|(height, hash)| chain.get_queued_block(height, &hash))
+            .map(|block| (block.header, false)));
+        Ok(ChainTreeJson::new(&head, blocks, &mut ResponseBudget::new(self.max_response_bytes)))
+    }
+}
+
+impl DebugRpcImpl {
+    fn get_blockchain(&self) -> TimedReadGuard<BlockChain> {
+        self.block_chain.read().expect("acquiring block_chain read lock")
+    }
+}
+
+fn to_hex<T: AsRef<[u8]>>(bytes: T) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::block::Block;
+    use network::block_queue::BlockQueue;
+    use network::p2p_trace::P2PTrace;
+    use map_core::chain_spec::ChainSpec;
+
+    #[test]
+    fn raw_reads() {
+        let mut chain = BlockChain::new_in_memory(&ChainSpec::default());
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+        let block_queue = Arc::new(RwLock::new(BlockQueue::new(&mut chain)));
+        let rpc = DebugRpcImpl {
+            block_chain: Arc::new(TimedRwLock::new("chain", chain)),
+            propagation: Arc::new(RwLock::new(Propagation::default())),
+            p2p_trace: Arc::new(RwLock::new(P2PTrace::new(std::env::temp_dir().join("map_rpc_p2p_trace_test")))),
+            block_queue,
+            max_response_bytes: crate::request_log::DEFAULT_MAX_RESPONSE_BYTES,
+        };
+
+        let raw = rpc.get_raw_block(genesis.hash()).unwrap().unwrap();
+        let decoded: Block = bincode::deserialize(&hex::decode(&raw[2..]).unwrap()).unwrap();
+        assert_eq!(decoded, genesis);
+        assert!(rpc.get_raw_header(genesis.hash()).unwrap().is_some());
+        assert!(rpc.get_trie_node(genesis.state_root()).unwrap().is_some());
+        assert_eq!(rpc.get_raw_block(Hash([1u8; 32])).unwrap(), None);
+        assert_eq!(rpc.block_propagation().unwrap(), PropagationReport::default());
+
+        assert!(!rpc.p2p_trace(None).unwrap().enabled);
+        assert!(rpc.p2p_trace(Some(true)).unwrap().enabled);
+        assert!(!rpc.p2p_trace(Some(false)).unwrap().enabled);
+
+        let tree = rpc.chain_tree(None).unwrap();
+        assert_eq!(tree.head, genesis.hash());
+        assert_eq!(tree.blocks.len(), 1);
+        assert!(!tree.truncated);
+        assert!(tree.blocks[0].canonical);
+        assert!(rpc.chain_tree(Some(0)).is_err());
+    }
+}
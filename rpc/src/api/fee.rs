@@ -0,0 +1,126 @@
+use std::sync::{Arc, RwLock};
+
+use jsonrpc_core::{Error, Result};
+use jsonrpc_derive::rpc;
+
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use executor::Executor;
+use pool::tx_pool::TxPoolManager;
+
+use crate::types::fee::{BlockFees, EstimateRequest, FeeEstimate, FeeHistory, GasPriceStats};
+
+/// Number of recent blocks sampled for gas price suggestion.
+const FEE_HISTORY_BLOCKS: u64 = 20;
+/// Gas limit and price used by `map_sendTransaction`.
+const DEFAULT_GAS: u64 = 1000;
+const DEFAULT_GAS_PRICE: u64 = 1000;
+/// Max number of blocks returned by `map_feeHistory`.
+const MAX_FEE_HISTORY_BLOCKS: u64 = 1024;
+
+#[rpc(server)]
+pub trait FeeRpc {
+    /// Simulate a transfer against the pending state and estimate its fee.
+    #[rpc(name = "map_estimateFee")]
+    fn estimate_fee(&self, tx: EstimateRequest) -> Result<FeeEstimate>;
+
+    /// Fee statistics of the latest `block_count` blocks, with the gas price at each of
+    /// the ascending `percentiles`.
+    #[rpc(name = "map_feeHistory")]
+    fn fee_history(&self, block_count: u64, percentiles: Vec<f64>) -> Result<FeeHistory>;
+
+    /// Lowest gas price the pool admits and packs into blocks.
+    #[rpc(name = "map_minGasPrice")]
+    fn min_gas_price(&self) -> Result<u64>;
+}
+
+pub(crate) struct FeeRpcImpl {
+    pub block_chain: Arc<TimedRwLock<BlockChain>>,
+    pub tx_pool: Arc<RwLock<TxPoolManager>>,
+}
+
+impl FeeRpc for FeeRpcImpl {
+    fn estimate_fee(&self, tx: EstimateRequest) -> Result<FeeEstimate> {
+        let from = tx.from;
+
+        let recent = {
+            let chain = self.block_chain.read().expect("acquiring block_chain read lock");
+            let head = chain.current_block();
+            let mut prices = Vec::new();
+            let start = head.height().saturating_sub(FEE_HISTORY_BLOCKS - 1);
+            for num in start..=head.height() {
+                if let Some(b) = chain.get_block_by_number(num) {
+                    prices.extend(b.get_txs().iter().map(|t| t.get_gas_price()));
+                }
+            }
+            GasPriceStats::from_prices(prices, DEFAULT_GAS_PRICE)
+        };
+
+        // Pool transactions of the sender apply before this one
+        let pool = self.tx_pool.read().expect("acquiring tx_pool read lock");
+        let account = pool.pending_account(&from);
+        let nonce = tx.nonce.unwrap_or(account.nonce + 1);
+
+        let congested = pool.pending_len() >= pool.block_limit();
+        let suggested = if congested { recent.high } else { recent.median }.max(pool.min_gas_price());
+
+        let (fee, error) = match Executor::check_transfer(account.nonce, account.balance, nonce, tx.value) {
+            Ok(fee) => (fee, None),
+            Err(e) => (executor::TRANSFER_FEE, Some(format!("{}", e))),
+        };
+
+        Ok(FeeEstimate {
+            fee,
+            gas: DEFAULT_GAS,
+            gas_price: tx.gas_price.map_or(suggested, |price| price.max(suggested)),
+            recent_gas_price: recent,
+            nonce,
+            pending: pool.pending_len(),
+            congested,
+            error,
+        })
+    }
+
+    fn fee_history(&self, block_count: u64, percentiles: Vec<f64>) -> Result<FeeHistory> {
+        if block_count == 0 || block_count > MAX_FEE_HISTORY_BLOCKS {
+            return Err(Error::invalid_params(format!("block count must be 1 to {}", MAX_FEE_HISTORY_BLOCKS)));
+        }
+        if percentiles.iter().any(|p| !(0.0..=100.0).contains(p)) {
+            return Err(Error::invalid_params("percentiles must be between 0 and 100"));
+        }
+        if percentiles.windows(2).any(|w| w[0] > w[1]) {
+            return Err(Error::invalid_params("percentiles must be ascending"));
+        }
+
+        let chain = self.block_chain.read().expect("acquiring block_chain read lock");
+        let head = chain.current_block().height();
+        let oldest = (head + 1).saturating_sub(block_count);
+        let mut blocks = Vec::new();
+        for num in oldest..=head {
+            let hash = match chain.get_header_by_number(num) {
+                Some(h) => h.hash(),
+                None => continue,
+            };
+            if let Some(stats) = chain.get_fee_stats(hash) {
+                blocks.push(BlockFees {
+                    height: num,
+                    tx_count: stats.tx_count,
+                    total_fees: stats.total_fees,
+                    min_gas_price: stats.min_gas_price,
+                    median_gas_price: stats.median_gas_price,
+                    max_gas_price: stats.max_gas_price,
+                    percentiles: percentiles.iter().map(|p| stats.percentile(*p)).collect(),
+                });
+            }
+        }
+
+        Ok(FeeHistory {
+            oldest_block: oldest,
+            blocks,
+        })
+    }
+
+    fn min_gas_price(&self) -> Result<u64> {
+        Ok(self.tx_pool.read().expect("acquiring tx_pool read lock").min_gas_price())
+    }
+}
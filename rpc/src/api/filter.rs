@@ -0,0 +1,133 @@
+use std::sync::{Arc, RwLock};
+
+use jsonrpc_core::{Error, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+
+use chain::blockchain::BlockChain;
+use chain::lock::{TimedRwLock, TimedWriteGuard};
+use chain::filters::FilterKind;
+use map_core::types::{Address, Hash};
+use pool::tx_pool::TxPoolManager;
+use crate::types::pool_event::PoolEventJson;
+
+/// All the pool event filter slots are taken or too many were installed in the last minute.
+const TOO_MANY_FILTERS: i64 = -32022;
+
+#[rpc(server)]
+pub trait FilterRpc {
+    /// Install a filter collecting hashes of new blocks, optionally only those proposed by `author`.
+    #[rpc(name = "map_newBlockFilter")]
+    fn new_block_filter(&self, author: Option<Address>) -> Result<u64>;
+
+    /// Install a filter collecting hashes of new transactions sent from or to `addr`.
+    #[rpc(name = "map_newTransactionFilter")]
+    fn new_transaction_filter(&self, addr: Address) -> Result<u64>;
+
+    /// Hashes matched by the filter since the previous call.
+    #[rpc(name = "map_getFilterChanges")]
+    fn get_filter_changes(&self, id: u64) -> Result<Vec<Hash>>;
+
+    #[rpc(name = "map_uninstallFilter")]
+    fn uninstall_filter(&self, id: u64) -> Result<bool>;
+
+    /// Install a filter collecting the added, replaced, dropped and included events of the
+    /// transaction pool.
+    #[rpc(name = "map_newPoolEventFilter")]
+    fn new_pool_event_filter(&self) -> Result<u64>;
+
+    /// Pool events since the previous call, oldest first.
+    #[rpc(name = "map_getPoolEventChanges")]
+    fn get_pool_event_changes(&self, id: u64) -> Result<Vec<PoolEventJson>>;
+
+    #[rpc(name = "map_uninstallPoolEventFilter")]
+    fn uninstall_pool_event_filter(&self, id: u64) -> Result<bool>;
+}
+
+pub(crate) struct FilterRpcImpl {
+    pub block_chain: Arc<TimedRwLock<BlockChain>>,
+    pub tx_pool: Arc<RwLock<TxPoolManager>>,
+}
+
+impl FilterRpc for FilterRpcImpl {
+    fn new_block_filter(&self, author: Option<Address>) -> Result<u64> {
+        Ok(self.get_blockchain().filters().install(FilterKind::Blocks { author }))
+    }
+
+    fn new_transaction_filter(&self, address: Address) -> Result<u64> {
+        Ok(self.get_blockchain().filters().install(FilterKind::Transactions { address }))
+    }
+
+    fn get_filter_changes(&self, id: u64) -> Result<Vec<Hash>> {
+        self.get_blockchain().filters().poll(id)
+            .ok_or_else(|| Error::invalid_params(format!("filter {} not found", id)))
+    }
+
+    fn uninstall_filter(&self, id: u64) -> Result<bool> {
+        Ok(self.get_blockchain().filters().uninstall(id))
+    }
+
+    fn new_pool_event_filter(&self) -> Result<u64> {
+        self.get_pool().events().install()
+            .ok_or_else(|| Error {
+                code: ErrorCode::ServerError(TOO_MANY_FILTERS),
+                message: "too many pool event filters installed, try again later".to_string(),
+                data: None,
+            })
+    }
+
+    fn get_pool_event_changes(&self, id: u64) -> Result<Vec<PoolEventJson>> {
+        self.get_pool().events().poll(id)
+            .map(|events| events.into_iter().map(PoolEventJson::from).collect())
+            .ok_or_else(|| Error::invalid_params(format!("pool event filter {} not found", id)))
+    }
+
+    fn uninstall_pool_event_filter(&self, id: u64) -> Result<bool> {
+        Ok(self.get_pool().events().uninstall(id))
+    }
+}
+
+impl FilterRpcImpl {
+    fn get_blockchain(&self) -> TimedWriteGuard<BlockChain> {
+        self.block_chain.write().expect("acquiring block_chain write lock")
+    }
+
+    fn get_pool(&self) -> std::sync::RwLockWriteGuard<TxPoolManager> {
+        self.tx_pool.write().expect("acquiring tx_pool write lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use jsonrpc_core::IoHandler;
+    use map_core::chain_spec::ChainSpec;
+
+    #[test]
+    fn test_filter_lifecycle() {
+        let dir = std::env::temp_dir().join("map_rpc_filter_test");
+        let _ = fs::remove_dir_all(&dir);
+        let mut chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        chain.load().unwrap();
+        let block_chain = Arc::new(TimedRwLock::new("chain", chain));
+        let tx_pool = Arc::new(RwLock::new(TxPoolManager::new(block_chain.clone())));
+        let rpc = FilterRpcImpl { block_chain: block_chain.clone(), tx_pool: tx_pool.clone() };
+
+        let mut io = IoHandler::new();
+        io.extend_with(FilterRpcImpl { block_chain, tx_pool }.to_delegate());
+        let request = r#"{"jsonrpc":"2.0","method":"map_newTransactionFilter","params":["0xzz"],"id":1}"#;
+        assert!(io.handle_request_sync(request).unwrap().contains("Invalid params"));
+        let id = rpc.new_block_filter(None).unwrap();
+        assert_eq!(rpc.get_filter_changes(id).unwrap(), Vec::<Hash>::new());
+        assert_eq!(rpc.uninstall_filter(id).unwrap(), true);
+        assert!(rpc.get_filter_changes(id).is_err());
+
+        let events = rpc.new_pool_event_filter().unwrap();
+        let tx = map_core::test_utils::transfer(&map_core::test_utils::genesis_key(), 1, Address::default(), 10);
+        rpc.tx_pool.write().unwrap().add_tx(tx.clone()).unwrap();
+        let changes = rpc.get_pool_event_changes(events).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].event, changes[0].hash), ("added", tx.hash()));
+        assert_eq!(rpc.uninstall_pool_event_filter(events).unwrap(), true);
+    }
+}
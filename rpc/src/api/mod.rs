@@ -1,5 +1,15 @@
+pub(crate) use self::admin::{AdminRpc, AdminRpcImpl};
 pub(crate) use self::chain::{ChainRpc, ChainRpcImpl};
 pub(crate) use self::account::{AccountManager, AccountManagerImpl};
+pub(crate) use self::debug::{DebugRpc, DebugRpcImpl};
+pub(crate) use self::sync::{SyncRpc, SyncRpcImpl};
+pub(crate) use self::fee::{FeeRpc, FeeRpcImpl};
+pub(crate) use self::filter::{FilterRpc, FilterRpcImpl};
 
 mod account;
+mod admin;
 mod chain;
+mod debug;
+mod fee;
+mod filter;
+mod sync;
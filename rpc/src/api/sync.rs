@@ -0,0 +1,46 @@
+use jsonrpc_core::Result;
+use jsonrpc_derive::rpc;
+
+use network::sync::{SharedSyncProgress, SyncProgress};
+
+#[rpc(server)]
+pub trait SyncRpc {
+    /// Returns the long-range sync progress, or null when the node is not syncing.
+    #[rpc(name = "map_syncing")]
+    fn syncing(&self) -> Result<Option<SyncProgress>>;
+}
+
+pub(crate) struct SyncRpcImpl {
+    pub sync_progress: SharedSyncProgress,
+}
+
+impl SyncRpc for SyncRpcImpl {
+    fn syncing(&self) -> Result<Option<SyncProgress>> {
+        let progress = self.sync_progress.read().expect("acquiring sync progress read lock");
+        if progress.syncing {
+            Ok(Some(progress.clone()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_json() {
+        let progress = SyncProgress {
+            syncing: true,
+            current_height: 40,
+            highest_height: 100,
+            eta_secs: Some(6),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(Some(progress)).unwrap();
+        let client: Option<map_rpc_client::types::SyncProgressJson> = serde_json::from_value(json).unwrap();
+        let client = client.unwrap();
+        assert_eq!((client.current_height, client.highest_height, client.eta_secs), (40, 100, Some(6)));
+    }
+}
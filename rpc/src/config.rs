@@ -1,9 +1,54 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Debug, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum API {
     Chain,
     Account,
+    Sync,
+    /// Peer management and `admin_restartRpc`, served on a loopback address only.
+    Admin,
+    /// Raw chain data, traces and the block tree.
+    Debug,
+}
+
+impl FromStr for API {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "chain" => Ok(API::Chain),
+            "account" => Ok(API::Account),
+            "sync" => Ok(API::Sync),
+            "admin" => Ok(API::Admin),
+            "debug" => Ok(API::Debug),
+            _ => Err(format!("unknown rpc module {}", name)),
+        }
+    }
+}
+
+/// Split a comma separated option value into modules.
+pub fn parse_modules(value: &str) -> Result<Vec<API>, String> {
+    value.split(',')
+        .map(|m| m.trim())
+        .filter(|m| !m.is_empty())
+        .map(API::from_str)
+        .collect()
+}
+
+/// Whether a listen address only accepts local clients.
+pub fn is_loopback(addr: &str) -> bool {
+    addr == "localhost" || addr.parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+}
+
+/// Check the admin module is only enabled on a loopback address.
+pub fn check_modules(modules: &[API], addr: &str) -> Result<(), String> {
+    if modules.contains(&API::Admin) && !is_loopback(addr) {
+        return Err(format!("the admin rpc module is only served on a loopback address, not {}", addr));
+    }
+    Ok(())
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -19,4 +64,30 @@ impl Config {
     pub fn config_account(&self) -> bool {
         self.modules.contains(&API::Account)
     }
+
+    pub fn config_sync(&self) -> bool {
+        self.modules.contains(&API::Sync)
+    }
+
+    pub fn config_admin(&self) -> bool {
+        self.modules.contains(&API::Admin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules() {
+        assert_eq!(parse_modules("admin, debug,"), Ok(vec![API::Admin, API::Debug]));
+        assert_eq!(parse_modules(""), Ok(vec![]));
+        assert!(parse_modules("admin,personal").is_err());
+
+        assert!(check_modules(&[API::Admin], "127.0.0.1").is_ok());
+        assert!(check_modules(&[API::Admin], "::1").is_ok());
+        assert!(check_modules(&[API::Admin], "localhost").is_ok());
+        assert!(check_modules(&[API::Admin], "0.0.0.0").is_err());
+        assert!(check_modules(&[API::Debug], "0.0.0.0").is_ok());
+    }
 }
@@ -1,57 +1,314 @@
-use std::sync::{Arc, RwLock};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{mpsc as std_mpsc, Arc, RwLock};
+use std::sync::atomic::AtomicUsize;
+use std::thread;
+use std::time::Duration;
 
 use tokio::sync::mpsc;
-use jsonrpc_http_server::{AccessControlAllowOrigin, DomainsValidation, RestApi, ServerBuilder};
+use jsonrpc_http_server::{AccessControlAllowOrigin, DomainsValidation, Host, RestApi, ServerBuilder};
+use serde::{Serialize, Deserialize};
 
 use network::manager::NetworkMessage;
+use network::sync::SharedSyncProgress;
+use network::peer_stats::SharedPeerStats;
+use network::time_sync::SharedClockOffsets;
+use network::topic_peers::SharedTopicPeers;
+use network::block_queue::SharedBlockQueue;
+use network::propagation::SharedPropagation;
+use network::p2p_trace::SharedP2PTrace;
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
 use pool::tx_pool::TxPoolManager;
 
+use crate::config::{self, API};
+use crate::request_log::{RequestIds, RpcHandler};
 use crate::rpc_build::RpcBuilder;
+use crate::status::{StatusMiddleware, StatusSource};
 
+#[derive(Clone)]
 pub struct RpcConfig {
     pub rpc_addr: String,
     pub rpc_port: u16,
     pub key:      String,
+    /// Origins allowed to make cross-origin requests, `*` allows any.
+    pub cors_domains: Vec<String>,
+    /// Values accepted in the Host header, `*` allows any.
+    pub vhosts: Vec<String>,
+    /// Largest result sent, larger ones fail with `RESPONSE_TOO_LARGE`.
+    pub max_response_bytes: usize,
+    /// Opt-in modules served besides the chain, account and sync ones, `Admin` and `Debug`.
+    pub modules: Vec<API>,
 }
 
+/// Settings changed by `admin_restartRpc` or a reload, fields left out keep their value.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RpcReload {
+    pub rpc_addr: Option<String>,
+    pub rpc_port: Option<u16>,
+    pub cors_domains: Option<Vec<String>>,
+    pub vhosts: Option<Vec<String>>,
+}
+
+impl RpcConfig {
+    fn url(&self) -> String {
+        format!("{}:{}", self.rpc_addr, self.rpc_port)
+    }
+
+    fn reload(&self, reload: &RpcReload) -> RpcConfig {
+        RpcConfig {
+            rpc_addr: reload.rpc_addr.clone().unwrap_or_else(|| self.rpc_addr.clone()),
+            rpc_port: reload.rpc_port.unwrap_or(self.rpc_port),
+            key: self.key.clone(),
+            cors_domains: reload.cors_domains.clone().unwrap_or_else(|| self.cors_domains.clone()),
+            vhosts: reload.vhosts.clone().unwrap_or_else(|| self.vhosts.clone()),
+            max_response_bytes: self.max_response_bytes,
+            modules: self.modules.clone(),
+        }
+    }
+}
+
+/// File of the datadir read on SIGUSR1, a json `RpcReload`.
+pub const RPC_RELOAD_FILE: &str = "rpc.json";
+/// Time the replaced server is left to answer the requests in flight, the restart request
+/// among them.
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+/// How long a restart request waits for the server to be rebound.
+const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default `vhosts`, only local clients are accepted.
+pub const DEFAULT_VHOSTS: &str = "localhost";
+
+/// Split a comma separated option value into domains.
+pub fn parse_domains(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| d.to_string())
+        .collect()
+}
+
+fn cors_validation(domains: &[String]) -> DomainsValidation<AccessControlAllowOrigin> {
+    if domains.iter().any(|d| d == "*") {
+        return DomainsValidation::AllowOnly(vec![AccessControlAllowOrigin::Any]);
+    }
+    DomainsValidation::AllowOnly(domains.iter().map(|d| d.as_str().into()).collect())
+}
+
+fn hosts_validation(hosts: &[String]) -> DomainsValidation<Host> {
+    if hosts.iter().any(|h| h == "*") {
+        return DomainsValidation::Disabled;
+    }
+    DomainsValidation::AllowOnly(hosts.iter().map(|h| h.as_str().into()).collect())
+}
+
+enum Control {
+    Restart(RpcReload, std_mpsc::Sender<Result<String, String>>),
+    Close,
+}
+
+/// Asks a running server to rebind, handed to `admin_restartRpc` and the reload signal.
+#[derive(Clone)]
+pub struct RpcRestarter {
+    control: std_mpsc::Sender<Control>,
+}
+
+impl RpcRestarter {
+    /// Start a server with the new settings, then close the current one. Returns the new
+    /// listen url, the current server keeps serving if the new one can't be started.
+    pub fn restart(&self, reload: RpcReload) -> Result<String, String> {
+        let (reply, result) = std_mpsc::channel();
+        self.control.send(Control::Restart(reload, reply)).map_err(|_| "rpc server stopped".to_string())?;
+        result.recv_timeout(RESTART_TIMEOUT).map_err(|_| "rpc restart timed out".to_string())?
+    }
+}
+
+/// Json rpc server run by a control thread that can rebind it without stopping the node.
 pub struct RpcServer {
-    pub http: jsonrpc_http_server::Server,
-    pub url: String,
+    restarter: RpcRestarter,
+    thread: thread::JoinHandle<()>,
 }
 
 pub fn start_http(
-    cfg: RpcConfig, block_chain: Arc<RwLock<BlockChain>>,
+    cfg: RpcConfig, block_chain: Arc<TimedRwLock<BlockChain>>,
     tx_pool : Arc<RwLock<TxPoolManager>>,
-    network_send: mpsc::UnboundedSender<NetworkMessage>
+    network_send: mpsc::UnboundedSender<NetworkMessage>,
+    sync_progress: SharedSyncProgress,
+    peer_count: Arc<AtomicUsize>,
+    peer_stats: SharedPeerStats,
+    propagation: SharedPropagation,
+    clock_offsets: SharedClockOffsets,
+    topic_peers: SharedTopicPeers,
+    p2p_trace: SharedP2PTrace,
+    block_queue: SharedBlockQueue,
 ) -> RpcServer {
-    let url = format!("{}:{}", cfg.rpc_addr, cfg.rpc_port);
+    let status = StatusSource::new(block_chain.clone())
+        .with_tx_pool(tx_pool.clone())
+        .with_peer_count(peer_count)
+        .with_sync_progress(sync_progress.clone());
+    let (control, requests) = std_mpsc::channel();
+    let restarter = RpcRestarter { control };
+    let mut builder = RpcBuilder::with_response_limit(cfg.max_response_bytes)
+        .config_chain(block_chain.clone(), Some(tx_pool.clone()))
+        .config_filter(block_chain.clone(), tx_pool.clone())
+        .config_fee(block_chain.clone(), tx_pool.clone())
+        .config_account(tx_pool.clone(), cfg.key.clone(), network_send.clone())
+        .config_sync(sync_progress);
+    if cfg.modules.contains(&API::Debug) {
+        builder = builder.config_debug(block_chain.clone(), propagation, p2p_trace, block_queue);
+    }
+    if cfg.modules.contains(&API::Admin) {
+        builder = builder.config_admin(network_send, peer_stats, clock_offsets, topic_peers, restarter.clone(), block_chain, tx_pool);
+    }
+    let handler = builder.build();
 
-    info!("using url {}", url);
+    serve(cfg, handler, status, restarter, requests)
+}
+
+/// Start a json rpc server exposing query methods only, which doesn't require a running
+/// transaction pool or p2p network.
+pub fn start_query_http(cfg: RpcConfig, block_chain: Arc<TimedRwLock<BlockChain>>) -> RpcServer {
+    let status = StatusSource::new(block_chain.clone());
+    let (control, requests) = std_mpsc::channel();
+    let handler = RpcBuilder::with_response_limit(cfg.max_response_bytes).config_chain(block_chain, None).build();
 
-    let addr = url.parse().map_err(|_| format!("Invalid  listen host/port given: {}", url)).unwrap();
+    serve(cfg, handler, status, RpcRestarter { control }, requests)
+}
 
-    let handler = RpcBuilder::new().config_chain(block_chain).config_account(tx_pool, cfg.key, network_send).build();
+fn bind(cfg: &RpcConfig, handler: RpcHandler, status: StatusSource) -> Result<jsonrpc_http_server::Server, String> {
+    let url = cfg.url();
+    let addr = url.parse().map_err(|_| format!("Invalid  listen host/port given: {}", url))?;
+    config::check_modules(&cfg.modules, &cfg.rpc_addr)?;
 
-    let http = ServerBuilder::new(handler)
+    // Several threads listen with SO_REUSEPORT, a new server can bind the port of the old one
+    ServerBuilder::with_meta_extractor(handler, RequestIds::new())
         .threads(4)
         .rest_api(RestApi::Unsecure)
-        .cors(DomainsValidation::AllowOnly(vec![AccessControlAllowOrigin::Any]))
+        .request_middleware(StatusMiddleware::new(status))
+        .cors(cors_validation(&cfg.cors_domains))
+        .allowed_hosts(hosts_validation(&cfg.vhosts))
         .start_http(&addr)
-        .expect("Start json rpc HTTP service failed");
-    RpcServer { http, url }
+        .map_err(|e| format!("failed to listen on {}: {}", url, e))
+}
+
+fn serve(
+    mut cfg: RpcConfig,
+    handler: RpcHandler,
+    status: StatusSource,
+    restarter: RpcRestarter,
+    requests: std_mpsc::Receiver<Control>,
+) -> RpcServer {
+    info!("using url {}", cfg.url());
+    let mut http = bind(&cfg, handler.clone(), status.clone()).expect("Start json rpc HTTP service failed");
+
+    let thread = thread::spawn(move || {
+        for request in requests {
+            match request {
+                Control::Restart(reload, reply) => {
+                    let next = cfg.reload(&reload);
+                    match bind(&next, handler.clone(), status.clone()) {
+                        Ok(server) => {
+                            let _ = reply.send(Ok(next.url()));
+                            let previous = mem::replace(&mut http, server);
+                            thread::sleep(CLOSE_GRACE);
+                            previous.close();
+                            info!("rpc http moved from {} to {}", cfg.url(), next.url());
+                            cfg = next;
+                        }
+                        Err(e) => {
+                            warn!("rpc restart failed: {}", e);
+                            let _ = reply.send(Err(e));
+                        }
+                    }
+                }
+                Control::Close => break,
+            }
+        }
+        http.close();
+        info!(" rpc http stop {} ", cfg.url());
+    });
+    RpcServer { restarter, thread }
 }
 
 impl RpcServer {
+    pub fn restarter(&self) -> RpcRestarter {
+        self.restarter.clone()
+    }
+
     pub fn close(self) {
-        self.http.close();
-        info!(" rpc http stop {} ", self.url);
+        let _ = self.restarter.control.send(Control::Close);
+        let _ = self.thread.join();
     }
 }
 
+/// Rebind `restarter` with the settings of `path` whenever the process receives SIGUSR1.
+#[cfg(unix)]
+pub fn reload_on_signal(restarter: RpcRestarter, path: PathBuf) {
+    let signals = match signal_hook::iterator::Signals::new(&[signal_hook::SIGUSR1]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!("rpc reload signal unavailable: {}", e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            let reload = std::fs::read(&path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+                .and_then(|data| serde_json::from_slice::<RpcReload>(&data)
+                    .map_err(|e| format!("invalid {}: {}", path.display(), e)));
+            match reload.and_then(|reload| restarter.restart(reload)) {
+                Ok(url) => info!("rpc reloaded, listening on {}", url),
+                Err(e) => warn!("rpc reload failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_signal(_restarter: RpcRestarter, _path: PathBuf) {}
+
 #[cfg(test)]
 mod tests {
     use jsonrpc_core::*;
+    use jsonrpc_http_server::{AccessControlAllowOrigin, DomainsValidation, Host};
+    use super::{cors_validation, hosts_validation, parse_domains, RpcConfig, RpcReload};
+
+    #[test]
+    fn test_domains() {
+        let domains = parse_domains("http://localhost:8080, https://wallet.example ,");
+        assert_eq!(domains, vec!["http://localhost:8080", "https://wallet.example"]);
+
+        assert_eq!(cors_validation(&[]), DomainsValidation::AllowOnly(vec![]));
+        assert_eq!(cors_validation(&domains), DomainsValidation::AllowOnly(vec![
+            AccessControlAllowOrigin::Value("http://localhost:8080".into()),
+            AccessControlAllowOrigin::Value("https://wallet.example".into()),
+        ]));
+        assert_eq!(cors_validation(&parse_domains("*")), DomainsValidation::AllowOnly(vec![AccessControlAllowOrigin::Any]));
+
+        assert_eq!(hosts_validation(&parse_domains("*")), DomainsValidation::Disabled);
+        assert_eq!(hosts_validation(&parse_domains("localhost")), DomainsValidation::AllowOnly(vec![Host::from("localhost")]));
+    }
+
+    #[test]
+    fn test_reload() {
+        let cfg = RpcConfig {
+            rpc_addr: "127.0.0.1".to_string(),
+            rpc_port: 9545,
+            key: String::new(),
+            cors_domains: vec![],
+            vhosts: parse_domains("localhost"),
+            max_response_bytes: 1024,
+            modules: vec![],
+        };
+        let reload: RpcReload = serde_json::from_str(r#"{"rpc_addr": "0.0.0.0", "cors_domains": ["*"]}"#).unwrap();
+        let next = cfg.reload(&reload);
+        assert_eq!(next.url(), "0.0.0.0:9545");
+        assert_eq!(next.cors_domains, vec!["*"]);
+        assert_eq!(next.vhosts, vec!["localhost"]);
+        assert_eq!(next.max_response_bytes, 1024);
+        assert_eq!(cfg.reload(&RpcReload::default()).url(), cfg.url());
+    }
 
     #[test]
     fn test_handler() {
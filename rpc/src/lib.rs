@@ -8,5 +8,7 @@ extern crate log;
 pub mod http_server;
 pub mod api;
 pub mod config;
+pub mod request_log;
 pub mod rpc_build;
+pub mod status;
 pub mod types;
\ No newline at end of file
@@ -0,0 +1,272 @@
+//! Request ids of the json rpc calls, logged with each call and added to the data of its error
+//! response so a client report can be matched with the node logs. Results over the response size
+//! limit are replaced by an error here too, the calls returning large results page them with a
+//! `ResponseBudget` while building them.
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use jsonrpc_core::futures::future::Either;
+use jsonrpc_core::futures::Future;
+use jsonrpc_core::middleware::NoopFuture;
+use jsonrpc_core::{Call, Error, ErrorCode, Failure, Metadata, MetaIoHandler, Middleware, Output, Params, Value};
+use jsonrpc_http_server::hyper::{Body, Request};
+use jsonrpc_http_server::MetaExtractor;
+use serde::Serialize;
+
+/// Header carrying the id a client chose for its request.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client id kept, longer ones are replaced by a node id.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Default largest result sent, in bytes of json.
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Json rpc error code of calls whose result is over the response size limit.
+pub const RESPONSE_TOO_LARGE: i64 = -32021;
+
+/// Bytes of a page kept for the fields around its items.
+pub const PAGE_OVERHEAD_BYTES: usize = 4096;
+
+/// Json rpc handler of the server, tagging each call with its request id.
+pub type RpcHandler = MetaIoHandler<RequestMeta, RequestLog>;
+
+#[derive(Clone, Debug, Default)]
+pub struct RequestMeta {
+    pub request_id: String,
+}
+
+impl Metadata for RequestMeta {}
+
+/// Takes the id of `X-Request-Id`, or numbers the requests without one.
+pub struct RequestIds {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl RequestIds {
+    pub fn new() -> Self {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        RequestIds { prefix: format!("{:x}", started), next: AtomicU64::new(1) }
+    }
+
+    pub fn request_id(&self, header: Option<&str>) -> String {
+        match header {
+            Some(id) if is_valid_id(id) => id.to_string(),
+            _ => format!("{}-{}", self.prefix, self.next.fetch_add(1, Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Client ids are written to the logs, only short printable ones are kept.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+impl MetaExtractor<RequestMeta> for RequestIds {
+    fn read_metadata(&self, request: &Request<Body>) -> RequestMeta {
+        let header = request.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok());
+        RequestMeta { request_id: self.request_id(header) }
+    }
+}
+
+/// Logs the method, params size, duration and outcome of each call, one `key=value` line under
+/// the `rpc` target: failures at info, successes at debug.
+#[derive(Clone)]
+pub struct RequestLog {
+    max_response_bytes: usize,
+}
+
+impl RequestLog {
+    pub fn new(max_response_bytes: usize) -> Self {
+        RequestLog { max_response_bytes }
+    }
+}
+
+impl Default for RequestLog {
+    fn default() -> Self {
+        RequestLog::new(DEFAULT_MAX_RESPONSE_BYTES)
+    }
+}
+
+impl Middleware<RequestMeta> for RequestLog {
+    type Future = NoopFuture;
+    type CallFuture = Box<dyn Future<Item = Option<Output>, Error = ()> + Send>;
+
+    fn on_call<F, X>(&self, call: Call, meta: RequestMeta, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, RequestMeta) -> X + Send + Sync,
+        X: Future<Item = Option<Output>, Error = ()> + Send + 'static,
+    {
+        let (method, params_bytes) = match call {
+            Call::MethodCall(ref call) => (call.method.clone(), params_len(&call.params)),
+            Call::Notification(ref call) => (call.method.clone(), params_len(&call.params)),
+            Call::Invalid { .. } => ("-".to_string(), 0),
+        };
+        let request_id = meta.request_id.clone();
+        let max_response_bytes = self.max_response_bytes;
+        let started = Instant::now();
+        Either::A(Box::new(next(call, meta).map(move |output| {
+            let output = output.map(|output| limit_size(output, max_response_bytes));
+            let duration_ms = started.elapsed().as_millis();
+            match output {
+                Some(Output::Failure(ref failure)) => info!(target: "rpc",
+                    "request_id={} method={} params_bytes={} duration_ms={} outcome=error code={}",
+                    request_id, method, params_bytes, duration_ms, failure.error.code.code()),
+                _ => debug!(target: "rpc",
+                    "request_id={} method={} params_bytes={} duration_ms={} outcome=ok",
+                    request_id, method, params_bytes, duration_ms),
+            }
+            output.map(|output| tag_failure(output, &request_id))
+        })))
+    }
+}
+
+fn params_len(params: &Params) -> usize {
+    serde_json::to_vec(params).map(|encoded| encoded.len()).unwrap_or(0)
+}
+
+/// Replace a result of more than `max` bytes by an error, the client narrows the call or pages
+/// through it. The paged calls stay under the limit, this only stops the others.
+fn limit_size(output: Output, max: usize) -> Output {
+    match output {
+        Output::Success(success) => {
+            if encoded_len(&success.result, max).is_some() {
+                return Output::Success(success);
+            }
+            Output::Failure(Failure { jsonrpc: success.jsonrpc, error: too_large(max), id: success.id })
+        }
+        failure => failure,
+    }
+}
+
+/// Error of a result over the response size limit of `max` bytes.
+pub fn too_large(max: usize) -> Error {
+    Error {
+        code: ErrorCode::ServerError(RESPONSE_TOO_LARGE),
+        message: format!("response exceeds the limit of {} bytes", max),
+        data: Some(serde_json::json!({ "limit": max })),
+    }
+}
+
+/// Length of the json of `value`, None as soon as it passes `max` bytes. Nothing is buffered
+/// and encoding stops at the limit.
+pub fn encoded_len<T: Serialize + ?Sized>(value: &T, max: usize) -> Option<usize> {
+    let mut counter = ByteCounter { len: 0, max };
+    serde_json::to_writer(&mut counter, value).ok()?;
+    Some(counter.len)
+}
+
+struct ByteCounter {
+    len: usize,
+    max: usize,
+}
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.len += buf.len();
+        if self.len > self.max {
+            return Err(io::Error::new(io::ErrorKind::Other, "over the limit"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Bytes of the response size limit left for the items of a page, taken by each item added so a
+/// page ends before the limit rather than failing on it.
+pub struct ResponseBudget {
+    left: usize,
+}
+
+impl ResponseBudget {
+    pub fn new(max_response_bytes: usize) -> Self {
+        ResponseBudget { left: max_response_bytes.saturating_sub(PAGE_OVERHEAD_BYTES) }
+    }
+
+    /// Whether `item` fits in what is left, which it is taken from if so.
+    pub fn take<T: Serialize + ?Sized>(&mut self, item: &T) -> bool {
+        // One more byte for the separator of the item in its list
+        match encoded_len(item, self.left) {
+            Some(len) => {
+                self.left = self.left.saturating_sub(len + 1);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Add `request_id` to the data of an error, which is an object or absent for the node errors.
+fn tag_failure(output: Output, request_id: &str) -> Output {
+    match output {
+        Output::Failure(mut failure) => {
+            let id = Value::String(request_id.to_string());
+            match failure.error.data {
+                Some(Value::Object(ref mut data)) => { data.insert("request_id".to_string(), id); }
+                None => failure.error.data = Some(serde_json::json!({ "request_id": id })),
+                Some(_) => {}
+            }
+            Output::Failure(failure)
+        }
+        success => success,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_carry_request_id() {
+        let mut io = RpcHandler::with_middleware(RequestLog::default());
+        io.add_method("ok", |_: Params| Ok(Value::Bool(true)));
+        io.add_method("fail", |_: Params| Err(Error::invalid_params("bad")));
+        let meta = RequestMeta { request_id: "abc".to_string() };
+
+        let ok = r#"{"jsonrpc": "2.0", "method": "ok", "params": [], "id": 1}"#;
+        assert_eq!(io.handle_request_sync(ok, meta.clone()), Some(r#"{"jsonrpc":"2.0","result":true,"id":1}"#.to_string()));
+        let fail = r#"{"jsonrpc": "2.0", "method": "fail", "params": [], "id": 2}"#;
+        let response: Value = serde_json::from_str(&io.handle_request_sync(fail, meta).unwrap()).unwrap();
+        assert_eq!(response["error"]["data"]["request_id"], "abc");
+
+        let ids = RequestIds::new();
+        assert_eq!(ids.request_id(Some("client-7")), "client-7");
+        let generated = ids.request_id(Some("has space"));
+        assert_ne!(generated, "has space");
+        assert_ne!(ids.request_id(None), generated);
+    }
+
+    #[test]
+    fn oversized_results_rejected() {
+        let mut io = RpcHandler::with_middleware(RequestLog::new(16));
+        io.add_method("short", |_: Params| Ok(Value::String("0123456789".to_string())));
+        io.add_method("long", |_: Params| Ok(Value::String("0123456789abcdef".to_string())));
+        let meta = RequestMeta { request_id: "abc".to_string() };
+
+        let short = r#"{"jsonrpc": "2.0", "method": "short", "params": [], "id": 1}"#;
+        let response: Value = serde_json::from_str(&io.handle_request_sync(short, meta.clone()).unwrap()).unwrap();
+        assert_eq!(response["result"], "0123456789");
+        let long = r#"{"jsonrpc": "2.0", "method": "long", "params": [], "id": 2}"#;
+        let response: Value = serde_json::from_str(&io.handle_request_sync(long, meta).unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], RESPONSE_TOO_LARGE);
+        assert_eq!(response["error"]["data"], serde_json::json!({ "limit": 16, "request_id": "abc" }));
+        assert_eq!(response["id"], 2);
+    }
+
+    #[test]
+    fn budget() {
+        assert_eq!(encoded_len("0123", 6), Some(6));
+        assert_eq!(encoded_len("01234", 6), None);
+
+        let mut budget = ResponseBudget::new(PAGE_OVERHEAD_BYTES + 10);
+        assert!(budget.take("0123"));
+        assert!(!budget.take("0123"));
+        assert!(budget.take("0"));
+        assert!(!budget.take("0"));
+    }
+}
@@ -1,31 +1,58 @@
-use jsonrpc_core::{IoHandler};
 use tokio::sync::mpsc;
 
 use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
 use pool::tx_pool::TxPoolManager;
 use std::sync::{Arc, RwLock};
 
 use network::manager::NetworkMessage;
+use network::sync::SharedSyncProgress;
+use network::peer_stats::SharedPeerStats;
+use network::time_sync::SharedClockOffsets;
+use network::topic_peers::SharedTopicPeers;
+use network::block_queue::SharedBlockQueue;
+use network::propagation::SharedPropagation;
+use network::p2p_trace::SharedP2PTrace;
+use crate::http_server::RpcRestarter;
+use crate::request_log::{RequestLog, RpcHandler, DEFAULT_MAX_RESPONSE_BYTES};
 use crate::api::{
     ChainRpc, ChainRpcImpl,
-    AccountManager, AccountManagerImpl};
+    AccountManager, AccountManagerImpl,
+    SyncRpc, SyncRpcImpl,
+    AdminRpc, AdminRpcImpl,
+    FeeRpc, FeeRpcImpl,
+    FilterRpc, FilterRpcImpl,
+    DebugRpc, DebugRpcImpl};
 
 pub struct RpcBuilder {
-    io_handler: IoHandler,
+    io_handler: RpcHandler,
+    max_response_bytes: usize,
 }
 
 impl RpcBuilder {
     pub fn new() -> Self {
+        Self::with_response_limit(DEFAULT_MAX_RESPONSE_BYTES)
+    }
+
+    /// Builder whose handler rejects results of more than `max_response_bytes` bytes
+    pub fn with_response_limit(max_response_bytes: usize) -> Self {
         Self {
-            io_handler: IoHandler::new(),
+            io_handler: RpcHandler::with_middleware(RequestLog::new(max_response_bytes)),
+            max_response_bytes,
         }
     }
-    pub fn config_chain(mut self, block_chain: Arc<RwLock<BlockChain>>) -> Self {
-        let chain = ChainRpcImpl { block_chain }.to_delegate();
+    pub fn config_chain(mut self, block_chain: Arc<TimedRwLock<BlockChain>>, tx_pool: Option<Arc<RwLock<TxPoolManager>>>) -> Self {
+        let chain = ChainRpcImpl { block_chain, tx_pool, max_response_bytes: self.max_response_bytes }.to_delegate();
         self.io_handler.extend_with(chain);
         self
     }
 
+    pub fn config_filter(mut self, block_chain: Arc<TimedRwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>) -> Self {
+        let filter = FilterRpcImpl { block_chain, tx_pool }.to_delegate();
+        self.io_handler.extend_with(filter);
+        self
+    }
+
     pub fn config_account(
         mut self,
         tx_pool: Arc<RwLock<TxPoolManager>>,
@@ -37,7 +64,46 @@ impl RpcBuilder {
         self
     }
 
-    pub fn build(self) -> IoHandler {
+    pub fn config_fee(mut self, block_chain: Arc<TimedRwLock<BlockChain>>, tx_pool: Arc<RwLock<TxPoolManager>>) -> Self {
+        let fee = FeeRpcImpl { block_chain, tx_pool }.to_delegate();
+        self.io_handler.extend_with(fee);
+        self
+    }
+
+    pub fn config_debug(mut self, block_chain: Arc<TimedRwLock<BlockChain>>, propagation: SharedPropagation, p2p_trace: SharedP2PTrace, block_queue: SharedBlockQueue) -> Self {
+        let debug = DebugRpcImpl {
+            block_chain,
+            propagation,
+            p2p_trace,
+            block_queue,
+            max_response_bytes: self.max_response_bytes,
+        }.to_delegate();
+        self.io_handler.extend_with(debug);
+        self
+    }
+
+    pub fn config_sync(mut self, sync_progress: SharedSyncProgress) -> Self {
+        let sync = SyncRpcImpl { sync_progress }.to_delegate();
+        self.io_handler.extend_with(sync);
+        self
+    }
+
+    pub fn config_admin(
+        mut self,
+        network_send: mpsc::UnboundedSender<NetworkMessage>,
+        peer_stats: SharedPeerStats,
+        clock_offsets: SharedClockOffsets,
+        topic_peers: SharedTopicPeers,
+        rpc_restarter: RpcRestarter,
+        block_chain: Arc<TimedRwLock<BlockChain>>,
+        tx_pool: Arc<RwLock<TxPoolManager>>,
+    ) -> Self {
+        let admin = AdminRpcImpl { network_send, peer_stats, clock_offsets, topic_peers, rpc_restarter, block_chain, tx_pool }.to_delegate();
+        self.io_handler.extend_with(admin);
+        self
+    }
+
+    pub fn build(self) -> RpcHandler {
         self.io_handler
     }
 }
@@ -0,0 +1,183 @@
+//! `GET /status` endpoint on the RPC port, a single JSON probe of the node state.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use jsonrpc_core::futures::future;
+use jsonrpc_http_server::hyper::{header, Body, Method, Request, Response};
+use jsonrpc_http_server::{RequestMiddleware, RequestMiddlewareAction};
+use serde::Serialize;
+
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use chain::prune::NodeMode;
+use map_core::types::Hash;
+use network::sync::{ProposalState, SharedSyncProgress};
+use pool::tx_pool::TxPoolManager;
+
+/// Path served by `StatusMiddleware`.
+pub const STATUS_PATH: &str = "/status";
+
+/// Node state summary. Fields of services not running in the process are null.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NodeStatus {
+    pub version: String,
+    pub network_id: u16,
+    pub head_height: u64,
+    pub head_hash: Hash,
+    pub finalized_height: u64,
+    /// Whether the state of old blocks is kept
+    pub mode: NodeMode,
+    /// Lowest height from which state queries are answered, besides genesis
+    pub earliest_state_height: u64,
+    pub peer_count: Option<usize>,
+    pub tx_pool_pending: Option<usize>,
+    pub tx_pool_size: Option<usize>,
+    pub syncing: Option<bool>,
+    /// Connected peers whose status matched our chain
+    pub chain_peers: Option<usize>,
+    /// Whether the block producer proposes in its slots
+    pub proposal: Option<ProposalState>,
+    pub uptime_secs: u64,
+}
+
+/// Collects the `NodeStatus` from the node services.
+#[derive(Clone)]
+pub struct StatusSource {
+    block_chain: Arc<TimedRwLock<BlockChain>>,
+    tx_pool: Option<Arc<RwLock<TxPoolManager>>>,
+    peer_count: Option<Arc<AtomicUsize>>,
+    sync_progress: Option<SharedSyncProgress>,
+    started: Instant,
+}
+
+impl StatusSource {
+    pub fn new(block_chain: Arc<TimedRwLock<BlockChain>>) -> Self {
+        StatusSource {
+            block_chain,
+            tx_pool: None,
+            peer_count: None,
+            sync_progress: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn with_tx_pool(mut self, tx_pool: Arc<RwLock<TxPoolManager>>) -> Self {
+        self.tx_pool = Some(tx_pool);
+        self
+    }
+
+    pub fn with_peer_count(mut self, peer_count: Arc<AtomicUsize>) -> Self {
+        self.peer_count = Some(peer_count);
+        self
+    }
+
+    pub fn with_sync_progress(mut self, sync_progress: SharedSyncProgress) -> Self {
+        self.sync_progress = Some(sync_progress);
+        self
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        let (head, network_id, mode, earliest_state_height) = {
+            let chain = self.block_chain.read().expect("acquiring block_chain read lock");
+            (chain.current_block(), chain.spec().network_id, chain.mode(), chain.earliest_state_height())
+        };
+        let (pending, size) = match self.tx_pool {
+            Some(ref pool) => {
+                let pool = pool.read().expect("acquiring tx_pool read lock");
+                (Some(pool.pending_len()), Some(pool.size()))
+            }
+            None => (None, None),
+        };
+
+        let progress = self.sync_progress.as_ref()
+            .map(|progress| progress.read().expect("acquiring sync progress lock").clone());
+
+        NodeStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            network_id,
+            head_height: head.height(),
+            head_hash: head.hash(),
+            // Blocks are final once imported
+            finalized_height: head.height(),
+            mode,
+            earliest_state_height,
+            peer_count: self.peer_count.as_ref().map(|count| count.load(Ordering::Relaxed)),
+            tx_pool_pending: pending,
+            tx_pool_size: size,
+            syncing: progress.as_ref().map(|progress| progress.syncing),
+            chain_peers: progress.as_ref().map(|progress| progress.chain_peers),
+            proposal: progress.as_ref().map(|progress| progress.proposal),
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+}
+
+/// Answers `GET /status` before the request reaches the json rpc handler.
+pub struct StatusMiddleware {
+    source: StatusSource,
+}
+
+impl StatusMiddleware {
+    pub fn new(source: StatusSource) -> Self {
+        StatusMiddleware { source }
+    }
+}
+
+impl RequestMiddleware for StatusMiddleware {
+    fn on_request(&self, request: Request<Body>) -> RequestMiddlewareAction {
+        if request.method() != Method::GET || request.uri().path() != STATUS_PATH {
+            return request.into();
+        }
+
+        let body = serde_json::to_string(&self.source.status()).expect("serialize node status");
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("valid status response");
+        RequestMiddlewareAction::Respond {
+            should_validate_hosts: true,
+            response: Box::new(future::ok(response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use map_core::chain_spec::ChainSpec;
+
+    #[test]
+    fn test_chain_only_status() {
+        let dir = std::env::temp_dir().join("map_rpc_status_test");
+        let _ = fs::remove_dir_all(&dir);
+        let mut chain = BlockChain::new(dir, &ChainSpec::default()).unwrap();
+        chain.load().unwrap();
+        let genesis = chain.current_block();
+
+        let source = StatusSource::new(Arc::new(TimedRwLock::new("chain", chain)))
+            .with_peer_count(Arc::new(AtomicUsize::new(3)));
+        let status = source.status();
+        assert_eq!(status.head_height, genesis.height());
+        assert_eq!(status.head_hash, genesis.hash());
+        assert_eq!(status.finalized_height, status.head_height);
+        assert_eq!(status.network_id, ChainSpec::default().network_id);
+        assert_eq!(status.peer_count, Some(3));
+        assert_eq!(status.tx_pool_size, None);
+        assert_eq!(status.syncing, None);
+        assert_eq!(status.mode, NodeMode::Archive);
+        assert_eq!(status.earliest_state_height, 0);
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert!(json["tx_pool_pending"].is_null());
+        assert_eq!(json["peer_count"], 3);
+        assert_eq!(json["mode"], "archive");
+        assert!(json["proposal"].is_null());
+
+        let progress = network::sync::SyncProgress { proposal: ProposalState::InsufficientPeers, ..Default::default() };
+        let json = serde_json::to_value(&source.with_sync_progress(Arc::new(RwLock::new(progress))).status()).unwrap();
+        assert_eq!(json["chain_peers"], 0);
+        assert_eq!(json["proposal"], "insufficient_peers");
+    }
+}
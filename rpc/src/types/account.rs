@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+use map_core::balance::Account;
+use map_core::types::Address;
+
+/// Account snapshot returned by `map_getAccounts`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountJson {
+    pub address: Address,
+    pub balance: u128,
+    pub nonce: u64,
+    /// Balance reserved by staking.
+    pub locked_balance: u128,
+}
+
+impl AccountJson {
+    pub fn new(address: Address, account: Account) -> Self {
+        AccountJson {
+            address,
+            balance: account.get_balance(),
+            nonce: account.get_nonce(),
+            locked_balance: account.get_locked_balance(),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use map_core::types::Hash;
+use pool::tx_pool::{TxAdmission, TxPoolError, TxStatus};
+
+/// Result of `map_sendTransaction` and `map_sendRawTransaction` for an accepted transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TxAdmissionJson {
+    pub hash: Hash,
+    /// `pending` if executable, `queued` if waiting for a free pool slot.
+    pub status: &'static str,
+    /// Transactions of the same status paying a higher gas price.
+    pub position: usize,
+    /// Transaction evicted to make room for this one.
+    pub replaced: Option<Hash>,
+}
+
+/// Result of `map_reserveNonces`, the nonces reserved for the caller, both included.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NonceRangeJson {
+    pub first: u64,
+    pub last: u64,
+    /// Seconds until the nonces left unused are handed out again.
+    pub expires_in: u64,
+}
+
+impl From<TxAdmission> for TxAdmissionJson {
+    fn from(admission: TxAdmission) -> Self {
+        TxAdmissionJson {
+            hash: admission.hash,
+            status: match admission.status {
+                TxStatus::Pending => "pending",
+                TxStatus::Queued => "queued",
+            },
+            position: admission.position,
+            replaced: admission.replaced,
+        }
+    }
+}
+
+/// Parameter of `map_sendTransaction` that doesn't decode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxInputError {
+    /// Not 20 hex encoded bytes.
+    InvalidAddress { field: &'static str, detail: String },
+    /// No key held by the node for the sender.
+    UnknownAccount,
+    /// Not a non-negative integer.
+    InvalidValue(String),
+    /// Integer above the max balance.
+    ValueOverflow(String),
+}
+
+impl TxInputError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TxInputError::InvalidAddress { .. } => "invalid_address",
+            TxInputError::UnknownAccount => "unknown_account",
+            TxInputError::InvalidValue(_) => "invalid_value",
+            TxInputError::ValueOverflow(_) => "value_overflow",
+        }
+    }
+
+    pub fn field(&self) -> &'static str {
+        match self {
+            TxInputError::InvalidAddress { field, .. } => field,
+            TxInputError::UnknownAccount => "from",
+            TxInputError::InvalidValue(_) | TxInputError::ValueOverflow(_) => "value",
+        }
+    }
+}
+
+impl fmt::Display for TxInputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxInputError::InvalidAddress { field, detail } => write!(f, "invalid {} address: {}", field, detail),
+            TxInputError::UnknownAccount => write!(f, "no key for the from account"),
+            TxInputError::InvalidValue(value) => write!(f, "invalid value {}", value),
+            TxInputError::ValueOverflow(value) => write!(f, "value {} overflows", value),
+        }
+    }
+}
+
+/// Error data of a transaction rejected by the pool or for its input, with the offending `field`.
+/// Fields not relevant to the reason are omitted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TxRejectionJson {
+    pub reason: &'static str,
+    pub field: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<Hash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_nonce: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_slots: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_gas_price: Option<u64>,
+}
+
+impl From<&TxPoolError> for TxRejectionJson {
+    fn from(e: &TxPoolError) -> Self {
+        let mut json = TxRejectionJson::new(e.reason(), e.field());
+        match *e {
+            TxPoolError::AlreadyKnown(hash) => json.hash = Some(hash),
+            TxPoolError::InsufficientFunds { balance, value } => {
+                json.balance = Some(balance);
+                json.value = Some(value);
+            }
+            TxPoolError::InvalidNonce { expected, got } => {
+                json.expected_nonce = Some(expected);
+                json.nonce = Some(got);
+            }
+            TxPoolError::AccountLimit(limit) => json.account_slots = Some(limit),
+            TxPoolError::Underpriced(price) | TxPoolError::BelowMinGasPrice(price) => json.min_gas_price = Some(price),
+            TxPoolError::ValueOverflow(value) => json.value = Some(value),
+            TxPoolError::UnknownCall(ref call) => json.detail = Some(call.clone()),
+            TxPoolError::InvalidSignature => {}
+        }
+        json
+    }
+}
+
+impl From<&TxInputError> for TxRejectionJson {
+    fn from(e: &TxInputError) -> Self {
+        let mut json = TxRejectionJson::new(e.reason(), e.field());
+        match e {
+            TxInputError::InvalidAddress { detail, .. } => json.detail = Some(detail.clone()),
+            TxInputError::UnknownAccount => {}
+            TxInputError::InvalidValue(value) | TxInputError::ValueOverflow(value) => json.detail = Some(value.clone()),
+        }
+        json
+    }
+}
+
+impl TxRejectionJson {
+    fn new(reason: &'static str, field: &'static str) -> Self {
+        TxRejectionJson {
+            reason,
+            field,
+            detail: None,
+            hash: None,
+            balance: None,
+            value: None,
+            expected_nonce: None,
+            nonce: None,
+            account_slots: None,
+            min_gas_price: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admission_json() {
+        let json = serde_json::to_value(TxAdmissionJson::from(TxAdmission {
+            hash: Hash::default(),
+            status: TxStatus::Queued,
+            position: 2,
+            replaced: None,
+        })).unwrap();
+        assert_eq!(json["status"], "queued");
+        assert_eq!(json["position"], 2);
+        assert!(json["replaced"].is_null());
+    }
+
+    #[test]
+    fn admission_client_json() {
+        let json = serde_json::to_value(TxAdmissionJson::from(TxAdmission {
+            hash: Hash::default(),
+            status: TxStatus::Pending,
+            position: 0,
+            replaced: Some(Hash::default()),
+        })).unwrap();
+        let client: map_rpc_client::types::TxAdmissionJson = serde_json::from_value(json).unwrap();
+        assert_eq!(client.status, "pending");
+        assert_eq!(client.replaced, Some(Hash::default()));
+    }
+
+    #[test]
+    fn rejection_json() {
+        let e = TxPoolError::InvalidNonce { expected: 3, got: 5 };
+        let json = serde_json::to_value(TxRejectionJson::from(&e)).unwrap();
+        assert_eq!(json["reason"], "invalid_nonce");
+        assert_eq!(json["expected_nonce"], 3);
+        assert_eq!(json["nonce"], 5);
+        assert_eq!(json["field"], "nonce");
+        assert!(json.get("balance").is_none());
+
+        let e = TxInputError::InvalidAddress { field: "to", detail: "Invalid string length".into() };
+        let json = serde_json::to_value(TxRejectionJson::from(&e)).unwrap();
+        assert_eq!((json["reason"].as_str(), json["field"].as_str()), (Some("invalid_address"), Some("to")));
+        assert_eq!(json["detail"], "Invalid string length");
+    }
+}
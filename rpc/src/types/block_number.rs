@@ -0,0 +1,43 @@
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+/// Block argument of `map_getBlockByNumber`, a height or one of the tags "latest" and "pending".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockNumber {
+    Num(u64),
+    /// The head
+    Latest,
+    /// The block the pool would fill next on top of the head
+    Pending,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawBlockNumber {
+    Num(u64),
+    Tag(String),
+}
+
+impl<'de> Deserialize<'de> for BlockNumber {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match RawBlockNumber::deserialize(deserializer)? {
+            RawBlockNumber::Num(num) => Ok(BlockNumber::Num(num)),
+            RawBlockNumber::Tag(ref tag) if tag == "latest" => Ok(BlockNumber::Latest),
+            RawBlockNumber::Tag(ref tag) if tag == "pending" => Ok(BlockNumber::Pending),
+            RawBlockNumber::Tag(tag) => Err(D::Error::custom(format!("unknown block tag {}", tag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_or_tag() {
+        assert_eq!(serde_json::from_str::<BlockNumber>("12").unwrap(), BlockNumber::Num(12));
+        assert_eq!(serde_json::from_str::<BlockNumber>("\"latest\"").unwrap(), BlockNumber::Latest);
+        assert_eq!(serde_json::from_str::<BlockNumber>("\"pending\"").unwrap(), BlockNumber::Pending);
+        assert!(serde_json::from_str::<BlockNumber>("\"earliest\"").is_err());
+    }
+}
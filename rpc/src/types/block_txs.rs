@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+use map_core::block::{Block, BlockProof, Header, VerificationItem};
+use map_core::transaction::Transaction;
+use map_core::types::Hash;
+
+use crate::request_log::ResponseBudget;
+
+/// Transactions of a block response, the full objects or only their hashes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum BlockTxs {
+    Full(Vec<Transaction>),
+    Hashes(Vec<Hash>),
+}
+
+/// Result of `map_getBlock` and `map_getBlockByNumber`, laid out as the block itself.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockTxsJson {
+    pub header: Header,
+    pub signs: Vec<VerificationItem>,
+    pub txs: BlockTxs,
+    pub proofs: Vec<BlockProof>,
+}
+
+impl BlockTxsJson {
+    pub fn new(block: Block, full_tx: bool) -> Self {
+        let txs = if full_tx {
+            BlockTxs::Full(block.txs)
+        } else {
+            BlockTxs::Hashes(block.txs.iter().map(|tx| tx.hash()).collect())
+        };
+        BlockTxsJson { header: block.header, signs: block.signs, txs, proofs: block.proofs }
+    }
+}
+
+/// Result of `map_getBlockTransactions`, a page of the transactions of a block.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockTransactionsJson {
+    pub block_hash: Hash,
+    /// Transactions in the block
+    pub total: usize,
+    /// Position of the first transaction of the page in the block
+    pub offset: usize,
+    pub transactions: Vec<Transaction>,
+    /// Position the next page starts at, null when the page reaches the last transaction
+    pub next_offset: Option<usize>,
+}
+
+impl BlockTransactionsJson {
+    /// Up to `limit` transactions from `offset`, fewer if the next one does not fit in `budget`.
+    pub fn new(block: &Block, offset: usize, limit: usize, budget: &mut ResponseBudget) -> Self {
+        let mut transactions = Vec::new();
+        for tx in block.txs.iter().skip(offset).take(limit) {
+            if !budget.take(tx) {
+                break;
+            }
+            transactions.push(tx.clone());
+        }
+        let end = offset + transactions.len();
+        BlockTransactionsJson {
+            block_hash: block.hash(),
+            total: block.txs.len(),
+            offset,
+            transactions,
+            next_offset: if end < block.txs.len() { Some(end) } else { None },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_or_full() {
+        let tx = Transaction { nonce: 1, ..Default::default() };
+        let block = Block::new(Header::default(), vec![tx.clone(), Transaction::default()], Vec::new(), Vec::new());
+
+        let full = serde_json::to_value(BlockTxsJson::new(block.clone(), true)).unwrap();
+        assert_eq!(full, serde_json::to_value(&block).unwrap());
+        let hashes = serde_json::to_value(BlockTxsJson::new(block.clone(), false)).unwrap();
+        assert_eq!(hashes["txs"][0], serde_json::to_value(tx.hash()).unwrap());
+
+        let budget = || ResponseBudget::new(1024 * 1024);
+        let page = BlockTransactionsJson::new(&block, 1, 5, &mut budget());
+        assert_eq!((page.total, page.transactions.len(), page.next_offset), (2, 1, None));
+        assert_eq!(BlockTransactionsJson::new(&block, 0, 1, &mut budget()).next_offset, Some(1));
+        assert!(BlockTransactionsJson::new(&block, 3, 5, &mut budget()).transactions.is_empty());
+
+        // A page ends before the size limit
+        let page = BlockTransactionsJson::new(&block, 0, 5, &mut ResponseBudget::new(0));
+        assert_eq!((page.transactions.len(), page.next_offset), (0, Some(0)));
+    }
+}
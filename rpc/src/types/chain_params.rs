@@ -0,0 +1,102 @@
+use serde::Serialize;
+
+use map_core::block::Block;
+use map_core::chain_spec::{ChainSpec, EngineSpec, GenesisSpec, SpecConstants};
+use map_core::types::{AddressScheme, Hash};
+
+use crate::types::block_txs::BlockTxsJson;
+
+/// Parameters of the chain the node follows, served by `map_chainParams` so clients don't
+/// hard-code protocol constants.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainParamsJson {
+    pub name: String,
+    pub network_id: u16,
+    pub chain_id: u32,
+    /// "poa" or "apos"
+    pub engine: String,
+    pub genesis_hash: Hash,
+    /// Unix time of the genesis block, the slot of a timestamp is
+    /// `(timestamp - genesis_time) / slot_duration`
+    pub genesis_time: u64,
+    /// Seconds per slot
+    pub slot_duration: u64,
+    /// Slots per epoch
+    pub epoch_length: u64,
+    /// Hash peers compare in the handshake, as printed by `spec export`
+    pub spec_hash: Hash,
+    pub address_scheme: AddressScheme,
+    pub fees: FeeParamsJson,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeeParamsJson {
+    /// Fee charged for a transfer
+    pub transfer_fee: u128,
+    /// Gas price floor recommended by the chain spec
+    pub spec_min_gas_price: u64,
+    /// Lowest gas price the pool of this node admits, None on the query only server
+    pub pool_min_gas_price: Option<u64>,
+}
+
+impl ChainParamsJson {
+    pub fn new(spec: &ChainSpec, address_scheme: AddressScheme, genesis_hash: Hash, genesis_time: u64, pool_min_gas_price: Option<u64>) -> Self {
+        let constants = SpecConstants::current();
+        let engine = match spec.engine {
+            EngineSpec::Poa { .. } => "poa",
+            EngineSpec::APoS => "apos",
+        };
+        ChainParamsJson {
+            name: spec.name.clone(),
+            network_id: spec.network_id,
+            chain_id: constants.chain_id,
+            engine: engine.to_string(),
+            genesis_hash,
+            genesis_time,
+            slot_duration: constants.slot_duration,
+            epoch_length: constants.epoch_length,
+            spec_hash: spec.spec_hash(),
+            address_scheme,
+            fees: FeeParamsJson {
+                transfer_fee: executor::TRANSFER_FEE,
+                spec_min_gas_price: spec.min_gas_price,
+                pool_min_gas_price,
+            },
+        }
+    }
+}
+
+/// Result of `map_getGenesis`, the genesis block with the allocation and validators it was
+/// built from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GenesisJson {
+    pub hash: Hash,
+    pub block: BlockTxsJson,
+    pub spec: GenesisSpec,
+    pub params: ChainParamsJson,
+}
+
+impl GenesisJson {
+    pub fn new(block: Block, spec: GenesisSpec, params: ChainParamsJson) -> Self {
+        GenesisJson { hash: block.hash(), block: BlockTxsJson::new(block, true), spec, params }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_of_spec() {
+        let spec = ChainSpec::testnet();
+        let params = ChainParamsJson::new(&spec, AddressScheme::default(), Hash([1; 32]), 100, None);
+        assert_eq!(params.network_id, spec.network_id);
+        assert_eq!(params.slot_duration, map_core::genesis::SLOT_DURATION);
+        assert_eq!(params.epoch_length, map_core::genesis::EPOCH_LENGTH);
+        assert_eq!(params.spec_hash, spec.spec_hash());
+
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json["fees"]["pool_min_gas_price"], serde_json::Value::Null);
+        assert!(json["engine"] == "poa" || json["engine"] == "apos");
+    }
+}
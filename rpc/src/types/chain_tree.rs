@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use map_core::block::Header;
+use map_core::types::Hash;
+
+use crate::request_log::ResponseBudget;
+
+/// Result of `debug_chainTree`, the blocks known near the head and how they link.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainTreeJson {
+    pub head: Hash,
+    pub head_height: u64,
+    /// By height, the canonical block first at each height
+    pub blocks: Vec<TreeBlockJson>,
+    /// Whether the lowest blocks were left out to stay under the response size limit
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TreeBlockJson {
+    pub hash: Hash,
+    pub parent_hash: Hash,
+    pub height: u64,
+    pub slot: u64,
+    /// On the chain of the head, false for blocks held in the block queue
+    pub canonical: bool,
+    /// Known blocks built on this one, more than one where the chain forks
+    pub children: Vec<Hash>,
+}
+
+impl ChainTreeJson {
+    /// Tree of `blocks`, each header with whether it is canonical. The highest blocks fitting in
+    /// `budget` are kept.
+    pub fn new(head: &Header, blocks: Vec<(Header, bool)>, budget: &mut ResponseBudget) -> Self {
+        let mut children: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for (header, _) in blocks.iter() {
+            children.entry(header.parent_hash).or_insert_with(Vec::new).push(header.hash());
+        }
+        let mut blocks: Vec<TreeBlockJson> = blocks.into_iter()
+            .map(|(header, canonical)| {
+                let hash = header.hash();
+                let mut children = children.remove(&hash).unwrap_or_default();
+                children.sort();
+                TreeBlockJson {
+                    hash,
+                    parent_hash: header.parent_hash,
+                    height: header.height,
+                    slot: header.slot,
+                    canonical,
+                    children,
+                }
+            })
+            .collect();
+        blocks.sort_by(|a, b| (a.height, !a.canonical, a.hash).cmp(&(b.height, !b.canonical, b.hash)));
+        let fitting = blocks.iter().rev().take_while(|block| budget.take(block)).count();
+        let truncated = fitting < blocks.len();
+        let blocks = blocks.split_off(blocks.len() - fitting);
+        ChainTreeJson { head: head.hash(), head_height: head.height, blocks, truncated }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_log::PAGE_OVERHEAD_BYTES;
+
+    fn header(height: u64, slot: u64, parent: Hash) -> Header {
+        Header { height, slot, parent_hash: parent, ..Header::default() }
+    }
+
+    #[test]
+    fn fork_children() {
+        let root = header(1, 1, Hash::default());
+        let head = header(2, 2, root.hash());
+        let fork = header(2, 3, root.hash());
+        let on_fork = header(3, 4, fork.hash());
+
+        let blocks = vec![(on_fork.clone(), false), (fork.clone(), false), (head.clone(), true), (root.clone(), true)];
+        let tree = ChainTreeJson::new(&head, blocks.clone(), &mut ResponseBudget::new(1024 * 1024));
+        assert!(!tree.truncated);
+        assert_eq!(tree.head, head.hash());
+        let order: Vec<Hash> = tree.blocks.iter().map(|b| b.hash).collect();
+        assert_eq!(order, vec![root.hash(), head.hash(), fork.hash(), on_fork.hash()]);
+
+        let mut forks = vec![head.hash(), fork.hash()];
+        forks.sort();
+        assert_eq!(tree.blocks[0].children, forks);
+        assert_eq!(tree.blocks[2].children, vec![on_fork.hash()]);
+        assert!(tree.blocks[1].children.is_empty());
+
+        // The highest blocks are kept under the size limit
+        let first = tree.blocks.len() - 1;
+        let size = serde_json::to_vec(&tree.blocks[first]).unwrap().len();
+        let short = ChainTreeJson::new(&head, blocks, &mut ResponseBudget::new(PAGE_OVERHEAD_BYTES + size));
+        assert!(short.truncated);
+        assert_eq!(short.blocks, vec![tree.blocks[first].clone()]);
+    }
+}
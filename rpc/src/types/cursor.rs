@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+/// Continuation token of a paged call: the position the next page starts at followed by the
+/// query it pages, hex encoded. Clients pass it back unchanged with the same parameters.
+pub fn encode<Q: Serialize>(position: u64, query: &Q) -> String {
+    let mut data = position.to_be_bytes().to_vec();
+    data.extend(bincode::serialize(query).unwrap_or_default());
+    hex::encode(data)
+}
+
+/// Position of a token made by `encode` for the same query, None for a malformed token or one
+/// of another query.
+pub fn decode<Q: Serialize>(cursor: &str, query: &Q) -> Option<u64> {
+    let data = hex::decode(cursor.trim_start_matches("0x")).ok()?;
+    if data.len() < 8 || data[8..] != bincode::serialize(query).ok()?[..] {
+        return None;
+    }
+    let mut position = [0u8; 8];
+    position.copy_from_slice(&data[..8]);
+    Some(u64::from_be_bytes(position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bound_to_query() {
+        let cursor = encode(42, &(1u64, 100u64));
+        assert_eq!(decode(&cursor, &(1u64, 100u64)), Some(42));
+        assert_eq!(decode(&cursor, &(1u64, 200u64)), None);
+        assert_eq!(decode("zz", &(1u64, 100u64)), None);
+        assert_eq!(decode("", &()), None);
+    }
+}
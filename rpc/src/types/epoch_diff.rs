@@ -0,0 +1,31 @@
+use serde::Serialize;
+
+use chain::epoch_diff::EpochDiff;
+
+use crate::request_log::ResponseBudget;
+
+/// Result of `map_getEpochDiff`, the diff with its balance changes a page at a time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EpochDiffJson {
+    #[serde(flatten)]
+    pub diff: EpochDiff,
+    /// Position in the balance changes the next page starts at, null on the last page
+    pub next_offset: Option<usize>,
+}
+
+impl EpochDiffJson {
+    /// `diff` with the balance changes from `offset` that fit in `budget` after the rest of it.
+    pub fn page(mut diff: EpochDiff, offset: usize, budget: &mut ResponseBudget) -> Self {
+        let changes = std::mem::replace(&mut diff.balance_changes, Vec::new());
+        budget.take(&diff);
+        let mut next_offset = None;
+        for (position, change) in changes.into_iter().enumerate().skip(offset) {
+            if !budget.take(&change) {
+                next_offset = Some(position);
+                break;
+            }
+            diff.balance_changes.push(change);
+        }
+        EpochDiffJson { diff, next_offset }
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use map_core::types::Address;
+
+/// Transfer to estimate the fee for. Omitted fields take the values `map_sendTransaction` uses.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EstimateRequest {
+    pub from: Address,
+    pub to: Address,
+    pub value: u128,
+    pub nonce: Option<u64>,
+    pub gas_price: Option<u64>,
+}
+
+/// Gas price percentiles of transactions in recent blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct GasPriceStats {
+    pub low: u64,
+    pub median: u64,
+    pub high: u64,
+}
+
+impl GasPriceStats {
+    /// Compute the 25th, 50th and 75th percentile, falling back to `default` without samples.
+    pub fn from_prices(mut prices: Vec<u64>, default: u64) -> Self {
+        if prices.is_empty() {
+            return GasPriceStats {
+                low: default,
+                median: default,
+                high: default,
+            };
+        }
+        prices.sort();
+        let at = |percent: usize| prices[(prices.len() - 1) * percent / 100];
+        GasPriceStats {
+            low: at(25),
+            median: at(50),
+            high: at(75),
+        }
+    }
+}
+
+/// Result of `map_estimateFee`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeEstimate {
+    /// Fee charged for the transaction.
+    pub fee: u128,
+    /// Gas limit of the transaction.
+    pub gas: u64,
+    /// Gas price suggested given the pool congestion.
+    pub gas_price: u64,
+    /// Gas price percentiles of recent blocks.
+    pub recent_gas_price: GasPriceStats,
+    /// Nonce the transaction is expected to have after pending transactions.
+    pub nonce: u64,
+    /// Number of pending transactions in the pool.
+    pub pending: usize,
+    /// True if pending transactions exceed a block capacity.
+    pub congested: bool,
+    /// Reason the transaction is expected to fail, if any.
+    pub error: Option<String>,
+}
+
+/// Fee statistics of a block in `map_feeHistory`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockFees {
+    pub height: u64,
+    pub tx_count: u64,
+    pub total_fees: u128,
+    pub min_gas_price: u64,
+    pub median_gas_price: u64,
+    pub max_gas_price: u64,
+    /// Gas price at each of the requested percentiles, 0 for empty blocks.
+    pub percentiles: Vec<u64>,
+}
+
+/// Result of `map_feeHistory`, blocks in ascending height.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeHistory {
+    pub oldest_block: u64,
+    pub blocks: Vec<BlockFees>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GasPriceStats;
+
+    #[test]
+    fn price_percentiles() {
+        assert_eq!(GasPriceStats::from_prices(vec![], 7).median, 7);
+
+        let stats = GasPriceStats::from_prices(vec![50, 10, 40, 20, 30], 0);
+        assert_eq!(stats, GasPriceStats { low: 20, median: 30, high: 40 });
+    }
+}
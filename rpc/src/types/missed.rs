@@ -0,0 +1,24 @@
+use serde::Serialize;
+
+use chain::missed_proposals::MissedProposals;
+use map_core::types::Address;
+
+/// Missed slots of a validator returned by `map_getMissedProposals`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MissedProposalsJson {
+    pub epoch: u64,
+    pub validator: Address,
+    pub missed: u64,
+    pub slots: Vec<u64>,
+}
+
+impl MissedProposalsJson {
+    pub fn new(epoch: u64, missed: MissedProposals) -> Self {
+        MissedProposalsJson {
+            epoch,
+            validator: missed.validator,
+            missed: missed.count(),
+            slots: missed.slots,
+        }
+    }
+}
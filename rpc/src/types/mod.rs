@@ -1 +1,17 @@
-pub mod block_json;
\ No newline at end of file
+pub mod account;
+pub mod admission;
+pub mod block_json;
+pub mod block_number;
+pub mod block_txs;
+pub mod chain_params;
+pub mod chain_tree;
+pub mod cursor;
+pub mod epoch_diff;
+pub mod fee;
+pub mod missed;
+pub mod performance;
+pub mod pool_event;
+pub mod rewind;
+pub mod staking;
+pub mod tx_status;
+pub mod validators;
\ No newline at end of file
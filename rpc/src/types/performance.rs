@@ -0,0 +1,40 @@
+use serde::Serialize;
+
+use map_core::types::Address;
+
+/// Blocks proposed and missed by a validator in one epoch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EpochPerformanceJson {
+    pub epoch: u64,
+    pub proposed: u64,
+    pub missed: u64,
+    /// Fees of the transactions of the proposed blocks.
+    pub rewards: u128,
+}
+
+/// Totals over an epoch range returned by `map_getValidatorPerformance`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidatorPerformanceJson {
+    pub validator: Address,
+    pub from_epoch: u64,
+    pub to_epoch: u64,
+    pub proposed: u64,
+    pub missed: u64,
+    pub rewards: u128,
+    /// Epochs of the range the validator proposed or missed a block in, ascending.
+    pub epochs: Vec<EpochPerformanceJson>,
+}
+
+impl ValidatorPerformanceJson {
+    pub fn new(validator: Address, from_epoch: u64, to_epoch: u64, epochs: Vec<EpochPerformanceJson>) -> Self {
+        ValidatorPerformanceJson {
+            validator,
+            from_epoch,
+            to_epoch,
+            proposed: epochs.iter().map(|e| e.proposed).sum(),
+            missed: epochs.iter().map(|e| e.missed).sum(),
+            rewards: epochs.iter().map(|e| e.rewards).sum(),
+            epochs,
+        }
+    }
+}
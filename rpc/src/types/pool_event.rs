@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use map_core::types::Hash;
+use pool::events::PoolEvent;
+use pool::tx_pool::TxStatus;
+
+/// Item of `map_getPoolEventChanges`. Fields not relevant to the event are omitted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolEventJson {
+    /// `added`, `replaced`, `dropped` or `included`.
+    pub event: &'static str,
+    pub hash: Hash,
+    /// `pending` or `queued`, for added transactions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'static str>,
+    /// Transaction the replaced one made room for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<Hash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block: Option<Hash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    /// Position of the transaction in the block.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+}
+
+impl From<PoolEvent> for PoolEventJson {
+    fn from(event: PoolEvent) -> Self {
+        let json = |event, hash| PoolEventJson {
+            event,
+            hash,
+            status: None,
+            by: None,
+            reason: None,
+            block: None,
+            height: None,
+            index: None,
+        };
+        match event {
+            PoolEvent::Added { hash, status } => PoolEventJson {
+                status: Some(match status {
+                    TxStatus::Pending => "pending",
+                    TxStatus::Queued => "queued",
+                }),
+                ..json("added", hash)
+            },
+            PoolEvent::Replaced { hash, by } => PoolEventJson { by: Some(by), ..json("replaced", hash) },
+            PoolEvent::Dropped { hash, reason } => PoolEventJson { reason: Some(reason), ..json("dropped", hash) },
+            PoolEvent::Included { hash, block, height, index } => PoolEventJson {
+                block: Some(block),
+                height: Some(height),
+                index: Some(index),
+                ..json("included", hash)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_fields() {
+        let added = PoolEvent::Added { hash: Hash::default(), status: TxStatus::Queued };
+        let json = serde_json::to_value(PoolEventJson::from(added)).unwrap();
+        assert_eq!(json["event"], "added");
+        assert_eq!(json["status"], "queued");
+        assert!(json.get("reason").is_none());
+
+        let included = PoolEvent::Included { hash: Hash::default(), block: Hash([1; 32]), height: 7, index: 0 };
+        let json = serde_json::to_value(PoolEventJson::from(included)).unwrap();
+        assert_eq!(json["event"], "included");
+        assert_eq!(json["height"], 7);
+        assert!(json.get("by").is_none());
+    }
+}
@@ -0,0 +1,21 @@
+use serde::Serialize;
+
+use chain::blockchain::HeadRewind;
+
+/// Head rollback done by `admin_allowDeepReorg`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HeadRewindJson {
+    pub from_height: u64,
+    pub to_height: u64,
+    pub head_height: u64,
+}
+
+impl From<HeadRewind> for HeadRewindJson {
+    fn from(rewind: HeadRewind) -> Self {
+        HeadRewindJson {
+            from_height: rewind.from_height,
+            to_height: rewind.to_height,
+            head_height: rewind.head_height,
+        }
+    }
+}
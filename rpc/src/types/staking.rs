@@ -0,0 +1,82 @@
+use serde::Serialize;
+
+use chain::blockchain::StakingEventRecord;
+use map_core::staking::StakingEvent;
+use map_core::types::{Address, Hash};
+
+/// Staking event returned by `map_getStakingEvents`. Fields not carried by the event are omitted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StakingEventJson {
+    pub height: u64,
+    pub block_hash: Hash,
+    pub tx_hash: Hash,
+    /// `ValidatorCreated`, `DepositAdded`, `ValidatorExited` or `KeyRotated`.
+    pub event: String,
+    /// Validator address.
+    pub address: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_height: Option<u64>,
+}
+
+/// Page of `map_getStakingEvents`, `next_cursor` continues the range after the last block of the
+/// page and is null on the last page.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StakingEventsJson {
+    pub events: Vec<StakingEventJson>,
+    pub next_cursor: Option<String>,
+}
+
+impl From<StakingEventRecord> for StakingEventJson {
+    fn from(record: StakingEventRecord) -> Self {
+        let mut json = StakingEventJson {
+            height: record.height,
+            block_hash: record.block_hash,
+            tx_hash: record.tx_hash,
+            event: record.event.name().to_string(),
+            address: *record.event.address(),
+            amount: None,
+            pubkey: None,
+            exit_height: None,
+        };
+        match record.event {
+            StakingEvent::ValidatorCreated { pubkey, amount, .. } => {
+                json.pubkey = Some(hex_key(&pubkey));
+                json.amount = Some(amount);
+            }
+            StakingEvent::DepositAdded { amount, .. } => {
+                json.amount = Some(amount);
+            }
+            StakingEvent::ValidatorExited { exit_height, .. } => json.exit_height = Some(exit_height),
+            StakingEvent::KeyRotated { pubkey, .. } => json.pubkey = Some(hex_key(&pubkey)),
+        }
+        json
+    }
+}
+
+fn hex_key(pubkey: &[u8]) -> String {
+    format!("0x{}", pubkey.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_json() {
+        let record = StakingEventRecord {
+            height: 3,
+            block_hash: Hash::default(),
+            tx_hash: Hash::default(),
+            event: StakingEvent::DepositAdded { address: Address::from_low_u64_be(1), amount: 7 },
+        };
+        let json = serde_json::to_value(StakingEventJson::from(record)).unwrap();
+        assert_eq!(json["event"], "DepositAdded");
+        assert_eq!(json["address"], "0x0000000000000000000000000000000000000001");
+        assert_eq!(json["amount"], 7);
+        assert!(json.get("pubkey").is_none());
+    }
+}
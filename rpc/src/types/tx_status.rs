@@ -0,0 +1,64 @@
+use serde::Serialize;
+
+use pool::tx_tracker::TrackedStatus;
+use map_core::types::Hash;
+
+/// Result of `map_getTransactionStatus`. Fields not relevant to the status are omitted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TxStatusJson {
+    /// `pending`, `included` or `dropped`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block: Option<Hash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    /// Position of the transaction in the block.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl From<TrackedStatus> for TxStatusJson {
+    fn from(status: TrackedStatus) -> Self {
+        let mut json = TxStatusJson {
+            status: "pending",
+            block: None,
+            height: None,
+            index: None,
+            reason: None,
+        };
+        match status {
+            TrackedStatus::Pending => {}
+            TrackedStatus::Included { block, height, index } => {
+                json.status = "included";
+                json.block = Some(block);
+                json.height = Some(height);
+                json.index = Some(index);
+            }
+            TrackedStatus::Dropped { reason } => {
+                json.status = "dropped";
+                json.reason = Some(reason);
+            }
+        }
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_fields() {
+        let pending = serde_json::to_value(TxStatusJson::from(TrackedStatus::Pending)).unwrap();
+        assert_eq!(pending, serde_json::json!({ "status": "pending" }));
+
+        let included = TrackedStatus::Included { block: Hash([1; 32]), height: 7, index: 2 };
+        let json = serde_json::to_value(TxStatusJson::from(included)).unwrap();
+        assert_eq!(json["status"], "included");
+        assert_eq!(json["height"], 7);
+        assert_eq!(json["index"], 2);
+        assert!(json.get("reason").is_none());
+    }
+}
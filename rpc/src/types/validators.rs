@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+use map_core::staking::Validator;
+use map_core::types::Hash;
+
+/// Validator list of a block state with aggregated stake info.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidatorsJson {
+    /// Block height the list was read at.
+    pub height: u64,
+    /// State root the list was read from.
+    pub state_root: Hash,
+    /// Sum of effective balance of all validators.
+    pub total_stake: u128,
+    /// Number of validators active at `height`.
+    pub active_count: u64,
+    /// Validators in list order.
+    pub validators: Vec<Validator>,
+}
+
+impl ValidatorsJson {
+    pub fn new(height: u64, state_root: Hash, validators: Vec<Validator>) -> Self {
+        let total_stake = validators.iter().map(|v| v.effective_balance).sum();
+        let active_count = validators.iter().filter(|v| v.is_active(height)).count() as u64;
+        ValidatorsJson {
+            height,
+            state_root,
+            total_stake,
+            active_count,
+            validators,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_core::types::Address;
+
+    #[test]
+    fn aggregate_stake() {
+        let mut first = Validator::create(Address::default());
+        first.effective_balance = 100;
+        let mut second = Validator::create(Address::default());
+        second.effective_balance = 50;
+        second.activate_height = 20;
+
+        let json = ValidatorsJson::new(10, Hash::default(), vec![first, second]);
+        assert_eq!(json.total_stake, 150);
+        assert_eq!(json.active_count, 1);
+    }
+
+    #[test]
+    fn client_json() {
+        let mut validator = Validator::create(Address::default());
+        validator.effective_balance = 100;
+        let json = serde_json::to_value(ValidatorsJson::new(10, Hash::default(), vec![validator.clone()])).unwrap();
+        let client: map_rpc_client::types::ValidatorsJson = serde_json::from_value(json).unwrap();
+        assert_eq!(client.total_stake, 100);
+        assert_eq!(client.validators, vec![validator]);
+    }
+}
@@ -24,23 +24,37 @@ extern crate executor;
 extern crate network;
 extern crate rpc;
 
+pub mod webhooks;
+
 use std::{sync::mpsc, thread};
 use std::path::PathBuf;
 use std::time::Duration;
-use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use std::sync::{Arc, RwLock};
 
 use futures::{Future};
 use tokio::runtime::{Builder as RuntimeBuilder, TaskExecutor};
 
-use chain::blockchain::BlockChain;
+use chain::blockchain::{BlockChain, DEFAULT_MAX_REORG_DEPTH};
+use chain::store::{Compression, DbEngine};
+use chain::audit;
+use chain::block_cache;
+use chain::snapshot::{self, SnapshotConfig};
+use chain::prune::NodeMode;
+use chain::lock::{TimedRwLock, TimedReadGuard, TimedWriteGuard};
+use core::chain_spec::ChainSpec;
+use core::types::Address;
 use ed25519::generator::create_key;
 // use ed25519::pubkey::Pubkey;
 use ed25519::privkey::PrivKey;
+use errors::{Error, InternalErrorKind};
 use generator::apos::EpochPoS;
-use generator::epoch::EpochProposal;
-use network::{manager as network_executor, Multiaddr, NetworkConfig};
-use pool::tx_pool::TxPoolManager;
-use rpc::http_server;
+use generator::epoch::{EpochProposal, DEFAULT_MAX_BLOCKS_BEHIND, DEFAULT_MIN_PROPOSAL_PEERS};
+use generator::signer::{BlockSigner, LocalSigner, RemoteSigner};
+use network::{manager as network_executor, Multiaddr, NetworkConfig, PeerId};
+use network::config::{RangeSyncConfig, TransportSecurity};
+use network::manager::NetworkExecutor;
+use pool::tx_pool::{self, PoolConfig, TxPoolManager};
+use rpc::{http_server, request_log};
 
 #[derive(Clone, Debug)]
 pub struct NodeConfig {
@@ -48,13 +62,77 @@ pub struct NodeConfig {
     pub data_dir: PathBuf,
     pub rpc_addr: String,
     pub rpc_port: u16,
+    /// Origins allowed to make cross-origin RPC requests.
+    pub rpc_cors: Vec<String>,
+    /// Host header values accepted by the RPC server.
+    pub rpc_vhosts: Vec<String>,
+    /// Largest RPC result sent, in bytes.
+    pub rpc_max_response_bytes: usize,
+    /// Opt-in RPC modules, admin and debug.
+    pub rpc_modules: Vec<rpc::config::API>,
     pub key: String,
-    pub poa_privkey: String,
+    /// Chain parameters, selects the consensus engine.
+    pub chain_spec: ChainSpec,
     pub dev_mode: bool,
     /// List of p2p nodes to initially connect to.
     pub dial_addrs: Vec<Multiaddr>,
     pub p2p_port: u16,
+    /// Listen on `/memory/<p2p_port>` instead of TCP, only reachable within the process.
+    pub memory_transport: bool,
+    /// Inbound p2p requests a single peer may have in flight before being refused.
+    pub max_peer_requests: usize,
+    /// Protocol encrypting and authenticating the p2p connections.
+    pub p2p_security: TransportSecurity,
+    /// Only these peers may connect or be dialed, empty for any peer.
+    pub allowed_peers: Vec<PeerId>,
+    /// Trace every p2p message to `network/p2p-trace.log` of the datadir.
+    pub p2p_trace: bool,
+    /// Batch sizes and parallelism of the block downloads of range sync.
+    pub range_sync: RangeSyncConfig,
     pub seal_block: bool,
+    /// Blocks the head may be behind the best peer head for the node to still propose.
+    pub max_blocks_behind: u64,
+    /// Connected peers on our chain required to propose, ignored in dev mode.
+    pub min_proposal_peers: usize,
+    /// Transaction pool size limits and local transaction journal.
+    pub txpool: PoolConfig,
+    /// Codecs of the block bodies and trie nodes written to the datadir.
+    pub compression: Compression,
+    /// Storage engine of a new datadir, existing ones keep theirs.
+    pub db_engine: Option<DbEngine>,
+    /// Recent block bodies kept in memory, 0 disables the cache.
+    pub block_cache_size: usize,
+    /// Keys signing the produced blocks.
+    pub signer: SignerConfig,
+    /// Hex encoded key the validator rotates to, signing once the committee holds it.
+    pub next_proposer_key: Option<String>,
+    /// Interval and retention of the state snapshots written to `datadir/snapshots`.
+    pub snapshot: SnapshotConfig,
+    /// Minutes between two consistency audits of the chain and the pool, 0 disables them.
+    pub audit_interval: u64,
+    /// Urls receiving a json POST on node events, see `webhooks`.
+    pub webhooks: Vec<String>,
+    /// Blocks of a head rollback at load from which a `reorg` webhook event is posted.
+    pub webhook_reorg_depth: u64,
+    /// Deepest head rollback done at startup, deeper ones wait for `admin_allowDeepReorg`.
+    pub max_reorg_depth: u64,
+    /// Keep the state of every block, or only of the recent ones.
+    pub mode: NodeMode,
+}
+
+/// Where the block proposer gets its signatures and VRF proofs from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignerConfig {
+    /// Hex encoded proposer key held by the node, `key` when empty.
+    Local(String),
+    /// Url of an external signer, the node holds no validator key.
+    Remote(String),
+}
+
+impl Default for SignerConfig {
+    fn default() -> Self {
+        SignerConfig::Local(String::new())
+    }
 }
 
 impl Default for NodeConfig {
@@ -64,34 +142,91 @@ impl Default for NodeConfig {
             data_dir: PathBuf::from("."),
             rpc_addr: "127.0.0.1".into(),
             rpc_port: 9545,
+            rpc_cors: vec![],
+            rpc_vhosts: http_server::parse_domains(http_server::DEFAULT_VHOSTS),
+            rpc_max_response_bytes: request_log::DEFAULT_MAX_RESPONSE_BYTES,
+            rpc_modules: vec![],
             key: "".into(),
-            poa_privkey: "".into(),
+            chain_spec: ChainSpec::default(),
             dev_mode: false,
             dial_addrs: vec![],
             p2p_port: 40313,
+            memory_transport: false,
+            max_peer_requests: network::p2p::MAX_INBOUND_REQUESTS_PER_PEER,
+            p2p_security: TransportSecurity::default(),
+            allowed_peers: vec![],
+            p2p_trace: false,
+            range_sync: RangeSyncConfig::default(),
             seal_block:false,
+            max_blocks_behind: DEFAULT_MAX_BLOCKS_BEHIND,
+            min_proposal_peers: DEFAULT_MIN_PROPOSAL_PEERS,
+            txpool: PoolConfig::default(),
+            compression: Compression::default(),
+            db_engine: None,
+            block_cache_size: block_cache::BLOCK_CACHE_SIZE,
+            signer: SignerConfig::default(),
+            next_proposer_key: None,
+            snapshot: SnapshotConfig::default(),
+            audit_interval: 0,
+            webhooks: vec![],
+            webhook_reorg_depth: 1,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            mode: NodeMode::default(),
         }
     }
 }
 
 //#[derive(Debug, Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Service {
-    pub block_chain: Arc<RwLock<BlockChain>>,
+    pub block_chain: Arc<TimedRwLock<BlockChain>>,
     pub tx_pool: Arc<RwLock<TxPoolManager>>,
     pub cfg: NodeConfig,
 }
 
 impl Service {
-    pub fn new_service(cfg: NodeConfig) -> Self {
-        let chain = Arc::new(RwLock::new(BlockChain::new(cfg.data_dir.clone(),cfg.poa_privkey.clone())));
+    pub fn new_service(cfg: NodeConfig) -> Result<Self, Error> {
+        let mut chain = BlockChain::with_storage(cfg.data_dir.clone(), &cfg.chain_spec, cfg.compression, cfg.db_engine)?;
+        chain.set_mode(cfg.mode)?;
+        chain.set_max_reorg_depth(cfg.max_reorg_depth);
+        chain.load()?;
+        chain.set_block_cache_size(cfg.block_cache_size);
+        let chain = Arc::new(TimedRwLock::new("chain", chain));
+
+        Ok(Service {
+            block_chain: chain.clone(),
+            tx_pool: Arc::new(RwLock::new(TxPoolManager::with_config(chain.clone(), cfg.txpool.clone()))),
+            cfg:   cfg.clone(),
+        })
+    }
+
+    /// Node keeping the chain in memory, used to run several nodes in one process.
+    pub fn new_in_memory(cfg: NodeConfig) -> Self {
+        let chain = Arc::new(TimedRwLock::new("chain", BlockChain::new_in_memory(&cfg.chain_spec)));
 
         Service {
             block_chain: chain.clone(),
-            tx_pool: Arc::new(RwLock::new(TxPoolManager::new(chain.clone()))),
-            cfg:   cfg.clone(),
+            tx_pool: Arc::new(RwLock::new(TxPoolManager::with_config(chain.clone(), cfg.txpool.clone()))),
+            cfg: cfg,
         }
     }
 
+    /// Start the p2p network on `executor`, without block production or RPC.
+    pub fn start_network(&self, cfg: &NodeConfig, executor: &TaskExecutor) -> Result<NetworkExecutor, Error> {
+        let mut config = NetworkConfig::new();
+        config.update_network_cfg(cfg.data_dir.clone(), cfg.dial_addrs.clone(), cfg.p2p_port).unwrap();
+        if cfg.memory_transport {
+            config.use_memory_transport(cfg.p2p_port as u64);
+        }
+        config.max_inbound_requests = cfg.max_peer_requests;
+        config.security = cfg.p2p_security;
+        config.allowed_peers = cfg.allowed_peers.clone();
+        config.p2p_trace = cfg.p2p_trace;
+        config.range_sync = cfg.range_sync.clone();
+        network_executor::NetworkExecutor::new(
+            config, self.block_chain.clone(), self.tx_pool.clone(), executor, cfg.log.clone())
+            .map_err(|e| InternalErrorKind::Other(format!("network start error: {:?}", e)).into())
+    }
+
     // fn get_poa(&self) -> POA {
     //     let key = self.cfg.poa_privkey.clone();
     //     POA::new_from_string(key)
@@ -103,51 +238,90 @@ impl Service {
 			.build()
 			.map_err(|e| format!("Failed to start runtime: {:?}", e)).expect("Failed to start runtime");
 
-        self.get_write_blockchain().load();
-        let network_block_chain = self.block_chain.clone();
+        self.tx_pool.write().expect("acquiring tx_pool write lock").load_journal();
+        let journal_signal = tx_pool::start_journal(
+            self.tx_pool.clone(), Duration::from_secs(tx_pool::JOURNAL_FLUSH_INTERVAL));
         let thread_executor: TaskExecutor = runtime.executor();
 
-        let mut config = NetworkConfig::new();
-        config.update_network_cfg(cfg.data_dir, cfg.dial_addrs, cfg.p2p_port).unwrap();
-        let network_ref = network_executor::NetworkExecutor::new(
-            config.clone(), network_block_chain, self.tx_pool.clone(), &thread_executor, cfg.log).expect("Network start error");
+        let network_ref = self.start_network(&cfg, &thread_executor).expect("Network start error");
 
         let rpc_server = http_server::start_http(http_server::RpcConfig {
             rpc_addr: cfg.rpc_addr,
             rpc_port: cfg.rpc_port,
             key: cfg.key.clone(),
-        }, self.block_chain.clone(), self.tx_pool.clone(), network_ref.network_send.clone());
+            cors_domains: cfg.rpc_cors.clone(),
+            vhosts: cfg.rpc_vhosts.clone(),
+            max_response_bytes: cfg.rpc_max_response_bytes,
+            modules: cfg.rpc_modules.clone(),
+        }, self.block_chain.clone(), self.tx_pool.clone(), network_ref.network_send.clone(),
+            network_ref.sync_progress.clone(), network_ref.peer_count.clone(),
+            network_ref.peer_stats.clone(), network_ref.propagation.clone(), network_ref.clock_offsets.clone(),
+            network_ref.topic_peers.clone(), network_ref.p2p_trace.clone(), network_ref.block_queue.clone());
+        http_server::reload_on_signal(rpc_server.restarter(), cfg.data_dir.join(http_server::RPC_RELOAD_FILE));
 
         let (tx, rx): (mpsc::Sender<i32>,mpsc::Receiver<i32>) = mpsc::channel();
 
         let shared_block_chain = self.block_chain.clone();
 
-        // Create random node key
-        let node_key = match PrivKey::from_hex(&cfg.key.clone()) {
-            Ok(k) => k,
-            _ => {
-                let (sk, _) = create_key();
-                sk
-            },
+        let signer: Arc<dyn BlockSigner> = match cfg.signer {
+            SignerConfig::Remote(ref url) => Arc::new(RemoteSigner::connect(url).expect("Remote signer error")),
+            SignerConfig::Local(ref key) => {
+                let key = if key.is_empty() { &cfg.key } else { key };
+                // Create random node key
+                let node_key = match PrivKey::from_hex(key) {
+                    Ok(k) => k,
+                    _ => {
+                        let (sk, _) = create_key();
+                        sk
+                    },
+                };
+                Arc::new(LocalSigner::new(node_key))
+            }
         };
 
+        let address_scheme = shared_block_chain.read().expect("acquiring chain read lock").address_scheme();
+        let validator = Some(Address::from_pubkey(&signer.pubkey(), address_scheme));
         let stake = Arc::new(RwLock::new(EpochPoS::new(shared_block_chain.clone(), cfg.dev_mode)));
         let slot_clock = EpochProposal::new(
-            node_key,
+            signer,
             shared_block_chain.clone(),
             stake.clone(),
             self.tx_pool.clone(),
+            network_ref.importer.clone(),
             network_ref.network_send.clone(),
+            network_ref.sync_progress.clone(),
+            cfg.max_blocks_behind,
+            if cfg.dev_mode { 0 } else { cfg.min_proposal_peers },
             thread_executor.clone(),
         );
+        let slot_clock = match cfg.next_proposer_key {
+            Some(ref key) => slot_clock.with_next_signer(Arc::new(LocalSigner::new(
+                PrivKey::from_hex(key).expect("Invalid next proposer key")))),
+            None => slot_clock,
+        };
         let slot_signal = slot_clock.start();
+        let snapshot_signal = snapshot::start(
+            shared_block_chain.clone(), cfg.data_dir.join(snapshot::SNAPSHOT_DIR), cfg.snapshot);
+        let audit_pool = self.tx_pool.clone();
+        let audit_signal = audit::start(
+            shared_block_chain.clone(), Duration::from_secs(cfg.audit_interval * 60),
+            Box::new(move || audit_pool.read().expect("acquiring tx_pool read lock").audit()));
+        let webhooks_signal = webhooks::start(
+            &cfg.webhooks, cfg.chain_spec.name.clone(), shared_block_chain.clone(),
+            network_ref.peer_count.clone(), network_ref.sync_progress.clone(), validator, cfg.webhook_reorg_depth)
+            .expect("Webhook url error");
 
 		// Cancel all tasks
 		thread::spawn(move || {
 			loop {
 				if rx.try_recv().is_ok() {
 					// Cancel slot tick service
-                    slot_signal.send(()).unwrap();
+                    let _ = slot_signal.send(());
+                    let _ = snapshot_signal.send(());
+                    let _ = audit_signal.send(());
+                    let _ = webhooks_signal.send(());
+                    let _ = journal_signal.send(());
+                    network_ref.importer.stop();
 
 					if !network_ref.exit_signal.is_closed() {
 						network_ref.exit_signal.send(1).expect("network exit error");
@@ -210,11 +384,11 @@ impl Service {
     //     self.get_readblockchain().get_block_by_number(height)
     // }
 
-    // fn get_readblockchain(&self) -> RwLockReadGuard<BlockChain> {
+    // fn get_readblockchain(&self) -> TimedReadGuard<BlockChain> {
     //     self.block_chain.read().expect("acquiring block_chain read lock")
     // }
 
-    fn get_write_blockchain(&self) -> RwLockWriteGuard<BlockChain> {
+    fn get_write_blockchain(&self) -> TimedWriteGuard<BlockChain> {
         self.block_chain.write().expect("acquiring block_chain write lock")
     }
 }
@@ -231,7 +405,7 @@ mod tests {
     fn test_service() {
         println!("begin service,for 60 seconds");
         let mut config = NodeConfig::default();
-        let service = Service::new_service(config.clone());
+        let service = Service::new_service(config.clone()).unwrap();
         let (tx,th_handle) = service.start(config.clone());
         thread::sleep(Duration::from_millis(60*1000));
         thread::spawn(move || {
@@ -0,0 +1,248 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Json POSTs to operator urls on the events worth an alert, for nodes run without a metrics
+//! stack. The node state is polled from a background thread and each event is posted once to
+//! every url, from a worker thread per url so that a hung one only delays its own events.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use map_rpc_client::RpcClient;
+use serde::Serialize;
+
+use chain::blockchain::{BlockChain, HeadRewind};
+use chain::lock::TimedRwLock;
+use core::genesis::EPOCH_LENGTH;
+use core::types::Address;
+use network::sync::SharedSyncProgress;
+
+/// Interval of the node state polls
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// Limit of each POST
+pub const POST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events waiting for a url, newer ones are dropped beyond it
+pub const POST_QUEUE: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+    NodeStarted { version: String, head_height: u64 },
+    /// The last peer disconnected
+    NoPeers,
+    /// A long-range sync reached the best peer head
+    SyncCompleted { head_height: u64 },
+    /// Our validator was elected for a slot and no block of it was included
+    ProposalMissed { validator: Address, epoch: u64, slot: u64 },
+    /// The head was rolled back `depth` blocks at load, to below the state lost by an unclean
+    /// shutdown, and stood at `head_height` once the stored blocks were imported again
+    Reorg { depth: u64, from_height: u64, head_height: u64 },
+    /// Critical: a head rollback at load beyond the max reorg depth is held until
+    /// `admin_allowDeepReorg`, and no block is imported meanwhile
+    DeepReorgHeld { depth: u64, from_height: u64, to_height: u64 },
+}
+
+/// Event of a head rollback at least `min_depth` deep
+pub fn reorg_event(rewind: &HeadRewind, min_depth: u64) -> Option<NodeEvent> {
+    let depth = rewind.from_height.saturating_sub(rewind.to_height);
+    if depth < min_depth {
+        return None;
+    }
+    Some(NodeEvent::Reorg { depth, from_height: rewind.from_height, head_height: rewind.head_height })
+}
+
+/// Body of the POSTs
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a NodeEvent,
+    network: &'a str,
+    /// Unix seconds
+    time: u64,
+}
+
+/// Node state read on each poll
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Observation {
+    pub peer_count: usize,
+    pub syncing: bool,
+    pub head_height: u64,
+    /// Slots our validator missed in the current and the previous epoch, ascending
+    pub missed_slots: Vec<u64>,
+}
+
+/// Turns the polled states into events, from the changes between two polls
+#[derive(Default)]
+pub struct Watcher {
+    last: Option<Observation>,
+    /// Highest missed slot reported
+    reported_slot: Option<u64>,
+}
+
+impl Watcher {
+    pub fn observe(&mut self, now: Observation, validator: Option<Address>) -> Vec<NodeEvent> {
+        let mut events = Vec::new();
+        match self.last {
+            // Slots missed before the start aren't news
+            None => self.reported_slot = now.missed_slots.last().cloned(),
+            Some(ref last) => {
+                if last.peer_count > 0 && now.peer_count == 0 {
+                    events.push(NodeEvent::NoPeers);
+                }
+                if last.syncing && !now.syncing {
+                    events.push(NodeEvent::SyncCompleted { head_height: now.head_height });
+                }
+                if let Some(validator) = validator {
+                    for slot in now.missed_slots.iter().filter(|s| Some(**s) > self.reported_slot) {
+                        events.push(NodeEvent::ProposalMissed { validator, epoch: slot / EPOCH_LENGTH, slot: *slot });
+                    }
+                }
+                if let Some(slot) = now.missed_slots.last() {
+                    self.reported_slot = self.reported_slot.max(Some(*slot));
+                }
+            }
+        }
+        self.last = Some(now);
+        events
+    }
+}
+
+/// Post `node_started`, a `reorg` of the head rollback at load if at least `reorg_depth` deep or
+/// `deep_reorg_held` if it was refused, and then the events of the node state to `urls` from a background thread, until the
+/// returned sender is signalled or dropped. Nothing runs without urls.
+pub fn start(
+    urls: &[String],
+    network: String,
+    chain: Arc<TimedRwLock<BlockChain>>,
+    peer_count: Arc<AtomicUsize>,
+    sync_progress: SharedSyncProgress,
+    validator: Option<Address>,
+    reorg_depth: u64,
+) -> Result<mpsc::Sender<()>, String> {
+    let (stop, signal) = mpsc::channel();
+    if urls.is_empty() {
+        return Ok(stop);
+    }
+    let hooks = urls.iter()
+        .map(|url| RpcClient::new(url).map(|client| (url.clone(), client.with_timeout(POST_TIMEOUT))))
+        .collect::<Result<Vec<_>, _>>()?;
+    // The workers end once the watcher drops their queues
+    let queues: Vec<(String, mpsc::SyncSender<Arc<Vec<u8>>>)> = hooks.into_iter().map(|(url, client)| {
+        let (queue, bodies) = mpsc::sync_channel::<Arc<Vec<u8>>>(POST_QUEUE);
+        let name = url.clone();
+        thread::spawn(move || {
+            for body in bodies {
+                if let Err(e) = client.post(&body) {
+                    warn!("webhook {} failed: {}", name, e);
+                }
+            }
+        });
+        (url, queue)
+    }).collect();
+    let post = move |event: &NodeEvent| {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let body = Arc::new(serde_json::to_vec(&Payload { event, network: &network, time }).expect("event serializes"));
+        for (url, queue) in queues.iter() {
+            if let Err(mpsc::TrySendError::Full(_)) = queue.try_send(body.clone()) {
+                warn!("webhook {} is behind, dropping {:?}", url, event);
+            }
+        }
+    };
+    info!("posting node events to {} webhooks", urls.len());
+
+    thread::spawn(move || {
+        let (head_height, rewind, held) = {
+            let chain = chain.read().expect("acquiring block_chain read lock");
+            (chain.current_block().height(), chain.head_rewind(), chain.held_rewind())
+        };
+        post(&NodeEvent::NodeStarted { version: env!("CARGO_PKG_VERSION").to_string(), head_height });
+        if let Some(event) = rewind.and_then(|rewind| reorg_event(&rewind, reorg_depth)) {
+            post(&event);
+        }
+        if let Some(held) = held {
+            post(&NodeEvent::DeepReorgHeld {
+                depth: held.from_height - held.to_height, from_height: held.from_height, to_height: held.to_height,
+            });
+        }
+        let mut watcher = Watcher::default();
+        loop {
+            let now = observe(&chain, &peer_count, &sync_progress, validator);
+            for event in watcher.observe(now, validator) {
+                post(&event);
+            }
+            if let Err(mpsc::RecvTimeoutError::Timeout) = signal.recv_timeout(WATCH_INTERVAL) {
+                continue;
+            }
+            break;
+        }
+    });
+    Ok(stop)
+}
+
+fn observe(
+    chain: &Arc<TimedRwLock<BlockChain>>,
+    peer_count: &AtomicUsize,
+    sync_progress: &SharedSyncProgress,
+    validator: Option<Address>,
+) -> Observation {
+    let syncing = sync_progress.read().expect("acquiring sync progress lock").syncing;
+    let chain = chain.read().expect("acquiring block_chain read lock");
+    let head = chain.current_block();
+    let mut missed_slots = Vec::new();
+    if let Some(validator) = validator {
+        let epoch = head.header.slot / EPOCH_LENGTH;
+        for epoch in epoch.saturating_sub(1)..=epoch {
+            missed_slots.extend(chain.get_missed_proposals(epoch).into_iter()
+                .filter(|m| m.validator == validator)
+                .flat_map(|m| m.slots));
+        }
+    }
+    Observation { peer_count: peer_count.load(Ordering::Relaxed), syncing, head_height: head.height(), missed_slots }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_of_changes() {
+        let validator = Address([1; 20]);
+        let mut watcher = Watcher::default();
+        let start = Observation { peer_count: 2, syncing: true, head_height: 10, missed_slots: vec![3] };
+        assert_eq!(watcher.observe(start, Some(validator)), vec![]);
+
+        let now = Observation { peer_count: 0, syncing: false, head_height: 50, missed_slots: vec![3, 7, 9] };
+        assert_eq!(watcher.observe(now.clone(), Some(validator)), vec![
+            NodeEvent::NoPeers,
+            NodeEvent::SyncCompleted { head_height: 50 },
+            NodeEvent::ProposalMissed { validator, epoch: 7 / EPOCH_LENGTH, slot: 7 },
+            NodeEvent::ProposalMissed { validator, epoch: 9 / EPOCH_LENGTH, slot: 9 },
+        ]);
+        // Each event is posted once
+        assert_eq!(watcher.observe(now, Some(validator)), vec![]);
+
+        let rewind = HeadRewind { from_height: 20, to_height: 12, head_height: 18 };
+        assert_eq!(reorg_event(&rewind, 8), Some(NodeEvent::Reorg { depth: 8, from_height: 20, head_height: 18 }));
+        assert_eq!(reorg_event(&rewind, 9), None);
+
+        let payload = Payload { event: &NodeEvent::NoPeers, network: "testnet", time: 1 };
+        assert_eq!(serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({"event": "no_peers", "network": "testnet", "time": 1}));
+    }
+}
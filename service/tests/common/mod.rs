@@ -0,0 +1,148 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-process network of nodes connected over the libp2p memory transport.
+#![allow(dead_code)]
+
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Runtime;
+
+use core::block::Block;
+use core::test_utils::BlockBuilder;
+use core::types::Hash;
+use network::manager::{self, NetworkExecutor};
+use network::multiaddr::{Multiaddr, Protocol};
+use map_service::{NodeConfig, Service};
+
+/// Memory transport ports are global to the process, tests running in parallel take distinct ones.
+static NEXT_PORT: AtomicU64 = AtomicU64::new(1);
+
+/// Time for the gossipsub mesh to form after peers connect.
+pub const MESH_SETTLE: Duration = Duration::from_secs(2);
+/// Default bound for nodes to converge.
+pub const CONVERGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct TestNode {
+    pub service: Service,
+    pub network: NetworkExecutor,
+    pub addr: Multiaddr,
+    _runtime: Runtime,
+}
+
+impl TestNode {
+    fn start(dial_addrs: Vec<Multiaddr>) -> Self {
+        let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+        let data_dir = std::env::temp_dir().join(format!("map_sim_node_{}_{}", std::process::id(), port));
+        let _ = fs::remove_dir_all(&data_dir);
+
+        let cfg = NodeConfig {
+            data_dir,
+            dial_addrs,
+            p2p_port: port as u16,
+            memory_transport: true,
+            ..Default::default()
+        };
+        let service = Service::new_in_memory(cfg.clone());
+        service.block_chain.write().unwrap().load().unwrap();
+
+        let runtime = Runtime::new().expect("start runtime");
+        let network = service.start_network(&cfg, &runtime.executor()).expect("start network");
+        TestNode {
+            service,
+            network,
+            addr: Protocol::Memory(port).into(),
+            _runtime: runtime,
+        }
+    }
+
+    pub fn head(&self) -> Block {
+        self.service.block_chain.read().unwrap().current_block()
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.network.peer_count.load(Ordering::Relaxed)
+    }
+
+    /// Build an empty block on the local head, import it and gossip it.
+    pub fn produce_block(&mut self) -> Block {
+        let block = BlockBuilder::on(&self.head()).build();
+        self.service.block_chain.write().unwrap().import_block(&block).expect("import produced block");
+        manager::publish_block(&mut self.network.network_send, block.clone());
+        block
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.service.cfg.data_dir);
+    }
+}
+
+pub struct TestNetwork {
+    pub nodes: Vec<TestNode>,
+}
+
+impl TestNetwork {
+    /// Start `n` nodes, each dialing the nodes started before it.
+    pub fn new(n: usize) -> Self {
+        let mut net = TestNetwork { nodes: Vec::new() };
+        for _ in 0..n {
+            net.add_node();
+        }
+        net
+    }
+
+    /// Start one more node connected to all running nodes, returns its index.
+    pub fn add_node(&mut self) -> usize {
+        let dial_addrs = self.nodes.iter().map(|node| node.addr.clone()).collect();
+        self.nodes.push(TestNode::start(dial_addrs));
+        self.nodes.len() - 1
+    }
+
+    pub fn node(&mut self, i: usize) -> &mut TestNode {
+        &mut self.nodes[i]
+    }
+
+    /// Wait until every node has a peer and let the gossip mesh settle.
+    pub fn wait_connected(&self, timeout: Duration) -> bool {
+        if !wait_until(timeout, || self.nodes.iter().all(|node| node.peer_count() > 0)) {
+            return false;
+        }
+        thread::sleep(MESH_SETTLE);
+        true
+    }
+
+    /// Wait until every node has `hash` as its head.
+    pub fn wait_for_head(&self, hash: Hash, timeout: Duration) -> bool {
+        wait_until(timeout, || self.nodes.iter().all(|node| node.head().hash() == hash))
+    }
+}
+
+/// Poll `cond` until it holds or `timeout` expires.
+pub fn wait_until<F: Fn() -> bool>(timeout: Duration, cond: F) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if cond() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    cond()
+}
@@ -0,0 +1,43 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+mod common;
+
+use common::{TestNetwork, CONVERGE_TIMEOUT};
+
+#[test]
+fn gossiped_blocks_reach_all_nodes() {
+    let mut net = TestNetwork::new(3);
+    assert!(net.wait_connected(CONVERGE_TIMEOUT), "nodes did not connect");
+
+    let mut head = net.node(0).head();
+    for _ in 0..3 {
+        head = net.node(0).produce_block();
+    }
+    assert!(net.wait_for_head(head.hash(), CONVERGE_TIMEOUT), "nodes did not converge on height {}", head.height());
+}
+
+#[test]
+fn late_node_syncs_chain() {
+    let mut net = TestNetwork::new(1);
+    let mut head = net.node(0).head();
+    for _ in 0..5 {
+        head = net.node(0).produce_block();
+    }
+
+    let late = net.add_node();
+    assert!(net.wait_for_head(head.hash(), CONVERGE_TIMEOUT), "node {} did not sync to height {}", late, head.height());
+}
@@ -29,19 +29,23 @@ use ed25519::generator::Generator;
 use ed25519::pubkey::Pubkey;
 use hash;
 
-fn display_address_by_pubkey(pk: Pubkey) {
+fn display_address_by_pubkey(pk: Pubkey, scheme: &str) {
 	let raw = pk.to_bytes();
+	let digest = match scheme {
+		"keccak256" => hash::keccak_256(&raw),
+		_ => hash::blake2b_256(&raw),
+	};
 	let mut addr:[u8; 20] = [0u8; 20];
-	addr.copy_from_slice(&(hash::blake2b_256(&raw)[12..]));
+	addr.copy_from_slice(&digest[12..]);
     println!("address:{}",hex::encode(&addr));
 }
 trait Crypto {
 	type Generator: Default;
 
-	fn display_new_key_infos() {
+	fn display_new_key_infos(scheme: &str) {
 		let (s,p) = Generator::default().new();
 		println!("Secret key:{},Public key:{}",s,p);
-		display_address_by_pubkey(p);
+		display_address_by_pubkey(p, scheme);
 	}
 }
 
@@ -56,7 +60,7 @@ fn execute<C: Crypto>(matches: clap::ArgMatches)
 	// let password = matches.value_of("password");
 	match matches.subcommand() {
 		("generate", Some(matches)) => {
-			C::display_new_key_infos();
+			C::display_new_key_infos(matches.value_of("scheme").unwrap_or("blake2b"));
 		}
 		("sign", Some(matches)) => {
 			println!("TODO, may be soon");
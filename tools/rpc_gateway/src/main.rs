@@ -0,0 +1,130 @@
+// Copyright 2021 MAP Protocol Authors.
+// This file is part of MAP Protocol.
+
+// MAP Protocol is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// MAP Protocol is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with MAP Protocol.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Query-only RPC server over a read-only chain database.
+//!
+//! The gateway doesn't run consensus, the transaction pool or the p2p network, so many instances
+//! can serve queries from a shared snapshot or a replicated datadir.
+#[macro_use]
+extern crate log;
+
+use std::path::PathBuf;
+use std::process;
+use std::sync::{mpsc, Arc};
+
+use clap::{App, Arg};
+use chain::blockchain::BlockChain;
+use chain::lock::TimedRwLock;
+use logger::LogConfig;
+use rpc::http_server::{self, RpcConfig};
+use rpc::request_log;
+
+fn main() {
+    let matches = App::new("map-rpc-gateway")
+        .version("0.0.1")
+        .about("MAP Protocol - read-only RPC gateway")
+        .arg(Arg::with_name("data_dir")
+            .long("datadir")
+            .short("d")
+            .value_name("PATH")
+            .takes_value(true)
+            .help("Chain data directory of a map node."))
+        .arg(Arg::with_name("log")
+            .long("log")
+            .short("l")
+            .value_name("LOG_FILTER")
+            .takes_value(true)
+            .help("Sets logging filter with <LOG_FILTER>."))
+        .arg(Arg::with_name("rpc_addr")
+            .long("rpc_addr")
+            .takes_value(true)
+            .default_value("127.0.0.1")
+            .help("Customize RPC listening address"))
+        .arg(Arg::with_name("rpc_port")
+            .long("rpc_port")
+            .takes_value(true)
+            .default_value("9545")
+            .help("Customize RPC listening port"))
+        .arg(Arg::with_name("rpc_corsdomain")
+            .long("rpc.corsdomain")
+            .value_name("DOMAINS")
+            .takes_value(true)
+            .default_value("")
+            .help("Comma separated origins allowed to make cross-origin requests, '*' allows any"))
+        .arg(Arg::with_name("rpc_vhosts")
+            .long("rpc.vhosts")
+            .value_name("HOSTS")
+            .takes_value(true)
+            .default_value(http_server::DEFAULT_VHOSTS)
+            .help("Comma separated Host header values accepted, '*' allows any"))
+        .arg(Arg::with_name("rpc_max_response_size")
+            .long("rpc.max-response-size")
+            .value_name("BYTES")
+            .takes_value(true)
+            .help("Largest result sent, larger ones fail and are to be narrowed or paged"))
+        .get_matches();
+
+    let mut log_config = LogConfig::default();
+    if let Some(filter) = matches.value_of("log") {
+        log_config.filter = filter.to_string();
+    }
+    logger::init(log_config);
+
+    let data_dir = PathBuf::from(matches.value_of("data_dir").unwrap_or("."));
+    let rpc_port = match matches.value_of("rpc_port").unwrap().parse::<u16>() {
+        Ok(port) => port,
+        Err(_) => {
+            eprintln!("Invalid rpc port");
+            process::exit(1);
+        }
+    };
+
+    let max_response_bytes = match matches.value_of("rpc_max_response_size").map(|size| size.parse::<usize>()) {
+        None => request_log::DEFAULT_MAX_RESPONSE_BYTES,
+        Some(Ok(size)) => size,
+        Some(Err(_)) => {
+            eprintln!("Invalid rpc max response size");
+            process::exit(1);
+        }
+    };
+
+    let chain = match BlockChain::open_read_only(data_dir) {
+        Some(chain) => chain,
+        None => {
+            eprintln!("No chain data found in datadir");
+            process::exit(1);
+        }
+    };
+    let current = chain.current_block();
+    info!("serving chain height={} hash={}", current.height(), current.hash());
+
+    let server = http_server::start_query_http(RpcConfig {
+        rpc_addr: matches.value_of("rpc_addr").unwrap().to_string(),
+        rpc_port: rpc_port,
+        key: String::new(),
+        cors_domains: http_server::parse_domains(matches.value_of("rpc_corsdomain").unwrap()),
+        vhosts: http_server::parse_domains(matches.value_of("rpc_vhosts").unwrap()),
+        max_response_bytes,
+        modules: vec![],
+    }, Arc::new(TimedRwLock::new("chain", chain)));
+
+    let (tx, rx) = mpsc::channel();
+    let _ = ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    });
+    let _ = rx.recv();
+    server.close();
+}